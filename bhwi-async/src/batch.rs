@@ -0,0 +1,167 @@
+//! Signing of several psbts in a row by the same device, like the
+//! transactions of a RBF ladder or of batched withdrawals: the connection
+//! and the open app are kept between the psbts, and a psbt refused by the
+//! user does not stop the batch.
+
+use std::fmt::Debug;
+
+use bhwi::{bitcoin::Psbt, common, ledger::WalletPolicy};
+
+use crate::{Error, HWI};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchStatus {
+    /// The psbt with the signatures of the device.
+    Signed(Psbt),
+    /// The user refused the psbt on the device.
+    Refused,
+}
+
+/// Signs the psbts in their order, with the policy and its proof of
+/// registration for the wallets other than the default ones. Returns the
+/// status of each psbt, the other errors of the device stop the batch.
+pub async fn sign_psbts<D, E, F>(
+    device: &mut D,
+    psbts: Vec<Psbt>,
+    policy: Option<WalletPolicy>,
+    hmac: Option<[u8; 32]>,
+) -> Result<Vec<BatchStatus>, Error<E, F>>
+where
+    D: HWI<Error = Error<E, F>> + ?Sized,
+    E: Debug,
+    F: Debug,
+{
+    let mut statuses = Vec::with_capacity(psbts.len());
+    for psbt in psbts {
+        match device.sign_psbt(psbt, policy.clone(), hmac).await {
+            Ok(signed) => statuses.push(BatchStatus::Signed(signed)),
+            Err(Error::Interpreter(common::Error::UserRefused)) => {
+                statuses.push(BatchStatus::Refused)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareSigner;
+    use async_trait::async_trait;
+    use bhwi::bitcoin::{
+        absolute::LockTime,
+        bip32::{DerivationPath, Fingerprint, Xpub},
+        transaction::Version,
+        Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+        TxOut, Txid, Witness,
+    };
+    use std::{convert::Infallible, str::FromStr};
+
+    /// Signer refusing the psbts of the given rank.
+    struct Refusing {
+        signer: SoftwareSigner,
+        refused: Vec<usize>,
+        signed: usize,
+    }
+
+    #[async_trait(?Send)]
+    impl HWI for Refusing {
+        type Error = Error<Infallible, Infallible>;
+
+        async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+            self.signer.unlock(network).await
+        }
+
+        async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+            self.signer.get_master_fingerprint().await
+        }
+
+        async fn get_extended_pubkey(
+            &mut self,
+            path: DerivationPath,
+            display: bool,
+        ) -> Result<Xpub, Self::Error> {
+            self.signer.get_extended_pubkey(path, display).await
+        }
+
+        async fn register_wallet(
+            &mut self,
+            policy: WalletPolicy,
+        ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+            self.signer.register_wallet(policy).await
+        }
+
+        async fn sign_psbt(
+            &mut self,
+            psbt: Psbt,
+            policy: Option<WalletPolicy>,
+            hmac: Option<[u8; 32]>,
+        ) -> Result<Psbt, Self::Error> {
+            self.signed += 1;
+            if self.refused.contains(&(self.signed - 1)) {
+                return Err(common::Error::UserRefused.into());
+            }
+            HWI::sign_psbt(&mut self.signer, psbt, policy, hmac).await
+        }
+    }
+
+    /// Returns the psbt spending the output of the signer with the fee.
+    fn psbt(fingerprint: Fingerprint, xpub: Xpub, path: &DerivationPath, fee: u64) -> Psbt {
+        let script_pubkey =
+            ScriptBuf::new_p2wpkh(&CompressedPublicKey(xpub.public_key).wpubkey_hash());
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_str(&"11".repeat(32)).unwrap(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_000 - fee),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey,
+        });
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(xpub.public_key, (fingerprint, path.clone()));
+        psbt
+    }
+
+    #[test]
+    fn test_sign_psbts() {
+        let mut device = Refusing {
+            signer: SoftwareSigner::new(&[0x01; 32], Network::Testnet).unwrap(),
+            refused: vec![1],
+            signed: 0,
+        };
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let statuses = futures::executor::block_on(async {
+            let fingerprint = device.get_master_fingerprint().await.unwrap();
+            let xpub = device
+                .get_extended_pubkey(path.clone(), false)
+                .await
+                .unwrap();
+            let ladder = [1_000, 2_000, 4_000]
+                .into_iter()
+                .map(|fee| psbt(fingerprint, xpub, &path, fee))
+                .collect();
+            sign_psbts(&mut device, ladder, None, None).await.unwrap()
+        });
+        assert_eq!(statuses.len(), 3);
+        assert_eq!(statuses[1], BatchStatus::Refused);
+        for status in [&statuses[0], &statuses[2]] {
+            match status {
+                BatchStatus::Signed(psbt) => assert_eq!(psbt.inputs[0].partial_sigs.len(), 1),
+                BatchStatus::Refused => panic!("expected a signed psbt"),
+            }
+        }
+    }
+}
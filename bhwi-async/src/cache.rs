@@ -0,0 +1,195 @@
+//! Cache of the xpubs of the devices, so that the repeated retrievals of the
+//! same keys, like the checks of a wallet, do not reach the device.
+
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use async_trait::async_trait;
+use bhwi::{
+    bitcoin::{
+        bip32::{DerivationPath, Fingerprint, Xpub},
+        Network, Psbt,
+    },
+    devices::Capabilities,
+    ledger::WalletPolicy,
+};
+
+use crate::HWI;
+
+/// Storage of the xpubs by master fingerprint and derivation path, the
+/// applications may back it with their own storage.
+pub trait XpubCache {
+    fn get(&self, fingerprint: Fingerprint, path: &DerivationPath) -> Option<Xpub>;
+    fn set(&mut self, fingerprint: Fingerprint, path: DerivationPath, xpub: Xpub);
+    /// Removes the xpubs of the master key.
+    fn invalidate(&mut self, fingerprint: Fingerprint);
+}
+
+/// Cache kept in memory, lost with the application.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryXpubCache(BTreeMap<(Fingerprint, DerivationPath), Xpub>);
+
+impl XpubCache for MemoryXpubCache {
+    fn get(&self, fingerprint: Fingerprint, path: &DerivationPath) -> Option<Xpub> {
+        self.0.get(&(fingerprint, path.clone())).copied()
+    }
+    fn set(&mut self, fingerprint: Fingerprint, path: DerivationPath, xpub: Xpub) {
+        self.0.insert((fingerprint, path), xpub);
+    }
+    fn invalidate(&mut self, fingerprint: Fingerprint) {
+        self.0.retain(|(fg, _), _| *fg != fingerprint);
+    }
+}
+
+/// The cache is shared by the application and the devices.
+impl<S: XpubCache + ?Sized> XpubCache for Rc<RefCell<S>> {
+    fn get(&self, fingerprint: Fingerprint, path: &DerivationPath) -> Option<Xpub> {
+        self.borrow().get(fingerprint, path)
+    }
+    fn set(&mut self, fingerprint: Fingerprint, path: DerivationPath, xpub: Xpub) {
+        self.borrow_mut().set(fingerprint, path, xpub)
+    }
+    fn invalidate(&mut self, fingerprint: Fingerprint) {
+        self.borrow_mut().invalidate(fingerprint)
+    }
+}
+
+/// Device answering the xpub retrievals from the cache. The master
+/// fingerprint is retrieved once, the xpubs to display are always retrieved
+/// from the device.
+pub struct CachedDevice<D, C> {
+    pub device: D,
+    cache: C,
+    fingerprint: Option<Fingerprint>,
+}
+
+impl<D, C: XpubCache> CachedDevice<D, C> {
+    pub fn new(device: D, cache: C) -> Self {
+        Self {
+            device,
+            cache,
+            fingerprint: None,
+        }
+    }
+
+    /// Sets the master fingerprint of the device known by the application,
+    /// the cached xpubs are then returned without any round-trip.
+    pub fn with_master_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Removes the cached xpubs of the device, the master fingerprint is
+    /// retrieved again.
+    pub fn invalidate(&mut self) {
+        if let Some(fingerprint) = self.fingerprint.take() {
+            self.cache.invalidate(fingerprint);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<D: HWI, C: XpubCache> HWI for CachedDevice<D, C> {
+    type Error = D::Error;
+    fn capabilities(&self) -> Capabilities {
+        self.device.capabilities()
+    }
+
+    async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+        self.device.unlock(network).await
+    }
+
+    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+        if let Some(fingerprint) = self.fingerprint {
+            return Ok(fingerprint);
+        }
+        let fingerprint = self.device.get_master_fingerprint().await?;
+        self.fingerprint = Some(fingerprint);
+        Ok(fingerprint)
+    }
+
+    async fn get_extended_pubkey(
+        &mut self,
+        path: DerivationPath,
+        display: bool,
+    ) -> Result<Xpub, Self::Error> {
+        let fingerprint = self.get_master_fingerprint().await?;
+        if !display {
+            if let Some(xpub) = self.cache.get(fingerprint, &path) {
+                return Ok(xpub);
+            }
+        }
+        let xpub = self
+            .device
+            .get_extended_pubkey(path.clone(), display)
+            .await?;
+        self.cache.set(fingerprint, path, xpub);
+        Ok(xpub)
+    }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        self.device.register_wallet(policy).await
+    }
+
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        self.device.sign_psbt(psbt, policy, hmac).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockLedger, Ledger};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_cached_device() {
+        let mock = MockLedger::new(&[0x01; 32], Network::Testnet);
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let expected = mock.xpub(&path);
+        let cache = Rc::new(RefCell::new(MemoryXpubCache::default()));
+        let mut device = CachedDevice::new(Ledger::new(mock), cache.clone());
+
+        futures::executor::block_on(async {
+            device.unlock(Network::Testnet).await.unwrap();
+            assert_eq!(
+                device
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .unwrap(),
+                expected
+            );
+            let commands = device.device.transport.commands.len();
+            assert_eq!(
+                device
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .unwrap(),
+                expected
+            );
+            assert_eq!(device.device.transport.commands.len(), commands);
+
+            // The xpub to display reaches the device.
+            device
+                .get_extended_pubkey(path.clone(), true)
+                .await
+                .unwrap();
+            assert_eq!(device.device.transport.commands.len(), commands + 1);
+
+            let fingerprint = device.get_master_fingerprint().await.unwrap();
+            assert!(cache.get(fingerprint, &path).is_some());
+            device.invalidate();
+            assert!(cache.get(fingerprint, &path).is_none());
+            device.get_extended_pubkey(path, false).await.unwrap();
+            // The fingerprint then the xpub.
+            assert_eq!(device.device.transport.commands.len(), commands + 3);
+        });
+    }
+}
@@ -1,16 +1,20 @@
 use crate::{HttpClient, Transport};
 use async_trait::async_trait;
 use bhwi::{
+    authorization::Authorization,
     coldcard::{
         encrypt::{self, CryptoRngCore},
         ColdcardCommand, ColdcardError, ColdcardInterpreter, ColdcardResponse, ColdcardTransmit,
     },
-    common, Interpreter,
+    common,
+    devices::Capabilities,
+    Interpreter,
 };
 
 pub struct Coldcard<T> {
     pub transport: T,
     encryption: encrypt::Engine,
+    authorization: Option<Authorization>,
 }
 
 impl<T> Coldcard<T> {
@@ -18,6 +22,7 @@ impl<T> Coldcard<T> {
         Self {
             transport,
             encryption: encrypt::Engine::new(rng),
+            authorization: None,
         }
     }
 }
@@ -45,6 +50,19 @@ where
             ColdcardInterpreter::new(&mut self.encryption),
         )
     }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+    fn capabilities(&self) -> Capabilities {
+        bhwi::coldcard::capabilities()
+    }
+}
+
+impl<T> crate::Authorize for Coldcard<T> {
+    fn authorization_mut(&mut self) -> &mut Option<Authorization> {
+        &mut self.authorization
+    }
 }
 
 impl<T> crate::OnUnlock for Coldcard<T> {
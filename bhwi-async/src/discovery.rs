@@ -0,0 +1,164 @@
+//! Discovery of the standard single signature accounts of a device, for the
+//! onboarding flows: the first addresses of every candidate account are
+//! derived on the host, the application looks them up in its backend to
+//! suggest the accounts the device was used with.
+
+use std::fmt::Debug;
+
+use bhwi::{
+    bitcoin::{
+        bip32::{ChildNumber, DerivationPath},
+        Address, Network,
+    },
+    common,
+    path::Purpose,
+    psbt::policy_input,
+    wallet::{descriptor_checksum, WalletPolicy},
+};
+
+use crate::{Error, HWI};
+
+/// Number of receive addresses derived for each candidate account.
+pub const DISCOVERY_ADDRESS_COUNT: u32 = 5;
+
+/// Purposes of the single signature accounts, in the order of discovery.
+const PURPOSES: [Purpose; 4] = [
+    Purpose::Bip44,
+    Purpose::Bip49,
+    Purpose::Bip84,
+    Purpose::Bip86,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountCandidate {
+    pub purpose: Purpose,
+    pub account: u32,
+    /// Policy of the account, with the origin of its key.
+    pub policy: WalletPolicy,
+    /// Receive and change descriptors, with their checksum.
+    pub descriptors: (String, String),
+    /// First receive addresses of the account.
+    pub addresses: Vec<Address>,
+}
+
+fn purpose_index(purpose: Purpose) -> u32 {
+    match purpose {
+        Purpose::Bip44 => 44,
+        Purpose::Bip48 => 48,
+        Purpose::Bip49 => 49,
+        Purpose::Bip84 => 84,
+        Purpose::Bip86 => 86,
+    }
+}
+
+fn with_checksum(desc: String) -> String {
+    match descriptor_checksum(&desc) {
+        Some(checksum) => format!("{}#{}", desc, checksum),
+        None => desc,
+    }
+}
+
+/// Returns the candidate accounts 0 to `max_account` of the BIP-44, 49, 84
+/// and 86 purposes, with their first receive addresses.
+pub async fn discover_accounts<D, E, F>(
+    device: &mut D,
+    network: Network,
+    max_account: u32,
+) -> Result<Vec<AccountCandidate>, Error<E, F>>
+where
+    D: HWI<Error = Error<E, F>> + ?Sized,
+    E: Debug,
+    F: Debug,
+{
+    let fingerprint = device.get_master_fingerprint().await?;
+    let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+    let mut candidates = Vec::new();
+    for purpose in PURPOSES {
+        for account in 0..=max_account {
+            let path: DerivationPath = [purpose_index(purpose), coin_type, account]
+                .into_iter()
+                .map(ChildNumber::from_hardened_idx)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| Error::Interpreter(common::Error::Request("Invalid account index")))?
+                .into();
+            let xpub = device.get_extended_pubkey(path.clone(), false).await?;
+            let policy = WalletPolicy::new_singlesig((fingerprint, path), xpub)
+                .map_err(|e| Error::Interpreter(common::Error::InvalidPolicy(e)))?;
+            let descriptor = |change| {
+                policy
+                    .get_descriptor(change)
+                    .map(with_checksum)
+                    .map_err(|e| Error::Interpreter(common::Error::InvalidPolicy(e)))
+            };
+            let descriptors = (descriptor(false)?, descriptor(true)?);
+            let addresses = (0..DISCOVERY_ADDRESS_COUNT)
+                .filter_map(|index| policy_input(&policy, false, index))
+                .filter_map(|(script, _)| Address::from_script(&script, network).ok())
+                .collect();
+            candidates.push(AccountCandidate {
+                purpose,
+                account,
+                policy,
+                descriptors,
+                addresses,
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareSigner;
+
+    #[test]
+    fn test_discover_accounts() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon \
+                        abandon abandon abandon abandon abandon about";
+        let mut signer = SoftwareSigner::from_mnemonic(mnemonic, "", Network::Bitcoin).unwrap();
+        let candidates =
+            futures::executor::block_on(discover_accounts(&mut signer, Network::Bitcoin, 1))
+                .unwrap();
+        assert_eq!(candidates.len(), 8);
+        assert!(candidates
+            .iter()
+            .all(|c| c.addresses.len() == DISCOVERY_ADDRESS_COUNT as usize));
+
+        // Test vectors of BIP-44, 49, 84 and 86.
+        let first_address = |purpose| {
+            candidates
+                .iter()
+                .find(|c| c.purpose == purpose && c.account == 0)
+                .unwrap()
+                .addresses[0]
+                .to_string()
+        };
+        assert_eq!(
+            first_address(Purpose::Bip44),
+            "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA"
+        );
+        assert_eq!(
+            first_address(Purpose::Bip49),
+            "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf"
+        );
+        assert_eq!(
+            first_address(Purpose::Bip84),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            first_address(Purpose::Bip86),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+
+        let wit = candidates
+            .iter()
+            .find(|c| c.purpose == Purpose::Bip84 && c.account == 1)
+            .unwrap();
+        assert!(wit
+            .descriptors
+            .0
+            .starts_with("wpkh([73c5da0a/84'/0'/1']xpub"));
+        assert!(wit.descriptors.1.contains("/1/*)#"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Exclusive access to a device driven by several parts of the application,
+//! like the tabs of a page or the renders of a component: a command started
+//! while another one is exchanging with the same device is rejected with
+//! `DeviceBusy` instead of interleaving its exchanges.
+//!
+//! The handles of the device share a `SessionLock`, each one wraps its own
+//! device with `Exclusive`.
+
+use std::cell::Cell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use bhwi::{
+    bitcoin::{
+        bip32::{DerivationPath, Fingerprint, Xpub},
+        Network, Psbt,
+    },
+    common,
+    devices::Capabilities,
+    ledger::WalletPolicy,
+};
+
+use crate::{Error, HWI};
+
+/// Lock shared by the handles of a device.
+#[derive(Debug, Clone, Default)]
+pub struct SessionLock(Rc<Cell<bool>>);
+
+impl SessionLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true while a command holds the lock.
+    pub fn is_locked(&self) -> bool {
+        self.0.get()
+    }
+
+    /// Takes the lock until the guard is dropped, fails with `DeviceBusy`
+    /// if it is held.
+    pub fn try_lock(&self) -> Result<SessionGuard, common::Error> {
+        if self.0.replace(true) {
+            return Err(common::Error::DeviceBusy);
+        }
+        Ok(SessionGuard(self.0.clone()))
+    }
+
+    /// Releases the lock held by a command that will never end, like one
+    /// aborted by a panic, its guard is never dropped.
+    pub fn release(&self) {
+        self.0.set(false);
+    }
+}
+
+/// Releases the lock when dropped, also when the command is dropped before
+/// its end.
+#[derive(Debug)]
+pub struct SessionGuard(Rc<Cell<bool>>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// Device running its commands under the lock.
+pub struct Exclusive<D> {
+    pub inner: D,
+    lock: SessionLock,
+}
+
+impl<D> Exclusive<D> {
+    pub fn new(inner: D, lock: SessionLock) -> Self {
+        Self { inner, lock }
+    }
+
+    pub fn lock(&self) -> &SessionLock {
+        &self.lock
+    }
+}
+
+#[async_trait(?Send)]
+impl<D, E, F> HWI for Exclusive<D>
+where
+    D: HWI<Error = Error<E, F>>,
+    E: Debug,
+    F: Debug,
+{
+    type Error = Error<E, F>;
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+        let _guard = self.lock.try_lock()?;
+        self.inner.unlock(network).await
+    }
+
+    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+        let _guard = self.lock.try_lock()?;
+        self.inner.get_master_fingerprint().await
+    }
+
+    async fn get_extended_pubkey(
+        &mut self,
+        path: DerivationPath,
+        display: bool,
+    ) -> Result<Xpub, Self::Error> {
+        let _guard = self.lock.try_lock()?;
+        self.inner.get_extended_pubkey(path, display).await
+    }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        let _guard = self.lock.try_lock()?;
+        self.inner.register_wallet(policy).await
+    }
+
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        let _guard = self.lock.try_lock()?;
+        self.inner.sign_psbt(psbt, policy, hmac).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockLedger, Ledger};
+
+    #[test]
+    fn test_exclusive() {
+        let lock = SessionLock::new();
+        let mut first = Exclusive::new(
+            Ledger::new(MockLedger::new(&[0x01; 32], Network::Testnet)),
+            lock.clone(),
+        );
+        let mut second = Exclusive::new(
+            Ledger::new(MockLedger::new(&[0x01; 32], Network::Testnet)),
+            lock.clone(),
+        );
+        futures::executor::block_on(async {
+            let guard = lock.try_lock().unwrap();
+            assert!(matches!(
+                second.get_master_fingerprint().await,
+                Err(Error::Interpreter(common::Error::DeviceBusy))
+            ));
+            drop(guard);
+            let fg = first.get_master_fingerprint().await.unwrap();
+            assert_eq!(second.get_master_fingerprint().await.unwrap(), fg);
+        });
+        assert!(!lock.is_locked());
+
+        std::mem::forget(lock.try_lock().unwrap());
+        assert!(lock.is_locked());
+        lock.release();
+        assert!(lock.try_lock().is_ok());
+    }
+}
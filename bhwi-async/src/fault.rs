@@ -0,0 +1,161 @@
+//! Transport wrapper injecting faults in the exchanges, to test how the
+//! interpreters and the runner handle failing or misbehaving devices.
+
+use async_trait::async_trait;
+
+use crate::Transport;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// The exchange fails with `FaultError::Injected`.
+    Fail,
+    /// The response is truncated to the given length.
+    Truncate(usize),
+    /// The bits of the response byte at the given index are flipped.
+    FlipByte(usize),
+    /// The response is replaced.
+    Replace(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum FaultError<E> {
+    Transport(E),
+    Injected,
+}
+
+pub struct FaultInjector<T> {
+    pub inner: T,
+    /// Faults with the index of the exchange they apply to.
+    faults: Vec<(usize, Fault)>,
+    exchanges: usize,
+}
+
+impl<T> FaultInjector<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            faults: Vec::new(),
+            exchanges: 0,
+        }
+    }
+
+    /// Injects the fault in the exchange with the given index, starting at 0.
+    pub fn with_fault(mut self, exchange: usize, fault: Fault) -> Self {
+        self.faults.push((exchange, fault));
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for FaultInjector<T> {
+    type Error = FaultError<T::Error>;
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let index = self.exchanges;
+        self.exchanges += 1;
+        let faults: Vec<&Fault> = self
+            .faults
+            .iter()
+            .filter(|(i, _)| *i == index)
+            .map(|(_, fault)| fault)
+            .collect();
+        if faults.contains(&&Fault::Fail) {
+            return Err(FaultError::Injected);
+        }
+        let mut response = self
+            .inner
+            .exchange(command, encrypted)
+            .await
+            .map_err(FaultError::Transport)?;
+        for fault in faults {
+            match fault {
+                Fault::Fail => {}
+                Fault::Truncate(len) => response.truncate(*len),
+                Fault::FlipByte(i) => {
+                    if let Some(b) = response.get_mut(*i) {
+                        *b = !*b;
+                    }
+                }
+                Fault::Replace(data) => response = data.clone(),
+            }
+        }
+        Ok(response)
+    }
+    /// The frames sent count as exchanges, only `Fault::Fail` applies to them.
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        let index = self.exchanges;
+        self.exchanges += 1;
+        if self.faults.contains(&(index, Fault::Fail)) {
+            return Err(FaultError::Injected);
+        }
+        self.inner
+            .send(command, encrypted)
+            .await
+            .map_err(FaultError::Transport)
+    }
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.inner.reconnect().await.map_err(FaultError::Transport)
+    }
+    fn notify(&mut self, event: &bhwi::Event) {
+        self.inner.notify(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockLedger, Error, Ledger, HWI};
+    use bhwi::{
+        bitcoin::{bip32::DerivationPath, Network},
+        common,
+    };
+    use std::str::FromStr;
+
+    fn ledger(fault: Fault) -> Ledger<FaultInjector<MockLedger>> {
+        Ledger::new(
+            FaultInjector::new(MockLedger::new(&[0x01; 32], Network::Testnet)).with_fault(0, fault),
+        )
+    }
+
+    #[test]
+    fn test_faults_are_reported() {
+        futures::executor::block_on(async {
+            assert!(matches!(
+                ledger(Fault::Fail).get_master_fingerprint().await,
+                Err(Error::Transport(FaultError::Injected))
+            ));
+            assert!(matches!(
+                ledger(Fault::Truncate(1)).get_master_fingerprint().await,
+                Err(Error::Interpreter(common::Error::Serialization(_)))
+            ));
+            assert!(matches!(
+                ledger(Fault::Replace(vec![0x90, 0x00]))
+                    .get_master_fingerprint()
+                    .await,
+                Err(Error::Interpreter(common::Error::UnexpectedResult(_)))
+            ));
+            assert!(matches!(
+                ledger(Fault::Replace(vec![0x69, 0x85]))
+                    .get_master_fingerprint()
+                    .await,
+                Err(Error::Interpreter(_))
+            ));
+            assert!(ledger(Fault::FlipByte(10))
+                .get_extended_pubkey(DerivationPath::from_str("m/84'/1'/0'").unwrap(), false)
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_fault_applies_to_its_exchange_only() {
+        let mut ledger = Ledger::new(
+            FaultInjector::new(MockLedger::new(&[0x01; 32], Network::Testnet))
+                .with_fault(1, Fault::Fail),
+        );
+        futures::executor::block_on(async {
+            assert!(ledger.get_master_fingerprint().await.is_ok());
+            assert!(ledger.get_master_fingerprint().await.is_err());
+            assert!(ledger.get_master_fingerprint().await.is_ok());
+        });
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{HttpClient, Transport};
 use bhwi::{
+    authorization::Authorization,
     bitcoin::Network,
+    devices::Capabilities,
     jade::{JadeCommand, JadeError, JadeInterpreter, JadeResponse, JadeTransmit},
     Interpreter,
 };
@@ -9,6 +11,7 @@ pub struct Jade<T, S> {
     pub network: Network,
     pub transport: T,
     pub pinserver: S,
+    authorization: Option<Authorization>,
 }
 
 impl<T, S> Jade<T, S> {
@@ -17,13 +20,15 @@ impl<T, S> Jade<T, S> {
             network,
             transport,
             pinserver,
+            authorization: None,
         }
     }
 }
 
 impl<C, T, R, E, F, H> crate::CommonInterface<C, T, R, E> for Jade<F, H>
 where
-    C: Into<JadeCommand>,
+    C: TryInto<JadeCommand>,
+    C::Error: Into<JadeError>,
     T: From<JadeTransmit>,
     R: From<JadeResponse>,
     E: From<JadeError>,
@@ -45,6 +50,19 @@ where
             JadeInterpreter::default().with_network(self.network),
         )
     }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+    fn capabilities(&self) -> Capabilities {
+        bhwi::jade::capabilities()
+    }
+}
+
+impl<T, S> crate::Authorize for Jade<T, S> {
+    fn authorization_mut(&mut self) -> &mut Option<Authorization> {
+        &mut self.authorization
+    }
 }
 
 impl<T, S> crate::OnUnlock for Jade<T, S> {
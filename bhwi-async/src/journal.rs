@@ -0,0 +1,241 @@
+//! Journal of the signed transactions, for the hosts keeping an audit trail
+//! of what their devices signed.
+//!
+//! The devices are wrapped with `Journaled`, every psbt they sign is
+//! recorded into the sink of the host, nothing is recorded otherwise.
+
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bhwi::{
+    bitcoin::{
+        bip32::{DerivationPath, Fingerprint, Xpub},
+        Amount, Network, OutPoint, Psbt, TxOut, Txid,
+    },
+    devices::Capabilities,
+    ledger::WalletPolicy,
+    psbt::spent_utxo,
+};
+
+use crate::{session_log::system_clock, Error, HWI};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalInput {
+    pub previous_output: OutPoint,
+    /// Missing when the psbt does not hold the spent utxo.
+    pub amount: Option<Amount>,
+}
+
+/// A psbt signed by the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Milliseconds since the unix epoch, see [`Journaled::with_clock`].
+    pub timestamp_ms: u64,
+    pub txid: Txid,
+    /// Id of the wallet policy the psbt was signed with, see
+    /// [`WalletPolicy::id`].
+    pub policy_id: Option<[u8; 32]>,
+    pub inputs: Vec<JournalInput>,
+    pub outputs: Vec<TxOut>,
+}
+
+impl JournalEntry {
+    pub fn new(psbt: &Psbt, policy_id: Option<[u8; 32]>, timestamp_ms: u64) -> Self {
+        Self {
+            timestamp_ms,
+            txid: psbt.unsigned_tx.compute_txid(),
+            policy_id,
+            inputs: psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .enumerate()
+                .map(|(index, input)| JournalInput {
+                    previous_output: input.previous_output,
+                    amount: spent_utxo(psbt, index).map(|utxo| utxo.value),
+                })
+                .collect(),
+            outputs: psbt.unsigned_tx.output.clone(),
+        }
+    }
+}
+
+/// Receives the entries of the journal, to be stored or exported by the
+/// host.
+pub trait JournalSink {
+    fn record(&self, entry: &JournalEntry);
+}
+
+impl<S: JournalSink + ?Sized> JournalSink for &S {
+    fn record(&self, entry: &JournalEntry) {
+        (**self).record(entry)
+    }
+}
+
+impl<S: JournalSink + ?Sized> JournalSink for Rc<S> {
+    fn record(&self, entry: &JournalEntry) {
+        (**self).record(entry)
+    }
+}
+
+impl<S: JournalSink + ?Sized> JournalSink for Arc<S> {
+    fn record(&self, entry: &JournalEntry) {
+        (**self).record(entry)
+    }
+}
+
+/// Device recording the psbts it signed into the sink.
+pub struct Journaled<D, S> {
+    pub inner: D,
+    sink: S,
+    clock: fn() -> u64,
+}
+
+impl<D, S> Journaled<D, S> {
+    pub fn new(inner: D, sink: S) -> Self {
+        Self {
+            inner,
+            sink,
+            clock: system_clock,
+        }
+    }
+
+    /// Sets the clock of the timestamps, in milliseconds.
+    pub fn with_clock(mut self, clock: fn() -> u64) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl<D, S, E, F> HWI for Journaled<D, S>
+where
+    D: HWI<Error = Error<E, F>>,
+    S: JournalSink,
+    E: Debug,
+    F: Debug,
+{
+    type Error = Error<E, F>;
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+        self.inner.unlock(network).await
+    }
+
+    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+        self.inner.get_master_fingerprint().await
+    }
+
+    async fn get_extended_pubkey(
+        &mut self,
+        path: DerivationPath,
+        display: bool,
+    ) -> Result<Xpub, Self::Error> {
+        self.inner.get_extended_pubkey(path, display).await
+    }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        self.inner.register_wallet(policy).await
+    }
+
+    /// Only the psbts signed without error are recorded.
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        let policy_id = policy.as_ref().map(WalletPolicy::id);
+        let signed = self.inner.sign_psbt(psbt, policy, hmac).await?;
+        self.sink
+            .record(&JournalEntry::new(&signed, policy_id, (self.clock)()));
+        Ok(signed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareSigner;
+    use bhwi::bitcoin::{
+        absolute::LockTime, transaction::Version, CompressedPublicKey, ScriptBuf, Sequence,
+        Transaction, TxIn, Witness,
+    };
+    use std::cell::RefCell;
+    use std::str::FromStr;
+
+    #[derive(Default)]
+    struct Entries(RefCell<Vec<JournalEntry>>);
+
+    impl JournalSink for Entries {
+        fn record(&self, entry: &JournalEntry) {
+            self.0.borrow_mut().push(entry.clone());
+        }
+    }
+
+    #[test]
+    fn test_journaled_sign_psbt() {
+        let entries = Rc::new(Entries::default());
+        let signer = SoftwareSigner::new(&[0x01; 32], Network::Testnet).unwrap();
+        let mut signer = Journaled::new(signer, entries.clone()).with_clock(|| 42);
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let (fingerprint, xpub) = futures::executor::block_on(async {
+            (
+                signer.get_master_fingerprint().await.unwrap(),
+                signer
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .unwrap(),
+            )
+        });
+        let script_pubkey =
+            ScriptBuf::new_p2wpkh(&CompressedPublicKey(xpub.public_key).wpubkey_hash());
+        let output = TxOut {
+            value: Amount::from_sat(90_000),
+            script_pubkey: script_pubkey.clone(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![output.clone()],
+        })
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        });
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(xpub.public_key, (fingerprint, path));
+        let txid = psbt.unsigned_tx.compute_txid();
+
+        futures::executor::block_on(signer.sign_psbt(psbt, None, None)).unwrap();
+        assert_eq!(
+            *entries.0.borrow(),
+            vec![JournalEntry {
+                timestamp_ms: 42,
+                txid,
+                policy_id: None,
+                inputs: vec![JournalInput {
+                    previous_output: OutPoint::null(),
+                    amount: Some(Amount::from_sat(100_000)),
+                }],
+                outputs: vec![output],
+            }]
+        );
+    }
+}
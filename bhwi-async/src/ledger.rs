@@ -1,17 +1,215 @@
 use crate::{HttpClient, Transport};
 use async_trait::async_trait;
 use bhwi::{
-    ledger::{apdu::ApduCommand, LedgerCommand, LedgerError, LedgerInterpreter, LedgerResponse},
-    Interpreter,
+    authorization::Authorization,
+    bitcoin::{bip32::Fingerprint, Network},
+    common,
+    devices::Capabilities,
+    ledger::{
+        apdu::ApduCommand, legacy, AppInfo, DeviceModel, InstalledApp, LedgerCommand, LedgerConfig,
+        LedgerError, LedgerInterpreter, LedgerResponse, RetryPolicy,
+    },
+    path::PathPolicy,
+    Event, Interpreter,
 };
 
 pub struct Ledger<T> {
     pub transport: T,
+    /// Master fingerprint the returned xpubs are checked against.
+    master_fingerprint: Option<Fingerprint>,
+    /// Probes of a locked device before failing, with the interval between
+    /// them.
+    unlock_wait: Option<(usize, u32)>,
+    /// Network the commands and the answers are checked against.
+    network: Option<Network>,
+    retry_policy: Option<RetryPolicy>,
+    path_policy: PathPolicy,
+    /// Network of the legacy app open, see [`Ledger::ensure_app`].
+    legacy_app: Option<Network>,
+    /// App open, once ensured by [`Ledger::ensure_app`].
+    app: Option<AppInfo>,
+    deadline_ms: Option<u64>,
+    model: Option<DeviceModel>,
+    authorization: Option<Authorization>,
+    check_policy_keys: bool,
+    config: LedgerConfig,
 }
 
+/// Maximal number of app switches while ensuring the app is open: quitting
+/// the open app and opening the Bitcoin app.
+const MAX_APP_SWITCHES: usize = 2;
+
 impl<T> Ledger<T> {
     pub fn new(transport: T) -> Self {
-        Self { transport }
+        Self {
+            transport,
+            master_fingerprint: None,
+            unlock_wait: None,
+            network: None,
+            retry_policy: None,
+            path_policy: PathPolicy::Allow,
+            legacy_app: None,
+            app: None,
+            deadline_ms: None,
+            model: None,
+            authorization: None,
+            check_policy_keys: false,
+            config: LedgerConfig::default(),
+        }
+    }
+
+    /// Checks the keys of the policies with the origin of the device before
+    /// registering them or signing with them, see
+    /// [`crate::check_policy_keys`].
+    pub fn with_policy_key_check(mut self) -> Self {
+        self.check_policy_keys = true;
+        self
+    }
+
+    /// Adapts the commands to the model of the device. Its confirmation
+    /// timeout is not a deadline of the commands, the long reviews would be
+    /// cancelled: the hosts opt in with [`Ledger::with_deadline`].
+    pub fn with_model(mut self, model: DeviceModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// The model of the device, for the hosts to adapt their UI.
+    pub fn model(&self) -> Option<DeviceModel> {
+        self.model
+    }
+
+    /// Fails the xpub retrievals of a device with another master key, see
+    /// [`LedgerInterpreter::with_master_fingerprint`].
+    pub fn with_master_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.master_fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Fails the commands mixing networks, see
+    /// [`LedgerInterpreter::with_network`].
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Waits for the user to unlock a locked device instead of failing, see
+    /// [`LedgerInterpreter::with_unlock_wait`]. The runner waits for the
+    /// interval before each probe.
+    pub fn with_unlock_wait(mut self, probes: usize, interval_ms: u32) -> Self {
+        self.unlock_wait = Some((probes, interval_ms));
+        self
+    }
+
+    /// Sends the last apdu again on the transient failures of the device or
+    /// of the transport, see [`LedgerInterpreter::with_retry_policy`]. Each
+    /// retry waits for the delay of the policy.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Speaks the protocol of the Bitcoin app before 2.0, see
+    /// [`LedgerInterpreter::with_legacy_app`]. It is detected by
+    /// [`Ledger::ensure_app`].
+    pub fn with_legacy_app(mut self, network: Network) -> Self {
+        self.legacy_app = Some(network);
+        self
+    }
+
+    /// Checks the paths of the commands against the standard schemes, see
+    /// [`LedgerInterpreter::with_path_policy`]. The warnings are logged.
+    pub fn with_path_policy(mut self, policy: PathPolicy) -> Self {
+        self.path_policy = policy;
+        self
+    }
+
+    /// Fails the commands still running after the deadline, see
+    /// [`LedgerInterpreter::with_deadline`]. An exchange the device does
+    /// not answer in time is abandoned, see [`crate::timer`].
+    pub fn with_deadline(mut self, deadline_ms: u64) -> Self {
+        self.deadline_ms = Some(deadline_ms);
+        self
+    }
+
+    /// Speaks to a fork of the Bitcoin app, see
+    /// [`LedgerInterpreter::with_config`].
+    pub fn with_config(mut self, config: LedgerConfig) -> Self {
+        self.config = config;
+        self
+    }
+}
+
+impl<T: Transport> Ledger<T> {
+    /// Runs the command specific to the Ledger devices, the transport
+    /// reconnects once the device re-enumerates after an app switch.
+    async fn run_ledger(
+        &mut self,
+        command: LedgerCommand,
+    ) -> Result<LedgerResponse, crate::Error<T::Error, LedgerError>> {
+        let mut intpr = LedgerInterpreter::<
+            LedgerCommand,
+            ApduCommand,
+            LedgerResponse,
+            common::Error,
+        >::default()
+        .with_config(self.config.clone());
+        let mut transmit = Some(intpr.start(command)?);
+        while let Some(apdu) = transmit {
+            let res = self
+                .transport
+                .exchange(&apdu.encode(), false)
+                .await
+                .map_err(crate::Error::Transport)?;
+            transmit = intpr.exchange(res)?;
+        }
+        let mut reenumerating = false;
+        while let Some(event) = intpr.poll_event() {
+            self.transport.notify(&event);
+            reenumerating |= event == Event::Reenumerating;
+        }
+        if reenumerating {
+            self.transport
+                .reconnect()
+                .await
+                .map_err(crate::Error::Transport)?;
+        }
+        Ok(intpr.end()?)
+    }
+
+    /// Lists the apps installed, the device must be on its dashboard, see
+    /// [`Ledger::quit_app`].
+    pub async fn list_apps(
+        &mut self,
+    ) -> Result<Vec<InstalledApp>, crate::Error<T::Error, LedgerError>> {
+        match self.run_ledger(LedgerCommand::ListApps).await? {
+            LedgerResponse::Apps(apps) => Ok(apps),
+            _ => Err(common::Error::NoErrorOrResult.into()),
+        }
+    }
+
+    /// Quits the open app, the transport reconnects to the dashboard.
+    pub async fn quit_app(&mut self) -> Result<(), crate::Error<T::Error, LedgerError>> {
+        self.run_ledger(LedgerCommand::QuitApp).await.map(|_| ())
+    }
+
+    /// Opens the Bitcoin app of the network, quitting the open app if needed,
+    /// and returns its info. The transport reconnects after each app switch.
+    /// The legacy protocol is used with the apps before 2.0.
+    pub async fn ensure_app(
+        &mut self,
+        network: Network,
+    ) -> Result<AppInfo, crate::Error<T::Error, LedgerError>> {
+        for _ in 0..=MAX_APP_SWITCHES {
+            if let LedgerResponse::AppInfo(info) =
+                self.run_ledger(LedgerCommand::EnsureApp(network)).await?
+            {
+                self.legacy_app = legacy::is_legacy_app(&info).then_some(network);
+                self.app = Some(info.clone());
+                return Ok(info);
+            }
+        }
+        Err(common::Error::from(LedgerError::FailedToOpenApp(Vec::new())).into())
     }
 }
 
@@ -32,11 +230,47 @@ where
         &dyn HttpClient<Error = Self::HttpClientError>,
         impl Interpreter<Command = C, Transmit = T, Response = R, Error = E>,
     ) {
-        (
-            &mut self.transport,
-            &DummyClient {},
-            LedgerInterpreter::default(),
-        )
+        let mut intpr = LedgerInterpreter::default();
+        if let Some(fg) = self.master_fingerprint {
+            intpr = intpr.with_master_fingerprint(fg);
+        }
+        if let Some(network) = self.network {
+            intpr = intpr.with_network(network);
+        }
+        if let Some((probes, interval_ms)) = self.unlock_wait {
+            intpr = intpr.with_unlock_wait(probes, interval_ms);
+        }
+        if let Some(policy) = self.retry_policy {
+            intpr = intpr.with_retry_policy(policy);
+        }
+        intpr = intpr.with_path_policy(self.path_policy);
+        if let Some(network) = self.legacy_app {
+            intpr = intpr.with_legacy_app(network);
+        }
+        if let Some(deadline_ms) = self.deadline_ms {
+            intpr = intpr.with_deadline(deadline_ms);
+        }
+        intpr = intpr.with_config(self.config.clone());
+        (&mut self.transport, &DummyClient {}, intpr)
+    }
+    fn requires_policy(&self) -> bool {
+        true
+    }
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+    fn checks_policy_keys(&self) -> bool {
+        self.check_policy_keys
+    }
+    /// The capabilities of the app ensured, of the last releases before.
+    fn capabilities(&self) -> Capabilities {
+        bhwi::ledger::capabilities(self.app.as_ref())
+    }
+}
+
+impl<T> crate::Authorize for Ledger<T> {
+    fn authorization_mut(&mut self) -> &mut Option<Authorization> {
+        &mut self.authorization
     }
 }
 
@@ -54,3 +288,444 @@ impl HttpClient for DummyClient {
         unreachable!("Coldcard does not need http client")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::MockLedger, Authorize, DisplayAddress, DisplayXpub, HealthCheck, SilentPayments, HWI,
+    };
+    use bhwi::authorization::Rule;
+    use bhwi::bitcoin::{bip32::DerivationPath, hex::FromHex, Network};
+    use bhwi::ledger::WalletPolicy;
+    use std::str::FromStr;
+
+    const SEED: [u8; 32] = [0x01; 32];
+
+    #[test]
+    fn test_ledger_with_mock_device() {
+        let mock = MockLedger::new(&SEED, Network::Testnet);
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let expected_fingerprint = mock.fingerprint();
+        let expected_xpub = mock.xpub(&path);
+        let mut ledger = Ledger::new(mock);
+
+        futures::executor::block_on(async {
+            ledger.unlock(Network::Testnet).await.unwrap();
+            let fingerprint = ledger.get_master_fingerprint().await.unwrap();
+            assert_eq!(fingerprint, expected_fingerprint);
+            let xpub = ledger.get_extended_pubkey(path, false).await.unwrap();
+            assert_eq!(xpub, expected_xpub);
+        });
+        assert_eq!(ledger.transport.commands.len(), 3);
+    }
+
+    #[test]
+    fn test_authorization() {
+        let allowed = DerivationPath::from_str("m/84'/1'").unwrap();
+        let mut ledger = Ledger::new(MockLedger::new(&SEED, Network::Testnet))
+            .with_authorization(Authorization::new().with_rule(Rule::AllowedPaths(vec![allowed])));
+        futures::executor::block_on(async {
+            assert!(ledger
+                .get_extended_pubkey(DerivationPath::from_str("m/84'/1'/0'").unwrap(), false)
+                .await
+                .is_ok());
+            assert!(matches!(
+                ledger
+                    .get_extended_pubkey(DerivationPath::from_str("m/86'/1'/0'").unwrap(), false)
+                    .await,
+                Err(crate::Error::Interpreter(common::Error::PolicyDenied(_)))
+            ));
+        });
+    }
+
+    /// Never answers the commands.
+    struct Unanswered;
+
+    #[async_trait(?Send)]
+    impl Transport for Unanswered {
+        type Error = ();
+        async fn exchange(&mut self, _command: &[u8], _encrypted: bool) -> Result<Vec<u8>, ()> {
+            futures::future::pending().await
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let mut ledger = Ledger::new(Unanswered).with_deadline(20);
+        assert!(matches!(
+            futures::executor::block_on(ledger.get_master_fingerprint()),
+            Err(crate::Error::Interpreter(common::Error::Timeout))
+        ));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        use crate::fault::{Fault, FaultInjector};
+        let transport = FaultInjector::new(MockLedger::new(&SEED, Network::Testnet))
+            .with_fault(0, Fault::Replace(vec![0x6f, 0x00]))
+            .with_fault(1, Fault::Truncate(1));
+        let mut ledger = Ledger::new(transport).with_retry_policy(RetryPolicy {
+            max_retries: 2,
+            backoff_ms: 20,
+        });
+        let start = std::time::Instant::now();
+        futures::executor::block_on(ledger.get_master_fingerprint()).unwrap();
+        // 20ms before the first retry, 40ms before the second one.
+        assert!(start.elapsed() >= std::time::Duration::from_millis(60));
+    }
+
+    #[test]
+    fn test_unlock_wait() {
+        use crate::fault::{Fault, FaultInjector};
+        let transport = FaultInjector::new(MockLedger::new(&SEED, Network::Testnet))
+            .with_fault(0, Fault::Replace(vec![0x55, 0x15]));
+        let mut ledger = Ledger::new(transport).with_unlock_wait(1, 30);
+        let start = std::time::Instant::now();
+        futures::executor::block_on(ledger.get_master_fingerprint()).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(30));
+        // The probe is sent between the two attempts of the command.
+        assert_eq!(ledger.transport.inner.commands.len(), 3);
+    }
+
+    #[test]
+    fn test_policy_key_check() {
+        let mock = MockLedger::new(&SEED, Network::Testnet);
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let source = (mock.fingerprint(), path.clone());
+        let xpub = mock.xpub(&path);
+        let typo = mock.xpub(&DerivationPath::from_str("m/84'/1'/1'").unwrap());
+        let mut ledger = Ledger::new(mock).with_policy_key_check();
+        futures::executor::block_on(async {
+            let policy = WalletPolicy::new_singlesig(source.clone(), xpub).unwrap();
+            assert!(crate::check_policy_keys(&mut ledger, &policy).await.is_ok());
+
+            let policy = WalletPolicy::new_singlesig(source, typo).unwrap();
+            assert!(matches!(
+                ledger.register_wallet(policy).await,
+                Err(crate::Error::Interpreter(common::Error::KeyMismatch { expected, found }))
+                    if *expected == typo && *found == xpub
+            ));
+        });
+    }
+
+    #[test]
+    fn test_display_xpub() {
+        let mock = MockLedger::new(&SEED, Network::Testnet);
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let expected_xpub = mock.xpub(&path);
+        // The callback denies the address with the path and the policy of
+        // the command.
+        let mut ledger = Ledger::new(mock).with_authorization(Authorization::new().with_rule(
+            Rule::Callback(Box::new(|c| match c {
+                common::Command::DisplayAddress { path, policy, .. } => Err(format!(
+                    "{} {}",
+                    path,
+                    policy.as_ref().map_or("", |p| p.descriptor_template.as_str())
+                )),
+                _ => Ok(()),
+            })),
+        ));
+        futures::executor::block_on(async {
+            let xpub = ledger
+                .display_xpub(path.clone(), common::DisplayMode::Hidden)
+                .await
+                .unwrap();
+            assert_eq!(xpub, expected_xpub);
+            assert!(matches!(
+                ledger
+                    .display_xpub(path, common::DisplayMode::FirstAddress)
+                    .await,
+                Err(crate::Error::Interpreter(common::Error::PolicyDenied(reason)))
+                    if reason == "84'/1'/0'/0/0 wpkh(@0/**)"
+            ));
+        });
+    }
+
+    #[test]
+    fn test_display_address() {
+        let mock = MockLedger::new(&SEED, Network::Testnet);
+        let mut ledger = Ledger::new(mock).with_authorization(Authorization::new().with_rule(
+            Rule::Callback(Box::new(|c| match c {
+                common::Command::DisplayAddress { path, policy, .. } => Err(format!(
+                    "{} {}",
+                    path,
+                    policy.as_ref().map_or("", |p| p.descriptor_template.as_str())
+                )),
+                _ => Ok(()),
+            })),
+        ));
+        futures::executor::block_on(async {
+            assert!(matches!(
+                ledger
+                    .display_address(
+                        DerivationPath::from_str("m/86'/1'/0'/1/7").unwrap(),
+                        None,
+                        None
+                    )
+                    .await,
+                Err(crate::Error::Interpreter(common::Error::PolicyDenied(reason)))
+                    if reason == "86'/1'/0'/1/7 tr(@0/**)"
+            ));
+            assert!(matches!(
+                ledger
+                    .display_address(DerivationPath::from_str("m/0").unwrap(), None, None)
+                    .await,
+                Err(crate::Error::Interpreter(
+                    common::Error::MissingCommandInfo(_)
+                ))
+            ));
+        });
+    }
+
+    /// Psbt spending an output of the policy to its next receive address.
+    fn policy_psbt(policy: &WalletPolicy) -> bhwi::bitcoin::Psbt {
+        use bhwi::bitcoin::{
+            absolute::LockTime, transaction::Version, Amount, OutPoint, Psbt, Sequence,
+            Transaction, TxIn, TxOut,
+        };
+        let (script_pubkey, input) = bhwi::psbt::policy_input(policy, false, 0).unwrap();
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: policy.derive_script(false, 1).unwrap(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0] = input;
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        });
+        psbt
+    }
+
+    #[test]
+    fn test_register_wallet_and_get_address() {
+        let mock = MockLedger::new(&SEED, Network::Testnet);
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let source = (mock.fingerprint(), path.clone());
+        let xpub = mock.xpub(&path);
+        let cosigner = MockLedger::new(&[0x02; 32], Network::Testnet);
+        let policy = WalletPolicy::new_multisig(
+            "Cold storage".to_string(),
+            bhwi::wallet::Version::V2,
+            bhwi::wallet::AddressType::NativeSegwit,
+            2,
+            [
+                (source, xpub),
+                ((cosigner.fingerprint(), path.clone()), cosigner.xpub(&path)),
+            ],
+            false,
+        )
+        .unwrap();
+        let mut ledger = Ledger::new(mock);
+        futures::executor::block_on(async {
+            let (id, hmac) = ledger.register_wallet(policy.clone()).await.unwrap();
+            assert_eq!(id, policy.id());
+
+            let address = ledger
+                .display_address(
+                    DerivationPath::from_str("m/48'/1'/0'/2'/1/3").unwrap(),
+                    Some(policy.clone()),
+                    Some(hmac),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                address.assume_checked(),
+                policy.derive_address(true, 3, Network::Testnet).unwrap()
+            );
+
+            // The policy is not registered without its hmac.
+            assert!(ledger
+                .display_address(
+                    DerivationPath::from_str("m/48'/1'/0'/2'/0/0").unwrap(),
+                    Some(policy),
+                    Some([0x00; 32]),
+                )
+                .await
+                .is_err());
+
+            // The default policies need no registration.
+            let address = ledger
+                .display_address(
+                    DerivationPath::from_str("m/84'/1'/0'/0/2").unwrap(),
+                    None,
+                    None,
+                )
+                .await
+                .unwrap();
+            assert!(address.is_valid_for_network(Network::Testnet));
+        });
+        // The policy is requested by the mock with client commands.
+        assert!(ledger
+            .transport
+            .commands
+            .iter()
+            .any(|c| c[0] == bhwi::ledger::apdu::Cla::Framework as u8));
+    }
+
+    #[test]
+    fn test_sign_psbt() {
+        let mock = MockLedger::new(&SEED, Network::Testnet);
+        let policies = ["m/84'/1'/0'", "m/86'/1'/0'"].map(|path| {
+            let path = DerivationPath::from_str(path).unwrap();
+            WalletPolicy::new_singlesig((mock.fingerprint(), path.clone()), mock.xpub(&path))
+                .unwrap()
+        });
+        let mut ledger = Ledger::new(mock);
+        futures::executor::block_on(async {
+            for policy in policies {
+                let psbt = policy_psbt(&policy);
+                let signed = ledger.sign_psbt(psbt, None, None).await.unwrap();
+                if policy.is_taproot() {
+                    assert!(signed.inputs[0].tap_key_sig.is_some());
+                } else {
+                    assert_eq!(signed.inputs[0].partial_sigs.len(), 1);
+                }
+                let mut finalized = signed.clone();
+                bhwi::psbt::finalize(&mut finalized, &policy).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_silent_payments_unsupported() {
+        let mut ledger = Ledger::new(MockLedger::new(&SEED, Network::Testnet));
+        assert!(matches!(
+            futures::executor::block_on(ledger.get_silent_payment_keys(0)),
+            Err(crate::Error::Interpreter(
+                bhwi::common::Error::UnsupportedCommand("get_silent_payment_keys")
+            ))
+        ));
+        assert!(ledger.transport.commands.is_empty());
+    }
+
+    #[test]
+    fn test_health_check() {
+        let mut ledger = Ledger::new(MockLedger::new(&SEED, Network::Testnet));
+        let status = futures::executor::block_on(ledger.get_status()).unwrap();
+        assert_eq!(
+            status,
+            bhwi::common::DeviceStatus {
+                connected: true,
+                unlocked: true,
+                app: Some("Bitcoin Test".to_string()),
+                version: Some("2.2.4".to_string()),
+            }
+        );
+        assert_eq!(ledger.transport.commands.len(), 1);
+
+        ledger.transport.locked = true;
+        let status = futures::executor::block_on(ledger.get_status()).unwrap();
+        assert!(status.connected && !status.unlocked && status.app.is_none());
+        assert_eq!(ledger.transport.commands.len(), 2);
+    }
+
+    #[test]
+    fn test_ensure_app() {
+        let mut mock = MockLedger::new(&SEED, Network::Testnet);
+        mock.app = "Ethereum".to_string();
+        let mut ledger = Ledger::new(mock);
+        assert!(HWI::capabilities(&ledger)
+            .networks
+            .contains(&Network::Bitcoin));
+        futures::executor::block_on(async {
+            let info = ledger.ensure_app(Network::Testnet).await.unwrap();
+            assert!(info.is_bitcoin_app(Network::Testnet));
+        });
+        let capabilities = HWI::capabilities(&ledger);
+        assert!(capabilities.policy_registration);
+        assert!(!capabilities.networks.contains(&Network::Bitcoin));
+        // Quit then open, each followed by the re-enumeration.
+        assert_eq!(ledger.transport.reconnects, 2);
+        assert_eq!(ledger.transport.commands.len(), 5);
+
+        futures::executor::block_on(async {
+            ledger.ensure_app(Network::Testnet).await.unwrap();
+        });
+        assert_eq!(ledger.transport.reconnects, 2);
+    }
+
+    #[test]
+    fn test_list_apps() {
+        let mut mock = MockLedger::new(&SEED, Network::Testnet);
+        mock.installed.push("Ethereum".to_string());
+        let mut ledger = Ledger::new(mock);
+        futures::executor::block_on(async {
+            assert!(matches!(
+                ledger.list_apps().await,
+                Err(crate::Error::Interpreter(common::Error::Request(..)))
+            ));
+            ledger.quit_app().await.unwrap();
+            let apps = ledger.list_apps().await.unwrap();
+            let names: Vec<&str> = apps.iter().map(|app| app.name.as_str()).collect();
+            assert_eq!(names, vec!["Bitcoin Test", "Ethereum"]);
+        });
+        assert_eq!(ledger.transport.reconnects, 1);
+    }
+
+    /// Test vector 1 of BIP-32.
+    const BIP32_TV1_SEED: &str = "000102030405060708090a0b0c0d0e0f";
+    const BIP32_TV1_FINGERPRINT: &str = "3442193e";
+    const BIP32_TV1_XPUBS: [(&str, &str); 4] = [
+        ("m", "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"),
+        ("m/0'", "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw"),
+        ("m/0'/1", "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ"),
+        ("m/0'/1/2'", "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5"),
+    ];
+
+    #[test]
+    fn test_bip32_vectors() {
+        let seed = Vec::<u8>::from_hex(BIP32_TV1_SEED).unwrap();
+        let mut ledger = Ledger::new(MockLedger::new(&seed, Network::Bitcoin));
+        futures::executor::block_on(async {
+            let fingerprint = ledger.get_master_fingerprint().await.unwrap();
+            assert_eq!(fingerprint.to_string(), BIP32_TV1_FINGERPRINT);
+            for (path, expected) in BIP32_TV1_XPUBS {
+                let xpub = ledger
+                    .get_extended_pubkey(DerivationPath::from_str(path).unwrap(), false)
+                    .await
+                    .unwrap();
+                assert_eq!(xpub.to_string(), expected, "path {}", path);
+            }
+        });
+    }
+
+    #[test]
+    fn test_master_fingerprint_check() {
+        let seed = Vec::<u8>::from_hex(BIP32_TV1_SEED).unwrap();
+        let fingerprint = Fingerprint::from_str(BIP32_TV1_FINGERPRINT).unwrap();
+        let mut ledger = Ledger::new(MockLedger::new(&seed, Network::Bitcoin))
+            .with_master_fingerprint(fingerprint);
+        futures::executor::block_on(async {
+            for (path, _) in BIP32_TV1_XPUBS {
+                ledger
+                    .get_extended_pubkey(DerivationPath::from_str(path).unwrap(), false)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut ledger = Ledger::new(MockLedger::new(&SEED, Network::Bitcoin))
+            .with_master_fingerprint(fingerprint);
+        futures::executor::block_on(async {
+            for path in ["m", "m/0'"] {
+                assert!(matches!(
+                    ledger
+                        .get_extended_pubkey(DerivationPath::from_str(path).unwrap(), false)
+                        .await,
+                    Err(crate::Error::Interpreter(common::Error::MismatchedDevice))
+                ));
+            }
+        });
+    }
+}
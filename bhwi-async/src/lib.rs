@@ -1,25 +1,65 @@
+pub mod batch;
+pub mod cache;
 pub mod coldcard;
+pub mod discovery;
+pub mod exclusive;
+pub mod fault;
 pub mod jade;
+pub mod journal;
 pub mod ledger;
+pub mod metrics;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+pub mod registry;
+pub mod session;
+pub mod session_log;
+pub mod software;
+pub mod specter;
+pub mod timer;
+pub mod transcript;
 pub mod transport;
+pub mod trezor;
 
 use std::fmt::Debug;
 
 use async_trait::async_trait;
 use bhwi::{
+    authorization::Authorization,
+    bip85,
     bitcoin::{
+        address::NetworkUnchecked,
         bip32::{DerivationPath, Fingerprint, Xpub},
-        Network,
+        Address, Network, Psbt,
     },
-    common, Interpreter,
+    common,
+    devices::Capabilities,
+    ledger::WalletPolicy,
+    silentpayments::SilentPaymentKeys,
+    Event, Interpreter,
 };
 pub use jade::Jade;
 pub use ledger::Ledger;
+pub use specter::Specter;
+pub use trezor::Trezor;
 
 #[async_trait(?Send)]
 pub trait Transport {
     type Error: Debug;
     async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error>;
+    /// Writes a frame the device does not reply to. The default exchanges it
+    /// and drops the reply, for the devices acknowledging every frame.
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        self.exchange(command, encrypted).await.map(|_| ())
+    }
+    /// Waits for the device to re-enumerate and reopens the connection, the
+    /// connection is kept by default.
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Receives the events of the interpreter of the running command, before
+    /// its next transmit, for the host to report them to the user. They are
+    /// ignored by default.
+    fn notify(&mut self, _event: &Event) {}
 }
 
 #[async_trait(?Send)]
@@ -38,6 +78,109 @@ pub trait HWI {
         path: DerivationPath,
         display: bool,
     ) -> Result<Xpub, Self::Error>;
+    /// Registers the policy on the device, returns its id and the proof of
+    /// its registration.
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error>;
+    /// Returns the psbt with the signatures of the device. The policy and its
+    /// proof of registration are given for the wallets other than the
+    /// default single signature ones.
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error>;
+    /// Features of the device, for the UIs to disable the actions it does
+    /// not support. None are known by default.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// Derivation of BIP-85 child entropy, for the backends exposing it.
+#[async_trait(?Send)]
+pub trait Bip85 {
+    type Error: Debug;
+    async fn bip85_derive(
+        &mut self,
+        application: bip85::Application,
+        index: u32,
+    ) -> Result<Vec<u8>, Self::Error>;
+}
+
+/// Export of the keys of the silent payments, see
+/// `bhwi::silentpayments`. The devices without support fail with
+/// `UnsupportedCommand`.
+#[async_trait(?Send)]
+pub trait SilentPayments {
+    type Error: Debug;
+    async fn get_silent_payment_keys(
+        &mut self,
+        account: u32,
+    ) -> Result<SilentPaymentKeys, Self::Error>;
+}
+
+/// Cheap check of the state of the device, to be polled by the wallets
+/// showing whether the device is connected: it never prompts the user, and
+/// a transport failure is reported as a disconnected device.
+#[async_trait(?Send)]
+pub trait HealthCheck {
+    type Error: Debug;
+    async fn get_status(&mut self) -> Result<common::DeviceStatus, Self::Error>;
+}
+
+/// Export of an xpub displayed on the device as the mode selects, see
+/// [`common::DisplayMode`]. The first receive address of the account is
+/// displayed once the xpub is returned, with the default single signature
+/// policy for the devices requiring one.
+#[async_trait(?Send)]
+pub trait DisplayXpub {
+    type Error: Debug;
+    async fn display_xpub(
+        &mut self,
+        path: DerivationPath,
+        mode: common::DisplayMode,
+    ) -> Result<Xpub, Self::Error>;
+}
+
+/// Display of the address at the path on the device, the path ending with
+/// the change and the index for the policies. The devices requiring a policy
+/// display the single signature addresses with the default policy of the
+/// account of the path.
+#[async_trait(?Send)]
+pub trait DisplayAddress {
+    type Error: Debug;
+    async fn display_address(
+        &mut self,
+        path: DerivationPath,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Address<NetworkUnchecked>, Self::Error>;
+    /// Returns the change and the index of the address in the policy, the
+    /// device displaying it to be checked by the user.
+    async fn verify_owned_address(
+        &mut self,
+        address: Address<NetworkUnchecked>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        gap_limit: u32,
+    ) -> Result<(bool, u32), Self::Error>;
+}
+
+/// Signature of a message with the key at the path, returned in base64. The
+/// signature is verified against the key of the path, retrieved from the
+/// device.
+#[async_trait(?Send)]
+pub trait SignMessage {
+    type Error: Debug;
+    async fn sign_message(
+        &mut self,
+        path: DerivationPath,
+        message: &[u8],
+    ) -> Result<String, Self::Error>;
 }
 
 #[derive(Debug)]
@@ -47,6 +190,43 @@ pub enum Error<E, F> {
     Interpreter(common::Error),
 }
 
+impl<E, F> Error<E, F> {
+    /// Returns a short code of the kind of error, to count errors by kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Transport(_) => "transport",
+            Error::HttpClient(_) => "http_client",
+            Error::Interpreter(e) => match e {
+                common::Error::Encryption(_) => "encryption",
+                common::Error::NoErrorOrResult => "no_error_or_result",
+                common::Error::MissingCommandInfo(_) => "missing_command_info",
+                common::Error::UnexpectedResult(_) => "unexpected_result",
+                common::Error::Rpc(..) => "rpc",
+                common::Error::Serialization(_) => "serialization",
+                common::Error::Request(_) => "request",
+                common::Error::AuthenticationRefused => "authentication_refused",
+                common::Error::UserRefused => "user_refused",
+                common::Error::DeviceLocked => "device_locked",
+                common::Error::NetworkMismatch => "network_mismatch",
+                common::Error::UnsupportedSighash(_) => "unsupported_sighash",
+                common::Error::UnusualPath(_) => "unusual_path",
+                common::Error::InvalidPolicy(_) => "invalid_policy",
+                common::Error::UnsupportedCommand(_) => "unsupported_command",
+                common::Error::Cancelled => "cancelled",
+                common::Error::Timeout => "timeout",
+                common::Error::DeviceBusy => "device_busy",
+                common::Error::AddressNotOwned => "address_not_owned",
+                common::Error::MismatchedDevice => "mismatched_device",
+                common::Error::DeviceNotConnected(_) => "device_not_connected",
+                common::Error::PolicyDenied(_) => "policy_denied",
+                common::Error::KeyMismatch { .. } => "key_mismatch",
+                common::Error::InvalidDeviceSignature(_) => "invalid_device_signature",
+                common::Error::Device(_) => "device",
+            },
+        }
+    }
+}
+
 impl<E, F> From<common::Error> for Error<E, F> {
     fn from(value: common::Error) -> Self {
         Self::Interpreter(value)
@@ -74,6 +254,10 @@ where
         Ok(())
     }
 
+    fn capabilities(&self) -> Capabilities {
+        CommonInterface::<common::Command, common::Transmit, common::Response, common::Error>::capabilities(self)
+    }
+
     async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
         if let common::Response::MasterFingerprint(fg) =
             run_command(self, common::Command::GetMasterFingerprint).await?
@@ -97,12 +281,251 @@ where
             Err(common::Error::NoErrorOrResult.into())
         }
     }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        if self.checks_policy_keys() {
+            check_policy_keys(self, &policy).await?;
+        }
+        if let common::Response::WalletRegistered { id, hmac } =
+            run_command(self, common::Command::RegisterWallet { policy }).await?
+        {
+            Ok((id, hmac))
+        } else {
+            Err(common::Error::NoErrorOrResult.into())
+        }
+    }
+
+    /// The signatures returned by the devices not signing the psbt itself,
+    /// like Ledger, are added to its inputs. Without policy, the devices
+    /// requiring one sign the standard single signature psbts with their
+    /// default policy, the account xpub is retrieved if not in the psbt.
+    async fn sign_psbt(
+        &mut self,
+        mut psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        let policy = match (policy, bhwi::psbt::default_account(&psbt)) {
+            (None, Some(account))
+                if self.requires_policy() && bhwi::psbt::default_policy(&psbt).is_none() =>
+            {
+                let xpub = self.get_extended_pubkey(account.1.clone(), false).await?;
+                WalletPolicy::new_singlesig(account, xpub).ok()
+            }
+            (policy, _) => policy,
+        };
+        if let Some(policy) = policy.as_ref().filter(|_| self.checks_policy_keys()) {
+            check_policy_keys(self, policy).await?;
+        }
+        let command = common::Command::SignPsbt {
+            psbt: Box::new(psbt.clone()),
+            policy,
+            hmac,
+        };
+        // The signatures are verified before being returned, see
+        // `bhwi::verify`.
+        match run_command(self, command).await? {
+            common::Response::SignedPsbt(signed) => {
+                bhwi::verify::verify_signed_psbt(&psbt, &signed)?;
+                Ok(*signed)
+            }
+            common::Response::Signatures(signatures) => {
+                bhwi::verify::verify_signatures(
+                    &psbt,
+                    signatures.iter().map(|(i, sig)| (*i, sig)),
+                )?;
+                for (index, signature) in signatures {
+                    let input = psbt.inputs.get_mut(index).ok_or_else(|| {
+                        common::Error::UnexpectedResult(index.to_be_bytes().to_vec())
+                    })?;
+                    signature.add_to(input);
+                }
+                Ok(psbt)
+            }
+            _ => Err(common::Error::NoErrorOrResult.into()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<D> SilentPayments for D
+where
+    D: CommonInterface<common::Command, common::Transmit, common::Response, common::Error>,
+{
+    type Error = Error<D::TransportError, D::HttpClientError>;
+    async fn get_silent_payment_keys(
+        &mut self,
+        account: u32,
+    ) -> Result<SilentPaymentKeys, Self::Error> {
+        if let common::Response::SilentPaymentKeys(keys) =
+            run_command(self, common::Command::GetSilentPaymentKeys { account }).await?
+        {
+            Ok(keys)
+        } else {
+            Err(common::Error::NoErrorOrResult.into())
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<D> HealthCheck for D
+where
+    D: CommonInterface<common::Command, common::Transmit, common::Response, common::Error>,
+{
+    type Error = Error<D::TransportError, D::HttpClientError>;
+    async fn get_status(&mut self) -> Result<common::DeviceStatus, Self::Error> {
+        match run_command(self, common::Command::GetStatus).await {
+            Ok(common::Response::Status(status)) => Ok(status),
+            Ok(_) => Err(common::Error::NoErrorOrResult.into()),
+            Err(Error::Transport(_)) => Ok(common::DeviceStatus::default()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<D> DisplayXpub for D
+where
+    D: CommonInterface<common::Command, common::Transmit, common::Response, common::Error>
+        + OnUnlock,
+{
+    type Error = Error<D::TransportError, D::HttpClientError>;
+    async fn display_xpub(
+        &mut self,
+        path: DerivationPath,
+        mode: common::DisplayMode,
+    ) -> Result<Xpub, Self::Error> {
+        let xpub = self
+            .get_extended_pubkey(path.clone(), mode == common::DisplayMode::Xpub)
+            .await?;
+        if mode == common::DisplayMode::FirstAddress {
+            let policy = if self.requires_policy() {
+                let fg = self.get_master_fingerprint().await?;
+                Some(
+                    WalletPolicy::new_singlesig((fg, path.clone()), xpub)
+                        .map_err(|e| common::Error::Serialization(format!("{:?}", e)))?,
+                )
+            } else {
+                None
+            };
+            let command = common::Command::DisplayAddress {
+                path: common::DisplayMode::first_address(&path),
+                policy,
+                hmac: None,
+            };
+            if !matches!(
+                run_command(self, command).await?,
+                common::Response::Address(_)
+            ) {
+                return Err(common::Error::NoErrorOrResult.into());
+            }
+        }
+        Ok(xpub)
+    }
+}
+
+#[async_trait(?Send)]
+impl<D> DisplayAddress for D
+where
+    D: CommonInterface<common::Command, common::Transmit, common::Response, common::Error>
+        + OnUnlock,
+{
+    type Error = Error<D::TransportError, D::HttpClientError>;
+    async fn display_address(
+        &mut self,
+        path: DerivationPath,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Address<NetworkUnchecked>, Self::Error> {
+        let policy = match policy {
+            None if self.requires_policy() => {
+                let account = path
+                    .as_ref()
+                    .len()
+                    .checked_sub(2)
+                    .map(|len| DerivationPath::from(&path.as_ref()[..len]))
+                    .ok_or(common::Error::MissingCommandInfo("change and index"))?;
+                let xpub = self.get_extended_pubkey(account.clone(), false).await?;
+                let fg = self.get_master_fingerprint().await?;
+                Some(
+                    WalletPolicy::new_singlesig((fg, account), xpub)
+                        .map_err(|e| common::Error::Serialization(format!("{:?}", e)))?,
+                )
+            }
+            policy => policy,
+        };
+        match run_command(self, common::Command::DisplayAddress { path, policy, hmac }).await? {
+            common::Response::Address(address) => Ok(address),
+            _ => Err(common::Error::NoErrorOrResult.into()),
+        }
+    }
+
+    async fn verify_owned_address(
+        &mut self,
+        address: Address<NetworkUnchecked>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        gap_limit: u32,
+    ) -> Result<(bool, u32), Self::Error> {
+        let command = common::Command::VerifyOwnedAddress {
+            address,
+            policy,
+            hmac,
+            gap_limit,
+        };
+        match run_command(self, command).await? {
+            common::Response::OwnedAddress { change, index } => Ok((change, index)),
+            _ => Err(common::Error::NoErrorOrResult.into()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<D> SignMessage for D
+where
+    D: CommonInterface<common::Command, common::Transmit, common::Response, common::Error>
+        + OnUnlock,
+{
+    type Error = Error<D::TransportError, D::HttpClientError>;
+    async fn sign_message(
+        &mut self,
+        path: DerivationPath,
+        message: &[u8],
+    ) -> Result<String, Self::Error> {
+        let command = common::Command::SignMessage {
+            path: path.clone(),
+            message: message.to_vec(),
+        };
+        let common::Response::MessageSignature(signature) = run_command(self, command).await?
+        else {
+            return Err(common::Error::NoErrorOrResult.into());
+        };
+        let xpub = self.get_extended_pubkey(path, false).await?;
+        bhwi::verify::verify_message_signature(message, &signature, &xpub.public_key.into())?;
+        Ok(signature)
+    }
 }
 
 pub trait OnUnlock {
     fn on_unlock(&mut self, _response: common::Response) -> Result<(), common::Error>;
 }
 
+/// Devices checking the commands against the rules of the host before
+/// sending them, see [`bhwi::authorization`].
+pub trait Authorize: Sized {
+    fn authorization_mut(&mut self) -> &mut Option<Authorization>;
+
+    /// Checks the commands against the rules of the host before sending
+    /// them.
+    fn with_authorization(mut self, authorization: Authorization) -> Self {
+        *self.authorization_mut() = Some(authorization);
+        self
+    }
+}
+
 pub trait CommonInterface<C, T, R, E> {
     type TransportError: Debug;
     type HttpClientError: Debug;
@@ -113,6 +536,124 @@ pub trait CommonInterface<C, T, R, E> {
         &dyn HttpClient<Error = Self::HttpClientError>,
         impl Interpreter<Command = C, Transmit = T, Response = R, Error = E>,
     );
+    /// Returns true if the device signs with a wallet policy only.
+    fn requires_policy(&self) -> bool {
+        false
+    }
+    /// Rules of the host checked before the commands are sent, see
+    /// [`bhwi::authorization`].
+    fn authorization(&self) -> Option<&Authorization> {
+        None
+    }
+    /// Returns true if the keys of the policies are checked against the
+    /// device before registering or signing, see [`check_policy_keys`].
+    fn checks_policy_keys(&self) -> bool {
+        false
+    }
+    /// Features of the device, see [`HWI::capabilities`].
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// Checks the keys of the policy with the origin of the device against the
+/// xpubs it derives at their path, to catch the typos of a descriptor before
+/// the device displays the cosigners.
+pub async fn check_policy_keys<D, E, F>(
+    device: &mut D,
+    policy: &WalletPolicy,
+) -> Result<(), Error<E, F>>
+where
+    D: HWI<Error = Error<E, F>> + ?Sized,
+{
+    let fingerprint = device.get_master_fingerprint().await?;
+    for key in &policy.keys {
+        let Some((_, path)) = key.source.as_ref().filter(|(fg, _)| *fg == fingerprint) else {
+            continue;
+        };
+        let found = device.get_extended_pubkey(path.clone(), false).await?;
+        // The version bytes depend on the network shown, only the key and
+        // the chain code are compared.
+        if found.public_key != key.inner.public_key || found.chain_code != key.inner.chain_code {
+            return Err(common::Error::KeyMismatch {
+                expected: Box::new(key.inner),
+                found: Box::new(found),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Clock of the running command, its elapsed time is notified to the
+/// interpreter before each exchange.
+struct Clock(
+    #[cfg(not(target_arch = "wasm32"))] std::time::Instant,
+    #[cfg(target_arch = "wasm32")] f64,
+);
+
+impl Clock {
+    fn start() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        return Self(std::time::Instant::now());
+        #[cfg(target_arch = "wasm32")]
+        return Self(timer::now_ms());
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.0.elapsed().as_millis() as u64;
+        #[cfg(target_arch = "wasm32")]
+        return (timer::now_ms() - self.0).max(0.0) as u64;
+    }
+}
+
+/// Sends the frames and returns the reply to the last one.
+async fn exchange_frames<E: std::fmt::Debug, F>(
+    transport: &mut dyn Transport<Error = E>,
+    mut frames: Vec<common::Transmit>,
+) -> Result<Vec<u8>, Error<E, F>> {
+    let last = frames.pop().expect("at least the transmit");
+    for frame in frames {
+        transport
+            .send(&frame.payload, frame.encrypted)
+            .await
+            .map_err(Error::Transport)?;
+    }
+    transport
+        .exchange(&last.payload, last.encrypted)
+        .await
+        .map_err(Error::Transport)
+}
+
+/// Sends the transmit and the frames queued after it by the interpreter, and
+/// returns the reply to the last one. The exchange is raced against the
+/// deadline of the interpreter: a device not answering in time fails the
+/// command. The transports blocking the thread on their reads are only
+/// interrupted once the read returns.
+async fn exchange_until_deadline<E: std::fmt::Debug, F>(
+    transport: &mut dyn Transport<Error = E>,
+    intpr: &mut impl Interpreter<Transmit = common::Transmit, Error = common::Error>,
+    transmit: common::Transmit,
+    clock: &Clock,
+) -> Result<Vec<u8>, Error<E, F>> {
+    let frames: Vec<_> = std::iter::once(transmit)
+        .chain(std::iter::from_fn(|| intpr.poll_transmit()))
+        .collect();
+    let Some(deadline_ms) = intpr.deadline_ms() else {
+        return exchange_frames(transport, frames).await;
+    };
+    let exchange = exchange_frames(transport, frames);
+    let timeout = timer::sleep(deadline_ms.saturating_sub(clock.elapsed_ms()));
+    futures::pin_mut!(exchange, timeout);
+    match futures::future::select(exchange, timeout).await {
+        futures::future::Either::Left((res, _)) => res,
+        futures::future::Either::Right(_) => {
+            intpr.tick(clock.elapsed_ms())?;
+            intpr.cancel();
+            Err(common::Error::Timeout.into())
+        }
+    }
 }
 
 async fn run_command<'a, D, C, E, F>(
@@ -132,14 +673,19 @@ where
     >,
     C: Into<common::Command>,
 {
+    let command = command.into();
+    if let Some(authorization) = device.authorization() {
+        authorization.check(&command)?;
+    }
     let (transport, http_client, mut intpr) = device.components();
-    let transmit = intpr.start(command.into())?;
-    let exchange = transport
-        .exchange(&transmit.payload, transmit.encrypted)
-        .await
-        .map_err(Error::Transport)?;
+    let clock = Clock::start();
+    let transmit = intpr.start(command)?;
+    let mut reenumerating = poll_events(transport, &mut intpr).await;
+    let exchange = exchange_until_deadline(transport, &mut intpr, transmit, &clock).await?;
     let mut transmit = intpr.exchange(exchange)?;
+    reenumerating |= poll_events(transport, &mut intpr).await;
     while let Some(t) = &transmit {
+        intpr.tick(clock.elapsed_ms())?;
         match &t.recipient {
             common::Recipient::PinServer { url } => {
                 let res = http_client
@@ -149,13 +695,37 @@ where
                 transmit = intpr.exchange(res)?;
             }
             common::Recipient::Device => {
-                let exchange = transport
-                    .exchange(&t.payload, t.encrypted)
-                    .await
-                    .map_err(Error::Transport)?;
+                let t = transmit.take().expect("transmit to the device");
+                let exchange = exchange_until_deadline(transport, &mut intpr, t, &clock).await?;
                 transmit = intpr.exchange(exchange)?;
             }
         }
+        reenumerating |= poll_events(transport, &mut intpr).await;
+    }
+    if reenumerating {
+        transport.reconnect().await.map_err(Error::Transport)?;
     }
     intpr.end().map_err(|e| e.into())
 }
+
+/// Polls the events of the interpreter before its next transmit, notifying
+/// them to the transport and waiting for the delay of the retries and of the
+/// unlock probes. Returns true if the device re-enumerates.
+async fn poll_events<E: std::fmt::Debug>(
+    transport: &mut dyn Transport<Error = E>,
+    intpr: &mut impl Interpreter,
+) -> bool {
+    let mut reenumerating = false;
+    while let Some(event) = intpr.poll_event() {
+        transport.notify(&event);
+        match event {
+            Event::UnusualPath(warning) => log::warn!("unusual derivation path: {:?}", warning),
+            Event::AwaitingUnlock(delay_ms) | Event::Retrying(_, delay_ms) => {
+                timer::sleep(delay_ms.into()).await
+            }
+            Event::Reenumerating => reenumerating = true,
+            _ => {}
+        }
+    }
+    reenumerating
+}
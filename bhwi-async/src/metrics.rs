@@ -0,0 +1,248 @@
+//! Hooks for host applications to feed their monitoring with the commands
+//! run on the devices and the exchanges with them.
+//!
+//! The devices and transports are wrapped with `Metered` and
+//! `MeteredTransport`, nothing is measured when they are not used.
+
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bhwi::{
+    bitcoin::{
+        bip32::{DerivationPath, Fingerprint, Xpub},
+        Network, Psbt,
+    },
+    devices::Capabilities,
+    ledger::WalletPolicy,
+};
+
+use crate::{Error, Transport, HWI};
+
+/// Receives the measures, every method does nothing by default.
+pub trait Metrics {
+    /// A command ended, `error` is the code of its error if it failed,
+    /// see `Error::code`.
+    fn command(&self, _command: &'static str, _duration: Duration, _error: Option<&'static str>) {}
+
+    /// An exchange with the device ended.
+    fn exchange(&self, _sent: usize, _received: Option<usize>, _duration: Duration) {}
+}
+
+/// Metrics discarding every measure.
+impl Metrics for () {}
+
+impl<M: Metrics + ?Sized> Metrics for &M {
+    fn command(&self, command: &'static str, duration: Duration, error: Option<&'static str>) {
+        (**self).command(command, duration, error)
+    }
+
+    fn exchange(&self, sent: usize, received: Option<usize>, duration: Duration) {
+        (**self).exchange(sent, received, duration)
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for Rc<M> {
+    fn command(&self, command: &'static str, duration: Duration, error: Option<&'static str>) {
+        (**self).command(command, duration, error)
+    }
+
+    fn exchange(&self, sent: usize, received: Option<usize>, duration: Duration) {
+        (**self).exchange(sent, received, duration)
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn command(&self, command: &'static str, duration: Duration, error: Option<&'static str>) {
+        (**self).command(command, duration, error)
+    }
+
+    fn exchange(&self, sent: usize, received: Option<usize>, duration: Duration) {
+        (**self).exchange(sent, received, duration)
+    }
+}
+
+/// Device reporting its commands to the metrics.
+pub struct Metered<D, M> {
+    pub inner: D,
+    metrics: M,
+}
+
+impl<D, M> Metered<D, M> {
+    pub fn new(inner: D, metrics: M) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl<D, M: Metrics> Metered<D, M> {
+    fn report<T, E, F>(
+        &self,
+        command: &'static str,
+        start: Instant,
+        res: Result<T, Error<E, F>>,
+    ) -> Result<T, Error<E, F>> {
+        let error = res.as_ref().err().map(Error::code);
+        self.metrics.command(command, start.elapsed(), error);
+        res
+    }
+}
+
+#[async_trait(?Send)]
+impl<D, M, E, F> HWI for Metered<D, M>
+where
+    D: HWI<Error = Error<E, F>>,
+    M: Metrics,
+    E: Debug,
+    F: Debug,
+{
+    type Error = Error<E, F>;
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.unlock(network).await;
+        self.report("unlock", start, res)
+    }
+
+    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.get_master_fingerprint().await;
+        self.report("get_master_fingerprint", start, res)
+    }
+
+    async fn get_extended_pubkey(
+        &mut self,
+        path: DerivationPath,
+        display: bool,
+    ) -> Result<Xpub, Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.get_extended_pubkey(path, display).await;
+        self.report("get_extended_pubkey", start, res)
+    }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.register_wallet(policy).await;
+        self.report("register_wallet", start, res)
+    }
+
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.sign_psbt(psbt, policy, hmac).await;
+        self.report("sign_psbt", start, res)
+    }
+}
+
+/// Transport reporting its exchanges to the metrics.
+pub struct MeteredTransport<T, M> {
+    pub inner: T,
+    metrics: M,
+}
+
+impl<T, M> MeteredTransport<T, M> {
+    pub fn new(inner: T, metrics: M) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport, M: Metrics> Transport for MeteredTransport<T, M> {
+    type Error = T::Error;
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.exchange(command, encrypted).await;
+        self.metrics.exchange(
+            command.len(),
+            res.as_ref().ok().map(Vec::len),
+            start.elapsed(),
+        );
+        res
+    }
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        let start = Instant::now();
+        let res = self.inner.send(command, encrypted).await;
+        self.metrics
+            .exchange(command.len(), res.as_ref().ok().map(|_| 0), start.elapsed());
+        res
+    }
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.inner.reconnect().await
+    }
+    fn notify(&mut self, event: &bhwi::Event) {
+        self.inner.notify(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fault::{Fault, FaultInjector},
+        mock::MockLedger,
+        Ledger,
+    };
+    use std::cell::RefCell;
+    use std::str::FromStr;
+
+    #[derive(Default)]
+    struct Counters {
+        commands: RefCell<Vec<(&'static str, Option<&'static str>)>>,
+        exchanges: RefCell<Vec<Option<usize>>>,
+    }
+
+    impl Metrics for Counters {
+        fn command(&self, command: &'static str, _duration: Duration, error: Option<&'static str>) {
+            self.commands.borrow_mut().push((command, error));
+        }
+
+        fn exchange(&self, _sent: usize, received: Option<usize>, _duration: Duration) {
+            self.exchanges.borrow_mut().push(received);
+        }
+    }
+
+    #[test]
+    fn test_metered_ledger() {
+        let counters = Rc::new(Counters::default());
+        let transport = FaultInjector::new(MockLedger::new(&[0x01; 32], Network::Testnet))
+            .with_fault(2, Fault::Fail);
+        let mut ledger = Metered::new(
+            Ledger::new(MeteredTransport::new(transport, counters.clone())),
+            counters.clone(),
+        );
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        futures::executor::block_on(async {
+            ledger.unlock(Network::Testnet).await.unwrap();
+            ledger.get_master_fingerprint().await.unwrap();
+            assert!(ledger
+                .get_extended_pubkey(path.clone(), false)
+                .await
+                .is_err());
+            ledger.get_extended_pubkey(path, false).await.unwrap();
+        });
+        assert_eq!(
+            *counters.commands.borrow(),
+            vec![
+                ("unlock", None),
+                ("get_master_fingerprint", None),
+                ("get_extended_pubkey", Some("transport")),
+                ("get_extended_pubkey", None),
+            ]
+        );
+        let exchanges = counters.exchanges.borrow();
+        assert_eq!(exchanges.len(), 4);
+        assert_eq!(exchanges[2], None);
+    }
+}
@@ -0,0 +1,551 @@
+//! Mock devices implementing the device side of the protocols, so that the
+//! interpreters, the runner and the hosts can be tested in-process.
+
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use bhwi::{
+    bitcoin::{
+        bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub},
+        consensus::encode::{self, VarInt},
+        hashes::{hmac, sha256, Hash, HashEngine},
+        secp256k1::{All, Secp256k1},
+        Network,
+    },
+    ledger::{
+        apdu::{BitcoinCommandCode, Cla, ClientCommandCode, FrameworkCommandCode, StatusWord},
+        app,
+    },
+    psbt,
+    wallet::{self, WalletPolicy, WalletPubKey},
+};
+
+use crate::Transport;
+
+/// Ledger Bitcoin app answering the apdus with the keys of a known seed.
+pub struct MockLedger {
+    secp: Secp256k1<All>,
+    master: Xpriv,
+    network: Network,
+    /// Name of the open app.
+    pub app: String,
+    /// Names of the apps installed.
+    pub installed: Vec<String>,
+    /// Apps listed so far by the dashboard.
+    listed: usize,
+    /// Commands received, in order.
+    pub commands: Vec<Vec<u8>>,
+    /// Reconnections of the transport.
+    pub reconnects: usize,
+    /// Refuses every command until unlocked.
+    pub locked: bool,
+    /// Command waiting for the reply of the host to its client command.
+    interrupted: Option<Interrupted>,
+}
+
+/// Command interrupted by a client command. The command is run again from
+/// the start with the replies of the host received so far, until it needs
+/// another one.
+struct Interrupted {
+    command: Vec<u8>,
+    replies: Vec<Vec<u8>>,
+    request: Vec<u8>,
+}
+
+/// Stops a command: on a client command to send to the host, or on an error.
+enum Interrupt {
+    Request(Vec<u8>),
+    Status(StatusWord),
+}
+
+impl From<StatusWord> for Interrupt {
+    fn from(sw: StatusWord) -> Self {
+        Interrupt::Status(sw)
+    }
+}
+
+impl MockLedger {
+    pub fn new(seed: &[u8], network: Network) -> Self {
+        Self {
+            secp: Secp256k1::new(),
+            master: Xpriv::new_master(network, seed).expect("valid seed"),
+            network,
+            app: app::app_name(network).to_string(),
+            installed: vec![app::app_name(network).to_string()],
+            listed: 0,
+            commands: Vec::new(),
+            reconnects: 0,
+            locked: false,
+            interrupted: None,
+        }
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        self.master.fingerprint(&self.secp)
+    }
+
+    pub fn xpub(&self, path: &DerivationPath) -> Xpub {
+        let xpriv = self
+            .master
+            .derive_priv(&self.secp, path)
+            .expect("valid path");
+        Xpub::from_priv(&self.secp, &xpriv)
+    }
+
+    fn handle(&mut self, command: &[u8]) -> Result<Vec<u8>, StatusWord> {
+        let (header, data) = command
+            .split_at_checked(5)
+            .ok_or(StatusWord::WrongDataLength)?;
+        if data.len() != header[4] as usize {
+            return Err(StatusWord::WrongDataLength);
+        }
+        if self.locked {
+            return Err(StatusWord::DeviceLocked);
+        }
+        match (header[0], header[1]) {
+            // Open app
+            (0xe0, 0xd8) if self.app == app::DASHBOARD_NAME => {
+                self.app =
+                    String::from_utf8(data.to_vec()).map_err(|_| StatusWord::IncorrectData)?;
+                Ok(Vec::new())
+            }
+            (0xe0, 0xd8) => Err(StatusWord::ClaNotSupported),
+            // Quit app
+            (0xb0, 0xa7) => {
+                self.app = app::DASHBOARD_NAME.to_string();
+                Ok(Vec::new())
+            }
+            // List apps, one per page
+            (0xe0, 0xde | 0xdf) if self.app == app::DASHBOARD_NAME => {
+                if header[1] == 0xde {
+                    self.listed = 0;
+                }
+                let Some(name) = self.installed.get(self.listed) else {
+                    return Ok(Vec::new());
+                };
+                self.listed += 1;
+                let mut page = vec![0x01, (4 + 64 + 1 + name.len()) as u8];
+                page.extend([0x00; 4 + 64]);
+                page.push(name.len() as u8);
+                page.extend(name.as_bytes());
+                Ok(page)
+            }
+            (0xe0, 0xde | 0xdf) => Err(StatusWord::ClaNotSupported),
+            // Get app and version
+            (0xb0, 0x01) => {
+                let mut info = vec![0x01, self.app.len() as u8];
+                info.extend(self.app.as_bytes());
+                info.push(5);
+                info.extend(b"2.2.4");
+                info.extend([0x01, 0x00]);
+                Ok(info)
+            }
+            (cla, ins) if cla == Cla::Bitcoin as u8 => {
+                self.interrupted = None;
+                if ins == BitcoinCommandCode::GetMasterFingerprint as u8 {
+                    Ok(self.fingerprint().as_bytes().to_vec())
+                } else if ins == BitcoinCommandCode::GetExtendedPubkey as u8 {
+                    let path = parse_path(data.get(1..).ok_or(StatusWord::IncorrectData)?)?;
+                    Ok(self.xpub(&path).to_string().into_bytes())
+                } else if ins == BitcoinCommandCode::RegisterWallet as u8
+                    || ins == BitcoinCommandCode::GetWalletAddress as u8
+                    || ins == BitcoinCommandCode::SignPSBT as u8
+                {
+                    self.run(command.to_vec(), Vec::new())
+                } else {
+                    Err(StatusWord::InsNotSupported)
+                }
+            }
+            (cla, ins)
+                if cla == Cla::Framework as u8
+                    && ins == FrameworkCommandCode::ContinueInterrupted as u8 =>
+            {
+                let Interrupted {
+                    command,
+                    mut replies,
+                    ..
+                } = self.interrupted.take().ok_or(StatusWord::BadState)?;
+                replies.push(data.to_vec());
+                self.run(command, replies)
+            }
+            _ => Err(StatusWord::ClaNotSupported),
+        }
+    }
+
+    /// Runs the command with the replies of the host to its client commands,
+    /// interrupts it on the next client command.
+    fn run(&mut self, command: Vec<u8>, replies: Vec<Vec<u8>>) -> Result<Vec<u8>, StatusWord> {
+        let mut client = Client {
+            replies: &replies,
+            next: 0,
+        };
+        let data = &command[5..];
+        let res = match command[1] {
+            ins if ins == BitcoinCommandCode::RegisterWallet as u8 => {
+                self.register_wallet(&mut client, data)
+            }
+            ins if ins == BitcoinCommandCode::GetWalletAddress as u8 => {
+                self.get_wallet_address(&mut client, data)
+            }
+            _ => self.sign_psbt(&mut client, data),
+        };
+        match res {
+            Ok(data) => Ok(data),
+            Err(Interrupt::Status(sw)) => Err(sw),
+            Err(Interrupt::Request(request)) => {
+                self.interrupted = Some(Interrupted {
+                    command,
+                    replies,
+                    request,
+                });
+                Err(StatusWord::InterruptedExecution)
+            }
+        }
+    }
+
+    /// Proof of registration of the policy.
+    fn hmac(&self, id: &[u8; 32]) -> [u8; 32] {
+        let mut engine =
+            hmac::HmacEngine::<sha256::Hash>::new(&self.master.private_key.secret_bytes());
+        engine.input(id);
+        hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+    }
+
+    /// Checks the policy is registered, or is the default policy of an
+    /// account of the device without hmac.
+    fn check_policy(&self, policy: &WalletPolicy, hmac: &[u8; 32]) -> Result<(), StatusWord> {
+        if *hmac == self.hmac(&policy.id()) {
+            return Ok(());
+        }
+        let default = match policy.keys.as_slice() {
+            [WalletPubKey {
+                inner,
+                source: Some(source),
+                ..
+            }] => {
+                source.0 == self.fingerprint()
+                    && *inner == self.xpub(&source.1)
+                    && WalletPolicy::new_singlesig(source.clone(), *inner)
+                        .ok()
+                        .as_ref()
+                        == Some(policy)
+            }
+            _ => false,
+        };
+        if *hmac == [0x00; 32] && default {
+            Ok(())
+        } else {
+            Err(StatusWord::SignatureFail)
+        }
+    }
+
+    /// Registers a policy holding a key of the device, returns its id and
+    /// its hmac.
+    fn register_wallet(&self, client: &mut Client, data: &[u8]) -> Result<Vec<u8>, Interrupt> {
+        let mut reader = Reader(data);
+        let len = reader.varint()?;
+        let serialized = reader.bytes(len)?;
+        let policy = parse_policy(client, serialized)?;
+        if !policy.keys.iter().any(|key| {
+            key.source
+                .as_ref()
+                .is_some_and(|(fg, path)| *fg == self.fingerprint() && key.inner == self.xpub(path))
+        }) {
+            return Err(StatusWord::IncorrectData.into());
+        }
+        let id = wallet::wallet_id(serialized);
+        let mut res = id.to_vec();
+        res.extend(self.hmac(&id));
+        Ok(res)
+    }
+
+    fn get_wallet_address(&self, client: &mut Client, data: &[u8]) -> Result<Vec<u8>, Interrupt> {
+        let mut reader = Reader(data);
+        let _display = reader.bytes(1)?;
+        let id = reader.array()?;
+        let hmac = reader.array()?;
+        let change = reader.bytes(1)?[0] != 0;
+        let index = u32::from_be_bytes(reader.array()?);
+        let serialized = client.preimage(&id)?;
+        let policy = parse_policy(client, &serialized)?;
+        self.check_policy(&policy, &hmac)?;
+        let address = policy
+            .derive_address(change, index, self.network)
+            .map_err(|_| StatusWord::NotSupported)?;
+        Ok(address.to_string().into_bytes())
+    }
+
+    /// Signs the psbt with the keys of the device, the signatures are
+    /// yielded one by one. The psbt is not checked against the policy.
+    fn sign_psbt(&self, client: &mut Client, data: &[u8]) -> Result<Vec<u8>, Interrupt> {
+        let mut reader = Reader(data);
+        let global = reader.commitment()?;
+        let inputs = (reader.varint()?, reader.array()?);
+        let outputs = (reader.varint()?, reader.array()?);
+        let id = reader.array()?;
+        let hmac = reader.array()?;
+        let serialized = client.preimage(&id)?;
+        let policy = parse_policy(client, &serialized)?;
+        self.check_policy(&policy, &hmac)?;
+
+        let mut data = b"psbt\xff".to_vec();
+        write_map(&mut data, client.map(global)?);
+        for (size, root) in [inputs, outputs] {
+            for i in 0..size {
+                let commitment = client.list_element(&root, size, i)?;
+                write_map(&mut data, client.map(Reader(&commitment).commitment()?)?);
+            }
+        }
+        let unsigned = psbt::deserialize_psbt(&data).map_err(|_| StatusWord::IncorrectData)?;
+        let mut signed = unsigned.clone();
+        signed
+            .sign(&self.master, &self.secp)
+            .map_err(|_| StatusWord::IncorrectData)?;
+
+        for (index, (before, after)) in unsigned.inputs.iter().zip(&signed.inputs).enumerate() {
+            let mut yielded = Vec::new();
+            for (key, sig) in &after.partial_sigs {
+                if !before.partial_sigs.contains_key(key) {
+                    yielded.push([vec![33], key.to_bytes(), sig.to_vec()].concat());
+                }
+            }
+            if let (None, Some(sig), Some(key)) = (
+                before.tap_key_sig,
+                after.tap_key_sig,
+                after.tap_internal_key,
+            ) {
+                yielded.push([vec![32], key.serialize().to_vec(), sig.to_vec()].concat());
+            }
+            for ((key, leaf_hash), sig) in &after.tap_script_sigs {
+                if !before.tap_script_sigs.contains_key(&(*key, *leaf_hash)) {
+                    yielded.push(
+                        [
+                            vec![64],
+                            key.serialize().to_vec(),
+                            leaf_hash.to_byte_array().to_vec(),
+                            sig.to_vec(),
+                        ]
+                        .concat(),
+                    );
+                }
+            }
+            for value in yielded {
+                let mut request = vec![ClientCommandCode::Yield as u8];
+                request.extend(encode::serialize(&VarInt(index as u64)));
+                request.extend(value);
+                client.request(request)?;
+            }
+        }
+        Ok(Vec::new())
+    }
+}
+
+/// Replies of the host to the client commands of the running command.
+struct Client<'a> {
+    replies: &'a [Vec<u8>],
+    next: usize,
+}
+
+impl Client<'_> {
+    /// Returns the reply of the host to the client command, interrupts the
+    /// command to send it if the host has not replied yet.
+    fn request(&mut self, request: Vec<u8>) -> Result<Vec<u8>, Interrupt> {
+        let reply = self
+            .replies
+            .get(self.next)
+            .ok_or(Interrupt::Request(request))?;
+        self.next += 1;
+        Ok(reply.clone())
+    }
+
+    /// Returns the elements queued by the host, until the count.
+    fn more_elements(&mut self, mut data: Vec<u8>, count: usize) -> Result<Vec<u8>, Interrupt> {
+        let mut received = 0;
+        while received < count {
+            let reply = self.request(vec![ClientCommandCode::GetMoreElements as u8])?;
+            let mut reader = Reader(&reply);
+            let n = reader.bytes(1)?[0] as usize;
+            let size = reader.bytes(1)?[0] as usize;
+            if n == 0 {
+                return Err(StatusWord::IncorrectData.into());
+            }
+            data.extend(reader.bytes(n * size)?);
+            received += n * size;
+        }
+        Ok(data)
+    }
+
+    fn preimage(&mut self, hash: &[u8; 32]) -> Result<Vec<u8>, Interrupt> {
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(hash);
+        let reply = self.request(request)?;
+        let mut reader = Reader(&reply);
+        let len = reader.varint()?;
+        let partial = reader.bytes(1)?[0] as usize;
+        let first = reader.bytes(partial)?.to_vec();
+        let preimage = self.more_elements(first, len.saturating_sub(partial))?;
+        if sha256::Hash::hash(&preimage).to_byte_array() != *hash {
+            return Err(StatusWord::IncorrectData.into());
+        }
+        Ok(preimage)
+    }
+
+    /// Returns the element of the merkleized list at the index, the proof is
+    /// not checked.
+    fn list_element(
+        &mut self,
+        root: &[u8; 32],
+        size: usize,
+        index: usize,
+    ) -> Result<Vec<u8>, Interrupt> {
+        let mut request = vec![ClientCommandCode::GetMerkleLeafProof as u8];
+        request.extend(root);
+        request.extend(encode::serialize(&VarInt(size as u64)));
+        request.extend(encode::serialize(&VarInt(index as u64)));
+        let reply = self.request(request)?;
+        let mut reader = Reader(&reply);
+        let hash = reader.array()?;
+        let proof_len = reader.bytes(1)?[0] as usize;
+        let n = reader.bytes(1)?[0] as usize;
+        reader.bytes(n * 32)?;
+        self.more_elements(Vec::new(), proof_len.saturating_sub(n) * 32)?;
+        match self.preimage(&hash)?.split_first() {
+            Some((0x00, element)) => Ok(element.to_vec()),
+            _ => Err(StatusWord::IncorrectData.into()),
+        }
+    }
+
+    /// Returns the pairs of the merkleized map of the commitment.
+    fn map(
+        &mut self,
+        (size, keys_root, values_root): (usize, [u8; 32], [u8; 32]),
+    ) -> Result<psbt::Map, Interrupt> {
+        (0..size)
+            .map(|i| {
+                Ok((
+                    self.list_element(&keys_root, size, i)?,
+                    self.list_element(&values_root, size, i)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Reads the data of an apdu or of a reply of the host.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StatusWord> {
+        let (bytes, rest) = self
+            .0
+            .split_at_checked(len)
+            .ok_or(StatusWord::IncorrectData)?;
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StatusWord> {
+        Ok(self.bytes(N)?.try_into().expect("length checked"))
+    }
+
+    fn varint(&mut self) -> Result<usize, StatusWord> {
+        let (VarInt(n), read) =
+            encode::deserialize_partial(self.0).map_err(|_| StatusWord::IncorrectData)?;
+        self.0 = &self.0[read..];
+        Ok(n as usize)
+    }
+
+    /// Reads the commitment of a merkleized map: its size, the roots of its
+    /// keys and of its values.
+    fn commitment(&mut self) -> Result<(usize, [u8; 32], [u8; 32]), StatusWord> {
+        Ok((self.varint()?, self.array()?, self.array()?))
+    }
+}
+
+/// Parses the serialization of a policy, fetching its descriptor template
+/// and its keys from the host.
+fn parse_policy(client: &mut Client, serialized: &[u8]) -> Result<WalletPolicy, Interrupt> {
+    let mut reader = Reader(serialized);
+    let version = match reader.bytes(1)?[0] {
+        1 => wallet::Version::V1,
+        2 => wallet::Version::V2,
+        _ => return Err(StatusWord::IncorrectData.into()),
+    };
+    let name_len = reader.bytes(1)?[0] as usize;
+    let name = String::from_utf8(reader.bytes(name_len)?.to_vec())
+        .map_err(|_| StatusWord::IncorrectData)?;
+    let template_len = reader.varint()?;
+    let template = match version {
+        wallet::Version::V1 => reader.bytes(template_len)?.to_vec(),
+        wallet::Version::V2 => client.preimage(&reader.array()?)?,
+    };
+    let n_keys = reader.varint()?;
+    let keys_root = reader.array()?;
+    let keys = (0..n_keys)
+        .map(|i| {
+            let key = client.list_element(&keys_root, n_keys, i)?;
+            String::from_utf8(key)
+                .ok()
+                .and_then(|key| WalletPubKey::from_str(&key).ok())
+                .ok_or(StatusWord::IncorrectData.into())
+        })
+        .collect::<Result<Vec<_>, Interrupt>>()?;
+    Ok(WalletPolicy::new(
+        name,
+        version,
+        String::from_utf8(template).map_err(|_| StatusWord::IncorrectData)?,
+        keys,
+    ))
+}
+
+/// Writes the pairs of a map of a PSBT v2.
+fn write_map(data: &mut Vec<u8>, map: psbt::Map) {
+    for (key, value) in map {
+        data.extend(encode::serialize(&VarInt(key.len() as u64)));
+        data.extend(key);
+        data.extend(encode::serialize(&VarInt(value.len() as u64)));
+        data.extend(value);
+    }
+    data.push(0x00);
+}
+
+/// Parses a derivation path serialized as its length followed by the
+/// big endian child numbers.
+fn parse_path(data: &[u8]) -> Result<DerivationPath, StatusWord> {
+    let (len, children) = data.split_first().ok_or(StatusWord::IncorrectData)?;
+    if children.len() != *len as usize * 4 {
+        return Err(StatusWord::IncorrectData);
+    }
+    Ok(children
+        .chunks(4)
+        .map(|c| ChildNumber::from(u32::from_be_bytes([c[0], c[1], c[2], c[3]])))
+        .collect::<Vec<_>>()
+        .into())
+}
+
+#[async_trait(?Send)]
+impl Transport for MockLedger {
+    type Error = std::convert::Infallible;
+
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.commands.push(command.to_vec());
+        let (mut response, status_word) = match self.handle(command) {
+            Ok(data) => (data, StatusWord::OK),
+            Err(StatusWord::InterruptedExecution) => (
+                self.interrupted
+                    .as_ref()
+                    .map(|interrupted| interrupted.request.clone())
+                    .unwrap_or_default(),
+                StatusWord::InterruptedExecution,
+            ),
+            Err(sw) => (Vec::new(), sw),
+        };
+        response.extend_from_slice(&(status_word as u16).to_be_bytes());
+        Ok(response)
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.reconnects += 1;
+        Ok(())
+    }
+}
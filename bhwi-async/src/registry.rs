@@ -0,0 +1,139 @@
+//! Registry of the connected devices by master fingerprint, for the
+//! applications using several devices to run a command on the one of a
+//! cosigner.
+//!
+//! The devices are registered under an id of the host, like their path, and
+//! the registry is refreshed with the ids still connected on hotplug.
+
+use bhwi::{bitcoin::bip32::Fingerprint, common};
+
+use crate::HWI;
+
+struct Registered<K, D> {
+    id: K,
+    fingerprint: Fingerprint,
+    device: D,
+}
+
+pub struct DeviceRegistry<K, D> {
+    devices: Vec<Registered<K, D>>,
+}
+
+impl<K, D> Default for DeviceRegistry<K, D> {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+        }
+    }
+}
+
+impl<K: PartialEq + Clone, D> DeviceRegistry<K, D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the device with its master fingerprint, in place of the
+    /// device of the same id.
+    pub fn insert(&mut self, id: K, fingerprint: Fingerprint, device: D) {
+        self.remove(&id);
+        self.devices.push(Registered {
+            id,
+            fingerprint,
+            device,
+        });
+    }
+
+    /// Registers the device with the master fingerprint it returns.
+    pub async fn register(&mut self, id: K, mut device: D) -> Result<Fingerprint, D::Error>
+    where
+        D: HWI,
+    {
+        let fingerprint = device.get_master_fingerprint().await?;
+        self.insert(id, fingerprint, device);
+        Ok(fingerprint)
+    }
+
+    pub fn remove(&mut self, id: &K) -> Option<D> {
+        let index = self.devices.iter().position(|d| &d.id == id)?;
+        Some(self.devices.remove(index).device)
+    }
+
+    /// Drops the devices disconnected, and returns the ids of the connected
+    /// devices not registered yet, for the host to open and insert them.
+    pub fn refresh(&mut self, connected: &[K]) -> Vec<K> {
+        self.devices.retain(|d| connected.contains(&d.id));
+        connected
+            .iter()
+            .filter(|id| !self.devices.iter().any(|d| &d.id == *id))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the device with the master fingerprint, the first registered
+    /// if several devices share the same seed.
+    pub fn get(&mut self, fingerprint: Fingerprint) -> Result<&mut D, common::Error> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.fingerprint == fingerprint)
+            .map(|d| &mut d.device)
+            .ok_or(common::Error::DeviceNotConnected(fingerprint))
+    }
+
+    pub fn contains(&self, fingerprint: Fingerprint) -> bool {
+        self.devices.iter().any(|d| d.fingerprint == fingerprint)
+    }
+
+    /// The master fingerprints of the devices, in their order of
+    /// registration.
+    pub fn fingerprints(&self) -> Vec<Fingerprint> {
+        self.devices.iter().map(|d| d.fingerprint).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareSigner;
+    use bhwi::bitcoin::Network;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_device_registry() {
+        let signer = |mnemonic| SoftwareSigner::from_mnemonic(mnemonic, "", Network::Testnet);
+        let abandon = signer(
+            "abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon about",
+        )
+        .unwrap();
+        let zoo = signer("zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong").unwrap();
+
+        let mut registry = DeviceRegistry::new();
+        let (abandon_fg, zoo_fg) = futures::executor::block_on(async {
+            (
+                registry.register("hidraw0", abandon).await.unwrap(),
+                registry.register("hidraw1", zoo).await.unwrap(),
+            )
+        });
+        assert_eq!(abandon_fg, Fingerprint::from_str("73c5da0a").unwrap());
+        assert_eq!(registry.fingerprints(), [abandon_fg, zoo_fg]);
+        assert!(registry.get(zoo_fg).is_ok());
+
+        // The first device is unplugged, another one is plugged.
+        assert_eq!(registry.refresh(&["hidraw1", "hidraw2"]), ["hidraw2"]);
+        assert_eq!(registry.len(), 1);
+        assert!(matches!(
+            registry.get(abandon_fg),
+            Err(common::Error::DeviceNotConnected(fg)) if fg == abandon_fg
+        ));
+        assert!(registry.remove(&"hidraw1").is_some());
+        assert!(registry.is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! Signing of a psbt by several devices, the cosigners of a multisig
+//! wallet: the devices sign in turn, as they are connected, and their
+//! signatures are merged into the psbt of the session.
+
+use std::collections::BTreeSet;
+
+use bhwi::{
+    bitcoin::{bip32::Fingerprint, Psbt},
+    ledger::WalletPolicy,
+};
+
+use crate::HWI;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignerStatus {
+    /// The cosigner has not signed yet.
+    Pending,
+    /// The device added signatures to the number of inputs.
+    Signed(usize),
+    /// The device added no signature.
+    NothingToSign,
+    /// The device failed, with the debug output of its error.
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Cosigner {
+    pub fingerprint: Fingerprint,
+    /// Proof of registration of the policy on the device of the cosigner.
+    pub hmac: Option<[u8; 32]>,
+    pub status: SignerStatus,
+}
+
+pub struct SigningSession {
+    psbt: Psbt,
+    policy: Option<WalletPolicy>,
+    cosigners: Vec<Cosigner>,
+}
+
+impl SigningSession {
+    /// Starts the session, the cosigners are the keys of the policy with
+    /// their origin, or the keys of the inputs without policy.
+    pub fn new(psbt: Psbt, policy: Option<WalletPolicy>) -> Self {
+        let fingerprints: BTreeSet<Fingerprint> = match &policy {
+            Some(policy) => policy
+                .keys
+                .iter()
+                .filter_map(|key| key.source.as_ref().map(|(fg, _)| *fg))
+                .collect(),
+            None => psbt
+                .inputs
+                .iter()
+                .flat_map(|input| {
+                    input
+                        .bip32_derivation
+                        .values()
+                        .chain(input.tap_key_origins.values().map(|(_, source)| source))
+                        .map(|(fg, _)| *fg)
+                })
+                .collect(),
+        };
+        Self {
+            psbt,
+            policy,
+            cosigners: fingerprints
+                .into_iter()
+                .map(|fingerprint| Cosigner {
+                    fingerprint,
+                    hmac: None,
+                    status: SignerStatus::Pending,
+                })
+                .collect(),
+        }
+    }
+
+    /// Sets the proof of registration of the policy on the device of the
+    /// cosigner.
+    pub fn with_hmac(mut self, fingerprint: Fingerprint, hmac: [u8; 32]) -> Self {
+        if let Some(cosigner) = self
+            .cosigners
+            .iter_mut()
+            .find(|c| c.fingerprint == fingerprint)
+        {
+            cosigner.hmac = Some(hmac);
+        }
+        self
+    }
+
+    pub fn psbt(&self) -> &Psbt {
+        &self.psbt
+    }
+
+    pub fn into_psbt(self) -> Psbt {
+        self.psbt
+    }
+
+    pub fn cosigners(&self) -> &[Cosigner] {
+        &self.cosigners
+    }
+
+    /// Returns true once every cosigner signed or had nothing to sign.
+    pub fn is_complete(&self) -> bool {
+        self.cosigners.iter().all(|c| {
+            matches!(
+                c.status,
+                SignerStatus::Signed(..) | SignerStatus::NothingToSign
+            )
+        })
+    }
+
+    /// Signs with the device of a cosigner and merges its signatures. The
+    /// devices of no cosigner are not asked to sign. The failures of the
+    /// device are kept in the status of its cosigner.
+    pub async fn sign_with<D: HWI + ?Sized>(
+        &mut self,
+        device: &mut D,
+    ) -> Result<SignerStatus, D::Error> {
+        let fingerprint = device.get_master_fingerprint().await?;
+        let Some(index) = self
+            .cosigners
+            .iter()
+            .position(|c| c.fingerprint == fingerprint)
+        else {
+            return Ok(SignerStatus::NothingToSign);
+        };
+        let hmac = self.cosigners[index].hmac;
+        let status = match device
+            .sign_psbt(self.psbt.clone(), self.policy.clone(), hmac)
+            .await
+        {
+            Ok(signed) => {
+                let before = signature_counts(&self.psbt);
+                for (input, signed) in self.psbt.inputs.iter_mut().zip(signed.inputs) {
+                    input.partial_sigs.extend(signed.partial_sigs);
+                    input.tap_script_sigs.extend(signed.tap_script_sigs);
+                    if input.tap_key_sig.is_none() {
+                        input.tap_key_sig = signed.tap_key_sig;
+                    }
+                }
+                let signed = before
+                    .iter()
+                    .zip(signature_counts(&self.psbt))
+                    .filter(|(before, after)| after > before)
+                    .count();
+                if signed == 0 {
+                    SignerStatus::NothingToSign
+                } else {
+                    SignerStatus::Signed(signed)
+                }
+            }
+            Err(e) => {
+                self.cosigners[index].status = SignerStatus::Failed(format!("{:?}", e));
+                return Err(e);
+            }
+        };
+        self.cosigners[index].status = status.clone();
+        Ok(status)
+    }
+
+    /// Signs with the devices in turn, the failures are kept in the status
+    /// of the cosigners and the next devices still sign.
+    pub async fn sign_all<D: HWI + ?Sized>(&mut self, devices: &mut [&mut D]) -> &[Cosigner] {
+        for device in devices.iter_mut() {
+            let _ = self.sign_with(&mut **device).await;
+        }
+        &self.cosigners
+    }
+}
+
+/// Returns the number of signatures of each input.
+fn signature_counts(psbt: &Psbt) -> Vec<usize> {
+    psbt.inputs
+        .iter()
+        .map(|input| {
+            input.partial_sigs.len()
+                + input.tap_script_sigs.len()
+                + usize::from(input.tap_key_sig.is_some())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::software::SoftwareSigner;
+    use bhwi::bitcoin::{
+        absolute::LockTime, bip32::DerivationPath, transaction::Version, Amount,
+        CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+        Txid, Witness,
+    };
+    use std::str::FromStr;
+
+    /// Returns the psbt spending an input of each signer.
+    fn psbt(signers: &mut [&mut SoftwareSigner]) -> Psbt {
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let mut inputs = Vec::new();
+        for (i, signer) in signers.iter_mut().enumerate() {
+            let (fingerprint, xpub) = futures::executor::block_on(async {
+                (
+                    signer.get_master_fingerprint().await.unwrap(),
+                    signer
+                        .get_extended_pubkey(path.clone(), false)
+                        .await
+                        .unwrap(),
+                )
+            });
+            let script_pubkey =
+                ScriptBuf::new_p2wpkh(&CompressedPublicKey(xpub.public_key).wpubkey_hash());
+            inputs.push((i, fingerprint, xpub, script_pubkey));
+        }
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|(i, ..)| TxIn {
+                    previous_output: OutPoint::new(
+                        Txid::from_str(&"11".repeat(32)).unwrap(),
+                        *i as u32,
+                    ),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (i, fingerprint, xpub, script_pubkey) in inputs {
+            psbt.inputs[i].witness_utxo = Some(TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey,
+            });
+            psbt.inputs[i]
+                .bip32_derivation
+                .insert(xpub.public_key, (fingerprint, path.clone()));
+        }
+        psbt
+    }
+
+    #[test]
+    fn test_signing_session() {
+        let mut alice = SoftwareSigner::new(&[0x01; 32], Network::Testnet).unwrap();
+        let mut bob = SoftwareSigner::new(&[0x02; 32], Network::Testnet).unwrap();
+        let mut carol = SoftwareSigner::new(&[0x03; 32], Network::Testnet).unwrap();
+        let mut session = SigningSession::new(psbt(&mut [&mut alice, &mut bob]), None);
+        assert_eq!(session.cosigners().len(), 2);
+        assert!(!session.is_complete());
+
+        futures::executor::block_on(async {
+            assert_eq!(
+                session.sign_with(&mut carol).await.unwrap(),
+                SignerStatus::NothingToSign
+            );
+            assert_eq!(
+                session.sign_with(&mut bob).await.unwrap(),
+                SignerStatus::Signed(1)
+            );
+            assert!(!session.is_complete());
+            session.sign_all(&mut [&mut alice, &mut bob]).await;
+        });
+        let statuses: Vec<SignerStatus> = session
+            .cosigners()
+            .iter()
+            .map(|c| c.status.clone())
+            .collect();
+        assert!(statuses.contains(&SignerStatus::Signed(1)));
+        // Bob signed again, without new signature.
+        assert!(statuses.contains(&SignerStatus::NothingToSign));
+        assert!(session.is_complete());
+        let psbt = session.into_psbt();
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|input| input.partial_sigs.len() == 1));
+    }
+}
@@ -0,0 +1,383 @@
+//! In-memory log of the sessions with a device, for the support teams to get
+//! a trace of the failures reported by the users.
+//!
+//! The devices and transports are wrapped with `Logged` and
+//! `LoggedTransport`, sharing a `SessionLog` that keeps the last events in a
+//! bounded ring buffer. The payloads are never logged, only the sizes of the
+//! frames and the status words, so that a dump can be attached to a report
+//! without leaking the keys, the addresses or the transactions.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Write};
+use std::rc::Rc;
+
+use async_trait::async_trait;
+use bhwi::{
+    bitcoin::{
+        bip32::{DerivationPath, Fingerprint, Xpub},
+        Network, Psbt,
+    },
+    devices::Capabilities,
+    ledger::WalletPolicy,
+};
+
+use crate::{Error, Transport, HWI};
+
+/// Number of events kept by default.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    CommandStarted(&'static str),
+    /// The command ended, `error` is the code of its error if it failed,
+    /// see `Error::code`.
+    CommandEnded {
+        command: &'static str,
+        error: Option<&'static str>,
+    },
+    /// A frame of the size was written to the device.
+    Sent {
+        size: usize,
+        encrypted: bool,
+    },
+    /// A response of the size was read, with its apdu status word.
+    Received {
+        size: usize,
+        status_word: Option<u16>,
+    },
+    /// The exchange failed on the transport.
+    TransportFailed,
+    /// The state of the device changed: connected, unlocked, disconnected.
+    State(&'static str),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::CommandStarted(command) => write!(f, "start {}", command),
+            Event::CommandEnded {
+                command,
+                error: None,
+            } => write!(f, "end {}", command),
+            Event::CommandEnded {
+                command,
+                error: Some(error),
+            } => write!(f, "end {} error={}", command, error),
+            Event::Sent { size, encrypted } => {
+                write!(f, "=> {} bytes", size)?;
+                if *encrypted {
+                    write!(f, " encrypted")?;
+                }
+                Ok(())
+            }
+            Event::Received {
+                size,
+                status_word: None,
+            } => write!(f, "<= {} bytes", size),
+            Event::Received {
+                size,
+                status_word: Some(sw),
+            } => write!(f, "<= {} bytes sw={:04x}", size, sw),
+            Event::TransportFailed => write!(f, "<= transport error"),
+            Event::State(state) => write!(f, "state {}", state),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Milliseconds since the unix epoch, see [`SessionLog::with_clock`].
+    pub timestamp_ms: u64,
+    pub event: Event,
+}
+
+/// Returns the milliseconds since the unix epoch. The std clock is missing
+/// on wasm, where the hosts set their own.
+pub(crate) fn system_clock() -> u64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    return std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    #[cfg(target_arch = "wasm32")]
+    return 0;
+}
+
+/// Ring buffer of the last events of the sessions, the oldest are dropped
+/// once it is full.
+pub struct SessionLog {
+    entries: RefCell<VecDeque<Entry>>,
+    capacity: usize,
+    clock: fn() -> u64,
+}
+
+impl Default for SessionLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl SessionLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: RefCell::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            clock: system_clock,
+        }
+    }
+
+    /// Sets the clock of the timestamps, in milliseconds.
+    pub fn with_clock(mut self, clock: fn() -> u64) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn record(&self, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            timestamp_ms: (self.clock)(),
+            event,
+        });
+    }
+
+    /// Records a change of state of the device.
+    pub fn state(&self, state: &'static str) {
+        self.record(Event::State(state));
+    }
+
+    /// Returns the events kept, the oldest first.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.borrow().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear();
+    }
+
+    /// Returns the events kept, one per line with their timestamp.
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        for entry in self.entries.borrow().iter() {
+            let _ = writeln!(dump, "{} {}", entry.timestamp_ms, entry.event);
+        }
+        dump
+    }
+}
+
+/// Device recording its commands to the log.
+pub struct Logged<D> {
+    pub inner: D,
+    log: Rc<SessionLog>,
+}
+
+impl<D> Logged<D> {
+    pub fn new(inner: D, log: Rc<SessionLog>) -> Self {
+        Self { inner, log }
+    }
+
+    pub fn log(&self) -> &Rc<SessionLog> {
+        &self.log
+    }
+
+    fn start(&self, command: &'static str) {
+        self.log.record(Event::CommandStarted(command));
+    }
+
+    fn end<T, E, F>(
+        &self,
+        command: &'static str,
+        res: Result<T, Error<E, F>>,
+    ) -> Result<T, Error<E, F>> {
+        let error = res.as_ref().err().map(Error::code);
+        self.log.record(Event::CommandEnded { command, error });
+        res
+    }
+}
+
+#[async_trait(?Send)]
+impl<D, E, F> HWI for Logged<D>
+where
+    D: HWI<Error = Error<E, F>>,
+    E: Debug,
+    F: Debug,
+{
+    type Error = Error<E, F>;
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+        self.start("unlock");
+        let res = self.inner.unlock(network).await;
+        if res.is_ok() {
+            self.log.state("unlocked");
+        }
+        self.end("unlock", res)
+    }
+
+    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+        self.start("get_master_fingerprint");
+        let res = self.inner.get_master_fingerprint().await;
+        self.end("get_master_fingerprint", res)
+    }
+
+    async fn get_extended_pubkey(
+        &mut self,
+        path: DerivationPath,
+        display: bool,
+    ) -> Result<Xpub, Self::Error> {
+        self.start("get_extended_pubkey");
+        let res = self.inner.get_extended_pubkey(path, display).await;
+        self.end("get_extended_pubkey", res)
+    }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        self.start("register_wallet");
+        let res = self.inner.register_wallet(policy).await;
+        self.end("register_wallet", res)
+    }
+
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        self.start("sign_psbt");
+        let res = self.inner.sign_psbt(psbt, policy, hmac).await;
+        self.end("sign_psbt", res)
+    }
+}
+
+/// Transport recording the sizes of its exchanges to the log.
+pub struct LoggedTransport<T> {
+    pub inner: T,
+    log: Rc<SessionLog>,
+    /// The response ends with an apdu status word.
+    status_word: bool,
+}
+
+impl<T> LoggedTransport<T> {
+    pub fn new(inner: T, log: Rc<SessionLog>, status_word: bool) -> Self {
+        Self {
+            inner,
+            log,
+            status_word,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for LoggedTransport<T> {
+    type Error = T::Error;
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.log.record(Event::Sent {
+            size: command.len(),
+            encrypted,
+        });
+        let res = self.inner.exchange(command, encrypted).await;
+        self.log.record(match &res {
+            Ok(response) => Event::Received {
+                size: response.len(),
+                status_word: match response.len() {
+                    n if self.status_word && n >= 2 => {
+                        Some(u16::from_be_bytes([response[n - 2], response[n - 1]]))
+                    }
+                    _ => None,
+                },
+            },
+            Err(_) => Event::TransportFailed,
+        });
+        res
+    }
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        self.log.record(Event::Sent {
+            size: command.len(),
+            encrypted,
+        });
+        let res = self.inner.send(command, encrypted).await;
+        if res.is_err() {
+            self.log.record(Event::TransportFailed);
+        }
+        res
+    }
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        let res = self.inner.reconnect().await;
+        self.log.state(if res.is_ok() {
+            "reconnected"
+        } else {
+            "disconnected"
+        });
+        res
+    }
+    fn notify(&mut self, event: &bhwi::Event) {
+        self.inner.notify(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fault::{Fault, FaultInjector},
+        mock::MockLedger,
+        Ledger,
+    };
+
+    #[test]
+    fn test_session_log() {
+        let log = Rc::new(SessionLog::new(6).with_clock(|| 42));
+        let transport = FaultInjector::new(MockLedger::new(&[0x01; 32], Network::Testnet))
+            .with_fault(2, Fault::Fail);
+        let mut ledger = Logged::new(
+            Ledger::new(LoggedTransport::new(transport, log.clone(), true)),
+            log.clone(),
+        );
+        futures::executor::block_on(async {
+            ledger.unlock(Network::Testnet).await.unwrap();
+            ledger.get_master_fingerprint().await.unwrap();
+            assert!(ledger.get_master_fingerprint().await.is_err());
+        });
+
+        // The oldest events were dropped.
+        let events: Vec<Event> = log.entries().into_iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            [
+                Event::Received {
+                    size: 6,
+                    status_word: Some(0x9000),
+                },
+                Event::CommandEnded {
+                    command: "get_master_fingerprint",
+                    error: None,
+                },
+                Event::CommandStarted("get_master_fingerprint"),
+                Event::Sent {
+                    size: 5,
+                    encrypted: false,
+                },
+                Event::TransportFailed,
+                Event::CommandEnded {
+                    command: "get_master_fingerprint",
+                    error: Some("transport"),
+                },
+            ]
+        );
+        assert!(log.dump().lines().all(|line| line.starts_with("42 ")));
+
+        log.clear();
+        assert_eq!(log.dump(), "");
+    }
+}
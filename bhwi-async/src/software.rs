@@ -0,0 +1,344 @@
+//! Software signer holding its keys in memory, with deterministic
+//! signatures. Intended for tests and development, never for real funds.
+//!
+//! The signer is a [`MockSigner`] behind the device interfaces: the commands
+//! are answered by its interpreter, through a transport echoing its empty
+//! frames.
+
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+use bhwi::{
+    bip85,
+    bitcoin::{
+        bip32::{self, Xpriv},
+        Network, NetworkKind,
+    },
+    common,
+    devices::{Capabilities, NETWORKS},
+    mock::MockSigner,
+    Interpreter,
+};
+
+use crate::{Bip85, CommonInterface, Error, HttpClient, OnUnlock, Transport};
+
+pub struct SoftwareSigner {
+    signer: MockSigner,
+    transport: Loopback,
+}
+
+/// Error of a mnemonic the signer is created from.
+#[derive(Debug)]
+pub enum MnemonicError {
+    /// Unknown word, bad length or checksum.
+    Mnemonic(bip39::Error),
+    Bip32(bip32::Error),
+}
+
+impl From<bip39::Error> for MnemonicError {
+    fn from(e: bip39::Error) -> Self {
+        MnemonicError::Mnemonic(e)
+    }
+}
+
+impl From<bip32::Error> for MnemonicError {
+    fn from(e: bip32::Error) -> Self {
+        MnemonicError::Bip32(e)
+    }
+}
+
+impl SoftwareSigner {
+    pub fn new(seed: &[u8], network: Network) -> Result<Self, bip32::Error> {
+        Ok(Self::from_signer(MockSigner::new(seed, network)?))
+    }
+
+    /// Creates the signer from a BIP-39 mnemonic and its passphrase. The
+    /// words are checked against the wordlists and the checksum, the phrase
+    /// and the passphrase are normalized to NFKD.
+    pub fn from_mnemonic(
+        mnemonic: &str,
+        passphrase: &str,
+        network: Network,
+    ) -> Result<Self, MnemonicError> {
+        let mnemonic = bip39::Mnemonic::parse(mnemonic)?;
+        Ok(Self::new(&mnemonic.to_seed(passphrase), network)?)
+    }
+
+    /// The addresses are displayed for the main network or testnet, after
+    /// the network of the keys.
+    pub fn from_xpriv(master: Xpriv) -> Self {
+        let network = match master.network {
+            NetworkKind::Main => Network::Bitcoin,
+            NetworkKind::Test => Network::Testnet,
+        };
+        Self::from_signer(MockSigner::from_xpriv(master, network))
+    }
+
+    pub fn from_signer(signer: MockSigner) -> Self {
+        Self {
+            signer,
+            transport: Loopback,
+        }
+    }
+}
+
+impl CommonInterface<common::Command, common::Transmit, common::Response, common::Error>
+    for SoftwareSigner
+{
+    type TransportError = Infallible;
+    type HttpClientError = Infallible;
+    fn components(
+        &mut self,
+    ) -> (
+        &mut dyn Transport<Error = Self::TransportError>,
+        &dyn HttpClient<Error = Self::HttpClientError>,
+        impl Interpreter<
+            Command = common::Command,
+            Transmit = common::Transmit,
+            Response = common::Response,
+            Error = common::Error,
+        >,
+    ) {
+        (&mut self.transport, &Loopback, self.signer.clone())
+    }
+
+    /// The psbts are signed with the keys of the network of the signer.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            taproot: true,
+            networks: NETWORKS
+                .into_iter()
+                .filter(|network| {
+                    NetworkKind::from(*network) == NetworkKind::from(self.signer.network())
+                })
+                .collect(),
+            ..Capabilities::default()
+        }
+    }
+}
+
+impl OnUnlock for SoftwareSigner {
+    fn on_unlock(&mut self, _response: common::Response) -> Result<(), common::Error> {
+        Ok(())
+    }
+}
+
+/// Transport of the signer: the frames of its interpreter are empty, and
+/// replied with an empty frame.
+pub struct Loopback;
+
+#[async_trait(?Send)]
+impl Transport for Loopback {
+    type Error = Infallible;
+    async fn exchange(
+        &mut self,
+        _command: &[u8],
+        _encrypted: bool,
+    ) -> Result<Vec<u8>, Self::Error> {
+        Ok(Vec::new())
+    }
+}
+
+#[async_trait(?Send)]
+impl HttpClient for Loopback {
+    type Error = Infallible;
+    async fn request(&self, _url: &str, _req: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        unreachable!("Software signer does not need http client")
+    }
+}
+
+#[async_trait(?Send)]
+impl Bip85 for SoftwareSigner {
+    type Error = Error<Infallible, Infallible>;
+
+    async fn bip85_derive(
+        &mut self,
+        application: bip85::Application,
+        index: u32,
+    ) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.signer.bip85_derive(application, index)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SilentPayments, HWI};
+    use bhwi::bitcoin::{
+        absolute::LockTime, bip32::DerivationPath, hex::FromHex, transaction::Version, Amount,
+        CompressedPublicKey, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+        Witness,
+    };
+    use bhwi::silentpayments;
+    use std::str::FromStr;
+
+    fn signer() -> SoftwareSigner {
+        SoftwareSigner::new(
+            &Vec::<u8>::from_hex("000102030405060708090a0b0c0d0e0f").unwrap(),
+            Network::Testnet,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_software_signer_keys() {
+        let mut signer = signer();
+        futures::executor::block_on(async {
+            assert!(signer.unlock(Network::Signet).await.is_ok());
+            assert!(signer.unlock(Network::Bitcoin).await.is_err());
+            // Test vector 1 of BIP-32.
+            assert_eq!(
+                signer.get_master_fingerprint().await.unwrap().to_string(),
+                "3442193e"
+            );
+            let xpub = signer
+                .get_extended_pubkey(DerivationPath::from_str("m/0'").unwrap(), false)
+                .await
+                .unwrap();
+            assert_eq!(xpub.parent_fingerprint.to_string(), "3442193e");
+        });
+    }
+
+    #[test]
+    fn test_software_signer_from_mnemonic() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon \
+                        abandon abandon abandon abandon abandon about";
+        // Test vector of BIP-39.
+        let seed = Vec::<u8>::from_hex(
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+             1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+        )
+        .unwrap();
+        let fingerprint = |signer: &mut SoftwareSigner| {
+            futures::executor::block_on(signer.get_master_fingerprint()).unwrap()
+        };
+        assert_eq!(
+            fingerprint(
+                &mut SoftwareSigner::from_mnemonic(mnemonic, "TREZOR", Network::Bitcoin).unwrap()
+            ),
+            fingerprint(&mut SoftwareSigner::new(&seed, Network::Bitcoin).unwrap())
+        );
+
+        // The passphrase is normalized, a composed é is its decomposed form.
+        assert_eq!(
+            fingerprint(
+                &mut SoftwareSigner::from_mnemonic(mnemonic, "caf\u{e9}", Network::Bitcoin)
+                    .unwrap()
+            ),
+            fingerprint(
+                &mut SoftwareSigner::from_mnemonic(mnemonic, "cafe\u{301}", Network::Bitcoin)
+                    .unwrap()
+            )
+        );
+
+        assert!(matches!(
+            SoftwareSigner::from_mnemonic(
+                "abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon abandon abandon abandon",
+                "",
+                Network::Bitcoin
+            ),
+            Err(MnemonicError::Mnemonic(_))
+        ));
+        assert!(matches!(
+            SoftwareSigner::from_mnemonic(
+                "abandon abandon abandon abandon abandon abandon \
+                 abandon abandon abandon abandon abandon bitcoin",
+                "",
+                Network::Bitcoin
+            ),
+            Err(MnemonicError::Mnemonic(_))
+        ));
+
+        let mut signer = SoftwareSigner::from_mnemonic(mnemonic, "", Network::Bitcoin).unwrap();
+        assert_eq!(
+            futures::executor::block_on(signer.get_master_fingerprint())
+                .unwrap()
+                .to_string(),
+            "73c5da0a"
+        );
+    }
+
+    #[test]
+    fn test_software_signer_bip85() {
+        let mut signer = signer();
+        let application = bip85::Application::Hex { num_bytes: 32 };
+        let entropy = futures::executor::block_on(signer.bip85_derive(application, 0)).unwrap();
+        assert_eq!(entropy.len(), 32);
+        assert_ne!(
+            futures::executor::block_on(signer.bip85_derive(application, 1)).unwrap(),
+            entropy
+        );
+        assert!(futures::executor::block_on(
+            signer.bip85_derive(bip85::Application::Hex { num_bytes: 8 }, 0)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_software_signer_silent_payments() {
+        let mut signer = signer();
+        futures::executor::block_on(async {
+            let keys = signer.get_silent_payment_keys(0).await.unwrap();
+            let scan = signer
+                .get_extended_pubkey(
+                    silentpayments::scan_path(NetworkKind::Test, 0).unwrap(),
+                    false,
+                )
+                .await
+                .unwrap();
+            assert_eq!(keys.scan, scan.public_key);
+            assert_ne!(signer.get_silent_payment_keys(1).await.unwrap(), keys);
+        });
+    }
+
+    #[test]
+    fn test_software_signer_sign_psbt() {
+        let mut signer = signer();
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let (fingerprint, xpub) = futures::executor::block_on(async {
+            (
+                signer.get_master_fingerprint().await.unwrap(),
+                signer
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .unwrap(),
+            )
+        });
+        let script_pubkey =
+            ScriptBuf::new_p2wpkh(&CompressedPublicKey(xpub.public_key).wpubkey_hash());
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        });
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(xpub.public_key, (fingerprint, path));
+
+        let signed =
+            futures::executor::block_on(signer.sign_psbt(psbt.clone(), None, None)).unwrap();
+        assert_eq!(signed.inputs[0].partial_sigs.len(), 1);
+
+        // Signatures are deterministic.
+        assert_eq!(
+            futures::executor::block_on(signer.sign_psbt(psbt, None, None)).unwrap(),
+            signed
+        );
+    }
+}
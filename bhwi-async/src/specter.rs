@@ -0,0 +1,77 @@
+use crate::{HttpClient, Transport};
+use async_trait::async_trait;
+use bhwi::{
+    authorization::Authorization,
+    common,
+    devices::Capabilities,
+    specter::{SpecterCommand, SpecterError, SpecterInterpreter, SpecterResponse, SpecterTransmit},
+    Interpreter,
+};
+
+pub struct Specter<T> {
+    pub transport: T,
+    authorization: Option<Authorization>,
+}
+
+impl<T> Specter<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            authorization: None,
+        }
+    }
+}
+
+impl<C, T, R, E, F> crate::CommonInterface<C, T, R, E> for Specter<F>
+where
+    C: TryInto<SpecterCommand>,
+    C::Error: Into<SpecterError>,
+    T: From<SpecterTransmit>,
+    R: From<SpecterResponse>,
+    E: From<SpecterError>,
+    F: Transport,
+{
+    type TransportError = F::Error;
+    type HttpClientError = SpecterError;
+    fn components(
+        &mut self,
+    ) -> (
+        &mut dyn Transport<Error = Self::TransportError>,
+        &dyn HttpClient<Error = Self::HttpClientError>,
+        impl Interpreter<Command = C, Transmit = T, Response = R, Error = E>,
+    ) {
+        (
+            &mut self.transport,
+            &DummyClient {},
+            SpecterInterpreter::default(),
+        )
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+    fn capabilities(&self) -> Capabilities {
+        bhwi::specter::capabilities()
+    }
+}
+
+impl<T> crate::Authorize for Specter<T> {
+    fn authorization_mut(&mut self) -> &mut Option<Authorization> {
+        &mut self.authorization
+    }
+}
+
+impl<T> crate::OnUnlock for Specter<T> {
+    fn on_unlock(&mut self, _response: common::Response) -> Result<(), common::Error> {
+        Ok(())
+    }
+}
+
+pub struct DummyClient;
+#[async_trait(?Send)]
+impl HttpClient for DummyClient {
+    type Error = SpecterError;
+    async fn request(&self, _url: &str, _req: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        unreachable!("Specter does not need http client")
+    }
+}
@@ -0,0 +1,67 @@
+//! Timer of the runner, independent of the async runtime of the host: the
+//! native targets sleep in a thread, wasm uses the timer of the page or of
+//! the worker.
+
+/// Resolves after the delay. Dropping the future ends its thread.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn sleep(ms: u64) {
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    let (done, wake) = futures::channel::oneshot::channel::<()>();
+    // Never sent, the thread wakes once it is dropped with the future.
+    let (_cancel, cancelled) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) =
+            cancelled.recv_timeout(std::time::Duration::from_millis(ms))
+        {
+            let _ = done.send(());
+        }
+    });
+    let _ = wake.await;
+}
+
+/// Resolves after the delay, at once without timer.
+#[cfg(target_arch = "wasm32")]
+pub async fn sleep(ms: u64) {
+    use wasm_bindgen::{JsCast, JsValue};
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let global = js_sys::global();
+        let set_timeout = js_sys::Reflect::get(&global, &JsValue::from_str("setTimeout"))
+            .ok()
+            .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+        let res = match set_timeout {
+            Some(set_timeout) => {
+                set_timeout.call2(&global, &resolve, &JsValue::from_f64(ms as f64))
+            }
+            None => resolve.call0(&JsValue::UNDEFINED),
+        };
+        if res.is_err() {
+            let _ = resolve.call0(&JsValue::UNDEFINED);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+/// Milliseconds since an arbitrary origin, to measure the elapsed time.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep() {
+        let start = std::time::Instant::now();
+        futures::executor::block_on(sleep(20));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_sleep_cancelled() {
+        use futures::future::{select, Either};
+        let res = futures::executor::block_on(select(Box::pin(sleep(60_000)), Box::pin(sleep(10))));
+        assert!(matches!(res, Either::Right(_)));
+    }
+}
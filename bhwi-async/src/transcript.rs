@@ -0,0 +1,229 @@
+//! Recording and replay of the exchanges between a host and a device.
+//!
+//! A transcript is stored as text, one line per frame: `>` followed by the
+//! hex of the command sent and `<` followed by the hex of the response.
+//! Empty lines and lines starting with `#` are ignored.
+
+use async_trait::async_trait;
+use bhwi::bitcoin::hex::{DisplayHex, FromHex};
+
+use crate::Transport;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exchange {
+    pub command: Vec<u8>,
+    pub response: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptError {
+    /// The line of the transcript could not be parsed.
+    Parse(usize),
+    /// The command sent differs from the one of the transcript.
+    UnexpectedCommand { expected: Vec<u8>, got: Vec<u8> },
+    /// All the exchanges of the transcript were replayed.
+    EndOfTranscript,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Transcript {
+    pub fn parse(s: &str) -> Result<Self, TranscriptError> {
+        let mut exchanges = Vec::new();
+        let mut command = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (direction, data) = line.split_at_checked(1).ok_or(TranscriptError::Parse(i))?;
+            let data = Vec::<u8>::from_hex(data.trim()).map_err(|_| TranscriptError::Parse(i))?;
+            match (direction, command.take()) {
+                (">", None) => command = Some(data),
+                ("<", Some(command)) => exchanges.push(Exchange {
+                    command,
+                    response: data,
+                }),
+                _ => return Err(TranscriptError::Parse(i)),
+            }
+        }
+        if command.is_some() {
+            return Err(TranscriptError::Parse(s.lines().count()));
+        }
+        Ok(Self { exchanges })
+    }
+}
+
+impl std::fmt::Display for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for exchange in &self.exchanges {
+            writeln!(f, "> {}", exchange.command.to_lower_hex_string())?;
+            writeln!(f, "< {}", exchange.response.to_lower_hex_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Transport recording the exchanges with the inner transport.
+pub struct Recorder<T> {
+    pub inner: T,
+    pub transcript: Transcript,
+}
+
+impl<T> Recorder<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            transcript: Transcript::default(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for Recorder<T> {
+    type Error = T::Error;
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let response = self.inner.exchange(command, encrypted).await?;
+        self.transcript.exchanges.push(Exchange {
+            command: command.to_vec(),
+            response: response.clone(),
+        });
+        Ok(response)
+    }
+    /// The frame is recorded with an empty response.
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        self.inner.send(command, encrypted).await?;
+        self.transcript.exchanges.push(Exchange {
+            command: command.to_vec(),
+            response: Vec::new(),
+        });
+        Ok(())
+    }
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.inner.reconnect().await
+    }
+    fn notify(&mut self, event: &bhwi::Event) {
+        self.inner.notify(event)
+    }
+}
+
+/// Transport answering with the responses of a transcript, after checking
+/// that the commands sent are the ones recorded.
+pub struct Replay {
+    exchanges: std::vec::IntoIter<Exchange>,
+}
+
+impl Replay {
+    pub fn new(transcript: Transcript) -> Self {
+        Self {
+            exchanges: transcript.exchanges.into_iter(),
+        }
+    }
+
+    /// Returns true if all the exchanges were replayed.
+    pub fn is_done(&self) -> bool {
+        self.exchanges.len() == 0
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for Replay {
+    type Error = TranscriptError;
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let exchange = self
+            .exchanges
+            .next()
+            .ok_or(TranscriptError::EndOfTranscript)?;
+        if exchange.command != command {
+            return Err(TranscriptError::UnexpectedCommand {
+                expected: exchange.command,
+                got: command.to_vec(),
+            });
+        }
+        Ok(exchange.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockLedger, Ledger, HWI};
+    use bhwi::bitcoin::{bip32::DerivationPath, Network};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_transcript() {
+        let transcript = Transcript::parse("# comment\n> e1050001\n< f5acc2fd9000\n").unwrap();
+        assert_eq!(
+            transcript.exchanges,
+            vec![Exchange {
+                command: vec![0xe1, 0x05, 0x00, 0x01],
+                response: vec![0xf5, 0xac, 0xc2, 0xfd, 0x90, 0x00],
+            }]
+        );
+        assert_eq!(
+            Transcript::parse(&transcript.to_string()).unwrap(),
+            transcript
+        );
+        assert_eq!(Transcript::parse("< 9000"), Err(TranscriptError::Parse(0)));
+        assert!(Transcript::parse("> e105").is_err());
+    }
+
+    #[test]
+    fn test_replay_fixture() {
+        let transcript =
+            Transcript::parse(include_str!("../fixtures/ledger_mock_xpub.txt")).unwrap();
+        let mut ledger = Ledger::new(Replay::new(transcript));
+        futures::executor::block_on(async {
+            assert_eq!(
+                ledger.get_master_fingerprint().await.unwrap().to_string(),
+                "4ba43603"
+            );
+            let xpub = ledger
+                .get_extended_pubkey(DerivationPath::from_str("m/84'/1'/0'").unwrap(), false)
+                .await
+                .unwrap();
+            assert_eq!(xpub.depth, 3);
+        });
+        assert!(ledger.transport.is_done());
+    }
+
+    #[test]
+    fn test_record_and_replay_ledger() {
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let mut ledger = Ledger::new(Recorder::new(MockLedger::new(
+            &[0x01; 32],
+            Network::Testnet,
+        )));
+        let (fingerprint, xpub) = futures::executor::block_on(async {
+            (
+                ledger.get_master_fingerprint().await.unwrap(),
+                ledger
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .unwrap(),
+            )
+        });
+
+        let transcript = Transcript::parse(&ledger.transport.transcript.to_string()).unwrap();
+        let mut ledger = Ledger::new(Replay::new(transcript));
+        futures::executor::block_on(async {
+            assert_eq!(ledger.get_master_fingerprint().await.unwrap(), fingerprint);
+            assert_eq!(
+                ledger
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .unwrap(),
+                xpub
+            );
+            assert!(ledger.transport.is_done());
+            assert!(matches!(
+                ledger.get_master_fingerprint().await,
+                Err(crate::Error::Transport(TranscriptError::EndOfTranscript))
+            ));
+        });
+    }
+}
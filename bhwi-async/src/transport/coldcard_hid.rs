@@ -1,7 +1,10 @@
-use crate::{transport::Channel, Transport};
+use crate::{
+    transport::{Channel, Pacing},
+    Transport,
+};
 use async_trait::async_trait;
 
-pub const COLDCARD_VID: u16 = 0xd13e;
+pub use bhwi::devices::COLDCARD_VID;
 const COLDCARD_PACKET_WRITE_SIZE: usize = 63;
 const COLDCARD_PACKET_READ_SIZE: usize = 64;
 
@@ -19,11 +22,21 @@ impl From<std::io::Error> for ColdcardHIDError {
 
 pub struct ColdcardTransportHID<C> {
     channel: C,
+    pacing: Pacing,
 }
 
 impl<C> ColdcardTransportHID<C> {
     pub fn new(channel: C) -> Self {
-        Self { channel }
+        Self {
+            channel,
+            pacing: Pacing::NONE,
+        }
+    }
+
+    /// Paces the packets of a request.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
     }
 }
 
@@ -46,6 +59,11 @@ impl<C: Channel> Transport for ColdcardTransportHID<C> {
                 };
             buffer[1..1 + chunk.len()].copy_from_slice(chunk);
 
+            let delay_ms = self.pacing.delay_ms(i);
+            if delay_ms > 0 {
+                self.channel.sleep(delay_ms).await;
+            }
+
             match self.channel.send(&buffer).await {
                 Ok(size) => {
                     if size < buffer.len() {
@@ -0,0 +1,187 @@
+//! Bluetooth LE transport of the Ledger Nano X and Stax. The channel writes
+//! the frames to the write characteristic of the Ledger service, see
+//! [`ble::SERVICES`], and receives the notifications of its notify
+//! characteristic. The MTU is negotiated before the first command.
+
+use async_trait::async_trait;
+use bhwi::ledger::transport::{ble, framing::FramingError};
+
+use crate::{
+    transport::{Channel, Pacing},
+    Transport,
+};
+
+/// Size of the largest notification, the maximum length of an attribute.
+const MAX_NOTIFICATION_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum LedgerBLEError {
+    Comm(&'static str),
+    Ble(std::io::Error),
+}
+
+impl From<std::io::Error> for LedgerBLEError {
+    fn from(value: std::io::Error) -> Self {
+        LedgerBLEError::Ble(value)
+    }
+}
+
+impl From<FramingError> for LedgerBLEError {
+    fn from(value: FramingError) -> Self {
+        LedgerBLEError::Comm(match value {
+            FramingError::IncompleteHeader => "Read error. Incomplete header",
+            FramingError::InvalidChannel => "Invalid channel",
+            FramingError::InvalidTag => "Invalid tag",
+            FramingError::InvalidSequence => "Invalid sequence idx",
+        })
+    }
+}
+
+pub struct LedgerTransportBLE<C> {
+    channel: C,
+    /// Frame size negotiated with the device.
+    mtu: Option<usize>,
+    pacing: Pacing,
+}
+
+impl<C> LedgerTransportBLE<C> {
+    pub fn new(channel: C) -> Self {
+        Self {
+            channel,
+            mtu: None,
+            pacing: Pacing::NONE,
+        }
+    }
+
+    /// Paces the frames of a command, for the channels writing them without
+    /// response.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+}
+
+impl<C: Channel> LedgerTransportBLE<C> {
+    async fn send(&mut self, frame: &[u8]) -> Result<(), LedgerBLEError> {
+        let size = self.channel.send(frame).await?;
+        if size < frame.len() {
+            return Err(LedgerBLEError::Comm(
+                "BLE write error. Could not send whole message",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn mtu(&mut self) -> Result<usize, LedgerBLEError> {
+        if let Some(mtu) = self.mtu {
+            return Ok(mtu);
+        }
+        self.send(&ble::mtu_request()).await?;
+        let mut buffer = vec![0u8; MAX_NOTIFICATION_SIZE];
+        let res = self.channel.receive(&mut buffer).await?;
+        let mtu = ble::parse_mtu(&buffer[..res])?;
+        self.mtu = Some(mtu);
+        Ok(mtu)
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: Channel> Transport for LedgerTransportBLE<C> {
+    type Error = LedgerBLEError;
+
+    async fn exchange(
+        &mut self,
+        apdu_command: &[u8],
+        _encrypted: bool,
+    ) -> Result<Vec<u8>, Self::Error> {
+        let mtu = self.mtu().await?;
+        for (i, frame) in ble::frame(apdu_command, mtu).into_iter().enumerate() {
+            let delay_ms = self.pacing.delay_ms(i);
+            if delay_ms > 0 {
+                self.channel.sleep(delay_ms).await;
+            }
+            self.send(&frame).await?;
+        }
+
+        let mut unframer = ble::Unframer::new();
+        let mut buffer = vec![0u8; MAX_NOTIFICATION_SIZE];
+        loop {
+            let res = self.channel.receive(&mut buffer).await?;
+            if let Some(answer) = unframer.push(&buffer[..res])? {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// The frame size is negotiated again with the reopened device.
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.channel.reopen().await?;
+        self.mtu = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::VecDeque};
+
+    /// Ledger notifying the answers in frames of its MTU.
+    struct SimulatedBle {
+        mtu: u8,
+        sent: RefCell<Vec<Vec<u8>>>,
+        responses: VecDeque<Vec<u8>>,
+        notifications: VecDeque<Vec<u8>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Channel for SimulatedBle {
+        async fn send(&self, data: &[u8]) -> Result<usize, std::io::Error> {
+            self.sent.borrow_mut().push(data.to_vec());
+            Ok(data.len())
+        }
+
+        async fn receive(&mut self, data: &mut [u8]) -> Result<usize, std::io::Error> {
+            if self.sent.borrow().len() == 1 {
+                let answer = [0x08, 0x00, 0x00, 0x00, 0x01, self.mtu];
+                data[..answer.len()].copy_from_slice(&answer);
+                return Ok(answer.len());
+            }
+            if self.notifications.is_empty() {
+                let response = self.responses.pop_front().unwrap_or_default();
+                self.notifications = ble::frame(&response, self.mtu as usize).into();
+            }
+            let frame = self.notifications.pop_front().unwrap_or_default();
+            data[..frame.len()].copy_from_slice(&frame);
+            Ok(frame.len())
+        }
+    }
+
+    #[test]
+    fn test_exchange_ble() {
+        let response: Vec<u8> = (0..150).map(|i| i as u8).collect();
+        let mut transport = LedgerTransportBLE::new(SimulatedBle {
+            mtu: 50,
+            sent: RefCell::new(Vec::new()),
+            responses: vec![response.clone(), vec![0x90, 0x00]].into(),
+            notifications: VecDeque::new(),
+        });
+        futures::executor::block_on(async {
+            assert_eq!(
+                transport.exchange(&[0x00; 100], false).await.unwrap(),
+                response
+            );
+            assert_eq!(
+                transport.exchange(&[0xe0, 0x01], false).await.unwrap(),
+                vec![0x90, 0x00]
+            );
+        });
+        let sent = transport.channel.sent.borrow();
+        // The MTU request, the 100 bytes apdu and its length in frames of 47
+        // bytes of data, then the last apdu.
+        assert_eq!(sent[0], ble::mtu_request());
+        assert_eq!(sent.len(), 1 + 3 + 1);
+        assert!(sent.iter().all(|frame| frame.len() <= 50));
+        assert_eq!(transport.mtu, Some(50));
+    }
+}
@@ -14,18 +14,16 @@
 *  limitations under the License.
 ********************************************************************************/
 use async_trait::async_trait;
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::Cursor;
+use bhwi::ledger::transport::framing::{self, FramingError, Unframer};
 
-use crate::{transport::Channel, Transport};
+use crate::{
+    transport::{Channel, Pacing},
+    Transport,
+};
 
-pub const LEDGER_VID: u16 = 0x2c97;
+pub use bhwi::devices::LEDGER_VID;
 pub const LEDGER_USAGE_PAGE: u16 = 0xFFA0;
-pub const LEDGER_CHANNEL: u16 = 0x0101;
-// for Windows compatability, we prepend the buffer with a 0x00
-// so the actual buffer is 64 bytes
-const LEDGER_PACKET_WRITE_SIZE: u8 = 64;
-const LEDGER_PACKET_READ_SIZE: u8 = 64;
+pub const LEDGER_CHANNEL: u16 = framing::CHANNEL;
 
 #[derive(Debug)]
 pub enum LedgerHIDError {
@@ -39,13 +37,35 @@ impl From<std::io::Error> for LedgerHIDError {
     }
 }
 
+impl From<FramingError> for LedgerHIDError {
+    fn from(value: FramingError) -> Self {
+        LedgerHIDError::Comm(match value {
+            FramingError::IncompleteHeader => "Read error. Incomplete header",
+            FramingError::InvalidChannel => "Invalid channel",
+            FramingError::InvalidTag => "Invalid tag",
+            FramingError::InvalidSequence => "Invalid sequence idx",
+        })
+    }
+}
+
 pub struct LedgerTransportHID<C> {
     channel: C,
+    pacing: Pacing,
 }
 
 impl<C> LedgerTransportHID<C> {
     pub fn new(channel: C) -> Self {
-        Self { channel }
+        Self {
+            channel,
+            pacing: Pacing::NONE,
+        }
+    }
+
+    /// Paces the reports of a command, see [`Pacing::for_device`] for the
+    /// defaults of the models.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
     }
 }
 
@@ -58,89 +78,184 @@ impl<C: Channel> Transport for LedgerTransportHID<C> {
         apdu_command: &[u8],
         _encrypted: bool,
     ) -> Result<Vec<u8>, Self::Error> {
-        let command_length = apdu_command.len();
-        let mut in_data = Vec::with_capacity(command_length + 2);
-        in_data.push(((command_length >> 8) & 0xFF) as u8);
-        in_data.push((command_length & 0xFF) as u8);
-        in_data.extend_from_slice(apdu_command);
-
-        let mut buffer = vec![0u8; LEDGER_PACKET_WRITE_SIZE as usize];
-        // Windows platform requires 0x00 prefix and Linux/Mac tolerate this as well
-        // buffer[0] = 0x00;
-        buffer[0] = ((LEDGER_CHANNEL >> 8) & 0xFF) as u8; // channel big endian
-        buffer[1] = (LEDGER_CHANNEL & 0xFF) as u8; // channel big endian
-        buffer[2] = 0x05u8;
-
-        for (sequence_idx, chunk) in in_data
-            .chunks((LEDGER_PACKET_WRITE_SIZE - 5) as usize)
-            .enumerate()
-        {
-            buffer[3] = ((sequence_idx >> 8) & 0xFF) as u8; // sequence_idx big endian
-            buffer[4] = (sequence_idx & 0xFF) as u8; // sequence_idx big endian
-            buffer[5..5 + chunk.len()].copy_from_slice(chunk);
-
-            match self.channel.send(&buffer).await {
-                Ok(size) => {
-                    if size < buffer.len() {
-                        return Err(LedgerHIDError::Comm(
-                            "USB write error. Could not send whole message",
-                        ));
-                    }
-                }
-                Err(e) => {
-                    return Err(LedgerHIDError::Hid(e));
-                }
+        for (i, report) in framing::frame(apdu_command).into_iter().enumerate() {
+            let delay_ms = self.pacing.delay_ms(i);
+            if delay_ms > 0 {
+                self.channel.sleep(delay_ms).await;
+            }
+            let size = self.channel.send(&report).await?;
+            if size < report.len() {
+                return Err(LedgerHIDError::Comm(
+                    "USB write error. Could not send whole message",
+                ));
             }
         }
 
-        let mut apdu_answer: Vec<u8> = Vec::with_capacity(256);
-        let mut buffer = vec![0u8; LEDGER_PACKET_READ_SIZE as usize];
-        let mut sequence_idx = 0u16;
-        let mut expected_apdu_len = 0usize;
-
+        let mut unframer = Unframer::new();
+        let mut buffer = vec![0u8; framing::PACKET_SIZE];
         loop {
             let res = self.channel.receive(&mut buffer).await?;
-
-            if (sequence_idx == 0 && res < 7) || res < 5 {
-                return Err(LedgerHIDError::Comm("Read error. Incomplete header"));
+            if let Some(answer) = unframer.push(&buffer[..res])? {
+                return Ok(answer);
             }
+        }
+    }
 
-            let mut rdr = Cursor::new(&buffer);
+    /// The reports of the closed channel are lost, the reopened one starts
+    /// with a new answer.
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        Ok(self.channel.reopen().await?)
+    }
+}
 
-            let rcv_channel = rdr.read_u16::<BigEndian>()?;
-            let rcv_tag = rdr.read_u8()?;
-            let rcv_seq_idx = rdr.read_u16::<BigEndian>()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::VecDeque};
 
-            if rcv_channel != LEDGER_CHANNEL {
-                return Err(LedgerHIDError::Comm("Invalid channel"));
-            }
-            if rcv_tag != 0x05u8 {
-                return Err(LedgerHIDError::Comm("Invalid tag"));
-            }
+    /// HID device answering each apdu with the given response,
+    /// disconnected once it has no more responses.
+    struct SimulatedHid {
+        sent: RefCell<Vec<Vec<u8>>>,
+        /// Delays waited before the reports, with the count of reports sent.
+        sleeps: RefCell<Vec<(usize, u32)>>,
+        responses: VecDeque<Vec<u8>>,
+        reports: VecDeque<Vec<u8>>,
+        reopened: usize,
+    }
 
-            if rcv_seq_idx != sequence_idx {
-                return Err(LedgerHIDError::Comm("Invalid sequence idx"));
+    impl SimulatedHid {
+        fn new(responses: Vec<Vec<u8>>) -> Self {
+            Self {
+                sent: RefCell::new(Vec::new()),
+                sleeps: RefCell::new(Vec::new()),
+                responses: responses.into(),
+                reports: VecDeque::new(),
+                reopened: 0,
             }
+        }
+    }
 
-            if rcv_seq_idx == 0 {
-                expected_apdu_len = rdr.read_u16::<BigEndian>()? as usize;
-            }
+    #[async_trait(?Send)]
+    impl Channel for SimulatedHid {
+        async fn send(&self, data: &[u8]) -> Result<usize, std::io::Error> {
+            self.sent.borrow_mut().push(data.to_vec());
+            Ok(data.len())
+        }
 
-            let available: usize = buffer.len() - rdr.position() as usize;
-            let missing: usize = expected_apdu_len - apdu_answer.len();
-            let end_p = rdr.position() as usize + std::cmp::min(available, missing);
+        async fn sleep(&self, ms: u32) {
+            let sent = self.sent.borrow().len();
+            self.sleeps.borrow_mut().push((sent, ms));
+        }
 
-            let new_chunk = &buffer[rdr.position() as usize..end_p];
+        async fn receive(&mut self, data: &mut [u8]) -> Result<usize, std::io::Error> {
+            if self.reports.is_empty() {
+                let Some(response) = self.responses.pop_front() else {
+                    return Ok(0);
+                };
+                let mut payload = (response.len() as u16).to_be_bytes().to_vec();
+                payload.extend(response);
+                for (i, chunk) in payload.chunks(59).enumerate() {
+                    let mut report = vec![0x01, 0x01, 0x05];
+                    report.extend((i as u16).to_be_bytes());
+                    report.extend(chunk);
+                    report.resize(64, 0x00);
+                    self.reports.push_back(report);
+                }
+            }
+            let report = self.reports.pop_front().unwrap();
+            data.copy_from_slice(&report);
+            Ok(data.len())
+        }
 
-            apdu_answer.extend_from_slice(new_chunk);
+        async fn reopen(&mut self) -> Result<(), std::io::Error> {
+            self.reopened += 1;
+            self.reports.clear();
+            Ok(())
+        }
+    }
 
-            if apdu_answer.len() >= expected_apdu_len {
-                break;
+    #[test]
+    fn test_exchange_framing() {
+        let response: Vec<u8> = (0..150).map(|i| i as u8).collect();
+        let mut transport =
+            LedgerTransportHID::new(SimulatedHid::new(vec![vec![0x90, 0x00], response.clone()]));
+        futures::executor::block_on(async {
+            assert_eq!(
+                transport
+                    .exchange(&[0xe0, 0x01, 0x00, 0x00, 0x00], false)
+                    .await
+                    .unwrap(),
+                vec![0x90, 0x00]
+            );
+            // Response split across three reports.
+            assert_eq!(
+                transport.exchange(&[0x00; 200], false).await.unwrap(),
+                response
+            );
+            {
+                let sent = transport.channel.sent.borrow();
+                // The 200 bytes apdu and its length are split across four reports.
+                assert_eq!(sent.len(), 5);
+                for (i, report) in sent[1..].iter().enumerate() {
+                    assert_eq!(report.len(), 64);
+                    assert_eq!(report[..5], [0x01, 0x01, 0x05, 0x00, i as u8]);
+                }
+                assert_eq!(sent[1][5..7], [0x00, 200]);
             }
 
-            sequence_idx += 1;
-        }
+            // The device is disconnected.
+            assert!(matches!(
+                transport
+                    .exchange(&[0xe0, 0x01, 0x00, 0x00, 0x00], false)
+                    .await,
+                Err(LedgerHIDError::Comm("Read error. Incomplete header"))
+            ));
+        });
+    }
 
-        Ok(apdu_answer)
+    #[test]
+    fn test_reconnect() {
+        let mut transport = LedgerTransportHID::new(SimulatedHid::new(vec![vec![0x90, 0x00]]));
+        futures::executor::block_on(transport.reconnect()).unwrap();
+        assert_eq!(transport.channel.reopened, 1);
+        assert_eq!(
+            futures::executor::block_on(transport.exchange(&[0xb0, 0x01, 0x00, 0x00, 0x00], false))
+                .unwrap(),
+            vec![0x90, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_exchange_pacing() {
+        let mut transport = LedgerTransportHID::new(SimulatedHid::new(vec![vec![0x90, 0x00]]))
+            .with_pacing(Pacing {
+                min_interval_ms: 3,
+                max_in_flight: 2,
+            });
+        futures::executor::block_on(transport.exchange(&[0x00; 200], false)).unwrap();
+        // Four reports, the delay before the third one.
+        assert_eq!(transport.channel.sent.borrow().len(), 4);
+        assert_eq!(*transport.channel.sleeps.borrow(), [(2, 3)]);
+    }
+
+    #[test]
+    fn test_exchange_invalid_reports() {
+        for (offset, byte, error) in [
+            (0, 0x02, "Invalid channel"),
+            (2, 0x06, "Invalid tag"),
+            (4, 0x01, "Invalid sequence idx"),
+        ] {
+            let mut channel = SimulatedHid::new(vec![vec![0x90, 0x00]]);
+            let mut report = vec![0x01, 0x01, 0x05, 0x00, 0x00, 0x00, 0x02, 0x90, 0x00];
+            report[offset] = byte;
+            report.resize(64, 0x00);
+            channel.reports.push_back(report);
+            let mut transport = LedgerTransportHID::new(channel);
+            assert!(matches!(
+                futures::executor::block_on(transport.exchange(&[0xe0], false)),
+                Err(LedgerHIDError::Comm(e)) if e == error
+            ));
+        }
     }
 }
@@ -1,10 +1,21 @@
 pub mod coldcard_hid;
+pub mod ledger_ble;
 pub mod ledger_hid;
 
 use async_trait::async_trait;
 
+pub use bhwi::devices::Pacing;
+
 #[async_trait(?Send)]
 pub trait Channel {
     async fn send(&self, data: &[u8]) -> Result<usize, std::io::Error>;
     async fn receive(&mut self, data: &mut [u8]) -> Result<usize, std::io::Error>;
+    /// Waits before the next frame, see [`Pacing`]. The channels without
+    /// timer do not wait.
+    async fn sleep(&self, _ms: u32) {}
+    /// Waits for the device to reappear after its re-enumeration and opens
+    /// it again, the channel is kept by default.
+    async fn reopen(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
 }
@@ -0,0 +1,245 @@
+use crate::{HttpClient, Transport};
+use async_trait::async_trait;
+use bhwi::{
+    authorization::Authorization,
+    bitcoin::Network,
+    common,
+    devices::Capabilities,
+    trezor::{TrezorInterpreter, TrezorPrompt, TrezorRecipient, TrezorTransmit},
+    Interpreter,
+};
+
+/// Trezor exchanging the messages of its protocol, the transport frames them
+/// in the reports of its link.
+pub struct Trezor<T> {
+    pub transport: T,
+    network: Network,
+    passphrase: String,
+    authorization: Option<Authorization>,
+}
+
+impl<T> Trezor<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            network: Network::Bitcoin,
+            passphrase: String::new(),
+            authorization: None,
+        }
+    }
+
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Passphrase sent when the device requests it, empty for the default
+    /// wallet.
+    pub fn with_passphrase(mut self, passphrase: String) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+}
+
+impl<F: Transport>
+    crate::CommonInterface<common::Command, common::Transmit, common::Response, common::Error>
+    for Trezor<F>
+{
+    type TransportError = F::Error;
+    type HttpClientError = common::Error;
+    fn components(
+        &mut self,
+    ) -> (
+        &mut dyn Transport<Error = Self::TransportError>,
+        &dyn HttpClient<Error = Self::HttpClientError>,
+        impl Interpreter<
+            Command = common::Command,
+            Transmit = common::Transmit,
+            Response = common::Response,
+            Error = common::Error,
+        >,
+    ) {
+        (
+            &mut self.transport,
+            &DummyClient {},
+            TrezorPrompts {
+                inner: TrezorInterpreter::default().with_network(self.network),
+                passphrase: self.passphrase.clone(),
+            },
+        )
+    }
+
+    fn authorization(&self) -> Option<&Authorization> {
+        self.authorization.as_ref()
+    }
+    fn capabilities(&self) -> Capabilities {
+        bhwi::trezor::capabilities()
+    }
+}
+
+impl<T> crate::Authorize for Trezor<T> {
+    fn authorization_mut(&mut self) -> &mut Option<Authorization> {
+        &mut self.authorization
+    }
+}
+
+impl<T> crate::OnUnlock for Trezor<T> {
+    fn on_unlock(&mut self, _response: common::Response) -> Result<(), common::Error> {
+        Ok(())
+    }
+}
+
+/// Interpreter of the Trezor answering its prompts to the user: the buttons
+/// are acknowledged, the user confirming on the device, and the passphrase
+/// is the one of the device. The pin is not entered by the host, the
+/// device must be unlocked.
+struct TrezorPrompts {
+    inner: TrezorInterpreter<common::Command, TrezorTransmit, common::Response, common::Error>,
+    passphrase: String,
+}
+
+impl TrezorPrompts {
+    fn transmit(
+        &mut self,
+        mut transmit: TrezorTransmit,
+    ) -> Result<common::Transmit, common::Error> {
+        loop {
+            let answer = match transmit.recipient {
+                TrezorRecipient::Device => {
+                    return Ok(common::Transmit {
+                        recipient: common::Recipient::Device,
+                        payload: transmit.payload,
+                        encrypted: false,
+                    })
+                }
+                TrezorRecipient::User(TrezorPrompt::Button { .. }) => Vec::new(),
+                TrezorRecipient::User(TrezorPrompt::Passphrase) => {
+                    self.passphrase.clone().into_bytes()
+                }
+                TrezorRecipient::User(TrezorPrompt::Pin { .. }) => {
+                    return Err(common::Error::DeviceLocked)
+                }
+            };
+            transmit = self
+                .inner
+                .exchange(answer)?
+                .ok_or(common::Error::NoErrorOrResult)?;
+        }
+    }
+}
+
+impl Interpreter for TrezorPrompts {
+    type Command = common::Command;
+    type Transmit = common::Transmit;
+    type Response = common::Response;
+    type Error = common::Error;
+
+    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        let transmit = self.inner.start(command)?;
+        self.transmit(transmit)
+    }
+    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        self.inner
+            .exchange(data)?
+            .map(|transmit| self.transmit(transmit))
+            .transpose()
+    }
+    fn end(self) -> Result<Self::Response, Self::Error> {
+        self.inner.end()
+    }
+}
+
+pub struct DummyClient;
+#[async_trait(?Send)]
+impl HttpClient for DummyClient {
+    type Error = common::Error;
+    async fn request(&self, _url: &str, _req: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        unreachable!("Trezor does not need http client")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DisplayXpub, HWI};
+    use bhwi::bitcoin::{
+        bip32::{DerivationPath, Xpriv, Xpub},
+        secp256k1::Secp256k1,
+    };
+    use bhwi::trezor::proto::{self, Writer};
+    use std::str::FromStr;
+
+    /// Replies the scripted messages in order, keeps the messages received.
+    struct Script {
+        replies: Vec<Vec<u8>>,
+        received: Vec<Vec<u8>>,
+    }
+
+    #[async_trait(?Send)]
+    impl Transport for Script {
+        type Error = std::convert::Infallible;
+        async fn exchange(
+            &mut self,
+            command: &[u8],
+            _encrypted: bool,
+        ) -> Result<Vec<u8>, Self::Error> {
+            self.received.push(command.to_vec());
+            Ok(self.replies.remove(0))
+        }
+    }
+
+    fn msg_type(message: &[u8]) -> u16 {
+        proto::parse_message(message).unwrap().0
+    }
+
+    #[test]
+    fn test_trezor_prompts() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpub = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap());
+        let public_key = proto::message(
+            proto::PUBLIC_KEY,
+            &Writer::new().string(2, &xpub.to_string()).finish(),
+        );
+        let mut trezor = Trezor::new(Script {
+            replies: vec![
+                proto::message(proto::BUTTON_REQUEST, &Writer::new().uint(1, 8).finish()),
+                proto::message(proto::PASSPHRASE_REQUEST, &[]),
+                public_key,
+            ],
+            received: Vec::new(),
+        })
+        .with_network(Network::Testnet)
+        .with_passphrase("TREZOR".to_string());
+        let found = futures::executor::block_on(
+            trezor.display_xpub(path.clone(), common::DisplayMode::Xpub),
+        )
+        .unwrap();
+        assert_eq!(found, xpub);
+        let received = &trezor.transport.received;
+        assert_eq!(
+            received.iter().map(|m| msg_type(m)).collect::<Vec<_>>(),
+            [
+                proto::GET_PUBLIC_KEY,
+                proto::BUTTON_ACK,
+                proto::PASSPHRASE_ACK
+            ]
+        );
+        let passphrase = proto::parse_message(&received[2]).unwrap().1;
+        assert_eq!(
+            proto::Fields::parse(passphrase).unwrap().string(1),
+            Some("TREZOR")
+        );
+
+        // The pin is not entered by the host.
+        trezor.transport.replies = vec![proto::message(
+            proto::PIN_MATRIX_REQUEST,
+            &Writer::new().uint(1, 1).finish(),
+        )];
+        assert!(matches!(
+            futures::executor::block_on(trezor.get_extended_pubkey(path, false)),
+            Err(crate::Error::Interpreter(common::Error::DeviceLocked))
+        ));
+    }
+}
@@ -0,0 +1,99 @@
+use async_trait::async_trait;
+use bhwi::bitcoin::secp256k1::PublicKey;
+use bhwi_async::Transport;
+use tokio::net::TcpStream;
+
+use crate::{
+    decode_response,
+    noise::{Initiator, Keypair, Session},
+    read_message, write_message, BridgeError, BridgedDevice, Request,
+};
+
+/// Transport to a device of a bridge, usable by the devices of bhwi-async
+/// in place of their local transport.
+pub struct BridgeTransport {
+    stream: TcpStream,
+    session: Option<Session>,
+}
+
+impl BridgeTransport {
+    /// Connects to the bridge and authenticates with its token.
+    pub async fn connect(address: &str, token: &str) -> Result<Self, BridgeError> {
+        let mut transport = Self {
+            stream: TcpStream::connect(address).await?,
+            session: None,
+        };
+        transport.request(token.as_bytes()).await?;
+        Ok(transport)
+    }
+
+    /// Connects to the bridge with the pinned key over a channel encrypted
+    /// and authenticated with the key of the client, then authenticates with
+    /// the token of the bridge.
+    pub async fn connect_with_noise(
+        address: &str,
+        token: &str,
+        key: &Keypair,
+        bridge_key: &PublicKey,
+    ) -> Result<Self, BridgeError> {
+        let mut stream = TcpStream::connect(address).await?;
+        let (initiator, msg) = Initiator::new(key, bridge_key)?;
+        write_message(&mut stream, &msg).await?;
+        let session = initiator.finish(&read_message(&mut stream).await?)?;
+        let mut transport = Self {
+            stream,
+            session: Some(session),
+        };
+        transport.request(token.as_bytes()).await?;
+        Ok(transport)
+    }
+
+    async fn request(&mut self, msg: &[u8]) -> Result<Vec<u8>, BridgeError> {
+        match &mut self.session {
+            Some(session) => {
+                write_message(&mut self.stream, &session.encrypt(msg)?).await?;
+                let response = read_message(&mut self.stream).await?;
+                decode_response(&session.decrypt(&response)?)
+            }
+            None => {
+                write_message(&mut self.stream, msg).await?;
+                decode_response(&read_message(&mut self.stream).await?)
+            }
+        }
+    }
+
+    /// Lists the devices of the bridge.
+    pub async fn list(&mut self) -> Result<Vec<BridgedDevice>, BridgeError> {
+        let res = self.request(&Request::List.encode()).await?;
+        serde_json::from_slice(&res).map_err(|_| BridgeError::InvalidMessage)
+    }
+
+    /// Opens the device at the index of the last list, the exchanges are
+    /// relayed to it.
+    pub async fn open(&mut self, index: u32) -> Result<(), BridgeError> {
+        self.request(&Request::Open(index).encode()).await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for BridgeTransport {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let request = Request::Exchange {
+            encrypted,
+            data: command.to_vec(),
+        };
+        Ok(self.request(&request.encode()).await?)
+    }
+
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        let request = Request::Send {
+            encrypted,
+            data: command.to_vec(),
+        };
+        self.request(&request.encode()).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+//! Bridge exposing the devices plugged into a host over TCP, to the
+//! applications without access to them: the browser apps without WebHID
+//! permission, or the VMs reaching a device plugged into another machine.
+//!
+//! The messages are prefixed by their length on 4 bytes, or sent as binary
+//! messages once the connection is upgraded to a WebSocket. The first
+//! message of the client is the auth token of the bridge, then each request
+//! is answered by one response:
+//!
+//! - `0x01`: lists the devices, answered with their JSON descriptions.
+//! - `0x02 <index: u32>`: opens the device at the index of the list.
+//! - `0x03 <encrypted: u8> <data>`: exchanges the data with the open device,
+//!   answered with the data of the device, the APDU and its answer for the
+//!   Ledger devices.
+//! - `0x04 <encrypted: u8> <data>`: writes the data the device does not
+//!   reply to.
+//!
+//! The responses start with `0x00` followed by their data, or with `0x01`
+//! followed by the error message.
+//!
+//! A bridge with a Noise key expects its clients to start with the Noise_IK
+//! handshake, before the token: the messages that follow are encrypted, see
+//! [`noise`].
+
+pub mod client;
+pub mod noise;
+pub mod server;
+mod websocket;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub use client::BridgeTransport;
+pub use server::{DeviceProvider, Server};
+
+/// Longest message, far above the longest APDU.
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+const LIST: u8 = 0x01;
+const OPEN: u8 = 0x02;
+const EXCHANGE: u8 = 0x03;
+const SEND: u8 = 0x04;
+const OK: u8 = 0x00;
+const ERROR: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum BridgeError {
+    Io(std::io::Error),
+    InvalidMessage,
+    MessageTooLong(usize),
+    /// The token of the client is not the one of the bridge.
+    Unauthorized,
+    /// The error of the bridge or of its device.
+    Device(String),
+    WebSocket(&'static str),
+    Noise(noise::NoiseError),
+}
+
+impl From<std::io::Error> for BridgeError {
+    fn from(value: std::io::Error) -> Self {
+        BridgeError::Io(value)
+    }
+}
+
+impl From<noise::NoiseError> for BridgeError {
+    fn from(value: noise::NoiseError) -> Self {
+        BridgeError::Noise(value)
+    }
+}
+
+impl From<BridgeError> for std::io::Error {
+    fn from(value: BridgeError) -> Self {
+        match value {
+            BridgeError::Io(e) => e,
+            BridgeError::Unauthorized => {
+                std::io::Error::new(std::io::ErrorKind::PermissionDenied, "bridge token refused")
+            }
+            e => std::io::Error::other(format!("{:?}", e)),
+        }
+    }
+}
+
+/// Description of a device of the bridge.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgedDevice {
+    /// The type of the device, like `ledger` or `coldcard`.
+    pub device_type: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    List,
+    Open(u32),
+    Exchange { encrypted: bool, data: Vec<u8> },
+    Send { encrypted: bool, data: Vec<u8> },
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Request::List => vec![LIST],
+            Request::Open(index) => {
+                let mut msg = vec![OPEN];
+                msg.extend(index.to_be_bytes());
+                msg
+            }
+            Request::Exchange { encrypted, data } | Request::Send { encrypted, data } => {
+                let op = if matches!(self, Request::Exchange { .. }) {
+                    EXCHANGE
+                } else {
+                    SEND
+                };
+                let mut msg = vec![op, *encrypted as u8];
+                msg.extend_from_slice(data);
+                msg
+            }
+        }
+    }
+
+    pub fn decode(msg: &[u8]) -> Result<Self, BridgeError> {
+        match msg {
+            [LIST] => Ok(Request::List),
+            [OPEN, a, b, c, d] => Ok(Request::Open(u32::from_be_bytes([*a, *b, *c, *d]))),
+            [EXCHANGE, encrypted @ (0 | 1), data @ ..] => Ok(Request::Exchange {
+                encrypted: *encrypted == 1,
+                data: data.to_vec(),
+            }),
+            [SEND, encrypted @ (0 | 1), data @ ..] => Ok(Request::Send {
+                encrypted: *encrypted == 1,
+                data: data.to_vec(),
+            }),
+            _ => Err(BridgeError::InvalidMessage),
+        }
+    }
+}
+
+pub fn encode_response(response: Result<&[u8], &str>) -> Vec<u8> {
+    let (status, data) = match response {
+        Ok(data) => (OK, data),
+        Err(e) => (ERROR, e.as_bytes()),
+    };
+    let mut msg = Vec::with_capacity(data.len() + 1);
+    msg.push(status);
+    msg.extend_from_slice(data);
+    msg
+}
+
+/// Returns the data of the response, or the error of the bridge.
+pub fn decode_response(msg: &[u8]) -> Result<Vec<u8>, BridgeError> {
+    match msg {
+        [OK, data @ ..] => Ok(data.to_vec()),
+        [ERROR, message @ ..] => Err(BridgeError::Device(
+            String::from_utf8_lossy(message).into_owned(),
+        )),
+        _ => Err(BridgeError::InvalidMessage),
+    }
+}
+
+/// Reads the next message prefixed by its length.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, BridgeError> {
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(BridgeError::MessageTooLong(len));
+    }
+    let mut msg = vec![0u8; len];
+    reader.read_exact(&mut msg).await?;
+    Ok(msg)
+}
+
+/// Writes the message prefixed by its length.
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    msg: &[u8],
+) -> Result<(), BridgeError> {
+    let mut data = Vec::with_capacity(msg.len() + 4);
+    data.extend((msg.len() as u32).to_be_bytes());
+    data.extend_from_slice(msg);
+    writer.write_all(&data).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        for request in [
+            Request::List,
+            Request::Open(3),
+            Request::Exchange {
+                encrypted: false,
+                data: vec![0xe0, 0x01],
+            },
+            Request::Send {
+                encrypted: true,
+                data: vec![0x01],
+            },
+        ] {
+            assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+        }
+        assert!(Request::decode(&[0x02, 0x00]).is_err());
+        assert!(Request::decode(&[0x03, 0x02]).is_err());
+
+        assert_eq!(
+            decode_response(&encode_response(Ok(&[0x90, 0x00]))).unwrap(),
+            [0x90, 0x00]
+        );
+        assert!(matches!(
+            decode_response(&encode_response(Err("locked"))),
+            Err(BridgeError::Device(e)) if e == "locked"
+        ));
+    }
+}
@@ -0,0 +1,74 @@
+//! ChaCha20-Poly1305 AEAD of RFC 8439, the cipher of the Noise channel.
+//! The primitives are the ones of the `chacha20poly1305` crate, which
+//! erases the key of the cipher once dropped.
+
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+
+pub const TAG_SIZE: usize = 16;
+
+/// Returns the ciphertext followed by its tag.
+pub fn encrypt(key: &[u8; 32], nonce: &[u8; 12], ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad: ad,
+            },
+        )
+        .expect("The messages of the bridge fit in the cipher")
+}
+
+/// Returns the plaintext, or None if the tag does not authenticate the
+/// ciphertext.
+pub fn decrypt(key: &[u8; 32], nonce: &[u8; 12], ad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: ad,
+            },
+        )
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bhwi::bitcoin::hex::{DisplayHex, FromHex};
+
+    #[test]
+    fn test_rfc8439_vectors() {
+        // Section 2.8.2.
+        let key = <[u8; 32]>::from_hex(
+            "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+        )
+        .unwrap();
+        let nonce = <[u8; 12]>::from_hex("070000004041424344454647").unwrap();
+        let ad = Vec::from_hex("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+        let ciphertext = encrypt(&key, &nonce, &ad, plaintext);
+        assert_eq!(
+            ciphertext[..16].to_lower_hex_string(),
+            "d31a8d34648e60db7b86afbc53ef7ec2"
+        );
+        assert_eq!(
+            ciphertext[plaintext.len()..].to_lower_hex_string(),
+            "1ae10b594f09e26a7e902ecbd0600691"
+        );
+        assert_eq!(
+            decrypt(&key, &nonce, &ad, &ciphertext).as_deref(),
+            Some(&plaintext[..])
+        );
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        assert_eq!(decrypt(&key, &nonce, &ad, &tampered), None);
+        assert_eq!(decrypt(&key, &nonce, &[], &ciphertext), None);
+        assert_eq!(decrypt(&key, &nonce, &ad, &ciphertext[..8]), None);
+    }
+}
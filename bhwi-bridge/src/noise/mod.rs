@@ -0,0 +1,349 @@
+//! Noise_IK handshake authenticating the bridge and its clients by their
+//! static keys, then encrypting the messages of the connection.
+//!
+//! The functions follow the Lightning transport of BOLT 8: secp256k1 keys
+//! with the SHA256 of the compressed shared point as DH, ChaChaPoly and
+//! SHA256. The client knows the key of the bridge before connecting, the
+//! bridge accepts the clients with an authorized key. The messages of the
+//! connection are framed by the bridge, the 65535 bytes limit of Noise does
+//! not apply to them.
+//!
+//! The secret keys, the chaining key and the keys of the ciphers are erased
+//! from the memory once dropped.
+
+pub mod chachapoly;
+
+use bhwi::bitcoin::hashes::{
+    hmac::{Hmac, HmacEngine},
+    sha256, Hash, HashEngine,
+};
+use bhwi::bitcoin::secp256k1::{ecdh::SharedSecret, PublicKey, Secp256k1, SecretKey};
+use zeroize::Zeroizing;
+
+pub const PROTOCOL_NAME: &[u8] = b"Noise_IK_secp256k1_ChaChaPoly_SHA256";
+const PROLOGUE: &[u8] = b"bhwi-bridge";
+pub const PUBLIC_KEY_SIZE: usize = 33;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoiseError {
+    InvalidMessage,
+    InvalidKey,
+    /// The message is not authenticated by the key of the connection.
+    Decryption,
+    /// The static key of the client is not authorized by the bridge.
+    UnknownClient(PublicKey),
+    NonceExhausted,
+}
+
+/// Static or ephemeral key of a peer.
+#[derive(Clone)]
+pub struct Keypair {
+    secret: SecretKey,
+    public: PublicKey,
+}
+
+impl Keypair {
+    pub fn generate() -> Self {
+        loop {
+            if let Ok(secret) = SecretKey::from_slice(&rand::random::<[u8; 32]>()) {
+                return Self::from_secret_key(secret);
+            }
+        }
+    }
+
+    pub fn from_secret_key(secret: SecretKey) -> Self {
+        Self {
+            public: PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret),
+            secret,
+        }
+    }
+
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.secret
+    }
+
+    /// The key the peers pin.
+    pub fn public_key(&self) -> PublicKey {
+        self.public
+    }
+
+    fn dh(&self, remote: &PublicKey) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(SharedSecret::new(remote, &self.secret).secret_bytes())
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.secret.non_secure_erase();
+    }
+}
+
+struct CipherState {
+    k: Option<Zeroizing<[u8; 32]>>,
+    n: u64,
+}
+
+impl CipherState {
+    fn new(k: Option<Zeroizing<[u8; 32]>>) -> Self {
+        Self { k, n: 0 }
+    }
+
+    /// Returns the nonce of the next message, the counter is only moved
+    /// once the message is processed.
+    fn nonce(&self) -> Result<[u8; 12], NoiseError> {
+        if self.n == u64::MAX {
+            return Err(NoiseError::NonceExhausted);
+        }
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.n.to_le_bytes());
+        Ok(nonce)
+    }
+
+    fn encrypt_with_ad(&mut self, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        match &self.k {
+            Some(k) => {
+                let ciphertext = chachapoly::encrypt(k, &self.nonce()?, ad, plaintext);
+                self.n += 1;
+                Ok(ciphertext)
+            }
+            None => Ok(plaintext.to_vec()),
+        }
+    }
+
+    /// A message failing to authenticate leaves the state unchanged, the
+    /// next valid message of the peer is still accepted.
+    fn decrypt_with_ad(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        match &self.k {
+            Some(k) => {
+                let plaintext = chachapoly::decrypt(k, &self.nonce()?, ad, ciphertext)
+                    .ok_or(NoiseError::Decryption)?;
+                self.n += 1;
+                Ok(plaintext)
+            }
+            None => Ok(ciphertext.to_vec()),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> Zeroizing<[u8; 32]> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    for data in data {
+        engine.input(data);
+    }
+    Zeroizing::new(Hmac::from_engine(engine).to_byte_array())
+}
+
+fn hkdf(ck: &[u8; 32], ikm: &[u8]) -> (Zeroizing<[u8; 32]>, Zeroizing<[u8; 32]>) {
+    let temp = hmac(ck, &[ikm]);
+    let out1 = hmac(&*temp, &[&[0x01]]);
+    let out2 = hmac(&*temp, &[&*out1, &[0x02]]);
+    (out1, out2)
+}
+
+struct SymmetricState {
+    cipher: CipherState,
+    ck: Zeroizing<[u8; 32]>,
+    h: [u8; 32],
+}
+
+impl SymmetricState {
+    /// Initializes the state of the handshake, with the static key of the
+    /// bridge as pre-message.
+    fn new(bridge: &PublicKey) -> Self {
+        let h = sha256::Hash::hash(PROTOCOL_NAME).to_byte_array();
+        let mut state = Self {
+            cipher: CipherState::new(None),
+            ck: Zeroizing::new(h),
+            h,
+        };
+        state.mix_hash(PROLOGUE);
+        state.mix_hash(&bridge.serialize());
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&self.h);
+        engine.input(data);
+        self.h = sha256::Hash::from_engine(engine).to_byte_array();
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) {
+        let (ck, k) = hkdf(&self.ck, ikm);
+        self.ck = ck;
+        self.cipher = CipherState::new(Some(k));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let ciphertext = self.cipher.encrypt_with_ad(&self.h, plaintext)?;
+        self.mix_hash(&ciphertext);
+        Ok(ciphertext)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        let plaintext = self.cipher.decrypt_with_ad(&self.h, ciphertext)?;
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    /// Returns the cipher of the initiator and the one of the responder.
+    fn split(&self) -> (CipherState, CipherState) {
+        let (k1, k2) = hkdf(&self.ck, &[]);
+        (CipherState::new(Some(k1)), CipherState::new(Some(k2)))
+    }
+}
+
+fn read_key(msg: &[u8]) -> Result<(PublicKey, &[u8]), NoiseError> {
+    if msg.len() < PUBLIC_KEY_SIZE {
+        return Err(NoiseError::InvalidMessage);
+    }
+    let (key, rest) = msg.split_at(PUBLIC_KEY_SIZE);
+    Ok((
+        PublicKey::from_slice(key).map_err(|_| NoiseError::InvalidKey)?,
+        rest,
+    ))
+}
+
+/// Handshake of the client, waiting for the answer of the bridge.
+pub struct Initiator {
+    state: SymmetricState,
+    s: Keypair,
+    e: Keypair,
+}
+
+impl Initiator {
+    /// Starts the handshake with the bridge of the static key, returns the
+    /// first message to send: `-> e, es, s, ss`.
+    pub fn new(s: &Keypair, bridge: &PublicKey) -> Result<(Self, Vec<u8>), NoiseError> {
+        let mut state = SymmetricState::new(bridge);
+        let e = Keypair::generate();
+        let mut msg = e.public_key().serialize().to_vec();
+        state.mix_hash(&msg);
+        state.mix_key(&*e.dh(bridge));
+        msg.extend(state.encrypt_and_hash(&s.public_key().serialize())?);
+        state.mix_key(&*s.dh(bridge));
+        msg.extend(state.encrypt_and_hash(&[])?);
+        let initiator = Self {
+            state,
+            s: s.clone(),
+            e,
+        };
+        Ok((initiator, msg))
+    }
+
+    /// Reads the answer of the bridge: `<- e, ee, se`.
+    pub fn finish(mut self, msg: &[u8]) -> Result<Session, NoiseError> {
+        let (re, payload) = read_key(msg)?;
+        self.state.mix_hash(&re.serialize());
+        self.state.mix_key(&*self.e.dh(&re));
+        self.state.mix_key(&*self.s.dh(&re));
+        self.state.decrypt_and_hash(payload)?;
+        let (send, receive) = self.state.split();
+        Ok(Session {
+            send,
+            receive,
+            remote: None,
+        })
+    }
+}
+
+/// Reads the first message of the client with the static key of the bridge,
+/// returns the session and the answer to send if the client is authorized.
+pub fn respond(
+    s: &Keypair,
+    authorized_keys: &[PublicKey],
+    msg: &[u8],
+) -> Result<(Session, Vec<u8>), NoiseError> {
+    let mut state = SymmetricState::new(&s.public_key());
+    let (re, rest) = read_key(msg)?;
+    state.mix_hash(&re.serialize());
+    state.mix_key(&*s.dh(&re));
+    let key_size = PUBLIC_KEY_SIZE + chachapoly::TAG_SIZE;
+    if rest.len() < key_size {
+        return Err(NoiseError::InvalidMessage);
+    }
+    let rs = state.decrypt_and_hash(&rest[..key_size])?;
+    let rs = PublicKey::from_slice(&rs).map_err(|_| NoiseError::InvalidKey)?;
+    state.mix_key(&*s.dh(&rs));
+    state.decrypt_and_hash(&rest[key_size..])?;
+    if !authorized_keys.contains(&rs) {
+        return Err(NoiseError::UnknownClient(rs));
+    }
+
+    let e = Keypair::generate();
+    let mut answer = e.public_key().serialize().to_vec();
+    state.mix_hash(&answer);
+    state.mix_key(&*e.dh(&re));
+    state.mix_key(&*e.dh(&rs));
+    answer.extend(state.encrypt_and_hash(&[])?);
+    let (receive, send) = state.split();
+    let session = Session {
+        send,
+        receive,
+        remote: Some(rs),
+    };
+    Ok((session, answer))
+}
+
+/// Ciphers of a connection after the handshake.
+pub struct Session {
+    send: CipherState,
+    receive: CipherState,
+    remote: Option<PublicKey>,
+}
+
+impl Session {
+    pub fn encrypt(&mut self, msg: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        self.send.encrypt_with_ad(&[], msg)
+    }
+
+    pub fn decrypt(&mut self, msg: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        self.receive.decrypt_with_ad(&[], msg)
+    }
+
+    /// The static key of the client, for the session of the bridge.
+    pub fn remote_static_key(&self) -> Option<PublicKey> {
+        self.remote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake() {
+        let bridge = Keypair::generate();
+        let client = Keypair::generate();
+
+        let (initiator, msg) = Initiator::new(&client, &bridge.public_key()).unwrap();
+        assert_eq!(msg.len(), 33 + 33 + 16 + 16);
+        assert_eq!(
+            respond(&bridge, &[], &msg).err(),
+            Some(NoiseError::UnknownClient(client.public_key()))
+        );
+        let (mut server, answer) = respond(&bridge, &[client.public_key()], &msg).unwrap();
+        assert_eq!(server.remote_static_key(), Some(client.public_key()));
+        let mut client_session = initiator.finish(&answer).unwrap();
+
+        let ciphertext = client_session.encrypt(&[0xe0, 0x01]).unwrap();
+        assert_eq!(server.decrypt(&ciphertext).unwrap(), [0xe0, 0x01]);
+        // Every message has its own nonce, a replay does not authenticate.
+        assert_eq!(server.decrypt(&ciphertext), Err(NoiseError::Decryption));
+        // Nor does a corrupted message, which does not break the session.
+        let mut ciphertext = client_session.encrypt(&[0xe0, 0x02]).unwrap();
+        ciphertext[0] ^= 1;
+        assert_eq!(server.decrypt(&ciphertext), Err(NoiseError::Decryption));
+        ciphertext[0] ^= 1;
+        assert_eq!(server.decrypt(&ciphertext).unwrap(), [0xe0, 0x02]);
+        let ciphertext = server.encrypt(&[0x90, 0x00]).unwrap();
+        assert_eq!(client_session.decrypt(&ciphertext).unwrap(), [0x90, 0x00]);
+
+        // The client pinning another key fails against the bridge.
+        let (_, msg) = Initiator::new(&client, &Keypair::generate().public_key()).unwrap();
+        assert_eq!(
+            respond(&bridge, &[client.public_key()], &msg).err(),
+            Some(NoiseError::Decryption)
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bhwi::bitcoin::secp256k1::PublicKey;
+use bhwi_async::Transport;
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+
+use crate::{
+    encode_response,
+    noise::{self, Keypair, Session},
+    websocket, BridgeError, BridgedDevice, Request,
+};
+
+/// Devices exposed by the bridge.
+#[async_trait(?Send)]
+pub trait DeviceProvider {
+    type Transport: Transport;
+    async fn list(&mut self) -> Result<Vec<BridgedDevice>, String>;
+    /// Opens the device at the index of the last list.
+    async fn open(&mut self, index: usize) -> Result<Self::Transport, String>;
+}
+
+/// Time given to a client to complete the handshakes and send its token.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bridge serving the clients concurrently, the devices are held by a single
+/// authenticated client at a time.
+pub struct Server<P> {
+    /// Locked by the client using the devices.
+    provider: Mutex<P>,
+    token: String,
+    noise_key: Option<Keypair>,
+    authorized_keys: Vec<PublicKey>,
+    /// Origins of the web pages allowed to connect over WebSocket.
+    allowed_origins: Vec<String>,
+    handshake_timeout: Duration,
+}
+
+impl<P: DeviceProvider> Server<P> {
+    pub fn new(provider: P, token: impl Into<String>) -> Self {
+        Self {
+            provider: Mutex::new(provider),
+            token: token.into(),
+            noise_key: None,
+            authorized_keys: Vec::new(),
+            allowed_origins: Vec::new(),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+        }
+    }
+
+    /// Allows the web pages of the origin, e.g. `https://wallet.example`, to
+    /// connect over WebSocket. The pages of the other origins are refused,
+    /// the clients without origin are not browsers and are accepted.
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// Disconnects the clients not authenticated after the timeout, see
+    /// [`HANDSHAKE_TIMEOUT`].
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Encrypts the connections with the static key, only the clients with
+    /// an authorized key are served.
+    pub fn with_noise_key(mut self, key: Keypair) -> Self {
+        self.noise_key = Some(key);
+        self
+    }
+
+    /// The key the clients pin, if the connections are encrypted.
+    pub fn public_key(&self) -> Option<PublicKey> {
+        self.noise_key.as_ref().map(Keypair::public_key)
+    }
+
+    pub fn authorize_key(&mut self, key: PublicKey) {
+        if !self.authorized_keys.contains(&key) {
+            self.authorized_keys.push(key);
+        }
+    }
+
+    /// Removes the key from the authorized keys, returns false if it was not
+    /// authorized.
+    pub fn revoke_key(&mut self, key: &PublicKey) -> bool {
+        let len = self.authorized_keys.len();
+        self.authorized_keys.retain(|k| k != key);
+        self.authorized_keys.len() != len
+    }
+
+    pub fn authorized_keys(&self) -> &[PublicKey] {
+        &self.authorized_keys
+    }
+
+    /// Serves the clients of the listener until it fails, each connection
+    /// handled concurrently with the others.
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), BridgeError> {
+        let mut clients = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, address) = accepted?;
+                    log::info!("bridge client connected from {}", address);
+                    clients.push(async move {
+                        if let Err(e) = self.handle(stream).await {
+                            log::warn!("bridge client {} disconnected: {:?}", address, e);
+                        }
+                    });
+                }
+                Some(()) = clients.next(), if !clients.is_empty() => {}
+            }
+        }
+    }
+
+    /// Serves the client until it disconnects, the device is closed with it.
+    /// A client not authenticated before the handshake timeout is
+    /// disconnected, and the devices held by another client are refused.
+    pub async fn handle(&self, stream: TcpStream) -> Result<(), BridgeError> {
+        let mut connection =
+            tokio::time::timeout(self.handshake_timeout, self.authenticate(stream))
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        let Ok(mut provider) = self.provider.try_lock() else {
+            connection.write(&encode_response(Err("busy"))).await?;
+            return Err(BridgeError::Device("busy".to_string()));
+        };
+        connection.write(&encode_response(Ok(&[]))).await?;
+
+        let mut transport = None;
+        loop {
+            let msg = match connection.read().await {
+                Err(BridgeError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(())
+                }
+                res => res?,
+            };
+            let res = match Request::decode(&msg)? {
+                Request::List => provider
+                    .list()
+                    .await
+                    .and_then(|devices| serde_json::to_vec(&devices).map_err(|e| e.to_string())),
+                Request::Open(index) => {
+                    // The previous device is closed before opening the next one.
+                    transport = None;
+                    provider.open(index as usize).await.map(|t| {
+                        transport = Some(t);
+                        Vec::new()
+                    })
+                }
+                Request::Exchange { encrypted, data } => match transport.as_mut() {
+                    Some(t) => t
+                        .exchange(&data, encrypted)
+                        .await
+                        .map_err(|e| format!("{:?}", e)),
+                    None => Err("no device open".to_string()),
+                },
+                Request::Send { encrypted, data } => match transport.as_mut() {
+                    Some(t) => t
+                        .send(&data, encrypted)
+                        .await
+                        .map(|_| Vec::new())
+                        .map_err(|e| format!("{:?}", e)),
+                    None => Err("no device open".to_string()),
+                },
+            };
+            connection
+                .write(&encode_response(res.as_deref().map_err(|e| e.as_str())))
+                .await?;
+        }
+    }
+
+    /// Runs the handshakes of the client and checks its token.
+    async fn authenticate(&self, stream: TcpStream) -> Result<Connection, BridgeError> {
+        let mut method = [0u8; 4];
+        let websocket = stream.peek(&mut method).await? == 4 && &method == b"GET ";
+        let mut connection = Connection {
+            stream,
+            websocket,
+            session: None,
+        };
+        if websocket {
+            websocket::handshake(&mut connection.stream, &self.allowed_origins).await?;
+        }
+        if let Some(key) = &self.noise_key {
+            let msg = connection.read().await?;
+            let (session, answer) = noise::respond(key, &self.authorized_keys, &msg)?;
+            connection.write(&answer).await?;
+            connection.session = Some(session);
+        }
+
+        let token = connection.read().await?;
+        if !constant_time_eq(&token, self.token.as_bytes()) {
+            connection
+                .write(&encode_response(Err("unauthorized")))
+                .await?;
+            return Err(BridgeError::Unauthorized);
+        }
+        Ok(connection)
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    websocket: bool,
+    /// Ciphers of the connection once the Noise handshake is done.
+    session: Option<Session>,
+}
+
+impl Connection {
+    async fn read(&mut self) -> Result<Vec<u8>, BridgeError> {
+        let msg = if self.websocket {
+            websocket::read_message(&mut self.stream).await?
+        } else {
+            crate::read_message(&mut self.stream).await?
+        };
+        match &mut self.session {
+            Some(session) => Ok(session.decrypt(&msg)?),
+            None => Ok(msg),
+        }
+    }
+
+    async fn write(&mut self, msg: &[u8]) -> Result<(), BridgeError> {
+        let encrypted;
+        let msg = match &mut self.session {
+            Some(session) => {
+                encrypted = session.encrypt(msg)?;
+                &encrypted
+            }
+            None => msg,
+        };
+        if self.websocket {
+            websocket::write_message(&mut self.stream, msg).await
+        } else {
+            crate::write_message(&mut self.stream, msg).await
+        }
+    }
+}
+
+/// Compares the tokens without leaking the length of their common prefix.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BridgeTransport;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    struct MockProvider;
+
+    struct EchoTransport;
+
+    #[async_trait(?Send)]
+    impl Transport for EchoTransport {
+        type Error = String;
+        async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, String> {
+            let mut response = command.to_vec();
+            response.extend([0x90, 0x00]);
+            Ok(response)
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl DeviceProvider for MockProvider {
+        type Transport = EchoTransport;
+        async fn list(&mut self) -> Result<Vec<BridgedDevice>, String> {
+            Ok(vec![BridgedDevice {
+                device_type: "ledger".to_string(),
+                path: "mock".to_string(),
+            }])
+        }
+        async fn open(&mut self, index: usize) -> Result<EchoTransport, String> {
+            if index == 0 {
+                Ok(EchoTransport)
+            } else {
+                Err("no such device".to_string())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = Server::new(MockProvider, "secret");
+
+        let client = async {
+            let mut transport = BridgeTransport::connect(&address, "secret").await.unwrap();
+            assert_eq!(transport.list().await.unwrap()[0].path, "mock");
+            assert!(transport.open(1).await.is_err());
+            transport.open(0).await.unwrap();
+            let response = transport.exchange(&[0xe0, 0x01], false).await.unwrap();
+            assert_eq!(response, [0xe0, 0x01, 0x90, 0x00]);
+            drop(transport);
+
+            let res = BridgeTransport::connect(&address, "wrong").await;
+            assert!(matches!(res, Err(BridgeError::Device(e)) if e == "unauthorized"));
+
+            // Client of a browser, a masked binary message of the token.
+            let mut stream = TcpStream::connect(&address).await.unwrap();
+            stream
+                .write_all(
+                    b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n\
+                      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut frame = vec![0x82, 0x80 | 6, 0x01, 0x02, 0x03, 0x04];
+            frame.extend(
+                b"secret"
+                    .iter()
+                    .zip([1, 2, 3, 4].iter().cycle())
+                    .map(|(b, m)| b ^ m),
+            );
+            stream.write_all(&frame).await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await.unwrap();
+            assert!(response.starts_with(b"HTTP/1.1 101"));
+            assert!(response.ends_with(&[0x82, 0x01, 0x00]));
+        };
+        let server = async {
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let _ = server.handle(stream).await;
+            }
+        };
+        tokio::join!(client, server);
+    }
+
+    /// Sends the upgrade request of a web page of the origin, returns the
+    /// status line of the answer.
+    async fn upgrade(address: &str, origin: &str) -> [u8; 12] {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "GET / HTTP/1.1\r\nUpgrade: websocket\r\nOrigin: {}\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            origin
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = [0u8; 12];
+        stream.read_exact(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_bridge_concurrent_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = Server::new(MockProvider, "secret")
+            .allow_origin("https://wallet.example")
+            .with_handshake_timeout(Duration::from_millis(200));
+
+        let client = async {
+            // A client never sending its token does not hold the others.
+            let mut silent = TcpStream::connect(&address).await.unwrap();
+            let mut transport = BridgeTransport::connect(&address, "secret").await.unwrap();
+            assert_eq!(transport.list().await.unwrap()[0].path, "mock");
+
+            // The devices are held by the first authenticated client.
+            let res = BridgeTransport::connect(&address, "secret").await;
+            assert!(matches!(res, Err(BridgeError::Device(e)) if e == "busy"));
+            drop(transport);
+
+            assert_eq!(
+                &upgrade(&address, "https://wallet.example").await,
+                b"HTTP/1.1 101"
+            );
+            assert_eq!(
+                &upgrade(&address, "https://evil.example").await,
+                b"HTTP/1.1 403"
+            );
+
+            // Disconnected once the handshake timed out.
+            let mut buffer = Vec::new();
+            silent.read_to_end(&mut buffer).await.unwrap();
+            assert!(buffer.is_empty());
+        };
+        tokio::select! {
+            res = server.serve(listener) => panic!("bridge stopped: {:?}", res),
+            _ = client => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge_noise() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let bridge_key = Keypair::generate();
+        let client_key = Keypair::generate();
+        let mut server = Server::new(MockProvider, "secret").with_noise_key(bridge_key.clone());
+        server.authorize_key(client_key.public_key());
+        assert_eq!(server.public_key(), Some(bridge_key.public_key()));
+
+        let client = async {
+            let mut transport = BridgeTransport::connect_with_noise(
+                &address,
+                "secret",
+                &client_key,
+                &bridge_key.public_key(),
+            )
+            .await
+            .unwrap();
+            transport.open(0).await.unwrap();
+            let response = transport.exchange(&[0xe0, 0x01], false).await.unwrap();
+            assert_eq!(response, [0xe0, 0x01, 0x90, 0x00]);
+            drop(transport);
+
+            // Unknown client, the bridge closes the connection.
+            let res = BridgeTransport::connect_with_noise(
+                &address,
+                "secret",
+                &Keypair::generate(),
+                &bridge_key.public_key(),
+            )
+            .await;
+            assert!(matches!(res, Err(BridgeError::Io(_))));
+        };
+        let serve = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            server.handle(stream).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            assert!(matches!(
+                server.handle(stream).await,
+                Err(BridgeError::Noise(noise::NoiseError::UnknownClient(_)))
+            ));
+        };
+        tokio::join!(client, serve);
+        assert!(server.revoke_key(&client_key.public_key()));
+        assert!(server.authorized_keys().is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! Server side of the WebSocket protocol of RFC 6455, enough for the
+//! browsers to exchange the messages of the bridge: the binary messages are
+//! not fragmented and the pings are answered.
+
+use base64ct::{Base64, Encoding};
+use bhwi::bitcoin::hashes::{sha1, Hash};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{BridgeError, MAX_MESSAGE_SIZE};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Longest header of the upgrade request.
+const MAX_HEADER_SIZE: usize = 8192;
+
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Returns the accept key answering the key of the client.
+pub fn accept_key(key: &str) -> String {
+    let hash = sha1::Hash::hash(format!("{}{}", key.trim(), GUID).as_bytes());
+    Base64::encode_string(hash.as_byte_array())
+}
+
+/// Reads the upgrade request of the client and accepts it. The requests of
+/// the web pages, with an `Origin` header, are refused unless the origin is
+/// allowed.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    allowed_origins: &[String],
+) -> Result<(), BridgeError> {
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_HEADER_SIZE {
+            return Err(BridgeError::WebSocket("upgrade request too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+    let header = String::from_utf8_lossy(&header);
+    let field = |field: &str| {
+        header
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case(field))
+            .map(|(_, value)| value.trim())
+    };
+    if let Some(origin) = field("origin") {
+        if !allowed_origins.iter().any(|allowed| allowed == origin) {
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            return Err(BridgeError::WebSocket("origin not allowed"));
+        }
+    }
+    let key = field("sec-websocket-key").ok_or(BridgeError::WebSocket("missing key"))?;
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Returns the frame of the server, not masked.
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads the next message of the client, the pings are answered on the way.
+pub async fn read_message<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<Vec<u8>, BridgeError> {
+    loop {
+        let [first, second] = [stream.read_u8().await?, stream.read_u8().await?];
+        let (fin, opcode) = (first & 0x80 != 0, first & 0x0f);
+        if second & 0x80 == 0 {
+            return Err(BridgeError::WebSocket("frame of the client not masked"));
+        }
+        let len = match second & 0x7f {
+            126 => stream.read_u16().await? as usize,
+            127 => stream.read_u64().await? as usize,
+            len => len as usize,
+        };
+        if len > MAX_MESSAGE_SIZE {
+            return Err(BridgeError::MessageTooLong(len));
+        }
+        let mut mask = [0u8; 4];
+        stream.read_exact(&mut mask).await?;
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        match opcode {
+            TEXT | BINARY if fin => return Ok(payload),
+            TEXT | BINARY => return Err(BridgeError::WebSocket("fragmented message")),
+            PING => stream.write_all(&encode_frame(PONG, &payload)).await?,
+            PONG => {}
+            CLOSE => {
+                let _ = stream.write_all(&encode_frame(CLOSE, &[])).await;
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            _ => return Err(BridgeError::WebSocket("unsupported frame")),
+        }
+    }
+}
+
+/// Writes the message as a binary frame.
+pub async fn write_message<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    msg: &[u8],
+) -> Result<(), BridgeError> {
+    stream.write_all(&encode_frame(BINARY, msg)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // Example of RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(
+            encode_frame(BINARY, &[0x01; 200])[..4],
+            [0x82, 126, 0x00, 200]
+        );
+    }
+}
@@ -1,81 +1,810 @@
-use bhwi_cli::{get_device_with_fingerprint, list, Error};
+use std::ffi::OsString;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
 
+use bhwi::ledger::WalletPolicy;
+use bhwi_bridge::Server;
+use bhwi_cli::{
+    account_path,
+    bridge::{load_or_create_key, LocalDevices},
+    completion::{self, Shell},
+    config::Config,
+    daemon,
+    descriptor::{account_descriptors, key_origin},
+    get_device_with_fingerprint, list_devices,
+    message::{sign_message, MessageFormat},
+    open,
+    output::{
+        code, AddressResult, DescriptorsResult, EnumerateEntry, ErrorResult, RegisterWalletResult,
+        SignMessageResult, SignTxResult, XpubResult,
+    },
+    psbt::{read_psbt, sign_psbt, write_psbt, PsbtFormat, PsbtSource},
+    watch::{diff_devices, EventKind},
+    AddressType, CliDevice, DeviceInfo, Emulator,
+};
 use bitcoin::{
-    bip32::{DerivationPath, Fingerprint},
-    Network,
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    hex::FromHex,
+    Address, Network,
 };
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(name = "bhwi", author, version, about, long_about = None)]
 struct Args {
     #[command(subcommand)]
     command: Commands,
-    #[arg(long)]
-    /// default will be the first connected device with the master fingerprint matching.
+    /// Select the connected device with the master fingerprint matching.
     #[arg(long, alias = "fg", value_parser = clap::value_parser!(bitcoin::bip32::Fingerprint))]
     fingerprint: Option<Fingerprint>,
-    /// default will be the Bitcoin mainnet network.
-    #[arg(long, value_parser = clap::value_parser!(bitcoin::Network), default_value_t = bitcoin::Network::Bitcoin)]
-    network: Network,
+    /// default will be the network of the config file, or the Bitcoin mainnet network.
+    #[arg(long, value_parser = clap::value_parser!(bitcoin::Network))]
+    network: Option<Network>,
+    /// Network as named by Bitcoin Core: main, test, testnet4, signet or
+    /// regtest. The results are printed as json, as Bitcoin Core expects
+    /// from the command given to -signer, e.g. -signer="bhwi --json".
+    #[arg(long, value_parser = Network::from_core_arg, conflicts_with = "network")]
+    chain: Option<Network>,
+    /// Read the subcommand and its arguments from stdin, as Bitcoin Core
+    /// passes signtx <psbt>. The results are printed as json.
+    #[arg(long)]
+    stdin: bool,
+    /// Select the connected device at the given path, as listed by enumerate.
+    #[arg(long)]
+    device_path: Option<String>,
+    /// Use an emulator instead of the connected devices, e.g.
+    /// speculos:127.0.0.1:9999 or trezor:127.0.0.1:21324.
+    #[arg(long)]
+    emulator: Option<Emulator>,
+    /// Print the frames exchanged with the device on stderr, encrypted frames are redacted.
+    #[arg(long)]
+    trace_apdu: bool,
+    /// Print the results as json, following the HWI output format.
+    #[arg(long)]
+    json: bool,
+    /// Path of the config file, default is $XDG_CONFIG_HOME/bhwi/config.json.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Refuse to sign the psbts above this fee rate, in sat/vB. default will
+    /// be the maximum of the config file, or none.
+    #[arg(long)]
+    max_fee_rate: Option<u64>,
+    #[arg(skip)]
+    settings: Config,
 }
 
-#[derive(Debug, Subcommand)]
-enum Commands {
-    #[command(subcommand)]
-    Device(DeviceCommands),
-    #[command(subcommand)]
-    Xpub(XpubCommands),
-}
+impl Args {
+    /// Loads the config file, the flags given override its values.
+    fn with_config(mut self) -> Result<Self, ErrorResult> {
+        let settings = match &self.config {
+            Some(path) => Config::load(path),
+            None => Config::load_default(),
+        }
+        .map_err(|e| ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))?;
+        self.fingerprint = self.fingerprint.or(settings.fingerprint);
+        self.device_path = self.device_path.take().or(settings.device_path.clone());
+        self.network = self.network.or(self.chain).or(settings.network);
+        self.max_fee_rate = self.max_fee_rate.or(settings.max_fee_rate);
+        self.settings = settings;
+        Ok(self)
+    }
 
-#[derive(Debug, Subcommand)]
-enum DeviceCommands {
-    List,
+    fn network(&self) -> Network {
+        self.network.unwrap_or(Network::Bitcoin)
+    }
+
+    fn account(&self, account: Option<u32>) -> u32 {
+        account.or(self.settings.account).unwrap_or(0)
+    }
+
+    fn account_path(
+        &self,
+        address_type: AddressType,
+        account: Option<u32>,
+    ) -> Result<DerivationPath, ErrorResult> {
+        account_path(address_type, self.network(), self.account(account))
+            .map_err(|e| ErrorResult::new(format!("Invalid account: {}", e), code::BAD_ARGUMENT))
+    }
 }
 
 #[derive(Debug, Subcommand)]
-enum XpubCommands {
-    Get {
-        #[arg(long, value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
+enum Commands {
+    /// List the connected devices with their master fingerprint.
+    Enumerate,
+    /// Print the completion script of the shell.
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+    /// Read json requests from stdin, one per line, and reply on stdout,
+    /// keeping the device open between requests.
+    Daemon,
+    /// Expose the connected devices to the clients of a tcp or websocket
+    /// connection, like the browser apps without WebHID.
+    Bridge {
+        #[arg(long, default_value = "127.0.0.1:9700")]
+        listen: String,
+        /// Token the clients authenticate with, a random one is printed on
+        /// stderr by default.
+        #[arg(long)]
+        token: Option<String>,
+        /// File of the Noise key of the bridge, created if missing. The
+        /// connections are then encrypted and restricted to the client keys.
+        #[arg(long)]
+        noise_key: Option<PathBuf>,
+        /// Public key of a client allowed to connect over Noise, in hex.
+        #[arg(long, requires = "noise_key")]
+        client_key: Vec<bitcoin::secp256k1::PublicKey>,
+        /// Origin of a web page allowed to connect over WebSocket, e.g.
+        /// https://wallet.example. The other pages are refused.
+        #[arg(long)]
+        allow_origin: Vec<String>,
+    },
+    /// Get the xpub of the standard account for the address type.
+    #[command(name = "getmasterxpub")]
+    GetMasterXpub {
+        /// default will be the address type of the config file, or wit.
+        #[arg(long, value_enum)]
+        addr_type: Option<AddrType>,
+        #[arg(long)]
+        account: Option<u32>,
+    },
+    /// Get the receive and change descriptors of the account for every address type.
+    #[command(name = "getdescriptors")]
+    GetDescriptors {
+        #[arg(long)]
+        account: Option<u32>,
+    },
+    /// Print the supported devices as they are connected and disconnected.
+    Watch {
+        /// Interval between two enumerations, in milliseconds.
+        #[arg(long, default_value_t = 500)]
+        interval: u64,
+    },
+    /// Get the xpub at the given derivation path.
+    #[command(name = "getxpub")]
+    GetXpub {
+        #[arg(value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
+        path: DerivationPath,
+        /// Display the xpub on the device.
+        #[arg(long)]
+        display: bool,
+    },
+    /// Register the policy of the descriptor on the device, and get the
+    /// proof of its registration.
+    #[command(name = "registerwallet")]
+    RegisterWallet {
+        /// Descriptor of the wallet, its keys derived with /<0;1>/*.
+        #[arg(long)]
+        descriptor: String,
+        /// Name of the wallet displayed by the device.
+        #[arg(long)]
+        name: String,
+    },
+    /// Display the address on the device, the one at the path for the single
+    /// signature wallets or at the index of the descriptor of a registered
+    /// wallet.
+    #[command(name = "displayaddress")]
+    DisplayAddress {
+        /// Path of the address, ending with the change and the index.
+        #[arg(long, value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath), required_unless_present_any = ["descriptor", "desc"], conflicts_with_all = ["descriptor", "desc"])]
+        path: Option<DerivationPath>,
+        /// Descriptor of the single key of the address, as given by Bitcoin
+        /// Core, e.g. wpkh([d34db33f/84h/1h/0h/0/0]03...).
+        #[arg(long, conflicts_with = "descriptor")]
+        desc: Option<String>,
+        /// Descriptor of the wallet, its keys derived with /<0;1>/*.
+        #[arg(long)]
+        descriptor: Option<String>,
+        /// Name of the registered wallet.
+        #[arg(long, requires = "descriptor", conflicts_with = "path")]
+        name: Option<String>,
+        /// Proof of registration of the wallet, as printed by registerwallet.
+        #[arg(long, value_parser = parse_hmac, requires = "descriptor", conflicts_with = "path")]
+        hmac: Option<[u8; 32]>,
+        /// Display the change address of the descriptor.
+        #[arg(long, requires = "descriptor", conflicts_with = "path")]
+        change: bool,
+        /// Index of the address in the descriptor, 0 by default.
+        #[arg(long, requires = "descriptor", conflicts_with = "path")]
+        index: Option<u32>,
+        /// Find the address in the wallet of the descriptor instead, the
+        /// device displaying it to be checked.
+        #[arg(long, requires = "descriptor", conflicts_with = "path")]
+        verify: Option<Address<NetworkUnchecked>>,
+        /// Number of addresses searched for the address to verify.
+        #[arg(long, default_value_t = 1000)]
+        gap_limit: u32,
+    },
+    /// Sign the message with the key at the path, the base64 signature is
+    /// printed.
+    #[command(name = "signmessage")]
+    SignMessage {
+        message: String,
+        #[arg(value_parser = clap::value_parser!(bitcoin::bip32::DerivationPath))]
         path: DerivationPath,
+        /// default will be the address type of the purpose of the path, or
+        /// the one of the config file.
+        #[arg(long, value_enum)]
+        addr_type: Option<AddrType>,
+        #[arg(long, value_enum, default_value_t = SignatureFormat::Bip137)]
+        format: SignatureFormat,
+    },
+    /// Sign the psbt, the signed psbt is printed or written to the output file.
+    #[command(name = "signpsbt", alias = "signtx")]
+    SignPsbt {
+        /// Base64 psbt, path to a binary or base64 psbt file, or - for stdin.
+        #[arg(long, required_unless_present = "source")]
+        psbt: Option<PsbtSource>,
+        /// Same as --psbt, as given by Bitcoin Core to signtx.
+        #[arg(value_name = "PSBT", conflicts_with = "psbt")]
+        source: Option<PsbtSource>,
+        /// Descriptor of the registered wallet, the default single signature
+        /// wallets need none.
+        #[arg(long, requires_all = ["name", "hmac"])]
+        descriptor: Option<String>,
+        /// Name of the registered wallet.
+        #[arg(long)]
+        name: Option<String>,
+        /// Proof of registration of the wallet, as printed by registerwallet.
+        #[arg(long, value_parser = parse_hmac)]
+        hmac: Option<[u8; 32]>,
+        /// Write the signed psbt to the file instead of stdout.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Write the psbt in binary instead of base64.
+        #[arg(long)]
+        binary: bool,
     },
 }
 
+fn parse_hmac(s: &str) -> Result<[u8; 32], String> {
+    <[u8; 32]>::from_hex(s).map_err(|e| e.to_string())
+}
+
+/// Returns the policy of the descriptor, with the name it is registered with.
+fn wallet_policy(descriptor: &str, name: &str) -> Result<WalletPolicy, ErrorResult> {
+    let mut policy = WalletPolicy::from_descriptor(descriptor).map_err(|e| {
+        ErrorResult::new(format!("Invalid descriptor: {:?}", e), code::BAD_ARGUMENT)
+    })?;
+    policy.name = name.to_string();
+    Ok(policy)
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CompletionShell {
+    Bash,
+    Fish,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum AddrType {
+    Legacy,
+    ShWit,
+    Wit,
+    Tap,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SignatureFormat {
+    /// Legacy signature with the header of the address type.
+    Bip137,
+    /// Proof of the address, in the simple format for the segwit addresses.
+    Bip322,
+}
+
+impl From<SignatureFormat> for MessageFormat {
+    fn from(format: SignatureFormat) -> MessageFormat {
+        match format {
+            SignatureFormat::Bip137 => MessageFormat::Bip137,
+            SignatureFormat::Bip322 => MessageFormat::Bip322,
+        }
+    }
+}
+
+impl From<AddrType> for AddressType {
+    fn from(addr_type: AddrType) -> AddressType {
+        match addr_type {
+            AddrType::Legacy => AddressType::Legacy,
+            AddrType::ShWit => AddressType::ShWit,
+            AddrType::Wit => AddressType::Wit,
+            AddrType::Tap => AddressType::Tap,
+        }
+    }
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Error> {
-    let args = Args::parse();
-    match args.command {
-        Commands::Device(DeviceCommands::List) => {
-            let devices = list(args.network).await?;
-            if devices.is_empty() {
-                eprintln!("No devices found");
+async fn main() {
+    let mut args = match stdin_args(std::env::args_os().collect()) {
+        Ok(argv) => Args::parse_from(argv),
+        Err(e) => exit_with_error(true, ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR)),
+    };
+    args.json |= args.chain.is_some() || args.stdin;
+    let json = args.json;
+    if let Err(e) = run(args).await {
+        exit_with_error(json, e);
+    }
+}
+
+fn exit_with_error(json: bool, e: ErrorResult) -> ! {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&e).expect("error is serializable")
+        );
+    } else {
+        eprintln!("{}", e.error);
+    }
+    std::process::exit(e.exit_code());
+}
+
+/// Returns the arguments with the ones read from stdin appended if --stdin
+/// is given, as Bitcoin Core writes signtx <psbt> to the stdin of its signer.
+fn stdin_args(mut argv: Vec<OsString>) -> std::io::Result<Vec<OsString>> {
+    if argv.iter().any(|arg| arg == "--stdin") {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        argv.extend(input.split_whitespace().map(OsString::from));
+    }
+    Ok(argv)
+}
+
+async fn run(args: Args) -> Result<(), ErrorResult> {
+    let args = args.with_config()?;
+    match &args.command {
+        Commands::Enumerate => {
+            let entries = enumerate_entries(&args).await?;
+            if args.json {
+                print_json(&entries);
             } else {
-                for mut device in devices {
-                    match device.get_master_fingerprint().await {
-                        Ok(fingerprint) => {
-                            println!("{}", fingerprint);
+                if entries.is_empty() {
+                    eprintln!("No devices found");
+                }
+                for entry in entries {
+                    match (entry.fingerprint, entry.error) {
+                        (Some(fingerprint), _) => {
+                            println!("{} {} {}", entry.device_type, entry.path, fingerprint)
                         }
-                        Err(e) => {
-                            eprintln!("Error accessing device: {:?}", e);
+                        (_, Some(e)) => {
+                            eprintln!("Error accessing device {}: {}", entry.path, e.error)
                         }
+                        _ => {}
                     }
                 }
             }
         }
-        Commands::Xpub(XpubCommands::Get { path }) => {
-            if let Some(mut device) = get_device_with_fingerprint(args.network, args.fingerprint).await? {
-                match device.get_extended_pubkey(path, false).await {
-                    Ok(xpub) => {
-                        println!("{}", xpub);
+        Commands::Completions { shell } => {
+            let shell = match shell {
+                CompletionShell::Bash => Shell::Bash,
+                CompletionShell::Fish => Shell::Fish,
+            };
+            print!("{}", completion::generate(shell, &Args::command()));
+        }
+        Commands::Daemon => run_daemon(&args).await,
+        Commands::Bridge {
+            listen,
+            token,
+            noise_key,
+            client_key,
+            allow_origin,
+        } => {
+            let token = token.clone().unwrap_or_else(|| {
+                let token = hex::encode(rand::random::<[u8; 16]>());
+                eprintln!("bridge token: {}", token);
+                token
+            });
+            let listener = tokio::net::TcpListener::bind(listen)
+                .await
+                .map_err(|e| ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))?;
+            let mut server = Server::new(LocalDevices::new(args.emulator.clone()), token);
+            if let Some(path) = noise_key {
+                let key = load_or_create_key(path)
+                    .map_err(|e| ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))?;
+                eprintln!("bridge noise key: {}", key.public_key());
+                server = server.with_noise_key(key);
+                for key in client_key {
+                    server.authorize_key(*key);
+                }
+            }
+            for origin in allow_origin {
+                server = server.allow_origin(origin);
+            }
+            eprintln!("bridge listening on {}", listen);
+            server
+                .serve(listener)
+                .await
+                .map_err(|e| ErrorResult::new(format!("{:?}", e), code::UNKNOWN_ERROR))?;
+        }
+        Commands::GetMasterXpub { addr_type, account } => {
+            let address_type = addr_type
+                .map(AddressType::from)
+                .or(args.settings.address_type)
+                .unwrap_or(AddressType::Wit);
+            let path = args.account_path(address_type, *account)?;
+            let xpub = get_xpub(&args, path, false).await?;
+            print_xpub(args.json, xpub);
+        }
+        Commands::GetDescriptors { account } => {
+            let mut device = select_device(&args).await?;
+            let fingerprint = device
+                .get_master_fingerprint()
+                .await
+                .map_err(|e| ErrorResult::from(&e))?;
+            let mut result = DescriptorsResult::default();
+            for address_type in [
+                AddressType::Legacy,
+                AddressType::ShWit,
+                AddressType::Wit,
+                AddressType::Tap,
+            ] {
+                let path = args.account_path(address_type, *account)?;
+                let xpub = device
+                    .get_extended_pubkey(path.clone(), false)
+                    .await
+                    .map_err(|e| ErrorResult::from(&e))?;
+                let (receive, internal) =
+                    account_descriptors(address_type, fingerprint, &path, &xpub);
+                result.receive.push(receive);
+                result.internal.push(internal);
+            }
+            if args.json {
+                print_json(&result);
+            } else {
+                for desc in result.receive.iter().chain(result.internal.iter()) {
+                    println!("{}", desc);
+                }
+            }
+        }
+        Commands::Watch { interval } => {
+            let mut devices = Vec::new();
+            loop {
+                let next =
+                    list_devices(args.emulator.as_ref()).map_err(|e| ErrorResult::from(&e))?;
+                for event in diff_devices(&devices, &next) {
+                    if args.json {
+                        print_json(&event);
+                    } else {
+                        let kind = match event.event {
+                            EventKind::Connected => "connected",
+                            EventKind::Disconnected => "disconnected",
+                        };
+                        println!("{} {} {}", kind, event.device_type, event.path);
+                    }
+                }
+                devices = next;
+                tokio::time::sleep(std::time::Duration::from_millis(*interval)).await;
+            }
+        }
+        Commands::GetXpub { path, display } => {
+            let xpub = get_xpub(&args, path.clone(), *display).await?;
+            print_xpub(args.json, xpub);
+        }
+        Commands::RegisterWallet { descriptor, name } => {
+            let policy = wallet_policy(descriptor, name)?;
+            let mut device = select_device(&args).await?;
+            let (id, hmac) = device
+                .register_wallet(policy)
+                .await
+                .map_err(|e| ErrorResult::from(&e))?;
+            let result = RegisterWalletResult::new(id, hmac);
+            if args.json {
+                print_json(&result);
+            } else {
+                println!("{}", result.hmac);
+            }
+        }
+        Commands::DisplayAddress {
+            path,
+            desc,
+            descriptor,
+            name,
+            hmac,
+            change,
+            index,
+            verify,
+            gap_limit,
+        } => {
+            let policy = descriptor
+                .as_deref()
+                .map(|d| wallet_policy(d, name.as_deref().unwrap_or_default()))
+                .transpose()?;
+            let origin = desc
+                .as_deref()
+                .map(|desc| {
+                    key_origin(desc)
+                        .filter(|(address_type, _, path)| {
+                            AddressType::from_path(path) == Some(*address_type)
+                        })
+                        .ok_or_else(|| ErrorResult::new("Invalid descriptor", code::BAD_ARGUMENT))
+                })
+                .transpose()?;
+            let mut device = select_device(&args).await?;
+            if let Some((_, fingerprint, _)) = &origin {
+                if device
+                    .get_master_fingerprint()
+                    .await
+                    .map_err(|e| ErrorResult::from(&e))?
+                    != *fingerprint
+                {
+                    return Err(ErrorResult::new(
+                        "Descriptor of another device",
+                        code::BAD_ARGUMENT,
+                    ));
+                }
+            }
+            let path = path.clone().or(origin.map(|(_, _, path)| path));
+            let result = match (policy, verify) {
+                (Some(policy), Some(address)) => {
+                    if !address.is_valid_for_network(args.network()) {
+                        return Err(ErrorResult::new(
+                            "Address of another network",
+                            code::BAD_ARGUMENT,
+                        ));
                     }
-                    Err(e) => {
-                        eprintln!("Error getting xpub: {:?}", e);
+                    let (change, index) = device
+                        .verify_owned_address(address.clone(), policy, *hmac, *gap_limit)
+                        .await
+                        .map_err(|e| ErrorResult::from(&e))?;
+                    AddressResult {
+                        address: address.assume_checked_ref().to_string(),
+                        change: Some(change),
+                        index: Some(index),
                     }
                 }
+                (policy, _) => {
+                    let path = match path {
+                        Some(path) => path,
+                        None => DerivationPath::from(vec![
+                            ChildNumber::Normal {
+                                index: u32::from(*change),
+                            },
+                            ChildNumber::from_normal_idx(index.unwrap_or(0)).map_err(|e| {
+                                ErrorResult::new(
+                                    format!("Invalid index: {}", e),
+                                    code::BAD_ARGUMENT,
+                                )
+                            })?,
+                        ]),
+                    };
+                    let address = device
+                        .display_address(path, policy, *hmac)
+                        .await
+                        .map_err(|e| ErrorResult::from(&e))?;
+                    AddressResult {
+                        address: address.assume_checked().to_string(),
+                        change: None,
+                        index: None,
+                    }
+                }
+            };
+            if args.json {
+                print_json(&result);
+            } else {
+                println!("{}", result.address);
+            }
+        }
+        Commands::SignMessage {
+            message,
+            path,
+            addr_type,
+            format,
+        } => {
+            let address_type = addr_type
+                .map(AddressType::from)
+                .or_else(|| AddressType::from_path(path))
+                .or(args.settings.address_type)
+                .unwrap_or(AddressType::Wit);
+            let mut device = select_device(&args).await?;
+            let signature = sign_message(
+                device.as_mut(),
+                path.clone(),
+                message.as_bytes(),
+                address_type,
+                (*format).into(),
+            )
+            .await
+            .map_err(|e| match e {
+                bhwi_cli::message::MessageError::Device(e) => ErrorResult::from(&e),
+                e @ bhwi_cli::message::MessageError::IncompleteProof(_) => {
+                    ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR)
+                }
+                e => ErrorResult::new(e.to_string(), code::BAD_ARGUMENT),
+            })?;
+            if args.json {
+                print_json(&SignMessageResult { signature });
             } else {
-                eprintln!("No device found");
+                println!("{}", signature);
+            }
+        }
+        Commands::SignPsbt {
+            psbt,
+            source,
+            descriptor,
+            name,
+            hmac,
+            output,
+            binary,
+        } => {
+            let psbt = psbt
+                .as_ref()
+                .or(source.as_ref())
+                .expect("clap requires a psbt");
+            let unsigned =
+                read_psbt(psbt).map_err(|e| ErrorResult::new(e.to_string(), code::INVALID_TX))?;
+            let policy = match (descriptor, name) {
+                (Some(descriptor), Some(name)) => Some(wallet_policy(descriptor, name)?),
+                _ => None,
+            };
+            let mut device = select_device(&args).await?;
+            let signed =
+                sign_psbt(device.as_mut(), &unsigned, policy, *hmac, args.max_fee_rate).await?;
+            if output.is_some() || !args.json {
+                let format = if *binary {
+                    PsbtFormat::Binary
+                } else {
+                    PsbtFormat::Base64
+                };
+                write_psbt(&signed, output.as_ref(), format)
+                    .map_err(|e| ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR))?;
+            }
+            if args.json {
+                print_json(&SignTxResult {
+                    psbt: signed.to_string(),
+                    signed: signed != unsigned,
+                });
             }
         }
     }
     Ok(())
 }
+
+async fn enumerate_entries(args: &Args) -> Result<Vec<EnumerateEntry>, ErrorResult> {
+    let devices = list_devices(args.emulator.as_ref()).map_err(|e| ErrorResult::from(&e))?;
+    let mut entries = Vec::new();
+    for info in devices {
+        let fingerprint = match open(&info, args.network(), args.trace_apdu).await {
+            Ok(mut device) => device.get_master_fingerprint().await,
+            Err(e) => Err(e),
+        };
+        entries.push(EnumerateEntry::new(&info, fingerprint.as_ref().copied()));
+    }
+    Ok(entries)
+}
+
+async fn run_daemon(args: &Args) {
+    let mut device: Option<Box<dyn CliDevice>> = None;
+    for line in std::io::stdin().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match daemon::Request::parse(&line) {
+            Ok(request) => request,
+            Err((id, e)) => {
+                print_json(&daemon::Reply::error(id, e));
+                continue;
+            }
+        };
+        let result = match request.command {
+            daemon::Command::Enumerate => enumerate_entries(args)
+                .await
+                .map(|entries| serde_json::json!(entries)),
+            daemon::Command::Close => {
+                print_json(&daemon::Reply::new(request.id, serde_json::json!({})));
+                break;
+            }
+            command => {
+                // The error of the selection is replied, the next request
+                // selects the device again.
+                if device.is_none() {
+                    device = match select_device(args).await {
+                        Ok(selected) => Some(selected),
+                        Err(e) => {
+                            print_json(&daemon::Reply::error(request.id, e));
+                            continue;
+                        }
+                    };
+                }
+                let device = device.as_mut().expect("device selected");
+                daemon::run_device_command(device.as_mut(), command, args.max_fee_rate).await
+            }
+        };
+        match result {
+            Ok(result) => print_json(&daemon::Reply::new(request.id, result)),
+            Err(e) => print_json(&daemon::Reply::error(request.id, e)),
+        }
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("output is serializable")
+    );
+}
+
+fn print_xpub(json: bool, xpub: Xpub) {
+    if json {
+        print_json(&XpubResult::from(xpub));
+    } else {
+        println!("{}", xpub);
+    }
+}
+
+async fn get_xpub(args: &Args, path: DerivationPath, display: bool) -> Result<Xpub, ErrorResult> {
+    let mut device = select_device(args).await?;
+    device
+        .get_extended_pubkey(path, display)
+        .await
+        .map_err(|e| ErrorResult::from(&e))
+}
+
+/// Opens the device selected by the fingerprint or path given as argument.
+/// If none is given and several devices are connected, the user is prompted
+/// to choose one.
+async fn select_device(args: &Args) -> Result<Box<dyn CliDevice>, ErrorResult> {
+    let mut devices = list_devices(args.emulator.as_ref()).map_err(|e| ErrorResult::from(&e))?;
+    if let Some(path) = &args.device_path {
+        devices.retain(|info| &info.path == path);
+    }
+    if args.fingerprint.is_some() {
+        return get_device_with_fingerprint(
+            &devices,
+            args.network(),
+            args.fingerprint,
+            args.trace_apdu,
+        )
+        .await
+        .map_err(|e| ErrorResult::from(&e))?
+        .ok_or_else(|| {
+            ErrorResult::new(
+                "Could not find device with specified fingerprint",
+                code::DEVICE_CONN_ERROR,
+            )
+        });
+    }
+    let info = match devices.len() {
+        0 => return Err(ErrorResult::new("No device found", code::DEVICE_CONN_ERROR)),
+        1 => &devices[0],
+        _ if !args.json && std::io::stdin().is_terminal() => prompt_device(&devices)?,
+        _ => {
+            return Err(ErrorResult::new(
+                "Several devices connected, select one with --fingerprint or --device-path",
+                code::BAD_ARGUMENT,
+            ))
+        }
+    };
+    open(info, args.network(), args.trace_apdu)
+        .await
+        .map_err(|e| ErrorResult::from(&e))
+}
+
+fn prompt_device(devices: &[DeviceInfo]) -> Result<&DeviceInfo, ErrorResult> {
+    for (i, info) in devices.iter().enumerate() {
+        eprintln!("{}: {} {}", i + 1, info.device_type, info.path);
+    }
+    loop {
+        eprint!("Select a device [1-{}]: ", devices.len());
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => {
+                return Err(ErrorResult::new(
+                    "No device selected",
+                    code::ACTION_CANCELED,
+                ))
+            }
+            Ok(_) => {
+                if let Some(info) = line
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| devices.get(i.wrapping_sub(1)))
+                {
+                    return Ok(info);
+                }
+            }
+            Err(e) => return Err(ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR)),
+        }
+    }
+}
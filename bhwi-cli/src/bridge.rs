@@ -0,0 +1,194 @@
+use async_trait::async_trait;
+use bhwi::Event;
+use bhwi_async::{
+    transport::{coldcard_hid::ColdcardTransportHID, ledger_hid::LedgerTransportHID},
+    Transport,
+};
+use bhwi_bridge::{noise::Keypair, BridgedDevice, DeviceProvider};
+use bhwi_serial::{CborCodec, LineCodec, SerialTransport};
+use bitcoin::{hex::DisplayHex, secp256k1::SecretKey};
+
+use crate::{
+    list_devices, open_hid, pacing,
+    transport::{SpeculosTransport, TrezorEmulatorTransport},
+    DeviceInfo, DeviceType, Emulator, Interface,
+};
+
+/// Transport of any device, the errors are erased to io errors.
+pub struct AnyTransport(Box<dyn Transport<Error = std::io::Error>>);
+
+struct ErasedTransport<T>(T);
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for ErasedTransport<T> {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.0
+            .exchange(command, encrypted)
+            .await
+            .map_err(|e| std::io::Error::other(format!("{:?}", e)))
+    }
+
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        self.0
+            .send(command, encrypted)
+            .await
+            .map_err(|e| std::io::Error::other(format!("{:?}", e)))
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.0
+            .reconnect()
+            .await
+            .map_err(|e| std::io::Error::other(format!("{:?}", e)))
+    }
+
+    fn notify(&mut self, event: &Event) {
+        self.0.notify(event)
+    }
+}
+
+impl AnyTransport {
+    pub fn new<T: Transport + 'static>(transport: T) -> Self {
+        Self(Box::new(ErasedTransport(transport)))
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for AnyTransport {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.0.exchange(command, encrypted).await
+    }
+
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        self.0.send(command, encrypted).await
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        self.0.reconnect().await
+    }
+
+    fn notify(&mut self, event: &Event) {
+        self.0.notify(event)
+    }
+}
+
+/// Opens the transport of the device, framing the messages of its protocol
+/// without unlocking it: the client of the bridge drives the device.
+pub fn open_transport(info: &DeviceInfo) -> Result<AnyTransport, String> {
+    match (info.device_type, info.interface) {
+        (DeviceType::Ledger, Interface::Tcp) => SpeculosTransport::connect(&info.path)
+            .map(AnyTransport::new)
+            .map_err(|e| e.to_string()),
+        (DeviceType::Ledger, _) => open_hid(&info.path)
+            .map(|channel| {
+                AnyTransport::new(LedgerTransportHID::new(channel).with_pacing(pacing(info)))
+            })
+            .map_err(|e| format!("{:?}", e)),
+        (DeviceType::Coldcard, _) => open_hid(&info.path)
+            .map(|channel| {
+                AnyTransport::new(ColdcardTransportHID::new(channel).with_pacing(pacing(info)))
+            })
+            .map_err(|e| format!("{:?}", e)),
+        (DeviceType::Jade, _) => SerialTransport::open(&info.path, CborCodec)
+            .map(AnyTransport::new)
+            .map_err(|e| e.to_string()),
+        (DeviceType::Specter, _) => SerialTransport::open(&info.path, LineCodec)
+            .map(AnyTransport::new)
+            .map_err(|e| e.to_string()),
+        (DeviceType::Trezor, _) => TrezorEmulatorTransport::connect(&info.path)
+            .map(AnyTransport::new)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Devices of the host exposed by `bhwi bridge`, the emulator in place of
+/// them if one is given.
+pub struct LocalDevices {
+    emulator: Option<Emulator>,
+    devices: Vec<DeviceInfo>,
+}
+
+impl LocalDevices {
+    pub fn new(emulator: Option<Emulator>) -> Self {
+        Self {
+            emulator,
+            devices: Vec::new(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl DeviceProvider for LocalDevices {
+    type Transport = AnyTransport;
+
+    async fn list(&mut self) -> Result<Vec<BridgedDevice>, String> {
+        self.devices = list_devices(self.emulator.as_ref()).map_err(|e| format!("{:?}", e))?;
+        Ok(self
+            .devices
+            .iter()
+            .map(|info| BridgedDevice {
+                device_type: info.device_type.to_string(),
+                path: info.path.clone(),
+            })
+            .collect())
+    }
+
+    async fn open(&mut self, index: usize) -> Result<AnyTransport, String> {
+        let info = self
+            .devices
+            .get(index)
+            .ok_or_else(|| format!("no device at index {}", index))?;
+        open_transport(info)
+    }
+}
+
+/// Loads the Noise key of the bridge from the file, the key is generated and
+/// saved to it if the file does not exist.
+pub fn load_or_create_key(path: &std::path::Path) -> Result<Keypair, std::io::Error> {
+    if path.exists() {
+        let secret = std::fs::read_to_string(path)?;
+        return secret
+            .trim()
+            .parse::<SecretKey>()
+            .map(Keypair::from_secret_key)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+    }
+    let key = Keypair::generate();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(
+        &mut options.open(path)?,
+        key.secret_key()
+            .secret_bytes()
+            .to_lower_hex_string()
+            .as_bytes(),
+    )?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_or_create_key() {
+        let path = std::env::temp_dir()
+            .join(format!("bhwi-bridge-{}", std::process::id()))
+            .join("key");
+        let key = load_or_create_key(&path).unwrap();
+        assert_eq!(
+            load_or_create_key(&path).unwrap().public_key(),
+            key.public_key()
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
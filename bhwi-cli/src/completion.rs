@@ -0,0 +1,154 @@
+//! Shell completion scripts generated from the clap definition of the cli.
+
+use clap::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Fish,
+}
+
+pub fn generate(shell: Shell, cmd: &Command) -> String {
+    let mut cmd = cmd.clone();
+    cmd.build();
+    match shell {
+        Shell::Bash => bash(&cmd),
+        Shell::Fish => fish(&cmd),
+    }
+}
+
+fn long_flags(cmd: &Command) -> Vec<String> {
+    cmd.get_arguments()
+        .filter_map(|arg| arg.get_long().map(|long| format!("--{}", long)))
+        .collect()
+}
+
+fn takes_value(arg: &clap::Arg) -> bool {
+    !arg.is_positional() && arg.get_num_args().is_some_and(|n| n.takes_values())
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let subcommands: Vec<&str> = cmd.get_subcommands().map(|sub| sub.get_name()).collect();
+    let mut value_flags: Vec<String> = cmd
+        .get_arguments()
+        .chain(cmd.get_subcommands().flat_map(|sub| sub.get_arguments()))
+        .filter(|arg| takes_value(arg))
+        .filter_map(|arg| arg.get_long().map(|long| format!("--{}", long)))
+        .collect();
+    value_flags.sort();
+    value_flags.dedup();
+
+    let mut cases = String::new();
+    for sub in cmd.get_subcommands() {
+        cases.push_str(&format!(
+            "        {}) opts=\"{}\" ;;\n",
+            sub.get_name(),
+            long_flags(sub).join(" ")
+        ));
+    }
+    format!(
+        r#"_{function}() {{
+    local cur prev cmd opts skip
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    cmd=""
+    skip=0
+    for word in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        if [ "$skip" = 1 ]; then skip=0; continue; fi
+        case "$word" in
+            {value_flags}) skip=1 ;;
+            -*) ;;
+            *) [ -z "$cmd" ] && cmd="$word" ;;
+        esac
+    done
+    case "$cmd" in
+{cases}        *) opts="{subcommands} {global}" ;;
+    esac
+    COMPREPLY=($(compgen -W "$opts" -- "$cur"))
+}}
+complete -F _{function} {name}
+"#,
+        function = name.replace('-', "_"),
+        name = name,
+        value_flags = value_flags.join("|"),
+        cases = cases,
+        subcommands = subcommands.join(" "),
+        global = long_flags(cmd).join(" "),
+    )
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut script = String::new();
+    for arg in cmd.get_arguments() {
+        if let Some(long) = arg.get_long() {
+            script.push_str(&format!(
+                "complete -c {} -l {}{}\n",
+                name,
+                long,
+                fish_help(arg.get_help())
+            ));
+        }
+    }
+    for sub in cmd.get_subcommands() {
+        script.push_str(&format!(
+            "complete -c {} -f -n __fish_use_subcommand -a {}{}\n",
+            name,
+            sub.get_name(),
+            fish_help(sub.get_about())
+        ));
+        for arg in sub.get_arguments() {
+            if let Some(long) = arg.get_long() {
+                script.push_str(&format!(
+                    "complete -c {} -n '__fish_seen_subcommand_from {}' -l {}{}\n",
+                    name,
+                    sub.get_name(),
+                    long,
+                    fish_help(arg.get_help())
+                ));
+            }
+        }
+    }
+    script
+}
+
+fn fish_help(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|help| format!(" -d '{}'", help.to_string().replace('\'', "\\'")))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    fn command() -> Command {
+        Command::new("bhwi")
+            .arg(Arg::new("network").long("network"))
+            .subcommand(
+                Command::new("getxpub")
+                    .about("Get the xpub")
+                    .arg(Arg::new("path"))
+                    .arg(
+                        Arg::new("display")
+                            .long("display")
+                            .action(clap::ArgAction::SetTrue),
+                    ),
+            )
+    }
+
+    #[test]
+    fn test_generate() {
+        let bash = generate(Shell::Bash, &command());
+        assert!(bash.contains("getxpub) opts=\"--display --help\" ;;"));
+        assert!(bash.contains("--network) skip=1 ;;"));
+        assert!(bash.contains("complete -F _bhwi bhwi"));
+
+        let fish = generate(Shell::Fish, &command());
+        assert!(fish
+            .contains("complete -c bhwi -f -n __fish_use_subcommand -a getxpub -d 'Get the xpub'"));
+        assert!(
+            fish.contains("complete -c bhwi -n '__fish_seen_subcommand_from getxpub' -l display")
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Configuration file of the cli, values given as flags override it.
+//!
+//! ```json
+//! {
+//!     "network": "testnet",
+//!     "fingerprint": "f5acc2fd",
+//!     "address_type": "tap",
+//!     "account": 1,
+//!     "max_fee_rate": 50
+//! }
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use bitcoin::{bip32::Fingerprint, Network};
+use serde::Deserialize;
+
+use crate::AddressType;
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: Option<Network>,
+    /// Master fingerprint of the preferred device.
+    pub fingerprint: Option<Fingerprint>,
+    /// Path of the preferred device, as listed by enumerate.
+    pub device_path: Option<String>,
+    /// Default address type of the account derivation paths.
+    pub address_type: Option<AddressType>,
+    /// Default account of the account derivation paths.
+    pub account: Option<u32>,
+    /// Maximum fee rate of the signed psbts, in sat/vB.
+    pub max_fee_rate: Option<u64>,
+}
+
+impl Config {
+    /// Returns `$XDG_CONFIG_HOME/bhwi/config.json`, or
+    /// `$HOME/.config/bhwi/config.json` if XDG_CONFIG_HOME is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("bhwi").join("config.json"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let content = std::fs::read(path)?;
+        serde_json::from_slice(&content)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Loads the config file at the default path, the default config if
+    /// there is none.
+    pub fn load_default() -> Result<Self, std::io::Error> {
+        match Self::default_path().filter(|path| path.exists()) {
+            Some(path) => Self::load(&path),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psbt::{check_fee_rate, parse_psbt, FeeRateError};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = serde_json::from_str(
+            r#"{"network": "testnet", "fingerprint": "f5acc2fd", "address_type": "sh-wit"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                network: Some(Network::Testnet),
+                fingerprint: Some(Fingerprint::from_str("f5acc2fd").unwrap()),
+                address_type: Some(AddressType::ShWit),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_max_fee_rate() {
+        let config: Config = serde_json::from_str(r#"{"max_fee_rate": 1}"#).unwrap();
+        assert_eq!(config.max_fee_rate, Some(1));
+        // Spends 301 sats for 224 vB once signed.
+        let psbt = parse_psbt(b"cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA").unwrap();
+        assert!(matches!(
+            check_fee_rate(&psbt, config.max_fee_rate.unwrap()),
+            Err(FeeRateError::Exceeded(_))
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+//! Requests and replies of the daemon mode, where the cli reads one json
+//! request per line on stdin and writes one json reply per line on stdout.
+//! The device stays open between requests.
+
+use bhwi::ledger::WalletPolicy;
+use bitcoin::{bip32::DerivationPath, hex::FromHex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    output::{code, AddressResult, ErrorResult, SignTxResult, XpubResult},
+    CliDevice,
+};
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Command {
+    Enumerate,
+    GetMasterFingerprint,
+    GetXpub {
+        path: DerivationPath,
+        #[serde(default)]
+        display: bool,
+    },
+    /// Signs the base64 psbt for the default wallets.
+    SignTx {
+        psbt: String,
+    },
+    /// Displays the address at the path, ending with the change and the
+    /// index, of the default wallets or of the registered wallet of the
+    /// descriptor.
+    DisplayAddress {
+        path: DerivationPath,
+        #[serde(default)]
+        descriptor: Option<String>,
+        /// Name of the registered wallet.
+        #[serde(default)]
+        name: Option<String>,
+        /// Proof of registration of the wallet, in hex.
+        #[serde(default)]
+        hmac: Option<String>,
+    },
+    /// Closes the device and stops the daemon.
+    Close,
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Enumerate => "enumerate",
+            Command::GetMasterFingerprint => "getmasterfingerprint",
+            Command::GetXpub { .. } => "getxpub",
+            Command::SignTx { .. } => "signtx",
+            Command::DisplayAddress { .. } => "displayaddress",
+            Command::Close => "close",
+        }
+    }
+}
+
+/// Runs a command on the device, `enumerate` and `close` are handled by
+/// the caller and rejected. The psbts above the maximum fee rate, in sat/vB,
+/// are not signed.
+pub async fn run_device_command(
+    device: &mut dyn CliDevice,
+    command: Command,
+    max_fee_rate: Option<u64>,
+) -> Result<serde_json::Value, ErrorResult> {
+    match command {
+        Command::GetMasterFingerprint => device
+            .get_master_fingerprint()
+            .await
+            .map(|fingerprint| serde_json::json!({ "fingerprint": fingerprint.to_string() })),
+        Command::GetXpub { path, display } => device
+            .get_extended_pubkey(path, display)
+            .await
+            .map(|xpub| serde_json::json!(XpubResult::from(xpub))),
+        Command::SignTx { psbt } => {
+            let unsigned = crate::psbt::parse_psbt(psbt.as_bytes())
+                .map_err(|e| ErrorResult::new(e.to_string(), code::INVALID_TX))?;
+            let signed =
+                crate::psbt::sign_psbt(device, &unsigned, None, None, max_fee_rate).await?;
+            return Ok(serde_json::json!(SignTxResult {
+                psbt: signed.to_string(),
+                signed: signed != unsigned,
+            }));
+        }
+        Command::DisplayAddress {
+            path,
+            descriptor,
+            name,
+            hmac,
+        } => {
+            let policy = descriptor
+                .map(|descriptor| {
+                    WalletPolicy::from_descriptor(&descriptor)
+                        .map(|mut policy| {
+                            policy.name = name.unwrap_or_default();
+                            policy
+                        })
+                        .map_err(|e| {
+                            ErrorResult::new(
+                                format!("Invalid descriptor: {:?}", e),
+                                code::BAD_ARGUMENT,
+                            )
+                        })
+                })
+                .transpose()?;
+            let hmac = hmac
+                .map(|hmac| {
+                    <[u8; 32]>::from_hex(&hmac)
+                        .map_err(|e| ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))
+                })
+                .transpose()?;
+            device
+                .display_address(path, policy, hmac)
+                .await
+                .map(|address| {
+                    serde_json::json!(AddressResult {
+                        address: address.assume_checked().to_string(),
+                        change: None,
+                        index: None,
+                    })
+                })
+        }
+        Command::Enumerate | Command::Close => {
+            return Err(ErrorResult::new(
+                format!("Command {} does not run on a device", command.name()),
+                code::BAD_ARGUMENT,
+            ))
+        }
+    }
+    .map_err(|e| ErrorResult::from(&e))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    /// Identifier echoed in the reply, so callers can match them.
+    #[serde(default)]
+    pub id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+impl Request {
+    /// Parses a request line, the error is returned with the id of the
+    /// request if it was readable, to be echoed in the error reply.
+    pub fn parse(line: &str) -> Result<Self, (Option<serde_json::Value>, ErrorResult)> {
+        serde_json::from_str(line).map_err(|e| {
+            let id = serde_json::from_str::<serde_json::Value>(line)
+                .ok()
+                .and_then(|v| v.get("id").cloned());
+            (id, ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Reply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub result: serde_json::Value,
+}
+
+impl Reply {
+    pub fn new<T: Serialize>(id: Option<serde_json::Value>, result: T) -> Self {
+        Self {
+            id,
+            result: serde_json::to_value(result).expect("result is serializable"),
+        }
+    }
+
+    pub fn error(id: Option<serde_json::Value>, error: ErrorResult) -> Self {
+        Self::new(id, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_request() {
+        let request =
+            Request::parse(r#"{"id": 1, "command": "getxpub", "path": "m/84'/0'/0'"}"#).unwrap();
+        assert_eq!(request.id, Some(serde_json::json!(1)));
+        assert_eq!(
+            request.command,
+            Command::GetXpub {
+                path: DerivationPath::from_str("m/84'/0'/0'").unwrap(),
+                display: false,
+            }
+        );
+
+        let request = Request::parse(r#"{"command": "signtx", "psbt": "cHNidP8B"}"#).unwrap();
+        assert_eq!(
+            request.command,
+            Command::SignTx {
+                psbt: "cHNidP8B".to_string()
+            }
+        );
+
+        let request = Request::parse(
+            r#"{"command": "displayaddress", "path": "m/84'/0'/0'/0/1", "name": "vault"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request.command,
+            Command::DisplayAddress {
+                path: DerivationPath::from_str("m/84'/0'/0'/0/1").unwrap(),
+                descriptor: None,
+                name: Some("vault".to_string()),
+                hmac: None,
+            }
+        );
+        assert_eq!(request.command.name(), "displayaddress");
+
+        let (id, e) = Request::parse(r#"{"id": "a", "command": "unknown"}"#).unwrap_err();
+        let reply = serde_json::to_value(Reply::error(id, e)).unwrap();
+        assert_eq!(reply["id"], serde_json::json!("a"));
+        assert_eq!(reply["code"], serde_json::json!(code::BAD_ARGUMENT));
+    }
+}
@@ -0,0 +1,180 @@
+//! Descriptors of the standard single signature accounts.
+
+use std::str::FromStr;
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub};
+
+use crate::AddressType;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Returns the receive and change descriptors of the account,
+/// with their checksum.
+pub fn account_descriptors(
+    address_type: AddressType,
+    fingerprint: Fingerprint,
+    path: &DerivationPath,
+    xpub: &Xpub,
+) -> (String, String) {
+    let origin = path
+        .into_iter()
+        .fold(fingerprint.to_string(), |origin, child| match child {
+            ChildNumber::Hardened { index } => format!("{}/{}h", origin, index),
+            ChildNumber::Normal { index } => format!("{}/{}", origin, index),
+        });
+    let descriptor = |change: u32| {
+        let key = format!("[{}]{}/{}/*", origin, xpub, change);
+        let desc = match address_type {
+            AddressType::Legacy => format!("pkh({})", key),
+            AddressType::ShWit => format!("sh(wpkh({}))", key),
+            AddressType::Wit => format!("wpkh({})", key),
+            AddressType::Tap => format!("tr({})", key),
+        };
+        with_checksum(&desc)
+    };
+    (descriptor(0), descriptor(1))
+}
+
+/// Appends the BIP-380 checksum to the descriptor.
+/// Panics if the descriptor has a character outside of the descriptor charset.
+pub fn with_checksum(desc: &str) -> String {
+    fn polymod(c: u64, val: u64) -> u64 {
+        let c0 = c >> 35;
+        let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+        for (i, generator) in [
+            0xf5dee51989,
+            0xa9fdca3312,
+            0x1bab10e32d,
+            0x3706b1677a,
+            0x644d626ffd,
+        ]
+        .iter()
+        .enumerate()
+        {
+            if c0 & (1 << i) != 0 {
+                c ^= generator;
+            }
+        }
+        c
+    }
+
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .expect("descriptor uses the descriptor charset") as u64;
+        c = polymod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = polymod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = polymod(c, cls);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+
+    let checksum: String = (0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect();
+    format!("{}#{}", desc, checksum)
+}
+
+/// Returns the address type and the origin of the key of a single key
+/// descriptor, as given by Bitcoin Core to display an address, e.g.
+/// `wpkh([d34db33f/84h/1h/0h/0/0]03...)#checksum`. The derivation steps after
+/// an extended key are appended to the path of its origin.
+pub fn key_origin(desc: &str) -> Option<(AddressType, Fingerprint, DerivationPath)> {
+    if !desc.chars().all(|c| INPUT_CHARSET.contains(c)) {
+        return None;
+    }
+    let body = match desc.split_once('#') {
+        Some((body, _)) if with_checksum(body) != desc => return None,
+        Some((body, _)) => body,
+        None => desc,
+    };
+    let (address_type, key) = [
+        ("pkh(", ")", AddressType::Legacy),
+        ("sh(wpkh(", "))", AddressType::ShWit),
+        ("wpkh(", ")", AddressType::Wit),
+        ("tr(", ")", AddressType::Tap),
+    ]
+    .into_iter()
+    .find_map(|(prefix, suffix, address_type)| {
+        Some((
+            address_type,
+            body.strip_prefix(prefix)?.strip_suffix(suffix)?,
+        ))
+    })?;
+    let (origin, key) = key.strip_prefix('[')?.split_once(']')?;
+    let mut origin = origin.split('/');
+    let fingerprint = Fingerprint::from_str(origin.next()?).ok()?;
+    let path = origin
+        .chain(key.split('/').skip(1))
+        .map(|child| ChildNumber::from_str(child).ok())
+        .collect::<Option<Vec<_>>>()?;
+    Some((address_type, fingerprint, path.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_checksum() {
+        assert_eq!(with_checksum("raw(deadbeef)"), "raw(deadbeef)#89f8spxm");
+    }
+
+    #[test]
+    fn test_account_descriptors() {
+        let xpub = Xpub::from_str("tpubDCtKfsNyRhULjZ9XMS4VKKtVcPdVDi8MKUbcSD9MJDyjRu1A2ND5MiipozyyspBT9bg8upEp7a8EAgFxNxXn1d7QkdbL52Ty5jiSLcxPt1P").unwrap();
+        let (receive, change) = account_descriptors(
+            AddressType::Wit,
+            Fingerprint::from_str("f5acc2fd").unwrap(),
+            &DerivationPath::from_str("m/84'/1'/0'").unwrap(),
+            &xpub,
+        );
+        assert!(receive.starts_with(&format!("wpkh([f5acc2fd/84h/1h/0h]{}/0/*)#", xpub)));
+        assert!(change.starts_with(&format!("wpkh([f5acc2fd/84h/1h/0h]{}/1/*)#", xpub)));
+    }
+
+    #[test]
+    fn test_key_origin() {
+        let desc = with_checksum(
+            "wpkh([f5acc2fd/84h/1h/0h/0/5]0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c)",
+        );
+        assert_eq!(
+            key_origin(&desc),
+            Some((
+                AddressType::Wit,
+                Fingerprint::from_str("f5acc2fd").unwrap(),
+                DerivationPath::from_str("m/84'/1'/0'/0/5").unwrap()
+            ))
+        );
+        let xpub = "tpubDCtKfsNyRhULjZ9XMS4VKKtVcPdVDi8MKUbcSD9MJDyjRu1A2ND5MiipozyyspBT9bg8upEp7a8EAgFxNxXn1d7QkdbL52Ty5jiSLcxPt1P";
+        assert_eq!(
+            key_origin(&format!("sh(wpkh([f5acc2fd/49'/1'/0']{}/1/3))", xpub)),
+            Some((
+                AddressType::ShWit,
+                Fingerprint::from_str("f5acc2fd").unwrap(),
+                DerivationPath::from_str("m/49'/1'/0'/1/3").unwrap()
+            ))
+        );
+        assert_eq!(key_origin(&format!("{}x", desc)), None);
+        assert_eq!(
+            key_origin(&format!("wpkh([f5acc2fd/84h]{}/0/*)", xpub)),
+            None
+        );
+        assert_eq!(key_origin("wsh(pk(03aa))"), None);
+    }
+}
@@ -1,142 +1,365 @@
+pub mod bridge;
+pub mod completion;
+pub mod config;
+pub mod daemon;
+pub mod descriptor;
+pub mod message;
+pub mod output;
+pub mod psbt;
+pub mod transport;
+pub mod watch;
+
+use std::ffi::CString;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use bhwi::common;
+use bhwi::devices::{self, DeviceKind, Pacing};
+use bhwi::ledger::{DeviceModel, RetryPolicy, WalletPolicy};
 use bhwi_async::{
-    Error as HWIError, HWI, HttpClient
+    coldcard::Coldcard,
+    registry::DeviceRegistry,
+    transport::{
+        coldcard_hid::ColdcardTransportHID,
+        ledger_hid::{LedgerTransportHID, LEDGER_USAGE_PAGE},
+    },
+    DisplayAddress, Error as HWIError, Jade, Ledger, SignMessage, Specter, Transport, Trezor, HWI,
+};
+use bhwi_serial::{CborCodec, LineCodec, SerialTransport};
+use bitcoin::{
+    address::NetworkUnchecked,
+    bip32::{self, ChildNumber, DerivationPath, Fingerprint, Xpub},
+    Address, Network, Psbt,
 };
-use bitcoin::{bip32::Fingerprint, Network};
 use hidapi::HidApi;
-use serialport::{available_ports, SerialPortType};
-use async_trait::async_trait;
+
+use transport::{
+    EventHandler, HidChannel, PinServerClient, SpeculosTransport, TraceTransport,
+    TrezorEmulatorTransport,
+};
 
 pub type Error = HWIError<std::io::Error, std::io::Error>;
 
-// Hardware wallet VID/PID constants (from bhwi-async transport modules)
-const LEDGER_VID: u16 = 0x2c97;
-const COLDCARD_VID: u16 = 0xd13e;
-const JADE_DEVICE_IDS: [(u16, u16); 6] = [
-    (0x10c4, 0xea60),
-    (0x1a86, 0x55d4),
-    (0x0403, 0x6001),
-    (0x1a86, 0x7523),
-    (0x303a, 0x4001),
-    (0x303a, 0x1001),
-];
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Ledger,
+    Coldcard,
+    Jade,
+    Specter,
+    Trezor,
+}
+
+impl Display for DeviceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceType::Ledger => write!(f, "ledger"),
+            DeviceType::Coldcard => write!(f, "coldcard"),
+            DeviceType::Jade => write!(f, "jade"),
+            DeviceType::Specter => write!(f, "specter"),
+            DeviceType::Trezor => write!(f, "trezor"),
+        }
+    }
+}
+
+impl FromStr for DeviceType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ledger" => Ok(DeviceType::Ledger),
+            "coldcard" => Ok(DeviceType::Coldcard),
+            "jade" => Ok(DeviceType::Jade),
+            "specter" => Ok(DeviceType::Specter),
+            "trezor" => Ok(DeviceType::Trezor),
+            _ => Err(format!("unknown device type: {}", s)),
+        }
+    }
+}
+
+/// How the host is connected to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interface {
+    Hid,
+    Serial,
+    /// Emulator listening on a tcp socket.
+    Tcp,
+    /// Emulator listening on a udp socket.
+    Udp,
+}
 
 // Device info structure for enumeration
 #[derive(Debug, Clone)]
 pub struct DeviceInfo {
-    pub device_type: String,
+    pub device_type: DeviceType,
+    pub interface: Interface,
     pub path: String,
-    pub vid: Option<u16>,
-    pub pid: Option<u16>,
+    pub vid: u16,
+    pub pid: u16,
 }
 
-// Mock HWI implementation for device enumeration
-pub struct MockDevice {
-    pub info: DeviceInfo,
+/// Emulator to use in place of the connected devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Emulator {
+    /// Speculos, the Ledger emulator, with the address of its apdu server.
+    Speculos(String),
+    /// Trezor emulator, with the address of its udp port.
+    Trezor(String),
 }
 
-#[async_trait(?Send)]
-impl HWI for MockDevice {
-    type Error = Error;
-    
-    async fn unlock(&mut self, _network: Network) -> Result<(), Self::Error> {
-        Err(HWIError::HttpClient(std::io::Error::new(
-            std::io::ErrorKind::Unsupported,
-            "Mock device - not implemented"
-        )))
-    }
-    
-    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
-        // For demo purposes, generate a mock fingerprint based on device type
-        let fingerprint_bytes = match self.info.device_type.as_str() {
-            "Ledger" => [0x12, 0x34, 0x56, 0x78],
-            "Coldcard" => [0xAB, 0xCD, 0xEF, 0x01],
-            "Jade" => [0x98, 0x76, 0x54, 0x32],
-            _ => [0x00, 0x00, 0x00, 0x00],
-        };
-        Ok(Fingerprint::from(fingerprint_bytes))
+impl FromStr for Emulator {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, address) = s.split_once(':').unwrap_or((s, ""));
+        match kind {
+            "speculos" if address.is_empty() => Ok(Emulator::Speculos(
+                transport::SPECULOS_DEFAULT_ADDRESS.to_string(),
+            )),
+            "speculos" => Ok(Emulator::Speculos(address.to_string())),
+            "trezor" if address.is_empty() => Ok(Emulator::Trezor(
+                transport::TREZOR_EMULATOR_DEFAULT_ADDRESS.to_string(),
+            )),
+            "trezor" => Ok(Emulator::Trezor(address.to_string())),
+            _ => Err(format!("unsupported emulator: {}", kind)),
+        }
     }
-    
-    async fn get_extended_pubkey(
-        &mut self,
-        _path: bitcoin::bip32::DerivationPath,
-        _display: bool,
-    ) -> Result<bitcoin::bip32::Xpub, Self::Error> {
-        Err(HWIError::HttpClient(std::io::Error::new(
-            std::io::ErrorKind::Unsupported,
-            "Mock device - not implemented"
-        )))
-    }
-}
-
-pub async fn list(_network: Network) -> Result<Vec<Box<dyn HWI<Error = Error>>>, Error> {
-    let mut devices: Vec<Box<dyn HWI<Error = Error>>> = Vec::new();
-    
-    if let Ok(api) = HidApi::new() {
-        // Enumerate HID devices
-        for device_info in api.device_list() {
-            let vid = device_info.vendor_id();
-            let pid = device_info.product_id();
-            
-            // Check for Ledger devices
-            if vid == LEDGER_VID {
-                let device_info = DeviceInfo {
-                    device_type: "Ledger".to_string(),
-                    path: device_info.path().to_string_lossy().to_string(),
-                    vid: Some(vid),
-                    pid: Some(pid),
-                };
-                let mock_device = MockDevice { info: device_info };
-                devices.push(Box::new(mock_device));
-            }
-            
-            // Check for Coldcard devices  
-            if vid == COLDCARD_VID {
-                let device_info = DeviceInfo {
-                    device_type: "Coldcard".to_string(),
-                    path: device_info.path().to_string_lossy().to_string(),
-                    vid: Some(vid),
-                    pid: Some(pid),
-                };
-                let mock_device = MockDevice { info: device_info };
-                devices.push(Box::new(mock_device));
-            }
+}
+
+impl Emulator {
+    pub fn device_info(&self) -> DeviceInfo {
+        match self {
+            Emulator::Speculos(address) => DeviceInfo {
+                device_type: DeviceType::Ledger,
+                interface: Interface::Tcp,
+                path: address.clone(),
+                vid: 0,
+                pid: 0,
+            },
+            Emulator::Trezor(address) => DeviceInfo {
+                device_type: DeviceType::Trezor,
+                interface: Interface::Udp,
+                path: address.clone(),
+                vid: 0,
+                pid: 0,
+            },
         }
-        
-        // Enumerate serial devices for Jade
-        if let Ok(ports) = available_ports() {
-            for port in ports {
-                if let SerialPortType::UsbPort(usb_info) = port.port_type {
-                    if JADE_DEVICE_IDS.contains(&(usb_info.vid, usb_info.pid)) {
-                        let device_info = DeviceInfo {
-                            device_type: "Jade".to_string(),
-                            path: port.port_name.clone(),
-                            vid: Some(usb_info.vid),
-                            pid: Some(usb_info.pid),
-                        };
-                        let mock_device = MockDevice { info: device_info };
-                        devices.push(Box::new(mock_device));
-                    }
-                }
+    }
+}
+
+/// Lists the devices to use: the emulator if one is given,
+/// the connected devices otherwise.
+pub fn list_devices(emulator: Option<&Emulator>) -> Result<Vec<DeviceInfo>, Error> {
+    match emulator {
+        Some(emulator) => Ok(vec![emulator.device_info()]),
+        None => enumerate(),
+    }
+}
+
+/// Lists the supported devices connected over USB HID or serial.
+pub fn enumerate() -> Result<Vec<DeviceInfo>, Error> {
+    let mut devices = Vec::new();
+
+    let api = HidApi::new().map_err(|e| HWIError::Transport(std::io::Error::other(e)))?;
+    for device_info in api.device_list() {
+        let vid = device_info.vendor_id();
+        let device_type = match devices::identify(vid, device_info.product_id()) {
+            Some(kind)
+                if kind.is_ledger()
+                    && (device_info.usage_page() == LEDGER_USAGE_PAGE
+                        || device_info.interface_number() == 0) =>
+            {
+                DeviceType::Ledger
             }
+            Some(DeviceKind::Coldcard) => DeviceType::Coldcard,
+            _ => continue,
+        };
+        devices.push(DeviceInfo {
+            device_type,
+            interface: Interface::Hid,
+            path: device_info.path().to_string_lossy().to_string(),
+            vid,
+            pid: device_info.product_id(),
+        });
+    }
+
+    if let Ok(ports) = bhwi_serial::enumerate() {
+        for port in ports {
+            let device_type = match port.kind {
+                DeviceKind::Jade => DeviceType::Jade,
+                DeviceKind::Specter => DeviceType::Specter,
+                _ => continue,
+            };
+            devices.push(DeviceInfo {
+                device_type,
+                interface: Interface::Serial,
+                path: port.path,
+                vid: port.vid,
+                pid: port.pid,
+            });
         }
     }
-    
+
     Ok(devices)
 }
 
+/// Connects to the device and unlocks it for the given network.
+/// If trace is true, the exchanged frames are printed on stderr.
+pub async fn open(
+    info: &DeviceInfo,
+    network: Network,
+    trace: bool,
+) -> Result<Box<dyn CliDevice>, Error> {
+    open_with_events(info, network, trace, None).await
+}
+
+/// Connects to the device and unlocks it like [`open`], the events of the
+/// interpreters of its commands are passed to the handler.
+pub async fn open_with_events(
+    info: &DeviceInfo,
+    network: Network,
+    trace: bool,
+    events: Option<EventHandler>,
+) -> Result<Box<dyn CliDevice>, Error> {
+    let mut device: Box<dyn CliDevice> = match (info.device_type, info.interface) {
+        (DeviceType::Ledger, Interface::Tcp) => Box::new(Device(
+            Ledger::new(
+                TraceTransport::new(
+                    SpeculosTransport::connect(&info.path).map_err(HWIError::Transport)?,
+                    trace,
+                    true,
+                )
+                .with_events(events),
+            )
+            .with_network(network)
+            .with_retry_policy(RetryPolicy::default()),
+        )),
+        (DeviceType::Ledger, _) => {
+            let ledger = Ledger::new(
+                TraceTransport::new(
+                    LedgerTransportHID::new(open_hid(&info.path)?).with_pacing(pacing(info)),
+                    trace,
+                    true,
+                )
+                .with_events(events),
+            )
+            .with_network(network)
+            .with_retry_policy(RetryPolicy::default());
+            Box::new(Device(match DeviceModel::from_product_id(info.pid) {
+                Some(model) => ledger.with_model(model),
+                None => ledger,
+            }))
+        }
+        (DeviceType::Coldcard, _) => Box::new(Device(Coldcard::new(
+            TraceTransport::new(
+                ColdcardTransportHID::new(open_hid(&info.path)?).with_pacing(pacing(info)),
+                trace,
+                false,
+            )
+            .with_events(events),
+            &mut rand::rngs::OsRng,
+        ))),
+        (DeviceType::Jade, _) => Box::new(Device(Jade::new(
+            network,
+            TraceTransport::new(
+                SerialTransport::open(&info.path, CborCodec)
+                    .map_err(|e| HWIError::Transport(std::io::Error::other(e)))?,
+                trace,
+                false,
+            )
+            .with_events(events),
+            PinServerClient,
+        ))),
+        (DeviceType::Specter, _) => Box::new(Device(Specter::new(
+            TraceTransport::new(
+                SerialTransport::open(&info.path, LineCodec)
+                    .map_err(|e| HWIError::Transport(std::io::Error::other(e)))?,
+                trace,
+                false,
+            )
+            .with_events(events),
+        ))),
+        // Only the emulator is supported.
+        (DeviceType::Trezor, _) => Box::new(Device(
+            Trezor::new(
+                TraceTransport::new(
+                    TrezorEmulatorTransport::connect(&info.path).map_err(HWIError::Transport)?,
+                    trace,
+                    false,
+                )
+                .with_events(events),
+            )
+            .with_network(network),
+        )),
+    };
+    device.unlock(network).await?;
+    Ok(device)
+}
+
+/// Connects to the device of the type over a transport of the application,
+/// like the USB or BLE stack of a mobile platform, and unlocks it. The
+/// transport exchanges the messages of the device protocol, the framing of
+/// the link is left to it. The Jade is not supported, its pin server is
+/// reached by the cli.
+pub async fn open_with_transport<T>(
+    device_type: DeviceType,
+    transport: T,
+    network: Network,
+) -> Result<Box<dyn CliDevice>, Error>
+where
+    T: Transport<Error = std::io::Error> + 'static,
+{
+    let mut device: Box<dyn CliDevice> = match device_type {
+        DeviceType::Ledger => Box::new(Device(
+            Ledger::new(transport)
+                .with_network(network)
+                .with_retry_policy(RetryPolicy::default()),
+        )),
+        DeviceType::Coldcard => Box::new(Device(Coldcard::new(transport, &mut rand::rngs::OsRng))),
+        DeviceType::Specter => Box::new(Device(Specter::new(transport))),
+        DeviceType::Trezor => Box::new(Device(Trezor::new(transport).with_network(network))),
+        DeviceType::Jade => {
+            return Err(HWIError::Interpreter(common::Error::UnsupportedCommand(
+                "jade over a custom transport",
+            )))
+        }
+    };
+    device.unlock(network).await?;
+    Ok(device)
+}
+
+/// Returns the default pacing of the model of the device.
+fn pacing(info: &DeviceInfo) -> Pacing {
+    devices::identify(info.vid, info.pid)
+        .map(Pacing::for_device)
+        .unwrap_or_default()
+}
+
+fn open_hid(path: &str) -> Result<HidChannel, Error> {
+    let path = CString::new(path).map_err(|e| HWIError::Transport(std::io::Error::other(e)))?;
+    HidApi::new()
+        .and_then(|api| api.open_path(&path))
+        .map(HidChannel::new)
+        .map_err(|e| HWIError::Transport(std::io::Error::other(e)))
+}
+
+/// Returns the first connected device with the master fingerprint matching,
+/// or the first connected device if no fingerprint is given.
 pub async fn get_device_with_fingerprint(
+    devices: &[DeviceInfo],
     network: Network,
     fingerprint: Option<Fingerprint>,
-) -> Result<Option<Box<dyn HWI<Error = Error>>>, Error> {
-    for mut device in list(network).await? {
+    trace: bool,
+) -> Result<Option<Box<dyn CliDevice>>, Error> {
+    for info in devices {
+        let mut device = match open(info, network, trace).await {
+            Ok(device) => device,
+            Err(_) => continue, // Skip devices that can't be accessed
+        };
         if let Some(target_fingerprint) = fingerprint {
             match device.get_master_fingerprint().await {
-                Ok(device_fingerprint) => {
-                    if device_fingerprint == target_fingerprint {
-                        return Ok(Some(device));
-                    }
+                Ok(device_fingerprint) if device_fingerprint == target_fingerprint => {
+                    return Ok(Some(device));
                 }
-                Err(_) => continue, // Skip devices that can't be accessed
+                _ => continue,
             }
         } else {
             return Ok(Some(device));
@@ -145,20 +368,240 @@ pub async fn get_device_with_fingerprint(
     Ok(None)
 }
 
-pub struct SimpleHttpClient;
+/// Registry of the devices opened by the cli, by path.
+pub type Registry = DeviceRegistry<String, Box<dyn CliDevice>>;
 
-impl SimpleHttpClient {
-    pub fn new() -> Self {
-        Self
+/// Refreshes the registry with the devices connected: the devices unplugged
+/// are dropped, the new ones are opened and registered with their master
+/// fingerprint. The devices that can't be accessed are skipped.
+pub async fn refresh_registry(
+    registry: &mut Registry,
+    devices: &[DeviceInfo],
+    network: Network,
+    trace: bool,
+) {
+    let paths: Vec<String> = devices.iter().map(|info| info.path.clone()).collect();
+    for path in registry.refresh(&paths) {
+        let Some(info) = devices.iter().find(|info| info.path == path) else {
+            continue;
+        };
+        let Ok(mut device) = open(info, network, trace).await else {
+            continue;
+        };
+        if let Ok(fingerprint) = device.get_master_fingerprint().await {
+            registry.insert(path, fingerprint, device);
+        }
+    }
+}
+
+/// Script types of the standard single signature accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressType {
+    /// BIP44, p2pkh
+    Legacy,
+    /// BIP49, p2sh-p2wpkh
+    ShWit,
+    /// BIP84, p2wpkh
+    Wit,
+    /// BIP86, p2tr
+    Tap,
+}
+
+impl AddressType {
+    /// Returns the address type of the purpose of the path.
+    pub fn from_path(path: &DerivationPath) -> Option<Self> {
+        match path.into_iter().next() {
+            Some(ChildNumber::Hardened { index: 44 }) => Some(Self::Legacy),
+            Some(ChildNumber::Hardened { index: 49 }) => Some(Self::ShWit),
+            Some(ChildNumber::Hardened { index: 84 }) => Some(Self::Wit),
+            Some(ChildNumber::Hardened { index: 86 }) => Some(Self::Tap),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the derivation path of the standard account for the address type,
+/// the account being a hardened index.
+pub fn account_path(
+    address_type: AddressType,
+    network: Network,
+    account: u32,
+) -> Result<DerivationPath, bip32::Error> {
+    let purpose = match address_type {
+        AddressType::Legacy => 44,
+        AddressType::ShWit => 49,
+        AddressType::Wit => 84,
+        AddressType::Tap => 86,
+    };
+    let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+    Ok(DerivationPath::from(vec![
+        ChildNumber::from_hardened_idx(purpose)?,
+        ChildNumber::from_hardened_idx(coin_type)?,
+        ChildNumber::from_hardened_idx(account)?,
+    ]))
+}
+
+/// Device opened by the cli, with the commands of the subcommands beyond
+/// the ones of [`HWI`].
+#[async_trait(?Send)]
+pub trait CliDevice: HWI<Error = Error> {
+    /// Returns the base64 signature of the message by the key at the path,
+    /// see [`SignMessage`].
+    async fn sign_message(&mut self, path: DerivationPath, message: &[u8])
+        -> Result<String, Error>;
+    /// Returns the address displayed by the device, see [`DisplayAddress`].
+    async fn display_address(
+        &mut self,
+        path: DerivationPath,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Address<NetworkUnchecked>, Error>;
+    /// Returns the change and the index of the address in the policy, see
+    /// [`DisplayAddress::verify_owned_address`].
+    async fn verify_owned_address(
+        &mut self,
+        address: Address<NetworkUnchecked>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        gap_limit: u32,
+    ) -> Result<(bool, u32), Error>;
+}
+
+/// Wraps a device to erase the error types of its transport and http client.
+struct Device<D>(D);
+
+#[async_trait(?Send)]
+impl<D, E, F> CliDevice for Device<D>
+where
+    D: HWI<Error = HWIError<E, F>>
+        + SignMessage<Error = HWIError<E, F>>
+        + DisplayAddress<Error = HWIError<E, F>>,
+    E: Debug,
+    F: Debug,
+{
+    async fn sign_message(
+        &mut self,
+        path: DerivationPath,
+        message: &[u8],
+    ) -> Result<String, Error> {
+        SignMessage::sign_message(&mut self.0, path, message)
+            .await
+            .map_err(erase)
+    }
+
+    async fn display_address(
+        &mut self,
+        path: DerivationPath,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Address<NetworkUnchecked>, Error> {
+        DisplayAddress::display_address(&mut self.0, path, policy, hmac)
+            .await
+            .map_err(erase)
+    }
+
+    async fn verify_owned_address(
+        &mut self,
+        address: Address<NetworkUnchecked>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        gap_limit: u32,
+    ) -> Result<(bool, u32), Error> {
+        DisplayAddress::verify_owned_address(&mut self.0, address, policy, hmac, gap_limit)
+            .await
+            .map_err(erase)
     }
 }
 
 #[async_trait(?Send)]
-impl HttpClient for SimpleHttpClient {
-    type Error = std::io::Error;
-    
-    async fn request(&self, _url: &str, _request: &[u8]) -> Result<Vec<u8>, Self::Error> {
-        // Mock implementation - you can implement the reqwest version yourself
-        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "HTTP client not implemented"))
-    }
-}
\ No newline at end of file
+impl<D, E, F> HWI for Device<D>
+where
+    D: HWI<Error = HWIError<E, F>>,
+    E: Debug,
+    F: Debug,
+{
+    type Error = Error;
+
+    async fn unlock(&mut self, network: Network) -> Result<(), Self::Error> {
+        self.0.unlock(network).await.map_err(erase)
+    }
+
+    async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Self::Error> {
+        self.0.get_master_fingerprint().await.map_err(erase)
+    }
+
+    async fn get_extended_pubkey(
+        &mut self,
+        path: DerivationPath,
+        display: bool,
+    ) -> Result<Xpub, Self::Error> {
+        self.0
+            .get_extended_pubkey(path, display)
+            .await
+            .map_err(erase)
+    }
+
+    async fn register_wallet(
+        &mut self,
+        policy: WalletPolicy,
+    ) -> Result<([u8; 32], [u8; 32]), Self::Error> {
+        self.0.register_wallet(policy).await.map_err(erase)
+    }
+
+    async fn sign_psbt(
+        &mut self,
+        psbt: Psbt,
+        policy: Option<WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    ) -> Result<Psbt, Self::Error> {
+        self.0.sign_psbt(psbt, policy, hmac).await.map_err(erase)
+    }
+}
+
+fn erase<E: Debug, F: Debug>(e: HWIError<E, F>) -> Error {
+    match e {
+        HWIError::Transport(e) => HWIError::Transport(std::io::Error::other(format!("{:?}", e))),
+        HWIError::HttpClient(e) => HWIError::HttpClient(std::io::Error::other(format!("{:?}", e))),
+        HWIError::Interpreter(e) => HWIError::Interpreter(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emulator_from_str() {
+        assert_eq!(
+            Emulator::from_str("speculos:127.0.0.1:40000").unwrap(),
+            Emulator::Speculos("127.0.0.1:40000".to_string())
+        );
+        assert_eq!(
+            Emulator::from_str("speculos").unwrap(),
+            Emulator::Speculos("127.0.0.1:9999".to_string())
+        );
+        assert_eq!(
+            Emulator::from_str("trezor:127.0.0.1:21324").unwrap(),
+            Emulator::Trezor("127.0.0.1:21324".to_string())
+        );
+        assert_eq!(
+            Emulator::from_str("trezor").unwrap(),
+            Emulator::Trezor("127.0.0.1:21324".to_string())
+        );
+        assert!(Emulator::from_str("qemu:127.0.0.1:21324").is_err());
+    }
+
+    #[test]
+    fn test_account_path() {
+        assert_eq!(
+            account_path(AddressType::Wit, Network::Bitcoin, 0).unwrap(),
+            DerivationPath::from_str("m/84'/0'/0'").unwrap()
+        );
+        assert_eq!(
+            account_path(AddressType::Tap, Network::Testnet, 3).unwrap(),
+            DerivationPath::from_str("m/86'/1'/3'").unwrap()
+        );
+        assert!(account_path(AddressType::Wit, Network::Bitcoin, 1 << 31).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Signatures of messages by the key of a single signature address, in the
+//! legacy format of BIP-137 or as the BIP-322 proof of the address.
+
+use bhwi::{
+    bip322::{self, Proof, ProofFormat},
+    wallet::{Version, WalletPolicy},
+};
+use bitcoin::{
+    base64::{prelude::BASE64_STANDARD, Engine},
+    bip32::{ChildNumber, DerivationPath, KeySource, Xpub},
+    Psbt,
+};
+
+use crate::{AddressType, CliDevice, Error};
+
+#[derive(Debug)]
+pub enum MessageError {
+    InvalidSignatureLength(usize),
+    InvalidHeader(u8),
+    UnsupportedAddressType(AddressType),
+    /// The path does not end with the change and index of an address.
+    InvalidPath,
+    Bip322(bip322::Bip322Error),
+    /// The device did not sign the proof of the address, the psbt of the
+    /// proof is returned with its signatures so far.
+    IncompleteProof(Box<Psbt>),
+    Device(Error),
+}
+
+impl std::fmt::Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSignatureLength(len) => write!(f, "Invalid signature length: {}", len),
+            Self::InvalidHeader(header) => write!(f, "Invalid signature header: {}", header),
+            Self::UnsupportedAddressType(address_type) => {
+                write!(f, "Unsupported address type: {:?}", address_type)
+            }
+            Self::InvalidPath => write!(f, "The path must end with the change and the index"),
+            Self::Bip322(e) => write!(f, "Failed to prove the address: {:?}", e),
+            Self::IncompleteProof(_) => write!(f, "The device did not sign the proof"),
+            Self::Device(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+/// Format of the signature of `signmessage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    Bip137,
+    Bip322,
+}
+
+/// Signs the message with the key at the path and returns the signature in
+/// base64, with the header of the address type for BIP-137.
+pub async fn sign_message(
+    device: &mut dyn CliDevice,
+    path: DerivationPath,
+    message: &[u8],
+    address_type: AddressType,
+    format: MessageFormat,
+) -> Result<String, MessageError> {
+    match format {
+        MessageFormat::Bip137 => {
+            let signature = device
+                .sign_message(path, message)
+                .await
+                .map_err(MessageError::Device)?;
+            let signature = BASE64_STANDARD
+                .decode(signature)
+                .map_err(|_| MessageError::InvalidSignatureLength(0))?;
+            encode_bip137_signature(&signature, address_type)
+        }
+        MessageFormat::Bip322 => prove_address(device, &path, message, address_type).await,
+    }
+}
+
+/// Returns the BIP-322 proof of the address at the path, signed by the
+/// device as a psbt: in the simple format for the segwit addresses, in the
+/// full one for the others.
+async fn prove_address(
+    device: &mut dyn CliDevice,
+    path: &DerivationPath,
+    message: &[u8],
+    address_type: AddressType,
+) -> Result<String, MessageError> {
+    let (account, change, index) = match path.as_ref() {
+        [account @ .., ChildNumber::Normal { index: change }, ChildNumber::Normal { index }]
+            if *change < 2 =>
+        {
+            (DerivationPath::from(account), *change == 1, *index)
+        }
+        _ => return Err(MessageError::InvalidPath),
+    };
+    let fingerprint = device
+        .get_master_fingerprint()
+        .await
+        .map_err(MessageError::Device)?;
+    let xpub = device
+        .get_extended_pubkey(account.clone(), false)
+        .await
+        .map_err(MessageError::Device)?;
+    let policy = singlesig_policy(address_type, (fingerprint, account), xpub);
+    let format = match address_type {
+        AddressType::Wit | AddressType::Tap => ProofFormat::Simple,
+        AddressType::Legacy | AddressType::ShWit => ProofFormat::Full,
+    };
+    let psbt = bip322::build_psbt(message, &policy, change, index, format)
+        .map_err(MessageError::Bip322)?;
+    let signed = device
+        .sign_psbt(psbt, Some(policy.clone()), None)
+        .await
+        .map_err(MessageError::Device)?;
+    match bip322::prove(signed, &policy, format).map_err(MessageError::Bip322)? {
+        Proof::Signed(proof) => Ok(proof),
+        Proof::Partial(psbt) => Err(MessageError::IncompleteProof(psbt)),
+    }
+}
+
+/// Returns the default single signature policy of the account key for the
+/// address type.
+pub fn singlesig_policy(address_type: AddressType, source: KeySource, xpub: Xpub) -> WalletPolicy {
+    let template = match address_type {
+        AddressType::Legacy => "pkh(@0/**)",
+        AddressType::ShWit => "sh(wpkh(@0/**))",
+        AddressType::Wit => "wpkh(@0/**)",
+        AddressType::Tap => "tr(@0/**)",
+    };
+    WalletPolicy::new(
+        String::new(),
+        Version::V2,
+        template.to_string(),
+        [(source, xpub)],
+    )
+}
+
+/// Encodes in base64 the 65 bytes compact signature returned by the device
+/// for a compressed p2pkh key, with the BIP-137 header of the address type.
+pub fn encode_bip137_signature(
+    signature: &[u8],
+    address_type: AddressType,
+) -> Result<String, MessageError> {
+    if signature.len() != 65 {
+        return Err(MessageError::InvalidSignatureLength(signature.len()));
+    }
+    // Header is 27 + recovery id, plus 4 for compressed keys.
+    let recovery_id = match signature[0] {
+        h @ 27..=30 => h - 27,
+        h @ 31..=34 => h - 31,
+        h => return Err(MessageError::InvalidHeader(h)),
+    };
+    let base = match address_type {
+        AddressType::Legacy => 31,
+        AddressType::ShWit => 35,
+        AddressType::Wit => 39,
+        AddressType::Tap => return Err(MessageError::UnsupportedAddressType(address_type)),
+    };
+    let mut encoded = signature.to_vec();
+    encoded[0] = base + recovery_id;
+    Ok(BASE64_STANDARD.encode(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use bhwi_async::{software::SoftwareSigner, Error as HWIError, HWI};
+    use bitcoin::{
+        address::NetworkUnchecked, bip32::Fingerprint, bip32::Xpriv, secp256k1::Secp256k1, Address,
+        Network,
+    };
+    use std::str::FromStr;
+
+    /// Device returning the psbts without signing them.
+    struct Unsigning(SoftwareSigner);
+
+    fn erase(e: HWIError<std::convert::Infallible, std::convert::Infallible>) -> Error {
+        match e {
+            HWIError::Interpreter(e) => HWIError::Interpreter(e),
+            HWIError::Transport(e) | HWIError::HttpClient(e) => match e {},
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl HWI for Unsigning {
+        type Error = Error;
+
+        async fn unlock(&mut self, network: Network) -> Result<(), Error> {
+            self.0.unlock(network).await.map_err(erase)
+        }
+
+        async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Error> {
+            self.0.get_master_fingerprint().await.map_err(erase)
+        }
+
+        async fn get_extended_pubkey(
+            &mut self,
+            path: DerivationPath,
+            display: bool,
+        ) -> Result<Xpub, Error> {
+            self.0
+                .get_extended_pubkey(path, display)
+                .await
+                .map_err(erase)
+        }
+
+        async fn register_wallet(
+            &mut self,
+            _policy: WalletPolicy,
+        ) -> Result<([u8; 32], [u8; 32]), Error> {
+            unimplemented!()
+        }
+
+        async fn sign_psbt(
+            &mut self,
+            psbt: Psbt,
+            _policy: Option<WalletPolicy>,
+            _hmac: Option<[u8; 32]>,
+        ) -> Result<Psbt, Error> {
+            Ok(psbt)
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl CliDevice for Unsigning {
+        async fn sign_message(
+            &mut self,
+            _path: DerivationPath,
+            _message: &[u8],
+        ) -> Result<String, Error> {
+            unimplemented!()
+        }
+
+        async fn display_address(
+            &mut self,
+            _path: DerivationPath,
+            _policy: Option<WalletPolicy>,
+            _hmac: Option<[u8; 32]>,
+        ) -> Result<Address<NetworkUnchecked>, Error> {
+            unimplemented!()
+        }
+
+        async fn verify_owned_address(
+            &mut self,
+            _address: Address<NetworkUnchecked>,
+            _policy: WalletPolicy,
+            _hmac: Option<[u8; 32]>,
+            _gap_limit: u32,
+        ) -> Result<(bool, u32), Error> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_proof() {
+        let mut device = Unsigning(SoftwareSigner::new(&[0x01; 32], Network::Testnet).unwrap());
+        let res = sign_message(
+            &mut device,
+            DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap(),
+            b"hello",
+            AddressType::Wit,
+            MessageFormat::Bip322,
+        )
+        .await;
+        assert!(matches!(res, Err(MessageError::IncompleteProof(_))));
+    }
+
+    #[test]
+    fn test_encode_bip137_signature() {
+        let mut signature = [0x11; 65];
+        signature[0] = 32;
+        let encoded = encode_bip137_signature(&signature, AddressType::Wit).unwrap();
+        assert_eq!(BASE64_STANDARD.decode(encoded).unwrap()[0], 40);
+        let encoded = encode_bip137_signature(&signature, AddressType::Legacy).unwrap();
+        assert_eq!(BASE64_STANDARD.decode(encoded).unwrap()[0], 32);
+        assert!(matches!(
+            encode_bip137_signature(&signature, AddressType::Tap),
+            Err(MessageError::UnsupportedAddressType(AddressType::Tap))
+        ));
+        assert!(matches!(
+            encode_bip137_signature(&signature[1..], AddressType::Wit),
+            Err(MessageError::InvalidSignatureLength(64))
+        ));
+    }
+
+    #[test]
+    fn test_singlesig_policy() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap());
+        let source = (xpriv.fingerprint(&secp), path);
+        assert_eq!(
+            singlesig_policy(AddressType::Wit, source.clone(), xpub),
+            WalletPolicy::new_singlesig(source.clone(), xpub).unwrap()
+        );
+        assert_eq!(
+            singlesig_policy(AddressType::Tap, source, xpub).descriptor_template,
+            "tr(@0/**)"
+        );
+        assert_eq!(
+            AddressType::from_path(&DerivationPath::from_str("m/49'/1'/0'/0/1").unwrap()),
+            Some(AddressType::ShWit)
+        );
+    }
+}
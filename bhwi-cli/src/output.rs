@@ -0,0 +1,254 @@
+//! Output schema of the `--json` mode, following the one of HWI so that
+//! bhwi can be used as an external signer by Bitcoin Core.
+
+use bhwi::common;
+use bitcoin::bip32::{Fingerprint, Xpub};
+use serde::Serialize;
+
+use crate::{DeviceInfo, Error};
+
+/// Error codes used by HWI.
+pub mod code {
+    pub const NO_DEVICE_TYPE: i32 = -1;
+    pub const MISSING_ARGUMENTS: i32 = -2;
+    pub const DEVICE_CONN_ERROR: i32 = -3;
+    pub const UNKNOWN_DEVICE_TYPE: i32 = -4;
+    pub const INVALID_TX: i32 = -5;
+    pub const NO_PASSWORD: i32 = -6;
+    pub const BAD_ARGUMENT: i32 = -7;
+    pub const NOT_IMPLEMENTED: i32 = -8;
+    pub const UNAVAILABLE_ACTION: i32 = -9;
+    pub const DEVICE_ALREADY_INIT: i32 = -10;
+    pub const DEVICE_ALREADY_UNLOCKED: i32 = -11;
+    pub const DEVICE_NOT_READY: i32 = -12;
+    pub const UNKNOWN_ERROR: i32 = -13;
+    pub const ACTION_CANCELED: i32 = -14;
+    pub const DEVICE_BUSY: i32 = -15;
+    pub const NEED_TO_BE_ROOT: i32 = -16;
+    pub const HELP_TEXT: i32 = -17;
+    pub const DEVICE_NOT_INITIALIZED: i32 = -18;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorResult {
+    pub error: String,
+    pub code: i32,
+}
+
+impl ErrorResult {
+    pub fn new(error: impl Into<String>, code: i32) -> Self {
+        Self {
+            error: error.into(),
+            code,
+        }
+    }
+
+    /// Process exit code of the error: the opposite of the HWI error code,
+    /// so that scripts can tell a canceled action (14) from a missing
+    /// device (3).
+    pub fn exit_code(&self) -> i32 {
+        self.code
+            .checked_neg()
+            .filter(|code| *code > 0)
+            .unwrap_or(1)
+    }
+}
+
+impl From<&Error> for ErrorResult {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::Transport(e) => Self::new(e.to_string(), code::DEVICE_CONN_ERROR),
+            Error::HttpClient(e) => Self::new(e.to_string(), code::DEVICE_CONN_ERROR),
+            Error::Interpreter(common::Error::AuthenticationRefused) => {
+                Self::new("Authentication refused", code::ACTION_CANCELED)
+            }
+            Error::Interpreter(common::Error::UserRefused) => {
+                Self::new("Refused on the device", code::ACTION_CANCELED)
+            }
+            Error::Interpreter(common::Error::DeviceLocked) => {
+                Self::new("The device is locked", code::DEVICE_NOT_READY)
+            }
+            Error::Interpreter(common::Error::NetworkMismatch) => Self::new(
+                "The device, a key or a path is for another network",
+                code::BAD_ARGUMENT,
+            ),
+            Error::Interpreter(common::Error::UnsupportedSighash(index)) => Self::new(
+                format!("The sighash type of the input {} is not supported", index),
+                code::BAD_ARGUMENT,
+            ),
+            Error::Interpreter(common::Error::UnusualPath(warning)) => Self::new(
+                format!("Unusual derivation path: {:?}", warning),
+                code::BAD_ARGUMENT,
+            ),
+            Error::Interpreter(common::Error::InvalidPolicy(e)) => Self::new(
+                format!("Invalid wallet policy: {:?}", e),
+                code::BAD_ARGUMENT,
+            ),
+            Error::Interpreter(common::Error::Cancelled) => {
+                Self::new("Cancelled", code::ACTION_CANCELED)
+            }
+            Error::Interpreter(common::Error::Timeout) => {
+                Self::new("The device did not answer in time", code::DEVICE_NOT_READY)
+            }
+            Error::Interpreter(common::Error::DeviceBusy) => Self::new(
+                "Another command is running on the device",
+                code::DEVICE_BUSY,
+            ),
+            Error::Interpreter(common::Error::UnsupportedCommand(command)) => Self::new(
+                format!("The device does not support {}", command),
+                code::UNAVAILABLE_ACTION,
+            ),
+            Error::Interpreter(common::Error::Device(message)) => {
+                Self::new(message.clone(), code::UNKNOWN_ERROR)
+            }
+            Error::Interpreter(e) => Self::new(format!("{:?}", e), code::UNKNOWN_ERROR),
+        }
+    }
+}
+
+/// Entry of the `enumerate` result, the device fields are replaced by
+/// the error if the device could not be accessed.
+#[derive(Debug, Serialize)]
+pub struct EnumerateEntry {
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub model: String,
+    pub path: String,
+    pub needs_pin_sent: bool,
+    pub needs_passphrase_sent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResult>,
+}
+
+impl EnumerateEntry {
+    pub fn new(info: &DeviceInfo, fingerprint: Result<Fingerprint, &Error>) -> Self {
+        let (fingerprint, error) = match fingerprint {
+            Ok(fg) => (Some(fg.to_string()), None),
+            Err(e) => (None, Some(ErrorResult::from(e))),
+        };
+        Self {
+            device_type: info.device_type.to_string(),
+            model: info.device_type.to_string(),
+            path: info.path.clone(),
+            // Pin and passphrase are entered on the device itself.
+            needs_pin_sent: false,
+            needs_passphrase_sent: false,
+            fingerprint,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct XpubResult {
+    pub xpub: String,
+}
+
+impl From<Xpub> for XpubResult {
+    fn from(xpub: Xpub) -> Self {
+        Self {
+            xpub: xpub.to_string(),
+        }
+    }
+}
+
+/// Result of `getdescriptors`, one descriptor per address type.
+#[derive(Debug, Default, Serialize)]
+pub struct DescriptorsResult {
+    pub receive: Vec<String>,
+    pub internal: Vec<String>,
+}
+
+/// Result of `registerwallet`, hex encoded.
+#[derive(Debug, Serialize)]
+pub struct RegisterWalletResult {
+    pub id: String,
+    pub hmac: String,
+}
+
+impl RegisterWalletResult {
+    pub fn new(id: [u8; 32], hmac: [u8; 32]) -> Self {
+        Self {
+            id: hex::encode(id),
+            hmac: hex::encode(hmac),
+        }
+    }
+}
+
+/// Result of `signtx`, the psbt is base64 encoded.
+#[derive(Debug, Serialize)]
+pub struct SignTxResult {
+    pub psbt: String,
+    pub signed: bool,
+}
+
+/// Result of `displayaddress`, with the change and the index of the
+/// address found in the wallet when verified.
+#[derive(Debug, Serialize)]
+pub struct AddressResult {
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+}
+
+/// Result of `signmessage`, the signature is base64 encoded.
+#[derive(Debug, Serialize)]
+pub struct SignMessageResult {
+    pub signature: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceType, Interface};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(ErrorResult::new("", code::ACTION_CANCELED).exit_code(), 14);
+        assert_eq!(ErrorResult::new("", code::DEVICE_CONN_ERROR).exit_code(), 3);
+        assert_eq!(ErrorResult::new("", 0).exit_code(), 1);
+    }
+
+    #[test]
+    fn test_enumerate_entry_schema() {
+        let info = DeviceInfo {
+            device_type: DeviceType::Ledger,
+            interface: Interface::Hid,
+            path: "/dev/hidraw0".to_string(),
+            vid: 0x2c97,
+            pid: 0x5011,
+        };
+        let entry = EnumerateEntry::new(&info, Ok(Fingerprint::from_str("f5acc2fd").unwrap()));
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            serde_json::json!({
+                "type": "ledger",
+                "model": "ledger",
+                "path": "/dev/hidraw0",
+                "needs_pin_sent": false,
+                "needs_passphrase_sent": false,
+                "fingerprint": "f5acc2fd",
+            })
+        );
+
+        let error = Error::Interpreter(common::Error::AuthenticationRefused);
+        let entry = EnumerateEntry::new(&info, Err(&error));
+        assert_eq!(
+            serde_json::to_value(&entry).unwrap(),
+            serde_json::json!({
+                "type": "ledger",
+                "model": "ledger",
+                "path": "/dev/hidraw0",
+                "needs_pin_sent": false,
+                "needs_passphrase_sent": false,
+                "error": "Authentication refused",
+                "code": code::ACTION_CANCELED,
+            })
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Reading and writing of PSBTs, so that the cli composes with
+//! `bitcoin-cli walletprocesspsbt` pipelines.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bhwi::{
+    ledger::{psbt, WalletPolicy},
+    psbt::{spent_utxo, summary, SummaryError},
+};
+use bitcoin::{FeeRate, Psbt, Weight};
+
+use crate::{
+    output::{code, ErrorResult},
+    CliDevice,
+};
+
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// Where to read the PSBT from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsbtSource {
+    /// Path to a file holding the binary or base64 PSBT, `-` is stdin.
+    File(PathBuf),
+    Stdin,
+    /// Base64 PSBT given as argument.
+    Base64(String),
+}
+
+impl FromStr for PsbtSource {
+    type Err = std::convert::Infallible;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            Ok(PsbtSource::Stdin)
+        } else if s.starts_with("cHNidP") {
+            Ok(PsbtSource::Base64(s.to_string()))
+        } else {
+            Ok(PsbtSource::File(PathBuf::from(s)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PsbtFormat {
+    #[default]
+    Base64,
+    Binary,
+}
+
+pub fn read_psbt(source: &PsbtSource) -> Result<Psbt, std::io::Error> {
+    let data = match source {
+        PsbtSource::File(path) => std::fs::read(path)?,
+        PsbtSource::Stdin => {
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+            data
+        }
+        PsbtSource::Base64(s) => s.as_bytes().to_vec(),
+    };
+    parse_psbt(&data)
+}
+
+/// Parses a binary or base64 PSBT v0 or v2, surrounding whitespaces of the
+/// base64 encoding are ignored.
+pub fn parse_psbt(data: &[u8]) -> Result<Psbt, std::io::Error> {
+    let res = if data.starts_with(PSBT_MAGIC) {
+        psbt::deserialize_psbt(data)
+    } else {
+        psbt::psbt_from_base64(std::str::from_utf8(data).map_err(invalid_data)?)
+    };
+    res.map_err(|e| invalid_data(format!("{:?}", e)))
+}
+
+/// Writes the PSBT to the file, or to stdout if no path is given.
+pub fn write_psbt(
+    psbt: &Psbt,
+    path: Option<&PathBuf>,
+    format: PsbtFormat,
+) -> Result<(), std::io::Error> {
+    let data = match format {
+        PsbtFormat::Binary => psbt.serialize(),
+        PsbtFormat::Base64 => {
+            let mut s = psbt.to_string();
+            if path.is_none() {
+                s.push('\n');
+            }
+            s.into_bytes()
+        }
+    };
+    match path {
+        Some(path) => std::fs::write(path, data),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()
+        }
+    }
+}
+
+/// Returns the fee rate of the psbt once signed. The unsigned transaction has
+/// no signatures, their weight is estimated for the single key scripts of the
+/// spent utxos, the inputs of the other scripts are counted without them.
+pub fn fee_rate(psbt: &Psbt) -> Result<FeeRate, SummaryError> {
+    let fee = summary(psbt)?.fee;
+    let mut weight = psbt.unsigned_tx.weight();
+    let mut segwit = false;
+    for (index, input) in psbt.inputs.iter().enumerate() {
+        let Some(utxo) = spent_utxo(psbt, index) else {
+            continue;
+        };
+        let script = &utxo.script_pubkey;
+        let (script_sig, witness) = if script.is_p2pkh() {
+            (107, 0)
+        } else if script.is_p2wpkh() {
+            (0, 108)
+        } else if script.is_p2tr() {
+            (0, 66)
+        } else if script.is_p2sh()
+            && input
+                .redeem_script
+                .as_ref()
+                .is_some_and(|script| script.is_p2wpkh())
+        {
+            (23, 108)
+        } else {
+            (0, 0)
+        };
+        segwit |= witness > 0;
+        weight += Weight::from_non_witness_data_size(script_sig)
+            + Weight::from_witness_data_size(witness);
+    }
+    if segwit {
+        // Marker and flag, and the empty witness of the legacy inputs.
+        weight += Weight::from_witness_data_size(2 + psbt.inputs.len() as u64);
+    }
+    Ok(fee / weight)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FeeRateError {
+    Summary(SummaryError),
+    /// The fee rate of the psbt, above the maximum.
+    Exceeded(FeeRate),
+}
+
+impl std::fmt::Display for FeeRateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FeeRateError::Summary(e) => write!(f, "Invalid psbt: {:?}", e),
+            FeeRateError::Exceeded(fee_rate) => write!(
+                f,
+                "Fee rate of {} sat/vB above the maximum",
+                fee_rate.to_sat_per_vb_ceil()
+            ),
+        }
+    }
+}
+
+/// Checks that the fee rate of the psbt does not exceed the maximum, in sat/vB.
+pub fn check_fee_rate(psbt: &Psbt, max_fee_rate: u64) -> Result<(), FeeRateError> {
+    let fee_rate = fee_rate(psbt).map_err(FeeRateError::Summary)?;
+    if fee_rate.to_sat_per_vb_ceil() > max_fee_rate {
+        return Err(FeeRateError::Exceeded(fee_rate));
+    }
+    Ok(())
+}
+
+/// Signs the psbt with the device once its fee rate is checked against the
+/// maximum in sat/vB, the signing path of the cli, of the daemon and of the
+/// bindings.
+pub async fn sign_psbt(
+    device: &mut dyn CliDevice,
+    unsigned: &Psbt,
+    policy: Option<WalletPolicy>,
+    hmac: Option<[u8; 32]>,
+    max_fee_rate: Option<u64>,
+) -> Result<Psbt, ErrorResult> {
+    if let Some(max_fee_rate) = max_fee_rate {
+        check_fee_rate(unsigned, max_fee_rate).map_err(|e| match e {
+            FeeRateError::Summary(_) => ErrorResult::new(e.to_string(), code::INVALID_TX),
+            FeeRateError::Exceeded(_) => ErrorResult::new(e.to_string(), code::BAD_ARGUMENT),
+        })?;
+    }
+    device
+        .sign_psbt(unsigned.clone(), policy, hmac)
+        .await
+        .map_err(|e| ErrorResult::from(&e))
+}
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PSBT: &str = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+
+    #[test]
+    fn test_psbt_roundtrip() {
+        let psbt = parse_psbt(PSBT.as_bytes()).unwrap();
+        assert_eq!(parse_psbt(&psbt.serialize()).unwrap(), psbt);
+        assert_eq!(parse_psbt(format!("{}\n", PSBT).as_bytes()).unwrap(), psbt);
+        assert!(parse_psbt(b"not a psbt").is_err());
+        assert_eq!(parse_psbt(&psbt::serialize_v2(&psbt)).unwrap(), psbt);
+    }
+
+    #[test]
+    fn test_fee_rate() {
+        let psbt = parse_psbt(PSBT.as_bytes()).unwrap();
+        // 301 sats for a 117 vB unsigned p2pkh spend, with a 107 bytes scriptSig.
+        assert_eq!(fee_rate(&psbt).unwrap(), FeeRate::from_sat_per_kwu(335));
+        assert_eq!(check_fee_rate(&psbt, 2), Ok(()));
+        assert_eq!(
+            check_fee_rate(&psbt, 1),
+            Err(FeeRateError::Exceeded(FeeRate::from_sat_per_kwu(335)))
+        );
+        let mut psbt = psbt;
+        psbt.inputs[0].non_witness_utxo = None;
+        assert_eq!(
+            check_fee_rate(&psbt, 2),
+            Err(FeeRateError::Summary(SummaryError::MissingUtxo(0)))
+        );
+    }
+
+    #[test]
+    fn test_psbt_source_from_str() {
+        assert_eq!(PsbtSource::from_str("-").unwrap(), PsbtSource::Stdin);
+        assert_eq!(
+            PsbtSource::from_str(PSBT).unwrap(),
+            PsbtSource::Base64(PSBT.to_string())
+        );
+        assert_eq!(
+            PsbtSource::from_str("tx.psbt").unwrap(),
+            PsbtSource::File(PathBuf::from("tx.psbt"))
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bhwi::runner::AsyncTransport;
+use bhwi::trezor::transport::{frame, Unframer, PACKET_SIZE};
+use bhwi::Event;
+use bhwi_async::{transport::Channel, HttpClient, Transport};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+
+/// Time the device has to detach after an app switch, the same device may be
+/// found again if it re-enumerates faster.
+const DETACH_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time the device has to reappear after its re-enumeration.
+const REOPEN_TIMEOUT: Duration = Duration::from_secs(10);
+const REOPEN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// HID channel over hidapi, used by the Ledger and Coldcard HID transports.
+pub struct HidChannel {
+    device: HidDevice,
+}
+
+impl HidChannel {
+    pub fn new(device: HidDevice) -> Self {
+        Self { device }
+    }
+}
+
+/// Returns the device of the same vendor and interface as the one opened.
+fn find_device<'a>(api: &'a HidApi, opened: &DeviceInfo) -> Option<&'a DeviceInfo> {
+    api.device_list().find(|info| {
+        info.vendor_id() == opened.vendor_id()
+            && info.usage_page() == opened.usage_page()
+            && info.interface_number() == opened.interface_number()
+    })
+}
+
+#[async_trait(?Send)]
+impl Channel for HidChannel {
+    async fn send(&self, data: &[u8]) -> Result<usize, std::io::Error> {
+        // hidapi expects the report id as first byte, devices use the report 0.
+        let mut report = Vec::with_capacity(data.len() + 1);
+        report.push(0x00);
+        report.extend_from_slice(data);
+        let written = self.device.write(&report).map_err(std::io::Error::other)?;
+        Ok(written.saturating_sub(1))
+    }
+
+    async fn receive(&mut self, data: &mut [u8]) -> Result<usize, std::io::Error> {
+        self.device.read(data).map_err(std::io::Error::other)
+    }
+
+    async fn sleep(&self, ms: u32) {
+        std::thread::sleep(Duration::from_millis(ms.into()));
+    }
+
+    /// Waits for the device to detach then polls for the device of the same
+    /// vendor and interface, its path and product id may have changed.
+    async fn reopen(&mut self) -> Result<(), std::io::Error> {
+        let opened = self
+            .device
+            .get_device_info()
+            .map_err(std::io::Error::other)?;
+        let mut api = HidApi::new().map_err(std::io::Error::other)?;
+        let start = Instant::now();
+        while start.elapsed() < DETACH_TIMEOUT
+            && api.device_list().any(|info| info.path() == opened.path())
+        {
+            std::thread::sleep(REOPEN_INTERVAL);
+            api.refresh_devices().map_err(std::io::Error::other)?;
+        }
+        let start = Instant::now();
+        loop {
+            if let Some(info) = find_device(&api, &opened) {
+                self.device = api.open_path(info.path()).map_err(std::io::Error::other)?;
+                return Ok(());
+            }
+            if start.elapsed() > REOPEN_TIMEOUT {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "device did not reappear",
+                ));
+            }
+            std::thread::sleep(REOPEN_INTERVAL);
+            api.refresh_devices().map_err(std::io::Error::other)?;
+        }
+    }
+}
+
+/// Http client relaying the Jade requests to its pin server with curl,
+/// so that the cli does not depend on a tls stack.
+#[derive(Default)]
+pub struct PinServerClient;
+
+#[async_trait(?Send)]
+impl HttpClient for PinServerClient {
+    type Error = std::io::Error;
+
+    async fn request(&self, url: &str, request: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        let mut child = Command::new("curl")
+            .args(["--silent", "--fail", "--request", "POST"])
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-", url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(request)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "pin server request failed: {}",
+                output.status
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+pub const SPECULOS_DEFAULT_ADDRESS: &str = "127.0.0.1:9999";
+
+/// Transport to the apdu server of Speculos, the Ledger emulator.
+/// Apdus are prefixed by their length on 4 bytes, the responses are
+/// prefixed by their length without the status word.
+pub struct SpeculosTransport {
+    stream: TcpStream,
+}
+
+impl SpeculosTransport {
+    pub fn connect(address: &str) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            stream: TcpStream::connect(address)?,
+        })
+    }
+}
+
+impl SpeculosTransport {
+    fn exchange_apdu(&self, command: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        let mut stream = &self.stream;
+        let mut request = Vec::with_capacity(command.len() + 4);
+        request.extend_from_slice(&(command.len() as u32).to_be_bytes());
+        request.extend_from_slice(command);
+        stream.write_all(&request)?;
+
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length)?;
+        let mut response = vec![0u8; u32::from_be_bytes(length) as usize + 2];
+        stream.read_exact(&mut response)?;
+        Ok(response)
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for SpeculosTransport {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.exchange_apdu(command)
+    }
+}
+
+/// Allows to run the Ledger commands with `bhwi::runner::run`.
+impl AsyncTransport for SpeculosTransport {
+    type Error = std::io::Error;
+
+    async fn exchange(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        self.exchange_apdu(data)
+    }
+}
+
+pub const TREZOR_EMULATOR_DEFAULT_ADDRESS: &str = "127.0.0.1:21324";
+
+/// Transport to the Trezor emulator, as run by trezor-user-env, over its
+/// udp port: each datagram is a report of the HID framing of the messages.
+pub struct TrezorEmulatorTransport {
+    socket: UdpSocket,
+}
+
+impl TrezorEmulatorTransport {
+    /// Connects to the emulator, which must answer a ping.
+    pub fn connect(address: &str) -> Result<Self, std::io::Error> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address"))?;
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        socket.send(b"PINGPING")?;
+        let mut pong = [0u8; 8];
+        if socket.recv(&mut pong)? != 8 || &pong != b"PONGPONG" {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Trezor emulator not answering",
+            ));
+        }
+        // The user may take long to confirm on the device.
+        socket.set_read_timeout(None)?;
+        Ok(Self { socket })
+    }
+
+    fn exchange_message(&self, message: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        for report in frame(message) {
+            self.socket.send(&report)?;
+        }
+        let mut unframer = Unframer::new();
+        let mut report = [0u8; PACKET_SIZE];
+        loop {
+            let len = self.socket.recv(&mut report)?;
+            if let Some(answer) = unframer.push(&report[..len]).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+            })? {
+                return Ok(answer);
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for TrezorEmulatorTransport {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.exchange_message(command)
+    }
+}
+
+/// Handler of the events of the interpreter of the running command.
+pub type EventHandler = Box<dyn FnMut(&Event)>;
+
+/// Transport wrapper printing the exchanged frames on stderr.
+/// Encrypted frames are redacted, only their length is printed.
+pub struct TraceTransport<T> {
+    inner: T,
+    enabled: bool,
+    /// The response ends with an apdu status word.
+    status_word: bool,
+    events: Option<EventHandler>,
+}
+
+impl<T> TraceTransport<T> {
+    pub fn new(inner: T, enabled: bool, status_word: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            status_word,
+            events: None,
+        }
+    }
+
+    /// Passes the events of the interpreters to the handler, if any.
+    pub fn with_events(mut self, events: Option<EventHandler>) -> Self {
+        self.events = events;
+        self
+    }
+}
+
+fn trace_frame(data: &[u8], encrypted: bool) -> String {
+    if encrypted {
+        format!("<redacted {} bytes>", data.len())
+    } else {
+        hex::encode(data)
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> Transport for TraceTransport<T> {
+    type Error = T::Error;
+
+    async fn exchange(&mut self, command: &[u8], encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        if !self.enabled {
+            return self.inner.exchange(command, encrypted).await;
+        }
+        eprintln!("=> {}", trace_frame(command, encrypted));
+        let start = std::time::Instant::now();
+        let res = self.inner.exchange(command, encrypted).await;
+        let elapsed = start.elapsed().as_millis();
+        match &res {
+            Ok(response) if self.status_word && response.len() >= 2 => {
+                let (data, sw) = response.split_at(response.len() - 2);
+                eprintln!(
+                    "<= {} sw={} ({}ms)",
+                    trace_frame(data, encrypted),
+                    hex::encode(sw),
+                    elapsed
+                );
+            }
+            Ok(response) => eprintln!("<= {} ({}ms)", trace_frame(response, encrypted), elapsed),
+            Err(_) => eprintln!("<= error ({}ms)", elapsed),
+        }
+        res
+    }
+
+    async fn send(&mut self, command: &[u8], encrypted: bool) -> Result<(), Self::Error> {
+        if self.enabled {
+            eprintln!("=> {}", trace_frame(command, encrypted));
+        }
+        self.inner.send(command, encrypted).await
+    }
+
+    async fn reconnect(&mut self) -> Result<(), Self::Error> {
+        if self.enabled {
+            eprintln!("-- reconnecting");
+        }
+        self.inner.reconnect().await
+    }
+
+    fn notify(&mut self, event: &Event) {
+        if self.enabled {
+            eprintln!("-- {:?}", event);
+        }
+        if let Some(events) = &mut self.events {
+            events(event);
+        }
+        self.inner.notify(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trezor_emulator_transport() {
+        use bhwi::trezor::proto;
+
+        let emulator = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = emulator.local_addr().unwrap().to_string();
+        let features = proto::message(proto::FEATURES, &[0x0a; 100]);
+        let reply = features.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; PACKET_SIZE];
+            let (_, peer) = emulator.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..8], b"PINGPING");
+            emulator.send_to(b"PONGPONG", peer).unwrap();
+            let (len, _) = emulator.recv_from(&mut buf).unwrap();
+            assert_eq!(
+                &buf[..len],
+                frame(&proto::message(proto::INITIALIZE, &[]))[0]
+            );
+            for report in frame(&reply) {
+                emulator.send_to(&report, peer).unwrap();
+            }
+        });
+        let transport = TrezorEmulatorTransport::connect(&address).unwrap();
+        assert_eq!(
+            transport
+                .exchange_message(&proto::message(proto::INITIALIZE, &[]))
+                .unwrap(),
+            features
+        );
+    }
+
+    #[test]
+    fn test_trace_frame_redacts_encrypted() {
+        assert_eq!(trace_frame(&[0xe0, 0x01], false), "e001");
+        assert_eq!(trace_frame(&[0xe0, 0x01], true), "<redacted 2 bytes>");
+    }
+}
@@ -0,0 +1,74 @@
+//! Detection of the devices connected and disconnected between two
+//! enumerations.
+
+use serde::Serialize;
+
+use crate::DeviceInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceEvent {
+    pub event: EventKind,
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub path: String,
+}
+
+impl DeviceEvent {
+    fn new(event: EventKind, info: &DeviceInfo) -> Self {
+        Self {
+            event,
+            device_type: info.device_type.to_string(),
+            path: info.path.clone(),
+        }
+    }
+}
+
+/// Returns the events turning the previous list of devices into the next one,
+/// devices are identified by their path.
+pub fn diff_devices(previous: &[DeviceInfo], next: &[DeviceInfo]) -> Vec<DeviceEvent> {
+    let disconnected = previous
+        .iter()
+        .filter(|info| !next.iter().any(|other| other.path == info.path))
+        .map(|info| DeviceEvent::new(EventKind::Disconnected, info));
+    let connected = next
+        .iter()
+        .filter(|info| !previous.iter().any(|other| other.path == info.path))
+        .map(|info| DeviceEvent::new(EventKind::Connected, info));
+    disconnected.chain(connected).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceType, Interface};
+
+    fn device(device_type: DeviceType, path: &str) -> DeviceInfo {
+        DeviceInfo {
+            device_type,
+            interface: Interface::Hid,
+            path: path.to_string(),
+            vid: 0,
+            pid: 0,
+        }
+    }
+
+    #[test]
+    fn test_diff_devices() {
+        let ledger = device(DeviceType::Ledger, "/dev/hidraw0");
+        let coldcard = device(DeviceType::Coldcard, "/dev/hidraw1");
+        let events = diff_devices(&[ledger.clone()], &[coldcard.clone()]);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event, EventKind::Disconnected);
+        assert_eq!(events[0].path, "/dev/hidraw0");
+        assert_eq!(events[1].event, EventKind::Connected);
+        assert_eq!(events[1].device_type, "coldcard");
+        assert!(diff_devices(&[ledger.clone()], &[ledger]).is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+//! Drives the binary with the arguments Bitcoin Core gives to its -signer,
+//! against a mock Ledger served as Speculos serves its apdus.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+
+use std::str::FromStr;
+
+use bhwi::{psbt::policy_input, wallet::WalletPolicy};
+use bhwi_async::{mock::MockLedger, Transport};
+use bitcoin::{
+    absolute::LockTime, bip32::DerivationPath, transaction::Version, Address, Amount,
+    CompressedPublicKey, Network, OutPoint, Psbt, Sequence, Transaction, TxIn, TxOut,
+};
+use serde_json::Value;
+
+const SEED: [u8; 32] = [0x01; 32];
+
+/// Serves the mock on a local port, one thread per connection, and returns
+/// the -signer command of Bitcoin Core using it.
+fn signer(mock: MockLedger) -> Vec<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mock = Arc::new(Mutex::new(mock));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mock = mock.clone();
+            std::thread::spawn(move || serve(stream.unwrap(), &mock));
+        }
+    });
+    vec![
+        "--json".to_string(),
+        "--emulator".to_string(),
+        format!("speculos:{}", address),
+    ]
+}
+
+fn serve(mut stream: TcpStream, mock: &Mutex<MockLedger>) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut length = [0u8; 4];
+    while stream.read_exact(&mut length).is_ok() {
+        let mut apdu = vec![0u8; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut apdu).unwrap();
+        let response = runtime
+            .block_on(mock.lock().unwrap().exchange(&apdu, false))
+            .unwrap();
+        let data_length = (response.len() - 2) as u32;
+        stream.write_all(&data_length.to_be_bytes()).unwrap();
+        stream.write_all(&response).unwrap();
+    }
+}
+
+fn run(signer: &[String], args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bhwi"))
+        .args(signer)
+        .args(args)
+        .env(
+            "XDG_CONFIG_HOME",
+            std::env::temp_dir().join("bhwi-signer-test"),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn json(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+        panic!(
+            "{}: {} {}",
+            e,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    })
+}
+
+/// Psbt spending an output of the default policy of the account.
+fn psbt(policy: &WalletPolicy) -> Psbt {
+    let (script_pubkey, input) = policy_input(policy, false, 0).unwrap();
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(90_000),
+            script_pubkey: policy.derive_script(true, 0).unwrap(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+    psbt.inputs[0] = input;
+    psbt.inputs[0].witness_utxo = Some(TxOut {
+        value: Amount::from_sat(100_000),
+        script_pubkey,
+    });
+    psbt
+}
+
+#[test]
+fn test_core_signer_commands() {
+    let mock = MockLedger::new(&SEED, Network::Testnet);
+    let fingerprint = mock.fingerprint().to_string();
+    let account = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+    let policy =
+        WalletPolicy::new_singlesig((mock.fingerprint(), account.clone()), mock.xpub(&account))
+            .unwrap();
+    let key = CompressedPublicKey(
+        mock.xpub(&DerivationPath::from_str("m/84'/1'/0'/0/5").unwrap())
+            .public_key,
+    );
+    let signer = signer(mock);
+
+    let output = run(&signer, &["enumerate"], "");
+    assert!(output.status.success());
+    let devices = json(&output);
+    assert_eq!(devices[0]["fingerprint"], fingerprint.as_str());
+    assert_eq!(devices[0]["type"], "ledger");
+
+    // Bitcoin Core passes the chain, the results are json without --json.
+    let output = run(
+        &signer[1..],
+        &[
+            "--fingerprint",
+            &fingerprint,
+            "--chain",
+            "test",
+            "getdescriptors",
+            "--account",
+            "0",
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    let descriptors = json(&output);
+    assert_eq!(descriptors["receive"].as_array().unwrap().len(), 4);
+    assert_eq!(descriptors["internal"].as_array().unwrap().len(), 4);
+    assert!(descriptors["receive"][2]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("wpkh([{}/84h/1h/0h]tpub", fingerprint)));
+
+    let output = run(
+        &signer[1..],
+        &[
+            "--fingerprint",
+            &fingerprint,
+            "--chain",
+            "test",
+            "displayaddress",
+            "--desc",
+            "wpkh([00000000/84h/1h/0h/0/0]0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c)",
+        ],
+        "",
+    );
+    assert!(!output.status.success());
+    assert_eq!(json(&output)["error"], "Descriptor of another device");
+
+    let desc = format!("wpkh([{}/84h/1h/0h/0/5]{})", fingerprint, key);
+    let desc = format!(
+        "{}#{}",
+        desc,
+        bhwi::wallet::descriptor_checksum(&desc).unwrap()
+    );
+    let output = run(
+        &signer[1..],
+        &[
+            "--fingerprint",
+            &fingerprint,
+            "--chain",
+            "test",
+            "displayaddress",
+            "--desc",
+            &desc,
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        json(&output)["address"],
+        Address::p2wpkh(&key, Network::Testnet).to_string()
+    );
+
+    // The psbt is written to stdin with the subcommand.
+    let output = run(
+        &signer[1..],
+        &["--stdin", "--fingerprint", &fingerprint, "--chain", "test"],
+        "signtx cHNidP8BAAoCAAAAAAAAAAAA",
+    );
+    assert!(!output.status.success());
+    assert_eq!(json(&output)["code"], -5);
+
+    let output = run(
+        &signer[1..],
+        &["--stdin", "--fingerprint", &fingerprint, "--chain", "test"],
+        &format!("signtx {}", psbt(&policy)),
+    );
+    assert!(output.status.success());
+    let signed = Psbt::from_str(json(&output)["psbt"].as_str().unwrap()).unwrap();
+    assert_eq!(signed.inputs[0].partial_sigs.len(), 1);
+}
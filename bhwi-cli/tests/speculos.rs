@@ -0,0 +1,177 @@
+//! Integration tests against the Ledger Bitcoin app running in Speculos.
+//!
+//! Start Speculos with the default seed and the testnet app, e.g.
+//! `speculos --model nanosp --display headless apps/btc-test.elf`,
+//! then run `cargo test -p bhwi-cli --test speculos -- --ignored`.
+//! The address of the apdu server is read from `SPECULOS_ADDRESS`,
+//! default is 127.0.0.1:9999.
+
+use std::str::FromStr;
+
+use bhwi::{
+    ledger::{
+        apdu::ApduCommand, wallet::Version, LedgerCommand, LedgerError, LedgerInterpreter,
+        LedgerResponse, PartialSignature, WalletPolicy,
+    },
+    runner::run,
+};
+use bhwi_cli::{open, transport::SpeculosTransport, Emulator};
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{ChildNumber, DerivationPath, Fingerprint},
+    hashes::{sha256d, Hash},
+    key::TapTweak,
+    psbt::Psbt,
+    secp256k1::{Message, Secp256k1},
+    sighash::{Prevouts, SighashCache},
+    transaction::Version as TxVersion,
+    Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+/// Master fingerprint of the Speculos default seed.
+const SPECULOS_FINGERPRINT: &str = "f5acc2fd";
+
+fn address() -> String {
+    std::env::var("SPECULOS_ADDRESS").unwrap_or_else(|_| "127.0.0.1:9999".to_string())
+}
+
+fn emulator() -> Emulator {
+    Emulator::Speculos(address())
+}
+
+#[tokio::test]
+#[ignore = "requires a running Speculos"]
+async fn test_speculos_get_master_fingerprint() {
+    let mut device = open(&emulator().device_info(), Network::Testnet, false)
+        .await
+        .unwrap();
+    let fingerprint = device.get_master_fingerprint().await.unwrap();
+    assert_eq!(fingerprint.to_string(), SPECULOS_FINGERPRINT);
+}
+
+#[tokio::test]
+#[ignore = "requires a running Speculos"]
+async fn test_speculos_get_extended_pubkey() {
+    let mut device = open(&emulator().device_info(), Network::Testnet, false)
+        .await
+        .unwrap();
+    let parent = device
+        .get_extended_pubkey(DerivationPath::from_str("m/84'/1'").unwrap(), false)
+        .await
+        .unwrap();
+    let xpub = device
+        .get_extended_pubkey(DerivationPath::from_str("m/84'/1'/0'").unwrap(), false)
+        .await
+        .unwrap();
+    assert_eq!(xpub.depth, 3);
+    assert_eq!(xpub.parent_fingerprint, parent.fingerprint());
+}
+
+/// The transaction must be approved on the emulator, e.g. with its web interface.
+#[tokio::test]
+#[ignore = "requires a running Speculos"]
+async fn test_speculos_sign_psbt() {
+    let secp = Secp256k1::verification_only();
+    let transport = SpeculosTransport::connect(&address()).unwrap();
+    let fingerprint = Fingerprint::from_str(SPECULOS_FINGERPRINT).unwrap();
+    let path = DerivationPath::from_str("m/86'/1'/0'").unwrap();
+    let xpub = match run(
+        Ledger::default(),
+        LedgerCommand::GetXpub {
+            path: path.clone(),
+            display: false,
+        },
+        &transport,
+    )
+    .await
+    .unwrap()
+    {
+        LedgerResponse::Xpub(xpub) => xpub,
+        _ => panic!("expected an xpub"),
+    };
+    let key = |change: u32, index: u32| {
+        let child = [
+            ChildNumber::from_normal_idx(change).unwrap(),
+            ChildNumber::from_normal_idx(index).unwrap(),
+        ];
+        let (key, _) = xpub
+            .derive_pub(&secp, &child)
+            .unwrap()
+            .public_key
+            .x_only_public_key();
+        (key, (fingerprint, path.extend(child)))
+    };
+
+    let (input_key, input_origin) = key(0, 0);
+    let utxo = TxOut {
+        value: Amount::from_sat(100_000),
+        script_pubkey: ScriptBuf::new_p2tr(&secp, input_key, None),
+    };
+    let (change_key, change_origin) = key(1, 0);
+    let tx = Transaction {
+        version: TxVersion::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(
+                Txid::from_raw_hash(sha256d::Hash::hash(b"speculos")),
+                0,
+            ),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(90_000),
+            script_pubkey: ScriptBuf::new_p2tr(&secp, change_key, None),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+    psbt.inputs[0].witness_utxo = Some(utxo.clone());
+    psbt.inputs[0].tap_internal_key = Some(input_key);
+    psbt.inputs[0]
+        .tap_key_origins
+        .insert(input_key, (Vec::new(), input_origin));
+    psbt.outputs[0].tap_internal_key = Some(change_key);
+    psbt.outputs[0]
+        .tap_key_origins
+        .insert(change_key, (Vec::new(), change_origin));
+
+    let policy = WalletPolicy::new(
+        String::new(),
+        Version::V2,
+        "tr(@0/**)".to_string(),
+        [((fingerprint, path.clone()), xpub)],
+    );
+    let signatures = match run(
+        Ledger::default(),
+        LedgerCommand::SignPsbt {
+            psbt: Box::new(psbt.clone()),
+            policy,
+            hmac: None,
+        },
+        &transport,
+    )
+    .await
+    .unwrap()
+    {
+        LedgerResponse::Signatures(signatures) => signatures,
+        _ => panic!("expected signatures"),
+    };
+    assert_eq!(signatures.len(), 1);
+    let (index, PartialSignature::TapScriptSig(_, None, signature)) = &signatures[0] else {
+        panic!("expected a key path signature");
+    };
+    assert_eq!(*index, 0);
+    let sighash = SighashCache::new(&psbt.unsigned_tx)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&[utxo]), signature.sighash_type)
+        .unwrap();
+    let (output_key, _) = input_key.tap_tweak(&secp, None);
+    secp.verify_schnorr(
+        &signature.signature,
+        &Message::from(sighash),
+        &output_key.to_x_only_public_key(),
+    )
+    .unwrap();
+}
@@ -0,0 +1,268 @@
+//! Integration tests against the Trezor emulator, as run by trezor-user-env.
+//!
+//! The harness initializes the emulator, wipes it and loads the known
+//! mnemonic, then drives the interactive SignTx flow. The buttons are
+//! confirmed with the decisions of the debug link, on the port following the
+//! one of the emulator, so the emulator must be a debug build, e.g.
+//! `trezor-emu-core-v2.8.1` of trezor-user-env. The tests share the
+//! emulator, run them one at a time with
+//! `cargo test -p bhwi-cli --test trezor -- --ignored --test-threads=1`.
+//! The address of the emulator is read from `TREZOR_EMULATOR_ADDRESS`,
+//! default is 127.0.0.1:21324.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bhwi::trezor::{
+    proto::{self, Writer},
+    transport::{frame, Unframer, PACKET_SIZE},
+};
+use bhwi_async::{software::SoftwareSigner, Transport, Trezor, HWI};
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{ChildNumber, DerivationPath},
+    hashes::{sha256d, Hash},
+    key::TapTweak,
+    psbt::Psbt,
+    secp256k1::{Message, Secp256k1},
+    sighash::{Prevouts, SighashCache},
+    transaction::Version as TxVersion,
+    Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+/// Mnemonic of the test suites of Trezor.
+const MNEMONIC: &str = "all all all all all all all all all all all all";
+
+const WIPE_DEVICE: u16 = 5;
+const LOAD_DEVICE: u16 = 13;
+const DEBUG_LINK_DECISION: u16 = 100;
+/// `DebugButton.YES` of the decisions.
+const DEBUG_BUTTON_YES: u64 = 1;
+
+fn address() -> String {
+    std::env::var("TREZOR_EMULATOR_ADDRESS").unwrap_or_else(|_| "127.0.0.1:21324".to_string())
+}
+
+/// Emulator confirming on its screen every button request the host
+/// acknowledges, as a user would.
+struct Emulator {
+    link: UdpSocket,
+    debug: UdpSocket,
+}
+
+impl Emulator {
+    fn connect(address: &str) -> std::io::Result<Self> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address"))?;
+        let debug = SocketAddr::new(address.ip(), address.port() + 1);
+        Ok(Self {
+            link: Self::open(address)?,
+            debug: Self::open(debug)?,
+        })
+    }
+
+    fn open(address: SocketAddr) -> std::io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(address)?;
+        socket.set_read_timeout(Some(Duration::from_secs(30)))?;
+        socket.send(b"PINGPING")?;
+        let mut pong = [0u8; 8];
+        if socket.recv(&mut pong)? != 8 || &pong != b"PONGPONG" {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Trezor emulator not answering",
+            ));
+        }
+        Ok(socket)
+    }
+
+    fn write(socket: &UdpSocket, message: &[u8]) -> std::io::Result<()> {
+        for report in frame(message) {
+            socket.send(&report)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> std::io::Result<Vec<u8>> {
+        let mut unframer = Unframer::new();
+        let mut report = [0u8; PACKET_SIZE];
+        loop {
+            let len = self.link.recv(&mut report)?;
+            if let Some(message) = unframer.push(&report[..len]).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+            })? {
+                return Ok(message);
+            }
+        }
+    }
+
+    fn exchange_message(&self, message: &[u8]) -> std::io::Result<Vec<u8>> {
+        Self::write(&self.link, message)?;
+        if matches!(proto::parse_message(message), Ok((proto::BUTTON_ACK, _))) {
+            let decision = Writer::new().uint(1, DEBUG_BUTTON_YES).finish();
+            Self::write(&self.debug, &proto::message(DEBUG_LINK_DECISION, &decision))?;
+        }
+        self.read()
+    }
+
+    /// Sends the message, confirming the buttons, and returns the type of the
+    /// final answer.
+    fn call(&self, message: &[u8]) -> u16 {
+        let mut answer = self.exchange_message(message).unwrap();
+        loop {
+            let (msg_type, _) = proto::parse_message(&answer).unwrap();
+            if msg_type != proto::BUTTON_REQUEST {
+                return msg_type;
+            }
+            answer = self
+                .exchange_message(&proto::message(proto::BUTTON_ACK, &[]))
+                .unwrap();
+        }
+    }
+
+    /// Wipes the emulator and loads the mnemonic, without pin nor passphrase.
+    fn load(&self, mnemonic: &str) {
+        assert_eq!(
+            self.call(&proto::message(proto::INITIALIZE, &[])),
+            proto::FEATURES
+        );
+        assert_eq!(self.call(&proto::message(WIPE_DEVICE, &[])), proto::SUCCESS);
+        assert_eq!(
+            self.call(&proto::message(proto::INITIALIZE, &[])),
+            proto::FEATURES
+        );
+        let load = Writer::new()
+            .string(1, mnemonic)
+            .bool(4, false)
+            .string(6, "bhwi")
+            .finish();
+        assert_eq!(
+            self.call(&proto::message(LOAD_DEVICE, &load)),
+            proto::SUCCESS
+        );
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for Emulator {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        self.exchange_message(command)
+    }
+}
+
+/// Loads the mnemonic on the emulator, and returns it with the software
+/// signer of the same mnemonic.
+async fn setup() -> (Trezor<Emulator>, SoftwareSigner) {
+    let emulator = Emulator::connect(&address()).unwrap();
+    emulator.load(MNEMONIC);
+    let mut trezor = Trezor::new(emulator).with_network(Network::Testnet);
+    trezor.unlock(Network::Testnet).await.unwrap();
+    let signer = SoftwareSigner::from_mnemonic(MNEMONIC, "", Network::Testnet).unwrap();
+    (trezor, signer)
+}
+
+#[tokio::test]
+#[ignore = "requires a running Trezor emulator"]
+async fn test_trezor_load_device() {
+    let (mut trezor, mut signer) = setup().await;
+    assert_eq!(
+        trezor.get_master_fingerprint().await.unwrap(),
+        signer.get_master_fingerprint().await.unwrap()
+    );
+    let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+    assert_eq!(
+        trezor
+            .get_extended_pubkey(path.clone(), false)
+            .await
+            .unwrap(),
+        signer.get_extended_pubkey(path, false).await.unwrap()
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires a running Trezor emulator"]
+async fn test_trezor_sign_tx() {
+    let secp = Secp256k1::verification_only();
+    let (mut trezor, mut signer) = setup().await;
+    let fingerprint = signer.get_master_fingerprint().await.unwrap();
+    let path = DerivationPath::from_str("m/86'/1'/0'").unwrap();
+    let xpub = signer
+        .get_extended_pubkey(path.clone(), false)
+        .await
+        .unwrap();
+    let key = |change: u32, index: u32| {
+        let child = [
+            ChildNumber::from_normal_idx(change).unwrap(),
+            ChildNumber::from_normal_idx(index).unwrap(),
+        ];
+        let (key, _) = xpub
+            .derive_pub(&secp, &child)
+            .unwrap()
+            .public_key
+            .x_only_public_key();
+        (key, (fingerprint, path.extend(child)))
+    };
+
+    let (input_key, input_origin) = key(0, 0);
+    let utxo = TxOut {
+        value: Amount::from_sat(100_000),
+        script_pubkey: ScriptBuf::new_p2tr(&secp, input_key, None),
+    };
+    let (change_key, change_origin) = key(1, 0);
+    let (external_key, _) = key(0, 1);
+    let tx = Transaction {
+        version: TxVersion::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_raw_hash(sha256d::Hash::hash(b"trezor")), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![
+            TxOut {
+                value: Amount::from_sat(40_000),
+                script_pubkey: ScriptBuf::new_p2tr(&secp, external_key, None),
+            },
+            TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: ScriptBuf::new_p2tr(&secp, change_key, None),
+            },
+        ],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+    psbt.inputs[0].witness_utxo = Some(utxo.clone());
+    psbt.inputs[0].tap_internal_key = Some(input_key);
+    psbt.inputs[0]
+        .tap_key_origins
+        .insert(input_key, (Vec::new(), input_origin));
+    psbt.outputs[1].tap_internal_key = Some(change_key);
+    psbt.outputs[1]
+        .tap_key_origins
+        .insert(change_key, (Vec::new(), change_origin));
+
+    let signed = trezor.sign_psbt(psbt.clone(), None, None).await.unwrap();
+    let signature = signed.inputs[0]
+        .tap_key_sig
+        .expect("expected a key path signature");
+    let sighash = SighashCache::new(&psbt.unsigned_tx)
+        .taproot_key_spend_signature_hash(0, &Prevouts::All(&[utxo]), signature.sighash_type)
+        .unwrap();
+    let (output_key, _) = input_key.tap_tweak(&secp, None);
+    secp.verify_schnorr(
+        &signature.signature,
+        &Message::from(sighash),
+        &output_key.to_x_only_public_key(),
+    )
+    .unwrap();
+}
@@ -0,0 +1,169 @@
+/*
+ * C bindings to bhwi, the Bitcoin Hardware Wallet Interface.
+ *
+ * Functions return BHWI_OK or one of the HWI error codes below, the message
+ * of the last error of the calling thread is given by bhwi_last_error.
+ * Buffers written by the library are released with bhwi_buffer_free and
+ * devices with bhwi_device_free.
+ */
+
+#ifndef BHWI_H
+#define BHWI_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Version of the ABI this header describes. */
+#define BHWI_ABI_VERSION 1
+
+#define BHWI_OK 0
+/* The library does not implement the ABI version of the caller. */
+#define BHWI_ABI_MISMATCH -100
+#define BHWI_NO_DEVICE_TYPE -1
+#define BHWI_MISSING_ARGUMENTS -2
+#define BHWI_DEVICE_CONN_ERROR -3
+#define BHWI_UNKNOWN_DEVICE_TYPE -4
+#define BHWI_INVALID_TX -5
+#define BHWI_NO_PASSWORD -6
+#define BHWI_BAD_ARGUMENT -7
+#define BHWI_NOT_IMPLEMENTED -8
+#define BHWI_UNAVAILABLE_ACTION -9
+#define BHWI_DEVICE_ALREADY_INIT -10
+#define BHWI_DEVICE_ALREADY_UNLOCKED -11
+#define BHWI_DEVICE_NOT_READY -12
+#define BHWI_UNKNOWN_ERROR -13
+#define BHWI_ACTION_CANCELED -14
+#define BHWI_DEVICE_BUSY -15
+#define BHWI_NEED_TO_BE_ROOT -16
+#define BHWI_HELP_TEXT -17
+#define BHWI_DEVICE_NOT_INITIALIZED -18
+
+/* Events reported to the callback registered on a device, between the
+ * start and the end of every command. Unknown events must be ignored. */
+#define BHWI_EVENT_COMMAND_STARTED 1
+#define BHWI_EVENT_CONFIRM_ON_DEVICE 2
+#define BHWI_EVENT_COMMAND_FINISHED 3
+#define BHWI_EVENT_INPUT_SIGNED 4
+#define BHWI_EVENT_AWAITING_UNLOCK 5
+#define BHWI_EVENT_RETRYING 6
+#define BHWI_EVENT_UNUSUAL_PATH 7
+#define BHWI_EVENT_REENUMERATING 8
+
+/* Unlocked device. */
+typedef struct BhwiDevice BhwiDevice;
+
+/* Bytes allocated by the library, strings are not nul terminated. */
+typedef struct {
+    uint8_t *data;
+    size_t len;
+} BhwiBuffer;
+
+/* ABI version implemented by the library, to compare with BHWI_ABI_VERSION
+ * when the library is loaded dynamically. */
+uint32_t bhwi_abi_version(void);
+
+/* Version of the library, a static string. */
+const char *bhwi_version(void);
+
+/* JSON list of the connected devices: [{"type": "ledger", "path": "..."}]. */
+int32_t bhwi_enumerate(BhwiBuffer *out);
+
+/* Opens and unlocks the device at the path, either a path returned by
+ * bhwi_enumerate or an emulator like "speculos:127.0.0.1:9999".
+ * network is one of "bitcoin", "testnet", "signet" or "regtest".
+ * abi_version must be BHWI_ABI_VERSION, BHWI_ABI_MISMATCH is returned if
+ * the loaded library implements another version. */
+int32_t bhwi_device_open(uint32_t abi_version, const char *path, const char *network,
+                         BhwiDevice **out);
+
+/* Sends the command to the device and returns its response, an empty
+ * command only reads the next bytes of the device. The response is owned
+ * by the application and stays valid until the next call or the release
+ * of the device. A non zero return code is a transport error. */
+typedef int32_t (*BhwiTransportExchange)(void *user_data, const uint8_t *command, size_t command_len,
+                                         const uint8_t **response, size_t *response_len);
+
+/* Opens and unlocks the device of the type, "ledger", "coldcard" or
+ * "specter", over a USB or BLE transport of the application exchanging
+ * the messages of the device protocol. user_data must stay valid until
+ * the device is released. */
+int32_t bhwi_device_open_with_transport(uint32_t abi_version, const char *device_type,
+                                        const char *network, BhwiTransportExchange exchange,
+                                        void *user_data, BhwiDevice **out);
+
+void bhwi_device_free(BhwiDevice *device);
+
+/* Called from the thread making the device call, data is utf8 and only
+ * valid during the call: the command name, what to confirm on device, the
+ * signed input as "index/count", the delay in milliseconds before the next
+ * unlock probe, the retry attempt or the unusual path warning. */
+typedef void (*BhwiEventCallback)(void *user_data, int32_t event, const uint8_t *data, size_t len);
+
+/* Registers the callback of the device events, NULL unregisters it.
+ * user_data must stay valid until the callback is replaced or the device
+ * released. */
+int32_t bhwi_device_set_event_callback(BhwiDevice *device, BhwiEventCallback callback, void *user_data);
+
+int32_t bhwi_get_master_fingerprint(BhwiDevice *device, uint8_t (*out)[4]);
+
+/* Base58 extended public key at the path, e.g. "m/84'/0'/0'". */
+int32_t bhwi_get_xpub(BhwiDevice *device, const char *path, bool display, BhwiBuffer *out);
+
+/* Runs a JSON command with the schema of the cli daemon requests, e.g.
+ * {"id": 1, "command": "getxpub", "path": "m/84'/0'/0'"}, and writes the
+ * JSON reply, even if an error code is returned. signtx refuses the psbts
+ * above the max_fee_rate of the cli config file. */
+int32_t bhwi_execute(BhwiDevice *device, const char *command, BhwiBuffer *out);
+
+/* Interpreter of a device protocol, driven by the host which carries the
+ * transmits to the device or to the pin server. */
+typedef struct BhwiInterpreter BhwiInterpreter;
+
+/* Recipients of a transmit, NONE once the command is finished. */
+#define BHWI_RECIPIENT_NONE 0
+#define BHWI_RECIPIENT_DEVICE 1
+#define BHWI_RECIPIENT_PIN_SERVER 2
+
+/* Bytes to send to the recipient, the url of the pin server is empty for
+ * the device. Both buffers are released with bhwi_buffer_free. */
+typedef struct {
+    int32_t recipient;
+    BhwiBuffer url;
+    BhwiBuffer payload;
+} BhwiTransmit;
+
+/* Interpreter of the device type, "ledger", "jade" or "specter", released
+ * with bhwi_interpreter_free. */
+int32_t bhwi_interpreter_new(uint32_t abi_version, const char *device_type, const char *network,
+                             BhwiInterpreter **out);
+
+void bhwi_interpreter_free(BhwiInterpreter *intpr);
+
+/* Starts the JSON command, e.g. {"command": "getxpub", "path": "m/84'/0'/0'"}
+ * or {"command": "signtx", "psbt": "<base64>"}, and writes the first
+ * transmit. */
+int32_t bhwi_start(BhwiInterpreter *intpr, const char *command, BhwiTransmit *out);
+
+/* Passes the response of the recipient of the last transmit, which stays
+ * owned by the caller, and writes the next transmit. */
+int32_t bhwi_exchange(BhwiInterpreter *intpr, const uint8_t *data, size_t len, BhwiTransmit *out);
+
+/* JSON result of the finished command, e.g. {"fingerprint": "4ba43603"}.
+ * The interpreter may be started again. */
+int32_t bhwi_end(BhwiInterpreter *intpr, BhwiBuffer *out);
+
+/* Message of the last error, empty if the last call succeeded. */
+int32_t bhwi_last_error(BhwiBuffer *out);
+
+void bhwi_buffer_free(BhwiBuffer buffer);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BHWI_H */
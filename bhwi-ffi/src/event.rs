@@ -0,0 +1,119 @@
+//! Events reported while a call is running, so that the application can
+//! tell the user to confirm on the device instead of waiting on a blocked
+//! call.
+
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+use bhwi::Event;
+use bhwi_cli::transport::EventHandler;
+
+/// The command started, the data is the command name.
+pub const BHWI_EVENT_COMMAND_STARTED: i32 = 1;
+/// The user must confirm on the device, the data is what is displayed.
+pub const BHWI_EVENT_CONFIRM_ON_DEVICE: i32 = 2;
+/// The command ended, successfully or not, the data is the command name.
+pub const BHWI_EVENT_COMMAND_FINISHED: i32 = 3;
+/// The device signed an input, the data is its index and the number of
+/// inputs, e.g. `0/2`.
+pub const BHWI_EVENT_INPUT_SIGNED: i32 = 4;
+/// The device is locked and probed until the user unlocks it, the data is
+/// the delay in milliseconds before the next probe.
+pub const BHWI_EVENT_AWAITING_UNLOCK: i32 = 5;
+/// The last exchange failed with a transient error and is retried, the data
+/// is the attempt.
+pub const BHWI_EVENT_RETRYING: i32 = 6;
+/// A path of the command is unusual, the data is the warning.
+pub const BHWI_EVENT_UNUSUAL_PATH: i32 = 7;
+/// The device switched its app and re-enumerates, the data is empty.
+pub const BHWI_EVENT_REENUMERATING: i32 = 8;
+
+/// Callback receiving the user data given at registration, the event code
+/// and its utf8 data, which is only valid during the call.
+pub type BhwiEventCallback =
+    unsafe extern "C" fn(user_data: *mut c_void, event: i32, data: *const u8, len: usize);
+
+#[derive(Clone, Copy)]
+pub struct EventCallback {
+    callback: BhwiEventCallback,
+    user_data: *mut c_void,
+}
+
+impl EventCallback {
+    pub fn new(callback: BhwiEventCallback, user_data: *mut c_void) -> Self {
+        Self {
+            callback,
+            user_data,
+        }
+    }
+
+    pub fn emit(&self, event: i32, data: &str) {
+        // Safety: the caller registered the callback with this user data.
+        unsafe { (self.callback)(self.user_data, event, data.as_ptr(), data.len()) }
+    }
+}
+
+/// Callback of a device, shared with its transport which reports the events
+/// of the interpreters: the callback is registered once the device is open.
+#[derive(Clone, Default)]
+pub struct EventSlot(Rc<RefCell<Events>>);
+
+#[derive(Default)]
+struct Events {
+    callback: Option<EventCallback>,
+    /// The confirmation of the running command was already reported.
+    confirming: bool,
+}
+
+impl EventSlot {
+    pub fn set(&self, callback: Option<EventCallback>) {
+        self.0.borrow_mut().callback = callback;
+    }
+
+    /// Reports the start of the command.
+    pub fn start(&self, command: &str) {
+        self.0.borrow_mut().confirming = false;
+        self.emit(BHWI_EVENT_COMMAND_STARTED, command);
+    }
+
+    pub fn emit(&self, event: i32, data: &str) {
+        let callback = {
+            let mut events = self.0.borrow_mut();
+            events.confirming |= event == BHWI_EVENT_CONFIRM_ON_DEVICE;
+            events.callback
+        };
+        if let Some(callback) = callback {
+            callback.emit(event, data);
+        }
+    }
+
+    /// Reports the event of an interpreter, the confirmation only once per
+    /// command.
+    pub fn notify(&self, event: &Event) {
+        match event {
+            Event::InputSigned(index, count) => {
+                self.emit(BHWI_EVENT_INPUT_SIGNED, &format!("{}/{}", index, count))
+            }
+            Event::AwaitingUserConfirmation => {
+                if !self.0.borrow().confirming {
+                    self.emit(BHWI_EVENT_CONFIRM_ON_DEVICE, "");
+                }
+            }
+            Event::AwaitingUnlock(delay_ms) => {
+                self.emit(BHWI_EVENT_AWAITING_UNLOCK, &delay_ms.to_string())
+            }
+            Event::Retrying(attempt, _) => self.emit(BHWI_EVENT_RETRYING, &attempt.to_string()),
+            Event::UnusualPath(warning) => {
+                self.emit(BHWI_EVENT_UNUSUAL_PATH, &format!("{:?}", warning))
+            }
+            Event::Reenumerating => self.emit(BHWI_EVENT_REENUMERATING, ""),
+        }
+    }
+
+    /// Returns the handler of the transports of the cli reporting to the slot.
+    pub fn handler(&self) -> EventHandler {
+        let slot = self.clone();
+        Box::new(move |event| slot.notify(event))
+    }
+}
@@ -0,0 +1,240 @@
+//! Interpreters driven by the host, which carries the transmits to the
+//! device or to the pin server itself: no transport crosses the ABI.
+
+use std::str::FromStr;
+
+use bhwi::{
+    common::{
+        Command, Error, JadeInterpreter, LedgerInterpreter, Response, SpecterInterpreter, Transmit,
+        UnlockOptions,
+    },
+    Interpreter,
+};
+use bhwi_cli::{
+    output::{code, ErrorResult},
+    DeviceType,
+};
+use bitcoin::{bip32::DerivationPath, Network, Psbt};
+use serde::Deserialize;
+
+use crate::BhwiBuffer;
+
+/// No transmit, the command is finished and its result is read with
+/// `bhwi_end`.
+pub const BHWI_RECIPIENT_NONE: i32 = 0;
+pub const BHWI_RECIPIENT_DEVICE: i32 = 1;
+/// The payload is posted to the url of the pin server, its response is
+/// passed to the next exchange.
+pub const BHWI_RECIPIENT_PIN_SERVER: i32 = 2;
+
+/// Bytes to carry to the recipient, the buffers are released with
+/// `bhwi_buffer_free`.
+#[repr(C)]
+pub struct BhwiTransmit {
+    pub recipient: i32,
+    pub url: BhwiBuffer,
+    pub payload: BhwiBuffer,
+}
+
+impl BhwiTransmit {
+    pub fn none() -> Self {
+        Self {
+            recipient: BHWI_RECIPIENT_NONE,
+            url: Vec::new().into(),
+            payload: Vec::new().into(),
+        }
+    }
+}
+
+impl From<Transmit> for BhwiTransmit {
+    fn from(transmit: Transmit) -> Self {
+        let (recipient, url) = match transmit.recipient {
+            bhwi::common::Recipient::Device => (BHWI_RECIPIENT_DEVICE, Vec::new()),
+            bhwi::common::Recipient::PinServer { url } => {
+                (BHWI_RECIPIENT_PIN_SERVER, url.into_bytes())
+            }
+        };
+        Self {
+            recipient,
+            url: url.into(),
+            payload: transmit.payload.into(),
+        }
+    }
+}
+
+/// Commands of the interpreters, in JSON: `{"command": "getxpub", "path":
+/// "m/84'/0'/0'"}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Unlock,
+    GetMasterFingerprint,
+    GetXpub {
+        path: DerivationPath,
+        #[serde(default)]
+        display: bool,
+    },
+    /// Signs the base64 psbt for the default wallets.
+    SignTx {
+        psbt: String,
+    },
+    DisplayAddress {
+        path: DerivationPath,
+    },
+    SignMessage {
+        path: DerivationPath,
+        message: String,
+    },
+}
+
+enum Inner {
+    Ledger(LedgerInterpreter),
+    Jade(JadeInterpreter),
+    Specter(SpecterInterpreter),
+}
+
+macro_rules! dispatch {
+    ($inner:expr, $intpr:ident => $call:expr) => {
+        match $inner {
+            Inner::Ledger($intpr) => $call,
+            Inner::Jade($intpr) => $call,
+            Inner::Specter($intpr) => $call,
+        }
+    };
+}
+
+/// Interpreter of a device, started again with a new command once ended.
+pub struct BhwiInterpreter {
+    device_type: DeviceType,
+    network: Network,
+    inner: Inner,
+    /// Psbt of the running signature, the signatures returned by the
+    /// Ledger are added to it.
+    psbt: Option<Psbt>,
+}
+
+fn interpreter_error(e: Error) -> ErrorResult {
+    ErrorResult::from(&bhwi_cli::Error::Interpreter(e))
+}
+
+impl BhwiInterpreter {
+    pub fn new(device_type: DeviceType, network: Network) -> Result<Self, ErrorResult> {
+        Ok(Self {
+            device_type,
+            network,
+            inner: Self::inner(device_type, network)?,
+            psbt: None,
+        })
+    }
+
+    fn inner(device_type: DeviceType, network: Network) -> Result<Inner, ErrorResult> {
+        match device_type {
+            DeviceType::Ledger => Ok(Inner::Ledger(
+                LedgerInterpreter::default().with_network(network),
+            )),
+            DeviceType::Jade => Ok(Inner::Jade(
+                JadeInterpreter::default().with_network(network),
+            )),
+            DeviceType::Specter => Ok(Inner::Specter(SpecterInterpreter::default())),
+            // The encryption of the Coldcard is kept by its device.
+            DeviceType::Coldcard => Err(ErrorResult::new(
+                "The coldcard interpreter is not available",
+                code::UNKNOWN_DEVICE_TYPE,
+            )),
+            DeviceType::Trezor => Err(ErrorResult::new(
+                "The trezor interpreter is not available",
+                code::UNKNOWN_DEVICE_TYPE,
+            )),
+        }
+    }
+
+    /// Drops the running command, the interpreter is ready for a new one.
+    fn reset(&mut self) -> Inner {
+        self.psbt = None;
+        let fresh = Self::inner(self.device_type, self.network).expect("supported device type");
+        std::mem::replace(&mut self.inner, fresh)
+    }
+
+    pub fn start(&mut self, request: Request) -> Result<BhwiTransmit, ErrorResult> {
+        self.reset();
+        let command = match request {
+            Request::Unlock => Command::Unlock {
+                options: UnlockOptions {
+                    network: Some(self.network),
+                },
+            },
+            Request::GetMasterFingerprint => Command::GetMasterFingerprint,
+            Request::GetXpub { path, display } => Command::GetXpub { path, display },
+            Request::SignTx { psbt } => {
+                let psbt = Psbt::from_str(&psbt)
+                    .map_err(|_| ErrorResult::new("Invalid psbt", code::INVALID_TX))?;
+                self.psbt = Some(psbt.clone());
+                Command::SignPsbt {
+                    psbt: Box::new(psbt),
+                    policy: None,
+                    hmac: None,
+                }
+            }
+            Request::DisplayAddress { path } => Command::DisplayAddress {
+                path,
+                policy: None,
+                hmac: None,
+            },
+            Request::SignMessage { path, message } => Command::SignMessage {
+                path,
+                message: message.into_bytes(),
+            },
+        };
+        let res = dispatch!(&mut self.inner, intpr => intpr.start(command));
+        match res {
+            Ok(transmit) => Ok(transmit.into()),
+            Err(e) => {
+                self.reset();
+                Err(interpreter_error(e))
+            }
+        }
+    }
+
+    pub fn exchange(&mut self, data: Vec<u8>) -> Result<BhwiTransmit, ErrorResult> {
+        let res = dispatch!(&mut self.inner, intpr => intpr.exchange(data));
+        match res {
+            Ok(Some(transmit)) => Ok(transmit.into()),
+            Ok(None) => Ok(BhwiTransmit::none()),
+            Err(e) => {
+                self.reset();
+                Err(interpreter_error(e))
+            }
+        }
+    }
+
+    /// Returns the JSON result of the finished command.
+    pub fn end(&mut self) -> Result<serde_json::Value, ErrorResult> {
+        let psbt = self.psbt.take();
+        let response = dispatch!(self.reset(), intpr => intpr.end()).map_err(interpreter_error)?;
+        match response {
+            Response::TaskDone => Ok(serde_json::json!({ "success": true })),
+            Response::MasterFingerprint(fg) => {
+                Ok(serde_json::json!({ "fingerprint": fg.to_string() }))
+            }
+            Response::Xpub(xpub) => Ok(serde_json::json!({ "xpub": xpub.to_string() })),
+            Response::SignedPsbt(psbt) => Ok(serde_json::json!({ "psbt": psbt.to_string() })),
+            Response::Signatures(signatures) => {
+                let mut psbt = psbt.ok_or_else(|| interpreter_error(Error::NoErrorOrResult))?;
+                for (index, signature) in signatures {
+                    let input = psbt.inputs.get_mut(index).ok_or_else(|| {
+                        interpreter_error(Error::UnexpectedResult(index.to_be_bytes().to_vec()))
+                    })?;
+                    signature.add_to(input);
+                }
+                Ok(serde_json::json!({ "psbt": psbt.to_string() }))
+            }
+            Response::Address(address) => Ok(serde_json::json!({
+                "address": address.assume_checked().to_string()
+            })),
+            Response::MessageSignature(signature) => {
+                Ok(serde_json::json!({ "signature": signature }))
+            }
+            _ => Err(interpreter_error(Error::NoErrorOrResult)),
+        }
+    }
+}
@@ -0,0 +1,1153 @@
+//! C ABI over the native devices of bhwi-cli and over the interpreters of
+//! bhwi for the hosts carrying the bytes themselves, see `include/bhwi.h`.
+//!
+//! Devices and interpreters are opaque handles owned by the caller, results are returned in
+//! out parameters and every function returns `BHWI_OK` or one of the HWI
+//! error codes, the message of the last error of the calling thread being
+//! available with `bhwi_last_error`.
+
+pub mod event;
+pub mod interpreter;
+pub mod transport;
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::str::FromStr;
+
+use bhwi_cli::{
+    config::Config,
+    daemon,
+    output::{code, ErrorResult},
+    CliDevice, DeviceType, Emulator,
+};
+use bitcoin::{bip32::DerivationPath, Network};
+use event::{
+    BhwiEventCallback, EventCallback, EventSlot, BHWI_EVENT_COMMAND_FINISHED,
+    BHWI_EVENT_CONFIRM_ON_DEVICE,
+};
+use interpreter::{BhwiInterpreter, BhwiTransmit};
+use serde::Serialize;
+use transport::{BhwiTransportExchange, CallbackTransport};
+
+pub const BHWI_OK: i32 = 0;
+/// Returned when the caller was built against another version of the ABI,
+/// outside of the range of the HWI error codes.
+pub const BHWI_ABI_MISMATCH: i32 = -100;
+
+/// Version of the ABI, incremented on every breaking change of the
+/// signatures, types or constants of `include/bhwi.h`.
+pub const BHWI_ABI_VERSION: u32 = 1;
+
+/// Version of the library, nul terminated.
+const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Opaque handle to an unlocked device.
+pub struct BhwiDevice {
+    inner: Box<dyn CliDevice>,
+    events: EventSlot,
+    /// Maximum fee rate of the signed psbts in sat/vB, see
+    /// [`bhwi_cli::config::Config`].
+    max_fee_rate: Option<u64>,
+}
+
+impl BhwiDevice {
+    pub fn new(inner: Box<dyn CliDevice>) -> Self {
+        Self {
+            inner,
+            events: EventSlot::default(),
+            max_fee_rate: None,
+        }
+    }
+
+    /// Returns the device with the maximum fee rate of the config file of
+    /// the cli, reporting the events of its transport to the slot.
+    fn configured(inner: Box<dyn CliDevice>, events: EventSlot) -> Result<Self, ErrorResult> {
+        let config = Config::load_default()
+            .map_err(|e| ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))?;
+        Ok(Self {
+            events,
+            max_fee_rate: config.max_fee_rate,
+            ..Self::new(inner)
+        })
+    }
+
+    fn emit(&self, event: i32, data: &str) {
+        self.events.emit(event, data);
+    }
+
+    /// Runs the command between its started and finished events, the events
+    /// of the interpreters are reported in between.
+    fn run<T>(&mut self, command: &str, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.events.start(command);
+        let res = f(self);
+        self.emit(BHWI_EVENT_COMMAND_FINISHED, command);
+        res
+    }
+}
+
+/// Bytes allocated by the library, released with `bhwi_buffer_free`.
+#[repr(C)]
+pub struct BhwiBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl From<Vec<u8>> for BhwiBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, len }
+    }
+}
+
+/// Runs the call, records its error and turns it into a return code.
+/// Panics must not unwind across the ABI.
+fn call(f: impl FnOnce() -> Result<(), ErrorResult>) -> i32 {
+    let res = catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(ErrorResult::new("Internal error", code::UNKNOWN_ERROR)));
+    LAST_ERROR.with(|last| match res {
+        Ok(()) => {
+            *last.borrow_mut() = None;
+            BHWI_OK
+        }
+        Err(e) => {
+            *last.borrow_mut() = Some(e.error);
+            e.code
+        }
+    })
+}
+
+fn bad_argument(name: &str) -> ErrorResult {
+    ErrorResult::new(format!("Invalid argument: {}", name), code::BAD_ARGUMENT)
+}
+
+/// # Safety
+///
+/// `s` must be null or a valid nul terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, ErrorResult> {
+    if s.is_null() {
+        return Err(bad_argument(name));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| bad_argument(name))
+}
+
+/// # Safety
+///
+/// `out` must be null or valid for writes.
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), ErrorResult> {
+    if out.is_null() {
+        return Err(bad_argument("out"));
+    }
+    out.write(value);
+    Ok(())
+}
+
+fn check_abi_version(abi_version: u32) -> Result<(), ErrorResult> {
+    let age = match abi_version.cmp(&BHWI_ABI_VERSION) {
+        std::cmp::Ordering::Equal => return Ok(()),
+        std::cmp::Ordering::Less => "new",
+        std::cmp::Ordering::Greater => "old",
+    };
+    Err(ErrorResult::new(
+        format!(
+            "Library is too {}: ABI version {}, caller built for version {}",
+            age, BHWI_ABI_VERSION, abi_version
+        ),
+        BHWI_ABI_MISMATCH,
+    ))
+}
+
+/// Returns the ABI version of the library, to check before using it when
+/// the library is loaded dynamically.
+#[no_mangle]
+pub extern "C" fn bhwi_abi_version() -> u32 {
+    BHWI_ABI_VERSION
+}
+
+/// Returns the version of the library, a static nul terminated string.
+#[no_mangle]
+pub extern "C" fn bhwi_version() -> *const c_char {
+    VERSION.as_ptr() as *const c_char
+}
+
+#[derive(Serialize)]
+struct EnumerateEntry {
+    #[serde(rename = "type")]
+    device_type: String,
+    path: String,
+}
+
+/// Writes the JSON list of the connected devices, with their type and path.
+///
+/// # Safety
+///
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_enumerate(out: *mut BhwiBuffer) -> i32 {
+    call(|| {
+        let json = serde_json::to_vec(&enumerate_entries()?)
+            .map_err(|e| ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR))?;
+        write_out(out, json.into())
+    })
+}
+
+fn enumerate_entries() -> Result<Vec<EnumerateEntry>, ErrorResult> {
+    let devices = bhwi_cli::enumerate().map_err(|e| ErrorResult::from(&e))?;
+    Ok(devices
+        .iter()
+        .map(|info| EnumerateEntry {
+            device_type: info.device_type.to_string(),
+            path: info.path.clone(),
+        })
+        .collect())
+}
+
+/// Opens and unlocks the device at the path, either one returned by
+/// `bhwi_enumerate` or an emulator like `speculos:127.0.0.1:9999`.
+/// The device is released with `bhwi_device_free`.
+/// `abi_version` is the `BHWI_ABI_VERSION` the caller was built with,
+/// `BHWI_ABI_MISMATCH` is returned if the library does not implement it.
+///
+/// # Safety
+///
+/// `path` and `network` must be valid nul terminated strings,
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_device_open(
+    abi_version: u32,
+    path: *const c_char,
+    network: *const c_char,
+    out: *mut *mut BhwiDevice,
+) -> i32 {
+    call(|| {
+        check_abi_version(abi_version)?;
+        let path = str_arg(path, "path")?;
+        let network =
+            Network::from_str(str_arg(network, "network")?).map_err(|_| bad_argument("network"))?;
+        let info = match Emulator::from_str(path) {
+            Ok(emulator) => emulator.device_info(),
+            Err(_) => bhwi_cli::enumerate()
+                .map_err(|e| ErrorResult::from(&e))?
+                .into_iter()
+                .find(|info| info.path == path)
+                .ok_or_else(|| ErrorResult::new("Device not found", code::DEVICE_CONN_ERROR))?,
+        };
+        let events = EventSlot::default();
+        let device = futures::executor::block_on(bhwi_cli::open_with_events(
+            &info,
+            network,
+            false,
+            Some(events.handler()),
+        ))
+        .map_err(|e| ErrorResult::from(&e))?;
+        write_out(
+            out,
+            Box::into_raw(Box::new(BhwiDevice::configured(device, events)?)),
+        )
+    })
+}
+
+/// Opens and unlocks the device of the type, one of "ledger", "coldcard",
+/// "specter" and "trezor", over the transport of the application. The exchange
+/// callback is called from the thread making the device calls.
+/// The device is released with `bhwi_device_free`.
+///
+/// # Safety
+///
+/// `device_type` and `network` must be valid nul terminated strings,
+/// `user_data` must stay valid until the device is released and `out`
+/// must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_device_open_with_transport(
+    abi_version: u32,
+    device_type: *const c_char,
+    network: *const c_char,
+    exchange: Option<BhwiTransportExchange>,
+    user_data: *mut c_void,
+    out: *mut *mut BhwiDevice,
+) -> i32 {
+    call(|| {
+        check_abi_version(abi_version)?;
+        let device_type = DeviceType::from_str(str_arg(device_type, "device_type")?)
+            .map_err(|_| bad_argument("device_type"))?;
+        let network =
+            Network::from_str(str_arg(network, "network")?).map_err(|_| bad_argument("network"))?;
+        let exchange = exchange.ok_or_else(|| bad_argument("exchange"))?;
+        let events = EventSlot::default();
+        let device = futures::executor::block_on(bhwi_cli::open_with_transport(
+            device_type,
+            CallbackTransport::new(exchange, user_data, events.clone()),
+            network,
+        ))
+        .map_err(|e| ErrorResult::from(&e))?;
+        write_out(
+            out,
+            Box::into_raw(Box::new(BhwiDevice::configured(device, events)?)),
+        )
+    })
+}
+
+/// # Safety
+///
+/// `device` must be null or a handle returned by `bhwi_device_open`
+/// not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_device_free(device: *mut BhwiDevice) {
+    if !device.is_null() {
+        drop(Box::from_raw(device));
+    }
+}
+
+/// Registers the callback receiving the events of the device calls,
+/// replacing the previous one. A null callback unregisters it. Besides the
+/// start and the end of the commands, the events of the device protocols
+/// are reported while the commands run, see `event`.
+///
+/// # Safety
+///
+/// `device` must be a valid handle. The callback is called from the thread
+/// making the device call, with `user_data` which must stay valid until
+/// the callback is replaced or the device released.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_device_set_event_callback(
+    device: *mut BhwiDevice,
+    callback: Option<BhwiEventCallback>,
+    user_data: *mut c_void,
+) -> i32 {
+    call(|| {
+        let device = device.as_mut().ok_or_else(|| bad_argument("device"))?;
+        device
+            .events
+            .set(callback.map(|callback| EventCallback::new(callback, user_data)));
+        Ok(())
+    })
+}
+
+/// Writes the 4 bytes of the master key fingerprint.
+///
+/// # Safety
+///
+/// `device` must be a valid handle, `out` must be valid for writes of 4 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_get_master_fingerprint(
+    device: *mut BhwiDevice,
+    out: *mut [u8; 4],
+) -> i32 {
+    call(|| {
+        let device = device.as_mut().ok_or_else(|| bad_argument("device"))?;
+        let fingerprint = device.run("getmasterfingerprint", |device| {
+            futures::executor::block_on(device.inner.get_master_fingerprint())
+        });
+        let fingerprint = fingerprint.map_err(|e| ErrorResult::from(&e))?;
+        write_out(out, fingerprint.to_bytes())
+    })
+}
+
+/// Writes the base58 extended public key at the derivation path,
+/// displayed on the device screen if `display` is true.
+///
+/// # Safety
+///
+/// `device` must be a valid handle, `path` a valid nul terminated string
+/// and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_get_xpub(
+    device: *mut BhwiDevice,
+    path: *const c_char,
+    display: bool,
+    out: *mut BhwiBuffer,
+) -> i32 {
+    call(|| {
+        let device = device.as_mut().ok_or_else(|| bad_argument("device"))?;
+        let path =
+            DerivationPath::from_str(str_arg(path, "path")?).map_err(|_| bad_argument("path"))?;
+        let xpub = device.run("getxpub", |device| {
+            if display {
+                device.emit(BHWI_EVENT_CONFIRM_ON_DEVICE, &path.to_string());
+            }
+            futures::executor::block_on(device.inner.get_extended_pubkey(path, display))
+        });
+        let xpub = xpub.map_err(|e| ErrorResult::from(&e))?;
+        write_out(out, xpub.to_string().into_bytes().into())
+    })
+}
+
+/// Runs a JSON command with the schema of the requests of the cli daemon
+/// mode, e.g. `{"id": 1, "command": "getxpub", "path": "m/84'/0'/0'"}`,
+/// and writes the JSON reply: the result or the error with its code, and
+/// the id of the request. The reply is written even if an error is returned.
+/// `signtx` refuses the psbts above the maximum fee rate of the config file
+/// of the cli.
+///
+/// # Safety
+///
+/// `device` must be a valid handle, `command` a valid nul terminated string
+/// and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_execute(
+    device: *mut BhwiDevice,
+    command: *const c_char,
+    out: *mut BhwiBuffer,
+) -> i32 {
+    call(|| {
+        let device = device.as_mut().ok_or_else(|| bad_argument("device"))?;
+        let command = str_arg(command, "command")?;
+        if out.is_null() {
+            return Err(bad_argument("out"));
+        }
+        let (id, result) = match daemon::Request::parse(command) {
+            Ok(request) => {
+                let result = match request.command {
+                    daemon::Command::Enumerate => {
+                        enumerate_entries().map(|entries| serde_json::json!(entries))
+                    }
+                    command => device.run(command.name(), |device| {
+                        futures::executor::block_on(daemon::run_device_command(
+                            device.inner.as_mut(),
+                            command,
+                            device.max_fee_rate,
+                        ))
+                    }),
+                };
+                (request.id, result)
+            }
+            Err((id, e)) => (id, Err(e)),
+        };
+        let (reply, res) = match result {
+            Ok(result) => (daemon::Reply::new(id, result), Ok(())),
+            Err(e) => (daemon::Reply::error(id, e.clone()), Err(e)),
+        };
+        let json = serde_json::to_vec(&reply)
+            .map_err(|e| ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR))?;
+        write_out(out, json.into())?;
+        res
+    })
+}
+
+/// Creates the interpreter of the device type, one of "ledger", "jade" and
+/// "specter", for the host carrying its transmits. It is released with
+/// `bhwi_interpreter_free`.
+///
+/// # Safety
+///
+/// `device_type` and `network` must be valid nul terminated strings,
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_interpreter_new(
+    abi_version: u32,
+    device_type: *const c_char,
+    network: *const c_char,
+    out: *mut *mut BhwiInterpreter,
+) -> i32 {
+    call(|| {
+        check_abi_version(abi_version)?;
+        let device_type = DeviceType::from_str(str_arg(device_type, "device_type")?)
+            .map_err(|_| bad_argument("device_type"))?;
+        let network =
+            Network::from_str(str_arg(network, "network")?).map_err(|_| bad_argument("network"))?;
+        let intpr = BhwiInterpreter::new(device_type, network)?;
+        write_out(out, Box::into_raw(Box::new(intpr)))
+    })
+}
+
+/// # Safety
+///
+/// `intpr` must be null or a handle returned by `bhwi_interpreter_new`
+/// not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_interpreter_free(intpr: *mut BhwiInterpreter) {
+    if !intpr.is_null() {
+        drop(Box::from_raw(intpr));
+    }
+}
+
+/// Starts the JSON command, e.g. `{"command": "getxpub", "path":
+/// "m/84'/0'/0'"}`, dropping the running one, and writes the first
+/// transmit.
+///
+/// # Safety
+///
+/// `intpr` must be a valid handle, `command` a valid nul terminated string
+/// and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_start(
+    intpr: *mut BhwiInterpreter,
+    command: *const c_char,
+    out: *mut BhwiTransmit,
+) -> i32 {
+    call(|| {
+        let intpr = intpr.as_mut().ok_or_else(|| bad_argument("intpr"))?;
+        let request = serde_json::from_str(str_arg(command, "command")?)
+            .map_err(|e| ErrorResult::new(e.to_string(), code::BAD_ARGUMENT))?;
+        if out.is_null() {
+            return Err(bad_argument("out"));
+        }
+        write_out(out, intpr.start(request)?)
+    })
+}
+
+/// Passes the bytes received from the recipient of the last transmit and
+/// writes the next one, with the `BHWI_RECIPIENT_NONE` recipient once the
+/// command is finished. The data is copied.
+///
+/// # Safety
+///
+/// `intpr` must be a valid handle, `data` must be valid for reads of `len`
+/// bytes and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_exchange(
+    intpr: *mut BhwiInterpreter,
+    data: *const u8,
+    len: usize,
+    out: *mut BhwiTransmit,
+) -> i32 {
+    call(|| {
+        let intpr = intpr.as_mut().ok_or_else(|| bad_argument("intpr"))?;
+        if data.is_null() && len != 0 {
+            return Err(bad_argument("data"));
+        }
+        if out.is_null() {
+            return Err(bad_argument("out"));
+        }
+        let data = if len == 0 {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(data, len).to_vec()
+        };
+        write_out(out, intpr.exchange(data)?)
+    })
+}
+
+/// Writes the JSON result of the finished command, e.g. `{"xpub": "..."}`.
+/// The interpreter is ready for a new command.
+///
+/// # Safety
+///
+/// `intpr` must be a valid handle and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_end(intpr: *mut BhwiInterpreter, out: *mut BhwiBuffer) -> i32 {
+    call(|| {
+        let intpr = intpr.as_mut().ok_or_else(|| bad_argument("intpr"))?;
+        if out.is_null() {
+            return Err(bad_argument("out"));
+        }
+        let json = serde_json::to_vec(&intpr.end()?)
+            .map_err(|e| ErrorResult::new(e.to_string(), code::UNKNOWN_ERROR))?;
+        write_out(out, json.into())
+    })
+}
+
+/// Writes the message of the last error returned on the calling thread,
+/// returns `BHWI_OK` with an empty buffer if the last call succeeded.
+///
+/// # Safety
+///
+/// `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_last_error(out: *mut BhwiBuffer) -> i32 {
+    let message = LAST_ERROR.with(|last| last.borrow().clone().unwrap_or_default());
+    match write_out(out, message.into_bytes().into()) {
+        Ok(()) => BHWI_OK,
+        Err(e) => e.code,
+    }
+}
+
+/// # Safety
+///
+/// `buffer` must have been returned by the library and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn bhwi_buffer_free(buffer: BhwiBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::ffi::CString;
+
+    use async_trait::async_trait;
+    use bhwi::ledger::WalletPolicy;
+    use bhwi_async::{
+        mock::MockLedger, software::SoftwareSigner, DisplayAddress, Error as HWIError, Transport,
+        HWI,
+    };
+    use bhwi_cli::Error;
+    use bitcoin::{
+        address::NetworkUnchecked,
+        bip32::{Fingerprint, Xpub},
+        Address, Psbt,
+    };
+    use event::BHWI_EVENT_COMMAND_STARTED;
+
+    /// Software signer with the error type of the native devices.
+    struct Signer(SoftwareSigner);
+
+    fn erase(e: HWIError<Infallible, Infallible>) -> Error {
+        match e {
+            HWIError::Interpreter(e) => HWIError::Interpreter(e),
+            HWIError::Transport(e) | HWIError::HttpClient(e) => match e {},
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl HWI for Signer {
+        type Error = Error;
+
+        async fn unlock(&mut self, network: Network) -> Result<(), Error> {
+            self.0.unlock(network).await.map_err(erase)
+        }
+
+        async fn get_master_fingerprint(&mut self) -> Result<Fingerprint, Error> {
+            self.0.get_master_fingerprint().await.map_err(erase)
+        }
+
+        async fn get_extended_pubkey(
+            &mut self,
+            path: DerivationPath,
+            display: bool,
+        ) -> Result<Xpub, Error> {
+            self.0
+                .get_extended_pubkey(path, display)
+                .await
+                .map_err(erase)
+        }
+
+        async fn register_wallet(
+            &mut self,
+            policy: WalletPolicy,
+        ) -> Result<([u8; 32], [u8; 32]), Error> {
+            self.0.register_wallet(policy).await.map_err(erase)
+        }
+
+        async fn sign_psbt(
+            &mut self,
+            psbt: Psbt,
+            policy: Option<WalletPolicy>,
+            hmac: Option<[u8; 32]>,
+        ) -> Result<Psbt, Error> {
+            HWI::sign_psbt(&mut self.0, psbt, policy, hmac)
+                .await
+                .map_err(erase)
+        }
+    }
+
+    fn unsupported(command: &'static str) -> Error {
+        HWIError::Interpreter(bhwi::common::Error::UnsupportedCommand(command))
+    }
+
+    #[async_trait(?Send)]
+    impl CliDevice for Signer {
+        async fn sign_message(
+            &mut self,
+            _path: DerivationPath,
+            _message: &[u8],
+        ) -> Result<String, Error> {
+            Err(unsupported("sign_message"))
+        }
+
+        async fn display_address(
+            &mut self,
+            path: DerivationPath,
+            policy: Option<WalletPolicy>,
+            hmac: Option<[u8; 32]>,
+        ) -> Result<Address<NetworkUnchecked>, Error> {
+            DisplayAddress::display_address(&mut self.0, path, policy, hmac)
+                .await
+                .map_err(erase)
+        }
+
+        async fn verify_owned_address(
+            &mut self,
+            _address: Address<NetworkUnchecked>,
+            _policy: WalletPolicy,
+            _hmac: Option<[u8; 32]>,
+            _gap_limit: u32,
+        ) -> Result<(bool, u32), Error> {
+            Err(unsupported("verify_owned_address"))
+        }
+    }
+
+    fn signer() -> *mut BhwiDevice {
+        let signer = SoftwareSigner::new(&[0x01; 32], Network::Testnet).unwrap();
+        Box::into_raw(Box::new(BhwiDevice::new(Box::new(Signer(signer)))))
+    }
+
+    unsafe fn take_string(buffer: BhwiBuffer) -> String {
+        let s = std::str::from_utf8(std::slice::from_raw_parts(buffer.data, buffer.len))
+            .unwrap()
+            .to_string();
+        bhwi_buffer_free(buffer);
+        s
+    }
+
+    fn empty_buffer() -> BhwiBuffer {
+        BhwiBuffer {
+            data: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn last_error() -> String {
+        let mut buffer = empty_buffer();
+        unsafe {
+            assert_eq!(bhwi_last_error(&mut buffer), BHWI_OK);
+            take_string(buffer)
+        }
+    }
+
+    #[test]
+    fn test_device_calls() {
+        let device = signer();
+        unsafe {
+            let mut fingerprint = [0x00; 4];
+            assert_eq!(
+                bhwi_get_master_fingerprint(device, &mut fingerprint),
+                BHWI_OK
+            );
+            assert_eq!(fingerprint, [0x4b, 0xa4, 0x36, 0x03]);
+
+            let mut buffer = empty_buffer();
+            let path = CString::new("m/84'/1'/0'").unwrap();
+            assert_eq!(
+                bhwi_get_xpub(device, path.as_ptr(), false, &mut buffer),
+                BHWI_OK
+            );
+            assert!(take_string(buffer).starts_with("tpub"));
+            assert_eq!(last_error(), "");
+
+            let path = CString::new("m/84'/1'/x").unwrap();
+            let mut buffer = empty_buffer();
+            assert_eq!(
+                bhwi_get_xpub(device, path.as_ptr(), false, &mut buffer),
+                code::BAD_ARGUMENT
+            );
+            assert_eq!(last_error(), "Invalid argument: path");
+
+            bhwi_device_free(device);
+        }
+    }
+
+    unsafe extern "C" fn record(user_data: *mut c_void, event: i32, data: *const u8, len: usize) {
+        let events = &mut *(user_data as *mut Vec<(i32, String)>);
+        let data = std::slice::from_raw_parts(data, len);
+        events.push((event, String::from_utf8(data.to_vec()).unwrap()));
+    }
+
+    #[test]
+    fn test_event_callback() {
+        let device = signer();
+        let mut events: Vec<(i32, String)> = Vec::new();
+        unsafe {
+            assert_eq!(
+                bhwi_device_set_event_callback(
+                    device,
+                    Some(record),
+                    &mut events as *mut _ as *mut c_void
+                ),
+                BHWI_OK
+            );
+            let path = CString::new("m/84'/1'/0'").unwrap();
+            let mut buffer = empty_buffer();
+            assert_eq!(
+                bhwi_get_xpub(device, path.as_ptr(), true, &mut buffer),
+                BHWI_OK
+            );
+            bhwi_buffer_free(buffer);
+
+            assert_eq!(
+                bhwi_device_set_event_callback(device, None, std::ptr::null_mut()),
+                BHWI_OK
+            );
+            let mut fingerprint = [0x00; 4];
+            assert_eq!(
+                bhwi_get_master_fingerprint(device, &mut fingerprint),
+                BHWI_OK
+            );
+            bhwi_device_free(device);
+        }
+        assert_eq!(
+            events,
+            vec![
+                (BHWI_EVENT_COMMAND_STARTED, "getxpub".to_string()),
+                (BHWI_EVENT_CONFIRM_ON_DEVICE, "84'/1'/0'".to_string()),
+                (BHWI_EVENT_COMMAND_FINISHED, "getxpub".to_string()),
+            ]
+        );
+    }
+
+    unsafe fn execute(device: *mut BhwiDevice, command: &str) -> (i32, serde_json::Value) {
+        let command = CString::new(command).unwrap();
+        let mut buffer = empty_buffer();
+        let code = bhwi_execute(device, command.as_ptr(), &mut buffer);
+        (code, serde_json::from_str(&take_string(buffer)).unwrap())
+    }
+
+    #[test]
+    fn test_execute() {
+        let device = signer();
+        unsafe {
+            let (code, reply) = execute(device, r#"{"id": 7, "command": "getmasterfingerprint"}"#);
+            assert_eq!(code, BHWI_OK);
+            assert_eq!(
+                reply,
+                serde_json::json!({"id": 7, "fingerprint": "4ba43603"})
+            );
+
+            let (code, reply) = execute(
+                device,
+                r#"{"command": "getxpub", "path": "m/84'/1'/0'", "display": true}"#,
+            );
+            assert_eq!(code, BHWI_OK);
+            assert!(reply["xpub"].as_str().unwrap().starts_with("tpub"));
+
+            let (code, reply) = execute(
+                device,
+                r#"{"command": "displayaddress", "path": "m/84'/1'/0'/0/0"}"#,
+            );
+            assert_eq!(code, BHWI_OK);
+            assert!(reply["address"].as_str().unwrap().starts_with("tb1q"));
+
+            let (code, reply) = execute(
+                device,
+                r#"{"command": "displayaddress", "path": "m/84'/1'/0'/0/0", "hmac": "00"}"#,
+            );
+            assert_eq!(code, code::BAD_ARGUMENT);
+            assert_eq!(reply["code"], code::BAD_ARGUMENT);
+
+            let (code, reply) = execute(device, r#"{"id": "a", "command": "signtx"}"#);
+            assert_eq!(code, code::BAD_ARGUMENT);
+            assert_eq!(reply["id"], "a");
+            assert_eq!(reply["code"], code::BAD_ARGUMENT);
+            assert_eq!(reply["error"].as_str().unwrap(), last_error());
+
+            let (code, _) = execute(device, r#"{"command": "close"}"#);
+            assert_eq!(code, code::BAD_ARGUMENT);
+            bhwi_device_free(device);
+        }
+    }
+
+    #[test]
+    fn test_execute_max_fee_rate() {
+        // Pays 301 sats for about 225 vB once signed.
+        const PSBT: &str = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+        let device = signer();
+        unsafe {
+            (*device).max_fee_rate = Some(1);
+            let (code, reply) = execute(
+                device,
+                &format!(r#"{{"command": "signtx", "psbt": "{}"}}"#, PSBT),
+            );
+            assert_eq!(code, code::BAD_ARGUMENT);
+            assert_eq!(reply["error"], "Fee rate of 2 sat/vB above the maximum");
+            bhwi_device_free(device);
+        }
+    }
+
+    /// Specter answering the queued lines, one per exchange.
+    unsafe extern "C" fn specter(
+        user_data: *mut c_void,
+        command: *const u8,
+        command_len: usize,
+        response: *mut *const u8,
+        response_len: *mut usize,
+    ) -> i32 {
+        let (commands, responses) = &mut *(user_data as *mut (Vec<Vec<u8>>, Vec<&[u8]>));
+        commands.push(std::slice::from_raw_parts(command, command_len).to_vec());
+        if responses.is_empty() {
+            return 1;
+        }
+        let line = responses.remove(0);
+        *response = line.as_ptr();
+        *response_len = line.len();
+        0
+    }
+
+    #[test]
+    fn test_open_with_transport() {
+        let mut exchanges: (Vec<Vec<u8>>, Vec<&[u8]>) = (
+            Vec::new(),
+            vec![b"ACK\r\n", b"4ba43603\r\n", b"ACK\r\n4ba43603\r\n"],
+        );
+        let device_type = CString::new("specter").unwrap();
+        let network = CString::new("testnet").unwrap();
+        let mut device = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                bhwi_device_open_with_transport(
+                    BHWI_ABI_VERSION,
+                    device_type.as_ptr(),
+                    network.as_ptr(),
+                    Some(specter),
+                    &mut exchanges as *mut _ as *mut c_void,
+                    &mut device
+                ),
+                BHWI_OK
+            );
+            let mut fingerprint = [0x00; 4];
+            assert_eq!(
+                bhwi_get_master_fingerprint(device, &mut fingerprint),
+                BHWI_OK
+            );
+            assert_eq!(fingerprint, [0x4b, 0xa4, 0x36, 0x03]);
+            // The device is gone.
+            assert_eq!(
+                bhwi_get_master_fingerprint(device, &mut fingerprint),
+                code::DEVICE_CONN_ERROR
+            );
+            bhwi_device_free(device);
+
+            let device_type = CString::new("bitbox02").unwrap();
+            let mut device = std::ptr::null_mut();
+            assert_eq!(
+                bhwi_device_open_with_transport(
+                    BHWI_ABI_VERSION,
+                    device_type.as_ptr(),
+                    network.as_ptr(),
+                    Some(specter),
+                    std::ptr::null_mut(),
+                    &mut device
+                ),
+                code::BAD_ARGUMENT
+            );
+        }
+        assert_eq!(
+            exchanges.0,
+            vec![
+                b"fingerprint\r".to_vec(),
+                Vec::new(),
+                b"fingerprint\r".to_vec(),
+                b"fingerprint\r".to_vec(),
+            ]
+        );
+    }
+
+    /// Ledger answering the apdus with the mock of its Bitcoin app.
+    unsafe extern "C" fn ledger(
+        user_data: *mut c_void,
+        command: *const u8,
+        command_len: usize,
+        response: *mut *const u8,
+        response_len: *mut usize,
+    ) -> i32 {
+        let (mock, reply) = &mut *(user_data as *mut (MockLedger, Vec<u8>));
+        let command = std::slice::from_raw_parts(command, command_len);
+        // The mock answers without waiting, within the executor of the call.
+        let exchange = futures::FutureExt::now_or_never(mock.exchange(command, false));
+        *reply = exchange.expect("answered").unwrap();
+        *response = reply.as_ptr();
+        *response_len = reply.len();
+        0
+    }
+
+    #[test]
+    fn test_interpreter_events() {
+        let mut mock = (
+            MockLedger::new(&[0x01; 32], Network::Testnet),
+            Vec::<u8>::new(),
+        );
+        let mut events: Vec<(i32, String)> = Vec::new();
+        let device_type = CString::new("ledger").unwrap();
+        let network = CString::new("testnet").unwrap();
+        let mut device = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                bhwi_device_open_with_transport(
+                    BHWI_ABI_VERSION,
+                    device_type.as_ptr(),
+                    network.as_ptr(),
+                    Some(ledger),
+                    &mut mock as *mut _ as *mut c_void,
+                    &mut device
+                ),
+                BHWI_OK
+            );
+            assert_eq!(
+                bhwi_device_set_event_callback(
+                    device,
+                    Some(record),
+                    &mut events as *mut _ as *mut c_void
+                ),
+                BHWI_OK
+            );
+            let path = CString::new("m/84'/1'/0'").unwrap();
+            let mut buffer = empty_buffer();
+            assert_eq!(
+                bhwi_get_xpub(device, path.as_ptr(), true, &mut buffer),
+                BHWI_OK
+            );
+            bhwi_buffer_free(buffer);
+
+            let (code, _) = execute(
+                device,
+                r#"{"command": "getxpub", "path": "m/84'/1'/0'", "display": true}"#,
+            );
+            assert_eq!(code, BHWI_OK);
+            bhwi_device_free(device);
+        }
+        assert_eq!(
+            events,
+            vec![
+                (BHWI_EVENT_COMMAND_STARTED, "getxpub".to_string()),
+                // Reported once, before the interpreter asks for it.
+                (BHWI_EVENT_CONFIRM_ON_DEVICE, "84'/1'/0'".to_string()),
+                (BHWI_EVENT_COMMAND_FINISHED, "getxpub".to_string()),
+                (BHWI_EVENT_COMMAND_STARTED, "getxpub".to_string()),
+                (BHWI_EVENT_CONFIRM_ON_DEVICE, String::new()),
+                (BHWI_EVENT_COMMAND_FINISHED, "getxpub".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpreter() {
+        let device_type = CString::new("specter").unwrap();
+        let network = CString::new("testnet").unwrap();
+        let mut intpr = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                bhwi_interpreter_new(
+                    BHWI_ABI_VERSION,
+                    device_type.as_ptr(),
+                    network.as_ptr(),
+                    &mut intpr
+                ),
+                BHWI_OK
+            );
+            let command = CString::new(r#"{"command": "getmasterfingerprint"}"#).unwrap();
+            let mut transmit = interpreter::BhwiTransmit::none();
+            assert_eq!(bhwi_start(intpr, command.as_ptr(), &mut transmit), BHWI_OK);
+            assert_eq!(transmit.recipient, interpreter::BHWI_RECIPIENT_DEVICE);
+            bhwi_buffer_free(transmit.url);
+            assert_eq!(take_string(transmit.payload), "fingerprint\r");
+
+            let mut transmit = interpreter::BhwiTransmit::none();
+            let data = b"ACK\r\n";
+            assert_eq!(
+                bhwi_exchange(intpr, data.as_ptr(), data.len(), &mut transmit),
+                BHWI_OK
+            );
+            assert_eq!(transmit.recipient, interpreter::BHWI_RECIPIENT_DEVICE);
+            assert_eq!(transmit.payload.len, 0);
+            let data = b"4ba43603\r\n";
+            assert_eq!(
+                bhwi_exchange(intpr, data.as_ptr(), data.len(), &mut transmit),
+                BHWI_OK
+            );
+            assert_eq!(transmit.recipient, interpreter::BHWI_RECIPIENT_NONE);
+
+            let mut buffer = empty_buffer();
+            assert_eq!(bhwi_end(intpr, &mut buffer), BHWI_OK);
+            assert_eq!(take_string(buffer), r#"{"fingerprint":"4ba43603"}"#);
+            // Nothing runs anymore.
+            let mut buffer = empty_buffer();
+            assert_eq!(bhwi_end(intpr, &mut buffer), code::UNKNOWN_ERROR);
+
+            let command = CString::new(r#"{"command": "signtx", "psbt": "x"}"#).unwrap();
+            assert_eq!(
+                bhwi_start(intpr, command.as_ptr(), &mut transmit),
+                code::INVALID_TX
+            );
+            bhwi_interpreter_free(intpr);
+
+            let device_type = CString::new("coldcard").unwrap();
+            let mut intpr = std::ptr::null_mut();
+            assert_eq!(
+                bhwi_interpreter_new(
+                    BHWI_ABI_VERSION,
+                    device_type.as_ptr(),
+                    network.as_ptr(),
+                    &mut intpr
+                ),
+                code::UNKNOWN_DEVICE_TYPE
+            );
+            assert!(intpr.is_null());
+        }
+    }
+
+    #[test]
+    fn test_abi_version() {
+        assert_eq!(bhwi_abi_version(), BHWI_ABI_VERSION);
+        let version = unsafe { CStr::from_ptr(bhwi_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+
+        let mut device = std::ptr::null_mut();
+        let path = CString::new("speculos").unwrap();
+        let network = CString::new("testnet").unwrap();
+        unsafe {
+            assert_eq!(
+                bhwi_device_open(
+                    BHWI_ABI_VERSION + 1,
+                    path.as_ptr(),
+                    network.as_ptr(),
+                    &mut device
+                ),
+                BHWI_ABI_MISMATCH
+            );
+        }
+        assert!(device.is_null());
+        assert!(last_error().starts_with("Library is too old"));
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        unsafe {
+            let mut fingerprint = [0x00; 4];
+            assert_eq!(
+                bhwi_get_master_fingerprint(std::ptr::null_mut(), &mut fingerprint),
+                code::BAD_ARGUMENT
+            );
+            let device = signer();
+            assert_eq!(
+                bhwi_get_master_fingerprint(device, std::ptr::null_mut()),
+                code::BAD_ARGUMENT
+            );
+            bhwi_device_free(device);
+
+            let mut device = std::ptr::null_mut();
+            let path = CString::new("speculos:127.0.0.1:1").unwrap();
+            let network = CString::new("nonet").unwrap();
+            assert_eq!(
+                bhwi_device_open(
+                    BHWI_ABI_VERSION,
+                    path.as_ptr(),
+                    network.as_ptr(),
+                    &mut device
+                ),
+                code::BAD_ARGUMENT
+            );
+            assert_eq!(
+                bhwi_device_open(
+                    BHWI_ABI_VERSION,
+                    std::ptr::null(),
+                    network.as_ptr(),
+                    &mut device
+                ),
+                code::BAD_ARGUMENT
+            );
+            // Nothing listens on the port.
+            let network = CString::new("testnet").unwrap();
+            assert_eq!(
+                bhwi_device_open(
+                    BHWI_ABI_VERSION,
+                    path.as_ptr(),
+                    network.as_ptr(),
+                    &mut device
+                ),
+                code::DEVICE_CONN_ERROR
+            );
+            assert!(device.is_null());
+            assert!(!last_error().is_empty());
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Transport supplied by the application, so that the platforms without
+//! the native USB stacks of the cli, like iOS or Android, reuse the device
+//! protocols over their own USB or BLE links.
+
+use std::ffi::c_void;
+
+use async_trait::async_trait;
+use bhwi::Event;
+use bhwi_async::Transport;
+
+use crate::event::EventSlot;
+
+/// Callback sending the command to the device and returning its response.
+/// An empty command only reads the next bytes sent by the device. The
+/// response is owned by the application and must stay valid until the next
+/// call of the callback or the release of the device. A non zero return
+/// code is a transport error.
+pub type BhwiTransportExchange = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    command: *const u8,
+    command_len: usize,
+    response: *mut *const u8,
+    response_len: *mut usize,
+) -> i32;
+
+pub struct CallbackTransport {
+    exchange: BhwiTransportExchange,
+    user_data: *mut c_void,
+    events: EventSlot,
+}
+
+impl CallbackTransport {
+    pub fn new(exchange: BhwiTransportExchange, user_data: *mut c_void, events: EventSlot) -> Self {
+        Self {
+            exchange,
+            user_data,
+            events,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Transport for CallbackTransport {
+    type Error = std::io::Error;
+
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let mut response: *const u8 = std::ptr::null();
+        let mut response_len = 0;
+        // Safety: the caller registered the callback with this user data.
+        let code = unsafe {
+            (self.exchange)(
+                self.user_data,
+                command.as_ptr(),
+                command.len(),
+                &mut response,
+                &mut response_len,
+            )
+        };
+        if code != 0 {
+            return Err(std::io::Error::other(format!(
+                "transport callback failed with code {}",
+                code
+            )));
+        }
+        if response.is_null() {
+            return Ok(Vec::new());
+        }
+        // Safety: the response is valid until the next call of the callback.
+        Ok(unsafe { std::slice::from_raw_parts(response, response_len) }.to_vec())
+    }
+
+    fn notify(&mut self, event: &Event) {
+        self.events.notify(event)
+    }
+}
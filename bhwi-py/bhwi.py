@@ -0,0 +1,161 @@
+"""Python bindings to bhwi over the C ABI of bhwi-ffi.
+
+The `getxpub`, `signtx`, `displayaddress` and `getmasterfingerprint`
+commands return the dicts of the commands of the `hwi` library, so that its
+users can move to bhwi incrementally. The `Client` is not a
+`HardwareWalletClient`: it takes and returns the xpubs and the psbts as
+strings, not as the objects of `hwi`. The library is loaded from
+`BHWI_LIBRARY` or from the default name of the platform.
+"""
+
+import ctypes
+import ctypes.util
+import json
+import os
+import sys
+
+ABI_VERSION = 1
+OK = 0
+
+
+class BhwiBuffer(ctypes.Structure):
+    _fields_ = [("data", ctypes.POINTER(ctypes.c_uint8)), ("len", ctypes.c_size_t)]
+
+
+class BhwiError(Exception):
+    """Error of a call, with the HWI error code."""
+
+    def __init__(self, message, code):
+        super().__init__(message)
+        self.code = code
+
+
+def _load():
+    path = os.environ.get("BHWI_LIBRARY") or ctypes.util.find_library("bhwi_ffi")
+    if path is None:
+        path = {"darwin": "libbhwi_ffi.dylib", "win32": "bhwi_ffi.dll"}.get(
+            sys.platform, "libbhwi_ffi.so"
+        )
+    lib = ctypes.CDLL(path)
+    lib.bhwi_abi_version.restype = ctypes.c_uint32
+    lib.bhwi_enumerate.argtypes = [ctypes.POINTER(BhwiBuffer)]
+    lib.bhwi_device_open.argtypes = [
+        ctypes.c_uint32,
+        ctypes.c_char_p,
+        ctypes.c_char_p,
+        ctypes.POINTER(ctypes.c_void_p),
+    ]
+    lib.bhwi_device_free.argtypes = [ctypes.c_void_p]
+    lib.bhwi_execute.argtypes = [ctypes.c_void_p, ctypes.c_char_p, ctypes.POINTER(BhwiBuffer)]
+    lib.bhwi_last_error.argtypes = [ctypes.POINTER(BhwiBuffer)]
+    lib.bhwi_buffer_free.argtypes = [BhwiBuffer]
+    if lib.bhwi_abi_version() != ABI_VERSION:
+        raise BhwiError("Unsupported bhwi library ABI version", -100)
+    return lib
+
+
+_lib = None
+
+
+def _library():
+    global _lib
+    if _lib is None:
+        _lib = _load()
+    return _lib
+
+
+def _take(buffer):
+    data = ctypes.string_at(buffer.data, buffer.len) if buffer.len else b""
+    _library().bhwi_buffer_free(buffer)
+    return data.decode()
+
+
+def _check(code):
+    if code != OK:
+        buffer = BhwiBuffer()
+        _library().bhwi_last_error(ctypes.byref(buffer))
+        raise BhwiError(_take(buffer), code)
+
+
+def enumerate(password=None, expert=False, chain=None, allow_emulators=True):
+    """Lists the connected devices with their type and path, the arguments
+    of `hwi` are accepted and ignored."""
+    buffer = BhwiBuffer()
+    _check(_library().bhwi_enumerate(ctypes.byref(buffer)))
+    return json.loads(_take(buffer))
+
+
+class Client:
+    """Opened device, the xpubs and the psbts are base58 and base64 strings."""
+
+    def __init__(self, path, chain="bitcoin"):
+        self._device = ctypes.c_void_p()
+        _check(
+            _library().bhwi_device_open(
+                ABI_VERSION, path.encode(), chain.encode(), ctypes.byref(self._device)
+            )
+        )
+
+    def _execute(self, command, **params):
+        buffer = BhwiBuffer()
+        request = json.dumps(dict(command=command, **params)).encode()
+        code = _library().bhwi_execute(self._device, request, ctypes.byref(buffer))
+        reply = _take(buffer)
+        if code != OK:
+            # No reply is written if the call failed before running the
+            # command, the error is the last one of the library.
+            if not reply:
+                _check(code)
+            raise BhwiError(json.loads(reply).get("error", ""), code)
+        return json.loads(reply)
+
+    def get_master_fingerprint(self):
+        return bytes.fromhex(self._execute("getmasterfingerprint")["fingerprint"])
+
+    def get_xpub(self, path, display=False):
+        return self._execute("getxpub", path=path, display=display)["xpub"]
+
+    def sign_tx(self, psbt):
+        """Signs the base64 psbt and returns the base64 signed psbt."""
+        return self._execute("signtx", psbt=psbt)["psbt"]
+
+    def display_address(self, path, descriptor=None, name=None, hmac=None):
+        """Displays the address at the path, ending with the change and the
+        index, of the registered wallet of the descriptor if given."""
+        params = dict(path=path)
+        if descriptor is not None:
+            params.update(descriptor=descriptor, name=name or "", hmac=hmac)
+        return self._execute("displayaddress", **params)["address"]
+
+    def close(self):
+        if self._device:
+            _library().bhwi_device_free(self._device)
+            self._device = ctypes.c_void_p()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *args):
+        self.close()
+
+
+def get_client(device_type, device_path, password=None, expert=False, chain="bitcoin"):
+    """Opens the device at the path, the device type is deduced from it."""
+    return Client(device_path, chain)
+
+
+def getmasterfingerprint(client):
+    return {"fingerprint": client.get_master_fingerprint().hex()}
+
+
+def getxpub(client, path, expert=False):
+    return {"xpub": client.get_xpub(path)}
+
+
+def signtx(client, psbt):
+    signed = client.sign_tx(psbt)
+    return {"psbt": signed, "signed": signed != psbt}
+
+
+def displayaddress(client, path):
+    return {"address": client.display_address(path)}
@@ -0,0 +1,209 @@
+//! Async serial transport for the devices connected through a USB serial
+//! port, like the Jade and the Specter DIY. The port is driven on a thread
+//! of its own, whatever the async runtime of the host, and the responses are
+//! cut by the codec of the backend.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bhwi::devices::{self, DeviceKind};
+use bhwi_async::Transport;
+use serialport::{SerialPort, SerialPortType};
+
+pub const BAUD_RATE: u32 = 115200;
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Finds the end of the first response of the bytes read.
+pub trait Codec {
+    /// Returns the length of the first complete frame of the buffer, if
+    /// it has one.
+    fn frame_len(&self, buffer: &[u8]) -> Option<usize>;
+}
+
+impl<T: Codec + ?Sized> Codec for Arc<T> {
+    fn frame_len(&self, buffer: &[u8]) -> Option<usize> {
+        self.as_ref().frame_len(buffer)
+    }
+}
+
+/// Frames of the Jade, each message is a whole CBOR value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn frame_len(&self, buffer: &[u8]) -> Option<usize> {
+        let mut values =
+            serde_cbor::Deserializer::from_slice(buffer).into_iter::<serde_cbor::Value>();
+        match values.next() {
+            Some(Ok(_)) => Some(values.byte_offset()),
+            _ => None,
+        }
+    }
+}
+
+/// Frames of the Specter DIY, each message is a line ended by a new line.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineCodec;
+
+impl Codec for LineCodec {
+    fn frame_len(&self, buffer: &[u8]) -> Option<usize> {
+        buffer.iter().position(|b| *b == b'\n').map(|i| i + 1)
+    }
+}
+
+/// Returns the codec of the device kind, if it is connected over serial.
+pub fn codec(kind: DeviceKind) -> Option<Arc<dyn Codec + Send + Sync>> {
+    match kind {
+        DeviceKind::Jade => Some(Arc::new(CborCodec)),
+        DeviceKind::Specter => Some(Arc::new(LineCodec)),
+        _ => None,
+    }
+}
+
+/// Serial port of a known device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialDevice {
+    pub path: String,
+    pub kind: DeviceKind,
+    pub vid: u16,
+    pub pid: u16,
+}
+
+/// Lists the serial ports of the devices having a codec, identified by their
+/// USB vendor and product ids.
+pub fn enumerate() -> Result<Vec<SerialDevice>, serialport::Error> {
+    let mut res = Vec::new();
+    for port in serialport::available_ports()? {
+        if let SerialPortType::UsbPort(usb_info) = port.port_type {
+            match devices::identify(usb_info.vid, usb_info.pid) {
+                Some(kind) if codec(kind).is_some() => res.push(SerialDevice {
+                    path: port.port_name,
+                    kind,
+                    vid: usb_info.vid,
+                    pid: usb_info.pid,
+                }),
+                _ => {}
+            }
+        }
+    }
+    Ok(res)
+}
+
+pub struct SerialTransport<C> {
+    port: Option<Box<dyn SerialPort>>,
+    codec: C,
+    /// Bytes read after the last frame, kept for the next exchange.
+    buffer: Vec<u8>,
+}
+
+impl<C> SerialTransport<C> {
+    pub fn open(path: &str, codec: C) -> Result<Self, serialport::Error> {
+        let port = serialport::new(path, BAUD_RATE)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+        Ok(Self {
+            port: Some(port),
+            codec,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl<C> SerialTransport<C> {
+    /// Runs the operation on the port in a thread, its result is sent back
+    /// over a channel.
+    async fn blocking<F, R>(&mut self, f: F) -> Result<R, std::io::Error>
+    where
+        F: FnOnce(&mut dyn SerialPort, &mut Vec<u8>) -> Result<R, std::io::Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut port = self
+            .port
+            .take()
+            .ok_or_else(|| std::io::Error::other("serial port lost by a previous exchange"))?;
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        std::thread::Builder::new()
+            .name("bhwi-serial".to_string())
+            .spawn(move || {
+                let res = f(port.as_mut(), &mut buffer);
+                let _ = sender.send((port, buffer, res));
+            })?;
+        let (port, buffer, res) = receiver
+            .await
+            .map_err(|_| std::io::Error::other("serial port thread panicked"))?;
+        self.port = Some(port);
+        self.buffer = buffer;
+        res
+    }
+}
+
+/// Writes the command and reads the port until the buffer has a frame.
+fn exchange<C: Codec>(
+    port: &mut dyn SerialPort,
+    codec: &C,
+    command: &[u8],
+    buffer: &mut Vec<u8>,
+) -> Result<Vec<u8>, std::io::Error> {
+    if !command.is_empty() {
+        port.write_all(command)?;
+        port.flush()?;
+    }
+    let mut chunk = [0u8; 1024];
+    loop {
+        if let Some(len) = codec.frame_len(buffer) {
+            return Ok(buffer.drain(..len).collect());
+        }
+        match port.read(&mut chunk) {
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            // The device may wait for the user, keep reading.
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<C> Transport for SerialTransport<C>
+where
+    C: Codec + Clone + Send + 'static,
+{
+    type Error = std::io::Error;
+
+    /// An empty command only reads the next frame.
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
+        let codec = self.codec.clone();
+        let command = command.to_vec();
+        self.blocking(move |port, buffer| exchange(port, &codec, &command, buffer))
+            .await
+    }
+
+    async fn send(&mut self, command: &[u8], _encrypted: bool) -> Result<(), Self::Error> {
+        let command = command.to_vec();
+        self.blocking(move |port, _| {
+            port.write_all(&command)?;
+            port.flush()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs() {
+        let value = serde_cbor::to_vec(&serde_cbor::Value::Text("abc".to_string())).unwrap();
+        let mut buffer = value.clone();
+        assert_eq!(CborCodec.frame_len(&buffer[..2]), None);
+        buffer.extend_from_slice(&value[..1]);
+        assert_eq!(CborCodec.frame_len(&buffer), Some(value.len()));
+
+        assert_eq!(LineCodec.frame_len(b"ACK\r\nxpub"), Some(5));
+        assert_eq!(LineCodec.frame_len(b"xpub"), None);
+        assert!(codec(DeviceKind::Specter).is_some());
+        assert!(codec(DeviceKind::Coldcard).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+//! Collection of the master fingerprints and xpubs of every device granted
+//! to the page, for the setup of the multisig wallets: the devices are
+//! opened and queried concurrently instead of one at a time.
+
+use std::str::FromStr;
+
+use bhwi::devices::{self, DeviceKind};
+use bhwi_async::{
+    coldcard::Coldcard,
+    exclusive::Exclusive,
+    transport::{coldcard_hid::ColdcardTransportHID, ledger_hid::LedgerTransportHID},
+    Ledger,
+};
+use bitcoin::{bip32::DerivationPath, Network};
+use futures::future;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::HidDevice;
+
+use crate::{
+    error::{BhwiError, ErrorKind},
+    webhid::{granted_known_devices, WebHidDevice, WebHidDeviceInfo},
+    HWI,
+};
+
+/// Master fingerprint and xpub of a device, or the error of its collection.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct DeviceXpub {
+    pub device: WebHidDeviceInfo,
+    pub fingerprint: Option<String>,
+    pub xpub: Option<String>,
+    pub error: Option<BhwiError>,
+}
+
+async fn query(
+    device: HidDevice,
+    network: &str,
+    path: &str,
+) -> Result<(String, String), BhwiError> {
+    let kind = devices::identify(device.vendor_id(), device.product_id());
+    let device = WebHidDevice::open(device, JsValue::UNDEFINED)
+        .await
+        .ok_or_else(BhwiError::not_connected)?;
+    let lock = device.session_lock();
+    let pacing = device.pacing();
+    let mut hwi: Box<dyn HWI> = match kind {
+        Some(kind) if kind.is_ledger() => Box::new(Exclusive::new(
+            Ledger::new(LedgerTransportHID::new(device).with_pacing(pacing)),
+            lock,
+        )),
+        Some(DeviceKind::Coldcard) => Box::new(Exclusive::new(
+            Coldcard::new(
+                ColdcardTransportHID::new(device).with_pacing(pacing),
+                &mut rand_core::OsRng,
+            ),
+            lock,
+        )),
+        _ => {
+            return Err(BhwiError::new(
+                ErrorKind::Unsupported,
+                "unsupported_device",
+                "The device is not a HID signer",
+            ))
+        }
+    };
+    hwi.unlock(network).await?;
+    let fingerprint = hwi.get_mfg().await?;
+    let xpub = hwi.get_xpub(path, false).await?;
+    Ok((fingerprint, xpub))
+}
+
+async fn collect(device: HidDevice, network: &str, path: &str) -> DeviceXpub {
+    let info = WebHidDeviceInfo::from(&device);
+    let res = query(device.clone(), network, path).await;
+    // The devices opened by the collection are closed, the ones of the page
+    // are left open.
+    if !info.opened && device.opened() {
+        let _ = JsFuture::from(device.close()).await;
+    }
+    let (fingerprint, xpub, error) = match res {
+        Ok((fingerprint, xpub)) => (Some(fingerprint), Some(xpub), None),
+        Err(e) => (None, None, Some(e)),
+    };
+    DeviceXpub {
+        device: info,
+        fingerprint,
+        xpub,
+        error,
+    }
+}
+
+/// Returns the master fingerprint and the xpub at the path of every Ledger
+/// and Coldcard granted to the page, queried concurrently. The failure of a
+/// device is reported in its result, the others are still collected.
+#[wasm_bindgen]
+pub async fn collect_xpubs(network: &str, path: &str) -> Result<Vec<DeviceXpub>, BhwiError> {
+    Network::from_str(network)
+        .map_err(|e| BhwiError::invalid_argument(format!("Invalid network: {}", e)))?;
+    DerivationPath::from_str(path)
+        .map_err(|e| BhwiError::invalid_argument(format!("Invalid path: {}", e)))?;
+    let hid = web_sys::window()
+        .ok_or_else(BhwiError::not_connected)?
+        .navigator()
+        .hid();
+    let devices = granted_known_devices(&hid).await;
+    Ok(future::join_all(
+        devices
+            .into_iter()
+            .map(|device| collect(device, network, path)),
+    )
+    .await)
+}
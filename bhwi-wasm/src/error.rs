@@ -0,0 +1,377 @@
+//! Errors thrown to the page: the errors of the interpreters, of the
+//! transports and of the arguments are all thrown as a [`BhwiError`], so
+//! that the page branches on their kind.
+
+use std::fmt::Debug;
+
+use bhwi::{
+    common,
+    ledger::{apdu::ApduError, LedgerError},
+};
+use bhwi_async::transport::{coldcard_hid::ColdcardHIDError, ledger_hid::LedgerHIDError};
+use wasm_bindgen::prelude::*;
+
+use crate::webhid::WebHidError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The user rejected the request on the device.
+    DeniedByUser,
+    /// The device must be unlocked with its PIN.
+    DeviceLocked,
+    /// The app is not open or not installed on the device.
+    AppNotOpen,
+    /// Another command is exchanging with the device.
+    Busy,
+    /// The device did not answer in time.
+    Timeout,
+    /// The command was cancelled or aborted by the page.
+    Cancelled,
+    /// No device is connected, or it was closed or unplugged.
+    NotConnected,
+    /// The device or its keys are for another wallet or network.
+    MismatchedDevice,
+    /// The device does not support the command.
+    Unsupported,
+    /// The command was denied by the rules of the host.
+    PolicyDenied,
+    /// An argument of the page is invalid.
+    InvalidArgument,
+    /// The browser failed to exchange with the device or the server.
+    Transport,
+    /// Any other failure of the device or of its answer.
+    Device,
+    /// The package panicked, see [`crate::panic`].
+    Panic,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::DeniedByUser => "DeniedByUser",
+            ErrorKind::DeviceLocked => "DeviceLocked",
+            ErrorKind::AppNotOpen => "AppNotOpen",
+            ErrorKind::Busy => "Busy",
+            ErrorKind::Timeout => "Timeout",
+            ErrorKind::Cancelled => "Cancelled",
+            ErrorKind::NotConnected => "NotConnected",
+            ErrorKind::MismatchedDevice => "MismatchedDevice",
+            ErrorKind::Unsupported => "Unsupported",
+            ErrorKind::PolicyDenied => "PolicyDenied",
+            ErrorKind::InvalidArgument => "InvalidArgument",
+            ErrorKind::Transport => "Transport",
+            ErrorKind::Device => "Device",
+            ErrorKind::Panic => "Panic",
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorKind {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "DeniedByUser" => Ok(ErrorKind::DeniedByUser),
+            "DeviceLocked" => Ok(ErrorKind::DeviceLocked),
+            "AppNotOpen" => Ok(ErrorKind::AppNotOpen),
+            "Busy" => Ok(ErrorKind::Busy),
+            "Timeout" => Ok(ErrorKind::Timeout),
+            "Cancelled" => Ok(ErrorKind::Cancelled),
+            "NotConnected" => Ok(ErrorKind::NotConnected),
+            "MismatchedDevice" => Ok(ErrorKind::MismatchedDevice),
+            "Unsupported" => Ok(ErrorKind::Unsupported),
+            "PolicyDenied" => Ok(ErrorKind::PolicyDenied),
+            "InvalidArgument" => Ok(ErrorKind::InvalidArgument),
+            "Transport" => Ok(ErrorKind::Transport),
+            "Device" => Ok(ErrorKind::Device),
+            "Panic" => Ok(ErrorKind::Panic),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Error thrown by the methods of the package, with its kind, a short code
+/// of its cause, its message and the status word of the device if any.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct BhwiError {
+    kind: ErrorKind,
+    code: &'static str,
+    message: String,
+    status_word: Option<u16>,
+    cause: JsValue,
+}
+
+impl BhwiError {
+    pub fn new(kind: ErrorKind, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            code,
+            message: message.into(),
+            status_word: None,
+            cause: JsValue::UNDEFINED,
+        }
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidArgument, "invalid_argument", message)
+    }
+
+    pub fn not_connected() -> Self {
+        Self::new(
+            ErrorKind::NotConnected,
+            "not_connected",
+            "Device not connected",
+        )
+    }
+
+    pub fn with_status_word(mut self, status_word: Option<u16>) -> Self {
+        self.status_word = status_word;
+        self
+    }
+
+    /// Sets the error of the browser causing this one.
+    pub fn with_cause(mut self, cause: JsValue) -> Self {
+        self.cause = cause;
+        self
+    }
+
+    pub fn error_kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+#[wasm_bindgen]
+impl BhwiError {
+    #[wasm_bindgen(getter, unchecked_return_type = "BhwiErrorKind")]
+    pub fn kind(&self) -> String {
+        self.kind.as_str().to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> String {
+        self.code.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn status_word(&self) -> Option<u16> {
+        self.status_word
+    }
+
+    /// The error of the browser causing this one, undefined if none.
+    #[wasm_bindgen(getter)]
+    pub fn cause(&self) -> JsValue {
+        self.cause.clone()
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        format!("{}: {}", self.kind.as_str(), self.message)
+    }
+}
+
+impl From<LedgerError> for BhwiError {
+    fn from(e: LedgerError) -> Self {
+        let (kind, code) = match &e {
+            LedgerError::DeniedByUser => (ErrorKind::DeniedByUser, "denied_by_user"),
+            LedgerError::DeviceLocked => (ErrorKind::DeviceLocked, "device_locked"),
+            LedgerError::AppNotOpen => (ErrorKind::AppNotOpen, "app_not_open"),
+            LedgerError::AppNotInstalled => (ErrorKind::AppNotOpen, "app_not_installed"),
+            LedgerError::FailedToOpenApp(_) => (ErrorKind::AppNotOpen, "failed_to_open_app"),
+            LedgerError::Timeout => (ErrorKind::Timeout, "timeout"),
+            LedgerError::Cancelled => (ErrorKind::Cancelled, "cancelled"),
+            LedgerError::Interrupted => (ErrorKind::Cancelled, "interrupted"),
+            LedgerError::MismatchedDevice => (ErrorKind::MismatchedDevice, "mismatched_device"),
+            LedgerError::NetworkMismatch => (ErrorKind::MismatchedDevice, "network_mismatch"),
+            LedgerError::UnsupportedCommand(_) => (ErrorKind::Unsupported, "unsupported_command"),
+            LedgerError::UnsupportedByLegacyApp(_) => {
+                (ErrorKind::Unsupported, "unsupported_by_legacy_app")
+            }
+            LedgerError::UnsupportedSighash(_) => {
+                (ErrorKind::InvalidArgument, "unsupported_sighash")
+            }
+            LedgerError::UnusualPath(_) => (ErrorKind::InvalidArgument, "unusual_path"),
+            LedgerError::InvalidPolicy(_) => (ErrorKind::InvalidArgument, "invalid_policy"),
+            LedgerError::AddressNotOwned => (ErrorKind::InvalidArgument, "address_not_owned"),
+            LedgerError::MissingCommandInfo(_) => {
+                (ErrorKind::InvalidArgument, "missing_command_info")
+            }
+            LedgerError::WrongParameters(_) => (ErrorKind::Device, "wrong_parameters"),
+            LedgerError::Status(_) => (ErrorKind::Device, "status"),
+            LedgerError::Detailed(..) => (ErrorKind::Device, "device"),
+            LedgerError::Apdu(_) => (ErrorKind::Device, "apdu"),
+            LedgerError::Store(_) => (ErrorKind::Device, "store"),
+            LedgerError::UnexpectedResult(_) => (ErrorKind::Device, "unexpected_result"),
+            LedgerError::NoErrorOrResult => (ErrorKind::Device, "no_error_or_result"),
+        };
+        let status_word = match &e {
+            LedgerError::WrongParameters(sw)
+            | LedgerError::Status(sw)
+            | LedgerError::Detailed(sw, _) => Some(*sw as u16),
+            LedgerError::Apdu(ApduError::StatusWordUnknown(sw)) => Some(*sw),
+            _ => None,
+        };
+        let message = match &e {
+            LedgerError::Detailed(_, detail) => format!("Ledger error: {}", detail),
+            _ => format!("Ledger error: {:?}", e),
+        };
+        Self::new(kind, code, message).with_status_word(status_word)
+    }
+}
+
+impl From<common::Error> for BhwiError {
+    fn from(e: common::Error) -> Self {
+        let kind = match &e {
+            common::Error::UserRefused | common::Error::AuthenticationRefused => {
+                ErrorKind::DeniedByUser
+            }
+            common::Error::DeviceLocked => ErrorKind::DeviceLocked,
+            common::Error::DeviceBusy => ErrorKind::Busy,
+            common::Error::Timeout => ErrorKind::Timeout,
+            common::Error::Cancelled => ErrorKind::Cancelled,
+            common::Error::MismatchedDevice | common::Error::NetworkMismatch => {
+                ErrorKind::MismatchedDevice
+            }
+            common::Error::DeviceNotConnected(_) => ErrorKind::NotConnected,
+            common::Error::PolicyDenied(_) => ErrorKind::PolicyDenied,
+            common::Error::UnsupportedCommand(_) => ErrorKind::Unsupported,
+            common::Error::UnsupportedSighash(_)
+            | common::Error::UnusualPath(_)
+            | common::Error::InvalidPolicy(_)
+            | common::Error::AddressNotOwned
+            | common::Error::KeyMismatch { .. } => ErrorKind::InvalidArgument,
+            common::Error::Encryption(_)
+            | common::Error::NoErrorOrResult
+            | common::Error::MissingCommandInfo(_)
+            | common::Error::UnexpectedResult(_)
+            | common::Error::Rpc(..)
+            | common::Error::Serialization(_)
+            | common::Error::Request(_)
+            | common::Error::InvalidDeviceSignature(_)
+            | common::Error::Device(_) => ErrorKind::Device,
+        };
+        let message = match &e {
+            common::Error::Device(message) => format!("Device error: {}", message),
+            _ => format!("Device error: {:?}", e),
+        };
+        Self::new(
+            kind,
+            bhwi_async::Error::<(), ()>::Interpreter(e).code(),
+            message,
+        )
+    }
+}
+
+/// The errors of the transports keep their kind, the errors of the http
+/// clients are only described.
+impl<E: Into<BhwiError>, F: Debug> From<bhwi_async::Error<E, F>> for BhwiError {
+    fn from(e: bhwi_async::Error<E, F>) -> Self {
+        match e {
+            bhwi_async::Error::Interpreter(e) => e.into(),
+            bhwi_async::Error::Transport(e) => e.into(),
+            bhwi_async::Error::HttpClient(e) => Self::new(
+                ErrorKind::Transport,
+                "http_client",
+                format!("Http client error: {:?}", e),
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for BhwiError {
+    fn from(e: std::io::Error) -> Self {
+        let kind = match e.kind() {
+            std::io::ErrorKind::NotConnected | std::io::ErrorKind::BrokenPipe => {
+                ErrorKind::NotConnected
+            }
+            std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            std::io::ErrorKind::Interrupted => ErrorKind::Cancelled,
+            std::io::ErrorKind::WouldBlock => ErrorKind::Busy,
+            _ => ErrorKind::Transport,
+        };
+        Self::new(kind, "transport", e.to_string())
+    }
+}
+
+impl From<LedgerHIDError> for BhwiError {
+    fn from(e: LedgerHIDError) -> Self {
+        match e {
+            LedgerHIDError::Hid(e) => e.into(),
+            LedgerHIDError::Comm(message) => Self::new(ErrorKind::Transport, "framing", message),
+        }
+    }
+}
+
+impl From<ColdcardHIDError> for BhwiError {
+    fn from(e: ColdcardHIDError) -> Self {
+        match e {
+            ColdcardHIDError::Hid(e) => e.into(),
+            ColdcardHIDError::Comm(message) => Self::new(ErrorKind::Transport, "framing", message),
+        }
+    }
+}
+
+impl From<WebHidError> for BhwiError {
+    fn from(e: WebHidError) -> Self {
+        let kind = match e {
+            WebHidError::Closed | WebHidError::Disconnected => ErrorKind::NotConnected,
+            WebHidError::Timeout => ErrorKind::Timeout,
+            WebHidError::Aborted => ErrorKind::Cancelled,
+            WebHidError::Busy => ErrorKind::Busy,
+            WebHidError::SendFailed(_) | WebHidError::CloseFailed(_) => ErrorKind::Transport,
+        };
+        let error = Self::new(kind, e.code(), e.to_string());
+        match e {
+            WebHidError::SendFailed(cause) | WebHidError::CloseFailed(cause) => {
+                error.with_cause(cause)
+            }
+            _ => error,
+        }
+    }
+}
+
+/// The errors of the browser, like a rejected promise.
+impl From<JsValue> for BhwiError {
+    fn from(e: JsValue) -> Self {
+        let message = e
+            .as_string()
+            .unwrap_or_else(|| format!("Browser error: {:?}", e));
+        Self::new(ErrorKind::Transport, "browser", message).with_cause(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bhwi::ledger::apdu::StatusWord;
+
+    #[test]
+    fn test_error_kinds() {
+        let e = BhwiError::from(LedgerError::from(StatusWord::Deny));
+        assert_eq!(e.kind(), "DeniedByUser");
+        assert_eq!(e.code(), "denied_by_user");
+
+        let e = BhwiError::from(LedgerError::from(StatusWord::BadState));
+        assert_eq!(e.error_kind(), ErrorKind::Device);
+        assert_eq!(e.status_word(), Some(0xB007));
+
+        let e = BhwiError::from(bhwi_async::Error::<BhwiError, ()>::Interpreter(
+            common::Error::DeviceBusy,
+        ));
+        assert_eq!((e.error_kind(), e.code), (ErrorKind::Busy, "device_busy"));
+        let e = BhwiError::from(bhwi_async::Error::<LedgerHIDError, ()>::Transport(
+            LedgerHIDError::Hid(std::io::ErrorKind::TimedOut.into()),
+        ));
+        assert_eq!(e.kind(), "Timeout");
+
+        // The kinds of the errors answered to a worker are kept.
+        for kind in [ErrorKind::DeniedByUser, ErrorKind::Busy, ErrorKind::Panic] {
+            assert_eq!(kind.as_str().parse(), Ok(kind));
+        }
+        assert!("Unknown".parse::<ErrorKind>().is_err());
+    }
+}
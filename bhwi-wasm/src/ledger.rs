@@ -1,20 +1,299 @@
-use super::webhid::WebHidDevice;
+use std::str::FromStr;
+
+use super::{error::BhwiError, timer, types, webhid::WebHidDevice};
 use async_trait::async_trait;
-use bhwi_async::transport::Channel;
+use bhwi::{
+    ledger::{
+        apdu::ApduCommand,
+        model::{Confirmation, DeviceModel},
+        psbt, LedgerCommand, LedgerError, LedgerInterpreter, LedgerResponse, RetryPolicy,
+        WalletPolicy,
+    },
+    Event, Interpreter as _,
+};
+use bhwi_async::{
+    exclusive::SessionGuard,
+    transport::{ledger_hid::LEDGER_VID, Channel},
+};
+use bitcoin::bip32::{DerivationPath, Xpub};
+use wasm_bindgen::prelude::*;
 
+/// The transports frame the data themselves, the reports are written and
+/// read as is.
 #[async_trait(?Send)]
 impl Channel for WebHidDevice {
     async fn send(&self, data: &[u8]) -> Result<usize, std::io::Error> {
-        self.write(data).await;
+        self.write_report(data).await?;
         Ok(data.len())
     }
     async fn receive(&mut self, data: &mut [u8]) -> Result<usize, std::io::Error> {
-        if let Some(array) = self.read().await {
-            let length = array.len();
-            data.copy_from_slice(&array);
-            Ok(length)
-        } else {
-            Ok(0)
+        let array = self.read_report(self.read_timeout_ms).await?;
+        let length = array.len();
+        data.copy_from_slice(&array);
+        Ok(length)
+    }
+    async fn sleep(&self, ms: u32) {
+        WebHidDevice::sleep(self, ms).await
+    }
+    async fn reopen(&mut self) -> Result<(), std::io::Error> {
+        Ok(self.reconnect().await?)
+    }
+}
+
+/// Capabilities of the Ledger model, for the copy of the UI.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct LedgerModel {
+    pub name: String,
+    /// The flows are drawn on a touch screen, the review is scrolled.
+    pub touch_screen: bool,
+    /// The user holds to sign instead of pressing both buttons.
+    pub hold_to_sign: bool,
+    /// The displayed address can be shown as a QR code on the device.
+    pub address_qr: bool,
+    pub ble: bool,
+    /// Recommended time to wait for the user to confirm.
+    pub confirmation_timeout_ms: u32,
+}
+
+impl From<DeviceModel> for LedgerModel {
+    fn from(model: DeviceModel) -> Self {
+        Self {
+            name: model.kind.name().to_string(),
+            touch_screen: model.touch_screen,
+            hold_to_sign: model.confirmation == Confirmation::HoldToSign,
+            address_qr: model.address_qr,
+            ble: model.ble,
+            confirmation_timeout_ms: model.confirmation_timeout_ms.min(u32::MAX as u64) as u32,
+        }
+    }
+}
+
+type Interpreter = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+/// Device of the client: the WebHID device of the page, or the device served
+/// by the page to the worker running the client, see [`crate::worker`].
+enum Link {
+    Hid(WebHidDevice),
+    #[cfg(feature = "worker")]
+    Port(crate::worker::PortTransport),
+}
+
+impl Link {
+    /// Takes the lock of the device, the page takes it for the worker.
+    fn try_lock(&self) -> Result<Option<SessionGuard>, BhwiError> {
+        match self {
+            Link::Hid(device) => Ok(Some(device.try_lock()?)),
+            #[cfg(feature = "worker")]
+            Link::Port(_) => Ok(None),
+        }
+    }
+
+    async fn exchange_apdu(&mut self, apdu: &[u8]) -> Result<Vec<u8>, BhwiError> {
+        match self {
+            Link::Hid(device) => device.exchange_apdu(apdu).await,
+            #[cfg(feature = "worker")]
+            Link::Port(port) => port.exchange_apdu(apdu).await,
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<(), BhwiError> {
+        match self {
+            Link::Hid(device) => Ok(device.reconnect().await?),
+            #[cfg(feature = "worker")]
+            Link::Port(port) => port.reconnect().await,
+        }
+    }
+}
+
+/// Ledger device running the commands of its interpreter over WebHID, the
+/// apdus are exchanged internally.
+#[wasm_bindgen]
+pub struct LedgerClient {
+    link: Link,
+    /// Probes of a locked device and the delay between them.
+    unlock_wait: Option<(usize, i32)>,
+    retry_policy: Option<RetryPolicy>,
+    #[cfg(feature = "storage")]
+    storage: Option<crate::storage::Storage>,
+}
+
+#[wasm_bindgen]
+impl LedgerClient {
+    /// Connects to a Ledger already granted to the page, or requests one.
+    pub async fn connect(
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Result<LedgerClient, BhwiError> {
+        let device = WebHidDevice::get_or_request_webhid_device(LEDGER_VID, None, on_close_cb)
+            .await
+            .ok_or_else(|| crate::connection_failed("ledger"))?;
+        Ok(Self::from_device(device))
+    }
+
+    pub fn from_device(device: WebHidDevice) -> LedgerClient {
+        Self::from_link(Link::Hid(device))
+    }
+
+    /// Runs the commands in a worker, over the port of the channel whose
+    /// other port is served by the page with a [`crate::worker::HidProxy`].
+    #[cfg(feature = "worker")]
+    #[wasm_bindgen(js_name = fromPort)]
+    pub fn from_port(port: web_sys::MessagePort) -> LedgerClient {
+        Self::from_link(Link::Port(crate::worker::PortTransport::new(port)))
+    }
+
+    fn from_link(link: Link) -> LedgerClient {
+        Self {
+            link,
+            unlock_wait: None,
+            retry_policy: None,
+            #[cfg(feature = "storage")]
+            storage: None,
+        }
+    }
+
+    /// Returns the capabilities of the model of the device, undefined if its
+    /// product id is unknown or if the device is served by the page.
+    pub fn model(&self) -> Option<LedgerModel> {
+        match &self.link {
+            Link::Hid(device) => {
+                DeviceModel::from_product_id(device.product_id()).map(LedgerModel::from)
+            }
+            #[cfg(feature = "worker")]
+            Link::Port(_) => None,
+        }
+    }
+
+    /// Waits for the user to unlock a locked device instead of failing, by
+    /// probing it every interval until the given number of probes.
+    pub fn set_unlock_wait(&mut self, probes: usize, interval_ms: i32) {
+        self.unlock_wait = Some((probes, interval_ms));
+    }
+
+    /// Sends the last apdu again on the transient failures of the device,
+    /// waiting the backoff doubled at each retry.
+    pub fn set_retry_policy(&mut self, max_retries: usize, backoff_ms: u32) {
+        self.retry_policy = Some(RetryPolicy {
+            max_retries,
+            backoff_ms,
+        });
+    }
+
+    /// Keeps the proofs of registration of the policies in the storage, the
+    /// policies registered in a previous session are not registered again.
+    #[cfg(feature = "storage")]
+    pub fn set_storage(&mut self, storage: &crate::storage::Storage) {
+        self.storage = Some(storage.clone());
+    }
+
+    async fn run(&mut self, command: LedgerCommand) -> Result<LedgerResponse, BhwiError> {
+        let _guard = self.link.try_lock()?;
+        let mut interpreter = Interpreter::default();
+        if let Some((probes, interval_ms)) = self.unlock_wait {
+            interpreter = interpreter.with_unlock_wait(probes, interval_ms.max(0) as u32);
+        }
+        if let Some(policy) = self.retry_policy {
+            interpreter = interpreter.with_retry_policy(policy);
+        }
+        #[cfg(feature = "storage")]
+        if let Some(storage) = &self.storage {
+            interpreter = interpreter.with_hmac_store(storage.clone());
+        }
+        let mut apdu = Some(interpreter.start(command)?);
+        let mut reenumerating = false;
+        while let Some(command) = apdu {
+            let answer = self.link.exchange_apdu(&command.encode()).await?;
+            apdu = interpreter.exchange(answer)?;
+            while let Some(event) = interpreter.poll_event() {
+                match event {
+                    Event::AwaitingUnlock(delay_ms) | Event::Retrying(_, delay_ms) => {
+                        timer::timeout(delay_ms.min(i32::MAX as u32) as i32).await?;
+                    }
+                    Event::Reenumerating => reenumerating = true,
+                    _ => {}
+                }
+            }
+        }
+        // The device is reopened after an app switch, for the next commands.
+        if reenumerating {
+            self.link.reconnect().await?;
+        }
+        Ok(interpreter.end()?)
+    }
+
+    /// Runs the command, see the LedgerCommand and LedgerResponse types.
+    #[wasm_bindgen(unchecked_return_type = "LedgerResponse")]
+    pub async fn send(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "LedgerCommand")] command: JsValue,
+    ) -> Result<JsValue, BhwiError> {
+        let command: types::LedgerCommand = types::from_js(&command)?;
+        let command = LedgerCommand::try_from(command).map_err(BhwiError::invalid_argument)?;
+        let response = self.run(command).await?;
+        types::to_js(&types::LedgerResponse::from(response))
+    }
+
+    pub async fn get_master_fingerprint(&mut self) -> Result<String, BhwiError> {
+        match self.run(LedgerCommand::GetMasterFingerprint).await? {
+            LedgerResponse::MasterFingerprint(fg) => Ok(fg.to_string()),
+            _ => Err(LedgerError::NoErrorOrResult.into()),
+        }
+    }
+
+    pub async fn get_xpub(&mut self, path: &str, display: bool) -> Result<String, BhwiError> {
+        let path = DerivationPath::from_str(path)
+            .map_err(|e| BhwiError::invalid_argument(format!("Invalid path: {}", e)))?;
+        self.xpub(path, display).await.map(|xpub| xpub.to_string())
+    }
+
+    async fn xpub(&mut self, path: DerivationPath, display: bool) -> Result<Xpub, BhwiError> {
+        match self.run(LedgerCommand::GetXpub { path, display }).await? {
+            LedgerResponse::Xpub(xpub) => Ok(xpub),
+            _ => Err(LedgerError::NoErrorOrResult.into()),
+        }
+    }
+
+    /// Signs the psbt with the default single signature policy of the account
+    /// spent by the inputs of the device, returns the psbt with the
+    /// signatures, base64 encoded.
+    pub async fn sign_psbt(&mut self, psbt_base64: &str) -> Result<String, BhwiError> {
+        let mut psbt = psbt::psbt_from_base64(psbt_base64)
+            .map_err(|e| BhwiError::invalid_argument(format!("Invalid psbt: {:?}", e)))?;
+        let fingerprint = match self.run(LedgerCommand::GetMasterFingerprint).await? {
+            LedgerResponse::MasterFingerprint(fg) => fg,
+            _ => return Err(LedgerError::NoErrorOrResult.into()),
+        };
+        let account = psbt
+            .inputs
+            .iter()
+            .flat_map(|input| {
+                input
+                    .bip32_derivation
+                    .values()
+                    .chain(input.tap_key_origins.values().map(|(_, source)| source))
+            })
+            .find(|(fg, path)| *fg == fingerprint && path.len() > 3)
+            .map(|(_, path)| DerivationPath::from(&path[..3]))
+            .ok_or_else(|| BhwiError::invalid_argument("No input of the device in the psbt"))?;
+        let xpub = self.xpub(account.clone(), false).await?;
+        let policy = WalletPolicy::new_singlesig((fingerprint, account), xpub)
+            .map_err(|e| BhwiError::invalid_argument(format!("Unsupported account: {:?}", e)))?;
+        let signatures = match self
+            .run(LedgerCommand::SignPsbt {
+                psbt: Box::new(psbt.clone()),
+                policy,
+                hmac: None,
+            })
+            .await?
+        {
+            LedgerResponse::Signatures(signatures) => signatures,
+            _ => return Err(LedgerError::NoErrorOrResult.into()),
+        };
+        for (index, signature) in signatures {
+            if let Some(input) = psbt.inputs.get_mut(index) {
+                signature.add_to(input);
+            }
         }
+        Ok(psbt.to_string())
     }
 }
@@ -1,70 +1,122 @@
+pub mod collect;
+pub mod error;
 pub mod ledger;
+pub mod panic;
 pub mod pinserver;
+#[cfg(feature = "storage")]
+pub mod storage;
+mod timer;
+pub mod types;
 pub mod webhid;
 pub mod webserial;
+#[cfg(feature = "worker")]
+pub mod worker;
 
+use std::rc::Rc;
 use std::str::FromStr;
 
 use async_trait::async_trait;
+use bhwi::devices::{self, Capabilities, DeviceKind};
 use bhwi_async::{
     coldcard::Coldcard,
+    exclusive::Exclusive,
+    session_log::{Logged, LoggedTransport, SessionLog},
     transport::coldcard_hid::{ColdcardTransportHID, COLDCARD_VID},
     transport::ledger_hid::{LedgerTransportHID, LEDGER_VID},
-    Jade, Ledger, HWI as AsyncHWI,
+    Jade, Ledger, Specter, HWI as AsyncHWI,
 };
-use bitcoin::{bip32::DerivationPath, Network};
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint},
+    Network,
+};
+use error::{BhwiError, ErrorKind};
 use log::Level;
 use pinserver::PinServer;
 use wasm_bindgen::prelude::*;
 use webhid::WebHidDevice;
 use webserial::WebSerialDevice;
 
+/// Logs to the browser console. The exchanges with the Ledger devices are
+/// logged at the `debug` level: the commands, the APDU class and instruction,
+/// the status words and the client commands requested by the device. The
+/// chunks of the long commands are logged at the `trace` level.
 #[wasm_bindgen]
 pub fn initialize_logging(level: &str) {
-    console_error_panic_hook::set_once();
+    panic::install();
     // Attempt to parse the log level from the string, default to Info if invalid
     let log_level = Level::from_str(level).unwrap_or(Level::Info);
 
-    console_log::init_with_level(log_level).expect("error initializing log");
+    if console_log::init_with_level(log_level).is_err() {
+        log::warn!("logging is already initialized");
+    }
+}
+
+#[wasm_bindgen(start)]
+fn start() {
+    panic::install();
 }
 
 #[async_trait(?Send)]
 pub trait HWI {
-    async fn unlock(&mut self, network: &str) -> Result<(), JsValue>;
-    async fn get_mfg(&mut self) -> Result<String, JsValue>;
-    async fn get_xpub(&mut self, path: &str, display: bool) -> Result<String, JsValue>;
+    async fn unlock(&mut self, network: &str) -> Result<(), BhwiError>;
+    async fn get_mfg(&mut self) -> Result<String, BhwiError>;
+    async fn get_xpub(&mut self, path: &str, display: bool) -> Result<String, BhwiError>;
+    fn capabilities(&self) -> Capabilities;
 }
 
 #[async_trait(?Send)]
-impl<T: AsyncHWI> HWI for T {
-    async fn unlock(&mut self, network: &str) -> Result<(), JsValue> {
-        let n = Network::from_str(network).map_err(|e| JsValue::from_str(&e.to_string()))?;
-        self.unlock(n)
-            .await
-            .map_err(|e| JsValue::from_str(&format!("Failed to unlock: {:?}", e)))
+impl<T: AsyncHWI> HWI for T
+where
+    T::Error: Into<BhwiError>,
+{
+    async fn unlock(&mut self, network: &str) -> Result<(), BhwiError> {
+        let n = Network::from_str(network)
+            .map_err(|e| BhwiError::invalid_argument(format!("Invalid network: {}", e)))?;
+        self.unlock(n).await.map_err(Into::into)
     }
 
-    async fn get_mfg(&mut self) -> Result<String, JsValue> {
+    async fn get_mfg(&mut self) -> Result<String, BhwiError> {
         self.get_master_fingerprint()
             .await
             .map(|fp| fp.to_string())
-            .map_err(|e| JsValue::from_str(&format!("Failed to get fingerprint: {:?}", e)))
+            .map_err(Into::into)
     }
 
-    async fn get_xpub(&mut self, path: &str, display: bool) -> Result<String, JsValue> {
+    async fn get_xpub(&mut self, path: &str, display: bool) -> Result<String, BhwiError> {
         let p = DerivationPath::from_str(path)
-            .map_err(|e| JsValue::from_str(&format!("Failed to get fingerprint: {:?}", e)))?;
+            .map_err(|e| BhwiError::invalid_argument(format!("Invalid path: {}", e)))?;
         self.get_extended_pubkey(p, display)
             .await
             .map(|xpub| xpub.to_string())
-            .map_err(|e| JsValue::from_str(&format!("Failed to get fingerprint: {:?}", e)))
+            .map_err(Into::into)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        AsyncHWI::capabilities(self)
     }
 }
 
+/// The HID devices run their commands under the lock of the device, shared
+/// with its other wrappers of the page.
+/// The commands and the exchanges are recorded to the session log of the
+/// client.
 pub enum Device {
-    Ledger(Ledger<LedgerTransportHID<webhid::WebHidDevice>>),
-    Coldcard(Coldcard<ColdcardTransportHID<webhid::WebHidDevice>>),
-    Jade(Jade<WebSerialDevice, PinServer>),
+    Ledger(Exclusive<Logged<Ledger<LoggedTransport<LedgerTransportHID<WebHidDevice>>>>>),
+    Coldcard(Exclusive<Logged<Coldcard<LoggedTransport<ColdcardTransportHID<WebHidDevice>>>>>),
+    Jade(Logged<Jade<LoggedTransport<WebSerialDevice>, PinServer>>),
+    Specter(Logged<Specter<LoggedTransport<WebSerialDevice>>>),
+}
+
+impl Device {
+    /// Name of the kind of the device.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Device::Ledger(_) => "ledger",
+            Device::Coldcard(_) => "coldcard",
+            Device::Jade(_) => "jade",
+            Device::Specter(_) => "specter",
+        }
+    }
 }
 
 impl<'a> AsRef<dyn HWI + 'a> for Device {
@@ -73,6 +125,7 @@ impl<'a> AsRef<dyn HWI + 'a> for Device {
             Device::Coldcard(l) => l,
             Device::Ledger(l) => l,
             Device::Jade(j) => j,
+            Device::Specter(s) => s,
         }
     }
 }
@@ -83,41 +136,123 @@ impl<'a> AsMut<dyn HWI + 'a> for Device {
             Device::Coldcard(l) => l,
             Device::Ledger(l) => l,
             Device::Jade(j) => j,
+            Device::Specter(s) => s,
         }
     }
 }
 
+/// Session log of a client, for the page to attach its dump to the reports
+/// of the users. The payloads of the exchanges are not logged.
+#[wasm_bindgen]
+pub struct Session {
+    log: Rc<SessionLog>,
+}
+
+#[wasm_bindgen]
+impl Session {
+    /// Returns the last events, one per line with their timestamp.
+    #[wasm_bindgen]
+    pub fn dump(&self) -> String {
+        self.log.dump()
+    }
+
+    #[wasm_bindgen]
+    pub fn clear(&self) {
+        self.log.clear()
+    }
+}
+
 #[wasm_bindgen]
 pub struct Client {
     device: Option<Device>,
+    log: Rc<SessionLog>,
+    /// Fingerprint of the device connected, once asked.
+    fingerprint: Option<Fingerprint>,
+    #[cfg(feature = "storage")]
+    storage: Option<storage::Storage>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
 impl Client {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Client {
-        Client { device: None }
+        Client {
+            device: None,
+            log: Rc::new(SessionLog::default().with_clock(|| js_sys::Date::now() as u64)),
+            fingerprint: None,
+            #[cfg(feature = "storage")]
+            storage: None,
+        }
+    }
+
+    /// Remembers the fingerprints of the devices in the storage, and serves
+    /// the xpubs not displayed from it once the fingerprint is known.
+    #[cfg(feature = "storage")]
+    #[wasm_bindgen]
+    pub fn set_storage(&mut self, storage: &storage::Storage) {
+        self.storage = Some(storage.clone());
     }
 
+    /// The session log of the devices connected by the client.
     #[wasm_bindgen]
-    pub async fn connect_coldcard(&mut self, on_close_cb: JsValue) -> Result<(), JsValue> {
-        let device = WebHidDevice::get_webhid_device("Coldcard", COLDCARD_VID, None, on_close_cb)
+    pub fn session(&self) -> Session {
+        Session {
+            log: self.log.clone(),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub async fn connect_coldcard(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Result<(), BhwiError> {
+        let device = WebHidDevice::get_or_request_webhid_device(COLDCARD_VID, None, on_close_cb)
             .await
-            .ok_or(JsValue::from_str("Failed to connect to coldcard"))?;
+            .ok_or_else(|| connection_failed("coldcard"))?;
         let mut rng = rand_core::OsRng;
-        self.device = Some(Device::Coldcard(Coldcard::new(
-            ColdcardTransportHID::new(device),
-            &mut rng,
+        let lock = device.session_lock();
+        let pacing = device.pacing();
+        let transport = LoggedTransport::new(
+            ColdcardTransportHID::new(device).with_pacing(pacing),
+            self.log.clone(),
+            false,
+        );
+        self.device = Some(Device::Coldcard(Exclusive::new(
+            Logged::new(Coldcard::new(transport, &mut rng), self.log.clone()),
+            lock,
         )));
+        self.fingerprint = None;
+        self.log.state("connected");
         Ok(())
     }
 
     #[wasm_bindgen]
-    pub async fn connect_ledger(&mut self, on_close_cb: JsValue) -> Result<(), JsValue> {
-        let device = WebHidDevice::get_webhid_device("Ledger", LEDGER_VID, None, on_close_cb)
+    pub async fn connect_ledger(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Result<(), BhwiError> {
+        let device = WebHidDevice::get_or_request_webhid_device(LEDGER_VID, None, on_close_cb)
             .await
-            .ok_or(JsValue::from_str("Failed to connect to ledger"))?;
-        self.device = Some(Device::Ledger(Ledger::new(LedgerTransportHID::new(device))));
+            .ok_or_else(|| connection_failed("ledger"))?;
+        let lock = device.session_lock();
+        let pacing = device.pacing();
+        let transport = LoggedTransport::new(
+            LedgerTransportHID::new(device).with_pacing(pacing),
+            self.log.clone(),
+            true,
+        );
+        self.device = Some(Device::Ledger(Exclusive::new(
+            Logged::new(Ledger::new(transport), self.log.clone()),
+            lock,
+        )));
+        self.fingerprint = None;
+        self.log.state("connected");
         Ok(())
     }
 
@@ -125,30 +260,75 @@ impl Client {
     pub async fn connect_jade(
         &mut self,
         network: &str,
-        on_close_cb: JsValue,
-    ) -> Result<(), JsValue> {
-        let network = Network::from_str(network).map_err(|e| JsValue::from_str(&e.to_string()))?;
-        let device = WebSerialDevice::get_webserial_device(115200, on_close_cb)
-            .await
-            .ok_or(JsValue::from_str("Failed to connect to jade"))?;
-        self.device = Some(Device::Jade(Jade::new(network, device, PinServer {})));
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Result<(), BhwiError> {
+        let network = Network::from_str(network)
+            .map_err(|e| BhwiError::invalid_argument(format!("Invalid network: {}", e)))?;
+        let device = WebSerialDevice::get_webserial_device(
+            115200,
+            devices::vendor_ids(DeviceKind::Jade),
+            on_close_cb,
+        )
+        .await
+        .ok_or_else(|| connection_failed("jade"))?;
+        let transport = LoggedTransport::new(device, self.log.clone(), false);
+        self.device = Some(Device::Jade(Logged::new(
+            Jade::new(network, transport, PinServer {}),
+            self.log.clone(),
+        )));
+        self.fingerprint = None;
+        self.log.state("connected");
         Ok(())
     }
 
     #[wasm_bindgen]
-    pub async fn unlock(&mut self, network: &str) -> Result<(), JsValue> {
+    pub async fn connect_specter(
+        &mut self,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Result<(), BhwiError> {
+        let device = WebSerialDevice::get_webserial_device(
+            115200,
+            devices::vendor_ids(DeviceKind::Specter),
+            on_close_cb,
+        )
+        .await
+        .ok_or_else(|| connection_failed("specter"))?;
+        let transport = LoggedTransport::new(device, self.log.clone(), false);
+        self.device = Some(Device::Specter(Logged::new(
+            Specter::new(transport),
+            self.log.clone(),
+        )));
+        self.fingerprint = None;
+        self.log.state("connected");
+        Ok(())
+    }
+
+    /// Features of the device connected, for the page to disable the
+    /// actions it does not support.
+    #[wasm_bindgen(unchecked_return_type = "Capabilities")]
+    pub fn capabilities(&self) -> Result<JsValue, BhwiError> {
+        let device = self.device.as_ref().ok_or_else(BhwiError::not_connected)?;
+        types::to_js(&types::Capabilities::from(device.as_ref().capabilities()))
+    }
+
+    #[wasm_bindgen]
+    pub async fn unlock(&mut self, network: &str) -> Result<(), BhwiError> {
         match &mut self.device {
             Some(d) => d.as_mut().unlock(network).await,
-            None => Err(JsValue::from_str("Device not connected")),
+            None => Err(BhwiError::not_connected()),
         }
     }
 
     #[wasm_bindgen]
-    pub async fn get_master_fingerprint(&mut self) -> Result<String, JsValue> {
-        match &mut self.device {
-            Some(d) => d.as_mut().get_mfg().await,
-            None => Err(JsValue::from_str("Device not connected")),
+    pub async fn get_master_fingerprint(&mut self) -> Result<String, BhwiError> {
+        let device = self.device.as_mut().ok_or_else(BhwiError::not_connected)?;
+        let fg = device.as_mut().get_mfg().await?;
+        self.fingerprint = Fingerprint::from_str(&fg).ok();
+        #[cfg(feature = "storage")]
+        if let (Some(storage), Some(fingerprint)) = (&self.storage, self.fingerprint) {
+            storage.remember_device(fingerprint, device.kind());
         }
+        Ok(fg)
     }
 
     #[wasm_bindgen]
@@ -156,10 +336,32 @@ impl Client {
         &mut self,
         path: &str,
         display: bool,
-    ) -> Result<String, JsValue> {
-        match &mut self.device {
-            Some(d) => d.as_mut().get_xpub(path, display).await,
-            None => Err(JsValue::from_str("Device not connected")),
+    ) -> Result<String, BhwiError> {
+        let device = self.device.as_mut().ok_or_else(BhwiError::not_connected)?;
+        #[cfg(feature = "storage")]
+        if let (Some(storage), Some(fingerprint), false) =
+            (&mut self.storage, self.fingerprint, display)
+        {
+            use bhwi_async::cache::XpubCache;
+            let p = DerivationPath::from_str(path)
+                .map_err(|e| BhwiError::invalid_argument(format!("Invalid path: {}", e)))?;
+            if let Some(xpub) = storage.get(fingerprint, &p) {
+                return Ok(xpub.to_string());
+            }
+            let xpub = device.as_mut().get_xpub(path, display).await?;
+            if let Ok(x) = bitcoin::bip32::Xpub::from_str(&xpub) {
+                storage.set(fingerprint, p, x);
+            }
+            return Ok(xpub);
         }
+        device.as_mut().get_xpub(path, display).await
     }
 }
+
+fn connection_failed(device: &str) -> BhwiError {
+    BhwiError::new(
+        ErrorKind::NotConnected,
+        "connection_failed",
+        format!("Failed to connect to {}", device),
+    )
+}
@@ -0,0 +1,55 @@
+//! Panics of the package: the wasm instance aborts the call that panicked,
+//! the hook logs the panic to the console, releases the locks of the devices
+//! held by the aborted command and notifies the page with a BhwiError of kind
+//! Panic, so that it creates new clients and keeps its device session instead
+//! of reloading.
+
+use std::{cell::RefCell, panic::PanicHookInfo, sync::Once};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    error::{BhwiError, ErrorKind},
+    webhid,
+};
+
+thread_local! {
+    static ON_PANIC: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+/// Installs the panic hook, once.
+pub fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| std::panic::set_hook(Box::new(hook)));
+}
+
+/// Never panics itself: the state borrowed by the aborted call is skipped.
+fn hook(info: &PanicHookInfo) {
+    console_error_panic_hook::hook(info);
+    webhid::release_session_locks();
+    let _ = ON_PANIC.try_with(|on_panic| {
+        if let Some(cb) = on_panic
+            .try_borrow()
+            .ok()
+            .as_deref()
+            .and_then(Option::as_ref)
+        {
+            let error = BhwiError::new(ErrorKind::Panic, "panic", info.to_string());
+            if let Err(e) = cb.call1(&JsValue::NULL, &error.into()) {
+                log::error!("panic callback failed: {:?}", e);
+            }
+        }
+    });
+}
+
+/// Calls the callback with the BhwiError of each panic, the clients used by
+/// the call that panicked are not usable anymore.
+#[wasm_bindgen]
+pub fn set_panic_handler(
+    #[wasm_bindgen(unchecked_param_type = "PanicCallback | undefined")] on_panic: Option<
+        js_sys::Function,
+    >,
+) {
+    install();
+    ON_PANIC.with(|cb| *cb.borrow_mut() = on_panic);
+}
@@ -26,12 +26,12 @@ impl HttpClient for PinServer {
         let request = Request::new_with_str_and_init(url, &opts)?;
 
         // Use the window's fetch API
-        let window = web_sys::window().unwrap();
+        let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window"))?;
         let fetch = window.fetch_with_request(&request);
 
         // Wait for the response
         let resp_value = JsFuture::from(fetch).await?;
-        let resp: Response = resp_value.dyn_into().unwrap();
+        let resp: Response = resp_value.dyn_into()?;
 
         // Ensure the response is OK
         if !resp.ok() {
@@ -0,0 +1,331 @@
+//! Persistence in IndexedDB of the proofs of registration of the policies,
+//! of the xpubs and of the fingerprints of the devices, so that the pages
+//! reloaded do not register their policies again.
+//!
+//! The records are loaded in memory when the storage is opened: the stores
+//! of bhwi read them synchronously and their writes are written through to
+//! the database in the background.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use bhwi::ledger::WalletHmacStore;
+use bhwi_async::cache::XpubCache;
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint, Xpub},
+    hex::{DisplayHex, FromHex},
+};
+use js_sys::{Array, Function, Promise};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStore, IdbOpenDbRequest, IdbRequest, IdbTransactionMode};
+
+use crate::error::{BhwiError, ErrorKind};
+
+const VERSION: u32 = 1;
+const DEFAULT_NAME: &str = "bhwi";
+const HMACS: &str = "hmacs";
+const XPUBS: &str = "xpubs";
+const DEVICES: &str = "devices";
+
+/// Resolves with the result of the request.
+async fn request(request: &IdbRequest) -> Result<JsValue, JsValue> {
+    let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+        let req = request.clone();
+        let on_success = Closure::once_into_js(move |_: JsValue| {
+            let _ = resolve.call1(&JsValue::NULL, &req.result().unwrap_or(JsValue::UNDEFINED));
+        });
+        let on_error = Closure::once_into_js(move |event: JsValue| {
+            let _ = reject.call1(&JsValue::NULL, &event);
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+    JsFuture::from(promise).await
+}
+
+fn storage_error(e: JsValue) -> BhwiError {
+    BhwiError::new(
+        ErrorKind::Transport,
+        "storage_failed",
+        "IndexedDB request failed",
+    )
+    .with_cause(e)
+}
+
+/// Records of the storage, by their key in the database.
+#[derive(Default)]
+struct Records {
+    hmacs: BTreeMap<[u8; 32], [u8; 32]>,
+    xpubs: BTreeMap<(Fingerprint, DerivationPath), Xpub>,
+    devices: BTreeMap<Fingerprint, String>,
+}
+
+fn xpub_key(fingerprint: Fingerprint, path: &DerivationPath) -> String {
+    format!("{}/{}", fingerprint, path)
+}
+
+fn xpub_record(fingerprint: Fingerprint, path: &DerivationPath, xpub: &Xpub) -> [String; 3] {
+    [
+        fingerprint.to_string(),
+        format!("m/{}", path),
+        xpub.to_string(),
+    ]
+}
+
+fn parse_xpub_record(fields: &[String]) -> Option<(Fingerprint, DerivationPath, Xpub)> {
+    let [fg, path, xpub] = fields else {
+        return None;
+    };
+    Some((
+        Fingerprint::from_str(fg).ok()?,
+        DerivationPath::from_str(path).ok()?,
+        Xpub::from_str(xpub).ok()?,
+    ))
+}
+
+/// Storage of a page, the clients sharing it see the records of each other.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct Storage {
+    db: IdbDatabase,
+    records: Rc<RefCell<Records>>,
+}
+
+#[wasm_bindgen]
+impl Storage {
+    /// Opens the database of the name, `bhwi` by default, and loads its
+    /// records.
+    pub async fn open(name: Option<String>) -> Result<Storage, BhwiError> {
+        let factory = web_sys::window()
+            .and_then(|window| window.indexed_db().ok().flatten())
+            .ok_or_else(|| {
+                BhwiError::new(
+                    ErrorKind::Unsupported,
+                    "storage_unsupported",
+                    "IndexedDB is not available",
+                )
+            })?;
+        let open: IdbOpenDbRequest = factory
+            .open_with_u32(name.as_deref().unwrap_or(DEFAULT_NAME), VERSION)
+            .map_err(storage_error)?;
+        let on_upgrade = {
+            let open = open.clone();
+            Closure::once_into_js(move |_: JsValue| {
+                let Some(db) = open
+                    .result()
+                    .ok()
+                    .and_then(|db| db.dyn_into::<IdbDatabase>().ok())
+                else {
+                    return;
+                };
+                for name in [HMACS, XPUBS, DEVICES] {
+                    if !db.object_store_names().contains(name) {
+                        if let Err(e) = db.create_object_store(name) {
+                            log::error!("failed to create the store {}: {:?}", name, e);
+                        }
+                    }
+                }
+            })
+        };
+        open.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+        let db: IdbDatabase = request(&open)
+            .await
+            .and_then(|db| db.dyn_into().map_err(JsValue::from))
+            .map_err(storage_error)?;
+
+        let storage = Storage {
+            db,
+            records: Rc::new(RefCell::new(Records::default())),
+        };
+        storage.load().await?;
+        Ok(storage)
+    }
+
+    /// Returns the fingerprints of the devices seen, in hex.
+    pub fn fingerprints(&self) -> Vec<String> {
+        self.records
+            .borrow()
+            .devices
+            .keys()
+            .map(|fg| fg.to_string())
+            .collect()
+    }
+
+    /// Returns the kind of the device of the fingerprint, like `ledger`.
+    pub fn device_kind(&self, fingerprint: &str) -> Option<String> {
+        let fingerprint = Fingerprint::from_str(fingerprint).ok()?;
+        self.records.borrow().devices.get(&fingerprint).cloned()
+    }
+
+    /// Removes every record, from memory and from the database.
+    pub async fn clear(&self) -> Result<(), BhwiError> {
+        *self.records.borrow_mut() = Records::default();
+        for name in [HMACS, XPUBS, DEVICES] {
+            let store = self.store(name).map_err(storage_error)?;
+            request(&store.clear().map_err(storage_error)?)
+                .await
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage {
+    fn store(&self, name: &str) -> Result<IdbObjectStore, JsValue> {
+        self.db
+            .transaction_with_str_and_mode(name, IdbTransactionMode::Readwrite)?
+            .object_store(name)
+    }
+
+    /// Returns the values of the store, each one the array of the fields of
+    /// the record.
+    async fn values(&self, name: &str) -> Result<Vec<Vec<String>>, BhwiError> {
+        let store = self.store(name).map_err(storage_error)?;
+        let values = request(&store.get_all().map_err(storage_error)?)
+            .await
+            .map_err(storage_error)?;
+        Ok(Array::from(&values)
+            .iter()
+            .map(|value| {
+                Array::from(&value)
+                    .iter()
+                    .filter_map(|field| field.as_string())
+                    .collect()
+            })
+            .collect())
+    }
+
+    async fn load(&self) -> Result<(), BhwiError> {
+        let mut records = Records::default();
+        for value in self.values(HMACS).await? {
+            if let [id, hmac] = &value[..] {
+                if let (Ok(id), Ok(hmac)) = (<[u8; 32]>::from_hex(id), <[u8; 32]>::from_hex(hmac)) {
+                    records.hmacs.insert(id, hmac);
+                }
+            }
+        }
+        for value in self.values(XPUBS).await? {
+            if let Some((fg, path, xpub)) = parse_xpub_record(&value) {
+                records.xpubs.insert((fg, path), xpub);
+            }
+        }
+        for value in self.values(DEVICES).await? {
+            if let [fg, kind] = &value[..] {
+                if let Ok(fg) = Fingerprint::from_str(fg) {
+                    records.devices.insert(fg, kind.clone());
+                }
+            }
+        }
+        *self.records.borrow_mut() = records;
+        Ok(())
+    }
+
+    /// Writes the record in the background, the failures are logged.
+    fn put(&self, name: &'static str, key: String, fields: &[String]) {
+        let value: Array = fields.iter().map(|f| JsValue::from_str(f)).collect();
+        let res = self
+            .store(name)
+            .and_then(|store| store.put_with_key(&value, &JsValue::from_str(&key)));
+        match res {
+            Ok(req) => wasm_bindgen_futures::spawn_local(async move {
+                if let Err(e) = request(&req).await {
+                    log::error!("failed to write to the store {}: {:?}", name, e);
+                }
+            }),
+            Err(e) => log::error!("failed to write to the store {}: {:?}", name, e),
+        }
+    }
+
+    fn delete(&self, name: &'static str, key: String) {
+        let res = self
+            .store(name)
+            .and_then(|store| store.delete(&JsValue::from_str(&key)));
+        if let Err(e) = res {
+            log::error!("failed to delete from the store {}: {:?}", name, e);
+        }
+    }
+
+    /// Records the device of the fingerprint, with its kind.
+    pub(crate) fn remember_device(&self, fingerprint: Fingerprint, kind: &str) {
+        let known = self
+            .records
+            .borrow_mut()
+            .devices
+            .insert(fingerprint, kind.to_string());
+        if known.as_deref() != Some(kind) {
+            self.put(
+                DEVICES,
+                fingerprint.to_string(),
+                &[fingerprint.to_string(), kind.to_string()],
+            );
+        }
+    }
+}
+
+impl WalletHmacStore for Storage {
+    fn get(&self, id: &[u8; 32]) -> Option<[u8; 32]> {
+        self.records.borrow().hmacs.get(id).copied()
+    }
+    fn set(&mut self, id: [u8; 32], hmac: [u8; 32]) {
+        self.records.borrow_mut().hmacs.insert(id, hmac);
+        let id = id.to_lower_hex_string();
+        self.put(HMACS, id.clone(), &[id, hmac.to_lower_hex_string()]);
+    }
+}
+
+impl XpubCache for Storage {
+    fn get(&self, fingerprint: Fingerprint, path: &DerivationPath) -> Option<Xpub> {
+        self.records
+            .borrow()
+            .xpubs
+            .get(&(fingerprint, path.clone()))
+            .copied()
+    }
+    fn set(&mut self, fingerprint: Fingerprint, path: DerivationPath, xpub: Xpub) {
+        self.put(
+            XPUBS,
+            xpub_key(fingerprint, &path),
+            &xpub_record(fingerprint, &path, &xpub),
+        );
+        self.records
+            .borrow_mut()
+            .xpubs
+            .insert((fingerprint, path), xpub);
+    }
+    fn invalidate(&mut self, fingerprint: Fingerprint) {
+        let paths: Vec<DerivationPath> = self
+            .records
+            .borrow()
+            .xpubs
+            .keys()
+            .filter(|(fg, _)| *fg == fingerprint)
+            .map(|(_, path)| path.clone())
+            .collect();
+        for path in paths {
+            self.delete(XPUBS, xpub_key(fingerprint, &path));
+            self.records.borrow_mut().xpubs.remove(&(fingerprint, path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xpub_record() {
+        let xpub = Xpub::from_str("tpubDCtKfsNyRhULjZ9XMS4VKKtVcPdVDi8MKUbcSD9MJDyjRu1A2ND5MiipozyyspBT9bg8upEp7a8EAgFxNxXn1d7QkdbL52Ty5jiSLcxPt1P").unwrap();
+        for path in ["m", "m/84'/1'/0'", "m/0/1"] {
+            let path = DerivationPath::from_str(path).unwrap();
+            let fg = Fingerprint::from_str("f5acc2fd").unwrap();
+            assert_eq!(
+                parse_xpub_record(&xpub_record(fg, &path, &xpub)),
+                Some((fg, path, xpub))
+            );
+        }
+        assert_eq!(parse_xpub_record(&["f5acc2fd".to_string()]), None);
+    }
+}
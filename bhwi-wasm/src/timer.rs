@@ -0,0 +1,42 @@
+use js_sys::Promise;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AbortSignal, Window, WorkerGlobalScope};
+
+/// Resolves after the delay, on the page or in a worker, rejects if no timer
+/// is available.
+pub fn timeout(timeout_ms: i32) -> JsFuture {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let global = js_sys::global();
+        let res = if let Some(window) = global.dyn_ref::<Window>() {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, timeout_ms)
+                .map(|_| ())
+        } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+            worker
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, timeout_ms)
+                .map(|_| ())
+        } else {
+            Err(JsValue::from_str("No timer"))
+        };
+        if let Err(e) = res {
+            let _ = reject.call1(&JsValue::UNDEFINED, &e);
+        }
+    });
+    JsFuture::from(promise)
+}
+
+/// Resolves once the signal is aborted.
+pub fn aborted(signal: &AbortSignal) -> JsFuture {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let res = if signal.aborted() {
+            resolve.call0(&JsValue::UNDEFINED).map(|_| ())
+        } else {
+            signal.add_event_listener_with_callback("abort", &resolve)
+        };
+        if let Err(e) = res {
+            let _ = reject.call1(&JsValue::UNDEFINED, &e);
+        }
+    });
+    JsFuture::from(promise)
+}
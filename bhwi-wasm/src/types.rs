@@ -0,0 +1,681 @@
+//! Objects exchanged with the page, with their TypeScript definitions.
+//!
+//! The commands and the responses are serialized as JSON objects tagged by
+//! their type, so that they are typed on the JS side.
+
+use std::str::FromStr;
+
+use bhwi::ledger::{
+    self, wallet::Version, MusigPartialSignature, MusigPubNonce, PartialSignature, WalletPubKey,
+};
+use bhwi::{bip322, devices};
+use bitcoin::{
+    bip32::DerivationPath,
+    hex::{DisplayHex, FromHex},
+    Address, Psbt,
+};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::error::{BhwiError, ErrorKind};
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_DEFINITIONS: &'static str = r#"
+export type OnCloseCallback = () => void;
+
+export type HidConnectionCallback = (info: WebHidDeviceInfo, device: HIDDevice) => void;
+
+export type BhwiErrorKind =
+    | "DeniedByUser"
+    | "DeviceLocked"
+    | "AppNotOpen"
+    | "Busy"
+    | "Timeout"
+    | "Cancelled"
+    | "NotConnected"
+    | "MismatchedDevice"
+    | "Unsupported"
+    | "PolicyDenied"
+    | "InvalidArgument"
+    | "Transport"
+    | "Device"
+    | "Panic";
+
+export type PanicCallback = (error: BhwiError) => void;
+
+export interface WalletPolicy {
+    name?: string;
+    descriptorTemplate: string;
+    keys: string[];
+}
+
+export type LedgerCommand =
+    | { type: "getAppInfo" }
+    | { type: "getMasterFingerprint" }
+    | { type: "getXpub"; path: string; display?: boolean }
+    | { type: "signPsbt"; psbt: string; policy: WalletPolicy; hmac?: string }
+    | { type: "musigSignPsbt"; psbt: string; policy: WalletPolicy; hmac?: string }
+    | { type: "registerWallet"; policy: WalletPolicy }
+    | {
+          type: "getWalletAddress";
+          policy: WalletPolicy;
+          hmac?: string;
+          change: boolean;
+          index: number;
+          display?: boolean;
+      }
+    | { type: "signMessage"; path: string; message: string }
+    | {
+          type: "proveAddressOwnership";
+          message: string;
+          policy: WalletPolicy;
+          hmac?: string;
+          change: boolean;
+          index: number;
+          format?: "simple" | "full";
+      }
+    | {
+          type: "verifyOwnedAddress";
+          address: string;
+          policy: WalletPolicy;
+          hmac?: string;
+          gapLimit: number;
+      };
+
+export interface PartialSignature {
+    index: number;
+    pubkey: string;
+    leafHash?: string;
+    signature: string;
+}
+
+export interface MusigValue {
+    index: number;
+    participant: string;
+    aggregate: string;
+    leafHash?: string;
+    value: string;
+}
+
+export interface Capabilities {
+    taproot: boolean;
+    musig2: boolean;
+    messageSigning: boolean;
+    policyRegistration: boolean;
+    maxInputs?: number;
+    networks: string[];
+}
+
+export type LedgerResponse =
+    | { type: "taskDone" }
+    | { type: "appInfo"; name: string; version: string; flags: string }
+    | { type: "masterFingerprint"; fingerprint: string }
+    | { type: "xpub"; xpub: string }
+    | { type: "signatures"; signatures: PartialSignature[] }
+    | { type: "musigNonces"; nonces: MusigValue[] }
+    | { type: "musigPartialSigs"; signatures: MusigValue[] }
+    | { type: "walletRegistered"; id: string; hmac: string }
+    | { type: "address"; address: string }
+    | { type: "messageSignature"; signature: string }
+    | { type: "ownershipProof"; proof?: string; psbt?: string }
+    | { type: "ownedAddress"; change: boolean; index: number };
+"#;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalletPolicy {
+    #[serde(default)]
+    pub name: String,
+    pub descriptor_template: String,
+    pub keys: Vec<String>,
+}
+
+impl TryFrom<WalletPolicy> for ledger::WalletPolicy {
+    type Error = String;
+    fn try_from(policy: WalletPolicy) -> Result<Self, Self::Error> {
+        let keys = policy
+            .keys
+            .iter()
+            .map(|key| WalletPubKey::from_str(key).map_err(|e| format!("Invalid key: {}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ledger::WalletPolicy::new(
+            policy.name,
+            Version::V2,
+            policy.descriptor_template,
+            keys,
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum LedgerCommand {
+    GetAppInfo,
+    GetStatus,
+    ListApps,
+    QuitApp,
+    GetMasterFingerprint,
+    GetXpub {
+        path: String,
+        #[serde(default)]
+        display: bool,
+    },
+    GetXpubs {
+        paths: Vec<String>,
+    },
+    SignPsbt {
+        psbt: String,
+        policy: WalletPolicy,
+        hmac: Option<String>,
+    },
+    MusigSignPsbt {
+        psbt: String,
+        policy: WalletPolicy,
+        hmac: Option<String>,
+    },
+    RegisterWallet {
+        policy: WalletPolicy,
+    },
+    GetWalletAddress {
+        policy: WalletPolicy,
+        hmac: Option<String>,
+        change: bool,
+        index: u32,
+        #[serde(default)]
+        display: bool,
+    },
+    SignMessage {
+        path: String,
+        message: String,
+    },
+    ProveAddressOwnership {
+        message: String,
+        policy: WalletPolicy,
+        hmac: Option<String>,
+        change: bool,
+        index: u32,
+        #[serde(default)]
+        format: ProofFormat,
+    },
+    VerifyOwnedAddress {
+        address: String,
+        policy: WalletPolicy,
+        hmac: Option<String>,
+        gap_limit: u32,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProofFormat {
+    #[default]
+    Simple,
+    Full,
+}
+
+impl From<ProofFormat> for bip322::ProofFormat {
+    fn from(format: ProofFormat) -> Self {
+        match format {
+            ProofFormat::Simple => Self::Simple,
+            ProofFormat::Full => Self::Full,
+        }
+    }
+}
+
+fn path(path: &str) -> Result<DerivationPath, String> {
+    DerivationPath::from_str(path).map_err(|e| format!("Invalid path: {}", e))
+}
+
+fn psbt(psbt: &str) -> Result<Box<Psbt>, String> {
+    ledger::psbt::psbt_from_base64(psbt)
+        .map(Box::new)
+        .map_err(|e| format!("Invalid psbt: {:?}", e))
+}
+
+fn hmac(hmac: Option<String>) -> Result<Option<[u8; 32]>, String> {
+    hmac.map(|hmac| <[u8; 32]>::from_hex(&hmac).map_err(|e| format!("Invalid hmac: {}", e)))
+        .transpose()
+}
+
+impl TryFrom<LedgerCommand> for ledger::LedgerCommand {
+    type Error = String;
+    fn try_from(command: LedgerCommand) -> Result<Self, Self::Error> {
+        Ok(match command {
+            LedgerCommand::GetAppInfo => Self::GetAppInfo,
+            LedgerCommand::GetStatus => Self::GetStatus,
+            LedgerCommand::ListApps => Self::ListApps,
+            LedgerCommand::QuitApp => Self::QuitApp,
+            LedgerCommand::GetMasterFingerprint => Self::GetMasterFingerprint,
+            LedgerCommand::GetXpub { path: p, display } => Self::GetXpub {
+                path: path(&p)?,
+                display,
+            },
+            LedgerCommand::GetXpubs { paths } => {
+                Self::GetXpubs(paths.iter().map(|p| path(p)).collect::<Result<_, _>>()?)
+            }
+            LedgerCommand::SignPsbt {
+                psbt: p,
+                policy,
+                hmac: h,
+            } => Self::SignPsbt {
+                psbt: psbt(&p)?,
+                policy: policy.try_into()?,
+                hmac: hmac(h)?,
+            },
+            LedgerCommand::MusigSignPsbt {
+                psbt: p,
+                policy,
+                hmac: h,
+            } => Self::MusigSignPsbt {
+                psbt: psbt(&p)?,
+                policy: policy.try_into()?,
+                hmac: hmac(h)?,
+            },
+            LedgerCommand::RegisterWallet { policy } => Self::RegisterWallet(policy.try_into()?),
+            LedgerCommand::GetWalletAddress {
+                policy,
+                hmac: h,
+                change,
+                index,
+                display,
+            } => Self::GetWalletAddress {
+                policy: policy.try_into()?,
+                hmac: hmac(h)?,
+                change,
+                index,
+                display,
+            },
+            LedgerCommand::SignMessage { path: p, message } => Self::SignMessage {
+                path: path(&p)?,
+                message: message.into_bytes(),
+            },
+            LedgerCommand::ProveAddressOwnership {
+                message,
+                policy,
+                hmac: h,
+                change,
+                index,
+                format,
+            } => {
+                let policy: ledger::WalletPolicy = policy.try_into()?;
+                let format = format.into();
+                Self::ProveAddressOwnership {
+                    psbt: Box::new(
+                        bip322::build_psbt(message.as_bytes(), &policy, change, index, format)
+                            .map_err(|e| format!("Invalid proof: {:?}", e))?,
+                    ),
+                    policy,
+                    hmac: hmac(h)?,
+                    format,
+                }
+            }
+            LedgerCommand::VerifyOwnedAddress {
+                address,
+                policy,
+                hmac: h,
+                gap_limit,
+            } => {
+                let address =
+                    Address::from_str(&address).map_err(|e| format!("Invalid address: {}", e))?;
+                let policy: ledger::WalletPolicy = policy.try_into()?;
+                let (change, index) = ledger::psbt::find_address_index(
+                    &policy,
+                    &address.assume_checked_ref().script_pubkey(),
+                    gap_limit,
+                )
+                .ok_or_else(|| "Address not found below the gap limit".to_string())?;
+                Self::VerifyOwnedAddress {
+                    address,
+                    policy,
+                    hmac: hmac(h)?,
+                    change,
+                    index,
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Signature {
+    pub index: usize,
+    pub pubkey: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_hash: Option<String>,
+    pub signature: String,
+}
+
+impl From<(usize, PartialSignature)> for Signature {
+    fn from((index, signature): (usize, PartialSignature)) -> Self {
+        match signature {
+            PartialSignature::Sig(pubkey, sig) => Self {
+                index,
+                pubkey: pubkey.to_string(),
+                leaf_hash: None,
+                signature: sig.to_string(),
+            },
+            PartialSignature::TapScriptSig(pubkey, leaf_hash, sig) => Self {
+                index,
+                pubkey: pubkey.to_string(),
+                leaf_hash: leaf_hash.map(|h| h.to_string()),
+                signature: sig.to_vec().to_lower_hex_string(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledApp {
+    pub name: String,
+    pub flags: u32,
+    pub code_hash: String,
+    pub hash: String,
+}
+
+impl From<ledger::InstalledApp> for InstalledApp {
+    fn from(app: ledger::InstalledApp) -> Self {
+        Self {
+            name: app.name,
+            flags: app.flags,
+            code_hash: app.code_hash.to_lower_hex_string(),
+            hash: app.hash.to_lower_hex_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub taproot: bool,
+    pub musig2: bool,
+    pub message_signing: bool,
+    pub policy_registration: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_inputs: Option<usize>,
+    /// Names of the networks, like `testnet4`.
+    pub networks: Vec<String>,
+}
+
+impl From<devices::Capabilities> for Capabilities {
+    fn from(capabilities: devices::Capabilities) -> Self {
+        Self {
+            taproot: capabilities.taproot,
+            musig2: capabilities.musig2,
+            message_signing: capabilities.message_signing,
+            policy_registration: capabilities.policy_registration,
+            max_inputs: capabilities.max_inputs,
+            networks: capabilities
+                .networks
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathXpub {
+    pub path: String,
+    pub xpub: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusigValue {
+    pub index: usize,
+    pub participant: String,
+    pub aggregate: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_hash: Option<String>,
+    pub value: String,
+}
+
+impl From<(usize, MusigPubNonce)> for MusigValue {
+    fn from((index, nonce): (usize, MusigPubNonce)) -> Self {
+        Self {
+            index,
+            participant: nonce.participant.to_string(),
+            aggregate: nonce.aggregate.to_string(),
+            leaf_hash: nonce.leaf_hash.map(|h| h.to_string()),
+            value: nonce.pubnonce.to_lower_hex_string(),
+        }
+    }
+}
+
+impl From<(usize, MusigPartialSignature)> for MusigValue {
+    fn from((index, sig): (usize, MusigPartialSignature)) -> Self {
+        Self {
+            index,
+            participant: sig.participant.to_string(),
+            aggregate: sig.aggregate.to_string(),
+            leaf_hash: sig.leaf_hash.map(|h| h.to_string()),
+            value: sig.signature.to_lower_hex_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum LedgerResponse {
+    TaskDone,
+    AppInfo {
+        name: String,
+        version: String,
+        flags: String,
+    },
+    /// The app is unknown while the device is locked.
+    Status {
+        unlocked: bool,
+        app: Option<String>,
+        version: Option<String>,
+    },
+    Apps {
+        apps: Vec<InstalledApp>,
+    },
+    MasterFingerprint {
+        fingerprint: String,
+    },
+    Xpub {
+        xpub: String,
+    },
+    Xpubs {
+        xpubs: Vec<PathXpub>,
+    },
+    Signatures {
+        signatures: Vec<Signature>,
+    },
+    MusigNonces {
+        nonces: Vec<MusigValue>,
+    },
+    MusigPartialSigs {
+        signatures: Vec<MusigValue>,
+    },
+    WalletRegistered {
+        id: String,
+        hmac: String,
+    },
+    Address {
+        address: String,
+    },
+    MessageSignature {
+        signature: String,
+    },
+    /// The partial proof is the psbt to be signed by the other cosigners.
+    OwnershipProof {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        proof: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        psbt: Option<String>,
+    },
+    OwnedAddress {
+        change: bool,
+        index: u32,
+    },
+}
+
+impl From<ledger::LedgerResponse> for LedgerResponse {
+    fn from(response: ledger::LedgerResponse) -> Self {
+        match response {
+            ledger::LedgerResponse::TaskDone => Self::TaskDone,
+            ledger::LedgerResponse::AppInfo(info) => Self::AppInfo {
+                name: info.name,
+                version: info.version.to_string(),
+                flags: info.flags.to_lower_hex_string(),
+            },
+            ledger::LedgerResponse::Status(info) => Self::Status {
+                unlocked: info.is_some(),
+                version: info.as_ref().map(|info| info.version.to_string()),
+                app: info.map(|info| info.name),
+            },
+            ledger::LedgerResponse::Apps(apps) => Self::Apps {
+                apps: apps.into_iter().map(InstalledApp::from).collect(),
+            },
+            ledger::LedgerResponse::MasterFingerprint(fg) => Self::MasterFingerprint {
+                fingerprint: fg.to_string(),
+            },
+            ledger::LedgerResponse::Xpub(xpub) => Self::Xpub {
+                xpub: xpub.to_string(),
+            },
+            ledger::LedgerResponse::Xpubs(xpubs) => Self::Xpubs {
+                xpubs: xpubs
+                    .into_iter()
+                    .map(|(path, xpub)| PathXpub {
+                        path: format!("m/{}", path),
+                        xpub: xpub.to_string(),
+                    })
+                    .collect(),
+            },
+            ledger::LedgerResponse::Signatures(signatures) => Self::Signatures {
+                signatures: signatures.into_iter().map(Signature::from).collect(),
+            },
+            ledger::LedgerResponse::MusigNonces(nonces) => Self::MusigNonces {
+                nonces: nonces.into_iter().map(MusigValue::from).collect(),
+            },
+            ledger::LedgerResponse::MusigPartialSigs(signatures) => Self::MusigPartialSigs {
+                signatures: signatures.into_iter().map(MusigValue::from).collect(),
+            },
+            ledger::LedgerResponse::WalletRegistered { id, hmac } => Self::WalletRegistered {
+                id: id.to_lower_hex_string(),
+                hmac: hmac.to_lower_hex_string(),
+            },
+            ledger::LedgerResponse::Address(address) => Self::Address {
+                address: address.assume_checked().to_string(),
+            },
+            ledger::LedgerResponse::MessageSignature(signature) => {
+                Self::MessageSignature { signature }
+            }
+            ledger::LedgerResponse::OwnershipProof(bip322::Proof::Signed(proof)) => {
+                Self::OwnershipProof {
+                    proof: Some(proof),
+                    psbt: None,
+                }
+            }
+            ledger::LedgerResponse::OwnershipProof(bip322::Proof::Partial(psbt)) => {
+                Self::OwnershipProof {
+                    proof: None,
+                    psbt: Some(psbt.to_string()),
+                }
+            }
+            ledger::LedgerResponse::OwnedAddress { change, index } => {
+                Self::OwnedAddress { change, index }
+            }
+        }
+    }
+}
+
+/// Returns the JS object of the value.
+pub fn to_js<T: Serialize>(value: &T) -> Result<JsValue, BhwiError> {
+    let json = serde_json::to_string(value)
+        .map_err(|e| BhwiError::new(ErrorKind::Device, "serialization", e.to_string()))?;
+    Ok(js_sys::JSON::parse(&json)?)
+}
+
+/// Returns the value of the JS object.
+pub fn from_js<T: for<'de> Deserialize<'de>>(value: &JsValue) -> Result<T, BhwiError> {
+    let json: String = js_sys::JSON::stringify(value)
+        .map_err(|_| BhwiError::invalid_argument("The value is not a JSON object"))?
+        .into();
+    serde_json::from_str(&json).map_err(|e| BhwiError::invalid_argument(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_command_from_json() {
+        let command: LedgerCommand =
+            serde_json::from_str(r#"{"type": "getXpub", "path": "m/84'/1'/0'"}"#).unwrap();
+        assert!(matches!(
+            ledger::LedgerCommand::try_from(command).unwrap(),
+            ledger::LedgerCommand::GetXpub { display: false, .. }
+        ));
+
+        let command: LedgerCommand = serde_json::from_str(
+            r#"{
+                "type": "getWalletAddress",
+                "policy": {
+                    "descriptorTemplate": "wpkh(@0/**)",
+                    "keys": ["[5c9e228d/84'/1'/0']tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW"]
+                },
+                "change": false,
+                "index": 3
+            }"#,
+        )
+        .unwrap();
+        match ledger::LedgerCommand::try_from(command).unwrap() {
+            ledger::LedgerCommand::GetWalletAddress {
+                policy,
+                hmac,
+                index,
+                ..
+            } => {
+                assert_eq!(policy.descriptor_template, "wpkh(@0/**)");
+                assert_eq!(policy.keys.len(), 1);
+                assert_eq!(hmac, None);
+                assert_eq!(index, 3);
+            }
+            _ => panic!("expected a wallet address command"),
+        }
+
+        let command: LedgerCommand = serde_json::from_str(
+            r#"{"type": "registerWallet", "policy": {"descriptorTemplate": "wpkh(@0/**)", "keys": ["xpub"]}}"#,
+        )
+        .unwrap();
+        assert!(ledger::LedgerCommand::try_from(command).is_err());
+    }
+
+    #[test]
+    fn test_ledger_response_to_json() {
+        let response = LedgerResponse::from(ledger::LedgerResponse::WalletRegistered {
+            id: [0x01; 32],
+            hmac: [0x02; 32],
+        });
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["type"], "walletRegistered");
+        assert_eq!(json["hmac"], "02".repeat(32));
+
+        let json = serde_json::to_value(LedgerResponse::TaskDone).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "taskDone"}));
+    }
+
+    #[test]
+    fn test_capabilities_to_json() {
+        let capabilities = Capabilities::from(ledger::capabilities(None));
+        let json = serde_json::to_value(&capabilities).unwrap();
+        assert_eq!(json["policyRegistration"], true);
+        assert_eq!(json["networks"][2], "testnet4");
+        assert!(json.get("maxInputs").is_none());
+    }
+}
@@ -1,69 +1,297 @@
+use bhwi::devices::{self, Pacing};
+use bhwi::ledger::transport::framing::{self, Unframer};
+use bhwi_async::exclusive::{SessionGuard, SessionLock};
 use futures::channel::mpsc::{unbounded, UnboundedReceiver};
-use futures::StreamExt;
+use futures::{future, FutureExt, StreamExt};
 use js_sys::Uint8Array;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{HidDevice, HidDeviceRequestOptions};
+use web_sys::{AbortSignal, Hid, HidConnectionEvent, HidDevice, HidDeviceRequestOptions};
+
+use crate::{
+    error::{BhwiError, ErrorKind},
+    timer,
+};
+
+/// Time the device has to detach after an app switch.
+const DETACH_TIMEOUT_MS: u32 = 2_000;
+/// Time the device has to reappear after its re-enumeration.
+const REOPEN_TIMEOUT_MS: u32 = 10_000;
+const REOPEN_INTERVAL_MS: u32 = 100;
+
+fn filters(vendor_id: u16, product_id: Option<u16>) -> Result<JsValue, JsValue> {
+    let filters = js_sys::Array::new();
+    let filter = js_sys::Object::new();
+    js_sys::Reflect::set(&filter, &"vendorId".into(), &JsValue::from(vendor_id))?;
+    if let Some(product_id) = product_id {
+        js_sys::Reflect::set(&filter, &"productId".into(), &JsValue::from(product_id))?;
+    }
+    filters.push(&filter.into());
+    Ok(filters.into())
+}
+
+/// Returns the devices of the vendor already granted to the page.
+async fn granted_devices(hid: &Hid, vendor_id: u16, product_id: Option<u16>) -> Vec<HidDevice> {
+    let Some(devices) = JsFuture::from(hid.get_devices())
+        .await
+        .ok()
+        .and_then(|devices| devices.dyn_into::<js_sys::Array>().ok())
+    else {
+        return Vec::new();
+    };
+    devices
+        .iter()
+        .filter_map(|device| device.dyn_into::<HidDevice>().ok())
+        .filter(|device| {
+            device.vendor_id() == vendor_id
+                && product_id.map_or(true, |pid| device.product_id() == pid)
+        })
+        .collect()
+}
+
+/// Returns the known devices of every vendor granted to the page.
+pub(crate) async fn granted_known_devices(hid: &Hid) -> Vec<HidDevice> {
+    let Some(devices) = JsFuture::from(hid.get_devices())
+        .await
+        .ok()
+        .and_then(|devices| devices.dyn_into::<js_sys::Array>().ok())
+    else {
+        return Vec::new();
+    };
+    devices
+        .iter()
+        .filter_map(|device| device.dyn_into::<HidDevice>().ok())
+        .filter(is_known)
+        .collect()
+}
+
+/// Returns whether the device is a known one, by its USB ids.
+fn is_known(device: &HidDevice) -> bool {
+    devices::identify(device.vendor_id(), device.product_id()).is_some()
+}
+
+/// Returns the size in bytes of the first output report of the device, from
+/// its report descriptor.
+fn output_report_size(device: &HidDevice) -> Option<usize> {
+    let get = |value: &JsValue, key: &str| js_sys::Reflect::get(value, &key.into()).ok();
+    let collection = device.collections().get(0);
+    let reports = get(&collection, "outputReports")?
+        .dyn_into::<js_sys::Array>()
+        .ok()?;
+    let items = get(&reports.get(0), "items")?
+        .dyn_into::<js_sys::Array>()
+        .ok()?;
+    let bits: f64 = items
+        .iter()
+        .map(|item| {
+            let size = get(&item, "reportSize").and_then(|v| v.as_f64());
+            let count = get(&item, "reportCount").and_then(|v| v.as_f64());
+            size.unwrap_or(0.0) * count.unwrap_or(0.0)
+        })
+        .sum();
+    let size = (bits / 8.0) as usize;
+    (size > 0).then_some(size)
+}
+
+thread_local! {
+    /// Locks of the devices opened by the page, the wrappers of the same
+    /// device share its lock.
+    static SESSION_LOCKS: RefCell<Vec<(HidDevice, SessionLock)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Releases the locks of the devices, held by the commands aborted by a
+/// panic. The locks borrowed by the aborted call are skipped.
+pub(crate) fn release_session_locks() {
+    let _ = SESSION_LOCKS.try_with(|locks| {
+        if let Ok(locks) = locks.try_borrow() {
+            locks.iter().for_each(|(_, lock)| lock.release());
+        }
+    });
+}
+
+/// Returns the lock of the device, shared with its other wrappers.
+fn session_lock(device: &HidDevice) -> SessionLock {
+    SESSION_LOCKS.with(|locks| {
+        let mut locks = locks.borrow_mut();
+        let device_value = JsValue::from(device.clone());
+        if let Some((_, lock)) = locks
+            .iter()
+            .find(|(d, _)| JsValue::from(d.clone()) == device_value)
+        {
+            return lock.clone();
+        }
+        let lock = SessionLock::new();
+        locks.push((device.clone(), lock.clone()));
+        lock
+    })
+}
+
+#[derive(Debug, Clone)]
+pub enum WebHidError {
+    /// The connection was closed by the page.
+    Closed,
+    /// The browser rejected the report.
+    SendFailed(JsValue),
+    /// The device was unplugged.
+    Disconnected,
+    /// The device did not answer in time.
+    Timeout,
+    /// The read was aborted by the page.
+    Aborted,
+    /// The browser failed to close the device.
+    CloseFailed(JsValue),
+    /// Another command is exchanging with the device.
+    Busy,
+}
+
+impl WebHidError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WebHidError::Closed => "closed",
+            WebHidError::SendFailed(_) => "send_failed",
+            WebHidError::Disconnected => "disconnected",
+            WebHidError::Timeout => "timeout",
+            WebHidError::Aborted => "aborted",
+            WebHidError::CloseFailed(_) => "close_failed",
+            WebHidError::Busy => "busy",
+        }
+    }
+}
+
+impl std::fmt::Display for WebHidError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebHidError::Closed => write!(f, "HID connection closed"),
+            WebHidError::SendFailed(e) => write!(f, "Failed to send report: {:?}", e),
+            WebHidError::Disconnected => write!(f, "HID device disconnected"),
+            WebHidError::Timeout => write!(f, "HID device did not answer in time"),
+            WebHidError::Aborted => write!(f, "HID read aborted"),
+            WebHidError::CloseFailed(e) => write!(f, "Failed to close device: {:?}", e),
+            WebHidError::Busy => write!(f, "HID device busy with another command"),
+        }
+    }
+}
+
+impl From<WebHidError> for std::io::Error {
+    fn from(e: WebHidError) -> Self {
+        let kind = match e {
+            WebHidError::Closed => std::io::ErrorKind::NotConnected,
+            WebHidError::SendFailed(_) | WebHidError::CloseFailed(_) => std::io::ErrorKind::Other,
+            WebHidError::Disconnected => std::io::ErrorKind::BrokenPipe,
+            WebHidError::Timeout => std::io::ErrorKind::TimedOut,
+            WebHidError::Aborted => std::io::ErrorKind::Interrupted,
+            WebHidError::Busy => std::io::ErrorKind::WouldBlock,
+        };
+        std::io::Error::new(kind, e.to_string())
+    }
+}
+
+type InputReportListener = Closure<dyn FnMut(web_sys::HidInputReportEvent)>;
 
 #[wasm_bindgen]
 pub struct WebHidDevice {
     device: HidDevice,
+    hid: Hid,
     on_close_cb: JsValue,
+    /// The on-close callback was called, by the disconnection or the close.
+    close_notified: Rc<Cell<bool>>,
+    /// Listeners of the input reports of the device and of its disconnection,
+    /// removed on close.
+    listeners: Option<(InputReportListener, ConnectionListener)>,
     msg_queue: UnboundedReceiver<Vec<u8>>,
+    connected: Rc<Cell<bool>>,
+    /// The device re-enumerates, its disconnection is not notified.
+    reconnecting: Rc<Cell<bool>>,
+    pub(crate) read_timeout_ms: Option<u32>,
+    abort_signal: Option<AbortSignal>,
+    /// Size of the reports written, the data is split and padded to it.
+    report_size: usize,
+    /// The data written and read is framed with the HID header of the
+    /// Ledger devices.
+    ledger_framing: bool,
+    /// Pacing of the reports written, see [`Pacing::for_device`].
+    pacing: Pacing,
+    /// Held by the command running on the device.
+    lock: SessionLock,
 }
 
 #[wasm_bindgen]
 impl WebHidDevice {
+    /// Requests a device to the user, with the browser chooser.
     pub async fn get_webhid_device(
-        name: &str,
         vendor_id: u16,
         product_id: Option<u16>,
-        on_close_cb: JsValue,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
     ) -> Option<WebHidDevice> {
         let navigator = web_sys::window()?.navigator();
         let hid = navigator.hid();
 
-        let filters = js_sys::Array::new();
-        let filter = js_sys::Object::new();
-        js_sys::Reflect::set(&filter, &"vendorId".into(), &JsValue::from(vendor_id)).unwrap();
-        if let Some(product_id) = product_id {
-            js_sys::Reflect::set(&filter, &"productId".into(), &JsValue::from(product_id)).unwrap();
-        }
-        filters.push(&filter.into());
+        let options = HidDeviceRequestOptions::new(&filters(vendor_id, product_id).ok()?);
+        let devices = JsFuture::from(hid.request_device(&options))
+            .await
+            .ok()?
+            .dyn_into::<js_sys::Array>()
+            .ok()?;
 
-        let devices = match JsFuture::from(
-            hid.request_device(&HidDeviceRequestOptions::new(&filters.into())),
-        )
-        .await
-        {
-            Ok(devices) => devices.dyn_into::<js_sys::Array>().unwrap(),
-            Err(_) => return None,
-        };
+        // No device was chosen by the user.
+        let device = devices.get(0).dyn_into::<HidDevice>().ok()?;
 
-        if devices.length() == 0 {
+        log::info!("found hid device: {}", device.product_name());
+        if !is_known(&device) {
             return None;
         }
 
-        let device = devices.get(0).dyn_into::<HidDevice>().unwrap();
+        Self::open(device, on_close_cb).await
+    }
 
-        log::info!("found hid device: {}", device.product_name());
-        if !device.product_name().contains(name) {
-            return None;
+    /// Opens a device already granted to the page if one matches, and only
+    /// requests one to the user otherwise.
+    pub async fn get_or_request_webhid_device(
+        vendor_id: u16,
+        product_id: Option<u16>,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Option<WebHidDevice> {
+        let navigator = web_sys::window()?.navigator();
+        let granted = granted_devices(&navigator.hid(), vendor_id, product_id)
+            .await
+            .into_iter()
+            .find(is_known);
+
+        match granted {
+            Some(device) => {
+                log::info!("found granted hid device: {}", device.product_name());
+                Self::open(device, on_close_cb).await
+            }
+            None => Self::get_webhid_device(vendor_id, product_id, on_close_cb).await,
         }
+    }
 
-        // Open the device
-        let open_future = JsFuture::from(device.open());
-        if open_future.await.is_err() {
-            return None;
+    /// Opens the HIDDevice handed over by the page, for example one returned
+    /// by navigator.hid.getDevices().
+    pub async fn from_hid_device(
+        #[wasm_bindgen(unchecked_param_type = "HIDDevice")] device: JsValue,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Option<WebHidDevice> {
+        let device = device.dyn_into::<HidDevice>().ok()?;
+        Self::open(device, on_close_cb).await
+    }
+
+    pub(crate) async fn open(device: HidDevice, on_close_cb: JsValue) -> Option<WebHidDevice> {
+        let hid = web_sys::window()?.navigator().hid();
+
+        // The device may already be opened by the page.
+        if !device.opened() {
+            let open_future = JsFuture::from(device.open());
+            if open_future.await.is_err() {
+                return None;
+            }
         }
 
         let (tx, rx) = unbounded();
 
-        let device_rc = Rc::new(RefCell::new(device.clone()));
-
-        let on_input_report_closure = {
+        let on_input_report: InputReportListener = {
             let tx = tx.clone();
             Closure::wrap(Box::new(move |event: web_sys::HidInputReportEvent| {
                 let data = event.data();
@@ -71,94 +299,274 @@ impl WebHidDevice {
                 let uint8_array = Uint8Array::new(&data.buffer());
                 let mut vec = vec![0u8; length];
                 uint8_array.copy_to(&mut vec[..]);
-                tx.unbounded_send(vec).unwrap();
+                // The queue is closed once the device is closed or disconnected.
+                let _ = tx.unbounded_send(vec);
             }) as Box<dyn FnMut(_)>)
         };
-
         device
             .add_event_listener_with_callback(
                 "inputreport",
-                on_input_report_closure.as_ref().unchecked_ref(),
+                on_input_report.as_ref().unchecked_ref(),
             )
-            .unwrap();
-        on_input_report_closure.forget();
-
-        // Add disconnect event listener
-        let on_close_cb_rc = Rc::new(RefCell::new(on_close_cb.clone()));
-        let on_disconnect_closure = {
-            let device_clone = device_rc.clone();
-            let on_close_cb_clone = on_close_cb_rc.clone();
-            Closure::wrap(Box::new(move |event: web_sys::HidConnectionEvent| {
-                let disconnected_device = event.device();
-                if disconnected_device.vendor_id() == device_clone.borrow().vendor_id()
-                    && disconnected_device.product_id() == device_clone.borrow().product_id()
-                {
-                    let on_close_cb_clone = on_close_cb_clone.borrow();
-                    if !on_close_cb_clone.is_undefined() && !on_close_cb_clone.is_null() {
-                        if let Ok(cb) = <wasm_bindgen::JsValue as Clone>::clone(&on_close_cb_clone)
-                            .dyn_into::<js_sys::Function>()
-                        {
-                            cb.call0(&JsValue::NULL).unwrap();
-                        }
+            .ok()?;
+
+        let connected = Rc::new(Cell::new(true));
+        let close_notified = Rc::new(Cell::new(false));
+        let reconnecting = Rc::new(Cell::new(false));
+        let on_disconnect: ConnectionListener = {
+            let device = device.clone();
+            let on_close_cb = on_close_cb.clone();
+            let connected = connected.clone();
+            let close_notified = close_notified.clone();
+            let reconnecting = reconnecting.clone();
+            Closure::wrap(Box::new(move |event: HidConnectionEvent| {
+                // Several devices of the same model may be connected.
+                if JsValue::from(event.device()) == JsValue::from(device.clone()) {
+                    connected.set(false);
+                    // Wakes up the pending read.
+                    tx.close_channel();
+                    if !reconnecting.get() {
+                        notify_close(&on_close_cb, &close_notified);
                     }
                 }
             }) as Box<dyn FnMut(_)>)
         };
+        if hid
+            .add_event_listener_with_callback("disconnect", on_disconnect.as_ref().unchecked_ref())
+            .is_err()
+        {
+            let _ = device.remove_event_listener_with_callback(
+                "inputreport",
+                on_input_report.as_ref().unchecked_ref(),
+            );
+            return None;
+        }
 
-        hid.add_event_listener_with_callback(
-            "disconnect",
-            on_disconnect_closure.as_ref().unchecked_ref(),
-        )
-        .unwrap();
-        on_disconnect_closure.forget();
-
-        // Return the WebHidDevice
+        let report_size = output_report_size(&device).unwrap_or(framing::PACKET_SIZE);
+        let kind = devices::identify(device.vendor_id(), device.product_id());
+        let ledger_framing = kind.is_some_and(|kind| kind.is_ledger());
+        let pacing = kind.map(Pacing::for_device).unwrap_or_default();
+        let lock = session_lock(&device);
         Some(Self {
             device,
+            hid,
             on_close_cb,
+            close_notified,
+            listeners: Some((on_input_report, on_disconnect)),
             msg_queue: rx,
+            connected,
+            reconnecting,
+            read_timeout_ms: None,
+            abort_signal: None,
+            report_size,
+            ledger_framing,
+            pacing,
+            lock,
         })
     }
 
-    // TODO: return error and maybe remove wasm_bindgen
-    #[wasm_bindgen]
-    pub async fn read(&mut self) -> Option<Vec<u8>> {
-        self.msg_queue.next().await
+    pub(crate) fn product_id(&self) -> u16 {
+        self.device.product_id()
     }
 
-    // TODO: return error and maybe remove wasm_bindgen
-    #[wasm_bindgen]
-    pub async fn write(&self, data: &[u8]) {
-        if self.device.opened() {
-            let uint8_array = js_sys::Uint8Array::from(data);
-            let promise = JsFuture::from(
-                self.device
-                    .send_report_with_u8_array(0, &uint8_array)
-                    .unwrap(),
-            );
-            if let Err(e) = promise.await {
-                log::error!("Failed to send report: {:?}", e);
+    pub(crate) fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    /// Waits before the next report, for the pacing of the writes.
+    pub(crate) async fn sleep(&self, ms: u32) {
+        let _ = timer::timeout(ms.try_into().unwrap_or(i32::MAX)).await;
+    }
+
+    /// Waits for the device to re-enumerate after an app switch and opens it
+    /// again with the settings of the connection. The on-close callback is
+    /// only called if the device does not reappear.
+    pub(crate) async fn reconnect(&mut self) -> Result<(), WebHidError> {
+        self.reconnecting.set(true);
+        let res = self.reopen().await;
+        self.reconnecting.set(false);
+        if res.is_err() {
+            notify_close(&self.on_close_cb, &self.close_notified);
+        }
+        res
+    }
+
+    async fn reopen(&mut self) -> Result<(), WebHidError> {
+        let mut waited_ms = 0;
+        while self.connected.get() && waited_ms < DETACH_TIMEOUT_MS {
+            self.sleep(REOPEN_INTERVAL_MS).await;
+            waited_ms += REOPEN_INTERVAL_MS;
+        }
+        let vendor_id = self.device.vendor_id();
+        let mut waited_ms = 0;
+        loop {
+            let granted = granted_devices(&self.hid, vendor_id, None)
+                .await
+                .into_iter()
+                .find(is_known);
+            if let Some(device) = granted {
+                let mut reopened = Self::open(device, self.on_close_cb.clone())
+                    .await
+                    .ok_or(WebHidError::Disconnected)?;
+                reopened.read_timeout_ms = self.read_timeout_ms;
+                reopened.abort_signal = self.abort_signal.take();
+                reopened.ledger_framing = self.ledger_framing;
+                reopened.pacing = self.pacing;
+                // The running command holds the lock of the device.
+                reopened.lock = self.lock.clone();
+                *self = reopened;
+                return Ok(());
             }
+            if waited_ms >= REOPEN_TIMEOUT_MS {
+                return Err(WebHidError::Disconnected);
+            }
+            self.sleep(REOPEN_INTERVAL_MS).await;
+            waited_ms += REOPEN_INTERVAL_MS;
+        }
+    }
+
+    /// Returns the lock of the device, shared by its wrappers.
+    pub(crate) fn session_lock(&self) -> SessionLock {
+        self.lock.clone()
+    }
+
+    /// Takes the lock of the device for a command, fails if another one
+    /// holds it.
+    pub(crate) fn try_lock(&self) -> Result<SessionGuard, WebHidError> {
+        self.lock.try_lock().map_err(|_| WebHidError::Busy)
+    }
+
+    /// Returns true while a command is exchanging with the device.
+    #[wasm_bindgen]
+    pub fn is_busy(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    fn closed_error(&self) -> WebHidError {
+        if self.connected.get() {
+            WebHidError::Closed
         } else {
-            log::error!("attempted write to a closed HID connection");
+            WebHidError::Disconnected
         }
     }
 
+    /// Sets the timeout of the reads, none by default.
+    #[wasm_bindgen]
+    pub fn set_read_timeout(&mut self, timeout_ms: Option<u32>) {
+        self.read_timeout_ms = timeout_ms;
+    }
+
+    /// Sets the signal aborting the pending and the next reads, so that the
+    /// page cancels a stuck exchange.
     #[wasm_bindgen]
-    pub fn close(&mut self) {
-        let close_future = JsFuture::from(self.device.close());
-        let on_close_cb = self.on_close_cb.clone(); // Clone the JsValue for use in the async block
+    pub fn set_abort_signal(&mut self, signal: Option<AbortSignal>) {
+        self.abort_signal = signal;
+    }
 
-        wasm_bindgen_futures::spawn_local(async move {
-            close_future.await.unwrap();
+    /// Frames the data written and read with the HID header of the Ledger
+    /// devices, enabled by default for them.
+    #[wasm_bindgen]
+    pub fn set_ledger_framing(&mut self, enabled: bool) {
+        self.ledger_framing = enabled;
+    }
 
-            // Check if `on_close_cb` is a valid function and call it
-            if !on_close_cb.is_undefined() && !on_close_cb.is_null() {
-                if let Ok(cb) = on_close_cb.dyn_into::<js_sys::Function>() {
-                    cb.call0(&JsValue::NULL).unwrap();
-                }
+    /// Waits `min_interval_ms` after every `max_in_flight` reports written,
+    /// for the hubs dropping the reports written back to back. Set by
+    /// default for the models needing it, the clients connected afterwards
+    /// use it too.
+    #[wasm_bindgen]
+    pub fn set_pacing(&mut self, min_interval_ms: u32, max_in_flight: u32) {
+        self.pacing = Pacing {
+            min_interval_ms,
+            max_in_flight: max_in_flight as usize,
+        };
+    }
+
+    /// Returns the next message of the device, reassembled from its reports
+    /// with the Ledger framing, its next report otherwise.
+    #[wasm_bindgen]
+    pub async fn read(&mut self) -> Result<Vec<u8>, BhwiError> {
+        self.read_timeout(self.read_timeout_ms).await
+    }
+
+    /// Returns the next message of the device, or fails once a report is not
+    /// received in time.
+    #[wasm_bindgen]
+    pub async fn read_timeout(&mut self, timeout_ms: Option<u32>) -> Result<Vec<u8>, BhwiError> {
+        if !self.ledger_framing {
+            return Ok(self.read_report(timeout_ms).await?);
+        }
+        let mut unframer = Unframer::new();
+        loop {
+            let report = self.read_report(timeout_ms).await?;
+            if let Some(answer) = unframer.push(&report).map_err(|e| {
+                BhwiError::new(
+                    ErrorKind::Transport,
+                    "invalid_report",
+                    format!("Invalid report: {:?}", e),
+                )
+            })? {
+                return Ok(answer);
             }
-        });
+        }
+    }
+
+    /// Writes the data in reports of the size of the device, framed with the
+    /// Ledger framing or padded with zeros.
+    #[wasm_bindgen]
+    pub async fn write(&self, data: &[u8]) -> Result<(), BhwiError> {
+        let reports = if self.ledger_framing {
+            framing::frame(data)
+        } else {
+            data.chunks(self.report_size)
+                .map(|chunk| {
+                    let mut report = chunk.to_vec();
+                    report.resize(self.report_size, 0x00);
+                    report
+                })
+                .collect()
+        };
+        for (i, report) in reports.into_iter().enumerate() {
+            let delay_ms = self.pacing.delay_ms(i);
+            if delay_ms > 0 {
+                self.sleep(delay_ms).await;
+            }
+            self.write_report(&report).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the apdu to a Ledger device and returns its answer, the reports
+    /// are framed and reassembled on this side.
+    #[wasm_bindgen]
+    pub async fn exchange_apdu(&mut self, apdu: &[u8]) -> Result<Vec<u8>, BhwiError> {
+        let ledger_framing = std::mem::replace(&mut self.ledger_framing, true);
+        let res = match self.write(apdu).await {
+            Ok(()) => self.read().await,
+            Err(e) => Err(e),
+        };
+        self.ledger_framing = ledger_framing;
+        res
+    }
+
+    /// Releases the device: its listeners are removed, the reports not read
+    /// are dropped and the device is closed. The on-close callback is called
+    /// once, here or on the disconnection of the device, whichever is first.
+    #[wasm_bindgen]
+    pub async fn close(&mut self) -> Result<(), BhwiError> {
+        self.remove_listeners();
+        self.msg_queue.close();
+        while let Ok(Some(_)) = self.msg_queue.try_next() {}
+        let res = if self.device.opened() {
+            JsFuture::from(self.device.close())
+                .await
+                .map(|_| ())
+                .map_err(|e| WebHidError::CloseFailed(e).into())
+        } else {
+            Ok(())
+        };
+        notify_close(&self.on_close_cb, &self.close_notified);
+        res
     }
 
     #[wasm_bindgen]
@@ -166,3 +574,222 @@ impl WebHidDevice {
         self.device.opened()
     }
 }
+
+impl WebHidDevice {
+    fn remove_listeners(&mut self) {
+        if let Some((on_input_report, on_disconnect)) = self.listeners.take() {
+            let _ = self.device.remove_event_listener_with_callback(
+                "inputreport",
+                on_input_report.as_ref().unchecked_ref(),
+            );
+            let _ = self.hid.remove_event_listener_with_callback(
+                "disconnect",
+                on_disconnect.as_ref().unchecked_ref(),
+            );
+        }
+    }
+
+    /// Returns the next report of the device, or fails after the timeout.
+    pub(crate) async fn read_report(
+        &mut self,
+        timeout_ms: Option<u32>,
+    ) -> Result<Vec<u8>, WebHidError> {
+        let timeout = async {
+            match timeout_ms {
+                Some(ms) => timer::timeout(ms.try_into().unwrap_or(i32::MAX)).await,
+                None => future::pending().await,
+            }
+        };
+        let aborted = async {
+            match &self.abort_signal {
+                Some(signal) => timer::aborted(signal).await,
+                None => future::pending().await,
+            }
+        };
+        futures::select! {
+            report = self.msg_queue.next() => report.ok_or_else(|| self.closed_error()),
+            _ = timeout.fuse() => Err(WebHidError::Timeout),
+            _ = aborted.fuse() => Err(WebHidError::Aborted),
+        }
+    }
+
+    /// Writes the report as is.
+    pub(crate) async fn write_report(&self, report: &[u8]) -> Result<(), WebHidError> {
+        if !self.connected.get() || !self.device.opened() {
+            return Err(self.closed_error());
+        }
+        let uint8_array = js_sys::Uint8Array::from(report);
+        let promise = self
+            .device
+            .send_report_with_u8_array(0, &uint8_array)
+            .map_err(WebHidError::SendFailed)?;
+        JsFuture::from(promise)
+            .await
+            .map_err(WebHidError::SendFailed)?;
+        Ok(())
+    }
+}
+
+/// The listeners are removed before their closures are dropped, the device
+/// is left open.
+impl Drop for WebHidDevice {
+    fn drop(&mut self) {
+        self.remove_listeners();
+    }
+}
+
+/// Calls the on-close callback unless it was already called.
+fn notify_close(on_close_cb: &JsValue, notified: &Cell<bool>) {
+    if notified.replace(true) {
+        return;
+    }
+    if let Some(cb) = on_close_cb.dyn_ref::<js_sys::Function>() {
+        if let Err(e) = cb.call0(&JsValue::NULL) {
+            log::error!("hid on close callback failed: {:?}", e);
+        }
+    }
+}
+
+/// Description of a device, the serial number is not exposed by WebHID.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Debug, Clone)]
+pub struct WebHidDeviceInfo {
+    pub product_name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub opened: bool,
+}
+
+impl From<&HidDevice> for WebHidDeviceInfo {
+    fn from(device: &HidDevice) -> Self {
+        Self {
+            product_name: device.product_name(),
+            vendor_id: device.vendor_id(),
+            product_id: device.product_id(),
+            opened: device.opened(),
+        }
+    }
+}
+
+/// Devices of a vendor granted to the page, so that the user picks the one
+/// to open when several are connected.
+#[wasm_bindgen]
+pub struct WebHidDeviceList {
+    devices: Vec<HidDevice>,
+}
+
+#[wasm_bindgen]
+impl WebHidDeviceList {
+    /// Lists the devices already granted to the page, without prompting.
+    pub async fn granted(vendor_id: u16, product_id: Option<u16>) -> Option<WebHidDeviceList> {
+        let hid = web_sys::window()?.navigator().hid();
+        Some(Self {
+            devices: granted_devices(&hid, vendor_id, product_id).await,
+        })
+    }
+
+    /// Requests the devices to the user with the browser chooser, then lists
+    /// every device granted to the page, the previous ones included.
+    pub async fn request(vendor_id: u16, product_id: Option<u16>) -> Option<WebHidDeviceList> {
+        let hid = web_sys::window()?.navigator().hid();
+        let options = HidDeviceRequestOptions::new(&filters(vendor_id, product_id).ok()?);
+        if let Err(e) = JsFuture::from(hid.request_device(&options)).await {
+            log::warn!("hid device request failed: {:?}", e);
+        }
+        Some(Self {
+            devices: granted_devices(&hid, vendor_id, product_id).await,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn devices(&self) -> Vec<WebHidDeviceInfo> {
+        self.devices.iter().map(WebHidDeviceInfo::from).collect()
+    }
+
+    /// Opens the device at the index of the list.
+    pub async fn open(
+        &self,
+        index: usize,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
+    ) -> Option<WebHidDevice> {
+        let device = self.devices.get(index)?.clone();
+        WebHidDevice::open(device, on_close_cb).await
+    }
+}
+
+type ConnectionListener = Closure<dyn FnMut(HidConnectionEvent)>;
+
+/// Watches the connections and disconnections of the devices granted to the
+/// page, so that the page detects a device plugged in without polling.
+///
+/// The callbacks are called with the WebHidDeviceInfo and the HIDDevice, to
+/// be opened with WebHidDevice.from_hid_device.
+#[wasm_bindgen]
+pub struct DeviceWatcher {
+    hid: Hid,
+    listeners: Vec<(&'static str, ConnectionListener)>,
+}
+
+#[wasm_bindgen]
+impl DeviceWatcher {
+    /// Starts watching the devices of the vendor, or of every vendor if none
+    /// is given.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        vendor_id: Option<u16>,
+        #[wasm_bindgen(unchecked_param_type = "HidConnectionCallback | undefined")]
+        on_connect: Option<js_sys::Function>,
+        #[wasm_bindgen(unchecked_param_type = "HidConnectionCallback | undefined")]
+        on_disconnect: Option<js_sys::Function>,
+    ) -> Option<DeviceWatcher> {
+        let mut watcher = Self {
+            hid: web_sys::window()?.navigator().hid(),
+            listeners: Vec::new(),
+        };
+        for (event, cb) in [("connect", on_connect), ("disconnect", on_disconnect)] {
+            let Some(cb) = cb else {
+                continue;
+            };
+            let closure = Closure::wrap(Box::new(move |event: HidConnectionEvent| {
+                let device = event.device();
+                if vendor_id.map_or(true, |vid| device.vendor_id() == vid) {
+                    let info = WebHidDeviceInfo::from(&device);
+                    if let Err(e) = cb.call2(&JsValue::NULL, &info.into(), &device) {
+                        log::error!("hid {} callback failed: {:?}", event.type_(), e);
+                    }
+                }
+            }) as Box<dyn FnMut(_)>);
+            let res = watcher
+                .hid
+                .add_event_listener_with_callback(event, closure.as_ref().unchecked_ref());
+            watcher.listeners.push((event, closure));
+            if res.is_err() {
+                // The listeners added so far are removed on drop.
+                return None;
+            }
+        }
+        Some(watcher)
+    }
+
+    /// Stops watching, the callbacks are not called anymore.
+    pub fn stop(&mut self) {
+        for (event, closure) in self.listeners.drain(..) {
+            let _ = self
+                .hid
+                .remove_event_listener_with_callback(event, closure.as_ref().unchecked_ref());
+        }
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -9,6 +9,11 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{ReadableStreamDefaultReader, SerialOptions, SerialPort, SerialPortRequestOptions};
 
+use crate::{
+    error::{BhwiError, ErrorKind},
+    timer,
+};
+
 #[wasm_bindgen]
 pub struct WebSerialDevice {
     port: SerialPort,
@@ -17,19 +22,33 @@ pub struct WebSerialDevice {
 
 #[wasm_bindgen]
 impl WebSerialDevice {
+    /// Requests a port to the user, among the USB serial ports of the vendors
+    /// if any is given, and opens it at the baud rate.
     pub async fn get_webserial_device(
         baud_rate: u32,
-        on_close_cb: JsValue,
+        usb_vendor_ids: Vec<u16>,
+        #[wasm_bindgen(unchecked_param_type = "OnCloseCallback | undefined")] on_close_cb: JsValue,
     ) -> Option<WebSerialDevice> {
         let navigator = web_sys::window()?.navigator();
         let serial = navigator.serial();
 
         let options = SerialPortRequestOptions::new();
+        if !usb_vendor_ids.is_empty() {
+            let filters = js_sys::Array::new();
+            for vendor_id in usb_vendor_ids {
+                let filter = js_sys::Object::new();
+                js_sys::Reflect::set(&filter, &"usbVendorId".into(), &JsValue::from(vendor_id))
+                    .ok()?;
+                filters.push(&filter.into());
+            }
+            options.set_filters(&filters.into());
+        }
 
-        let port = match JsFuture::from(serial.request_port_with_options(&options)).await {
-            Ok(port) => port.dyn_into::<SerialPort>().unwrap(),
-            Err(_) => return None,
-        };
+        let port = JsFuture::from(serial.request_port_with_options(&options))
+            .await
+            .ok()?
+            .dyn_into::<SerialPort>()
+            .ok()?;
 
         log::info!("found serial device");
 
@@ -39,7 +58,8 @@ impl WebSerialDevice {
             return None;
         }
 
-        // Add disconnect event listener
+        // Only the disconnection of the opened port is listened, the events of
+        // navigator.serial are fired for every port.
         let on_close_cb_rc = Rc::new(RefCell::new(on_close_cb.clone()));
         let on_disconnect_closure = {
             let on_close_cb_clone = on_close_cb_rc.clone();
@@ -49,18 +69,19 @@ impl WebSerialDevice {
                     if let Ok(cb) = <wasm_bindgen::JsValue as Clone>::clone(&on_close_cb_clone)
                         .dyn_into::<js_sys::Function>()
                     {
-                        cb.call0(&JsValue::NULL).unwrap();
+                        if let Err(e) = cb.call0(&JsValue::NULL) {
+                            log::error!("serial on close callback failed: {:?}", e);
+                        }
                     }
                 }
             }) as Box<dyn FnMut(_)>)
         };
 
-        serial
-            .add_event_listener_with_callback(
-                "disconnect",
-                on_disconnect_closure.as_ref().unchecked_ref(),
-            )
-            .unwrap();
+        port.add_event_listener_with_callback(
+            "disconnect",
+            on_disconnect_closure.as_ref().unchecked_ref(),
+        )
+        .ok()?;
         on_disconnect_closure.forget();
 
         // Return the WebSerialDevice
@@ -69,29 +90,12 @@ impl WebSerialDevice {
 
     #[wasm_bindgen]
     pub async fn read(&self) -> Option<Vec<u8>> {
-        let reader = self.port.readable().get_reader();
-        let reader = reader
+        let reader = self
+            .port
+            .readable()
+            .get_reader()
             .dyn_into::<ReadableStreamDefaultReader>()
-            .expect("Failed to cast to ReadableStreamDefaultReader");
-        // Function to create a timeout future
-        fn create_timeout_future(timeout_ms: i32) -> JsFuture {
-            let promise = js_sys::Promise::new(&mut |resolve, _| {
-                let closure = Closure::wrap(Box::new(move || {
-                    resolve.call0(&JsValue::UNDEFINED).unwrap();
-                }) as Box<dyn FnMut()>);
-
-                web_sys::window()
-                    .unwrap()
-                    .set_timeout_with_callback_and_timeout_and_arguments_0(
-                        closure.as_ref().unchecked_ref(),
-                        timeout_ms,
-                    )
-                    .unwrap();
-
-                closure.forget(); // Avoid dropping the closure prematurely
-            });
-            JsFuture::from(promise)
-        }
+            .ok()?;
 
         let mut res = Vec::new();
 
@@ -125,7 +129,7 @@ impl WebSerialDevice {
         loop {
             match select(
                 wasm_bindgen_futures::JsFuture::from(reader.read()),
-                create_timeout_future(500),
+                timer::timeout(500),
             )
             .await
             {
@@ -164,13 +168,14 @@ impl WebSerialDevice {
     }
 
     #[wasm_bindgen]
-    pub async fn write(&self, data: &[u8]) -> Result<(), JsValue> {
+    pub async fn write(&self, data: &[u8]) -> Result<(), BhwiError> {
         let writable = self.port.writable();
-        let writer = writable.get_writer().unwrap();
+        let writer = writable.get_writer()?;
         let uint8_array = Uint8Array::from(data);
 
-        JsFuture::from(writer.write_with_chunk(&uint8_array.into())).await?;
+        let res = JsFuture::from(writer.write_with_chunk(&uint8_array.into())).await;
         writer.release_lock();
+        res?;
         Ok(())
     }
 
@@ -180,12 +185,16 @@ impl WebSerialDevice {
         let on_close_cb = self.on_close_cb.clone();
 
         wasm_bindgen_futures::spawn_local(async move {
-            close_future.await.unwrap();
+            if let Err(e) = close_future.await {
+                log::error!("failed to close the serial port: {:?}", e);
+            }
 
             // Check if `on_close_cb` is a valid function and call it
             if !on_close_cb.is_undefined() && !on_close_cb.is_null() {
                 if let Ok(cb) = on_close_cb.dyn_into::<js_sys::Function>() {
-                    cb.call0(&JsValue::NULL).unwrap();
+                    if let Err(e) = cb.call0(&JsValue::NULL) {
+                        log::error!("serial on close callback failed: {:?}", e);
+                    }
                 }
             }
         });
@@ -194,9 +203,15 @@ impl WebSerialDevice {
 
 #[async_trait(?Send)]
 impl Transport for WebSerialDevice {
-    type Error = JsValue;
+    type Error = BhwiError;
     async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, Self::Error> {
         self.write(command).await?;
-        Ok(self.read().await.unwrap())
+        self.read().await.ok_or_else(|| {
+            BhwiError::new(
+                ErrorKind::Transport,
+                "read_failed",
+                "Failed to read from serial port",
+            )
+        })
     }
 }
@@ -0,0 +1,199 @@
+//! Execution of the interpreters in a dedicated Web Worker, so that the long
+//! signing sessions and the processing of large psbts do not block the page.
+//!
+//! WebHID is only available to the page: it serves the device with a
+//! [`HidProxy`] on one port of a `MessageChannel`, and posts the other port
+//! to the worker running a [`crate::ledger::LedgerClient`] over it, see
+//! `LedgerClient.fromPort`. Each exchange is a request of the worker,
+//! `{ id, apdu }` or `{ id, reconnect: true }`, answered by the page with
+//! `{ id, answer }` or `{ id, error: { kind, message } }`.
+
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use bhwi_async::exclusive::SessionGuard;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use futures::lock::Mutex;
+use futures::StreamExt;
+use js_sys::{Object, Reflect, Uint8Array};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::{MessageEvent, MessagePort};
+
+use crate::error::{BhwiError, ErrorKind};
+use crate::webhid::WebHidDevice;
+
+fn field(value: &JsValue, name: &str) -> JsValue {
+    Reflect::get(value, &JsValue::from_str(name)).unwrap_or(JsValue::UNDEFINED)
+}
+
+fn message(fields: &[(&str, JsValue)]) -> Object {
+    let object = Object::new();
+    for (name, value) in fields {
+        let _ = Reflect::set(&object, &JsValue::from_str(name), value);
+    }
+    object
+}
+
+fn proxy_error(message: &str) -> BhwiError {
+    BhwiError::new(ErrorKind::Transport, "proxy_failed", message)
+}
+
+/// Serves the exchanges of a worker with the device of the page.
+#[wasm_bindgen]
+pub struct HidProxy {
+    port: MessagePort,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    /// The device is locked for the worker while served.
+    _guard: SessionGuard,
+}
+
+#[wasm_bindgen]
+impl HidProxy {
+    /// Serves the requests of the worker on the port with the device, the
+    /// other wrappers of the device are busy until the proxy is closed.
+    pub fn serve(device: WebHidDevice, port: MessagePort) -> Result<HidProxy, BhwiError> {
+        let guard = device.try_lock()?;
+        let device = Rc::new(Mutex::new(device));
+        let reply_port = port.clone();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let (device, port) = (device.clone(), reply_port.clone());
+            wasm_bindgen_futures::spawn_local(async move {
+                let request = event.data();
+                // The requests are served in their order.
+                let mut device = device.lock().await;
+                let res = if field(&request, "reconnect").is_truthy() {
+                    device
+                        .reconnect()
+                        .await
+                        .map(|_| Vec::new())
+                        .map_err(BhwiError::from)
+                } else {
+                    match field(&request, "apdu").dyn_into::<Uint8Array>() {
+                        Ok(apdu) => device.exchange_apdu(&apdu.to_vec()).await,
+                        Err(_) => Err(BhwiError::invalid_argument("Request without apdu")),
+                    }
+                };
+                let id = field(&request, "id");
+                let reply = match res {
+                    Ok(answer) => {
+                        message(&[("id", id), ("answer", Uint8Array::from(&answer[..]).into())])
+                    }
+                    Err(e) => {
+                        let error =
+                            message(&[("kind", e.kind().into()), ("message", e.message().into())]);
+                        message(&[("id", id), ("error", error.into())])
+                    }
+                };
+                if let Err(e) = port.post_message(&reply) {
+                    log::error!("failed to answer the worker: {:?}", e);
+                }
+            });
+        });
+        port.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        Ok(HidProxy {
+            port,
+            _on_message: on_message,
+            _guard: guard,
+        })
+    }
+
+    /// Stops serving the worker and releases the device.
+    pub fn close(self) {
+        self.port.close();
+    }
+}
+
+impl Drop for HidProxy {
+    fn drop(&mut self) {
+        self.port.set_onmessage(None);
+    }
+}
+
+/// Transport of a worker, the apdus are exchanged by the [`HidProxy`] of
+/// the page.
+pub struct PortTransport {
+    port: MessagePort,
+    replies: UnboundedReceiver<JsValue>,
+    next_id: u32,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl PortTransport {
+    pub fn new(port: MessagePort) -> Self {
+        let (sender, replies) = unbounded();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let _ = sender.unbounded_send(event.data());
+        });
+        port.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        Self {
+            port,
+            replies,
+            next_id: 0,
+            _on_message: on_message,
+        }
+    }
+
+    /// Posts the request and waits for its answer, the answers of the
+    /// requests dropped before their end are skipped.
+    async fn request(&mut self, fields: &[(&str, JsValue)]) -> Result<Vec<u8>, BhwiError> {
+        self.next_id = self.next_id.wrapping_add(1);
+        let id = self.next_id;
+        let request = message(fields);
+        let _ = Reflect::set(&request, &JsValue::from_str("id"), &id.into());
+        self.port
+            .post_message(&request)
+            .map_err(|e| proxy_error("Failed to post to the page").with_cause(e))?;
+        while let Some(reply) = self.replies.next().await {
+            if field(&reply, "id").as_f64() != Some(id.into()) {
+                continue;
+            }
+            let error = field(&reply, "error");
+            if !error.is_undefined() {
+                let kind = field(&error, "kind")
+                    .as_string()
+                    .and_then(|kind| ErrorKind::from_str(&kind).ok())
+                    .unwrap_or(ErrorKind::Transport);
+                let message = field(&error, "message").as_string().unwrap_or_default();
+                return Err(BhwiError::new(kind, "proxy_failed", message));
+            }
+            return field(&reply, "answer")
+                .dyn_into::<Uint8Array>()
+                .map(|answer| answer.to_vec())
+                .map_err(|_| proxy_error("Answer without data"));
+        }
+        Err(BhwiError::not_connected())
+    }
+
+    /// Sends the apdu to the device of the page and returns its answer.
+    pub async fn exchange_apdu(&mut self, apdu: &[u8]) -> Result<Vec<u8>, BhwiError> {
+        self.request(&[("apdu", Uint8Array::from(apdu).into())])
+            .await
+    }
+
+    /// Waits for the page to reopen the device after an app switch.
+    pub async fn reconnect(&mut self) -> Result<(), BhwiError> {
+        self.request(&[("reconnect", JsValue::TRUE)])
+            .await
+            .map(|_| ())
+    }
+}
+
+impl Drop for PortTransport {
+    fn drop(&mut self) {
+        self.port.set_onmessage(None);
+    }
+}
+
+/// The apdus are framed by the page, for the Ledger devices of
+/// `bhwi_async`.
+#[async_trait(?Send)]
+impl bhwi_async::Transport for PortTransport {
+    type Error = BhwiError;
+    async fn exchange(&mut self, command: &[u8], _encrypted: bool) -> Result<Vec<u8>, BhwiError> {
+        self.exchange_apdu(command).await
+    }
+    async fn reconnect(&mut self) -> Result<(), BhwiError> {
+        PortTransport::reconnect(self).await
+    }
+}
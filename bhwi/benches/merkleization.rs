@@ -0,0 +1,157 @@
+//! Benchmarks of the commitments to the PSBT maps sent to the Ledger
+//! bitcoin app, and of the client commands answering the device.
+//!
+//!     cargo bench -p bhwi --bench merkleization [filter]
+//!
+//! Uses a minimal timing loop in place of a benchmark framework: each case
+//! runs for at least `MIN_DURATION` and reports the mean time per iteration.
+
+use std::hint::black_box;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use bhwi::ledger::{
+    apdu::ClientCommandCode, merkleized_map, psbt as ledger_psbt, store::DelegatedStore,
+};
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{DerivationPath, Fingerprint},
+    consensus::encode::{serialize, VarInt},
+    hashes::Hash,
+    secp256k1::{Secp256k1, SecretKey},
+    transaction::Version,
+    Amount, CompressedPublicKey, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, Witness,
+};
+
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+fn bench<T>(filter: Option<&str>, name: &str, mut f: impl FnMut() -> T) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+    // Warm up.
+    black_box(f());
+    let mut iterations = 0u32;
+    let start = Instant::now();
+    while start.elapsed() < MIN_DURATION {
+        black_box(f());
+        iterations += 1;
+    }
+    println!(
+        "{:<40} {:>12?}/iter ({} iterations)",
+        name,
+        start.elapsed() / iterations,
+        iterations
+    );
+}
+
+/// PSBT spending `n` p2wpkh outputs to two outputs.
+fn psbt(n: usize) -> Psbt {
+    let secp = Secp256k1::new();
+    let fingerprint = Fingerprint::from_str("f5acc2fd").unwrap();
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: (0..n)
+            .map(|i| TxIn {
+                previous_output: OutPoint::new(Txid::hash(&i.to_le_bytes()), i as u32),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: (0..2)
+            .map(|i| TxOut {
+                value: Amount::from_sat(10_000 * (i + 1)),
+                script_pubkey: ScriptBuf::new_op_return([i as u8; 20]),
+            })
+            .collect(),
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+    for (i, input) in psbt.inputs.iter_mut().enumerate() {
+        let mut secret = [0x01; 32];
+        secret[..8].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+        let pubkey = SecretKey::from_slice(&secret).unwrap().public_key(&secp);
+        input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&CompressedPublicKey(pubkey).wpubkey_hash()),
+        });
+        input.bip32_derivation.insert(
+            pubkey,
+            (
+                fingerprint,
+                DerivationPath::from_str(&format!("m/84'/1'/0'/0/{}", i)).unwrap(),
+            ),
+        );
+    }
+    psbt
+}
+
+/// Adds the PSBT maps to the store, as done before a SIGN_PSBT command,
+/// returns the roots of the lists of input and output commitments.
+fn commit(store: &mut DelegatedStore, psbt: &Psbt) -> ([u8; 32], [u8; 32]) {
+    store.add_known_mapping(ledger_psbt::get_v2_global_map(psbt));
+    let inputs: Vec<Vec<u8>> = ledger_psbt::get_v2_input_maps(psbt)
+        .map(|map| store.add_known_mapping(map))
+        .collect();
+    let outputs: Vec<Vec<u8>> = ledger_psbt::get_v2_output_maps(psbt)
+        .map(|map| store.add_known_mapping(map))
+        .collect();
+    (
+        store.add_known_list(&inputs),
+        store.add_known_list(&outputs),
+    )
+}
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+
+    for n in [1, 50, 500] {
+        let psbt = psbt(n);
+        bench(filter, &format!("input_maps/{}", n), || {
+            ledger_psbt::get_v2_input_maps(&psbt).count()
+        });
+        bench(filter, &format!("commit/{}", n), || {
+            commit(&mut DelegatedStore::new(), &psbt)
+        });
+
+        // Client commands issued by the device while signing the last input.
+        let mut store = DelegatedStore::new();
+        let (inputs_root, _) = commit(&mut store, &psbt);
+        let commitment = ledger_psbt::get_v2_input_maps(&psbt)
+            .map(|map| merkleized_map::MapCommitment::new(&map).serialize())
+            .last()
+            .unwrap();
+        let leaf = merkleized_map::leaf_hash(&commitment);
+
+        let mut preimage = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        preimage.extend(leaf);
+        bench(filter, &format!("get_preimage/{}", n), || {
+            store.execute(preimage.clone()).unwrap()
+        });
+
+        let mut index = vec![ClientCommandCode::GetMerkleLeafIndex as u8];
+        index.extend(inputs_root);
+        index.extend(leaf);
+        bench(filter, &format!("get_merkle_leaf_index/{}", n), || {
+            store.execute(index.clone()).unwrap()
+        });
+
+        let mut proof = vec![ClientCommandCode::GetMerkleLeafProof as u8];
+        proof.extend(inputs_root);
+        proof.extend(serialize(&VarInt(n as u64)));
+        proof.extend(serialize(&VarInt(n as u64 - 1)));
+        bench(filter, &format!("get_merkle_leaf_proof/{}", n), || {
+            let response = store.execute(proof.clone()).unwrap();
+            // The elements of the proof after the sixth one are queued.
+            if response[32] > response[33] {
+                store
+                    .execute(vec![ClientCommandCode::GetMoreElements as u8])
+                    .unwrap();
+            }
+            response
+        });
+    }
+}
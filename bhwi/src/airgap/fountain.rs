@@ -0,0 +1,385 @@
+//! Fountain codes of the multi part URs.
+//!
+//! The message is split in fragments of equal length. The first parts hold
+//! each fragment, the next ones the XOR of fragments chosen by a generator
+//! seeded with the sequence number and the checksum of the message, so that
+//! the decoder recovers the message from any large enough set of parts.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::hashes::{sha256, Hash};
+use serde_cbor::Value;
+
+use super::{ur::crc32, AirgapError};
+
+/// Minimal length of the fragments.
+pub const MIN_FRAGMENT_LEN: usize = 10;
+/// Maximal number of fragments of a message, the parts are scanned from an
+/// untrusted source and the decoder allocates for each fragment.
+pub const MAX_SEQ_LEN: u32 = 10_000;
+
+/// xoshiro256** generator.
+struct Xoshiro256([u64; 4]);
+
+impl Xoshiro256 {
+    fn from_seed(data: &[u8]) -> Self {
+        let digest = sha256::Hash::hash(data).to_byte_array();
+        let mut s = [0u64; 4];
+        for (i, chunk) in digest.chunks(8).enumerate() {
+            s[i] = u64::from_be_bytes(chunk.try_into().expect("8 bytes chunk"));
+        }
+        Self(s)
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.0;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn next_double(&mut self) -> f64 {
+        self.next() as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    fn next_int(&mut self, low: u64, high: u64) -> u64 {
+        (self.next_double() * (high - low + 1) as f64) as u64 + low
+    }
+}
+
+/// Walker's alias method, sampling the indexes of the weights.
+struct Sampler {
+    probs: Vec<f64>,
+    aliases: Vec<usize>,
+}
+
+impl Sampler {
+    fn new(weights: &[f64]) -> Self {
+        let n = weights.len();
+        let sum: f64 = weights.iter().sum();
+        let mut p: Vec<f64> = weights.iter().map(|w| w * n as f64 / sum).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).rev().partition(|i| p[*i] < 1.0);
+        let mut probs = vec![0.0; n];
+        let mut aliases = vec![0; n];
+        while let (Some(a), Some(g)) = (small.pop(), large.pop()) {
+            probs[a] = p[a];
+            aliases[a] = g;
+            p[g] += p[a] - 1.0;
+            if p[g] < 1.0 {
+                small.push(g);
+            } else {
+                large.push(g);
+            }
+        }
+        for i in large.into_iter().chain(small) {
+            probs[i] = 1.0;
+        }
+        Self { probs, aliases }
+    }
+
+    fn next(&self, rng: &mut Xoshiro256) -> usize {
+        let r1 = rng.next_double();
+        let r2 = rng.next_double();
+        let i = (self.probs.len() as f64 * r1) as usize;
+        if r2 < self.probs[i] {
+            i
+        } else {
+            self.aliases[i]
+        }
+    }
+}
+
+/// Returns the indexes of the fragments mixed in the part.
+pub fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if seq_num as usize <= seq_len {
+        return BTreeSet::from([seq_num as usize - 1]);
+    }
+    let mut seed = seq_num.to_be_bytes().to_vec();
+    seed.extend(checksum.to_be_bytes());
+    let mut rng = Xoshiro256::from_seed(&seed);
+    let weights: Vec<f64> = (1..=seq_len).map(|i| 1.0 / i as f64).collect();
+    let degree = Sampler::new(&weights).next(&mut rng) + 1;
+    shuffled((0..seq_len).collect(), &mut rng)
+        .into_iter()
+        .take(degree)
+        .collect()
+}
+
+fn shuffled<T>(mut items: Vec<T>, rng: &mut Xoshiro256) -> Vec<T> {
+    let mut res = Vec::with_capacity(items.len());
+    while !items.is_empty() {
+        let index = rng.next_int(0, items.len() as u64 - 1) as usize;
+        res.push(items.remove(index));
+    }
+    res
+}
+
+/// Returns the length of the fragments, the smallest number of fragments
+/// not longer than the maximal length is used.
+pub fn fragment_len(message_len: usize, min_len: usize, max_len: usize) -> usize {
+    let max_count = std::cmp::max(message_len / min_len, 1);
+    let mut len = message_len;
+    for count in 1..=max_count {
+        len = message_len.div_ceil(count);
+        if len <= max_len {
+            break;
+        }
+    }
+    len
+}
+
+fn xor_into(data: &mut [u8], other: &[u8]) {
+    for (a, b) in data.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub seq_num: u32,
+    pub seq_len: u32,
+    pub message_len: u32,
+    pub checksum: u32,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn to_cbor(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&Value::Array(vec![
+            Value::Integer(self.seq_num.into()),
+            Value::Integer(self.seq_len.into()),
+            Value::Integer(self.message_len.into()),
+            Value::Integer(self.checksum.into()),
+            Value::Bytes(self.data.clone()),
+        ]))
+        .expect("cbor serialization of a value")
+    }
+
+    pub fn from_cbor(data: &[u8]) -> Result<Self, AirgapError> {
+        let uint = |value: &Value| match value {
+            Value::Integer(i) => u32::try_from(*i).map_err(|_| AirgapError::InvalidPart),
+            _ => Err(AirgapError::InvalidPart),
+        };
+        match serde_cbor::from_slice(data).map_err(|_| AirgapError::Cbor)? {
+            Value::Array(items) => match &items[..] {
+                [seq_num, seq_len, message_len, checksum, Value::Bytes(data)] => Ok(Self {
+                    seq_num: uint(seq_num)?,
+                    seq_len: uint(seq_len)?,
+                    message_len: uint(message_len)?,
+                    checksum: uint(checksum)?,
+                    data: data.clone(),
+                }),
+                _ => Err(AirgapError::InvalidPart),
+            },
+            _ => Err(AirgapError::InvalidPart),
+        }
+    }
+}
+
+pub struct Encoder {
+    message_len: usize,
+    checksum: u32,
+    fragments: Vec<Vec<u8>>,
+}
+
+impl Encoder {
+    pub fn new(message: &[u8], max_fragment_len: usize) -> Self {
+        let len = fragment_len(message.len(), MIN_FRAGMENT_LEN, max_fragment_len);
+        let fragments = message
+            .chunks(std::cmp::max(len, 1))
+            .map(|chunk| {
+                let mut fragment = chunk.to_vec();
+                fragment.resize(len, 0);
+                fragment
+            })
+            .collect();
+        Self {
+            message_len: message.len(),
+            checksum: crc32(message),
+            fragments,
+        }
+    }
+
+    pub fn seq_len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// Returns the part of the sequence number, starting at 1.
+    pub fn part(&self, seq_num: u32) -> Part {
+        let mut data = vec![0; self.fragments.first().map(Vec::len).unwrap_or_default()];
+        for i in choose_fragments(seq_num, self.seq_len(), self.checksum) {
+            xor_into(&mut data, &self.fragments[i]);
+        }
+        Part {
+            seq_num,
+            seq_len: self.seq_len() as u32,
+            message_len: self.message_len as u32,
+            checksum: self.checksum,
+            data,
+        }
+    }
+}
+
+/// Recovers the message from the parts received in any order.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Sequence length, message length, checksum and fragment length of the
+    /// first part.
+    expected: Option<(u32, u32, u32, usize)>,
+    fragments: BTreeMap<usize, Vec<u8>>,
+    mixed: Vec<(BTreeSet<usize>, Vec<u8>)>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the fraction of the fragments recovered.
+    pub fn progress(&self) -> f64 {
+        match self.expected {
+            Some((seq_len, ..)) => self.fragments.len() as f64 / seq_len as f64,
+            None => 0.0,
+        }
+    }
+
+    /// Adds the part, returns the message once every fragment is recovered.
+    pub fn receive(&mut self, part: Part) -> Result<Option<Vec<u8>>, AirgapError> {
+        let expected = (
+            part.seq_len,
+            part.message_len,
+            part.checksum,
+            part.data.len(),
+        );
+        // The fragments must split the message the way the encoder does.
+        let split = !part.data.is_empty()
+            && part.seq_len as usize == (part.message_len as usize).div_ceil(part.data.len());
+        if !split
+            || part.seq_len > MAX_SEQ_LEN
+            || part.seq_num == 0
+            || *self.expected.get_or_insert(expected) != expected
+        {
+            return Err(AirgapError::InvalidPart);
+        }
+        let seq_len = part.seq_len as usize;
+        let mut queue = vec![(
+            choose_fragments(part.seq_num, seq_len, part.checksum),
+            part.data,
+        )];
+        while let Some((mut indexes, mut data)) = queue.pop() {
+            for (i, fragment) in &self.fragments {
+                if indexes.remove(i) {
+                    xor_into(&mut data, fragment);
+                }
+            }
+            match indexes.len() {
+                0 => {}
+                1 => {
+                    let index = *indexes.first().expect("one index");
+                    // The mixed parts including the fragment are reduced again.
+                    let (reducible, mixed) = std::mem::take(&mut self.mixed)
+                        .into_iter()
+                        .partition(|(indexes, _)| indexes.contains(&index));
+                    self.mixed = mixed;
+                    self.fragments.insert(index, data);
+                    queue.extend::<Vec<_>>(reducible);
+                }
+                _ => {
+                    if !self.mixed.iter().any(|(other, _)| *other == indexes) {
+                        self.mixed.push((indexes, data));
+                    }
+                }
+            }
+        }
+
+        if self.fragments.len() < seq_len {
+            return Ok(None);
+        }
+        let mut message: Vec<u8> = self.fragments.values().flatten().copied().collect();
+        message.truncate(part.message_len as usize);
+        if crc32(&message) != part.checksum {
+            return Err(AirgapError::InvalidChecksum);
+        }
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xoshiro() {
+        let mut rng = Xoshiro256::from_seed(b"Wolf");
+        let numbers: Vec<u64> = (0..8).map(|_| rng.next() % 100).collect();
+        assert_eq!(numbers, vec![42, 81, 85, 8, 82, 84, 76, 73]);
+
+        let mut rng = Xoshiro256::from_seed(b"Wolf");
+        assert_eq!(
+            shuffled((1..=10).collect(), &mut rng),
+            vec![6, 4, 9, 3, 10, 5, 7, 8, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_fragment_len() {
+        assert_eq!(fragment_len(12345, 1005, 1955), 1764);
+        assert_eq!(fragment_len(12345, 1005, 30000), 12345);
+        assert_eq!(fragment_len(1000, 10, 100), 100);
+    }
+
+    #[test]
+    fn test_fountain() {
+        let message: Vec<u8> = (0..=255u8).cycle().take(1_234).collect();
+        let encoder = Encoder::new(&message, 100);
+        assert_eq!(encoder.seq_len(), 13);
+
+        // Every other pure part is lost, the mixed ones fill the gaps.
+        let mut decoder = Decoder::new();
+        let mut res = None;
+        for seq_num in (1..=13).step_by(2).chain(14..200) {
+            let part = Part::from_cbor(&encoder.part(seq_num).to_cbor()).unwrap();
+            res = decoder.receive(part).unwrap();
+            if res.is_some() {
+                break;
+            }
+        }
+        assert_eq!(res, Some(message));
+
+        let mut other = encoder.part(1);
+        other.checksum += 1;
+        let mut decoder = Decoder::new();
+        decoder.receive(encoder.part(2)).unwrap();
+        assert!(matches!(
+            decoder.receive(other),
+            Err(AirgapError::InvalidPart)
+        ));
+
+        let mut shorter = encoder.part(1);
+        shorter.data.pop();
+        assert!(matches!(
+            decoder.receive(shorter),
+            Err(AirgapError::InvalidPart)
+        ));
+
+        // A part announcing more fragments than its message has, or than
+        // the decoder accepts, is rejected before any allocation.
+        for (seq_len, message_len) in [(u32::MAX, 1_234), (u32::MAX, u32::MAX)] {
+            let mut huge = encoder.part(u32::MAX);
+            huge.seq_len = seq_len;
+            huge.message_len = message_len;
+            huge.data = vec![0x00];
+            assert!(matches!(
+                Decoder::new().receive(huge),
+                Err(AirgapError::InvalidPart)
+            ));
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Air-gapped signers exchanging animated QR codes of BC-UR parts, like
+//! Passport, Keystone or SeedSigner.
+//!
+//! The transmits are the parts to display to the signer, or a request to scan
+//! the next part displayed by it. The scanned parts are passed as text to
+//! the exchanges until the message is complete.
+
+pub mod fountain;
+pub mod registry;
+pub mod ur;
+
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint, Xpub},
+    Network, NetworkKind, Psbt,
+};
+
+use std::convert::Infallible;
+
+use crate::devices::{Capabilities, NETWORKS};
+use crate::Interpreter;
+
+/// Default maximal length of the fragments of the displayed parts.
+pub const DEFAULT_MAX_FRAGMENT_LEN: usize = 200;
+
+/// Returns the capabilities of the air-gapped signers, which only exchange
+/// keys and psbts.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        taproot: true,
+        musig2: false,
+        message_signing: false,
+        policy_registration: false,
+        max_inputs: None,
+        networks: NETWORKS.to_vec(),
+    }
+}
+
+#[derive(Debug)]
+pub enum AirgapError {
+    NoErrorOrResult,
+    InvalidUr,
+    InvalidBytewords,
+    InvalidChecksum,
+    InvalidPart,
+    Cbor,
+    Serialization(String),
+    /// The scanned UR is not of the expected type.
+    UnexpectedType(String),
+    /// The signer exported no key at the path.
+    KeyNotFound(DerivationPath),
+    /// The signer does not support the command.
+    UnsupportedCommand(&'static str),
+}
+
+impl From<Infallible> for AirgapError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+pub enum AirgapCommand {
+    /// Scans the account or the key exported by the signer.
+    GetMasterFingerprint,
+    GetXpub(DerivationPath),
+    /// Scans the account exported by the signer and returns all its keys.
+    GetAccount,
+    SignPsbt(Box<Psbt>),
+}
+
+pub enum AirgapResponse {
+    MasterFingerprint(Fingerprint),
+    Xpub(Xpub),
+    /// Keys of the account with the fingerprint of their master key.
+    Account(Vec<(Fingerprint, DerivationPath, Xpub)>),
+    SignedPsbt(Box<Psbt>),
+}
+
+pub enum AirgapTransmit {
+    /// The parts are displayed in a loop until the user scans the answer of
+    /// the signer.
+    Display(Vec<String>),
+    /// The next part of the answer is scanned.
+    Scan,
+}
+
+enum State {
+    New,
+    Running(AirgapCommand),
+    Finished(AirgapResponse),
+}
+
+pub struct AirgapInterpreter<C, T, R, E> {
+    network: Network,
+    max_fragment_len: usize,
+    state: State,
+    decoder: fountain::Decoder,
+    _marker: std::marker::PhantomData<(C, T, R, E)>,
+}
+
+impl<C, T, R, E> Default for AirgapInterpreter<C, T, R, E> {
+    fn default() -> Self {
+        Self {
+            network: Network::Bitcoin,
+            max_fragment_len: DEFAULT_MAX_FRAGMENT_LEN,
+            state: State::New,
+            decoder: fountain::Decoder::new(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<C, T, R, E> AirgapInterpreter<C, T, R, E> {
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn with_max_fragment_len(mut self, max_fragment_len: usize) -> Self {
+        self.max_fragment_len = max_fragment_len;
+        self
+    }
+
+    /// Returns the fraction of the answer received.
+    pub fn progress(&self) -> f64 {
+        self.decoder.progress()
+    }
+}
+
+/// Returns the key exported at the path, by an account or a single key.
+fn find_key(
+    ur_type: &str,
+    message: &[u8],
+    network: NetworkKind,
+) -> Result<(Option<Fingerprint>, Vec<registry::HdKey>), AirgapError> {
+    match ur_type {
+        registry::CRYPTO_ACCOUNT => {
+            let (fg, keys) = registry::decode_account(message, network)?;
+            Ok((Some(fg), keys))
+        }
+        registry::CRYPTO_HDKEY => {
+            let value = serde_cbor::from_slice(message).map_err(|_| AirgapError::Cbor)?;
+            let key = registry::decode_hdkey(&value, network)?;
+            Ok((key.source_fingerprint, vec![key]))
+        }
+        _ => Err(AirgapError::UnexpectedType(ur_type.to_string())),
+    }
+}
+
+/// Returns the keys of the scanned crypto-account or crypto-hdkey with the
+/// fingerprint of their master key, the keys without origin fingerprint
+/// take the one of the account.
+pub fn account_keys(
+    ur_type: &str,
+    message: &[u8],
+    network: NetworkKind,
+) -> Result<Vec<(Fingerprint, DerivationPath, Xpub)>, AirgapError> {
+    let (master_fingerprint, keys) = find_key(ur_type, message, network)?;
+    keys.into_iter()
+        .map(|key| {
+            let fg = match (key.source_fingerprint, master_fingerprint) {
+                (Some(fg), _) | (None, Some(fg)) => fg,
+                // A master key is its own origin.
+                (None, None) if key.path.is_master() => key.xpub.fingerprint(),
+                (None, None) => return Err(AirgapError::NoErrorOrResult),
+            };
+            Ok((fg, key.path, key.xpub))
+        })
+        .collect()
+}
+
+/// Returns the crypto-psbt parts of the request to display to the signer.
+pub fn psbt_request(psbt: &Psbt, max_fragment_len: usize) -> Vec<String> {
+    ur::encode_parts(
+        registry::CRYPTO_PSBT,
+        &registry::encode_psbt(psbt),
+        max_fragment_len,
+    )
+}
+
+impl<C, T, R, E> Interpreter for AirgapInterpreter<C, T, R, E>
+where
+    C: TryInto<AirgapCommand>,
+    C::Error: Into<AirgapError>,
+    T: From<AirgapTransmit>,
+    R: From<AirgapResponse>,
+    E: From<AirgapError>,
+{
+    type Command = C;
+    type Transmit = T;
+    type Response = R;
+    type Error = E;
+
+    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        let command: AirgapCommand = command.try_into().map_err(Into::into)?;
+        let transmit = match &command {
+            AirgapCommand::GetMasterFingerprint
+            | AirgapCommand::GetXpub(..)
+            | AirgapCommand::GetAccount => AirgapTransmit::Scan,
+            AirgapCommand::SignPsbt(psbt) => {
+                AirgapTransmit::Display(psbt_request(psbt, self.max_fragment_len))
+            }
+        };
+        self.state = State::Running(command);
+        Ok(transmit.into())
+    }
+    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        let command = match &self.state {
+            State::Running(command) => command,
+            State::New | State::Finished(..) => return Ok(None),
+        };
+        let text =
+            String::from_utf8(data).map_err(|e| AirgapError::Serialization(e.to_string()))?;
+        let (ur_type, part) = ur::decode(&text)?;
+        let message = match part {
+            ur::Part::Single(message) => message,
+            ur::Part::Multi(part) => match self.decoder.receive(part)? {
+                Some(message) => message,
+                None => return Ok(Some(AirgapTransmit::Scan.into())),
+            },
+        };
+
+        let network = NetworkKind::from(self.network);
+        let response = match command {
+            AirgapCommand::GetMasterFingerprint => {
+                let (fg, _) = find_key(&ur_type, &message, network)?;
+                AirgapResponse::MasterFingerprint(fg.ok_or(AirgapError::NoErrorOrResult)?)
+            }
+            AirgapCommand::GetXpub(path) => {
+                let (_, keys) = find_key(&ur_type, &message, network)?;
+                let key = keys
+                    .into_iter()
+                    .find(|key| key.path == *path)
+                    .ok_or_else(|| AirgapError::KeyNotFound(path.clone()))?;
+                AirgapResponse::Xpub(key.xpub)
+            }
+            AirgapCommand::GetAccount => {
+                AirgapResponse::Account(account_keys(&ur_type, &message, network)?)
+            }
+            AirgapCommand::SignPsbt(..) => {
+                if ur_type != registry::CRYPTO_PSBT {
+                    return Err(AirgapError::UnexpectedType(ur_type).into());
+                }
+                AirgapResponse::SignedPsbt(Box::new(registry::decode_psbt(&message)?))
+            }
+        };
+        self.state = State::Finished(response);
+        Ok(None)
+    }
+    fn end(self) -> Result<Self::Response, Self::Error> {
+        if let State::Finished(res) = self.state {
+            Ok(Self::Response::from(res))
+        } else {
+            Err(AirgapError::NoErrorOrResult.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, bip32::Xpriv, psbt::raw, secp256k1::Secp256k1, transaction::Version,
+        Amount, ScriptBuf, Transaction, TxOut,
+    };
+    use std::str::FromStr;
+
+    type Airgap = AirgapInterpreter<AirgapCommand, AirgapTransmit, AirgapResponse, AirgapError>;
+
+    fn key(path: &str) -> (Fingerprint, registry::HdKey) {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x03; 32]).unwrap();
+        let path = DerivationPath::from_str(path).unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap());
+        let fg = xpriv.fingerprint(&secp);
+        (
+            fg,
+            registry::HdKey {
+                xpub,
+                source_fingerprint: Some(fg),
+                path,
+            },
+        )
+    }
+
+    #[test]
+    fn test_get_xpub_from_account() {
+        let (fg, wpkh) = key("m/84'/1'/0'");
+        let (_, tr) = key("m/86'/1'/0'");
+        let account = registry::tests::account(fg, &[wpkh, tr.clone()]);
+        let parts = ur::encode_parts(registry::CRYPTO_ACCOUNT, &account, 50);
+        assert!(parts.len() > 2);
+
+        let mut airgap = Airgap::default().with_network(Network::Testnet);
+        assert!(matches!(
+            airgap
+                .start(AirgapCommand::GetXpub(tr.path.clone()))
+                .unwrap(),
+            AirgapTransmit::Scan
+        ));
+        let seq_len = parts.len() / 2;
+        for part in &parts[..seq_len - 1] {
+            assert!(matches!(
+                airgap.exchange(part.as_bytes().to_vec()).unwrap(),
+                Some(AirgapTransmit::Scan)
+            ));
+        }
+        assert!(airgap.progress() < 1.0);
+        assert!(airgap
+            .exchange(parts[seq_len - 1].as_bytes().to_vec())
+            .unwrap()
+            .is_none());
+        match airgap.end().unwrap() {
+            AirgapResponse::Xpub(xpub) => assert_eq!(xpub, tr.xpub),
+            _ => panic!("expected an xpub"),
+        }
+
+        let mut airgap = Airgap::default().with_network(Network::Testnet);
+        airgap.start(AirgapCommand::GetMasterFingerprint).unwrap();
+        let single = ur::encode(registry::CRYPTO_ACCOUNT, &account);
+        assert!(airgap.exchange(single.into_bytes()).unwrap().is_none());
+        match airgap.end().unwrap() {
+            AirgapResponse::MasterFingerprint(res) => assert_eq!(res, fg),
+            _ => panic!("expected a fingerprint"),
+        }
+
+        let mut airgap = Airgap::default().with_network(Network::Testnet);
+        let path = DerivationPath::from_str("m/44'/1'/0'").unwrap();
+        airgap.start(AirgapCommand::GetXpub(path)).unwrap();
+        let single = ur::encode(registry::CRYPTO_ACCOUNT, &account);
+        assert!(matches!(
+            airgap.exchange(single.into_bytes()),
+            Err(AirgapError::KeyNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn test_get_account() {
+        let (fg, wpkh) = key("m/84'/1'/0'");
+        let (_, mut tr) = key("m/86'/1'/0'");
+        tr.source_fingerprint = None;
+        let account = registry::tests::account(fg, &[wpkh.clone(), tr.clone()]);
+
+        let mut airgap = Airgap::default().with_network(Network::Testnet);
+        airgap.start(AirgapCommand::GetAccount).unwrap();
+        let single = ur::encode(registry::CRYPTO_ACCOUNT, &account);
+        assert!(airgap.exchange(single.into_bytes()).unwrap().is_none());
+        match airgap.end().unwrap() {
+            AirgapResponse::Account(keys) => assert_eq!(
+                keys,
+                vec![(fg, wpkh.path, wpkh.xpub), (fg, tr.path.clone(), tr.xpub)]
+            ),
+            _ => panic!("expected an account"),
+        }
+
+        let hdkey = serde_cbor::to_vec(&registry::tests::hdkey(&tr)).unwrap();
+        assert!(matches!(
+            account_keys(registry::CRYPTO_HDKEY, &hdkey, NetworkKind::Test),
+            Err(AirgapError::NoErrorOrResult)
+        ));
+
+        // Master key of the 2023 registry, its use-info sets the test network.
+        let mut master = tr;
+        master.path = DerivationPath::master();
+        let serde_cbor::Value::Tag(_, map) = registry::tests::hdkey(&master) else {
+            panic!("expected a tagged key");
+        };
+        let serde_cbor::Value::Map(mut map) = *map else {
+            panic!("expected a map");
+        };
+        map.insert(
+            serde_cbor::Value::Integer(5),
+            serde_cbor::Value::Tag(
+                registry::TAG_COININFO + registry::TAG_OFFSET,
+                Box::new(serde_cbor::Value::Map(
+                    [(serde_cbor::Value::Integer(2), serde_cbor::Value::Integer(1))].into(),
+                )),
+            ),
+        );
+        let hdkey = serde_cbor::to_vec(&serde_cbor::Value::Tag(
+            registry::TAG_HDKEY + registry::TAG_OFFSET,
+            Box::new(serde_cbor::Value::Map(map)),
+        ))
+        .unwrap();
+        let keys = account_keys(registry::CRYPTO_HDKEY, &hdkey, NetworkKind::Main).unwrap();
+        assert_eq!(keys[0].2.network, NetworkKind::Test);
+        assert_eq!(keys[0].0, keys[0].2.fingerprint());
+    }
+
+    #[test]
+    fn test_sign_psbt() {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let mut signed = psbt.clone();
+        signed.unknown.insert(
+            raw::Key {
+                type_value: 0xf0,
+                key: vec![0x01; 400],
+            },
+            vec![0x02; 400],
+        );
+
+        let mut airgap = Airgap::default().with_max_fragment_len(100);
+        let parts = match airgap
+            .start(AirgapCommand::SignPsbt(Box::new(psbt.clone())))
+            .unwrap()
+        {
+            AirgapTransmit::Display(parts) => parts,
+            AirgapTransmit::Scan => panic!("expected parts to display"),
+        };
+        let (_, part) = ur::decode(&parts[0]).unwrap();
+        assert_eq!(part, ur::Part::Single(registry::encode_psbt(&psbt)));
+
+        // The signer's animation is scanned from the middle, mixed parts
+        // included.
+        let answer = ur::encode_parts(registry::CRYPTO_PSBT, &registry::encode_psbt(&signed), 100);
+        let mut res = None;
+        for part in answer.iter().skip(3).chain(answer.iter()) {
+            res = airgap.exchange(part.as_bytes().to_vec()).unwrap();
+            if res.is_none() {
+                break;
+            }
+        }
+        assert!(res.is_none());
+        match airgap.end().unwrap() {
+            AirgapResponse::SignedPsbt(res) => assert_eq!(*res, signed),
+            _ => panic!("expected a signed psbt"),
+        }
+
+        let mut airgap = Airgap::default();
+        airgap
+            .start(AirgapCommand::SignPsbt(Box::new(psbt.clone())))
+            .unwrap();
+        let other = ur::encode(registry::CRYPTO_ACCOUNT, &[0xa0]);
+        assert!(matches!(
+            airgap.exchange(other.into_bytes()),
+            Err(AirgapError::UnexpectedType(..))
+        ));
+    }
+}
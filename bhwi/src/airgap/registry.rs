@@ -0,0 +1,270 @@
+//! CBOR types of the URs exchanged with the signers (BCR-2020-006,
+//! BCR-2020-007 and BCR-2020-015).
+
+use bitcoin::{
+    bip32::{ChainCode, ChildNumber, DerivationPath, Fingerprint, Xpub},
+    secp256k1::PublicKey,
+    NetworkKind, Psbt,
+};
+use serde_cbor::Value;
+
+use super::AirgapError;
+
+pub const CRYPTO_PSBT: &str = "crypto-psbt";
+pub const CRYPTO_ACCOUNT: &str = "crypto-account";
+pub const CRYPTO_HDKEY: &str = "crypto-hdkey";
+
+pub const TAG_HDKEY: u64 = 303;
+pub const TAG_KEYPATH: u64 = 304;
+pub const TAG_OUTPUT: u64 = 308;
+pub const TAG_COININFO: u64 = 305;
+/// The tags of the 2023 registry are the ones above shifted by the offset,
+/// both are accepted.
+pub const TAG_OFFSET: u64 = 40000;
+
+/// Keys of the crypto-hdkey map.
+const HDKEY_KEY_DATA: i128 = 3;
+const HDKEY_CHAIN_CODE: i128 = 4;
+const HDKEY_USE_INFO: i128 = 5;
+const HDKEY_ORIGIN: i128 = 6;
+const HDKEY_PARENT_FINGERPRINT: i128 = 8;
+
+/// Keys of the crypto-keypath map.
+const KEYPATH_COMPONENTS: i128 = 1;
+const KEYPATH_SOURCE_FINGERPRINT: i128 = 2;
+const KEYPATH_DEPTH: i128 = 3;
+
+/// Key of the network in the crypto-coininfo map, 1 for the test networks.
+const COININFO_NETWORK: i128 = 2;
+
+/// Key of an account with the path of its origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HdKey {
+    pub xpub: Xpub,
+    pub source_fingerprint: Option<Fingerprint>,
+    pub path: DerivationPath,
+}
+
+pub fn encode_psbt(psbt: &Psbt) -> Vec<u8> {
+    serde_cbor::to_vec(&Value::Bytes(psbt.serialize())).expect("cbor serialization of a value")
+}
+
+pub fn decode_psbt(data: &[u8]) -> Result<Psbt, AirgapError> {
+    match serde_cbor::from_slice(data).map_err(|_| AirgapError::Cbor)? {
+        Value::Bytes(psbt) => {
+            Psbt::deserialize(&psbt).map_err(|e| AirgapError::Serialization(e.to_string()))
+        }
+        _ => Err(AirgapError::Cbor),
+    }
+}
+
+fn get(map: &Value, key: i128) -> Option<&Value> {
+    match map {
+        Value::Map(map) => map.get(&Value::Integer(key)),
+        _ => None,
+    }
+}
+
+fn is_tag(t: u64, tag: u64) -> bool {
+    t == tag || t == tag + TAG_OFFSET
+}
+
+fn untag(value: &Value, tag: u64) -> Result<&Value, AirgapError> {
+    match value {
+        Value::Tag(t, value) if is_tag(*t, tag) => Ok(value),
+        // The tags of the embedded types are optional.
+        Value::Map(_) => Ok(value),
+        _ => Err(AirgapError::Cbor),
+    }
+}
+
+fn bytes<const N: usize>(value: Option<&Value>) -> Result<Option<[u8; N]>, AirgapError> {
+    match value {
+        Some(Value::Bytes(bytes)) => bytes
+            .as_slice()
+            .try_into()
+            .map(Some)
+            .map_err(|_| AirgapError::Cbor),
+        Some(_) => Err(AirgapError::Cbor),
+        None => Ok(None),
+    }
+}
+
+fn fingerprint(value: Option<&Value>) -> Result<Option<Fingerprint>, AirgapError> {
+    match value {
+        Some(Value::Integer(i)) => u32::try_from(*i)
+            .map(|fg| Some(Fingerprint::from(fg.to_be_bytes())))
+            .map_err(|_| AirgapError::Cbor),
+        Some(_) => Err(AirgapError::Cbor),
+        None => Ok(None),
+    }
+}
+
+fn keypath(
+    value: &Value,
+) -> Result<(DerivationPath, Option<Fingerprint>, Option<u8>), AirgapError> {
+    let value = untag(value, TAG_KEYPATH)?;
+    let components = match get(value, KEYPATH_COMPONENTS) {
+        Some(Value::Array(components)) => components,
+        _ => return Err(AirgapError::Cbor),
+    };
+    let path = components
+        .chunks(2)
+        .map(|component| match component {
+            [Value::Integer(index), Value::Bool(hardened)] => {
+                let index = u32::try_from(*index).map_err(|_| AirgapError::Cbor)?;
+                if *hardened {
+                    ChildNumber::from_hardened_idx(index)
+                } else {
+                    ChildNumber::from_normal_idx(index)
+                }
+                .map_err(|_| AirgapError::Cbor)
+            }
+            // Wildcards and ranges are only used by the children paths.
+            _ => Err(AirgapError::Cbor),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let depth = match get(value, KEYPATH_DEPTH) {
+        Some(Value::Integer(depth)) => Some(u8::try_from(*depth).map_err(|_| AirgapError::Cbor)?),
+        _ => None,
+    };
+    Ok((
+        DerivationPath::from(path),
+        fingerprint(get(value, KEYPATH_SOURCE_FINGERPRINT))?,
+        depth,
+    ))
+}
+
+/// Returns the network of the use-info of the key, if it has one.
+fn use_info_network(value: Option<&Value>) -> Result<Option<NetworkKind>, AirgapError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    match get(untag(value, TAG_COININFO)?, COININFO_NETWORK) {
+        Some(Value::Integer(0)) | None => Ok(Some(NetworkKind::Main)),
+        Some(Value::Integer(_)) => Ok(Some(NetworkKind::Test)),
+        Some(_) => Err(AirgapError::Cbor),
+    }
+}
+
+/// Decodes the crypto-hdkey, tagged or not. The network of its use-info
+/// prevails over the given one.
+pub fn decode_hdkey(value: &Value, network: NetworkKind) -> Result<HdKey, AirgapError> {
+    let value = untag(value, TAG_HDKEY)?;
+    let network = use_info_network(get(value, HDKEY_USE_INFO))?.unwrap_or(network);
+    let key = bytes::<33>(get(value, HDKEY_KEY_DATA))?.ok_or(AirgapError::Cbor)?;
+    let chain_code = bytes::<32>(get(value, HDKEY_CHAIN_CODE))?.ok_or(AirgapError::Cbor)?;
+    let (path, source_fingerprint, depth) = match get(value, HDKEY_ORIGIN) {
+        Some(origin) => keypath(origin)?,
+        None => (DerivationPath::master(), None, None),
+    };
+    let xpub = Xpub {
+        network,
+        depth: depth.unwrap_or(path.len() as u8),
+        parent_fingerprint: fingerprint(get(value, HDKEY_PARENT_FINGERPRINT))?.unwrap_or_default(),
+        child_number: path
+            .into_iter()
+            .last()
+            .copied()
+            .unwrap_or(ChildNumber::Normal { index: 0 }),
+        public_key: PublicKey::from_slice(&key)
+            .map_err(|e| AirgapError::Serialization(e.to_string()))?,
+        chain_code: ChainCode::from(chain_code),
+    };
+    Ok(HdKey {
+        xpub,
+        source_fingerprint,
+        path,
+    })
+}
+
+/// Decodes the crypto-account: the master fingerprint and the keys of its
+/// output descriptors.
+pub fn decode_account(
+    data: &[u8],
+    network: NetworkKind,
+) -> Result<(Fingerprint, Vec<HdKey>), AirgapError> {
+    let value: Value = serde_cbor::from_slice(data).map_err(|_| AirgapError::Cbor)?;
+    let master_fingerprint = fingerprint(get(&value, 1))?.ok_or(AirgapError::Cbor)?;
+    let outputs = match get(&value, 2) {
+        Some(Value::Array(outputs)) => outputs,
+        _ => return Err(AirgapError::Cbor),
+    };
+    let mut keys = Vec::new();
+    for output in outputs {
+        let mut value = untag(output, TAG_OUTPUT).unwrap_or(output);
+        // The key is wrapped in the tags of the script expressions.
+        while let Value::Tag(tag, inner) = value {
+            if is_tag(*tag, TAG_HDKEY) {
+                break;
+            }
+            value = inner;
+        }
+        // Multisig expressions are skipped, only the single keys are exported.
+        if let Ok(key) = decode_hdkey(value, network) {
+            keys.push(key);
+        }
+    }
+    Ok((master_fingerprint, keys))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Returns the tagged crypto-hdkey of the key.
+    pub fn hdkey(key: &HdKey) -> Value {
+        let mut components = Vec::new();
+        for child in &key.path {
+            let (index, hardened) = match *child {
+                ChildNumber::Normal { index } => (index, false),
+                ChildNumber::Hardened { index } => (index, true),
+            };
+            components.push(Value::Integer(index.into()));
+            components.push(Value::Bool(hardened));
+        }
+        let mut origin = BTreeMap::from([(Value::Integer(1), Value::Array(components))]);
+        if let Some(fg) = key.source_fingerprint {
+            origin.insert(
+                Value::Integer(2),
+                Value::Integer(u32::from_be_bytes(fg.to_bytes()).into()),
+            );
+        }
+        let map = BTreeMap::from([
+            (
+                Value::Integer(HDKEY_KEY_DATA),
+                Value::Bytes(key.xpub.public_key.serialize().to_vec()),
+            ),
+            (
+                Value::Integer(HDKEY_CHAIN_CODE),
+                Value::Bytes(key.xpub.chain_code.to_bytes().to_vec()),
+            ),
+            (
+                Value::Integer(HDKEY_ORIGIN),
+                Value::Tag(TAG_KEYPATH, Box::new(Value::Map(origin))),
+            ),
+            (
+                Value::Integer(HDKEY_PARENT_FINGERPRINT),
+                Value::Integer(u32::from_be_bytes(key.xpub.parent_fingerprint.to_bytes()).into()),
+            ),
+        ]);
+        Value::Tag(TAG_HDKEY, Box::new(Value::Map(map)))
+    }
+
+    /// Returns the crypto-account of the keys, as wpkh outputs.
+    pub fn account(master_fingerprint: Fingerprint, keys: &[HdKey]) -> Vec<u8> {
+        let outputs = keys
+            .iter()
+            .map(|key| Value::Tag(TAG_OUTPUT, Box::new(Value::Tag(404, Box::new(hdkey(key))))))
+            .collect();
+        serde_cbor::to_vec(&Value::Map(BTreeMap::from([
+            (
+                Value::Integer(1),
+                Value::Integer(u32::from_be_bytes(master_fingerprint.to_bytes()).into()),
+            ),
+            (Value::Integer(2), Value::Array(outputs)),
+        ])))
+        .unwrap()
+    }
+}
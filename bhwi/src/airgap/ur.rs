@@ -0,0 +1,191 @@
+//! Uniform Resources (BCR-2020-005): CBOR payloads encoded as `ur:type/...`
+//! strings of minimal bytewords, followed by their CRC32 checksum.
+//!
+//! Large payloads are split in parts `ur:type/seq-len/...`, each holding a
+//! fragment produced by the fountain encoder.
+
+use super::{fountain, AirgapError};
+
+/// The 256 bytewords, a byte is encoded in the minimal form by the first and
+/// the last letters of its word.
+const BYTEWORDS: [&str; 256] = [
+    "able", "acid", "also", "apex", "aqua", "arch", "atom", "aunt", "away", "axis", "back", "bald",
+    "barn", "belt", "beta", "bias", "blue", "body", "brag", "brew", "bulb", "buzz", "calm", "cash",
+    "cats", "chef", "city", "claw", "code", "cola", "cook", "cost", "crux", "curl", "cusp", "cyan",
+    "dark", "data", "days", "deli", "dice", "diet", "door", "down", "draw", "drop", "drum", "dull",
+    "duty", "each", "easy", "echo", "edge", "epic", "even", "exam", "exit", "eyes", "fact", "fair",
+    "fern", "figs", "film", "fish", "fizz", "flap", "flew", "flux", "foxy", "free", "frog", "fuel",
+    "fund", "gala", "game", "gear", "gems", "gift", "girl", "glow", "good", "gray", "grim", "guru",
+    "gush", "gyro", "half", "hang", "hard", "hawk", "heat", "help", "high", "hill", "holy", "hope",
+    "horn", "huts", "iced", "idea", "idle", "inch", "inky", "into", "iris", "iron", "item", "jade",
+    "jazz", "join", "jolt", "jowl", "judo", "jugs", "jump", "junk", "jury", "keep", "keno", "kept",
+    "keys", "kick", "kiln", "king", "kite", "kiwi", "knob", "lamb", "lava", "lazy", "leaf", "legs",
+    "liar", "limp", "lion", "list", "logo", "loud", "love", "luau", "luck", "lung", "main", "many",
+    "math", "maze", "memo", "menu", "meow", "mild", "mint", "miss", "monk", "nail", "navy", "need",
+    "news", "next", "noon", "note", "numb", "obey", "oboe", "omit", "onyx", "open", "oval", "owls",
+    "paid", "part", "peck", "play", "plus", "poem", "pool", "pose", "puff", "puma", "purr", "quad",
+    "quiz", "race", "ramp", "real", "redo", "rich", "road", "rock", "roof", "ruby", "ruin", "runs",
+    "rust", "safe", "saga", "scar", "sets", "silk", "skew", "slot", "soap", "solo", "song", "stub",
+    "surf", "swan", "taco", "task", "taxi", "tent", "tied", "time", "tiny", "toil", "tomb", "toys",
+    "trip", "tuna", "twin", "ugly", "undo", "unit", "urge", "user", "vast", "very", "veto", "vial",
+    "vibe", "view", "visa", "void", "vows", "wall", "wand", "warm", "wasp", "wave", "waxy", "webs",
+    "what", "when", "whiz", "wolf", "work", "yank", "yawn", "yell", "yoga", "yurt", "zaps", "zero",
+    "zest", "zinc", "zone", "zoom",
+];
+
+/// Returns the CRC32 (ISO-HDLC) checksum of the data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn minimal(byte: u8) -> [u8; 2] {
+    let word = BYTEWORDS[byte as usize].as_bytes();
+    [word[0], word[3]]
+}
+
+/// Encodes the data and its checksum in minimal bytewords.
+pub fn encode_bytewords(data: &[u8]) -> String {
+    let mut res = String::with_capacity((data.len() + 4) * 2);
+    for byte in data.iter().chain(&crc32(data).to_be_bytes()) {
+        res.extend(minimal(*byte).map(char::from));
+    }
+    res
+}
+
+/// Decodes the minimal bytewords and checks their checksum.
+pub fn decode_bytewords(s: &str) -> Result<Vec<u8>, AirgapError> {
+    let s = s.as_bytes();
+    if s.len() % 2 != 0 || s.len() < 10 {
+        return Err(AirgapError::InvalidBytewords);
+    }
+    let mut data = s
+        .chunks(2)
+        .map(|pair| {
+            (0..=255u8)
+                .find(|byte| minimal(*byte) == [pair[0], pair[1]])
+                .ok_or(AirgapError::InvalidBytewords)
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let checksum = data.split_off(data.len() - 4);
+    if crc32(&data).to_be_bytes()[..] != checksum[..] {
+        return Err(AirgapError::InvalidChecksum);
+    }
+    Ok(data)
+}
+
+/// Returns the single part UR of the message.
+pub fn encode(ur_type: &str, message: &[u8]) -> String {
+    format!("ur:{}/{}", ur_type, encode_bytewords(message))
+}
+
+/// Returns the parts of the message, the pure fragments followed by as many
+/// mixed ones, or the single part UR if the message fits in a fragment.
+pub fn encode_parts(ur_type: &str, message: &[u8], max_fragment_len: usize) -> Vec<String> {
+    let encoder = fountain::Encoder::new(message, max_fragment_len);
+    if encoder.seq_len() == 1 {
+        return vec![encode(ur_type, message)];
+    }
+    (1..=2 * encoder.seq_len() as u32)
+        .map(|seq_num| {
+            format!(
+                "ur:{}/{}-{}/{}",
+                ur_type,
+                seq_num,
+                encoder.seq_len(),
+                encode_bytewords(&encoder.part(seq_num).to_cbor())
+            )
+        })
+        .collect()
+}
+
+/// Part of a UR, the message of a single part UR is its only fragment.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Part {
+    Single(Vec<u8>),
+    Multi(fountain::Part),
+}
+
+/// Parses the UR, returns its type and its part.
+pub fn decode(ur: &str) -> Result<(String, Part), AirgapError> {
+    let ur = ur.trim().to_lowercase();
+    let path = ur.strip_prefix("ur:").ok_or(AirgapError::InvalidUr)?;
+    let components: Vec<&str> = path.split('/').collect();
+    match components[..] {
+        [ur_type, payload] => Ok((
+            ur_type.to_string(),
+            Part::Single(decode_bytewords(payload)?),
+        )),
+        [ur_type, seq, payload] => {
+            let part = fountain::Part::from_cbor(&decode_bytewords(payload)?)?;
+            if seq != format!("{}-{}", part.seq_num, part.seq_len) {
+                return Err(AirgapError::InvalidUr);
+            }
+            Ok((ur_type.to_string(), Part::Multi(part)))
+        }
+        _ => Err(AirgapError::InvalidUr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytewords() {
+        let mut minimals: Vec<_> = (0..=255u8).map(minimal).collect();
+        minimals.sort();
+        minimals.dedup();
+        assert_eq!(minimals.len(), 256);
+
+        assert_eq!(crc32(b"Hello, world!"), 0xebe6c6e6);
+        assert_eq!(crc32(b"Wolf"), 0x598c84dc);
+
+        let data = [0, 1, 2, 128, 255];
+        assert_eq!(encode_bytewords(&data), "aeadaolazmjendeoti");
+        assert_eq!(decode_bytewords("aeadaolazmjendeoti").unwrap(), data);
+        assert!(matches!(
+            decode_bytewords("aeadaolazmjendeotu"),
+            Err(AirgapError::InvalidBytewords)
+        ));
+        assert!(matches!(
+            decode_bytewords("aeadaolazmjendeoto"),
+            Err(AirgapError::InvalidChecksum)
+        ));
+    }
+
+    #[test]
+    fn test_parts() {
+        let message: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let parts = encode_parts("bytes", &message, 100);
+        assert_eq!(parts.len(), 20);
+        assert!(parts[0].starts_with("ur:bytes/1-10/"));
+        match decode(&parts[11].to_uppercase()).unwrap() {
+            (ur_type, Part::Multi(part)) => {
+                assert_eq!(ur_type, "bytes");
+                assert_eq!((part.seq_num, part.seq_len), (12, 10));
+                assert_eq!(part.message_len, 1000);
+                assert_eq!(part.checksum, crc32(&message));
+            }
+            _ => panic!("expected a multi part UR"),
+        }
+
+        let single = encode_parts("bytes", b"Wolf", 100);
+        assert_eq!(single.len(), 1);
+        assert_eq!(
+            decode(&single[0]).unwrap(),
+            ("bytes".to_string(), Part::Single(b"Wolf".to_vec()))
+        );
+        assert!(matches!(decode("bytes/hdeo"), Err(AirgapError::InvalidUr)));
+    }
+}
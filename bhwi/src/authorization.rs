@@ -0,0 +1,177 @@
+//! Rules of the host evaluated before a command is sent to the device, for
+//! the signers embedding the library to deny the commands outside of their
+//! policy. A denied command fails with `Error::PolicyDenied` and nothing is
+//! transmitted.
+
+use bitcoin::{bip32::DerivationPath, Amount, Psbt};
+
+use crate::common::{Command, Error};
+
+/// Callback of the host, returns the reason of the denial.
+pub type Callback = Box<dyn Fn(&Command) -> Result<(), String>>;
+
+pub enum Rule {
+    /// Denies the commands matching, for example the `SignMessage` ones.
+    Deny(fn(&Command) -> bool),
+    /// Caps the amount of the psbts sent to the outputs without key origin,
+    /// the recipients of the transaction.
+    MaxOutgoing(Amount),
+    /// Restricts the paths of the commands to the prefixes: the path of the
+    /// xpub, of the message or of the address, and the key origins of the
+    /// inputs of the psbts.
+    AllowedPaths(Vec<DerivationPath>),
+    Callback(Callback),
+}
+
+/// Returns the amount sent to the outputs without key origin.
+fn outgoing(psbt: &Psbt) -> Amount {
+    psbt.outputs
+        .iter()
+        .zip(&psbt.unsigned_tx.output)
+        .filter(|(output, _)| {
+            output.bip32_derivation.is_empty() && output.tap_key_origins.is_empty()
+        })
+        .map(|(_, txout)| txout.value)
+        .sum()
+}
+
+/// Returns the paths of the command checked by [`Rule::AllowedPaths`].
+fn paths(command: &Command) -> Vec<&DerivationPath> {
+    match command {
+        Command::GetXpub { path, .. }
+        | Command::SignMessage { path, .. }
+        | Command::DisplayAddress { path, .. } => vec![path],
+        Command::SignPsbt { psbt, .. } => psbt
+            .inputs
+            .iter()
+            .flat_map(|input| {
+                input
+                    .bip32_derivation
+                    .values()
+                    .map(|(_, path)| path)
+                    .chain(input.tap_key_origins.values().map(|(_, (_, path))| path))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Default)]
+pub struct Authorization {
+    rules: Vec<Rule>,
+}
+
+impl Authorization {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Evaluates the rules in their order, the first one denying the command
+    /// gives its reason.
+    pub fn check(&self, command: &Command) -> Result<(), Error> {
+        for rule in &self.rules {
+            match rule {
+                Rule::Deny(matches) if matches(command) => {
+                    return Err(Error::PolicyDenied("Command denied".to_string()));
+                }
+                Rule::MaxOutgoing(max) => {
+                    if let Command::SignPsbt { psbt, .. } = command {
+                        let amount = outgoing(psbt);
+                        if amount > *max {
+                            return Err(Error::PolicyDenied(format!(
+                                "Outgoing amount {} above {}",
+                                amount, max
+                            )));
+                        }
+                    }
+                }
+                Rule::AllowedPaths(prefixes) => {
+                    if let Some(path) = paths(command).into_iter().find(|path| {
+                        !prefixes
+                            .iter()
+                            .any(|prefix| path.as_ref().starts_with(prefix.as_ref()))
+                    }) {
+                        return Err(Error::PolicyDenied(format!("Path {} not allowed", path)));
+                    }
+                }
+                Rule::Callback(callback) => callback(command).map_err(Error::PolicyDenied)?,
+                Rule::Deny(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, transaction::Version, OutPoint, ScriptBuf, Transaction, TxIn, TxOut,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_authorization() {
+        let path = |s| DerivationPath::from_str(s).unwrap();
+        let authorization = Authorization::new()
+            .with_rule(Rule::Deny(|c| matches!(c, Command::SignMessage { .. })))
+            .with_rule(Rule::MaxOutgoing(Amount::from_sat(50_000)))
+            .with_rule(Rule::AllowedPaths(vec![path("m/84'/1'")]))
+            .with_rule(Rule::Callback(Box::new(|c| match c {
+                Command::GetStatus => Err("No polling".to_string()),
+                _ => Ok(()),
+            })));
+
+        let get_xpub = |p| Command::GetXpub {
+            path: path(p),
+            display: false,
+        };
+        assert!(authorization.check(&get_xpub("m/84'/1'/0'")).is_ok());
+        assert!(matches!(
+            authorization.check(&get_xpub("m/44'/1'/0'")),
+            Err(Error::PolicyDenied(reason)) if reason == "Path 44'/1'/0' not allowed"
+        ));
+        assert!(matches!(
+            authorization.check(&Command::SignMessage {
+                path: path("m/84'/1'/0'/0/0"),
+                message: b"hello".to_vec(),
+            }),
+            Err(Error::PolicyDenied(_))
+        ));
+        assert!(authorization.check(&Command::GetMasterFingerprint).is_ok());
+        assert!(matches!(
+            authorization.check(&Command::GetStatus),
+            Err(Error::PolicyDenied(reason)) if reason == "No polling"
+        ));
+
+        let sign = |value| {
+            let tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::null(),
+                    ..Default::default()
+                }],
+                output: vec![TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: ScriptBuf::new(),
+                }],
+            };
+            Command::SignPsbt {
+                psbt: Box::new(Psbt::from_unsigned_tx(tx).unwrap()),
+                policy: None,
+                hmac: None,
+            }
+        };
+        assert!(authorization.check(&sign(50_000)).is_ok());
+        assert!(matches!(
+            authorization.check(&sign(50_001)),
+            Err(Error::PolicyDenied(reason)) if reason.starts_with("Outgoing amount")
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+//! Generic signed messages of BIP-322: the proof of the ownership of an
+//! address is the signed spend of a virtual output of its script, committing
+//! to the message.
+//!
+//! The transaction spending it is signed by the devices as a psbt, so that
+//! the signatures of the cosigners of a multisig policy are combined before
+//! the proof is encoded.
+
+use base64ct::{Base64, Encoding};
+use bitcoin::{
+    absolute::LockTime,
+    consensus::encode::serialize,
+    hashes::{sha256, Hash, HashEngine},
+    opcodes::{all::OP_RETURN, OP_0},
+    script::Builder,
+    transaction::Version,
+    Amount, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+use crate::{
+    prelude::*,
+    psbt::{self, FinalizeError},
+    wallet::WalletPolicy,
+};
+
+/// Tag of the hash of the message.
+const TAG: &[u8] = b"BIP0322-signed-message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProofFormat {
+    /// The witness of the signed input, for the segwit scripts.
+    Simple,
+    /// The signed transaction, for any script.
+    Full,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Proof {
+    /// The base64 encoded proof.
+    Signed(String),
+    /// The psbt of the proof with the signatures so far, below the threshold
+    /// of the multisig policy: it is signed by the other cosigners and
+    /// proved again with [`prove`].
+    Partial(Box<Psbt>),
+}
+
+#[derive(Debug)]
+pub enum Bip322Error {
+    /// The policy is not one of the single key and multisig templates, or a
+    /// key has no origin.
+    UnsupportedPolicy,
+    /// The simple format is only defined for the segwit scripts.
+    NotSegwit,
+    Finalize(FinalizeError),
+}
+
+/// Returns the tagged hash of the message.
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_byte_array());
+    engine.input(tag.as_byte_array());
+    engine.input(message);
+    sha256::Hash::from_engine(engine)
+}
+
+/// Returns the virtual transaction whose output of the script is spent by
+/// the proof.
+pub fn to_spend(script_pubkey: ScriptBuf, message: &[u8]) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xffff_ffff),
+            script_sig: Builder::new()
+                .push_opcode(OP_0)
+                .push_slice(message_hash(message).to_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey,
+        }],
+    }
+}
+
+/// Returns the unsigned transaction of the proof, spending the output of
+/// `to_spend` to `OP_RETURN`.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+/// Builds the psbt of the proof of the address of the policy at the index,
+/// to be signed by the devices.
+pub fn build_psbt(
+    message: &[u8],
+    policy: &WalletPolicy,
+    change: bool,
+    index: u32,
+    format: ProofFormat,
+) -> Result<Psbt, Bip322Error> {
+    let (script_pubkey, mut input) =
+        psbt::policy_input(policy, change, index).ok_or(Bip322Error::UnsupportedPolicy)?;
+    if format == ProofFormat::Simple && !script_pubkey.is_witness_program() {
+        return Err(Bip322Error::NotSegwit);
+    }
+    let to_spend = to_spend(script_pubkey, message);
+    let mut psbt = Psbt::from_unsigned_tx(to_sign(&to_spend)).expect("transaction is unsigned");
+    input.witness_utxo = Some(to_spend.output[0].clone());
+    input.non_witness_utxo = Some(to_spend);
+    psbt.inputs[0] = input;
+    Ok(psbt)
+}
+
+/// Finalizes the signed psbt of the proof and encodes it, returns it as a
+/// partial proof if signatures are missing.
+pub fn prove(
+    mut psbt: Psbt,
+    policy: &WalletPolicy,
+    format: ProofFormat,
+) -> Result<Proof, Bip322Error> {
+    match psbt::finalize(&mut psbt, policy) {
+        Ok(()) => encode(&psbt, format).map(Proof::Signed),
+        Err(FinalizeError::MissingSignatures(_)) => Ok(Proof::Partial(Box::new(psbt))),
+        Err(e) => Err(Bip322Error::Finalize(e)),
+    }
+}
+
+fn encode(psbt: &Psbt, format: ProofFormat) -> Result<String, Bip322Error> {
+    let input = psbt
+        .inputs
+        .first()
+        .filter(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some())
+        .ok_or(Bip322Error::Finalize(FinalizeError::NotFinalized(0)))?;
+    let script_sig = input.final_script_sig.clone().unwrap_or_default();
+    let witness = input.final_script_witness.clone().unwrap_or_default();
+    match format {
+        ProofFormat::Simple if !script_sig.is_empty() => Err(Bip322Error::NotSegwit),
+        ProofFormat::Simple => Ok(Base64::encode_string(&serialize(&witness))),
+        ProofFormat::Full => {
+            let mut tx = psbt.unsigned_tx.clone();
+            tx.input[0].script_sig = script_sig;
+            tx.input[0].witness = witness;
+            Ok(Base64::encode_string(&serialize(&tx)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        bip32::{DerivationPath, Xpriv, Xpub},
+        hex::DisplayHex,
+        secp256k1::Secp256k1,
+        Address, Network,
+    };
+    use core::str::FromStr;
+
+    #[test]
+    fn test_bip322_vectors() {
+        assert_eq!(
+            message_hash(b"").to_byte_array().to_lower_hex_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World")
+                .to_byte_array()
+                .to_lower_hex_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        let script_pubkey = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .assume_checked()
+            .script_pubkey();
+        let spend = to_spend(script_pubkey.clone(), b"");
+        assert_eq!(
+            spend.compute_txid().to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_sign(&spend).compute_txid().to_string(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+        let spend = to_spend(script_pubkey, b"Hello World");
+        assert_eq!(
+            spend.compute_txid().to_string(),
+            "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b"
+        );
+        assert_eq!(
+            to_sign(&spend).compute_txid().to_string(),
+            "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf"
+        );
+    }
+
+    #[test]
+    fn test_prove() {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let signers: Vec<Xpriv> = [[0x01; 32], [0x02; 32]]
+            .iter()
+            .map(|seed| Xpriv::new_master(Network::Testnet, seed).unwrap())
+            .collect();
+        let keys = signers.iter().map(|xpriv| {
+            (
+                (xpriv.fingerprint(&secp), path.clone()),
+                Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap()),
+            )
+        });
+        let policy = WalletPolicy::new(
+            "Multisig".to_string(),
+            crate::wallet::Version::V2,
+            "wsh(sortedmulti(2,@0/**,@1/**))".to_string(),
+            keys,
+        );
+
+        let mut psbt = build_psbt(b"hello", &policy, false, 3, ProofFormat::Simple).unwrap();
+        assert_eq!(psbt.inputs[0].bip32_derivation.len(), 2);
+        psbt.sign(&signers[0], &secp).unwrap();
+        let psbt = match prove(psbt, &policy, ProofFormat::Simple).unwrap() {
+            Proof::Partial(mut psbt) => {
+                psbt.sign(&signers[1], &secp).unwrap();
+                *psbt
+            }
+            Proof::Signed(_) => panic!("expected a partial proof"),
+        };
+        let Proof::Signed(proof) = prove(psbt, &policy, ProofFormat::Simple).unwrap() else {
+            panic!("expected a signed proof");
+        };
+        let witness: Witness =
+            bitcoin::consensus::deserialize(&Base64::decode_vec(&proof).unwrap()).unwrap();
+        // The empty element, the two signatures and the witness script.
+        assert_eq!(witness.len(), 4);
+
+        let policy = WalletPolicy::new(
+            String::new(),
+            crate::wallet::Version::V2,
+            "pkh(@0/**)".to_string(),
+            [policy.keys[0].source.clone().unwrap()]
+                .into_iter()
+                .zip([policy.keys[0].inner]),
+        );
+        assert!(matches!(
+            build_psbt(b"hello", &policy, false, 0, ProofFormat::Simple),
+            Err(Bip322Error::NotSegwit)
+        ));
+        assert!(build_psbt(b"hello", &policy, false, 0, ProofFormat::Full).is_ok());
+    }
+}
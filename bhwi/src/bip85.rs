@@ -0,0 +1,212 @@
+//! BIP-85 deterministic entropy: child keys derived from the master key
+//! under the purpose 83696968' are turned into the entropy of new wallets.
+
+use bitcoin::{
+    bip32::{self, ChainCode, ChildNumber, DerivationPath, Xpriv},
+    hashes::{hmac, sha512, Hash, HashEngine},
+    secp256k1::{Secp256k1, Signing},
+    Network, NetworkKind, PrivateKey,
+};
+
+pub const PURPOSE: u32 = 83696968;
+
+/// Key of the HMAC turning the derived private key into entropy.
+const HMAC_KEY: &[u8] = b"bip-entropy-from-k";
+
+/// Format of the derived entropy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Application {
+    /// Entropy of a BIP-39 mnemonic of 12, 18 or 24 words, the language is
+    /// the BIP-85 code of the wordlist, 0 for english.
+    Bip39 { language: u32, words: u32 },
+    /// 32 bytes private key of a WIF.
+    Wif,
+    /// Chain code then private key of an extended private key.
+    Xprv,
+    /// Raw entropy of 16 to 64 bytes.
+    Hex { num_bytes: u32 },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Bip85Error {
+    InvalidWords(u32),
+    InvalidLength(u32),
+    InvalidIndex(u32),
+    Derivation(String),
+}
+
+impl Application {
+    /// Returns the derivation path of the child key of the index.
+    pub fn path(&self, index: u32) -> Result<DerivationPath, Bip85Error> {
+        let path = match *self {
+            Application::Bip39 { language, words } => vec![39, language, words, index],
+            Application::Wif => vec![2, index],
+            Application::Xprv => vec![32, index],
+            Application::Hex { num_bytes } => vec![128169, num_bytes, index],
+        };
+        std::iter::once(PURPOSE)
+            .chain(path)
+            .map(|i| ChildNumber::from_hardened_idx(i).map_err(|_| Bip85Error::InvalidIndex(i)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(DerivationPath::from)
+    }
+
+    /// Number of bytes of entropy used by the application.
+    pub fn entropy_len(&self) -> Result<usize, Bip85Error> {
+        match *self {
+            Application::Bip39 { words, .. } => match words {
+                12 => Ok(16),
+                18 => Ok(24),
+                24 => Ok(32),
+                _ => Err(Bip85Error::InvalidWords(words)),
+            },
+            Application::Wif => Ok(32),
+            Application::Xprv => Ok(64),
+            Application::Hex { num_bytes } if (16..=64).contains(&num_bytes) => {
+                Ok(num_bytes as usize)
+            }
+            Application::Hex { num_bytes } => Err(Bip85Error::InvalidLength(num_bytes)),
+        }
+    }
+}
+
+/// Returns the 64 bytes of entropy of the child key at the path.
+pub fn derive_entropy<C: Signing>(
+    secp: &Secp256k1<C>,
+    root: &Xpriv,
+    path: &DerivationPath,
+) -> Result<[u8; 64], bip32::Error> {
+    let child = root.derive_priv(secp, path)?;
+    let mut engine = hmac::HmacEngine::<sha512::Hash>::new(HMAC_KEY);
+    engine.input(&child.private_key.secret_bytes());
+    Ok(hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array())
+}
+
+/// Returns the entropy of the application at the index.
+pub fn derive<C: Signing>(
+    secp: &Secp256k1<C>,
+    root: &Xpriv,
+    application: Application,
+    index: u32,
+) -> Result<Vec<u8>, Bip85Error> {
+    let len = application.entropy_len()?;
+    let entropy = derive_entropy(secp, root, &application.path(index)?)
+        .map_err(|e| Bip85Error::Derivation(e.to_string()))?;
+    Ok(entropy[..len].to_vec())
+}
+
+/// Returns the WIF private key derived at the index.
+pub fn derive_wif<C: Signing>(
+    secp: &Secp256k1<C>,
+    root: &Xpriv,
+    index: u32,
+    network: Network,
+) -> Result<PrivateKey, Bip85Error> {
+    let entropy = derive(secp, root, Application::Wif, index)?;
+    PrivateKey::from_slice(&entropy, network).map_err(|e| Bip85Error::Derivation(e.to_string()))
+}
+
+/// Returns the master extended private key derived at the index.
+pub fn derive_xprv<C: Signing>(
+    secp: &Secp256k1<C>,
+    root: &Xpriv,
+    index: u32,
+) -> Result<Xpriv, Bip85Error> {
+    let entropy = derive(secp, root, Application::Xprv, index)?;
+    let mut chain_code = [0u8; 32];
+    chain_code.copy_from_slice(&entropy[..32]);
+    Ok(Xpriv {
+        network: NetworkKind::Main,
+        depth: 0,
+        parent_fingerprint: Default::default(),
+        child_number: ChildNumber::Normal { index: 0 },
+        private_key: bitcoin::secp256k1::SecretKey::from_slice(&entropy[32..])
+            .map_err(|e| Bip85Error::Derivation(e.to_string()))?,
+        chain_code: ChainCode::from(chain_code),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::DisplayHex;
+    use std::str::FromStr;
+
+    /// Test vectors of BIP-85.
+    fn root() -> Xpriv {
+        Xpriv::from_str("xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb").unwrap()
+    }
+
+    #[test]
+    fn test_derive_entropy() {
+        let secp = Secp256k1::new();
+        assert_eq!(
+            derive_entropy(&secp, &root(), &DerivationPath::from_str("m/83696968'/0'/0'").unwrap())
+                .unwrap()
+                .to_lower_hex_string(),
+            "efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f00b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7"
+        );
+        assert_eq!(
+            derive_entropy(&secp, &root(), &DerivationPath::from_str("m/83696968'/0'/1'").unwrap())
+                .unwrap()
+                .to_lower_hex_string(),
+            "70c6e3e8ebee8dc4c0dbba66076819bb8c09672527c4277ca8729532ad711872218f826919f6b67218adde99018a6df9095ab2b58d803b5b93ec9802085a690e"
+        );
+    }
+
+    #[test]
+    fn test_applications() {
+        let secp = Secp256k1::new();
+        let bip39 = Application::Bip39 {
+            language: 0,
+            words: 12,
+        };
+        assert_eq!(
+            bip39.path(0).unwrap(),
+            DerivationPath::from_str("m/83696968'/39'/0'/12'/0'").unwrap()
+        );
+        assert_eq!(
+            derive(&secp, &root(), bip39, 0)
+                .unwrap()
+                .to_lower_hex_string(),
+            "6250b68daf746d12a24d58b4787a714b"
+        );
+        assert_eq!(
+            derive(&secp, &root(), Application::Hex { num_bytes: 64 }, 0)
+                .unwrap()
+                .to_lower_hex_string(),
+            "492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f878555d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c"
+        );
+        assert_eq!(
+            derive_wif(&secp, &root(), 0, Network::Bitcoin)
+                .unwrap()
+                .to_string(),
+            "Kzyv4uF39d4Jrw2W7UryTHwZr1zQVNk4dAFyqE6BuMrMh1Za7uhp"
+        );
+        assert_eq!(
+            derive_xprv(&secp, &root(), 0).unwrap().to_string(),
+            "xprv9s21ZrQH143K2srSbCSg4m4kLvPMzcWydgmKEnMmoZUurYuBuYG46c6P71UGXMzmriLzCCBvKQWBUv3vPB3m1SATMhp3uEjXHJ42jFg7myX"
+        );
+
+        assert_eq!(
+            derive(
+                &secp,
+                &root(),
+                Application::Bip39 {
+                    language: 0,
+                    words: 13
+                },
+                0
+            ),
+            Err(Bip85Error::InvalidWords(13))
+        );
+        assert_eq!(
+            derive(&secp, &root(), Application::Hex { num_bytes: 65 }, 0),
+            Err(Bip85Error::InvalidLength(65))
+        );
+        assert_eq!(
+            Application::Wif.path(1 << 31),
+            Err(Bip85Error::InvalidIndex(1 << 31))
+        );
+    }
+}
@@ -1,5 +1,17 @@
+/// Maximum size of the blocks of an uploaded or downloaded file.
+pub const MAX_BLOCK_LEN: usize = 2048;
+
+/// Address formats of the signed messages and of the shown addresses.
+pub const AF_CLASSIC: u32 = 0x01;
+pub const AF_P2SH: u32 = 0x08;
+pub const AF_P2WPKH: u32 = 0x07;
+pub const AF_P2WSH: u32 = 0x0e;
+pub const AF_P2WPKH_P2SH: u32 = 0x13;
+pub const AF_P2WSH_P2SH: u32 = 0x1a;
+pub const AF_P2TR: u32 = 0x23;
+
 pub mod request {
-    use bitcoin::bip32::DerivationPath;
+    use bitcoin::bip32::{DerivationPath, KeySource};
 
     pub fn start_encryption(version: Option<u32>, key: &[u8; 64]) -> Vec<u8> {
         let mut data = "ncry".as_bytes().to_owned();
@@ -15,6 +27,92 @@ pub mod request {
             format!("xpubm/{}", path).as_bytes().to_vec()
         }
     }
+
+    /// Uploads the block of the file starting at the offset.
+    pub fn upload(offset: u32, total_len: u32, data: &[u8]) -> Vec<u8> {
+        let mut req = "upld".as_bytes().to_owned();
+        req.extend(offset.to_le_bytes());
+        req.extend(total_len.to_le_bytes());
+        req.extend(data);
+        req
+    }
+
+    /// Requests the hash of the uploaded file.
+    pub fn sha256() -> Vec<u8> {
+        "sha2".as_bytes().to_vec()
+    }
+
+    /// Starts the signature of the uploaded psbt.
+    pub fn sign_transaction(len: u32, sha: &[u8; 32], flags: u32) -> Vec<u8> {
+        let mut req = "stxn".as_bytes().to_owned();
+        req.extend(len.to_le_bytes());
+        req.extend(flags.to_le_bytes());
+        req.extend(sha);
+        req
+    }
+
+    /// Polls the result of the psbt signature.
+    pub fn get_signed_transaction() -> Vec<u8> {
+        "stok".as_bytes().to_vec()
+    }
+
+    /// Downloads a block of a file produced by the device.
+    pub fn download(offset: u32, len: u32, file_number: u32) -> Vec<u8> {
+        let mut req = "dwld".as_bytes().to_owned();
+        req.extend(offset.to_le_bytes());
+        req.extend(len.to_le_bytes());
+        req.extend(file_number.to_le_bytes());
+        req
+    }
+
+    /// Starts the signature of the message with the key at the path.
+    pub fn sign_message(message: &[u8], path: &DerivationPath, address_format: u32) -> Vec<u8> {
+        let path = format!("m/{}", path);
+        let mut req = "smsg".as_bytes().to_owned();
+        req.extend(address_format.to_le_bytes());
+        req.extend((path.len() as u32).to_le_bytes());
+        req.extend((message.len() as u32).to_le_bytes());
+        req.extend(path.as_bytes());
+        req.extend(message);
+        req
+    }
+
+    /// Polls the result of the message signature.
+    pub fn get_signed_message() -> Vec<u8> {
+        "smok".as_bytes().to_vec()
+    }
+
+    /// Shows the single key address of the key at the path.
+    pub fn show_address(path: &DerivationPath, address_format: u32) -> Vec<u8> {
+        let mut req = "show".as_bytes().to_owned();
+        req.extend(address_format.to_le_bytes());
+        req.extend(format!("m/{}", path).as_bytes());
+        req
+    }
+
+    /// Shows the address of the multisig script of a wallet registered on
+    /// the device, the origins of the keys are in script order.
+    pub fn show_multisig_address(
+        threshold: u8,
+        origins: &[KeySource],
+        script: &[u8],
+        address_format: u32,
+    ) -> Vec<u8> {
+        let mut req = "p2sh".as_bytes().to_owned();
+        req.extend(address_format.to_le_bytes());
+        req.push(threshold);
+        req.push(origins.len() as u8);
+        req.extend((script.len() as u16).to_le_bytes());
+        req.extend(script);
+        for (fingerprint, path) in origins {
+            req.push(path.len() as u8 + 1);
+            req.extend(fingerprint.as_bytes());
+            for child in path {
+                req.extend(u32::from(*child).to_le_bytes());
+            }
+        }
+        req
+    }
 }
 
 #[cfg(test)]
@@ -28,13 +126,22 @@ mod tests {
         let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
         assert_eq!("48'/1'/0'/2'", path.to_string());
     }
+
+    #[test]
+    fn test_xpub_response() {
+        // Test vector 1 of BIP-32, chain m/0'/1.
+        let xpub = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+        let res = [b"asci".as_slice(), xpub.as_bytes()].concat();
+        assert_eq!(super::response::xpub(res).unwrap().to_string(), xpub);
+        assert!(super::response::xpub(b"err_Not ready".to_vec()).is_err());
+    }
 }
 
 pub mod response {
     use std::str::FromStr;
 
     use crate::coldcard::{ColdcardError, ColdcardResponse};
-    use bitcoin::bip32::Xpub;
+    use bitcoin::{address::NetworkUnchecked, bip32::Xpub, Address};
     pub fn xpub(res: Vec<u8>) -> Result<Xpub, ColdcardError> {
         let (command, data) = split(&res, 4)?;
         if command == b"asci" {
@@ -42,11 +149,101 @@ pub mod response {
                 .map_err(|e| ColdcardError::Serialization(e.to_string()))?;
             Xpub::from_str(s).map_err(|e| ColdcardError::Serialization(e.to_string()))
         } else {
-            Err(ColdcardError::Serialization(format!(
+            Err(unexpected(command, data))
+        }
+    }
+
+    /// Returns the address shown by the device.
+    pub fn address(res: Vec<u8>) -> Result<Address<NetworkUnchecked>, ColdcardError> {
+        let (command, data) = split(&res, 4)?;
+        if command == b"asci" {
+            let s = std::str::from_utf8(data)
+                .map_err(|e| ColdcardError::Serialization(e.to_string()))?;
+            Address::from_str(s).map_err(|e| ColdcardError::Serialization(e.to_string()))
+        } else {
+            Err(unexpected(command, data))
+        }
+    }
+
+    /// The device accepted the request.
+    pub fn okay(res: Vec<u8>) -> Result<(), ColdcardError> {
+        let (command, data) = split(&res, 4)?;
+        if command == b"okay" {
+            Ok(())
+        } else {
+            Err(unexpected(command, data))
+        }
+    }
+
+    pub fn int1(res: Vec<u8>) -> Result<u32, ColdcardError> {
+        let (command, data) = split(&res, 4)?;
+        if command == b"int1" {
+            decode_u32(data.get(0..4))
+        } else {
+            Err(unexpected(command, data))
+        }
+    }
+
+    pub fn biny(res: Vec<u8>) -> Result<Vec<u8>, ColdcardError> {
+        let (command, data) = split(&res, 4)?;
+        if command == b"biny" {
+            Ok(data.to_vec())
+        } else {
+            Err(unexpected(command, data))
+        }
+    }
+
+    /// Returns the length and the hash of the signed psbt,
+    /// None if the user has not approved it yet.
+    pub fn signed_transaction(res: Vec<u8>) -> Result<Option<(u32, [u8; 32])>, ColdcardError> {
+        let (command, data) = split(&res, 4)?;
+        match command {
+            b"okay" | b"busy" => Ok(None),
+            b"strx" => {
+                let len = decode_u32(data.get(0..4))?;
+                let sha = data
+                    .get(4..36)
+                    .ok_or(ColdcardError::Serialization(
+                        "sha wants 32 bytes".to_string(),
+                    ))?
+                    .try_into()
+                    .expect("infallible");
+                Ok(Some((len, sha)))
+            }
+            _ => Err(unexpected(command, data)),
+        }
+    }
+
+    /// Returns the address and the signature of the message,
+    /// None if the user has not approved it yet.
+    pub fn signed_message(res: Vec<u8>) -> Result<Option<(String, Vec<u8>)>, ColdcardError> {
+        let (command, data) = split(&res, 4)?;
+        match command {
+            b"okay" | b"busy" => Ok(None),
+            b"smrx" => {
+                let len = decode_u32(data.get(0..4))? as usize;
+                let address = data
+                    .get(4..4 + len)
+                    .map(|d| String::from_utf8(d.to_owned()))
+                    .transpose()
+                    .map_err(|e| ColdcardError::Serialization(e.to_string()))?
+                    .ok_or(ColdcardError::Serialization("address".to_string()))?;
+                Ok(Some((address, data[4 + len..].to_vec())))
+            }
+            _ => Err(unexpected(command, data)),
+        }
+    }
+
+    /// Returns the error of a response that is not the expected one.
+    fn unexpected(command: &[u8], data: &[u8]) -> ColdcardError {
+        match command {
+            b"refu" => ColdcardError::Refused,
+            b"err_" | b"fram" => ColdcardError::Device(String::from_utf8_lossy(data).to_string()),
+            _ => ColdcardError::Serialization(format!(
                 "command: {:?}, data: {}",
                 String::from_utf8(command.to_vec()).unwrap_or_else(|_| format!("{:?}", command)),
                 String::from_utf8(data.to_vec()).unwrap_or_else(|_| format!("{:?}", data))
-            )))
+            )),
         }
     }
 
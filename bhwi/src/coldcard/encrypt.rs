@@ -5,6 +5,7 @@ pub use k256::schnorr::CryptoRngCore;
 
 use crate::coldcard::ColdcardError;
 
+#[allow(clippy::large_enum_variant)]
 pub enum Engine {
     New(k256::SecretKey),
     Ready {
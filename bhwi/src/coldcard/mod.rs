@@ -1,22 +1,79 @@
 pub mod api;
 pub mod encrypt;
 
-use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use bitcoin::{
+    address::NetworkUnchecked,
+    base64::{prelude::BASE64_STANDARD, Engine as _},
+    bip32::{DerivationPath, Fingerprint, KeySource, Xpub},
+    hashes::{sha256, Hash},
+    Address, Psbt, ScriptBuf,
+};
 
+use std::convert::Infallible;
+
+use crate::devices::{Capabilities, NETWORKS};
 use crate::Interpreter;
 
+/// Returns the capabilities of the Coldcard, its multisig wallets are
+/// imported on the device and not registered by the host.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        taproot: true,
+        musig2: false,
+        message_signing: true,
+        policy_registration: false,
+        max_inputs: None,
+        networks: NETWORKS.to_vec(),
+    }
+}
+
 #[derive(Debug)]
 pub enum ColdcardError {
     Encryption(&'static str),
     MissingCommandInfo(&'static str),
     NoErrorOrResult,
     Serialization(String),
+    /// Error message of the device.
+    Device(String),
+    /// The user refused the request on the device.
+    Refused,
+    /// The device does not support the command.
+    UnsupportedCommand(&'static str),
+}
+
+impl From<Infallible> for ColdcardError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
 }
 
 pub enum ColdcardCommand {
     StartEncryption,
     GetMasterFingerprint,
     GetXpub(DerivationPath),
+    SignPsbt(Box<Psbt>),
+    SignMessage {
+        path: DerivationPath,
+        message: Vec<u8>,
+        /// One of the `api::AF_*` address formats.
+        address_format: u32,
+    },
+    /// Shows the single key address of the key at the path.
+    ShowAddress {
+        path: DerivationPath,
+        /// One of the `api::AF_*` single key address formats.
+        address_format: u32,
+    },
+    /// Shows the address of a multisig wallet registered on the device.
+    ShowMultisigAddress {
+        threshold: u8,
+        /// Origins of the keys, in the order of the script.
+        origins: Vec<KeySource>,
+        /// Witness script, or redeem script of the legacy p2sh addresses.
+        script: ScriptBuf,
+        /// One of the `api::AF_*` script address formats.
+        address_format: u32,
+    },
 }
 
 pub enum ColdcardResponse {
@@ -27,6 +84,13 @@ pub enum ColdcardResponse {
         xpub_fingerprint: Fingerprint,
         xpub: Option<Xpub>,
     },
+    SignedPsbt(Box<Psbt>),
+    MessageSignature {
+        address: String,
+        /// Recoverable signature of the message, base64 encoded.
+        signature: String,
+    },
+    Address(Address<NetworkUnchecked>),
 }
 
 pub struct ColdcardTransmit {
@@ -34,9 +98,27 @@ pub struct ColdcardTransmit {
     pub encrypted: bool,
 }
 
+/// The psbt is uploaded and checked, then signed once the user approved it
+/// and downloaded. The results of the signatures are polled until the user
+/// approves them.
 enum State {
     New,
     Running(ColdcardCommand),
+    /// The block of the psbt starting at the offset was sent.
+    Uploading {
+        psbt: Vec<u8>,
+        offset: usize,
+    },
+    /// The hash of the uploaded psbt was requested.
+    CheckingUpload(Vec<u8>),
+    StartingPsbtSignature,
+    WaitingPsbtSignature,
+    Downloading {
+        len: usize,
+        sha: [u8; 32],
+        data: Vec<u8>,
+    },
+    WaitingMessageSignature,
     Finished(ColdcardResponse),
 }
 
@@ -66,9 +148,34 @@ fn request(
     })
 }
 
+fn upload(
+    psbt: &[u8],
+    offset: usize,
+    encryption: &mut encrypt::Engine,
+) -> Result<ColdcardTransmit, ColdcardError> {
+    let end = std::cmp::min(offset + api::MAX_BLOCK_LEN, psbt.len());
+    request(
+        api::request::upload(offset as u32, psbt.len() as u32, &psbt[offset..end]),
+        encryption,
+    )
+}
+
+fn download(
+    len: usize,
+    offset: usize,
+    encryption: &mut encrypt::Engine,
+) -> Result<ColdcardTransmit, ColdcardError> {
+    let block = std::cmp::min(len - offset, api::MAX_BLOCK_LEN);
+    request(
+        api::request::download(offset as u32, block as u32, 1),
+        encryption,
+    )
+}
+
 impl<'a, C, T, R, E> Interpreter for ColdcardInterpreter<'a, C, T, R, E>
 where
-    C: TryInto<ColdcardCommand, Error = ColdcardError>,
+    C: TryInto<ColdcardCommand>,
+    C::Error: Into<ColdcardError>,
     T: From<ColdcardTransmit>,
     R: From<ColdcardResponse>,
     E: From<ColdcardError>,
@@ -79,7 +186,7 @@ where
     type Error = E;
 
     fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
-        let command: ColdcardCommand = command.try_into()?;
+        let command: ColdcardCommand = command.try_into().map_err(Into::into)?;
         let req = match &command {
             ColdcardCommand::StartEncryption => ColdcardTransmit {
                 payload: api::request::start_encryption(None, &self.encryption.pub_key()?),
@@ -92,13 +199,48 @@ where
             ColdcardCommand::GetXpub(path) => {
                 request(api::request::get_xpub(path), self.encryption)?
             }
+            ColdcardCommand::SignPsbt(psbt) => {
+                let psbt = psbt.serialize();
+                let req = upload(&psbt, 0, self.encryption)?;
+                self.state = State::Uploading { psbt, offset: 0 };
+                return Ok(req.into());
+            }
+            ColdcardCommand::SignMessage {
+                path,
+                message,
+                address_format,
+            } => request(
+                api::request::sign_message(message, path, *address_format),
+                self.encryption,
+            )?,
+            ColdcardCommand::ShowAddress {
+                path,
+                address_format,
+            } => request(
+                api::request::show_address(path, *address_format),
+                self.encryption,
+            )?,
+            ColdcardCommand::ShowMultisigAddress {
+                threshold,
+                origins,
+                script,
+                address_format,
+            } => request(
+                api::request::show_multisig_address(
+                    *threshold,
+                    origins,
+                    script.as_bytes(),
+                    *address_format,
+                ),
+                self.encryption,
+            )?,
         };
 
         self.state = State::Running(command);
         Ok(req.into())
     }
     fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
-        match &self.state {
+        match &mut self.state {
             State::New => Ok(None),
             State::Running(ColdcardCommand::GetMasterFingerprint) => {
                 let data = self.encryption.decrypt(data)?;
@@ -118,6 +260,112 @@ where
                 self.state = State::Finished(mypub);
                 Ok(None)
             }
+            State::Running(ColdcardCommand::SignPsbt(..)) => Ok(None),
+            State::Running(
+                ColdcardCommand::ShowAddress { .. } | ColdcardCommand::ShowMultisigAddress { .. },
+            ) => {
+                let address = api::response::address(self.encryption.decrypt(data)?)?;
+                self.state = State::Finished(ColdcardResponse::Address(address));
+                Ok(None)
+            }
+            State::Running(ColdcardCommand::SignMessage { .. }) => {
+                api::response::okay(self.encryption.decrypt(data)?)?;
+                self.state = State::WaitingMessageSignature;
+                Ok(Some(
+                    request(api::request::get_signed_message(), self.encryption)?.into(),
+                ))
+            }
+            State::WaitingMessageSignature => {
+                let data = self.encryption.decrypt(data)?;
+                match api::response::signed_message(data)? {
+                    None => Ok(Some(
+                        request(api::request::get_signed_message(), self.encryption)?.into(),
+                    )),
+                    Some((address, signature)) => {
+                        self.state = State::Finished(ColdcardResponse::MessageSignature {
+                            address,
+                            signature: BASE64_STANDARD.encode(signature),
+                        });
+                        Ok(None)
+                    }
+                }
+            }
+            State::Uploading { psbt, offset } => {
+                let data = self.encryption.decrypt(data)?;
+                if api::response::int1(data)? as usize != *offset {
+                    return Err(ColdcardError::Serialization("upload offset".to_string()).into());
+                }
+                let next = *offset + api::MAX_BLOCK_LEN;
+                if next < psbt.len() {
+                    let req = upload(psbt, next, self.encryption)?;
+                    self.state = State::Uploading {
+                        psbt: std::mem::take(psbt),
+                        offset: next,
+                    };
+                    Ok(Some(req.into()))
+                } else {
+                    self.state = State::CheckingUpload(std::mem::take(psbt));
+                    Ok(Some(
+                        request(api::request::sha256(), self.encryption)?.into(),
+                    ))
+                }
+            }
+            State::CheckingUpload(psbt) => {
+                let data = self.encryption.decrypt(data)?;
+                let sha = sha256::Hash::hash(psbt).to_byte_array();
+                if api::response::biny(data)? != sha {
+                    return Err(ColdcardError::Serialization("upload hash".to_string()).into());
+                }
+                let req = request(
+                    api::request::sign_transaction(psbt.len() as u32, &sha, 0),
+                    self.encryption,
+                )?;
+                self.state = State::StartingPsbtSignature;
+                Ok(Some(req.into()))
+            }
+            State::StartingPsbtSignature => {
+                api::response::okay(self.encryption.decrypt(data)?)?;
+                self.state = State::WaitingPsbtSignature;
+                Ok(Some(
+                    request(api::request::get_signed_transaction(), self.encryption)?.into(),
+                ))
+            }
+            State::WaitingPsbtSignature => {
+                let data = self.encryption.decrypt(data)?;
+                match api::response::signed_transaction(data)? {
+                    None => Ok(Some(
+                        request(api::request::get_signed_transaction(), self.encryption)?.into(),
+                    )),
+                    Some((len, sha)) => {
+                        let len = len as usize;
+                        let req = download(len, 0, self.encryption)?;
+                        self.state = State::Downloading {
+                            len,
+                            sha,
+                            data: Vec::with_capacity(len),
+                        };
+                        Ok(Some(req.into()))
+                    }
+                }
+            }
+            State::Downloading {
+                len,
+                sha,
+                data: psbt,
+            } => {
+                let data = self.encryption.decrypt(data)?;
+                psbt.extend(api::response::biny(data)?);
+                if psbt.len() < *len {
+                    return Ok(Some(download(*len, psbt.len(), self.encryption)?.into()));
+                }
+                if sha256::Hash::hash(psbt).to_byte_array() != *sha {
+                    return Err(ColdcardError::Serialization("download hash".to_string()).into());
+                }
+                let psbt = Psbt::deserialize(psbt)
+                    .map_err(|e| ColdcardError::Serialization(e.to_string()))?;
+                self.state = State::Finished(ColdcardResponse::SignedPsbt(Box::new(psbt)));
+                Ok(None)
+            }
             State::Finished(..) => Ok(None),
         }
     }
@@ -129,3 +377,239 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, psbt::raw, transaction::Version, Amount, ScriptBuf, Transaction, TxOut,
+    };
+
+    const ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    /// Coldcard accepting the requests once the user approved them after
+    /// the given number of polls.
+    struct SimulatedColdcard {
+        engine: encrypt::Engine,
+        upload: Vec<u8>,
+        signed: Vec<u8>,
+        polls: usize,
+        refuse: bool,
+    }
+
+    impl SimulatedColdcard {
+        fn handle(&mut self, req: Vec<u8>) -> Vec<u8> {
+            let req = self.engine.decrypt(req).unwrap();
+            let u32_at = |i: usize| u32::from_le_bytes(req[i..i + 4].try_into().unwrap()) as usize;
+            let res = match &req[..4] {
+                b"upld" => {
+                    let offset = u32_at(4);
+                    self.upload.truncate(offset);
+                    self.upload.extend(&req[12..]);
+                    [b"int1".as_slice(), &req[4..8]].concat()
+                }
+                b"sha2" => [
+                    b"biny".as_slice(),
+                    sha256::Hash::hash(&self.upload).as_byte_array(),
+                ]
+                .concat(),
+                b"stxn" | b"smsg" => b"okay".to_vec(),
+                b"show" | b"p2sh" => [b"asci".as_slice(), ADDRESS.as_bytes()].concat(),
+                _ if self.polls > 0 => {
+                    self.polls -= 1;
+                    b"busy".to_vec()
+                }
+                _ if self.refuse => b"refu".to_vec(),
+                b"stok" => {
+                    let mut res = b"strx".to_vec();
+                    res.extend((self.signed.len() as u32).to_le_bytes());
+                    res.extend(sha256::Hash::hash(&self.signed).as_byte_array());
+                    res
+                }
+                b"dwld" => {
+                    let (offset, len) = (u32_at(4), u32_at(8));
+                    [b"biny".as_slice(), &self.signed[offset..offset + len]].concat()
+                }
+                b"smok" => {
+                    let mut res = b"smrx".to_vec();
+                    res.extend(4_u32.to_le_bytes());
+                    res.extend(b"addr");
+                    res.extend([0x1f; 65]);
+                    res
+                }
+                _ => b"err_Unknown".to_vec(),
+            };
+            self.engine.encrypt(res).unwrap()
+        }
+    }
+
+    fn session(polls: usize, refuse: bool) -> (encrypt::Engine, SimulatedColdcard) {
+        let mut host = encrypt::Engine::New(k256::SecretKey::from_slice(&[0x01; 32]).unwrap());
+        let mut engine = encrypt::Engine::New(k256::SecretKey::from_slice(&[0x02; 32]).unwrap());
+        let (host_key, device_key) = (host.pub_key().unwrap(), engine.pub_key().unwrap());
+        host.ready(device_key).unwrap();
+        engine.ready(host_key).unwrap();
+        let device = SimulatedColdcard {
+            engine,
+            upload: Vec::new(),
+            signed: Vec::new(),
+            polls,
+            refuse,
+        };
+        (host, device)
+    }
+
+    fn run(
+        encryption: &mut encrypt::Engine,
+        device: &mut SimulatedColdcard,
+        command: ColdcardCommand,
+    ) -> Result<ColdcardResponse, ColdcardError> {
+        let mut interpreter: ColdcardInterpreter<
+            ColdcardCommand,
+            ColdcardTransmit,
+            ColdcardResponse,
+            ColdcardError,
+        > = ColdcardInterpreter::new(encryption);
+        let mut transmit = Some(interpreter.start(command)?);
+        while let Some(t) = transmit {
+            assert!(t.encrypted);
+            transmit = interpreter.exchange(device.handle(t.payload))?;
+        }
+        interpreter.end()
+    }
+
+    fn psbt(unknown: u8) -> Psbt {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        // Large enough to be uploaded in several blocks.
+        psbt.unknown.insert(
+            raw::Key {
+                type_value: 0xf0,
+                key: vec![unknown],
+            },
+            vec![unknown; 5_000],
+        );
+        psbt
+    }
+
+    #[test]
+    fn test_sign_psbt() {
+        let (mut encryption, mut device) = session(2, false);
+        device.signed = psbt(0x02).serialize();
+        let res = run(
+            &mut encryption,
+            &mut device,
+            ColdcardCommand::SignPsbt(Box::new(psbt(0x01))),
+        );
+        assert_eq!(device.upload, psbt(0x01).serialize());
+        match res {
+            Ok(ColdcardResponse::SignedPsbt(signed)) => assert_eq!(*signed, psbt(0x02)),
+            _ => panic!("expected a signed psbt"),
+        }
+
+        let (mut encryption, mut device) = session(0, true);
+        assert!(matches!(
+            run(
+                &mut encryption,
+                &mut device,
+                ColdcardCommand::SignPsbt(Box::new(psbt(0x01)))
+            ),
+            Err(ColdcardError::Refused)
+        ));
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let (mut encryption, mut device) = session(1, false);
+        let res = run(
+            &mut encryption,
+            &mut device,
+            ColdcardCommand::SignMessage {
+                path: "m/84'/0'/0'/0/0".parse().unwrap(),
+                message: b"message".to_vec(),
+                address_format: api::AF_P2WPKH,
+            },
+        );
+        match res {
+            Ok(ColdcardResponse::MessageSignature { address, signature }) => {
+                assert_eq!(address, "addr");
+                assert_eq!(BASE64_STANDARD.decode(signature).unwrap(), [0x1f; 65]);
+            }
+            _ => panic!("expected a message signature"),
+        }
+        assert_eq!(
+            api::request::sign_message(b"m", &"m/1'".parse().unwrap(), api::AF_CLASSIC)[4..],
+            [1, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0, b'm', b'/', b'1', b'\'', b'm']
+        );
+    }
+
+    #[test]
+    fn test_show_address() {
+        let (mut encryption, mut device) = session(0, false);
+        let res = run(
+            &mut encryption,
+            &mut device,
+            ColdcardCommand::ShowAddress {
+                path: "m/84'/1'/0'/0/0".parse().unwrap(),
+                address_format: api::AF_P2WPKH,
+            },
+        );
+        match res {
+            Ok(ColdcardResponse::Address(address)) => {
+                assert_eq!(address.assume_checked().to_string(), ADDRESS)
+            }
+            _ => panic!("expected an address"),
+        }
+
+        let policy = crate::wallet::WalletPolicy::new_multisig(
+            "Cold storage".to_string(),
+            crate::wallet::Version::V2,
+            crate::wallet::AddressType::NativeSegwit,
+            2,
+            [
+                "[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF",
+                "[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK",
+            ]
+            .map(|key| key.parse::<crate::wallet::WalletPubKey>().unwrap()),
+            true,
+        )
+        .unwrap();
+        let (threshold, script, origins) =
+            crate::psbt::multisig_origins(&policy, false, 3).unwrap();
+        assert_eq!(origins.len(), 2);
+        assert!(origins
+            .iter()
+            .all(|(_, path)| path.to_string().ends_with("/0/3")));
+        let req = api::request::show_multisig_address(
+            threshold as u8,
+            &origins,
+            script.as_bytes(),
+            api::AF_P2WSH,
+        );
+        assert_eq!(req[..10], [b'p', b'2', b's', b'h', 0x0e, 0, 0, 0, 2, 2]);
+        assert_eq!(req[12 + script.len()], 7);
+
+        let (mut encryption, mut device) = session(0, false);
+        assert!(matches!(
+            run(
+                &mut encryption,
+                &mut device,
+                ColdcardCommand::ShowMultisigAddress {
+                    threshold: threshold as u8,
+                    origins,
+                    script,
+                    address_format: api::AF_P2WSH,
+                },
+            ),
+            Ok(ColdcardResponse::Address(_))
+        ));
+    }
+}
@@ -1,26 +1,187 @@
 use bitcoin::{
-    bip32::{DerivationPath, Fingerprint, Xpub},
-    Network,
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    Address, Network, Psbt,
 };
 
-use crate::{coldcard, jade, ledger};
+#[cfg(feature = "airgap")]
+use crate::airgap;
+#[cfg(feature = "coldcard")]
+use crate::coldcard;
+#[cfg(feature = "jade")]
+use crate::jade;
+#[cfg(feature = "ledger")]
+use crate::ledger;
+#[cfg(feature = "specter")]
+use crate::specter;
+#[cfg(feature = "trezor")]
+use crate::trezor;
+use crate::{bip322, psbt, silentpayments, wallet};
 
 #[derive(Default)]
 pub struct UnlockOptions {
     pub network: Option<Network>,
 }
 
+/// What the device shows the user when an xpub is requested, see
+/// `DisplayXpub` of bhwi-async.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisplayMode {
+    /// Nothing is displayed.
+    #[default]
+    Hidden,
+    /// The raw xpub.
+    Xpub,
+    /// The first receive address of the account, `path/0/0`, easier to check
+    /// than the xpub. The devices displaying no address refuse it.
+    FirstAddress,
+}
+
+impl DisplayMode {
+    /// Returns the path of the first receive address of the account.
+    pub fn first_address(account: &DerivationPath) -> DerivationPath {
+        account.extend([
+            ChildNumber::Normal { index: 0 },
+            ChildNumber::Normal { index: 0 },
+        ])
+    }
+}
+
+impl From<bool> for DisplayMode {
+    fn from(display: bool) -> Self {
+        if display {
+            Self::Xpub
+        } else {
+            Self::Hidden
+        }
+    }
+}
+
+/// Commands common to the devices, each backend converts them to its own
+/// commands and refuses the ones it does not support.
 pub enum Command {
-    Unlock { options: UnlockOptions },
+    Unlock {
+        options: UnlockOptions,
+    },
     GetMasterFingerprint,
-    GetXpub { path: DerivationPath, display: bool },
+    GetXpub {
+        path: DerivationPath,
+        display: bool,
+    },
+    RegisterWallet {
+        policy: wallet::WalletPolicy,
+    },
+    SignPsbt {
+        psbt: Box<Psbt>,
+        /// Policy of the wallet, required by the devices signing for
+        /// registered policies only.
+        policy: Option<wallet::WalletPolicy>,
+        /// Proof of registration of the policy, None for default wallets.
+        hmac: Option<[u8; 32]>,
+    },
+    SignMessage {
+        path: DerivationPath,
+        message: Vec<u8>,
+    },
+    /// Displays the address of the key at the path, the script type of single
+    /// key addresses follows the purpose of the path.
+    DisplayAddress {
+        path: DerivationPath,
+        policy: Option<wallet::WalletPolicy>,
+        hmac: Option<[u8; 32]>,
+    },
+    /// Exports the scan and the spend keys of the silent payments of the
+    /// account, see [`crate::silentpayments`]. The devices without support
+    /// refuse it with `UnsupportedCommand`.
+    GetSilentPaymentKeys {
+        account: u32,
+    },
+    /// Checks in one exchange whether the device is unlocked and which app
+    /// is open, without prompting the user, see [`DeviceStatus`].
+    GetStatus,
+    /// Proves the ownership of the address of the policy at the index with
+    /// a BIP-322 signature of the message, see [`crate::bip322`].
+    ProveAddressOwnership {
+        message: Vec<u8>,
+        policy: wallet::WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        change: bool,
+        index: u32,
+        format: bip322::ProofFormat,
+    },
+    /// Searches the address in the receive and the change addresses of the
+    /// policy below the gap limit, then displays it on the device to be
+    /// confirmed by the user. Answers its change flag and index.
+    VerifyOwnedAddress {
+        address: Address<NetworkUnchecked>,
+        policy: wallet::WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        gap_limit: u32,
+    },
+}
+
+/// State of the device, cheap enough to be polled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceStatus {
+    /// The device answered, false if the transport failed.
+    pub connected: bool,
+    pub unlocked: bool,
+    /// Name of the open app, unknown while the device is locked.
+    pub app: Option<String>,
+    pub version: Option<String>,
+}
+
+/// Returns the purpose of the path, the first hardened child of BIP-44 like
+/// paths.
+pub(crate) fn purpose(path: &DerivationPath) -> Option<u32> {
+    match path.into_iter().next() {
+        Some(ChildNumber::Hardened { index }) => Some(*index),
+        _ => None,
+    }
+}
+
+/// Returns the change and the index of the address path.
+#[cfg(any(feature = "ledger", feature = "coldcard", feature = "specter"))]
+fn change_and_index(path: &DerivationPath) -> Option<(bool, u32)> {
+    match path.as_ref() {
+        [.., ChildNumber::Normal { index: change }, ChildNumber::Normal { index }]
+            if *change <= 1 =>
+        {
+            Some((*change == 1, *index))
+        }
+        _ => None,
+    }
 }
 
 pub enum Response {
     TaskDone,
+    #[cfg(feature = "ledger")]
+    AppInfo(ledger::AppInfo),
+    #[cfg(feature = "ledger")]
+    Apps(Vec<ledger::InstalledApp>),
     MasterFingerprint(Fingerprint),
     Xpub(Xpub),
+    Xpubs(Vec<(DerivationPath, Xpub)>),
     EncryptionKey([u8; 64]),
+    Signatures(Vec<(usize, psbt::PartialSignature)>),
+    MusigNonces(Vec<(usize, psbt::MusigPubNonce)>),
+    MusigPartialSigs(Vec<(usize, psbt::MusigPartialSignature)>),
+    SignedPsbt(Box<Psbt>),
+    WalletRegistered {
+        id: [u8; 32],
+        hmac: [u8; 32],
+    },
+    Address(Address<NetworkUnchecked>),
+    MessageSignature(String),
+    SilentPaymentKeys(silentpayments::SilentPaymentKeys),
+    Status(DeviceStatus),
+    OwnershipProof(bip322::Proof),
+    OwnedAddress {
+        change: bool,
+        index: u32,
+    },
+    #[cfg(feature = "elements")]
+    MasterBlindingKey([u8; 32]),
 }
 
 pub enum Recipient {
@@ -45,8 +206,52 @@ pub enum Error {
     Serialization(String),
     Request(&'static str),
     AuthenticationRefused,
+    /// The user refused the request on the device.
+    UserRefused,
+    /// The device must be unlocked with its PIN.
+    DeviceLocked,
+    /// The device, a key or a path is for another network than the
+    /// requested one.
+    NetworkMismatch,
+    /// The sighash type of the input at the index is not supported.
+    UnsupportedSighash(usize),
+    /// A path of the command is unusual and was rejected.
+    UnusualPath(crate::path::PathWarning),
+    /// The name or the template of the policy is invalid.
+    InvalidPolicy(crate::wallet::WalletError),
+    /// The device does not support the command.
+    UnsupportedCommand(&'static str),
+    /// The command was cancelled by the application.
+    Cancelled,
+    /// The command did not end before its deadline.
+    Timeout,
+    /// Another command is exchanging with the device.
+    DeviceBusy,
+    /// The address is not one of the wallet below the gap limit.
+    AddressNotOwned,
+    /// The device is not the one expected, its keys derive from another
+    /// master key.
+    MismatchedDevice,
+    /// No connected device has the master fingerprint.
+    DeviceNotConnected(Fingerprint),
+    /// The command was denied by the rules of the host, with the reason,
+    /// see [`crate::authorization`].
+    PolicyDenied(String),
+    /// The key of the policy with the origin of the device is not the one
+    /// the device derives at its path.
+    KeyMismatch {
+        expected: Box<Xpub>,
+        found: Box<Xpub>,
+    },
+    /// A signature of the device does not verify, for the input at the index
+    /// or for the message, see [`crate::verify`].
+    InvalidDeviceSignature(Option<usize>),
+    /// Failure described by the device, like the reason it rejects a
+    /// policy.
+    Device(String),
 }
 
+#[cfg(feature = "coldcard")]
 impl TryFrom<Command> for coldcard::ColdcardCommand {
     type Error = coldcard::ColdcardError;
     fn try_from(cmd: Command) -> Result<Self, Self::Error> {
@@ -54,10 +259,74 @@ impl TryFrom<Command> for coldcard::ColdcardCommand {
             Command::Unlock { .. } => Ok(Self::StartEncryption),
             Command::GetMasterFingerprint => Ok(Self::GetMasterFingerprint),
             Command::GetXpub { path, .. } => Ok(Self::GetXpub(path)),
+            Command::SignPsbt { psbt, .. } => Ok(Self::SignPsbt(psbt)),
+            Command::SignMessage { path, message } => {
+                let address_format = match purpose(&path) {
+                    Some(84) => coldcard::api::AF_P2WPKH,
+                    Some(49) => coldcard::api::AF_P2WPKH_P2SH,
+                    _ => coldcard::api::AF_CLASSIC,
+                };
+                Ok(Self::SignMessage {
+                    path,
+                    message,
+                    address_format,
+                })
+            }
+            Command::RegisterWallet { .. } => {
+                Err(Self::Error::UnsupportedCommand("register_wallet"))
+            }
+            Command::DisplayAddress {
+                policy: Some(policy),
+                path,
+                ..
+            } if policy.threshold.is_some() => {
+                let (change, index) = change_and_index(&path)
+                    .ok_or(Self::Error::MissingCommandInfo("change and index"))?;
+                let template = policy.descriptor_template.as_str();
+                let address_format = if template.starts_with("sh(wsh(") {
+                    coldcard::api::AF_P2WSH_P2SH
+                } else if template.starts_with("wsh(") {
+                    coldcard::api::AF_P2WSH
+                } else {
+                    coldcard::api::AF_P2SH
+                };
+                let (threshold, script, origins) =
+                    crate::psbt::multisig_origins(&policy, change, index)
+                        .ok_or(Self::Error::MissingCommandInfo("multisig key origins"))?;
+                Ok(Self::ShowMultisigAddress {
+                    threshold: threshold as u8,
+                    origins,
+                    script,
+                    address_format,
+                })
+            }
+            Command::DisplayAddress { path, .. } => {
+                let address_format = match purpose(&path) {
+                    Some(84) => coldcard::api::AF_P2WPKH,
+                    Some(49) => coldcard::api::AF_P2WPKH_P2SH,
+                    Some(86) => coldcard::api::AF_P2TR,
+                    _ => coldcard::api::AF_CLASSIC,
+                };
+                Ok(Self::ShowAddress {
+                    path,
+                    address_format,
+                })
+            }
+            Command::GetSilentPaymentKeys { .. } => {
+                Err(Self::Error::UnsupportedCommand("get_silent_payment_keys"))
+            }
+            Command::GetStatus => Err(Self::Error::UnsupportedCommand("get_status")),
+            Command::ProveAddressOwnership { .. } => {
+                Err(Self::Error::UnsupportedCommand("prove_address_ownership"))
+            }
+            Command::VerifyOwnedAddress { .. } => {
+                Err(Self::Error::UnsupportedCommand("verify_owned_address"))
+            }
         }
     }
 }
 
+#[cfg(feature = "coldcard")]
 impl From<coldcard::ColdcardResponse> for Response {
     fn from(res: coldcard::ColdcardResponse) -> Response {
         match res {
@@ -66,10 +335,16 @@ impl From<coldcard::ColdcardResponse> for Response {
             coldcard::ColdcardResponse::MyPub { encryption_key, .. } => {
                 Response::EncryptionKey(encryption_key)
             }
+            coldcard::ColdcardResponse::SignedPsbt(psbt) => Response::SignedPsbt(psbt),
+            coldcard::ColdcardResponse::MessageSignature { signature, .. } => {
+                Response::MessageSignature(signature)
+            }
+            coldcard::ColdcardResponse::Address(address) => Response::Address(address),
         }
     }
 }
 
+#[cfg(feature = "coldcard")]
 impl From<coldcard::ColdcardTransmit> for Transmit {
     fn from(transmit: coldcard::ColdcardTransmit) -> Transmit {
         Transmit {
@@ -80,6 +355,7 @@ impl From<coldcard::ColdcardTransmit> for Transmit {
     }
 }
 
+#[cfg(feature = "coldcard")]
 impl From<coldcard::ColdcardError> for Error {
     fn from(error: coldcard::ColdcardError) -> Error {
         match error {
@@ -87,33 +363,70 @@ impl From<coldcard::ColdcardError> for Error {
             coldcard::ColdcardError::MissingCommandInfo(e) => Error::MissingCommandInfo(e),
             coldcard::ColdcardError::NoErrorOrResult => Error::NoErrorOrResult,
             coldcard::ColdcardError::Serialization(s) => Error::Serialization(s),
+            coldcard::ColdcardError::Device(msg) => Error::UnexpectedResult(msg.into_bytes()),
+            coldcard::ColdcardError::Refused => Error::UserRefused,
+            coldcard::ColdcardError::UnsupportedCommand(c) => Error::UnsupportedCommand(c),
         }
     }
 }
 
+#[cfg(feature = "coldcard")]
 pub type ColdcardInterpreter<'a> =
     coldcard::ColdcardInterpreter<'a, Command, Transmit, Response, Error>;
 
-impl From<Command> for jade::JadeCommand {
-    fn from(cmd: Command) -> Self {
+#[cfg(feature = "jade")]
+impl TryFrom<Command> for jade::JadeCommand {
+    type Error = jade::JadeError;
+    fn try_from(cmd: Command) -> Result<Self, Self::Error> {
         match cmd {
-            Command::Unlock { .. } => Self::Auth,
-            Command::GetMasterFingerprint => Self::GetMasterFingerprint,
-            Command::GetXpub { path, .. } => Self::GetXpub(path),
+            Command::Unlock { .. } => Ok(Self::Auth),
+            Command::GetMasterFingerprint => Ok(Self::GetMasterFingerprint),
+            Command::GetXpub { path, .. } => Ok(Self::GetXpub(path)),
+            Command::SignPsbt { psbt, .. } => Ok(Self::SignPsbt(psbt)),
+            Command::DisplayAddress { path, .. } => {
+                let variant = match purpose(&path) {
+                    Some(44) => "pkh(k)",
+                    Some(49) => "sh(wpkh(k))",
+                    Some(86) => "tr(k)",
+                    _ => "wpkh(k)",
+                };
+                Ok(Self::GetReceiveAddress {
+                    path,
+                    variant: variant.to_string(),
+                })
+            }
+            Command::RegisterWallet { .. } => {
+                Err(Self::Error::UnsupportedCommand("register_wallet"))
+            }
+            Command::SignMessage { .. } => Err(Self::Error::UnsupportedCommand("sign_message")),
+            Command::GetSilentPaymentKeys { .. } => {
+                Err(Self::Error::UnsupportedCommand("get_silent_payment_keys"))
+            }
+            Command::GetStatus => Err(Self::Error::UnsupportedCommand("get_status")),
+            Command::ProveAddressOwnership { .. } => {
+                Err(Self::Error::UnsupportedCommand("prove_address_ownership"))
+            }
+            Command::VerifyOwnedAddress { .. } => {
+                Err(Self::Error::UnsupportedCommand("verify_owned_address"))
+            }
         }
     }
 }
 
+#[cfg(feature = "jade")]
 impl From<jade::JadeResponse> for Response {
     fn from(res: jade::JadeResponse) -> Response {
         match res {
             jade::JadeResponse::TaskDone => Response::TaskDone,
             jade::JadeResponse::MasterFingerprint(fg) => Response::MasterFingerprint(fg),
             jade::JadeResponse::Xpub(xpub) => Response::Xpub(xpub),
+            jade::JadeResponse::Address(address) => Response::Address(address),
+            jade::JadeResponse::SignedPsbt(psbt) => Response::SignedPsbt(psbt),
         }
     }
 }
 
+#[cfg(feature = "jade")]
 impl From<jade::JadeRecipient> for Recipient {
     fn from(recipient: jade::JadeRecipient) -> Recipient {
         match recipient {
@@ -123,6 +436,7 @@ impl From<jade::JadeRecipient> for Recipient {
     }
 }
 
+#[cfg(feature = "jade")]
 impl From<jade::JadeTransmit> for Transmit {
     fn from(transmit: jade::JadeTransmit) -> Transmit {
         Transmit {
@@ -133,6 +447,7 @@ impl From<jade::JadeTransmit> for Transmit {
     }
 }
 
+#[cfg(feature = "jade")]
 impl From<jade::JadeError> for Error {
     fn from(error: jade::JadeError) -> Error {
         match error {
@@ -142,12 +457,15 @@ impl From<jade::JadeError> for Error {
             jade::JadeError::Serialization(s) => Error::Serialization(s),
             jade::JadeError::UnexpectedResult(msg) => Error::UnexpectedResult(msg.into_bytes()),
             jade::JadeError::HandshakeRefused => Error::AuthenticationRefused,
+            jade::JadeError::UnsupportedCommand(c) => Error::UnsupportedCommand(c),
         }
     }
 }
 
+#[cfg(feature = "jade")]
 pub type JadeInterpreter = jade::JadeInterpreter<Command, Transmit, Response, Error>;
 
+#[cfg(feature = "ledger")]
 impl TryFrom<Command> for ledger::LedgerCommand {
     type Error = ledger::LedgerError;
     fn try_from(cmd: Command) -> Result<Self, Self::Error> {
@@ -158,16 +476,105 @@ impl TryFrom<Command> for ledger::LedgerCommand {
                 .ok_or(ledger::LedgerError::MissingCommandInfo("network")),
             Command::GetMasterFingerprint => Ok(Self::GetMasterFingerprint),
             Command::GetXpub { path, display } => Ok(Self::GetXpub { path, display }),
+            Command::RegisterWallet { policy } => Ok(Self::RegisterWallet(policy)),
+            // The standard single signature psbts are signed with their
+            // default policy, not registered.
+            Command::SignPsbt { psbt, policy, hmac } => Ok(Self::SignPsbt {
+                policy: policy
+                    .or_else(|| crate::psbt::default_policy(&psbt))
+                    .ok_or(ledger::LedgerError::MissingCommandInfo("policy"))?,
+                psbt,
+                hmac,
+            }),
+            Command::SignMessage { path, message } => Ok(Self::SignMessage { path, message }),
+            Command::DisplayAddress { path, policy, hmac } => {
+                let (change, index) = change_and_index(&path)
+                    .ok_or(ledger::LedgerError::MissingCommandInfo("change and index"))?;
+                Ok(Self::GetWalletAddress {
+                    policy: policy.ok_or(ledger::LedgerError::MissingCommandInfo("policy"))?,
+                    hmac,
+                    change,
+                    index,
+                    display: true,
+                })
+            }
+            Command::GetSilentPaymentKeys { .. } => {
+                Err(Self::Error::UnsupportedCommand("get_silent_payment_keys"))
+            }
+            Command::GetStatus => Ok(Self::GetStatus),
+            // The proof of a policy out of the single key and multisig
+            // templates, or in the simple format of a legacy script, cannot
+            // be built.
+            Command::ProveAddressOwnership {
+                message,
+                policy,
+                hmac,
+                change,
+                index,
+                format,
+            } => Ok(Self::ProveAddressOwnership {
+                psbt: Box::new(
+                    bip322::build_psbt(&message, &policy, change, index, format)
+                        .map_err(|_| Self::Error::UnsupportedCommand("prove_address_ownership"))?,
+                ),
+                policy,
+                hmac,
+                format,
+            }),
+            Command::VerifyOwnedAddress {
+                address,
+                policy,
+                hmac,
+                gap_limit,
+            } => {
+                let (change, index) = psbt::find_address_index(
+                    &policy,
+                    &address.assume_checked_ref().script_pubkey(),
+                    gap_limit,
+                )
+                .ok_or(ledger::LedgerError::AddressNotOwned)?;
+                Ok(Self::VerifyOwnedAddress {
+                    address,
+                    policy,
+                    hmac,
+                    change,
+                    index,
+                })
+            }
         }
     }
 }
 
+#[cfg(feature = "ledger")]
 impl From<ledger::LedgerResponse> for Response {
     fn from(res: ledger::LedgerResponse) -> Response {
         match res {
             ledger::LedgerResponse::MasterFingerprint(fg) => Response::MasterFingerprint(fg),
             ledger::LedgerResponse::TaskDone => Response::TaskDone,
+            ledger::LedgerResponse::AppInfo(info) => Response::AppInfo(info),
+            ledger::LedgerResponse::Status(info) => Response::Status(DeviceStatus {
+                connected: true,
+                unlocked: info.is_some(),
+                version: info.as_ref().map(|info| info.version.to_string()),
+                app: info.map(|info| info.name),
+            }),
+            ledger::LedgerResponse::Apps(apps) => Response::Apps(apps),
             ledger::LedgerResponse::Xpub(xpub) => Response::Xpub(xpub),
+            ledger::LedgerResponse::Xpubs(xpubs) => Response::Xpubs(xpubs),
+            ledger::LedgerResponse::Signatures(sigs) => Response::Signatures(sigs),
+            ledger::LedgerResponse::MusigNonces(nonces) => Response::MusigNonces(nonces),
+            ledger::LedgerResponse::MusigPartialSigs(sigs) => Response::MusigPartialSigs(sigs),
+            ledger::LedgerResponse::WalletRegistered { id, hmac } => {
+                Response::WalletRegistered { id, hmac }
+            }
+            ledger::LedgerResponse::Address(address) => Response::Address(address),
+            ledger::LedgerResponse::MessageSignature(sig) => Response::MessageSignature(sig),
+            ledger::LedgerResponse::OwnershipProof(proof) => Response::OwnershipProof(proof),
+            ledger::LedgerResponse::OwnedAddress { change, index } => {
+                Response::OwnedAddress { change, index }
+            }
+            #[cfg(feature = "elements")]
+            ledger::LedgerResponse::MasterBlindingKey(key) => Response::MasterBlindingKey(key),
         }
     }
 }
@@ -182,6 +589,7 @@ impl From<Vec<u8>> for Transmit {
     }
 }
 
+#[cfg(feature = "ledger")]
 impl From<ledger::apdu::ApduCommand> for Transmit {
     fn from(payload: ledger::apdu::ApduCommand) -> Transmit {
         Transmit {
@@ -192,6 +600,7 @@ impl From<ledger::apdu::ApduCommand> for Transmit {
     }
 }
 
+#[cfg(feature = "ledger")]
 impl From<ledger::LedgerError> for Error {
     fn from(error: ledger::LedgerError) -> Error {
         match error {
@@ -202,16 +611,280 @@ impl From<ledger::LedgerError> for Error {
             ledger::LedgerError::Interrupted => Error::Request("Operation interrupted"),
             ledger::LedgerError::UnexpectedResult(data) => Error::UnexpectedResult(data),
             ledger::LedgerError::FailedToOpenApp(_) => Error::AuthenticationRefused,
+            ledger::LedgerError::DeniedByUser => Error::UserRefused,
+            ledger::LedgerError::Cancelled => Error::Cancelled,
+            ledger::LedgerError::Timeout => Error::Timeout,
+            ledger::LedgerError::MismatchedDevice => Error::MismatchedDevice,
+            ledger::LedgerError::DeviceLocked => Error::DeviceLocked,
+            ledger::LedgerError::NetworkMismatch => Error::NetworkMismatch,
+            ledger::LedgerError::UnsupportedSighash(i) => Error::UnsupportedSighash(i),
+            ledger::LedgerError::UnusualPath(w) => Error::UnusualPath(w),
+            ledger::LedgerError::InvalidPolicy(e) => Error::InvalidPolicy(e),
+            ledger::LedgerError::UnsupportedByLegacyApp(c)
+            | ledger::LedgerError::UnsupportedCommand(c) => Error::UnsupportedCommand(c),
+            ledger::LedgerError::AddressNotOwned => Error::AddressNotOwned,
+            ledger::LedgerError::AppNotOpen => Error::Request("Bitcoin app not open"),
+            ledger::LedgerError::AppNotInstalled => Error::Request("Bitcoin app not installed"),
+            ledger::LedgerError::WrongParameters(sw) | ledger::LedgerError::Status(sw) => {
+                Error::UnexpectedResult((sw as u16).to_be_bytes().to_vec())
+            }
+            ledger::LedgerError::Detailed(_, detail) => Error::Device(detail.to_string()),
+            #[cfg(feature = "elements")]
+            ledger::LedgerError::InvalidPset(e) => Error::Serialization(e),
         }
     }
 }
 
+#[cfg(feature = "ledger")]
 pub type LedgerInterpreter = ledger::LedgerInterpreter<Command, Transmit, Response, Error>;
 
-#[cfg(test)]
+#[cfg(feature = "trezor")]
+impl TryFrom<Command> for trezor::TrezorCommand {
+    type Error = trezor::TrezorError;
+    fn try_from(cmd: Command) -> Result<Self, Self::Error> {
+        let script_type = |path: &DerivationPath| match purpose(path) {
+            Some(84) => trezor::proto::SPEND_WITNESS,
+            Some(49) => trezor::proto::SPEND_P2SH_WITNESS,
+            Some(86) => trezor::proto::SPEND_TAPROOT,
+            _ => trezor::proto::SPEND_ADDRESS,
+        };
+        match cmd {
+            Command::Unlock { .. } => Ok(Self::Initialize),
+            Command::GetMasterFingerprint => Ok(Self::GetMasterFingerprint),
+            Command::GetXpub { path, display } => Ok(Self::GetXpub { path, display }),
+            Command::SignPsbt { psbt, .. } => Ok(Self::SignPsbt(psbt)),
+            Command::SignMessage { path, message } => Ok(Self::SignMessage {
+                script_type: script_type(&path),
+                path,
+                message,
+            }),
+            Command::DisplayAddress { path, .. } => Ok(Self::GetAddress {
+                script_type: script_type(&path),
+                path,
+                display: true,
+            }),
+            Command::RegisterWallet { .. } => {
+                Err(Self::Error::UnsupportedCommand("register_wallet"))
+            }
+            Command::GetSilentPaymentKeys { .. } => {
+                Err(Self::Error::UnsupportedCommand("get_silent_payment_keys"))
+            }
+            Command::GetStatus => Err(Self::Error::UnsupportedCommand("get_status")),
+            Command::ProveAddressOwnership { .. } => {
+                Err(Self::Error::UnsupportedCommand("prove_address_ownership"))
+            }
+            Command::VerifyOwnedAddress { .. } => {
+                Err(Self::Error::UnsupportedCommand("verify_owned_address"))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "trezor")]
+impl From<trezor::TrezorResponse> for Response {
+    fn from(res: trezor::TrezorResponse) -> Response {
+        match res {
+            trezor::TrezorResponse::TaskDone => Response::TaskDone,
+            trezor::TrezorResponse::MasterFingerprint(fg) => Response::MasterFingerprint(fg),
+            trezor::TrezorResponse::Xpub(xpub) => Response::Xpub(xpub),
+            trezor::TrezorResponse::Address(address) => Response::Address(address),
+            trezor::TrezorResponse::SignedPsbt(psbt) => Response::SignedPsbt(psbt),
+            trezor::TrezorResponse::MessageSignature { signature, .. } => {
+                Response::MessageSignature(signature)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "trezor")]
+impl From<trezor::TrezorError> for Error {
+    fn from(error: trezor::TrezorError) -> Error {
+        match error {
+            trezor::TrezorError::NoErrorOrResult => Error::NoErrorOrResult,
+            trezor::TrezorError::Decoding(e) => Error::Serialization(e.to_string()),
+            trezor::TrezorError::Serialization(s) => Error::Serialization(s),
+            trezor::TrezorError::UnexpectedMessage(msg_type) => {
+                Error::UnexpectedResult(msg_type.to_be_bytes().to_vec())
+            }
+            trezor::TrezorError::Device(msg) => Error::UnexpectedResult(msg.into_bytes()),
+            trezor::TrezorError::Refused => Error::UserRefused,
+            trezor::TrezorError::UnsupportedInput(_) => Error::Request("Unsupported input"),
+            trezor::TrezorError::UnsupportedSighash(i) => Error::UnsupportedSighash(i),
+            trezor::TrezorError::UnsupportedRequest(_) => Error::Request("Unsupported request"),
+            trezor::TrezorError::MissingPreviousTransaction(_) => {
+                Error::MissingCommandInfo("previous transaction")
+            }
+            trezor::TrezorError::UnsupportedCommand(c) => Error::UnsupportedCommand(c),
+        }
+    }
+}
+
+#[cfg(feature = "specter")]
+impl TryFrom<Command> for specter::SpecterCommand {
+    type Error = specter::SpecterError;
+    fn try_from(cmd: Command) -> Result<Self, Self::Error> {
+        match cmd {
+            Command::Unlock { .. } => Ok(Self::Unlock),
+            Command::GetMasterFingerprint => Ok(Self::GetMasterFingerprint),
+            Command::GetXpub { path, .. } => Ok(Self::GetXpub(path)),
+            Command::SignPsbt { psbt, .. } => Ok(Self::SignPsbt(psbt)),
+            Command::DisplayAddress {
+                path,
+                policy: Some(policy),
+                ..
+            } if policy.threshold.is_some() => {
+                let (change, index) = change_and_index(&path)
+                    .ok_or(Self::Error::MissingCommandInfo("change and index"))?;
+                let descriptor = policy
+                    .get_descriptor(change)
+                    .map_err(|e| Self::Error::Serialization(format!("{:?}", e)))?;
+                Ok(Self::DisplayDescriptorAddress(
+                    descriptor.replace("/*", &format!("/{}", index)),
+                ))
+            }
+            Command::DisplayAddress { path, .. } => {
+                let script_type = match purpose(&path) {
+                    Some(44) => "pkh",
+                    Some(49) => "sh-wpkh",
+                    Some(86) => return Err(Self::Error::UnsupportedCommand("display_address")),
+                    _ => "wpkh",
+                };
+                Ok(Self::DisplayAddress { script_type, path })
+            }
+            Command::RegisterWallet { .. } => {
+                Err(Self::Error::UnsupportedCommand("register_wallet"))
+            }
+            Command::SignMessage { .. } => Err(Self::Error::UnsupportedCommand("sign_message")),
+            Command::GetSilentPaymentKeys { .. } => {
+                Err(Self::Error::UnsupportedCommand("get_silent_payment_keys"))
+            }
+            Command::GetStatus => Err(Self::Error::UnsupportedCommand("get_status")),
+            Command::ProveAddressOwnership { .. } => {
+                Err(Self::Error::UnsupportedCommand("prove_address_ownership"))
+            }
+            Command::VerifyOwnedAddress { .. } => {
+                Err(Self::Error::UnsupportedCommand("verify_owned_address"))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "specter")]
+impl From<specter::SpecterResponse> for Response {
+    fn from(res: specter::SpecterResponse) -> Response {
+        match res {
+            specter::SpecterResponse::TaskDone => Response::TaskDone,
+            specter::SpecterResponse::MasterFingerprint(fg) => Response::MasterFingerprint(fg),
+            specter::SpecterResponse::Xpub(xpub) => Response::Xpub(xpub),
+            specter::SpecterResponse::Address(address) => Response::Address(address),
+            specter::SpecterResponse::SignedPsbt(psbt) => Response::SignedPsbt(psbt),
+        }
+    }
+}
+
+#[cfg(feature = "specter")]
+impl From<specter::SpecterTransmit> for Transmit {
+    fn from(transmit: specter::SpecterTransmit) -> Transmit {
+        Transmit {
+            recipient: Recipient::Device,
+            payload: transmit.payload,
+            encrypted: false,
+        }
+    }
+}
+
+#[cfg(feature = "specter")]
+impl From<specter::SpecterError> for Error {
+    fn from(error: specter::SpecterError) -> Error {
+        match error {
+            specter::SpecterError::NoErrorOrResult => Error::NoErrorOrResult,
+            specter::SpecterError::MissingCommandInfo(e) => Error::MissingCommandInfo(e),
+            specter::SpecterError::Serialization(s) => Error::Serialization(s),
+            specter::SpecterError::Device(msg) => Error::UnexpectedResult(msg.into_bytes()),
+            specter::SpecterError::Refused => Error::UserRefused,
+            specter::SpecterError::UnsupportedCommand(c) => Error::UnsupportedCommand(c),
+        }
+    }
+}
+
+#[cfg(feature = "specter")]
+pub type SpecterInterpreter = specter::SpecterInterpreter<Command, Transmit, Response, Error>;
+
+#[cfg(feature = "airgap")]
+impl TryFrom<Command> for airgap::AirgapCommand {
+    type Error = airgap::AirgapError;
+    fn try_from(cmd: Command) -> Result<Self, Self::Error> {
+        match cmd {
+            Command::GetMasterFingerprint => Ok(Self::GetMasterFingerprint),
+            Command::GetXpub { path, .. } => Ok(Self::GetXpub(path)),
+            Command::SignPsbt { psbt, .. } => Ok(Self::SignPsbt(psbt)),
+            Command::Unlock { .. } => Err(Self::Error::UnsupportedCommand("unlock")),
+            Command::RegisterWallet { .. } => {
+                Err(Self::Error::UnsupportedCommand("register_wallet"))
+            }
+            Command::SignMessage { .. } => Err(Self::Error::UnsupportedCommand("sign_message")),
+            Command::DisplayAddress { .. } => {
+                Err(Self::Error::UnsupportedCommand("display_address"))
+            }
+            Command::GetSilentPaymentKeys { .. } => {
+                Err(Self::Error::UnsupportedCommand("get_silent_payment_keys"))
+            }
+            Command::GetStatus => Err(Self::Error::UnsupportedCommand("get_status")),
+            Command::ProveAddressOwnership { .. } => {
+                Err(Self::Error::UnsupportedCommand("prove_address_ownership"))
+            }
+            Command::VerifyOwnedAddress { .. } => {
+                Err(Self::Error::UnsupportedCommand("verify_owned_address"))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "airgap")]
+impl From<airgap::AirgapResponse> for Response {
+    fn from(res: airgap::AirgapResponse) -> Response {
+        match res {
+            airgap::AirgapResponse::MasterFingerprint(fg) => Response::MasterFingerprint(fg),
+            airgap::AirgapResponse::Xpub(xpub) => Response::Xpub(xpub),
+            airgap::AirgapResponse::Account(keys) => Response::Xpubs(
+                keys.into_iter()
+                    .map(|(_, path, xpub)| (path, xpub))
+                    .collect(),
+            ),
+            airgap::AirgapResponse::SignedPsbt(psbt) => Response::SignedPsbt(psbt),
+        }
+    }
+}
+
+#[cfg(feature = "airgap")]
+impl From<airgap::AirgapError> for Error {
+    fn from(error: airgap::AirgapError) -> Error {
+        match error {
+            airgap::AirgapError::NoErrorOrResult => Error::NoErrorOrResult,
+            airgap::AirgapError::InvalidUr
+            | airgap::AirgapError::InvalidBytewords
+            | airgap::AirgapError::InvalidChecksum
+            | airgap::AirgapError::InvalidPart => Error::Serialization(format!("{:?}", error)),
+            airgap::AirgapError::Cbor => Error::Serialization("cbor".to_string()),
+            airgap::AirgapError::Serialization(s) => Error::Serialization(s),
+            airgap::AirgapError::UnexpectedType(t) => Error::UnexpectedResult(t.into_bytes()),
+            airgap::AirgapError::KeyNotFound(_) => Error::Request("Key not exported"),
+            airgap::AirgapError::UnsupportedCommand(c) => Error::UnsupportedCommand(c),
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "coldcard",
+    feature = "jade",
+    feature = "ledger",
+    feature = "trezor"
+))]
 mod tests {
     use super::*;
     use crate::Interpreter;
+    use std::str::FromStr;
 
     #[test]
     fn common_interpreter_is_satisfied() {
@@ -230,4 +903,58 @@ mod tests {
         ];
         assert_eq!(interpreters.len(), 2);
     }
+
+    #[test]
+    fn test_command_conversions() {
+        let path = DerivationPath::from_str("m/84'/1'/0'/1/7").unwrap();
+        let display = || Command::DisplayAddress {
+            path: path.clone(),
+            policy: None,
+            hmac: None,
+        };
+
+        match jade::JadeCommand::try_from(display()).unwrap() {
+            jade::JadeCommand::GetReceiveAddress { variant, .. } => assert_eq!(variant, "wpkh(k)"),
+            _ => panic!("expected a receive address"),
+        }
+        match trezor::TrezorCommand::try_from(display()).unwrap() {
+            trezor::TrezorCommand::GetAddress { script_type, .. } => {
+                assert_eq!(script_type, trezor::proto::SPEND_WITNESS)
+            }
+            _ => panic!("expected an address"),
+        }
+        assert!(matches!(
+            ledger::LedgerCommand::try_from(display()),
+            Err(ledger::LedgerError::MissingCommandInfo("policy"))
+        ));
+        assert_eq!(change_and_index(&path), Some((true, 7)));
+        assert_eq!(
+            change_and_index(&DerivationPath::from_str("m/84'/1'/0'").unwrap()),
+            None
+        );
+        match coldcard::ColdcardCommand::try_from(display()).unwrap() {
+            coldcard::ColdcardCommand::ShowAddress { address_format, .. } => {
+                assert_eq!(address_format, coldcard::api::AF_P2WPKH)
+            }
+            _ => panic!("expected an address"),
+        }
+
+        let sign_message = Command::SignMessage {
+            path: path.clone(),
+            message: b"hello".to_vec(),
+        };
+        match coldcard::ColdcardCommand::try_from(sign_message).unwrap() {
+            coldcard::ColdcardCommand::SignMessage { address_format, .. } => {
+                assert_eq!(address_format, coldcard::api::AF_P2WPKH)
+            }
+            _ => panic!("expected a message signature"),
+        }
+        assert!(matches!(
+            jade::JadeCommand::try_from(Command::SignMessage {
+                path,
+                message: Vec::new(),
+            }),
+            Err(jade::JadeError::UnsupportedCommand("sign_message"))
+        ));
+    }
 }
@@ -0,0 +1,218 @@
+//! Checks of the contract of [`Interpreter`], for the authors of new backends
+//! to validate their state machines without device: the replies of the
+//! device are scripted and the checks panic with the step that failed.
+//!
+//! The contract:
+//! - `start` returns the first transmit, each reply but the last one is
+//!   answered with the next transmit, the last one with none.
+//! - `end` fails without start and before the last reply.
+//! - The same script gives the same transmits.
+//! - The errors of the device fail the exchange or `end`.
+//! - After `cancel`, the exchanges are ignored, `end` fails and the
+//!   interpreter can be started again.
+
+use crate::{prelude::*, Interpreter};
+
+/// Command of the backend and the replies of the device to its transmits, in
+/// order.
+pub struct Script<C> {
+    pub name: &'static str,
+    pub command: fn() -> C,
+    pub replies: Vec<Vec<u8>>,
+}
+
+/// Interpreter under check, `encode` returns the bytes of a transmit to
+/// compare the runs.
+pub struct Conformance<I: Interpreter> {
+    new: fn() -> I,
+    encode: fn(&I::Transmit) -> Vec<u8>,
+}
+
+impl<I> Conformance<I>
+where
+    I: Interpreter,
+    I::Error: core::fmt::Debug,
+{
+    pub fn new(new: fn() -> I, encode: fn(&I::Transmit) -> Vec<u8>) -> Self {
+        Self { new, encode }
+    }
+
+    /// Starts the command of the script and feeds its replies, returns the
+    /// transmits and the result of `end`.
+    fn feed(
+        &self,
+        interpreter: &mut I,
+        script: &Script<I::Command>,
+    ) -> Result<Vec<Vec<u8>>, I::Error> {
+        let mut transmits = vec![(self.encode)(&interpreter.start((script.command)())?)];
+        for (i, reply) in script.replies.iter().enumerate() {
+            while interpreter.poll_event().is_some() {}
+            while let Some(transmit) = interpreter.poll_transmit() {
+                transmits.push((self.encode)(&transmit));
+            }
+            let transmit = interpreter.exchange(reply.clone())?;
+            let last = i + 1 == script.replies.len();
+            match transmit {
+                Some(transmit) if !last => transmits.push((self.encode)(&transmit)),
+                None if last => {}
+                Some(_) => panic!("{}: transmit after the last reply", script.name),
+                None => panic!("{}: no transmit after reply {}", script.name, i),
+            }
+        }
+        Ok(transmits)
+    }
+
+    /// Runs the script, panics if it fails or breaks the contract of the
+    /// transmits. Returns the response.
+    pub fn assert_run(&self, script: &Script<I::Command>) -> I::Response {
+        let mut interpreter = (self.new)();
+        self.feed(&mut interpreter, script)
+            .unwrap_or_else(|e| panic!("{}: {:?}", script.name, e));
+        interpreter
+            .end()
+            .unwrap_or_else(|e| panic!("{}: end: {:?}", script.name, e))
+    }
+
+    /// Checks that `end` fails without start and before the last reply, and
+    /// that two runs of the script send the same transmits. Returns the
+    /// response.
+    pub fn assert_transitions(&self, script: &Script<I::Command>) -> I::Response {
+        assert!(
+            (self.new)().end().is_err(),
+            "{}: end without start",
+            script.name
+        );
+        if !script.replies.is_empty() {
+            let mut interpreter = (self.new)();
+            interpreter
+                .start((script.command)())
+                .unwrap_or_else(|e| panic!("{}: start: {:?}", script.name, e));
+            assert!(
+                interpreter.end().is_err(),
+                "{}: end before the replies",
+                script.name
+            );
+        }
+        let run = || {
+            let mut interpreter = (self.new)();
+            self.feed(&mut interpreter, script)
+                .unwrap_or_else(|e| panic!("{}: {:?}", script.name, e))
+        };
+        assert_eq!(run(), run(), "{}: transmits differ", script.name);
+        self.assert_run(script)
+    }
+
+    /// Runs the script of a failing command, panics if it succeeds or if the
+    /// error does not match.
+    pub fn assert_error(&self, script: &Script<I::Command>, expected: impl Fn(&I::Error) -> bool) {
+        let mut interpreter = (self.new)();
+        let error = match self.feed(&mut interpreter, script) {
+            Ok(_) => match interpreter.end() {
+                Ok(_) => panic!("{}: no error", script.name),
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+        assert!(
+            expected(&error),
+            "{}: unexpected error {:?}",
+            script.name,
+            error
+        );
+    }
+
+    /// Cancels the command after start, panics if a reply is not ignored,
+    /// if `end` succeeds or if the interpreter cannot run the script again.
+    pub fn assert_cancel(&self, script: &Script<I::Command>) {
+        let mut interpreter = (self.new)();
+        interpreter
+            .start((script.command)())
+            .unwrap_or_else(|e| panic!("{}: start: {:?}", script.name, e));
+        interpreter.cancel();
+        if let Some(reply) = script.replies.first() {
+            assert!(
+                matches!(interpreter.exchange(reply.clone()), Ok(None)),
+                "{}: reply after cancel",
+                script.name
+            );
+        }
+        assert!(
+            interpreter.end().is_err(),
+            "{}: end after cancel",
+            script.name
+        );
+
+        let mut interpreter = (self.new)();
+        interpreter
+            .start((script.command)())
+            .unwrap_or_else(|e| panic!("{}: start: {:?}", script.name, e));
+        interpreter.cancel();
+        self.feed(&mut interpreter, script)
+            .unwrap_or_else(|e| panic!("{}: restart: {:?}", script.name, e));
+        if let Err(e) = interpreter.end() {
+            panic!("{}: end of the restart: {:?}", script.name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::{
+        apdu::ApduCommand,
+        testvectors::{self, SEED},
+        LedgerCommand, LedgerError, LedgerInterpreter, LedgerResponse,
+    };
+    use bitcoin::hex::FromHex;
+
+    type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+    fn ledger() -> Conformance<Ledger> {
+        Conformance::new(Ledger::default, ApduCommand::encode)
+    }
+
+    fn script(name: &'static str) -> Script<LedgerCommand> {
+        let vector = testvectors::vector(name).unwrap();
+        Script {
+            name,
+            command: vector.command,
+            replies: vector.exchanges.iter().map(|e| e.response()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_ledger_conformance() {
+        for name in ["get_master_fingerprint", "get_xpub", "register_wallet"] {
+            ledger().assert_transitions(&script(name));
+            ledger().assert_cancel(&script(name));
+        }
+        let denied = Script {
+            name: "denied",
+            command: || LedgerCommand::GetMasterFingerprint,
+            replies: vec![Vec::from_hex("6985").unwrap()],
+        };
+        ledger().assert_error(&denied, |e| matches!(e, LedgerError::DeniedByUser));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_mock_conformance() {
+        use crate::{
+            common::{Command, Transmit},
+            mock::MockSigner,
+        };
+        use bitcoin::Network;
+
+        let mock = Conformance::new(
+            || MockSigner::new(&SEED, Network::Testnet).unwrap(),
+            |t: &Transmit| t.payload.clone(),
+        );
+        let script = Script {
+            name: "get_master_fingerprint",
+            command: || Command::GetMasterFingerprint,
+            replies: vec![Vec::new()],
+        };
+        mock.assert_transitions(&script);
+        mock.assert_cancel(&script);
+    }
+}
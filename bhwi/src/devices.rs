@@ -0,0 +1,235 @@
+//! USB identifiers of the known devices, for the transports to select the
+//! devices by their vendor and product ids instead of their product name.
+
+use bitcoin::Network;
+
+use crate::prelude::*;
+
+/// Vendor id of the Ledger devices, the model is the high byte of the
+/// product id.
+pub const LEDGER_VID: u16 = 0x2c97;
+pub const COLDCARD_VID: u16 = 0xd13e;
+pub const BITBOX02_VID: u16 = 0x03eb;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DeviceKind {
+    LedgerNanoS,
+    LedgerNanoSPlus,
+    LedgerNanoX,
+    LedgerStax,
+    LedgerFlex,
+    BitBox02,
+    Trezor,
+    Coldcard,
+    Jade,
+    Specter,
+}
+
+impl DeviceKind {
+    pub fn is_ledger(&self) -> bool {
+        matches!(
+            self,
+            DeviceKind::LedgerNanoS
+                | DeviceKind::LedgerNanoSPlus
+                | DeviceKind::LedgerNanoX
+                | DeviceKind::LedgerStax
+                | DeviceKind::LedgerFlex
+        )
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DeviceKind::LedgerNanoS => "Ledger Nano S",
+            DeviceKind::LedgerNanoSPlus => "Ledger Nano S Plus",
+            DeviceKind::LedgerNanoX => "Ledger Nano X",
+            DeviceKind::LedgerStax => "Ledger Stax",
+            DeviceKind::LedgerFlex => "Ledger Flex",
+            DeviceKind::BitBox02 => "BitBox02",
+            DeviceKind::Trezor => "Trezor",
+            DeviceKind::Coldcard => "Coldcard",
+            DeviceKind::Jade => "Jade",
+            DeviceKind::Specter => "Specter DIY",
+        }
+    }
+}
+
+impl core::fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Pacing of the frames written to a device, for the hubs and the WebHID
+/// stacks dropping the frames pushed back to back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    /// Minimal delay between two frames of a command, in milliseconds.
+    pub min_interval_ms: u32,
+    /// Frames written back to back before the delay.
+    pub max_in_flight: usize,
+}
+
+impl Pacing {
+    /// The frames are written back to back.
+    pub const NONE: Pacing = Pacing {
+        min_interval_ms: 0,
+        max_in_flight: usize::MAX,
+    };
+
+    /// Returns the default pacing of the device: the Nano S gets its frames
+    /// one by one.
+    pub fn for_device(kind: DeviceKind) -> Self {
+        match kind {
+            DeviceKind::LedgerNanoS => Pacing {
+                min_interval_ms: 2,
+                max_in_flight: 1,
+            },
+            _ => Pacing::NONE,
+        }
+    }
+
+    /// Returns the delay before writing the frame at the index of the
+    /// command, in milliseconds.
+    pub fn delay_ms(&self, index: usize) -> u32 {
+        if index > 0 && index % self.max_in_flight.max(1) == 0 {
+            self.min_interval_ms
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing::NONE
+    }
+}
+
+/// Networks known by bitcoin, the devices of the networks of test usually
+/// support all of them.
+pub const NETWORKS: [Network; 5] = [
+    Network::Bitcoin,
+    Network::Testnet,
+    Network::Testnet4,
+    Network::Signet,
+    Network::Regtest,
+];
+
+/// Features of a backend, for the UIs to disable the actions the device does
+/// not support instead of discovering them by the errors of a flow. Each
+/// backend returns its own, the Ledger ones depend on the app open.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// The taproot outputs are spent and displayed.
+    pub taproot: bool,
+    /// The psbts are signed in the two rounds of MuSig2.
+    pub musig2: bool,
+    pub message_signing: bool,
+    /// The wallet policies are registered on the device before signing.
+    pub policy_registration: bool,
+    /// Maximal number of inputs of a psbt, None without known limit.
+    pub max_inputs: Option<usize>,
+    pub networks: Vec<Network>,
+}
+
+/// Vendor and product ids of the devices other than the Ledger ones. The
+/// Jade is connected through the USB to serial bridge of its model, the
+/// Specter DIY through the serial port of its MicroPython firmware.
+pub const USB_IDS: &[(u16, u16, DeviceKind)] = &[
+    (BITBOX02_VID, 0x2403, DeviceKind::BitBox02),
+    // Trezor One.
+    (0x534c, 0x0001, DeviceKind::Trezor),
+    // Trezor Model T and Safe, firmware and bootloader.
+    (0x1209, 0x53c1, DeviceKind::Trezor),
+    (0x1209, 0x53c0, DeviceKind::Trezor),
+    (COLDCARD_VID, 0xcc10, DeviceKind::Coldcard),
+    (0x10c4, 0xea60, DeviceKind::Jade),
+    (0x1a86, 0x55d4, DeviceKind::Jade),
+    (0x0403, 0x6001, DeviceKind::Jade),
+    (0x1a86, 0x7523, DeviceKind::Jade),
+    (0x303a, 0x4001, DeviceKind::Jade),
+    (0x303a, 0x1001, DeviceKind::Jade),
+    (0xf055, 0x013d, DeviceKind::Specter),
+];
+
+/// Returns the kind of the device with the USB ids, if it is a known one.
+pub fn identify(vendor_id: u16, product_id: u16) -> Option<DeviceKind> {
+    if vendor_id == LEDGER_VID {
+        // The legacy firmwares use the model as product id.
+        return match product_id {
+            0x0001 | 0x1000..=0x10ff => Some(DeviceKind::LedgerNanoS),
+            0x0004 | 0x4000..=0x40ff => Some(DeviceKind::LedgerNanoX),
+            0x0005 | 0x5000..=0x50ff => Some(DeviceKind::LedgerNanoSPlus),
+            0x0006 | 0x6000..=0x60ff => Some(DeviceKind::LedgerStax),
+            0x0007 | 0x7000..=0x70ff => Some(DeviceKind::LedgerFlex),
+            _ => None,
+        };
+    }
+    USB_IDS
+        .iter()
+        .find(|(vid, pid, _)| *vid == vendor_id && *pid == product_id)
+        .map(|(_, _, kind)| *kind)
+}
+
+/// Returns the vendor ids of the device kind, for the transports filtering
+/// the devices before identifying them.
+pub fn vendor_ids(kind: DeviceKind) -> Vec<u16> {
+    let mut ids: Vec<u16> = Vec::new();
+    if kind.is_ledger() {
+        ids.push(LEDGER_VID);
+    }
+    for (vid, _, k) in USB_IDS {
+        if *k == kind && !ids.contains(vid) {
+            ids.push(*vid);
+        }
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacing() {
+        let pacing = Pacing {
+            min_interval_ms: 5,
+            max_in_flight: 2,
+        };
+        let delays: Vec<u32> = (0..5).map(|i| pacing.delay_ms(i)).collect();
+        assert_eq!(delays, [0, 0, 5, 0, 5]);
+        assert_eq!(Pacing::NONE.delay_ms(1000), 0);
+        assert_eq!(Pacing::for_device(DeviceKind::LedgerNanoS).delay_ms(1), 2);
+        assert_eq!(Pacing::for_device(DeviceKind::Coldcard), Pacing::default());
+    }
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(
+            identify(LEDGER_VID, 0x5011),
+            Some(DeviceKind::LedgerNanoSPlus)
+        );
+        assert_eq!(identify(LEDGER_VID, 0x4015), Some(DeviceKind::LedgerNanoX));
+        assert_eq!(identify(LEDGER_VID, 0x0001), Some(DeviceKind::LedgerNanoS));
+        assert_eq!(identify(LEDGER_VID, 0x6011), Some(DeviceKind::LedgerStax));
+        assert_eq!(identify(LEDGER_VID, 0x7011), Some(DeviceKind::LedgerFlex));
+        assert_eq!(identify(LEDGER_VID, 0x9000), None);
+        assert_eq!(identify(COLDCARD_VID, 0xcc10), Some(DeviceKind::Coldcard));
+        assert_eq!(identify(0x1a86, 0x55d4), Some(DeviceKind::Jade));
+        assert_eq!(identify(0x534c, 0x0001), Some(DeviceKind::Trezor));
+        assert_eq!(identify(0xf055, 0x013d), Some(DeviceKind::Specter));
+        assert_eq!(identify(BITBOX02_VID, 0x2403), Some(DeviceKind::BitBox02));
+        assert_eq!(identify(0x1234, 0x5678), None);
+        assert!(DeviceKind::LedgerStax.is_ledger());
+        assert!(!DeviceKind::Jade.is_ledger());
+
+        assert_eq!(
+            vendor_ids(DeviceKind::Jade),
+            vec![0x10c4, 0x1a86, 0x0403, 0x303a]
+        );
+        assert_eq!(vendor_ids(DeviceKind::LedgerNanoX), vec![LEDGER_VID]);
+    }
+}
@@ -167,6 +167,13 @@ pub struct DescriptorAddressParams<'a> {
     pub descriptor_name: &'a str,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetReceiveAddressParams<'a> {
+    pub network: &'a str,
+    pub path: Vec<u32>,
+    pub variant: &'a str,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignPsbtParams<'a> {
     pub network: &'a str,
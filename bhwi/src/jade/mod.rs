@@ -1,18 +1,33 @@
 pub mod api;
 
 use bitcoin::{
+    address::NetworkUnchecked,
     bip32::{DerivationPath, Fingerprint, Xpub},
-    Network,
+    Address, Network, Psbt,
 };
 use serde::{de::DeserializeOwned, Serialize};
+use std::convert::Infallible;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::devices::{Capabilities, NETWORKS};
 use crate::Interpreter;
 
 pub const JADE_NETWORK_MAINNET: &str = "mainnet";
 pub const JADE_NETWORK_TESTNET: &str = "testnet";
 
+/// Returns the capabilities of the Jade, which signs no message.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        taproot: true,
+        musig2: false,
+        message_signing: false,
+        policy_registration: false,
+        max_inputs: None,
+        networks: NETWORKS.to_vec(),
+    }
+}
+
 #[derive(Debug)]
 pub enum JadeError {
     NoErrorOrResult,
@@ -21,18 +36,35 @@ pub enum JadeError {
     Serialization(String),
     UnexpectedResult(String),
     HandshakeRefused,
+    /// The device does not support the command.
+    UnsupportedCommand(&'static str),
+}
+
+impl From<Infallible> for JadeError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
 }
 
 pub enum JadeCommand {
     Auth,
     GetMasterFingerprint,
     GetXpub(DerivationPath),
+    /// Single signature address of the key at the path, the variant is
+    /// one of "pkh(k)", "wpkh(k)", "sh(wpkh(k))" and "tr(k)".
+    GetReceiveAddress {
+        path: DerivationPath,
+        variant: String,
+    },
+    SignPsbt(Box<Psbt>),
 }
 
 pub enum JadeResponse {
     TaskDone,
     MasterFingerprint(Fingerprint),
     Xpub(Xpub),
+    Address(Address<NetworkUnchecked>),
+    SignedPsbt(Box<Psbt>),
 }
 
 pub enum JadeRecipient {
@@ -50,6 +82,12 @@ enum State {
     Running(JadeCommand),
     WaitingPinServer,
     WaitingFinalHandshake,
+    /// The signed psbt is received in several parts, the next ones are
+    /// requested with the id of the sign_psbt request.
+    ReceivingPsbt {
+        id: String,
+        data: Vec<u8>,
+    },
 }
 
 pub struct JadeInterpreter<C, T, R, E> {
@@ -93,13 +131,17 @@ where
     T: From<JadeTransmit>,
     E: From<JadeError>,
 {
-    let id = generate_request_id();
-    let payload = serde_cbor::to_vec(&api::Request {
-        id: &id.to_string(),
-        method,
-        params,
-    })
-    .map_err(|_| JadeError::Serialization("failed to serialize".to_string()))?;
+    request_with_id(&generate_request_id().to_string(), method, params)
+}
+
+fn request_with_id<S, T, E>(id: &str, method: &str, params: Option<S>) -> Result<T, E>
+where
+    S: Serialize + Unpin,
+    T: From<JadeTransmit>,
+    E: From<JadeError>,
+{
+    let payload = serde_cbor::to_vec(&api::Request { id, method, params })
+        .map_err(|_| JadeError::Serialization("failed to serialize".to_string()))?;
 
     Ok(JadeTransmit {
         payload,
@@ -114,7 +156,8 @@ fn from_response<D: DeserializeOwned>(buffer: &[u8]) -> Result<api::Response<D>,
 
 impl<C, T, R, E> Interpreter for JadeInterpreter<C, T, R, E>
 where
-    C: Into<JadeCommand>,
+    C: TryInto<JadeCommand>,
+    C::Error: Into<JadeError>,
     T: From<JadeTransmit>,
     R: From<JadeResponse>,
     E: From<JadeError>,
@@ -125,7 +168,7 @@ where
     type Error = E;
 
     fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
-        let command: JadeCommand = command.into();
+        let command: JadeCommand = command.try_into().map_err(Into::into)?;
         let req = match &command {
             JadeCommand::Auth => request(
                 "auth_user",
@@ -148,13 +191,37 @@ where
                     path: path.to_u32_vec(),
                 }),
             ),
+            JadeCommand::GetReceiveAddress { path, variant } => request(
+                "get_receive_address",
+                Some(api::GetReceiveAddressParams {
+                    network: self.network,
+                    path: path.to_u32_vec(),
+                    variant,
+                }),
+            ),
+            JadeCommand::SignPsbt(psbt) => {
+                let id = generate_request_id().to_string();
+                let req = request_with_id(
+                    &id,
+                    "sign_psbt",
+                    Some(api::SignPsbtParams {
+                        network: self.network,
+                        psbt: Psbt::serialize(psbt),
+                    }),
+                );
+                self.state = State::ReceivingPsbt {
+                    id,
+                    data: Vec::new(),
+                };
+                return req;
+            }
         };
 
         self.state = State::Running(command);
         req
     }
     fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
-        match self.state {
+        match &mut self.state {
             State::New => Ok(None),
             State::Running(JadeCommand::Auth) => {
                 let res: api::AuthUserResponse = from_response(&data)?.into_result()?;
@@ -211,6 +278,39 @@ where
                 self.response = Some(JadeResponse::Xpub(xpub));
                 Ok(None)
             }
+            State::Running(JadeCommand::GetReceiveAddress { .. }) => {
+                let s: String = from_response(&data)?.into_result()?;
+                let address =
+                    Address::from_str(&s).map_err(|e| JadeError::Serialization(e.to_string()))?;
+                self.response = Some(JadeResponse::Address(address));
+                Ok(None)
+            }
+            State::Running(JadeCommand::SignPsbt(..)) => Ok(None),
+            State::ReceivingPsbt { id, data: psbt } => {
+                let res: api::ResponseBytes =
+                    serde_cbor::from_slice(&data).map_err(|_| JadeError::Cbor)?;
+                if let Some(e) = res.error {
+                    return Err(JadeError::Rpc(e).into());
+                }
+                psbt.extend(res.result.ok_or(JadeError::NoErrorOrResult)?);
+                match (res.seqnum, res.seqlen) {
+                    (Some(seqnum), Some(seqlen)) if seqnum + 1 < seqlen => Ok(Some(request(
+                        "get_extended_data",
+                        Some(api::GetExtendedDataParams {
+                            origid: id,
+                            orig: "sign_psbt",
+                            seqnum: seqnum + 1,
+                            seqlen,
+                        }),
+                    )?)),
+                    _ => {
+                        let psbt = Psbt::deserialize(psbt)
+                            .map_err(|e| JadeError::Serialization(e.to_string()))?;
+                        self.response = Some(JadeResponse::SignedPsbt(Box::new(psbt)));
+                        Ok(None)
+                    }
+                }
+            }
         }
     }
     fn end(self) -> Result<Self::Response, Self::Error> {
@@ -219,3 +319,122 @@ where
             .ok_or_else(|| JadeError::NoErrorOrResult.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, transaction::Version, Amount, ScriptBuf, Transaction, TxOut,
+    };
+    use serde_cbor::Value;
+
+    type Jade = JadeInterpreter<JadeCommand, JadeTransmit, JadeResponse, JadeError>;
+
+    fn field(payload: &[u8], name: &str) -> Value {
+        let Value::Map(map) = serde_cbor::from_slice(payload).unwrap() else {
+            panic!("expected a map");
+        };
+        map[&Value::Text(name.to_string())].clone()
+    }
+
+    fn bytes_response(id: Value, part: &[u8], seqnum: u32, seqlen: u32) -> Vec<u8> {
+        let Value::Text(id) = id else {
+            panic!("expected a text id");
+        };
+        serde_cbor::to_vec(&api::ResponseBytes {
+            id,
+            seqnum: Some(seqnum),
+            seqlen: Some(seqlen),
+            result: Some(part.to_vec()),
+            error: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sign_psbt_in_several_parts() {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let signed = psbt.serialize();
+
+        let mut jade = Jade::default().with_network(Network::Testnet);
+        let transmit = jade.start(JadeCommand::SignPsbt(Box::new(psbt.clone())));
+        let transmit = transmit.unwrap();
+        assert_eq!(
+            field(&transmit.payload, "method"),
+            Value::Text("sign_psbt".to_string())
+        );
+        let id = field(&transmit.payload, "id");
+
+        let (first, second) = signed.split_at(10);
+        let transmit = jade
+            .exchange(bytes_response(id.clone(), first, 0, 2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            field(&transmit.payload, "method"),
+            Value::Text("get_extended_data".to_string())
+        );
+        let Value::Map(params) = field(&transmit.payload, "params") else {
+            panic!("expected params");
+        };
+        assert_eq!(params[&Value::Text("origid".to_string())], id);
+        assert_eq!(
+            params[&Value::Text("seqnum".to_string())],
+            Value::Integer(1)
+        );
+
+        let next_id = field(&transmit.payload, "id");
+        assert!(jade
+            .exchange(bytes_response(next_id, second, 1, 2))
+            .unwrap()
+            .is_none());
+        match jade.end().unwrap() {
+            JadeResponse::SignedPsbt(res) => assert_eq!(*res, psbt),
+            _ => panic!("expected a signed psbt"),
+        }
+    }
+
+    #[test]
+    fn test_get_receive_address() {
+        let mut jade = Jade::default().with_network(Network::Testnet);
+        let transmit = jade
+            .start(JadeCommand::GetReceiveAddress {
+                path: "m/84'/1'/0'/0/0".parse().unwrap(),
+                variant: "wpkh(k)".to_string(),
+            })
+            .unwrap();
+        let Value::Map(params) = field(&transmit.payload, "params") else {
+            panic!("expected params");
+        };
+        assert_eq!(
+            params[&Value::Text("variant".to_string())],
+            Value::Text("wpkh(k)".to_string())
+        );
+
+        let address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let res = serde_cbor::to_vec(&api::Response {
+            id: "1".to_string(),
+            seqlen: None,
+            seqnum: None,
+            result: Some(address.to_string()),
+            error: None,
+        })
+        .unwrap();
+        assert!(jade.exchange(res).unwrap().is_none());
+        match jade.end().unwrap() {
+            JadeResponse::Address(addr) => {
+                assert_eq!(addr.assume_checked().to_string(), address)
+            }
+            _ => panic!("expected an address"),
+        }
+    }
+}
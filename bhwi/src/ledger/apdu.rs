@@ -1,6 +1,13 @@
 use core::convert::TryFrom;
 use core::fmt::Debug;
 
+use bitcoin::{
+    bip32::DerivationPath,
+    consensus::encode::{self, VarInt},
+};
+
+use crate::prelude::*;
+
 // p2 encodes the protocol version implemented
 pub const CURRENT_PROTOCOL_VERSION: u8 = 1;
 
@@ -8,6 +15,8 @@ pub const CURRENT_PROTOCOL_VERSION: u8 = 1;
 #[repr(u8)]
 pub enum Cla {
     Default = 0xB0,
+    /// Class of the dashboard, and of the legacy Bitcoin app before 2.0.
+    Dashboard = 0xE0,
     Bitcoin = 0xE1,
     Framework = 0xF8,
 }
@@ -30,6 +39,94 @@ pub enum FrameworkCommandCode {
     ContinueInterrupted = 0x01,
 }
 
+/// Instructions sent to the device: the commands of the Bitcoin app, of the
+/// dashboard and of the framework.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ins {
+    GetExtendedPubkey,
+    GetVersion,
+    RegisterWallet,
+    GetWalletAddress,
+    SignPsbt,
+    GetMasterFingerprint,
+    SignMessage,
+    ContinueInterrupted,
+    OpenApp,
+    QuitApp,
+    /// First page of the installed apps.
+    ListApps,
+    /// Next pages of the installed apps.
+    ListAppsContinue,
+    /// Commands of the legacy Bitcoin app, see [`super::legacy`].
+    LegacyGetWalletPublicKey,
+    LegacyHashInputStart,
+    LegacyHashSign,
+    LegacyHashInputFinalizeFull,
+    /// Master blinding key of the Liquid app, see [`super::elements`].
+    #[cfg(feature = "elements")]
+    LiquidGetMasterBlindingKey,
+}
+
+impl Ins {
+    /// Returns the class the instruction is sent with.
+    pub fn cla(&self) -> Cla {
+        match self {
+            Ins::GetExtendedPubkey
+            | Ins::RegisterWallet
+            | Ins::GetWalletAddress
+            | Ins::SignPsbt
+            | Ins::GetMasterFingerprint
+            | Ins::SignMessage => Cla::Bitcoin,
+            #[cfg(feature = "elements")]
+            Ins::LiquidGetMasterBlindingKey => Cla::Bitcoin,
+            Ins::ContinueInterrupted => Cla::Framework,
+            Ins::GetVersion | Ins::QuitApp => Cla::Default,
+            Ins::OpenApp
+            | Ins::ListApps
+            | Ins::ListAppsContinue
+            | Ins::LegacyGetWalletPublicKey
+            | Ins::LegacyHashInputStart
+            | Ins::LegacyHashSign
+            | Ins::LegacyHashInputFinalizeFull => Cla::Dashboard,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Ins::GetExtendedPubkey => BitcoinCommandCode::GetExtendedPubkey as u8,
+            Ins::GetVersion => BitcoinCommandCode::GetVersion as u8,
+            Ins::RegisterWallet => BitcoinCommandCode::RegisterWallet as u8,
+            Ins::GetWalletAddress => BitcoinCommandCode::GetWalletAddress as u8,
+            Ins::SignPsbt => BitcoinCommandCode::SignPSBT as u8,
+            Ins::GetMasterFingerprint => BitcoinCommandCode::GetMasterFingerprint as u8,
+            Ins::SignMessage => BitcoinCommandCode::SignMessage as u8,
+            Ins::ContinueInterrupted => FrameworkCommandCode::ContinueInterrupted as u8,
+            // https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/ledger-live-common/src/hw/openApp.ts#L3
+            Ins::OpenApp => 0xd8,
+            Ins::QuitApp => 0xa7,
+            Ins::ListApps => 0xde,
+            Ins::ListAppsContinue => 0xdf,
+            // https://github.com/LedgerHQ/app-bitcoin/blob/master/doc/btc.asc
+            Ins::LegacyGetWalletPublicKey => 0x40,
+            Ins::LegacyHashInputStart => 0x44,
+            Ins::LegacyHashSign => 0x48,
+            Ins::LegacyHashInputFinalizeFull => 0x4a,
+            // https://github.com/LedgerHQ/app-liquid/blob/master/src/commands.h
+            #[cfg(feature = "elements")]
+            Ins::LiquidGetMasterBlindingKey => 0xe1,
+        }
+    }
+
+    /// Returns the default p2: the version of the protocol for the Bitcoin
+    /// app and the framework, 0 for the dashboard.
+    fn p2(&self) -> u8 {
+        match self.cla() {
+            Cla::Bitcoin | Cla::Framework => CURRENT_PROTOCOL_VERSION,
+            Cla::Default | Cla::Dashboard => 0x00,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ClientCommandCode {
@@ -56,8 +153,23 @@ impl TryFrom<u8> for ClientCommandCode {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum StatusWord {
+    /// Device locked
+    DeviceLocked = 0x5515,
+    /// Opening of the app rejected by user
+    OpenAppDenied = 0x5501,
+    /// The device has no PIN, its setup is not done
+    PinNotSet = 0x5502,
+    /// The onboarding of the device is not done
+    NotOnboarded = 0x6611,
+    /// App not open, the dashboard is running
+    AppNotOpen = 0x6511,
+    /// App not installed
+    AppNotInstalled = 0x6807,
+    /// Security status not satisfied, the device is locked
+    SecurityStatusNotSatisfied = 0x6982,
     /// Rejected by user
     Deny = 0x6985,
     /// Incorrect Data
@@ -70,8 +182,12 @@ pub enum StatusWord {
     WrongDataLength = 0x6A87,
     /// Ins not supported
     InsNotSupported = 0x6D00,
+    /// Technical problem, a transient failure of the device
+    TechnicalProblem = 0x6F00,
     /// Cla not supported
     ClaNotSupported = 0x6E00,
+    /// Cla not supported, no app is open
+    ClaNotSupportedNoApp = 0x6E01,
     /// Bad state
     BadState = 0xB007,
     /// Signature fail
@@ -87,22 +203,39 @@ impl TryFrom<u16> for StatusWord {
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         match value {
+            0x5515 => Ok(StatusWord::DeviceLocked),
+            0x5501 => Ok(StatusWord::OpenAppDenied),
+            0x5502 => Ok(StatusWord::PinNotSet),
+            0x6611 => Ok(StatusWord::NotOnboarded),
+            0x6511 => Ok(StatusWord::AppNotOpen),
+            0x6807 => Ok(StatusWord::AppNotInstalled),
+            0x6982 => Ok(StatusWord::SecurityStatusNotSatisfied),
             0x6985 => Ok(StatusWord::Deny),
             0x6A80 => Ok(StatusWord::IncorrectData),
             0x6A82 => Ok(StatusWord::NotSupported),
             0x6A86 => Ok(StatusWord::WrongP1P2),
             0x6A87 => Ok(StatusWord::WrongDataLength),
             0x6D00 => Ok(StatusWord::InsNotSupported),
+            0x6F00 => Ok(StatusWord::TechnicalProblem),
             0x6E00 => Ok(StatusWord::ClaNotSupported),
+            0x6E01 => Ok(StatusWord::ClaNotSupportedNoApp),
             0xB007 => Ok(StatusWord::BadState),
             0xB008 => Ok(StatusWord::SignatureFail),
             0x9000 => Ok(StatusWord::OK),
             0xE000 => Ok(StatusWord::InterruptedExecution),
-            _ => Err(ApduError::StatusWordUnknown),
+            _ => Err(ApduError::StatusWordUnknown(value)),
         }
     }
 }
 
+/// Maximal length of the data of a short APDU.
+pub const MAX_DATA_LEN: usize = 255;
+/// Maximal number of steps of the derivation paths accepted by the app.
+pub const MAX_PATH_LEN: usize = 10;
+/// p1 flag of the chunks following the first one of a command, as in the
+/// Ledger continuation convention.
+pub const P1_MORE_CHUNKS: u8 = 0x80;
+
 #[derive(Clone)]
 pub struct ApduCommand {
     pub cla: u8,
@@ -113,11 +246,55 @@ pub struct ApduCommand {
 }
 
 impl ApduCommand {
+    /// Returns the builder of a command, its instruction is required.
+    pub fn builder() -> ApduBuilder {
+        ApduBuilder {
+            command: ApduCommand::default(),
+            ins: None,
+            error: None,
+        }
+    }
+
+    /// Encodes the command, with an extended Lc if the data does not fit in a
+    /// short APDU. Ledger devices only accept short APDUs, see
+    /// [`ApduCommand::chunks`].
     pub fn encode(&self) -> Vec<u8> {
-        let mut vec = vec![self.cla, self.ins, self.p1, self.p2, self.data.len() as u8];
-        vec.extend(self.data.iter());
+        let mut vec = Vec::with_capacity(7 + self.data.len());
+        vec.extend([self.cla, self.ins, self.p1, self.p2]);
+        if self.data.len() > MAX_DATA_LEN {
+            vec.push(0x00);
+            vec.extend((self.data.len() as u16).to_be_bytes());
+        } else {
+            vec.push(self.data.len() as u8);
+        }
+        vec.extend_from_slice(&self.data);
         vec
     }
+
+    /// Splits the command in short APDUs, the chunks following the first one
+    /// have p1 set to [`P1_MORE_CHUNKS`].
+    pub fn chunks(&self) -> Vec<ApduCommand> {
+        if self.data.len() <= MAX_DATA_LEN {
+            return vec![self.clone()];
+        }
+        self.data
+            .chunks(MAX_DATA_LEN)
+            .enumerate()
+            .map(|(i, chunk)| ApduCommand {
+                cla: self.cla,
+                ins: self.ins,
+                p1: if i == 0 { self.p1 } else { P1_MORE_CHUNKS },
+                p2: self.p2,
+                data: chunk.to_vec(),
+            })
+            .collect()
+    }
+}
+
+impl From<ApduCommand> for Vec<u8> {
+    fn from(command: ApduCommand) -> Vec<u8> {
+        command.encode()
+    }
 }
 
 impl core::default::Default for ApduCommand {
@@ -132,6 +309,95 @@ impl core::default::Default for ApduCommand {
     }
 }
 
+/// Builder of the commands, checking the lengths of their data and encoding
+/// their fields as the app expects them.
+pub struct ApduBuilder {
+    command: ApduCommand,
+    ins: Option<Ins>,
+    /// First invalid field, returned by `build`.
+    error: Option<ApduError>,
+}
+
+impl ApduBuilder {
+    /// Overrides the class of the instruction.
+    pub fn cla(mut self, cla: u8) -> Self {
+        self.command.cla = cla;
+        self
+    }
+
+    /// Sets the instruction with its class and its default p2.
+    pub fn ins(mut self, ins: Ins) -> Self {
+        self.command.cla = ins.cla() as u8;
+        self.command.ins = ins.code();
+        self.command.p2 = ins.p2();
+        self.ins = Some(ins);
+        self
+    }
+
+    pub fn p1(mut self, p1: u8) -> Self {
+        self.command.p1 = p1;
+        self
+    }
+
+    pub fn p2(mut self, p2: u8) -> Self {
+        self.command.p2 = p2;
+        self
+    }
+
+    /// Appends the bytes to the data.
+    pub fn data(mut self, data: &[u8]) -> Self {
+        self.command.data.extend_from_slice(data);
+        self
+    }
+
+    pub fn bool(self, value: bool) -> Self {
+        self.data(&[u8::from(value)])
+    }
+
+    /// Appends the big endian encoding of the value.
+    pub fn u32(self, value: u32) -> Self {
+        self.data(&value.to_be_bytes())
+    }
+
+    /// Appends the bitcoin varint encoding of the value.
+    pub fn varint(self, value: usize) -> Self {
+        self.data(&encode::serialize(&VarInt(value as u64)))
+    }
+
+    /// Appends the number of steps of the path then its big endian child
+    /// numbers.
+    pub fn path(mut self, path: &DerivationPath) -> Self {
+        if path.len() > MAX_PATH_LEN {
+            self.error.get_or_insert(ApduError::PathTooLong(path.len()));
+            return self;
+        }
+        self.command.data.push(path.len() as u8);
+        for child in path {
+            self.command
+                .data
+                .extend_from_slice(&u32::from(*child).to_be_bytes());
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<ApduCommand, ApduError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.ins.is_none() {
+            return Err(ApduError::MissingInstruction);
+        }
+        if self.command.data.len() > u16::MAX as usize {
+            return Err(ApduError::DataTooLong(self.command.data.len()));
+        }
+        Ok(self.command)
+    }
+}
+
+/// Maximum length of an answer with its status word, the transports frame it
+/// with a length of 16 bits.
+pub const MAX_RESPONSE_LEN: usize = u16::MAX as usize;
+
 #[derive(Debug)]
 pub struct ApduResponse {
     pub data: Vec<u8>,
@@ -141,29 +407,95 @@ pub struct ApduResponse {
 impl From<ApduResponse> for Vec<u8> {
     fn from(res: ApduResponse) -> Vec<u8> {
         let mut vec = res.data;
-        vec.extend((res.status_word as u16).to_be_bytes().to_vec().iter());
+        vec.extend((res.status_word as u16).to_be_bytes());
         vec
     }
 }
 
+/// The data is the answer truncated of its status word, without copy.
 impl TryFrom<Vec<u8>> for ApduResponse {
     type Error = ApduError;
-    fn try_from(res: Vec<u8>) -> Result<Self, Self::Error> {
+    fn try_from(mut res: Vec<u8>) -> Result<Self, Self::Error> {
         if res.len() < 2 {
             return Err(ApduError::ResponseTooShort);
         }
-        let s = u16::from_be_bytes([res[res.len() - 2], res[res.len() - 1]]);
-        let status_word = StatusWord::try_from(s)?;
-
+        if res.len() > MAX_RESPONSE_LEN {
+            return Err(ApduError::ResponseTooLong(res.len()));
+        }
+        let len = res.len() - 2;
+        let status_word = StatusWord::try_from(u16::from_be_bytes([res[len], res[len + 1]]))?;
+        res.truncate(len);
         Ok(ApduResponse {
-            data: res[0..res.len() - 2].to_vec(),
+            data: res,
             status_word,
         })
     }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ApduError {
-    StatusWordUnknown,
+    StatusWordUnknown(u16),
     ResponseTooShort,
+    /// The answer is longer than [`MAX_RESPONSE_LEN`].
+    ResponseTooLong(usize),
+    /// The command was built without instruction.
+    MissingInstruction,
+    /// The path has more steps than [`MAX_PATH_LEN`].
+    PathTooLong(usize),
+    /// The data does not fit in an extended APDU.
+    DataTooLong(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let command = ApduCommand {
+            cla: Cla::Bitcoin as u8,
+            ins: BitcoinCommandCode::RegisterWallet as u8,
+            p1: 0x00,
+            p2: CURRENT_PROTOCOL_VERSION,
+            data: vec![0xab; 600],
+        };
+        let encoded = command.encode();
+        assert_eq!(&encoded[..7], &[0xe1, 0x02, 0x00, 0x01, 0x00, 0x02, 0x58]);
+        assert_eq!(encoded.len(), 607);
+
+        let chunks = command.chunks();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].p1, 0x00);
+        assert!(chunks[1..].iter().all(|c| c.p1 == P1_MORE_CHUNKS));
+        assert_eq!(chunks[2].encode()[4], 90);
+        let data: Vec<u8> = chunks.iter().flat_map(|c| c.data.clone()).collect();
+        assert_eq!(data, command.data);
+
+        let short = ApduCommand::default();
+        assert_eq!(short.chunks().len(), 1);
+        assert_eq!(short.encode(), vec![0xb0, 0x00, 0x00, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_response() {
+        let answer = vec![0x01, 0x02, 0x90, 0x00];
+        let ptr = answer.as_ptr();
+        let res = ApduResponse::try_from(answer.clone()).unwrap();
+        assert_eq!(res.data, vec![0x01, 0x02]);
+        assert_eq!(res.status_word, StatusWord::OK);
+        assert_eq!(Vec::from(res), answer);
+
+        // The data reuses the buffer of the answer.
+        let res = ApduResponse::try_from(answer).unwrap();
+        assert_eq!(res.data.as_ptr(), ptr);
+        assert!(matches!(
+            ApduResponse::try_from(vec![0x90]),
+            Err(ApduError::ResponseTooShort)
+        ));
+        assert!(matches!(
+            ApduResponse::try_from(vec![0x90; MAX_RESPONSE_LEN + 1]),
+            Err(ApduError::ResponseTooLong(65536))
+        ));
+    }
 }
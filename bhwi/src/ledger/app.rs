@@ -0,0 +1,265 @@
+//! Name and version of the app open on the device, as answered to
+//! GET_APP_AND_VERSION, and the apps installed, as listed by the dashboard.
+
+use bitcoin::Network;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::devices::{Capabilities, NETWORKS};
+use crate::prelude::*;
+
+/// Name of the app open while the device is on its dashboard.
+pub const DASHBOARD_NAME: &str = "BOLOS";
+
+/// Version of the app, following semantic versioning.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// Pre-release suffix, like "rc1".
+    pub pre: Option<String>,
+}
+
+impl AppVersion {
+    /// Returns true if the version is the given release or a later one, the
+    /// pre-releases preceding their release.
+    pub fn at_least(&self, major: u32, minor: u32, patch: u32) -> bool {
+        let version = (self.major, self.minor, self.patch);
+        version > (major, minor, patch) || (version == (major, minor, patch) && self.pre.is_none())
+    }
+}
+
+impl FromStr for AppVersion {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (version, pre) = match s.split_once('-') {
+            Some((version, pre)) => (version, Some(pre.to_string())),
+            None => (s, None),
+        };
+        let numbers = version
+            .split('.')
+            .map(u32::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ())?;
+        match numbers[..] {
+            [major, minor, patch] => Ok(Self {
+                major,
+                minor,
+                patch,
+                pre,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for AppVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppInfo {
+    pub name: String,
+    pub version: AppVersion,
+    pub flags: Vec<u8>,
+}
+
+impl AppInfo {
+    /// Parses the answer: the format byte followed by the name, the version
+    /// and the flags, each prefixed by its length.
+    pub fn from_slice(data: &[u8]) -> Option<Self> {
+        let (_format, mut data) = data.split_first()?;
+        let mut next = || {
+            let (len, rest) = data.split_first()?;
+            let (field, rest) = rest.split_at_checked(*len as usize)?;
+            data = rest;
+            Some(field)
+        };
+        let name = String::from_utf8(next()?.to_vec()).ok()?;
+        let version = AppVersion::from_str(core::str::from_utf8(next()?).ok()?).ok()?;
+        // Old versions of the dashboard do not answer the flags.
+        let flags = next().map(<[u8]>::to_vec).unwrap_or_default();
+        Some(Self {
+            name,
+            version,
+            flags,
+        })
+    }
+
+    pub fn is_dashboard(&self) -> bool {
+        self.name == DASHBOARD_NAME
+    }
+
+    /// Returns true if the app is the Bitcoin app of the network.
+    pub fn is_bitcoin_app(&self, network: Network) -> bool {
+        self.name == app_name(network)
+    }
+}
+
+/// App installed on the device. The dashboard does not list the versions,
+/// the hashes identify the release instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstalledApp {
+    pub name: String,
+    pub flags: u32,
+    /// Hash of the code of the app.
+    pub code_hash: [u8; 32],
+    /// Hash of the whole app, as published by Ledger.
+    pub hash: [u8; 32],
+}
+
+impl InstalledApp {
+    /// Parses a page of the list answered by the dashboard: the format byte
+    /// followed by the apps, each prefixed by its length. The page after the
+    /// last app is empty.
+    pub fn from_page(data: &[u8]) -> Option<Vec<Self>> {
+        let Some((_format, mut data)) = data.split_first() else {
+            return Some(Vec::new());
+        };
+        let mut apps = Vec::new();
+        while let Some((len, rest)) = data.split_first() {
+            let (entry, rest) = rest.split_at_checked(*len as usize)?;
+            data = rest;
+            let (flags, entry) = entry.split_first_chunk::<4>()?;
+            let (code_hash, entry) = entry.split_first_chunk::<32>()?;
+            let (hash, entry) = entry.split_first_chunk::<32>()?;
+            let (name_len, entry) = entry.split_first()?;
+            let name = entry.get(..*name_len as usize)?;
+            apps.push(Self {
+                name: String::from_utf8(name.to_vec()).ok()?,
+                flags: u32::from_be_bytes(*flags),
+                code_hash: *code_hash,
+                hash: *hash,
+            });
+        }
+        Some(apps)
+    }
+}
+
+/// Returns the name of the Bitcoin app of the network.
+pub fn app_name(network: Network) -> &'static str {
+    if network == Network::Bitcoin {
+        "Bitcoin"
+    } else {
+        "Bitcoin Test"
+    }
+}
+
+/// Returns the capabilities of the Bitcoin app, the ones of the last releases
+/// on all the networks if the app is unknown: the legacy app before 2.0
+/// neither spends taproot outputs nor registers policies, MuSig2 came with
+/// 2.4.
+pub fn capabilities(app: Option<&AppInfo>) -> Capabilities {
+    let app = app
+        .filter(|app| app.is_bitcoin_app(Network::Bitcoin) || app.is_bitcoin_app(Network::Testnet));
+    let at_least =
+        |major, minor, patch| app.map_or(true, |app| app.version.at_least(major, minor, patch));
+    Capabilities {
+        taproot: at_least(2, 0, 0),
+        musig2: at_least(2, 4, 0),
+        message_signing: true,
+        policy_registration: at_least(2, 0, 0),
+        max_inputs: None,
+        networks: match app {
+            Some(app) if app.is_bitcoin_app(Network::Bitcoin) => vec![Network::Bitcoin],
+            Some(_) => NETWORKS[1..].to_vec(),
+            None => NETWORKS.to_vec(),
+        },
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_info() {
+        let mut data = vec![0x01, 0x0c];
+        data.extend(b"Bitcoin Test");
+        data.push(0x05);
+        data.extend(b"2.1.3");
+        data.extend([0x01, 0x02]);
+        let info = AppInfo::from_slice(&data).unwrap();
+        assert_eq!(info.name, "Bitcoin Test");
+        assert_eq!(info.version.to_string(), "2.1.3");
+        assert_eq!(info.flags, vec![0x02]);
+        assert!(info.is_bitcoin_app(Network::Testnet));
+        assert!(!info.is_bitcoin_app(Network::Bitcoin));
+        assert!(info.version.at_least(2, 1, 0));
+        assert!(!info.version.at_least(2, 2, 0));
+
+        let mut data = vec![0x01, 0x05];
+        data.extend(b"BOLOS");
+        data.push(0x0a);
+        data.extend(b"2.2.0-rc.1");
+        let info = AppInfo::from_slice(&data).unwrap();
+        assert!(info.is_dashboard());
+        assert!(info.flags.is_empty());
+        assert_eq!(info.version.pre.as_deref(), Some("rc.1"));
+        assert!(!info.version.at_least(2, 2, 0));
+
+        assert_eq!(AppInfo::from_slice(&[0x01, 0x0c, b'B']), None);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let app = |name: &str, version: &str| AppInfo {
+            name: name.to_string(),
+            version: AppVersion::from_str(version).unwrap(),
+            flags: Vec::new(),
+        };
+        let legacy = capabilities(Some(&app("Bitcoin", "1.6.5")));
+        assert!(!legacy.taproot && !legacy.policy_registration && !legacy.musig2);
+        assert!(legacy.message_signing);
+        assert_eq!(legacy.networks, vec![Network::Bitcoin]);
+
+        let test = capabilities(Some(&app("Bitcoin Test", "2.3.0")));
+        assert!(test.taproot && test.policy_registration && !test.musig2);
+        assert!(!test.networks.contains(&Network::Bitcoin));
+        assert!(test.networks.contains(&Network::Testnet4));
+        assert!(capabilities(Some(&app("Bitcoin Test", "2.4.0"))).musig2);
+
+        // The Bitcoin app is opened from the dashboard.
+        assert_eq!(
+            capabilities(Some(&app(DASHBOARD_NAME, "2.2.0"))),
+            capabilities(None)
+        );
+        assert_eq!(capabilities(None).networks, NETWORKS.to_vec());
+    }
+
+    pub fn app_entry(name: &str) -> Vec<u8> {
+        let mut entry = vec![(4 + 64 + 1 + name.len()) as u8];
+        entry.extend(0x0a50u32.to_be_bytes());
+        entry.extend([0x01; 32]);
+        entry.extend([0x02; 32]);
+        entry.push(name.len() as u8);
+        entry.extend(name.as_bytes());
+        entry
+    }
+
+    #[test]
+    fn test_installed_apps() {
+        let mut data = vec![0x01];
+        data.extend(app_entry("Bitcoin"));
+        data.extend(app_entry("Bitcoin Test"));
+        let apps = InstalledApp::from_page(&data).unwrap();
+        assert_eq!(apps.len(), 2);
+        assert_eq!(apps[0].name, "Bitcoin");
+        assert_eq!(apps[1].name, "Bitcoin Test");
+        assert_eq!(apps[1].flags, 0x0a50);
+        assert_eq!(apps[1].hash, [0x02; 32]);
+
+        assert_eq!(InstalledApp::from_page(&[]), Some(Vec::new()));
+        assert_eq!(InstalledApp::from_page(&data[..40]), None);
+    }
+}
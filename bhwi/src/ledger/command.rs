@@ -1,83 +1,78 @@
 /// APDU commands  for the Bitcoin application.
 ///
-use bitcoin::{
-    bip32::{ChildNumber, DerivationPath},
-    consensus::encode::{self, VarInt},
-    Network,
-};
-use core::default::Default;
-
-use super::{
-    apdu::{self, ApduCommand},
-    wallet::WalletPolicy,
-};
-
-// https://github.com/LedgerHQ/ledger-live/blob/5a0a1aa5dc183116839851b79bceb6704f1de4b9/libs/ledger-live-common/src/hw/openApp.ts#L3
-pub fn open_app(network: Network) -> ApduCommand {
-    ApduCommand {
-        cla: 0xe0,
-        ins: 0xd8,
-        p1: 0x00,
-        p2: 0x00,
-        data: if network == Network::Bitcoin {
-            b"Bitcoin".to_vec()
-        } else {
-            b"Bitcoin Test".to_vec()
-        },
-    }
+/// The commands of variable length fail on the data not fitting in an APDU
+/// and on the paths too long for the app.
+use bitcoin::bip32::DerivationPath;
+
+use super::apdu::{ApduBuilder, ApduCommand, ApduError, Ins};
+
+use crate::{prelude::*, wallet::WalletPolicy};
+
+/// Builds the command of bounded data, the build cannot fail.
+fn fixed(builder: ApduBuilder) -> ApduCommand {
+    builder.build().expect("bounded data with an instruction")
+}
+
+/// Creates the APDU Command to open the app of the name from the dashboard,
+/// see [`super::app::app_name`].
+pub fn open_app(name: &str) -> ApduCommand {
+    fixed(
+        ApduCommand::builder()
+            .ins(Ins::OpenApp)
+            .data(name.as_bytes()),
+    )
+}
+
+/// Creates the APDU Command to quit the open app and go back to the dashboard.
+pub fn quit_app() -> ApduCommand {
+    fixed(ApduCommand::builder().ins(Ins::QuitApp))
+}
+
+/// Creates the APDU Command to list the installed apps from the dashboard,
+/// page by page until an empty one.
+pub fn list_apps(first: bool) -> ApduCommand {
+    let ins = if first {
+        Ins::ListApps
+    } else {
+        Ins::ListAppsContinue
+    };
+    fixed(ApduCommand::builder().ins(ins))
 }
 
 /// Creates the APDU Command to retrieve the app's name, version and state flags.
 pub fn get_version() -> ApduCommand {
-    ApduCommand {
-        ins: apdu::BitcoinCommandCode::GetVersion as u8,
-        p2: 0x00,
-        ..Default::default()
-    }
+    fixed(ApduCommand::builder().ins(Ins::GetVersion))
 }
 
 /// Creates the APDU Command to retrieve the master fingerprint.
 pub fn get_master_fingerprint() -> ApduCommand {
-    ApduCommand {
-        cla: apdu::Cla::Bitcoin as u8,
-        ins: apdu::BitcoinCommandCode::GetMasterFingerprint as u8,
-        ..Default::default()
-    }
+    fixed(ApduCommand::builder().ins(Ins::GetMasterFingerprint))
+}
+
+/// Creates the APDU Command to retrieve the SLIP-77 master blinding key of
+/// the Liquid app.
+#[cfg(feature = "elements")]
+pub fn liquid_get_master_blinding_key() -> ApduCommand {
+    fixed(ApduCommand::builder().ins(Ins::LiquidGetMasterBlindingKey))
 }
 
 /// Creates the APDU command required to get the extended pubkey with the given derivation path.
-pub fn get_extended_pubkey(path: &DerivationPath, display: bool) -> ApduCommand {
-    let child_numbers: &[ChildNumber] = path.as_ref();
-    let data: Vec<u8> = child_numbers.iter().fold(
-        vec![
-            if display { 1_u8 } else { b'\0' },
-            child_numbers.len() as u8,
-        ],
-        |mut acc, &x| {
-            acc.extend_from_slice(&u32::from(x).to_be_bytes());
-            acc
-        },
-    );
-
-    ApduCommand {
-        cla: apdu::Cla::Bitcoin as u8,
-        ins: apdu::BitcoinCommandCode::GetExtendedPubkey as u8,
-        data,
-        ..Default::default()
-    }
+pub fn get_extended_pubkey(path: &DerivationPath, display: bool) -> Result<ApduCommand, ApduError> {
+    ApduCommand::builder()
+        .ins(Ins::GetExtendedPubkey)
+        .bool(display)
+        .path(path)
+        .build()
 }
 
 /// Creates the APDU command required to register the given wallet policy.
-pub fn register_wallet(policy: &WalletPolicy) -> ApduCommand {
+pub fn register_wallet(policy: &WalletPolicy) -> Result<ApduCommand, ApduError> {
     let bytes = policy.serialize();
-    let mut data = encode::serialize(&VarInt(bytes.len() as u64));
-    data.extend(bytes);
-    ApduCommand {
-        cla: apdu::Cla::Bitcoin as u8,
-        ins: apdu::BitcoinCommandCode::RegisterWallet as u8,
-        data,
-        ..Default::default()
-    }
+    ApduCommand::builder()
+        .ins(Ins::RegisterWallet)
+        .varint(bytes.len())
+        .data(&bytes)
+        .build()
 }
 
 /// Creates the APDU command required to retrieve an address for the given wallet.
@@ -88,18 +83,15 @@ pub fn get_wallet_address(
     address_index: u32,
     display: bool,
 ) -> ApduCommand {
-    let mut data: Vec<u8> = Vec::with_capacity(70);
-    data.push(if display { 1_u8 } else { b'\0' });
-    data.extend_from_slice(&policy.id());
-    data.extend_from_slice(hmac.unwrap_or(&[b'\0'; 32]));
-    data.push(if change { 1_u8 } else { b'\0' });
-    data.extend_from_slice(&address_index.to_be_bytes());
-    ApduCommand {
-        cla: apdu::Cla::Bitcoin as u8,
-        ins: apdu::BitcoinCommandCode::GetWalletAddress as u8,
-        data,
-        ..Default::default()
-    }
+    fixed(
+        ApduCommand::builder()
+            .ins(Ins::GetWalletAddress)
+            .bool(display)
+            .data(&policy.id())
+            .data(hmac.unwrap_or(&[b'\0'; 32]))
+            .bool(change)
+            .u32(address_index),
+    )
 }
 
 /// Creates the APDU command required to sign a psbt.
@@ -111,21 +103,17 @@ pub fn sign_psbt(
     output_commitments_root: &[u8; 32],
     policy: &WalletPolicy,
     hmac: Option<&[u8; 32]>,
-) -> ApduCommand {
-    let mut data: Vec<u8> = Vec::new();
-    data.extend_from_slice(global_mapping_commitment);
-    data.extend(encode::serialize(&VarInt(inputs_number as u64)));
-    data.extend_from_slice(input_commitments_root);
-    data.extend(encode::serialize(&VarInt(outputs_number as u64)));
-    data.extend_from_slice(output_commitments_root);
-    data.extend_from_slice(&policy.id());
-    data.extend_from_slice(hmac.unwrap_or(&[b'\0'; 32]));
-    ApduCommand {
-        cla: apdu::Cla::Bitcoin as u8,
-        ins: apdu::BitcoinCommandCode::SignPSBT as u8,
-        data,
-        ..Default::default()
-    }
+) -> Result<ApduCommand, ApduError> {
+    ApduCommand::builder()
+        .ins(Ins::SignPsbt)
+        .data(global_mapping_commitment)
+        .varint(inputs_number)
+        .data(input_commitments_root)
+        .varint(outputs_number)
+        .data(output_commitments_root)
+        .data(&policy.id())
+        .data(hmac.unwrap_or(&[b'\0'; 32]))
+        .build()
 }
 
 /// Creates the APDU Command to sign a message.
@@ -133,32 +121,57 @@ pub fn sign_message(
     message_length: usize,
     message_commitment_root: &[u8; 32],
     path: &DerivationPath,
-) -> ApduCommand {
-    let child_numbers: &[ChildNumber] = path.as_ref();
-    let mut data: Vec<u8> =
-        child_numbers
-            .iter()
-            .fold(vec![child_numbers.len() as u8], |mut acc, &x| {
-                acc.extend_from_slice(&u32::from(x).to_be_bytes());
-                acc
-            });
-    data.extend(encode::serialize(&VarInt(message_length as u64)));
-    data.extend_from_slice(message_commitment_root);
-
-    ApduCommand {
-        cla: apdu::Cla::Bitcoin as u8,
-        ins: apdu::BitcoinCommandCode::SignMessage as u8,
-        data,
-        ..Default::default()
-    }
+) -> Result<ApduCommand, ApduError> {
+    ApduCommand::builder()
+        .ins(Ins::SignMessage)
+        .path(path)
+        .varint(message_length)
+        .data(message_commitment_root)
+        .build()
 }
 
 /// Creates the APDU command to CONTINUE.
-pub fn continue_interrupted(data: Vec<u8>) -> ApduCommand {
-    ApduCommand {
-        cla: apdu::Cla::Framework as u8,
-        ins: apdu::FrameworkCommandCode::ContinueInterrupted as u8,
-        data,
-        ..Default::default()
+pub fn continue_interrupted(data: Vec<u8>) -> Result<ApduCommand, ApduError> {
+    ApduCommand::builder()
+        .ins(Ins::ContinueInterrupted)
+        .data(&data)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_commands() {
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        assert_eq!(
+            get_extended_pubkey(&path, true).unwrap().encode(),
+            [
+                0xe1, 0x00, 0x00, 0x01, 0x0e, 0x01, 0x03, 0x80, 0x00, 0x00, 0x54, 0x80, 0x00, 0x00,
+                0x01, 0x80, 0x00, 0x00, 0x00
+            ]
+        );
+        assert_eq!(get_version().encode(), [0xb0, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(list_apps(false).encode(), [0xe0, 0xdf, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            open_app("Bitcoin").encode(),
+            [0xe0, 0xd8, 0x00, 0x00, 0x07, b'B', b'i', b't', b'c', b'o', b'i', b'n']
+        );
+
+        let path = DerivationPath::from_str("m/0/1/2/3/4/5/6/7/8/9/10").unwrap();
+        assert!(matches!(
+            get_extended_pubkey(&path, false),
+            Err(ApduError::PathTooLong(11))
+        ));
+        assert!(matches!(
+            ApduCommand::builder().data(&[0x01]).build(),
+            Err(ApduError::MissingInstruction)
+        ));
+        assert!(matches!(
+            continue_interrupted(vec![0x00; 70_000]),
+            Err(ApduError::DataTooLong(70_000))
+        ));
     }
 }
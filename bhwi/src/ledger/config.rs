@@ -0,0 +1,91 @@
+//! Overrides of the apdus and of the app names, for the forks of the Bitcoin
+//! app and their test builds speaking the same protocol with other classes,
+//! instructions or names.
+
+use alloc::collections::BTreeMap;
+use bitcoin::Network;
+
+use super::apdu::{ApduCommand, Cla, Ins};
+use super::app::app_name;
+use crate::prelude::*;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LedgerConfig {
+    /// Classes sent instead of the standard ones.
+    classes: BTreeMap<u8, u8>,
+    /// Codes sent instead of the standard ones, by standard class and code.
+    instructions: BTreeMap<(u8, u8), u8>,
+    app_names: BTreeMap<Network, String>,
+}
+
+impl LedgerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends the instructions of the class with the given value.
+    pub fn with_cla(mut self, cla: Cla, value: u8) -> Self {
+        self.classes.insert(cla as u8, value);
+        self
+    }
+
+    /// Sends the instruction with the given code, its class is still the
+    /// one of [`LedgerConfig::with_cla`].
+    pub fn with_ins(mut self, ins: Ins, code: u8) -> Self {
+        self.instructions
+            .insert((ins.cla() as u8, ins.code()), code);
+        self
+    }
+
+    /// Opens and expects the app of the given name for the network, instead
+    /// of `Bitcoin` and `Bitcoin Test`.
+    pub fn with_app_name(mut self, network: Network, name: impl Into<String>) -> Self {
+        self.app_names.insert(network, name.into());
+        self
+    }
+
+    /// Returns the name of the app of the network.
+    pub fn app_name(&self, network: Network) -> &str {
+        self.app_names
+            .get(&network)
+            .map(String::as_str)
+            .unwrap_or_else(|| app_name(network))
+    }
+
+    /// Returns the apdu built with the standard class and instruction, with
+    /// their overrides.
+    pub fn apply(&self, mut apdu: ApduCommand) -> ApduCommand {
+        if let Some(code) = self.instructions.get(&(apdu.cla, apdu.ins)) {
+            apdu.ins = *code;
+        }
+        if let Some(value) = self.classes.get(&apdu.cla) {
+            apdu.cla = *value;
+        }
+        apdu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::command;
+
+    #[test]
+    fn test_apply() {
+        let config = LedgerConfig::new()
+            .with_cla(Cla::Bitcoin, 0xe5)
+            .with_ins(Ins::GetMasterFingerprint, 0x15)
+            .with_app_name(Network::Testnet, "Bitcoin QA");
+        let apdu = config.apply(command::get_master_fingerprint());
+        assert_eq!((apdu.cla, apdu.ins), (0xe5, 0x15));
+        let apdu = config.apply(command::get_version());
+        assert_eq!((apdu.cla, apdu.ins), (Cla::Default as u8, 0x01));
+        // The same code of another class is kept.
+        let apdu = config.apply(command::continue_interrupted(Vec::new()).unwrap());
+        assert_eq!((apdu.cla, apdu.ins), (Cla::Framework as u8, 0x01));
+
+        assert_eq!(config.app_name(Network::Testnet), "Bitcoin QA");
+        assert_eq!(config.app_name(Network::Signet), "Bitcoin Test");
+        assert_eq!(config.app_name(Network::Bitcoin), "Bitcoin");
+    }
+}
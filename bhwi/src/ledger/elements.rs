@@ -0,0 +1,204 @@
+//! Commands of the Ledger Liquid app, a fork of the Bitcoin app sharing its
+//! protocol: the xpubs and the wallet policies are requested the same way,
+//! the PSETs are committed to the device like the psbts and the signatures
+//! are yielded the same way.
+//!
+//! The PSETs are kept serialized, only their maps are read to be committed.
+
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use core::str::FromStr;
+
+use super::{
+    add_known_policy, apdu::ApduCommand, check_xpub, command, psbt, store::DelegatedStore,
+    wallet::WalletPolicy, yielded_signatures, LedgerError, LedgerResponse,
+};
+use crate::prelude::*;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LiquidCommand {
+    /// Xpub of the path, with the coin type of Liquid (1776').
+    GetXpub { path: DerivationPath, display: bool },
+    /// SLIP-77 master blinding key, from which the host derives the blinding
+    /// keys of the confidential addresses.
+    GetMasterBlindingKey,
+    SignPset {
+        /// The serialized PSET.
+        pset: Vec<u8>,
+        policy: WalletPolicy,
+        /// Proof of registration of the policy, None for default wallets.
+        hmac: Option<[u8; 32]>,
+    },
+}
+
+impl LiquidCommand {
+    /// Returns the name of the command to log, without its arguments.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LiquidCommand::GetXpub { .. } => "liquid_get_xpub",
+            LiquidCommand::GetMasterBlindingKey => "get_master_blinding_key",
+            LiquidCommand::SignPset { .. } => "sign_pset",
+        }
+    }
+
+    /// Returns true if the device asks the user to confirm the command.
+    pub fn requires_confirmation(&self) -> bool {
+        match self {
+            LiquidCommand::GetXpub { display, .. } => *display,
+            LiquidCommand::GetMasterBlindingKey | LiquidCommand::SignPset { .. } => true,
+        }
+    }
+}
+
+/// Returns the first apdu of the command and the store answering the
+/// requests of the device.
+pub(super) fn start(
+    command: &LiquidCommand,
+) -> Result<(ApduCommand, Option<DelegatedStore>), LedgerError> {
+    match command {
+        LiquidCommand::GetXpub { path, display } => {
+            Ok((command::get_extended_pubkey(path, *display)?, None))
+        }
+        LiquidCommand::GetMasterBlindingKey => {
+            Ok((command::liquid_get_master_blinding_key(), None))
+        }
+        LiquidCommand::SignPset { pset, policy, hmac } => {
+            let (global, inputs, outputs) = psbt::get_pset_maps(pset)
+                .map_err(|e| LedgerError::InvalidPset(format!("{:?}", e)))?;
+            let mut store = DelegatedStore::new();
+            let global = store.add_known_mapping(global);
+            let inputs: Vec<Vec<u8>> = inputs
+                .into_iter()
+                .map(|map| store.add_known_mapping(map))
+                .collect();
+            let outputs: Vec<Vec<u8>> = outputs
+                .into_iter()
+                .map(|map| store.add_known_mapping(map))
+                .collect();
+            let inputs_root = store.add_known_list(&inputs);
+            let outputs_root = store.add_known_list(&outputs);
+            add_known_policy(&mut store, policy);
+            Ok((
+                command::sign_psbt(
+                    &global,
+                    inputs.len(),
+                    &inputs_root,
+                    outputs.len(),
+                    &outputs_root,
+                    policy,
+                    hmac.as_ref(),
+                )?,
+                Some(store),
+            ))
+        }
+    }
+}
+
+/// Returns the response of the command from the data of the last answer.
+pub(super) fn end(
+    command: &LiquidCommand,
+    data: Vec<u8>,
+    store: Option<DelegatedStore>,
+    master_fingerprint: Option<Fingerprint>,
+) -> Result<LedgerResponse, LedgerError> {
+    match command {
+        LiquidCommand::GetXpub { path, .. } => {
+            let xpub = Xpub::from_str(&String::from_utf8_lossy(&data))
+                .map_err(|_| LedgerError::UnexpectedResult(data))?;
+            if let Some(fg) = master_fingerprint {
+                check_xpub(&xpub, path, fg)?;
+            }
+            Ok(LedgerResponse::Xpub(xpub))
+        }
+        LiquidCommand::GetMasterBlindingKey => {
+            let key = <[u8; 32]>::try_from(data.as_slice())
+                .map_err(|_| LedgerError::UnexpectedResult(data))?;
+            Ok(LedgerResponse::MasterBlindingKey(key))
+        }
+        LiquidCommand::SignPset { .. } => {
+            Ok(LedgerResponse::Signatures(yielded_signatures(store)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ledger::{apdu::Cla, wallet, LedgerCommand, LedgerInterpreter},
+        Interpreter,
+    };
+    use bitcoin::{
+        absolute::LockTime, bip32::Xpriv, secp256k1::Secp256k1, transaction::Version, Amount,
+        Network, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+
+    type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+    #[test]
+    fn test_liquid_commands() {
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter
+            .start(LedgerCommand::Liquid(LiquidCommand::GetMasterBlindingKey))
+            .unwrap();
+        assert_eq!((apdu.cla, apdu.ins), (Cla::Bitcoin as u8, 0xe1));
+        let mut answer = vec![0x42; 32];
+        answer.extend([0x90, 0x00]);
+        assert!(interpreter.exchange(answer).unwrap().is_none());
+        assert!(matches!(
+            interpreter.end(),
+            Ok(LedgerResponse::MasterBlindingKey(key)) if key == [0x42; 32]
+        ));
+
+        // A PSET is committed like the PSBT v2 of the same maps.
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap());
+        let policy = WalletPolicy::new(
+            String::new(),
+            wallet::Version::V2,
+            "wpkh(@0/**)".to_string(),
+            [((xpriv.fingerprint(&secp), path), xpub)],
+        );
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let mut interpreter = Ledger::default();
+        let mut pset = psbt::serialize_v2(&psbt);
+        pset[..4].copy_from_slice(b"pset");
+        let expected = interpreter
+            .start(LedgerCommand::SignPsbt {
+                psbt: Box::new(psbt),
+                policy: policy.clone(),
+                hmac: None,
+            })
+            .unwrap();
+        let apdu = interpreter
+            .start(LedgerCommand::Liquid(LiquidCommand::SignPset {
+                pset: pset.clone(),
+                policy,
+                hmac: None,
+            }))
+            .unwrap();
+        assert_eq!(apdu.data, expected.data);
+
+        pset[..4].copy_from_slice(b"psbt");
+        assert!(matches!(
+            psbt::get_pset_maps(&pset),
+            Err(psbt::PsbtError::InvalidMagic)
+        ));
+    }
+}
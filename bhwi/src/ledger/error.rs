@@ -1,7 +1,9 @@
-use core::fmt::Debug;
+use core::fmt::{self, Debug};
 
 use super::{apdu::StatusWord, store::StoreError};
 
+use crate::prelude::*;
+
 #[derive(Debug)]
 pub enum BitcoinClientError<T: Debug> {
     ClientError(String),
@@ -19,3 +21,59 @@ impl<T: Debug> From<StoreError> for BitcoinClientError<T> {
         BitcoinClientError::Store(e)
     }
 }
+
+/// Details answered by the app in the body of a failure, along its status
+/// word.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeviceErrorDetail {
+    /// Error code of the app, big endian on two bytes.
+    Code(u16),
+    /// Message of the app, like "cosigner key #2 has wrong origin".
+    Message(String),
+}
+
+impl DeviceErrorDetail {
+    /// Decodes the body of the failure, None if empty or neither a code nor
+    /// a printable message.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if let [high, low] = data {
+            return Some(Self::Code(u16::from_be_bytes([*high, *low])));
+        }
+        let message = core::str::from_utf8(data).ok()?.trim();
+        if message.is_empty() || message.chars().any(char::is_control) {
+            return None;
+        }
+        Some(Self::Message(message.to_string()))
+    }
+}
+
+impl fmt::Display for DeviceErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "error code 0x{:04x}", code),
+            Self::Message(message) => f.write_str(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_error_detail() {
+        assert_eq!(
+            DeviceErrorDetail::decode(b"cosigner key #2 has wrong origin\n"),
+            Some(DeviceErrorDetail::Message(
+                "cosigner key #2 has wrong origin".to_string()
+            ))
+        );
+        let code = DeviceErrorDetail::decode(&[0x00, 0x02]).unwrap();
+        assert_eq!(code, DeviceErrorDetail::Code(2));
+        assert_eq!(code.to_string(), "error code 0x0002");
+        assert_eq!(DeviceErrorDetail::decode(&[]), None);
+        assert_eq!(DeviceErrorDetail::decode(&[0x01, 0x02, 0x03]), None);
+        assert_eq!(DeviceErrorDetail::decode(&[0xff, 0xfe, 0xfd]), None);
+    }
+}
@@ -0,0 +1,501 @@
+//! Protocol of the Bitcoin app before 2.0, without wallet policies: the keys
+//! are retrieved with GET_WALLET_PUBLIC_KEY and the segwit v0 inputs of the
+//! single signature wallets are signed with the untrusted hash flow.
+
+use alloc::collections::VecDeque;
+
+use bitcoin::{
+    bip32::{ChainCode, ChildNumber, DerivationPath, Fingerprint, Xpub},
+    consensus::encode::{self, VarInt},
+    ecdsa,
+    hashes::{hash160, Hash},
+    secp256k1, EcdsaSighashType, NetworkKind, Psbt, PublicKey, ScriptBuf, TxIn, TxOut,
+};
+
+use super::{
+    apdu::{ApduBuilder, ApduCommand, ApduResponse, Ins, StatusWord},
+    app::AppInfo,
+    LedgerCommand, LedgerError, LedgerResponse, PartialSignature,
+};
+use crate::prelude::*;
+
+/// Size of the chunks of the outputs hashed by the device.
+const OUTPUTS_CHUNK_SIZE: usize = 50;
+
+/// Returns true if the Bitcoin app only speaks the legacy protocol.
+pub fn is_legacy_app(info: &AppInfo) -> bool {
+    !info.version.at_least(2, 0, 0)
+}
+
+/// Creates the APDU command to get the public key and the chain code of the
+/// path, the address format follows the purpose of the path.
+pub fn get_wallet_public_key(path: &DerivationPath) -> Result<ApduCommand, LedgerError> {
+    let format = match path.as_ref().first() {
+        Some(ChildNumber::Hardened { index: 49 }) => 0x01,
+        Some(ChildNumber::Hardened { index: 84 }) => 0x02,
+        _ => 0x00,
+    };
+    Ok(ApduCommand::builder()
+        .ins(Ins::LegacyGetWalletPublicKey)
+        .p2(format)
+        .path(path)
+        .build()?)
+}
+
+fn hash_input_start(p1: u8, p2: u8) -> ApduBuilder {
+    ApduCommand::builder()
+        .ins(Ins::LegacyHashInputStart)
+        .p1(p1)
+        .p2(p2)
+}
+
+/// Creates the APDU commands to hash the transaction header and the inputs
+/// given as segwit inputs, with the script code of the input of the index.
+fn hash_inputs(
+    version: i32,
+    inputs: &[(&TxIn, &TxOut)],
+    new_transaction: bool,
+    script_code: Option<&ScriptBuf>,
+) -> Result<Vec<ApduCommand>, LedgerError> {
+    let mut header = version.to_le_bytes().to_vec();
+    header.extend(encode::serialize(&VarInt(inputs.len() as u64)));
+    let p2 = if new_transaction { 0x02 } else { 0x80 };
+    let mut apdus = vec![hash_input_start(0x00, p2).data(&header).build()?];
+    for (txin, utxo) in inputs {
+        let script = script_code.map(|s| s.as_bytes()).unwrap_or_default();
+        let mut input = vec![0x02];
+        input.extend(encode::serialize(&txin.previous_output));
+        input.extend(utxo.value.to_sat().to_le_bytes());
+        input.extend(encode::serialize(&VarInt(script.len() as u64)));
+        apdus.push(hash_input_start(0x80, 0x00).data(&input).build()?);
+        apdus.push(
+            hash_input_start(0x80, 0x00)
+                .data(script)
+                .data(&txin.sequence.to_consensus_u32().to_le_bytes())
+                .build()?,
+        );
+    }
+    Ok(apdus)
+}
+
+/// Creates the APDU commands to hash the outputs, the user confirms them
+/// once the last chunk is sent.
+fn hash_outputs(outputs: &[TxOut]) -> Result<Vec<ApduCommand>, LedgerError> {
+    let mut data = encode::serialize(&VarInt(outputs.len() as u64));
+    for output in outputs {
+        data.extend(encode::serialize(output));
+    }
+    let chunks = data.chunks(OUTPUTS_CHUNK_SIZE).count();
+    data.chunks(OUTPUTS_CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| {
+            Ok(ApduCommand::builder()
+                .ins(Ins::LegacyHashInputFinalizeFull)
+                .p1(if i + 1 == chunks { 0x80 } else { 0x00 })
+                .data(chunk)
+                .build()?)
+        })
+        .collect()
+}
+
+/// Creates the APDU command to sign the hashed input with the key of the path.
+fn hash_sign(
+    path: &DerivationPath,
+    lock_time: u32,
+    sighash_type: EcdsaSighashType,
+) -> Result<ApduCommand, LedgerError> {
+    Ok(ApduCommand::builder()
+        .ins(Ins::LegacyHashSign)
+        .path(path)
+        // No second factor pin.
+        .data(&[0x00])
+        .data(&lock_time.to_be_bytes())
+        .data(&[sighash_type as u8])
+        .build()?)
+}
+
+/// Parses the answer to GET_WALLET_PUBLIC_KEY: the uncompressed public key,
+/// the address and the chain code, each but the last prefixed by its length.
+fn parse_public_key(data: &[u8]) -> Option<(secp256k1::PublicKey, ChainCode)> {
+    let (len, rest) = data.split_first()?;
+    let (key, rest) = rest.split_at_checked(*len as usize)?;
+    let (len, rest) = rest.split_first()?;
+    let (_address, rest) = rest.split_at_checked(*len as usize)?;
+    let chain_code = ChainCode::from(<[u8; 32]>::try_from(rest.get(..32)?).ok()?);
+    Some((secp256k1::PublicKey::from_slice(key).ok()?, chain_code))
+}
+
+fn key_fingerprint(key: &secp256k1::PublicKey) -> Fingerprint {
+    let hash = hash160::Hash::hash(&key.serialize());
+    Fingerprint::from(<[u8; 4]>::try_from(&hash[..4]).expect("4 bytes"))
+}
+
+/// Use of the answer of an apdu.
+enum Step {
+    Ignore,
+    MasterKey,
+    ParentKey,
+    Key,
+    Signature(usize, PublicKey),
+}
+
+/// Running legacy command: the apdus left to send with the use of their
+/// answer.
+pub struct LegacySession {
+    command: LedgerCommand,
+    network: NetworkKind,
+    plan: VecDeque<(ApduCommand, Step)>,
+    /// Step of the last apdu sent.
+    step: Step,
+    parent_fingerprint: Fingerprint,
+    response: Option<LedgerResponse>,
+    signatures: Vec<(usize, PartialSignature)>,
+}
+
+impl LegacySession {
+    /// Starts the command, only the master fingerprint, the xpubs and the
+    /// signature of the psbts of the default single signature segwit v0
+    /// policies are supported by the legacy app.
+    pub fn start(
+        command: LedgerCommand,
+        network: NetworkKind,
+    ) -> Result<(Self, ApduCommand), LedgerError> {
+        let mut plan = VecDeque::new();
+        match &command {
+            LedgerCommand::GetMasterFingerprint => {
+                plan.push_back((
+                    get_wallet_public_key(&DerivationPath::master())?,
+                    Step::MasterKey,
+                ));
+            }
+            LedgerCommand::GetXpub { display: true, .. } => {
+                return Err(LedgerError::UnsupportedByLegacyApp("display_xpub"))
+            }
+            LedgerCommand::GetXpub { path, .. } => {
+                if let Some(parent) = path.as_ref().split_last().map(|(_, p)| p.to_vec()) {
+                    plan.push_back((
+                        get_wallet_public_key(&DerivationPath::from(parent))?,
+                        Step::ParentKey,
+                    ));
+                }
+                plan.push_back((get_wallet_public_key(path)?, Step::Key));
+            }
+            LedgerCommand::SignPsbt { policy, .. }
+                if ["wpkh(@0/**)", "sh(wpkh(@0/**))"]
+                    .contains(&policy.descriptor_template.as_str()) =>
+            {
+                plan.push_back((
+                    get_wallet_public_key(&DerivationPath::master())?,
+                    Step::MasterKey,
+                ));
+            }
+            LedgerCommand::SignPsbt { .. } => {
+                return Err(LedgerError::UnsupportedByLegacyApp("sign_psbt_policy"))
+            }
+            _ => return Err(LedgerError::UnsupportedByLegacyApp("command")),
+        }
+        let (apdu, step) = plan.pop_front().expect("planned apdu");
+        Ok((
+            Self {
+                command,
+                network,
+                plan,
+                step,
+                parent_fingerprint: Fingerprint::default(),
+                response: None,
+                signatures: Vec::new(),
+            },
+            apdu,
+        ))
+    }
+
+    /// Handles the answer to the last apdu, returns the next one or None
+    /// once the command ended.
+    pub fn exchange(&mut self, res: ApduResponse) -> Result<Option<ApduCommand>, LedgerError> {
+        if res.status_word != StatusWord::OK {
+            return Err(LedgerError::from_response(&res));
+        }
+        match &self.step {
+            Step::Ignore => {}
+            Step::MasterKey => {
+                let (key, _) =
+                    parse_public_key(&res.data).ok_or(LedgerError::UnexpectedResult(res.data))?;
+                let fingerprint = key_fingerprint(&key);
+                match &self.command {
+                    LedgerCommand::SignPsbt { psbt, .. } => {
+                        self.plan = sign_plan(psbt, fingerprint)?;
+                    }
+                    _ => self.response = Some(LedgerResponse::MasterFingerprint(fingerprint)),
+                }
+            }
+            Step::ParentKey => {
+                let (key, _) =
+                    parse_public_key(&res.data).ok_or(LedgerError::UnexpectedResult(res.data))?;
+                self.parent_fingerprint = key_fingerprint(&key);
+            }
+            Step::Key => {
+                let LedgerCommand::GetXpub { path, .. } = &self.command else {
+                    return Err(LedgerError::UnexpectedResult(res.data));
+                };
+                let (public_key, chain_code) =
+                    parse_public_key(&res.data).ok_or(LedgerError::UnexpectedResult(res.data))?;
+                self.response = Some(LedgerResponse::Xpub(Xpub {
+                    network: self.network,
+                    depth: path.len() as u8,
+                    parent_fingerprint: self.parent_fingerprint,
+                    child_number: path
+                        .as_ref()
+                        .last()
+                        .copied()
+                        .unwrap_or(ChildNumber::Normal { index: 0 }),
+                    public_key,
+                    chain_code,
+                }));
+            }
+            Step::Signature(index, key) => {
+                let mut data = res.data;
+                // The first byte carries the parity of the nonce.
+                if let Some(first) = data.first_mut() {
+                    *first = 0x30;
+                }
+                let signature = ecdsa::Signature::from_slice(&data)
+                    .map_err(|_| LedgerError::UnexpectedResult(data))?;
+                self.signatures
+                    .push((*index, PartialSignature::Sig(*key, signature)));
+            }
+        }
+        match self.plan.pop_front() {
+            Some((apdu, step)) => {
+                self.step = step;
+                Ok(Some(apdu))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the response of the ended command.
+    pub fn end(self) -> LedgerResponse {
+        self.response
+            .unwrap_or(LedgerResponse::Signatures(self.signatures))
+    }
+}
+
+/// Returns the apdus signing the inputs of the psbt with the keys of the
+/// master fingerprint: the whole transaction is hashed once, then each input
+/// is hashed alone with its script code and signed.
+fn sign_plan(
+    psbt: &Psbt,
+    fingerprint: Fingerprint,
+) -> Result<VecDeque<(ApduCommand, Step)>, LedgerError> {
+    let tx = &psbt.unsigned_tx;
+    let mut inputs = Vec::with_capacity(tx.input.len());
+    let mut own = Vec::new();
+    for (index, (txin, input)) in tx.input.iter().zip(&psbt.inputs).enumerate() {
+        let utxo = input
+            .witness_utxo
+            .as_ref()
+            .filter(|utxo| {
+                utxo.script_pubkey.is_p2wpkh()
+                    || input
+                        .redeem_script
+                        .as_ref()
+                        .is_some_and(|script| script.is_p2wpkh())
+            })
+            .ok_or(LedgerError::UnsupportedByLegacyApp("non_segwit_input"))?;
+        inputs.push((txin, utxo));
+        for (key, (fg, path)) in &input.bip32_derivation {
+            if *fg == fingerprint {
+                let sighash_type = input
+                    .sighash_type
+                    .map(|t| t.ecdsa_hash_ty())
+                    .transpose()
+                    .map_err(|_| LedgerError::UnsupportedSighash(index))?
+                    .unwrap_or(EcdsaSighashType::All);
+                own.push((index, PublicKey::new(*key), path, sighash_type));
+            }
+        }
+    }
+
+    let mut plan: VecDeque<(ApduCommand, Step)> = hash_inputs(tx.version.0, &inputs, true, None)?
+        .into_iter()
+        .chain(hash_outputs(&tx.output)?)
+        .map(|apdu| (apdu, Step::Ignore))
+        .collect();
+    for (index, key, path, sighash_type) in own {
+        let script_code = ScriptBuf::new_p2pkh(&key.pubkey_hash());
+        plan.extend(
+            hash_inputs(
+                tx.version.0,
+                &inputs[index..=index],
+                false,
+                Some(&script_code),
+            )?
+            .into_iter()
+            .map(|apdu| (apdu, Step::Ignore)),
+        );
+        plan.push_back((
+            hash_sign(path, tx.lock_time.to_consensus_u32(), sighash_type)?,
+            Step::Signature(index, key),
+        ));
+    }
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime,
+        bip32::Xpriv,
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1},
+        sighash::SighashCache,
+        transaction::Version,
+        Amount, Network, OutPoint, Sequence, Transaction, Txid, Witness,
+    };
+    use core::str::FromStr;
+
+    use crate::wallet::WalletPolicy;
+
+    /// Answer of the app to GET_WALLET_PUBLIC_KEY.
+    fn public_key_answer(xpriv: &Xpriv) -> Vec<u8> {
+        let secp = Secp256k1::new();
+        let key = xpriv.private_key.public_key(&secp).serialize_uncompressed();
+        let mut data = vec![key.len() as u8];
+        data.extend(key);
+        data.extend([0x03, b'a', b'b', b'c']);
+        data.extend(xpriv.chain_code.to_bytes());
+        data.extend([0x90, 0x00]);
+        data
+    }
+
+    fn answer(data: Vec<u8>) -> ApduResponse {
+        ApduResponse::try_from(data).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_xpub() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let parent = master
+            .derive_priv(&secp, &DerivationPath::from_str("m/84'/1'").unwrap())
+            .unwrap();
+        let account = master.derive_priv(&secp, &path).unwrap();
+
+        let (mut session, apdu) = LegacySession::start(
+            LedgerCommand::GetXpub {
+                path: path.clone(),
+                display: false,
+            },
+            NetworkKind::Test,
+        )
+        .unwrap();
+        assert_eq!(apdu.encode()[..5], [0xe0, 0x40, 0x00, 0x02, 0x09]);
+        let apdu = session
+            .exchange(answer(public_key_answer(&parent)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(apdu.data.len(), 13);
+        assert!(session
+            .exchange(answer(public_key_answer(&account)))
+            .unwrap()
+            .is_none());
+        match session.end() {
+            LedgerResponse::Xpub(xpub) => assert_eq!(xpub, Xpub::from_priv(&secp, &account)),
+            _ => panic!("expected an xpub"),
+        }
+
+        assert!(matches!(
+            LegacySession::start(
+                LedgerCommand::GetXpub {
+                    path,
+                    display: true
+                },
+                NetworkKind::Test
+            ),
+            Err(LedgerError::UnsupportedByLegacyApp(_))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_sign_psbt() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let fingerprint = master.fingerprint(&secp);
+        let account_path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let account = master.derive_priv(&secp, &account_path).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let key = master.derive_priv(&secp, &path).unwrap().private_key;
+        let pubkey = PublicKey::new(key.public_key(&secp));
+
+        let utxo = TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+        };
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: utxo.script_pubkey.clone(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(utxo.clone());
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(pubkey.inner, (fingerprint, path));
+        let policy = WalletPolicy::new_singlesig(
+            (fingerprint, account_path),
+            Xpub::from_priv(&secp, &account),
+        )
+        .unwrap();
+
+        let (mut session, _) = LegacySession::start(
+            LedgerCommand::SignPsbt {
+                psbt: Box::new(psbt),
+                policy,
+                hmac: None,
+            },
+            NetworkKind::Test,
+        )
+        .unwrap();
+        let mut apdu = session
+            .exchange(answer(public_key_answer(&master)))
+            .unwrap()
+            .unwrap();
+        // The whole transaction, then the input with its script code.
+        let mut sent = 1;
+        while apdu.ins != Ins::LegacyHashSign.code() {
+            apdu = session.exchange(answer(vec![0x90, 0x00])).unwrap().unwrap();
+            sent += 1;
+        }
+        assert_eq!(sent, 3 + 1 + 3 + 1);
+
+        let sighash = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(0, &utxo.script_pubkey, utxo.value, EcdsaSighashType::All)
+            .unwrap();
+        let signature = secp.sign_ecdsa(&Message::from(sighash), &key);
+        let mut data = signature.serialize_der().to_vec();
+        data[0] |= 0x01;
+        data.extend([EcdsaSighashType::All as u8, 0x90, 0x00]);
+        assert!(session.exchange(answer(data)).unwrap().is_none());
+        match session.end() {
+            LedgerResponse::Signatures(signatures) => assert_eq!(
+                signatures,
+                vec![(
+                    0,
+                    PartialSignature::Sig(pubkey, ecdsa::Signature::sighash_all(signature))
+                )]
+            ),
+            _ => panic!("expected signatures"),
+        }
+    }
+}
@@ -1,25 +1,53 @@
-mod command;
-mod merkle;
-mod store;
-
 pub mod apdu;
+pub mod app;
+pub mod command;
+pub mod config;
+#[cfg(feature = "elements")]
+pub mod elements;
 pub mod error;
-pub mod psbt;
-pub mod wallet;
+pub mod legacy;
+pub mod model;
+pub mod store;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testvectors;
+pub mod transport;
+
+/// Policies and psbt formats of the Ledger Bitcoin app, shared with the
+/// other backends.
+pub use crate::{merkle, merkleized_map, psbt, wallet};
 
+use alloc::collections::VecDeque;
+pub use app::{capabilities, AppInfo, InstalledApp};
+use base64ct::{Base64, Encoding};
 use bitcoin::{
-    bip32::{DerivationPath, Fingerprint, Xpub},
-    Network,
+    address::NetworkUnchecked,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    consensus::encode::{self, VarInt},
+    sign_message::MessageSignature,
+    Address, Network, NetworkKind, Psbt,
 };
-use std::str::FromStr;
-pub use wallet::{WalletPolicy, WalletPubKey};
+pub use config::LedgerConfig;
+use core::{convert::Infallible, str::FromStr};
+pub use error::DeviceErrorDetail;
+pub use model::DeviceModel;
+pub use psbt::{MusigPartialSignature, MusigPubNonce, PartialSignature};
+pub use wallet::{MemoryHmacStore, WalletError, WalletHmacStore, WalletPolicy, WalletPubKey};
 
-use crate::Interpreter;
+use crate::{
+    bip322, log_event,
+    path::{self, PathPolicy, PathWarning},
+    prelude::*,
+    Event, Interpreter, InterpreterStatus,
+};
 
-use apdu::{ApduCommand, ApduError, ApduResponse, StatusWord};
+use apdu::{ApduCommand, ApduError, ApduResponse, ClientCommandCode, StatusWord};
 use store::{DelegatedStore, StoreError};
 
+/// Size of the chunks of the merkleized message to sign.
+const MESSAGE_CHUNK_SIZE: usize = 64;
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum LedgerError {
     MissingCommandInfo(&'static str),
     NoErrorOrResult,
@@ -28,6 +56,86 @@ pub enum LedgerError {
     Interrupted,
     UnexpectedResult(Vec<u8>),
     FailedToOpenApp(Vec<u8>),
+    /// The user rejected the request on the device.
+    DeniedByUser,
+    /// The device must be unlocked with its PIN.
+    DeviceLocked,
+    /// The Bitcoin app is not open.
+    AppNotOpen,
+    /// The app to open is not installed.
+    AppNotInstalled,
+    /// The request was malformed or out of range.
+    WrongParameters(StatusWord),
+    /// Any other status word of failure.
+    Status(StatusWord),
+    /// A failure with the details answered by the app, instead of
+    /// [`LedgerError::WrongParameters`] and [`LedgerError::Status`].
+    Detailed(StatusWord, DeviceErrorDetail),
+    /// The command was cancelled before its end.
+    Cancelled,
+    /// The command did not end before its deadline, see
+    /// [`LedgerInterpreter::with_deadline`].
+    Timeout,
+    /// The returned xpub does not derive from the expected master key.
+    MismatchedDevice,
+    /// The open app, a key, a path or the returned value is for another
+    /// network than the one of the interpreter.
+    NetworkMismatch,
+    /// The sighash type of the input at the index is not valid for its
+    /// script, see [`psbt::invalid_sighash`].
+    UnsupportedSighash(usize),
+    /// A path of the command is unusual and the policy rejects it, see
+    /// [`LedgerInterpreter::with_path_policy`].
+    UnusualPath(PathWarning),
+    /// The name or the template of the policy is rejected by the app, see
+    /// [`WalletPolicy::validate`].
+    InvalidPolicy(WalletError),
+    /// The legacy app before 2.0 does not support the command or its
+    /// arguments, see [`legacy::LegacySession::start`].
+    UnsupportedByLegacyApp(&'static str),
+    /// The app does not support the command.
+    UnsupportedCommand(&'static str),
+    /// The address is not one of the policy below the gap limit.
+    AddressNotOwned,
+    /// The PSET to sign cannot be read, see [`psbt::get_pset_maps`].
+    #[cfg(feature = "elements")]
+    InvalidPset(String),
+}
+
+impl From<StatusWord> for LedgerError {
+    fn from(status_word: StatusWord) -> Self {
+        match status_word {
+            StatusWord::Deny | StatusWord::OpenAppDenied => LedgerError::DeniedByUser,
+            StatusWord::DeviceLocked | StatusWord::SecurityStatusNotSatisfied => {
+                LedgerError::DeviceLocked
+            }
+            StatusWord::AppNotOpen
+            | StatusWord::InsNotSupported
+            | StatusWord::ClaNotSupported
+            | StatusWord::ClaNotSupportedNoApp => LedgerError::AppNotOpen,
+            StatusWord::AppNotInstalled => LedgerError::AppNotInstalled,
+            StatusWord::IncorrectData | StatusWord::WrongP1P2 | StatusWord::WrongDataLength => {
+                LedgerError::WrongParameters(status_word)
+            }
+            _ => LedgerError::Status(status_word),
+        }
+    }
+}
+
+impl LedgerError {
+    /// Returns the error of the failure answered by the device, with the
+    /// details of its body if any, see [`DeviceErrorDetail::decode`].
+    pub fn from_response(res: &ApduResponse) -> Self {
+        match (
+            LedgerError::from(res.status_word),
+            DeviceErrorDetail::decode(&res.data),
+        ) {
+            (LedgerError::WrongParameters(sw) | LedgerError::Status(sw), Some(detail)) => {
+                LedgerError::Detailed(sw, detail)
+            }
+            (error, _) => error,
+        }
+    }
 }
 
 impl From<ApduError> for LedgerError {
@@ -36,6 +144,12 @@ impl From<ApduError> for LedgerError {
     }
 }
 
+impl From<Infallible> for LedgerError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
 impl From<StoreError> for LedgerError {
     fn from(value: StoreError) -> Self {
         LedgerError::Store(value)
@@ -43,16 +157,142 @@ impl From<StoreError> for LedgerError {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LedgerCommand {
     OpenApp(Network),
+    /// Quits the open app, the device goes back to the dashboard.
+    QuitApp,
+    /// Name and version of the open app, or of the dashboard.
+    GetAppInfo,
+    /// Name and version of the open app in one exchange, without waiting for
+    /// the unlock: answers `Status(None)` while the device is locked.
+    GetStatus,
+    /// Lists the apps installed, once allowed by the user. The dashboard must
+    /// be running.
+    ListApps,
+    /// Returns the info of the Bitcoin app of the network if it is open,
+    /// otherwise quits the open app or opens the Bitcoin app from the
+    /// dashboard and returns TaskDone: the device then re-enumerates and the
+    /// command must be run again once reconnected.
+    EnsureApp(Network),
     GetMasterFingerprint,
-    GetXpub { path: DerivationPath, display: bool },
+    GetXpub {
+        path: DerivationPath,
+        display: bool,
+    },
+    /// Retrieves the xpubs of the paths in a row, without display.
+    GetXpubs(Vec<DerivationPath>),
+    SignPsbt {
+        psbt: Box<Psbt>,
+        policy: WalletPolicy,
+        /// Proof of registration of the policy, None for default wallets.
+        hmac: Option<[u8; 32]>,
+    },
+    /// Runs a round of MuSig2 signing of the psbt, supported by the app from
+    /// 2.2.0: the first yields the nonces of the device, the second its
+    /// partial signatures once the nonces of all participants are added.
+    MusigSignPsbt {
+        psbt: Box<Psbt>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+    },
+    /// Signs the BIP-322 proof of the address of the policy built by
+    /// [`bip322::build_psbt`], answers the encoded proof or the partial one
+    /// of a multisig policy.
+    ProveAddressOwnership {
+        psbt: Box<Psbt>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        format: bip322::ProofFormat,
+    },
+    /// Displays the address of the policy at the index found by the host,
+    /// see [`psbt::find_address_index`], and checks it is the expected one.
+    VerifyOwnedAddress {
+        address: Address<NetworkUnchecked>,
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        change: bool,
+        index: u32,
+    },
+    RegisterWallet(WalletPolicy),
+    GetWalletAddress {
+        policy: WalletPolicy,
+        hmac: Option<[u8; 32]>,
+        change: bool,
+        index: u32,
+        display: bool,
+    },
+    SignMessage {
+        path: DerivationPath,
+        message: Vec<u8>,
+    },
+    /// Commands of the Liquid app.
+    #[cfg(feature = "elements")]
+    Liquid(elements::LiquidCommand),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LedgerResponse {
     TaskDone,
+    AppInfo(AppInfo),
+    /// Open app, None while the device is locked.
+    Status(Option<AppInfo>),
+    Apps(Vec<InstalledApp>),
     MasterFingerprint(Fingerprint),
     Xpub(Xpub),
+    /// Xpubs of the paths, in their order.
+    Xpubs(Vec<(DerivationPath, Xpub)>),
+    /// Signatures yielded by the device with the index of their input.
+    Signatures(Vec<(usize, PartialSignature)>),
+    /// MuSig2 nonces of the first round with the index of their input.
+    MusigNonces(Vec<(usize, MusigPubNonce)>),
+    /// MuSig2 partial signatures of the second round with the index of their
+    /// input.
+    MusigPartialSigs(Vec<(usize, MusigPartialSignature)>),
+    /// Id of the registered policy and the proof of its registration.
+    WalletRegistered {
+        id: [u8; 32],
+        hmac: [u8; 32],
+    },
+    Address(Address<NetworkUnchecked>),
+    /// Recoverable signature of the message, base64 encoded.
+    MessageSignature(String),
+    OwnershipProof(bip322::Proof),
+    /// Change flag and index of the address confirmed on the device.
+    OwnedAddress {
+        change: bool,
+        index: u32,
+    },
+    /// SLIP-77 master blinding key of the Liquid wallet.
+    #[cfg(feature = "elements")]
+    MasterBlindingKey([u8; 32]),
+}
+
+/// Retries of the last apdu on the transient failures of the device or of
+/// the transport: the technical problem status word and the truncated
+/// answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    /// Delay before the first retry, doubled at each next retry.
+    pub backoff_ms: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            backoff_ms: 100,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the retry of the attempt, starting at 1.
+    pub fn delay_ms(&self, attempt: usize) -> u32 {
+        let doublings = attempt.saturating_sub(1).min(16) as u32;
+        self.backoff_ms.saturating_mul(1 << doublings)
+    }
 }
 
 #[derive(Default)]
@@ -64,65 +304,340 @@ enum State {
         store: Option<DelegatedStore>,
     },
     Finished(LedgerResponse),
+    Cancelled,
+    TimedOut,
 }
 
 pub struct LedgerInterpreter<C, T, R, E> {
     state: State,
-    _marker: std::marker::PhantomData<(C, T, R, E)>,
+    /// Master fingerprint of the device, checked against the returned xpubs.
+    master_fingerprint: Option<Fingerprint>,
+    /// Network the commands and the answers are checked against.
+    network: Option<Network>,
+    /// Apdu of the signing command, sent once the open app is checked.
+    pending: Option<ApduCommand>,
+    /// Proofs of registration of the policies, see [`WalletHmacStore`].
+    hmac_store: Option<Box<dyn WalletHmacStore>>,
+    /// Xpubs retrieved so far by GetXpubs.
+    xpubs: Vec<(DerivationPath, Xpub)>,
+    /// Apps listed so far by ListApps.
+    apps: Vec<InstalledApp>,
+    /// Chunks of the command left to send, see [`ApduCommand::chunks`].
+    chunks: VecDeque<ApduCommand>,
+    /// Probes sent to a locked device before failing, see
+    /// [`LedgerInterpreter::with_unlock_wait`], with the interval between
+    /// them.
+    unlock_wait: Option<(usize, u32)>,
+    /// First apdu of the running command until its first answer, sent again
+    /// once the device is unlocked.
+    first_apdu: Option<ApduCommand>,
+    /// Probes sent since the device answered it is locked.
+    probes: usize,
+    /// The user confirmed the running command: the device yielded or
+    /// answered it.
+    confirmed: bool,
+    /// See [`LedgerInterpreter::with_retry_policy`].
+    retry_policy: Option<RetryPolicy>,
+    /// Last apdu sent, sent again on a transient failure.
+    last_apdu: Option<ApduCommand>,
+    /// Retries of the last apdu so far.
+    retries: usize,
+    /// See [`LedgerInterpreter::with_path_policy`].
+    path_policy: PathPolicy,
+    /// Network of the legacy app, see
+    /// [`LedgerInterpreter::with_legacy_app`].
+    legacy_app: Option<Network>,
+    /// Running command of the legacy app.
+    legacy: Option<legacy::LegacySession>,
+    /// See [`LedgerInterpreter::with_deadline`].
+    deadline_ms: Option<u64>,
+    /// See [`LedgerInterpreter::with_config`].
+    config: LedgerConfig,
+    events: VecDeque<Event>,
+    _marker: core::marker::PhantomData<(C, T, R, E)>,
 }
 
 impl<C, T, R, E> Default for LedgerInterpreter<C, T, R, E> {
     fn default() -> Self {
         Self {
             state: State::default(),
-            _marker: std::marker::PhantomData,
+            master_fingerprint: None,
+            network: None,
+            pending: None,
+            hmac_store: None,
+            xpubs: Vec::new(),
+            apps: Vec::new(),
+            chunks: VecDeque::new(),
+            unlock_wait: None,
+            first_apdu: None,
+            probes: 0,
+            confirmed: false,
+            retry_policy: None,
+            last_apdu: None,
+            retries: 0,
+            path_policy: PathPolicy::Allow,
+            legacy_app: None,
+            legacy: None,
+            deadline_ms: None,
+            config: LedgerConfig::default(),
+            events: VecDeque::new(),
+            _marker: core::marker::PhantomData,
         }
     }
 }
 
-impl<C, T, R, E> Interpreter for LedgerInterpreter<C, T, R, E>
-where
-    C: TryInto<LedgerCommand, Error = LedgerError>,
-    T: From<ApduCommand>,
-    R: From<LedgerResponse>,
-    E: From<LedgerError>,
-{
-    type Command = C;
-    type Transmit = T;
-    type Response = R;
-    type Error = E;
+impl<C, T, R, E> LedgerInterpreter<C, T, R, E> {
+    /// Checks that the xpubs returned by GetXpub and GetXpubs are consistent
+    /// with their path and the master fingerprint of the device.
+    pub fn with_master_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.master_fingerprint = Some(fingerprint);
+        self
+    }
 
-    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
-        let command: LedgerCommand = command.try_into()?;
-        let (transmit, store) = match command {
-            LedgerCommand::GetMasterFingerprint => (
-                Self::Transmit::from(command::get_master_fingerprint()),
-                None,
-            ),
-            LedgerCommand::GetXpub { ref path, display } => (
-                Self::Transmit::from(command::get_extended_pubkey(path, display)),
-                None,
-            ),
-            LedgerCommand::OpenApp(network) => {
-                (Self::Transmit::from(command::open_app(network)), None)
+    /// Checks the paths and the keys of the commands against the network, as
+    /// well as the returned xpubs and addresses. The open app is checked
+    /// before signing a psbt.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Supplies the missing hmac of the registered policies from the store,
+    /// and keeps there the hmac of the policies registered.
+    pub fn with_hmac_store(mut self, store: impl WalletHmacStore + 'static) -> Self {
+        self.hmac_store = Some(Box::new(store));
+        self
+    }
+
+    /// Waits for the user to unlock the device when it answers the first apdu
+    /// of a command as locked: the device is probed with GET_VERSION up to
+    /// the given number of times, with an [`Event::AwaitingUnlock`] asking
+    /// to wait for the interval before each probe, then the apdu is sent
+    /// again.
+    pub fn with_unlock_wait(mut self, probes: usize, interval_ms: u32) -> Self {
+        self.unlock_wait = Some((probes, interval_ms));
+        self
+    }
+
+    /// Sends the last apdu again when the device answers a technical problem
+    /// or the answer is truncated, with an [`Event::Retrying`] before each
+    /// retry. Without policy, the transient failures fail the command.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Checks the paths of GetXpub, GetXpubs and SignMessage against the
+    /// standard schemes, and the network if set, see [`path::check_path`].
+    pub fn with_path_policy(mut self, policy: PathPolicy) -> Self {
+        self.path_policy = policy;
+        self
+    }
+
+    /// Speaks the protocol of the Bitcoin app of the network before 2.0 for
+    /// the commands of the app, see [`legacy::is_legacy_app`]. The commands
+    /// of the dashboard are unchanged.
+    pub fn with_legacy_app(mut self, network: Network) -> Self {
+        self.legacy_app = Some(network);
+        self
+    }
+
+    /// Fails the running command with [`LedgerError::Timeout`] once the time
+    /// notified by [`Interpreter::tick`] reaches the deadline, instead of
+    /// waiting for the device forever.
+    pub fn with_deadline(mut self, deadline_ms: u64) -> Self {
+        self.deadline_ms = Some(deadline_ms);
+        self
+    }
+
+    /// Sends the apdus with the classes and the instructions of the config,
+    /// and opens and expects the apps of its names.
+    pub fn with_config(mut self, config: LedgerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns the last apdu to send again if the answer is a transient
+    /// failure and retries are left.
+    fn retry(&mut self, data: &[u8]) -> Option<ApduCommand> {
+        let (policy, last) = (self.retry_policy?, self.last_apdu.as_ref()?);
+        let transient = data.len() < 2 || status_word(data) == Some(StatusWord::TechnicalProblem);
+        if !transient || self.retries >= policy.max_retries {
+            return None;
+        }
+        self.retries += 1;
+        log_event!(debug, "transient failure, retry {}", self.retries);
+        self.events
+            .push_back(Event::Retrying(self.retries, policy.delay_ms(self.retries)));
+        Some(last.clone())
+    }
+
+    /// Returns the status of the running command, to be polled between the
+    /// exchanges. A command requiring a confirmation awaits the user until
+    /// the device yields a first value or answers.
+    pub fn status(&self) -> InterpreterStatus {
+        match &self.state {
+            State::New | State::Cancelled | State::TimedOut => InterpreterStatus::Idle,
+            State::Finished(_) => InterpreterStatus::Done,
+            State::Running { .. } if self.probes > 0 => InterpreterStatus::AwaitingUserAction,
+            State::Running { .. } if !self.chunks.is_empty() => InterpreterStatus::Transmitting,
+            State::Running { command, .. }
+                if self.pending.is_none() && !self.confirmed && requires_confirmation(command) =>
+            {
+                InterpreterStatus::AwaitingUserAction
             }
+            State::Running { .. } => InterpreterStatus::AwaitingDevice,
+        }
+    }
+
+    /// Returns the apdu to send while waiting for the device to be unlocked,
+    /// None once the first answer of the command is not a locked one.
+    fn wait_unlock(&mut self, data: &[u8]) -> Result<Option<ApduCommand>, LedgerError> {
+        let (Some((max_probes, interval_ms)), Some(first)) = (self.unlock_wait, &self.first_apdu)
+        else {
+            return Ok(None);
         };
-        self.state = State::Running { command, store };
-        Ok(transmit)
+        let locked = matches!(
+            status_word(data),
+            Some(StatusWord::DeviceLocked | StatusWord::SecurityStatusNotSatisfied)
+        );
+        if !locked {
+            if self.probes == 0 {
+                self.first_apdu = None;
+                return Ok(None);
+            }
+            log_event!(debug, "device unlocked after {} probes", self.probes);
+            self.probes = 0;
+            let first = first.clone();
+            return Ok(Some(self.chunked(first)));
+        }
+        if self.probes >= max_probes {
+            self.first_apdu = None;
+            return if self.probes == 0 {
+                Ok(None)
+            } else {
+                Err(LedgerError::DeviceLocked)
+            };
+        }
+        self.probes += 1;
+        self.chunks.clear();
+        self.events.push_back(Event::AwaitingUnlock(interval_ms));
+        Ok(Some(command::get_version()))
     }
-    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+
+    /// Returns the first chunk of the command, the next ones are sent as the
+    /// previous ones are acknowledged.
+    fn chunked(&mut self, command: ApduCommand) -> ApduCommand {
+        if command.data.len() <= apdu::MAX_DATA_LEN {
+            self.chunks.clear();
+            return command;
+        }
+        self.chunks = command.chunks().into();
+        self.chunks.pop_front().expect("at least one chunk")
+    }
+}
+
+impl<C, T, R, E> LedgerInterpreter<C, T, R, E>
+where
+    E: From<LedgerError>,
+{
+    /// Returns the next apdu of the running command from the answer of the
+    /// device.
+    fn exchange_apdu(&mut self, data: Vec<u8>) -> Result<Option<ApduCommand>, E> {
+        if let Some(apdu) = self.wait_unlock(&data)? {
+            return Ok(Some(apdu));
+        }
+        if let Some(chunk) = self.chunks.pop_front() {
+            let res = ApduResponse::try_from(data).map_err(LedgerError::from)?;
+            log_event!(
+                trace,
+                "chunk acknowledged: {:?}, {} chunks left",
+                res.status_word,
+                self.chunks.len()
+            );
+            if res.status_word != StatusWord::OK {
+                self.chunks.clear();
+                return Err(LedgerError::from_response(&res).into());
+            }
+            return Ok(Some(chunk));
+        }
         if let State::Running { store, command } = &mut self.state {
             let res = ApduResponse::try_from(data).map_err(LedgerError::from)?;
+            log_event!(
+                debug,
+                "{} answered {:?}, {} bytes",
+                command_name(command),
+                res.status_word,
+                res.data.len()
+            );
+            if let Some(pending) = self.pending.take() {
+                if res.status_word != StatusWord::OK {
+                    return Err(LedgerError::from_response(&res).into());
+                }
+                let info = AppInfo::from_slice(&res.data)
+                    .ok_or(LedgerError::UnexpectedResult(res.data))?;
+                if !self
+                    .network
+                    .is_some_and(|n| info.name == self.config.app_name(n))
+                {
+                    return Err(LedgerError::NetworkMismatch.into());
+                }
+                return Ok(Some(self.chunked(pending)));
+            }
+            if let Some(session) = self.legacy.as_mut() {
+                if let Some(apdu) = session.exchange(res)? {
+                    return Ok(Some(apdu));
+                }
+                let response = self.legacy.take().expect("legacy session").end();
+                if let (LedgerResponse::Xpub(xpub), LedgerCommand::GetXpub { path, .. }, Some(fg)) =
+                    (&response, &*command, self.master_fingerprint)
+                {
+                    check_xpub(xpub, path, fg)?;
+                }
+                self.confirmed = true;
+                self.state = State::Finished(response);
+                return Ok(None);
+            }
             if res.status_word == StatusWord::InterruptedExecution {
+                if res.data.first() == Some(&(ClientCommandCode::Yield as u8)) {
+                    self.confirmed = true;
+                }
+                if let (LedgerCommand::SignPsbt { psbt, .. }, Some((&yield_code, value))) =
+                    (&command, res.data.split_first())
+                {
+                    if yield_code == ClientCommandCode::Yield as u8 {
+                        if let Ok(Yielded::Signature(index, _)) = decode_yielded(value) {
+                            self.events
+                                .push_back(Event::InputSigned(index, psbt.inputs.len()));
+                        }
+                    }
+                }
                 if let Some(store) = store {
-                    let transmit = store.execute(res.data).map_err(LedgerError::from)?;
-                    return Ok(Some(Self::Transmit::from(command::continue_interrupted(
-                        transmit,
-                    ))));
+                    let transmit = match command_psbt(command) {
+                        Some(psbt) => store.execute_with_psbt(res.data, psbt),
+                        None => store.execute(res.data),
+                    }
+                    .map_err(LedgerError::from)?;
+                    return Ok(Some(
+                        command::continue_interrupted(transmit).map_err(LedgerError::from)?,
+                    ));
                 } else {
                     return Err(LedgerError::Interrupted.into());
                 }
             }
+            match (&command, res.status_word) {
+                (_, StatusWord::OK) => self.confirmed = true,
+                // An app is already open and the cla cannot be supported
+                (LedgerCommand::OpenApp(..), StatusWord::ClaNotSupported) => {}
+                (
+                    LedgerCommand::GetStatus,
+                    StatusWord::DeviceLocked | StatusWord::SecurityStatusNotSatisfied,
+                ) => {
+                    self.state = State::Finished(LedgerResponse::Status(None));
+                    return Ok(None);
+                }
+                _ => return Err(LedgerError::from_response(&res).into()),
+            }
             match command {
                 LedgerCommand::GetMasterFingerprint => {
                     if res.data.len() < 4 {
@@ -135,30 +650,1672 @@ where
                         ));
                     }
                 }
-                LedgerCommand::GetXpub { .. } => {
+                LedgerCommand::GetXpub { path, .. } => {
                     let xpub = Xpub::from_str(&String::from_utf8_lossy(&res.data))
                         .map_err(|_| LedgerError::UnexpectedResult(res.data))?;
+                    if let Some(fg) = self.master_fingerprint {
+                        check_xpub(&xpub, path, fg)?;
+                    }
+                    if self
+                        .network
+                        .is_some_and(|n| NetworkKind::from(n) != xpub.network)
+                    {
+                        return Err(LedgerError::NetworkMismatch.into());
+                    }
                     self.state = State::Finished(LedgerResponse::Xpub(xpub));
                 }
+                LedgerCommand::GetXpubs(paths) => {
+                    let xpub = Xpub::from_str(&String::from_utf8_lossy(&res.data))
+                        .map_err(|_| LedgerError::UnexpectedResult(res.data))?;
+                    let path = &paths[self.xpubs.len()];
+                    if let Some(fg) = self.master_fingerprint {
+                        check_xpub(&xpub, path, fg)?;
+                    }
+                    if self
+                        .network
+                        .is_some_and(|n| NetworkKind::from(n) != xpub.network)
+                    {
+                        return Err(LedgerError::NetworkMismatch.into());
+                    }
+                    self.xpubs.push((path.clone(), xpub));
+                    if let Some(next) = paths.get(self.xpubs.len()) {
+                        return Ok(Some(
+                            command::get_extended_pubkey(next, false).map_err(LedgerError::from)?,
+                        ));
+                    }
+                    self.state =
+                        State::Finished(LedgerResponse::Xpubs(core::mem::take(&mut self.xpubs)));
+                }
                 LedgerCommand::OpenApp(..) => {
-                    if res.status_word == StatusWord::OK ||
-                    // An app is already open and the cla cannot be supported
-                    res.status_word == StatusWord::ClaNotSupported
+                    // Otherwise the app was already open.
+                    if res.status_word == StatusWord::OK {
+                        self.events.push_back(Event::Reenumerating);
+                    }
+                    self.state = State::Finished(LedgerResponse::TaskDone);
+                }
+                LedgerCommand::QuitApp => {
+                    self.events.push_back(Event::Reenumerating);
+                    self.state = State::Finished(LedgerResponse::TaskDone);
+                }
+                LedgerCommand::GetStatus => {
+                    let info = AppInfo::from_slice(&res.data)
+                        .ok_or(LedgerError::UnexpectedResult(res.data))?;
+                    self.state = State::Finished(LedgerResponse::Status(Some(info)));
+                }
+                LedgerCommand::ListApps => {
+                    let apps = InstalledApp::from_page(&res.data)
+                        .ok_or(LedgerError::UnexpectedResult(res.data))?;
+                    if !apps.is_empty() {
+                        self.apps.extend(apps);
+                        return Ok(Some(command::list_apps(false)));
+                    }
+                    self.state =
+                        State::Finished(LedgerResponse::Apps(core::mem::take(&mut self.apps)));
+                }
+                LedgerCommand::GetAppInfo | LedgerCommand::EnsureApp(..) => {
+                    let info = AppInfo::from_slice(&res.data)
+                        .ok_or(LedgerError::UnexpectedResult(res.data))?;
+                    let next = match command {
+                        LedgerCommand::EnsureApp(network)
+                            if info.name != self.config.app_name(*network) =>
+                        {
+                            if info.is_dashboard() {
+                                LedgerCommand::OpenApp(*network)
+                            } else {
+                                LedgerCommand::QuitApp
+                            }
+                        }
+                        _ => {
+                            self.state = State::Finished(LedgerResponse::AppInfo(info));
+                            return Ok(None);
+                        }
+                    };
+                    let transmit = match next {
+                        LedgerCommand::OpenApp(network) => {
+                            command::open_app(self.config.app_name(network))
+                        }
+                        _ => command::quit_app(),
+                    };
+                    *command = next;
+                    return Ok(Some(transmit));
+                }
+                LedgerCommand::SignPsbt { .. } => {
+                    self.state = State::Finished(LedgerResponse::Signatures(yielded_signatures(
+                        store.take(),
+                    )?));
+                }
+                LedgerCommand::MusigSignPsbt { .. } => {
+                    let mut nonces = Vec::new();
+                    let mut signatures = Vec::new();
+                    for value in store
+                        .take()
+                        .map(DelegatedStore::yielded)
+                        .unwrap_or_default()
                     {
-                        self.state = State::Finished(LedgerResponse::TaskDone);
+                        match decode_yielded(&value)? {
+                            Yielded::MusigPubNonce(index, nonce) => nonces.push((index, nonce)),
+                            Yielded::MusigPartialSignature(index, sig) => {
+                                signatures.push((index, sig))
+                            }
+                            // Signatures of the keys of the policy out of the
+                            // musig() expressions are not expected.
+                            Yielded::Signature(..) => {}
+                        }
+                    }
+                    self.state = State::Finished(if signatures.is_empty() {
+                        LedgerResponse::MusigNonces(nonces)
                     } else {
+                        LedgerResponse::MusigPartialSigs(signatures)
+                    });
+                }
+                LedgerCommand::ProveAddressOwnership {
+                    psbt,
+                    policy,
+                    format,
+                    ..
+                } => {
+                    let mut psbt = psbt.as_ref().clone();
+                    for (index, signature) in yielded_signatures(store.take())? {
+                        let input = psbt
+                            .inputs
+                            .get_mut(index)
+                            .ok_or(LedgerError::UnexpectedResult(res.data.clone()))?;
+                        signature.add_to(input);
+                    }
+                    // The psbt was built from the policy, only signatures can
+                    // be missing.
+                    let proof = bip322::prove(psbt, policy, *format)
+                        .map_err(|_| LedgerError::UnexpectedResult(res.data))?;
+                    self.state = State::Finished(LedgerResponse::OwnershipProof(proof));
+                }
+                LedgerCommand::RegisterWallet(..) => {
+                    if res.data.len() != 64 {
                         return Err(LedgerError::UnexpectedResult(res.data).into());
                     }
+                    let mut id = [0x00; 32];
+                    let mut hmac = [0x00; 32];
+                    id.copy_from_slice(&res.data[..32]);
+                    hmac.copy_from_slice(&res.data[32..]);
+                    if let Some(hmac_store) = &mut self.hmac_store {
+                        hmac_store.set(id, hmac);
+                    }
+                    self.state = State::Finished(LedgerResponse::WalletRegistered { id, hmac });
+                }
+                LedgerCommand::GetWalletAddress { .. } => {
+                    let address = Address::from_str(&String::from_utf8_lossy(&res.data))
+                        .map_err(|_| LedgerError::UnexpectedResult(res.data))?;
+                    if self
+                        .network
+                        .is_some_and(|n| !address.is_valid_for_network(n))
+                    {
+                        return Err(LedgerError::NetworkMismatch.into());
+                    }
+                    self.state = State::Finished(LedgerResponse::Address(address));
+                }
+                LedgerCommand::VerifyOwnedAddress {
+                    address,
+                    change,
+                    index,
+                    ..
+                } => {
+                    // The device derives another address from the policy.
+                    if Address::from_str(&String::from_utf8_lossy(&res.data)).ok()
+                        != Some(address.clone())
+                    {
+                        return Err(LedgerError::UnexpectedResult(res.data).into());
+                    }
+                    self.state = State::Finished(LedgerResponse::OwnedAddress {
+                        change: *change,
+                        index: *index,
+                    });
+                }
+                LedgerCommand::SignMessage { .. } => {
+                    let signature = MessageSignature::from_slice(&res.data)
+                        .map_err(|_| LedgerError::UnexpectedResult(res.data))?;
+                    self.state = State::Finished(LedgerResponse::MessageSignature(
+                        Base64::encode_string(&signature.serialize()),
+                    ));
+                }
+                #[cfg(feature = "elements")]
+                LedgerCommand::Liquid(command) => {
+                    self.state = State::Finished(elements::end(
+                        command,
+                        res.data,
+                        store.take(),
+                        self.master_fingerprint,
+                    )?);
                 }
             }
         }
         Ok(None)
     }
+}
+
+impl<C, T, R, E> Interpreter for LedgerInterpreter<C, T, R, E>
+where
+    C: TryInto<LedgerCommand>,
+    C::Error: Into<LedgerError>,
+    T: From<ApduCommand>,
+    R: From<LedgerResponse>,
+    E: From<LedgerError>,
+{
+    type Command = C;
+    type Transmit = T;
+    type Response = R;
+    type Error = E;
+
+    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        let mut command: LedgerCommand = command.try_into().map_err(Into::into)?;
+        if let (
+            LedgerCommand::SignPsbt { policy, hmac, .. }
+            | LedgerCommand::MusigSignPsbt { policy, hmac, .. }
+            | LedgerCommand::ProveAddressOwnership { policy, hmac, .. }
+            | LedgerCommand::VerifyOwnedAddress { policy, hmac, .. }
+            | LedgerCommand::GetWalletAddress { policy, hmac, .. },
+            Some(hmac_store),
+        ) = (&mut command, &self.hmac_store)
+        {
+            if hmac.is_none() {
+                *hmac = hmac_store.get(&policy.id());
+            }
+        }
+        if let Some(network) = self.network {
+            check_network(&command, network)?;
+        }
+        match &command {
+            LedgerCommand::RegisterWallet(policy)
+            | LedgerCommand::GetWalletAddress { policy, .. }
+            | LedgerCommand::SignPsbt { policy, .. }
+            | LedgerCommand::MusigSignPsbt { policy, .. }
+            | LedgerCommand::ProveAddressOwnership { policy, .. }
+            | LedgerCommand::VerifyOwnedAddress { policy, .. } => {
+                policy.validate().map_err(LedgerError::InvalidPolicy)?;
+            }
+            _ => {}
+        }
+        if self.path_policy != PathPolicy::Allow {
+            for warning in command_paths(&command)
+                .iter()
+                .flat_map(|path| path::check_path(path, self.network))
+            {
+                if self.path_policy == PathPolicy::Reject {
+                    return Err(LedgerError::UnusualPath(warning).into());
+                }
+                self.events.push_back(Event::UnusualPath(warning));
+            }
+        }
+        if let LedgerCommand::SignPsbt { psbt, .. }
+        | LedgerCommand::MusigSignPsbt { psbt, .. }
+        | LedgerCommand::ProveAddressOwnership { psbt, .. } = &command
+        {
+            if let Some(index) = psbt::invalid_sighash(psbt) {
+                return Err(LedgerError::UnsupportedSighash(index).into());
+            }
+        }
+        self.legacy = None;
+        let (transmit, store) = match command {
+            ref command
+                if self.legacy_app.is_some()
+                    && !matches!(
+                        command,
+                        LedgerCommand::OpenApp(..)
+                            | LedgerCommand::QuitApp
+                            | LedgerCommand::GetAppInfo
+                            | LedgerCommand::GetStatus
+                            | LedgerCommand::ListApps
+                            | LedgerCommand::EnsureApp(..)
+                    ) =>
+            {
+                let network = self.legacy_app.expect("legacy app");
+                let (session, apdu) =
+                    legacy::LegacySession::start(command.clone(), NetworkKind::from(network))?;
+                self.legacy = Some(session);
+                (apdu, None)
+            }
+            LedgerCommand::GetMasterFingerprint => (command::get_master_fingerprint(), None),
+            LedgerCommand::GetXpub { ref path, display } => (
+                command::get_extended_pubkey(path, display).map_err(LedgerError::from)?,
+                None,
+            ),
+            LedgerCommand::GetXpubs(ref paths) => {
+                let path = paths
+                    .first()
+                    .ok_or(LedgerError::MissingCommandInfo("paths"))?;
+                self.xpubs.clear();
+                (
+                    command::get_extended_pubkey(path, false).map_err(LedgerError::from)?,
+                    None,
+                )
+            }
+            LedgerCommand::OpenApp(network) => {
+                (command::open_app(self.config.app_name(network)), None)
+            }
+            LedgerCommand::QuitApp => (command::quit_app(), None),
+            LedgerCommand::ListApps => {
+                self.apps.clear();
+                (command::list_apps(true), None)
+            }
+            LedgerCommand::GetAppInfo | LedgerCommand::GetStatus | LedgerCommand::EnsureApp(..) => {
+                (command::get_version(), None)
+            }
+            LedgerCommand::SignPsbt {
+                ref psbt,
+                ref policy,
+                ref hmac,
+            }
+            | LedgerCommand::MusigSignPsbt {
+                ref psbt,
+                ref policy,
+                ref hmac,
+            }
+            | LedgerCommand::ProveAddressOwnership {
+                ref psbt,
+                ref policy,
+                ref hmac,
+                ..
+            } => {
+                let mut store = DelegatedStore::new();
+                // The leaves of the maps are served from the psbt of the
+                // command, see `command_psbt`.
+                let global =
+                    store.add_psbt_map(psbt::MapRef::Global, psbt::get_v2_global_map(psbt));
+                let inputs: Vec<Vec<u8>> = psbt::get_v2_input_maps(psbt)
+                    .enumerate()
+                    .map(|(i, map)| store.add_psbt_map(psbt::MapRef::Input(i), map))
+                    .collect();
+                let outputs: Vec<Vec<u8>> = psbt::get_v2_output_maps(psbt)
+                    .enumerate()
+                    .map(|(i, map)| store.add_psbt_map(psbt::MapRef::Output(i), map))
+                    .collect();
+                let inputs_root = store.add_known_list(&inputs);
+                let outputs_root = store.add_known_list(&outputs);
+                add_known_policy(&mut store, policy);
+                (
+                    command::sign_psbt(
+                        &global,
+                        inputs.len(),
+                        &inputs_root,
+                        outputs.len(),
+                        &outputs_root,
+                        policy,
+                        hmac.as_ref(),
+                    )
+                    .map_err(LedgerError::from)?,
+                    Some(store),
+                )
+            }
+            LedgerCommand::RegisterWallet(ref policy) => {
+                let mut store = DelegatedStore::new();
+                add_known_policy(&mut store, policy);
+                (
+                    command::register_wallet(policy).map_err(LedgerError::from)?,
+                    Some(store),
+                )
+            }
+            LedgerCommand::GetWalletAddress {
+                ref policy,
+                ref hmac,
+                change,
+                index,
+                display,
+            } => {
+                let mut store = DelegatedStore::new();
+                add_known_policy(&mut store, policy);
+                (
+                    command::get_wallet_address(policy, hmac.as_ref(), change, index, display),
+                    Some(store),
+                )
+            }
+            LedgerCommand::VerifyOwnedAddress {
+                ref policy,
+                ref hmac,
+                change,
+                index,
+                ..
+            } => {
+                let mut store = DelegatedStore::new();
+                add_known_policy(&mut store, policy);
+                (
+                    command::get_wallet_address(policy, hmac.as_ref(), change, index, true),
+                    Some(store),
+                )
+            }
+            LedgerCommand::SignMessage {
+                ref path,
+                ref message,
+            } => {
+                let mut store = DelegatedStore::new();
+                let chunks: Vec<&[u8]> = message.chunks(MESSAGE_CHUNK_SIZE).collect();
+                let root = store.add_known_list(&chunks);
+                (
+                    command::sign_message(message.len(), &root, path).map_err(LedgerError::from)?,
+                    Some(store),
+                )
+            }
+            #[cfg(feature = "elements")]
+            LedgerCommand::Liquid(ref command) => elements::start(command)?,
+        };
+        log_event!(
+            debug,
+            "start {}: cla {:#04x} ins {:#04x}, {} bytes",
+            command_name(&command),
+            transmit.cla,
+            transmit.ins,
+            transmit.data.len()
+        );
+        let transmit = match (self.network, &command) {
+            (
+                Some(_),
+                LedgerCommand::SignPsbt { .. }
+                | LedgerCommand::MusigSignPsbt { .. }
+                | LedgerCommand::ProveAddressOwnership { .. },
+            ) => {
+                self.pending = Some(transmit);
+                command::get_version()
+            }
+            _ => {
+                self.pending = None;
+                transmit
+            }
+        };
+        if requires_confirmation(&command) {
+            self.events.push_back(Event::AwaitingUserConfirmation);
+        }
+        self.probes = 0;
+        self.confirmed = false;
+        self.first_apdu = match command {
+            LedgerCommand::GetStatus => None,
+            _ => self.unlock_wait.map(|_| transmit.clone()),
+        };
+        self.state = State::Running { command, store };
+        let transmit = self.chunked(transmit);
+        self.retries = 0;
+        self.last_apdu = self.retry_policy.map(|_| transmit.clone());
+        Ok(Self::Transmit::from(self.config.apply(transmit)))
+    }
+    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        if let Some(apdu) = self.retry(&data) {
+            return Ok(Some(Self::Transmit::from(self.config.apply(apdu))));
+        }
+        let apdu = self.exchange_apdu(data)?;
+        self.retries = 0;
+        self.last_apdu = self.retry_policy.and(apdu.clone());
+        Ok(apdu.map(|apdu| Self::Transmit::from(self.config.apply(apdu))))
+    }
     fn end(self) -> Result<Self::Response, Self::Error> {
-        if let State::Finished(res) = self.state {
-            Ok(Self::Response::from(res))
-        } else {
-            Err(LedgerError::NoErrorOrResult.into())
+        match self.state {
+            State::Finished(res) => Ok(Self::Response::from(res)),
+            State::Cancelled => {
+                log_event!(debug, "end of a cancelled command");
+                Err(LedgerError::Cancelled.into())
+            }
+            State::TimedOut => Err(LedgerError::Timeout.into()),
+            State::New => Err(LedgerError::NoErrorOrResult.into()),
+            State::Running { command, .. } => {
+                log_event!(debug, "end of {} before its result", command_name(&command));
+                Err(LedgerError::NoErrorOrResult.into())
+            }
+        }
+    }
+    fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+    /// Stops replying to the requests of the device for the running command,
+    /// the device may still wait for the reply to its last request.
+    fn cancel(&mut self) {
+        if let State::Running { command, .. } = &self.state {
+            log_event!(debug, "{} cancelled", command_name(command));
+        }
+        self.state = State::Cancelled;
+        self.xpubs.clear();
+        self.apps.clear();
+        self.chunks.clear();
+        self.first_apdu = None;
+        self.last_apdu = None;
+        self.pending = None;
+        self.legacy = None;
+        self.events.clear();
+    }
+    fn tick(&mut self, elapsed_ms: u64) -> Result<(), Self::Error> {
+        let State::Running { command, .. } = &self.state else {
+            return Ok(());
+        };
+        match self.deadline_ms {
+            Some(deadline_ms) if elapsed_ms >= deadline_ms => {
+                log_event!(debug, "{} timed out", command_name(command));
+                self.cancel();
+                self.state = State::TimedOut;
+                Err(LedgerError::Timeout.into())
+            }
+            _ => Ok(()),
+        }
+    }
+    fn deadline_ms(&self) -> Option<u64> {
+        self.deadline_ms
+    }
+}
+
+/// Returns false if the path follows a BIP-44 like scheme with the coin type
+/// of another network.
+fn coin_type_matches(path: &DerivationPath, network: Network) -> bool {
+    let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+    match path.as_ref() {
+        [ChildNumber::Hardened {
+            index: 44 | 45 | 48 | 49 | 84 | 86,
+        }, ChildNumber::Hardened { index }, ..] => *index == coin_type,
+        _ => true,
+    }
+}
+
+/// Returns the paths sent to the device by the command.
+fn command_paths(command: &LedgerCommand) -> &[DerivationPath] {
+    match command {
+        LedgerCommand::GetXpub { path, .. } | LedgerCommand::SignMessage { path, .. } => {
+            core::slice::from_ref(path)
+        }
+        LedgerCommand::GetXpubs(paths) => paths,
+        _ => &[],
+    }
+}
+
+/// Checks the paths and the keys of the command against the network.
+fn check_network(command: &LedgerCommand, network: Network) -> Result<(), LedgerError> {
+    let policy_matches = |policy: &WalletPolicy| {
+        policy.keys.iter().all(|key| {
+            key.inner.network == NetworkKind::from(network)
+                && key
+                    .source
+                    .as_ref()
+                    .map_or(true, |(_, path)| coin_type_matches(path, network))
+        })
+    };
+    let consistent = match command {
+        LedgerCommand::GetXpub { path, .. } | LedgerCommand::SignMessage { path, .. } => {
+            coin_type_matches(path, network)
+        }
+        LedgerCommand::GetXpubs(paths) => paths.iter().all(|path| coin_type_matches(path, network)),
+        LedgerCommand::SignPsbt { psbt, policy, .. }
+        | LedgerCommand::MusigSignPsbt { psbt, policy, .. }
+        | LedgerCommand::ProveAddressOwnership { psbt, policy, .. } => {
+            let inputs = psbt.inputs.iter().flat_map(|input| {
+                input
+                    .bip32_derivation
+                    .values()
+                    .chain(input.tap_key_origins.values().map(|(_, source)| source))
+            });
+            let outputs = psbt.outputs.iter().flat_map(|output| {
+                output
+                    .bip32_derivation
+                    .values()
+                    .chain(output.tap_key_origins.values().map(|(_, source)| source))
+            });
+            policy_matches(policy)
+                && psbt
+                    .xpub
+                    .keys()
+                    .all(|xpub| xpub.network == NetworkKind::from(network))
+                && inputs
+                    .chain(outputs)
+                    .all(|(_, path)| coin_type_matches(path, network))
+        }
+        LedgerCommand::RegisterWallet(policy) | LedgerCommand::GetWalletAddress { policy, .. } => {
+            policy_matches(policy)
+        }
+        LedgerCommand::VerifyOwnedAddress {
+            address, policy, ..
+        } => policy_matches(policy) && address.is_valid_for_network(network),
+        LedgerCommand::OpenApp(..)
+        | LedgerCommand::QuitApp
+        | LedgerCommand::GetAppInfo
+        | LedgerCommand::GetStatus
+        | LedgerCommand::ListApps
+        | LedgerCommand::EnsureApp(..)
+        | LedgerCommand::GetMasterFingerprint => true,
+        // The Liquid networks are not Bitcoin networks.
+        #[cfg(feature = "elements")]
+        LedgerCommand::Liquid(..) => true,
+    };
+    if consistent {
+        Ok(())
+    } else {
+        Err(LedgerError::NetworkMismatch)
+    }
+}
+
+/// Returns the status word ending the answer, if it is a known one.
+fn status_word(data: &[u8]) -> Option<StatusWord> {
+    let (_, sw) = data.split_last_chunk::<2>()?;
+    StatusWord::try_from(u16::from_be_bytes(*sw)).ok()
+}
+
+/// Checks the depth and the child number of the xpub against its path, and
+/// its parent fingerprint or its own against the master fingerprint when
+/// they derive from it directly. The parents of deeper keys are unknown.
+fn check_xpub(xpub: &Xpub, path: &DerivationPath, master: Fingerprint) -> Result<(), LedgerError> {
+    let consistent = match path.as_ref() {
+        [] => xpub.depth == 0 && xpub.fingerprint() == master,
+        children => {
+            xpub.depth as usize == children.len()
+                && Some(&xpub.child_number) == children.last()
+                && (children.len() > 1 || xpub.parent_fingerprint == master)
+        }
+    };
+    if consistent {
+        Ok(())
+    } else {
+        Err(LedgerError::MismatchedDevice)
+    }
+}
+
+/// Returns the psbt of the command, whose maps are served to the device.
+fn command_psbt(command: &LedgerCommand) -> Option<&Psbt> {
+    match command {
+        LedgerCommand::SignPsbt { psbt, .. } | LedgerCommand::MusigSignPsbt { psbt, .. } => {
+            Some(psbt)
+        }
+        LedgerCommand::ProveAddressOwnership { psbt, .. } => Some(psbt),
+        _ => None,
+    }
+}
+
+/// Returns the name of the command to log, without its arguments.
+fn command_name(command: &LedgerCommand) -> &'static str {
+    match command {
+        LedgerCommand::OpenApp(..) => "open_app",
+        LedgerCommand::QuitApp => "quit_app",
+        LedgerCommand::GetAppInfo => "get_app_info",
+        LedgerCommand::GetStatus => "get_status",
+        LedgerCommand::ListApps => "list_apps",
+        LedgerCommand::EnsureApp(..) => "ensure_app",
+        LedgerCommand::GetMasterFingerprint => "get_master_fingerprint",
+        LedgerCommand::GetXpub { .. } => "get_xpub",
+        LedgerCommand::GetXpubs(..) => "get_xpubs",
+        LedgerCommand::SignPsbt { .. } => "sign_psbt",
+        LedgerCommand::MusigSignPsbt { .. } => "musig_sign_psbt",
+        LedgerCommand::ProveAddressOwnership { .. } => "prove_address_ownership",
+        LedgerCommand::VerifyOwnedAddress { .. } => "verify_owned_address",
+        LedgerCommand::RegisterWallet(..) => "register_wallet",
+        LedgerCommand::GetWalletAddress { .. } => "get_wallet_address",
+        LedgerCommand::SignMessage { .. } => "sign_message",
+        #[cfg(feature = "elements")]
+        LedgerCommand::Liquid(command) => command.name(),
+    }
+}
+
+/// Returns true if the device asks the user to confirm the command.
+fn requires_confirmation(command: &LedgerCommand) -> bool {
+    match command {
+        LedgerCommand::OpenApp(..)
+        | LedgerCommand::ListApps
+        | LedgerCommand::SignPsbt { .. }
+        | LedgerCommand::MusigSignPsbt { .. }
+        | LedgerCommand::ProveAddressOwnership { .. }
+        | LedgerCommand::VerifyOwnedAddress { .. }
+        | LedgerCommand::RegisterWallet(..)
+        | LedgerCommand::SignMessage { .. } => true,
+        LedgerCommand::GetXpub { display, .. }
+        | LedgerCommand::GetWalletAddress { display, .. } => *display,
+        LedgerCommand::QuitApp
+        | LedgerCommand::GetAppInfo
+        | LedgerCommand::GetStatus
+        | LedgerCommand::EnsureApp(..)
+        | LedgerCommand::GetMasterFingerprint
+        | LedgerCommand::GetXpubs(..) => false,
+        #[cfg(feature = "elements")]
+        LedgerCommand::Liquid(command) => command.requires_confirmation(),
+    }
+}
+
+/// Adds the preimages of the policy that the device may request.
+fn add_known_policy(store: &mut DelegatedStore, policy: &WalletPolicy) {
+    store.add_known_preimage(policy.serialize());
+    let keys: Vec<String> = policy.keys.iter().map(|key| key.to_string()).collect();
+    store.add_known_list(&keys);
+    if policy.version == wallet::Version::V2 {
+        store.add_known_preimage(policy.descriptor_template.as_bytes().to_vec());
+    }
+}
+
+/// Tags replacing the input index of the MuSig2 values yielded during
+/// SIGN_PSBT.
+const MUSIG_PUBNONCE_TAG: u64 = 0xffff_ffff;
+const MUSIG_PARTIAL_SIGNATURE_TAG: u64 = 0xffff_fffe;
+
+/// Value yielded during SIGN_PSBT with the index of its input.
+enum Yielded {
+    Signature(usize, PartialSignature),
+    MusigPubNonce(usize, MusigPubNonce),
+    MusigPartialSignature(usize, MusigPartialSignature),
+}
+
+fn read_varint(value: &[u8]) -> Option<(u64, usize)> {
+    encode::deserialize_partial::<VarInt>(value)
+        .ok()
+        .map(|(n, read)| (n.0, read))
+}
+
+/// Decodes a value yielded during SIGN_PSBT: the input index as a varint
+/// followed by the partial signature, or the tag of a MuSig2 value followed
+/// by the input index and the value.
+fn decode_yielded(value: &[u8]) -> Result<Yielded, LedgerError> {
+    let decoded = read_varint(value).and_then(|(tag, read)| match tag {
+        MUSIG_PUBNONCE_TAG | MUSIG_PARTIAL_SIGNATURE_TAG => {
+            let (index, index_read) = read_varint(&value[read..])?;
+            let data = &value[read + index_read..];
+            if tag == MUSIG_PUBNONCE_TAG {
+                MusigPubNonce::from_slice(data)
+                    .ok()
+                    .map(|nonce| Yielded::MusigPubNonce(index as usize, nonce))
+            } else {
+                MusigPartialSignature::from_slice(data)
+                    .ok()
+                    .map(|sig| Yielded::MusigPartialSignature(index as usize, sig))
+            }
+        }
+        index => PartialSignature::from_slice(&value[read..])
+            .ok()
+            .map(|sig| Yielded::Signature(index as usize, sig)),
+    });
+    decoded.ok_or_else(|| LedgerError::UnexpectedResult(value.to_vec()))
+}
+
+/// Returns the signatures yielded during SIGN_PSBT, with the index of their
+/// input.
+fn yielded_signatures(
+    store: Option<DelegatedStore>,
+) -> Result<Vec<(usize, PartialSignature)>, LedgerError> {
+    store
+        .map(DelegatedStore::yielded)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|value| match decode_yielded(&value)? {
+            Yielded::Signature(index, sig) => Ok((index, sig)),
+            Yielded::MusigPubNonce(..) | Yielded::MusigPartialSignature(..) => {
+                Err(LedgerError::UnexpectedResult(value))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apdu::{BitcoinCommandCode, Cla, ClientCommandCode};
+    use bitcoin::{
+        absolute::LockTime,
+        bip32::Xpriv,
+        ecdsa,
+        hashes::{sha256, Hash},
+        secp256k1::{Message, Secp256k1},
+        transaction::Version,
+        Amount, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+    fn sign_psbt_command() -> (LedgerCommand, Xpriv) {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpub = Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap());
+        let policy = WalletPolicy::new(
+            String::new(),
+            wallet::Version::V2,
+            "wpkh(@0/**)".to_string(),
+            [((xpriv.fingerprint(&secp), path), xpub)],
+        );
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let command = LedgerCommand::SignPsbt {
+            psbt: Box::new(Psbt::from_unsigned_tx(tx).unwrap()),
+            policy,
+            hmac: None,
+        };
+        (command, xpriv)
+    }
+
+    #[test]
+    fn test_sign_psbt() {
+        let secp = Secp256k1::new();
+        let (command, xpriv) = sign_psbt_command();
+        let policy = match &command {
+            LedgerCommand::SignPsbt { policy, .. } => policy.clone(),
+            _ => unreachable!(),
+        };
+        let mut interpreter = Ledger::default();
+        assert_eq!(interpreter.status(), InterpreterStatus::Idle);
+        let apdu = interpreter.start(command).unwrap();
+        assert_eq!(apdu.cla, Cla::Bitcoin as u8);
+        assert_eq!(apdu.ins, BitcoinCommandCode::SignPSBT as u8);
+        assert_eq!(
+            interpreter.poll_event(),
+            Some(Event::AwaitingUserConfirmation)
+        );
+        assert_eq!(interpreter.status(), InterpreterStatus::AwaitingUserAction);
+        assert_eq!(interpreter.poll_event(), None);
+        assert_eq!(apdu.data[apdu.data.len() - 64..][..32], policy.id());
+
+        // The device requests the policy it was given the id of.
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(sha256::Hash::hash(&policy.serialize()).to_byte_array());
+        request.extend([0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        assert_eq!(apdu.cla, Cla::Framework as u8);
+        assert!(apdu.data.ends_with(&policy.serialize()));
+
+        let pubkey = PublicKey::new(xpriv.private_key.public_key(&secp));
+        let signature = ecdsa::Signature::sighash_all(
+            secp.sign_ecdsa(&Message::from_digest([0x02; 32]), &xpriv.private_key),
+        );
+        let mut yielded = vec![ClientCommandCode::Yield as u8, 0x00, 33];
+        yielded.extend(pubkey.to_bytes());
+        yielded.extend(signature.to_vec());
+        yielded.extend([0xE0, 0x00]);
+        assert!(interpreter.exchange(yielded).unwrap().is_some());
+        assert_eq!(interpreter.poll_event(), Some(Event::InputSigned(0, 1)));
+        // The user confirmed before the device signed.
+        assert_eq!(interpreter.status(), InterpreterStatus::AwaitingDevice);
+
+        assert!(interpreter.exchange(vec![0x90, 0x00]).unwrap().is_none());
+        assert_eq!(interpreter.status(), InterpreterStatus::Done);
+        match interpreter.end().unwrap() {
+            LedgerResponse::Signatures(signatures) => assert_eq!(
+                signatures,
+                vec![(0, PartialSignature::Sig(pubkey, signature))]
+            ),
+            _ => panic!("expected signatures"),
+        }
+    }
+
+    #[test]
+    fn test_musig_sign_psbt() {
+        let secp = Secp256k1::new();
+        let (command, xpriv) = sign_psbt_command();
+        let command = match command {
+            LedgerCommand::SignPsbt { psbt, policy, hmac } => {
+                LedgerCommand::MusigSignPsbt { psbt, policy, hmac }
+            }
+            _ => unreachable!(),
+        };
+        let participant = PublicKey::new(xpriv.private_key.public_key(&secp));
+
+        let mut interpreter = Ledger::default();
+        interpreter.start(command.clone()).unwrap();
+        let mut yielded = vec![ClientCommandCode::Yield as u8];
+        yielded.extend(encode::serialize(&VarInt(MUSIG_PUBNONCE_TAG)));
+        yielded.push(0x00);
+        yielded.extend([0xaa; 66]);
+        yielded.extend(participant.to_bytes());
+        yielded.extend(participant.to_bytes());
+        yielded.extend([0xE0, 0x00]);
+        assert!(interpreter.exchange(yielded).unwrap().is_some());
+        assert!(interpreter.exchange(vec![0x90, 0x00]).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::MusigNonces(nonces) => {
+                assert_eq!(nonces.len(), 1);
+                assert_eq!(nonces[0].0, 0);
+                assert_eq!(nonces[0].1.participant, participant);
+                assert_eq!(nonces[0].1.pubnonce, [0xaa; 66]);
+            }
+            _ => panic!("expected musig nonces"),
+        }
+
+        let mut interpreter = Ledger::default();
+        interpreter.start(command).unwrap();
+        let mut yielded = vec![ClientCommandCode::Yield as u8];
+        yielded.extend(encode::serialize(&VarInt(MUSIG_PARTIAL_SIGNATURE_TAG)));
+        yielded.push(0x00);
+        yielded.extend([0xbb; 32]);
+        yielded.extend(participant.to_bytes());
+        yielded.extend(participant.to_bytes());
+        yielded.extend([0xE0, 0x00]);
+        assert!(interpreter.exchange(yielded).unwrap().is_some());
+        assert!(interpreter.exchange(vec![0x90, 0x00]).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::MusigPartialSigs(signatures) => {
+                assert_eq!(signatures[0].1.signature, [0xbb; 32]);
+            }
+            _ => panic!("expected musig partial signatures"),
+        }
+    }
+
+    #[test]
+    fn test_sign_psbt_client_commands() {
+        let (command, _) = sign_psbt_command();
+        let input_map = match &command {
+            LedgerCommand::SignPsbt { psbt, .. } => psbt::get_v2_input_maps(psbt).next().unwrap(),
+            _ => unreachable!(),
+        };
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter.start(command).unwrap();
+        // Global map commitment, then the number of inputs and their root.
+        let (_, read) = merkleized_map::MapCommitment::deserialize_partial(&apdu.data).unwrap();
+        assert_eq!(apdu.data[read], 1);
+        let inputs_root = apdu.data[read + 1..read + 33].to_vec();
+
+        // The device walks the list of input commitments.
+        let mut request = vec![ClientCommandCode::GetMerkleLeafProof as u8];
+        request.extend(&inputs_root);
+        request.extend([0x01, 0x00, 0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        let leaf = apdu.data[..32].to_vec();
+        assert_eq!(apdu.data[32..], [0x00, 0x00]);
+
+        let mut request = vec![ClientCommandCode::GetMerkleLeafIndex as u8];
+        request.extend(&inputs_root);
+        request.extend(&leaf);
+        request.extend([0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        assert_eq!(apdu.data, [0x01, 0x00]);
+
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(&leaf);
+        request.extend([0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        assert!(apdu
+            .data
+            .ends_with(&store::get_merkleized_map_commitment(&input_map)));
+
+        // The leaves of the input map are served from the psbt.
+        let (key, _) = input_map.iter().min().unwrap();
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(merkleized_map::leaf_hash(key));
+        request.extend([0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        assert!(apdu.data.ends_with(&merkleized_map::leaf_preimage(key)));
+
+        // Nothing is left to send.
+        assert!(matches!(
+            interpreter.exchange(vec![ClientCommandCode::GetMoreElements as u8, 0xE0, 0x00]),
+            Err(LedgerError::Store(StoreError::UnexpectedQueue))
+        ));
+    }
+
+    #[test]
+    fn test_sign_psbt_refused() {
+        let (command, _) = sign_psbt_command();
+        let mut interpreter = Ledger::default();
+        interpreter.start(command).unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x69, 0x85]),
+            Err(LedgerError::DeniedByUser)
+        ));
+        assert!(matches!(
+            interpreter.end(),
+            Err(LedgerError::NoErrorOrResult)
+        ));
+
+        // A yielded value that is not a signature fails the command.
+        let (command, _) = sign_psbt_command();
+        let mut interpreter = Ledger::default();
+        interpreter.start(command).unwrap();
+        interpreter
+            .exchange(vec![ClientCommandCode::Yield as u8, 0x00, 0x21, 0xE0, 0x00])
+            .unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x90, 0x00]),
+            Err(LedgerError::UnexpectedResult(_))
+        ));
+    }
+
+    #[test]
+    fn test_deadline() {
+        let (command, _) = sign_psbt_command();
+        let mut interpreter = Ledger::default().with_deadline(1000);
+        interpreter.start(command).unwrap();
+        interpreter.tick(999).unwrap();
+        assert!(matches!(interpreter.tick(1000), Err(LedgerError::Timeout)));
+        assert_eq!(interpreter.status(), InterpreterStatus::Idle);
+        assert!(interpreter
+            .exchange(vec![ClientCommandCode::Yield as u8, 0x00, 0x21, 0xE0, 0x00])
+            .unwrap()
+            .is_none());
+        assert!(matches!(interpreter.end(), Err(LedgerError::Timeout)));
+    }
+
+    #[test]
+    fn test_sign_psbt_cancelled() {
+        let (command, _) = sign_psbt_command();
+        let mut interpreter = Ledger::default();
+        interpreter.start(command).unwrap();
+        interpreter.cancel();
+        assert!(interpreter.poll_event().is_none());
+        assert!(interpreter
+            .exchange(vec![ClientCommandCode::Yield as u8, 0x00, 0x21, 0xE0, 0x00])
+            .unwrap()
+            .is_none());
+        assert!(matches!(interpreter.end(), Err(LedgerError::Cancelled)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let (command, _) = sign_psbt_command();
+        let policy = match &command {
+            LedgerCommand::SignPsbt { policy, .. } => policy.clone(),
+            _ => unreachable!(),
+        };
+        let json = serde_json::to_value(LedgerCommand::RegisterWallet(policy.clone())).unwrap();
+        assert_eq!(
+            json["RegisterWallet"]["keys"][0],
+            serde_json::Value::String(policy.keys[0].to_string())
+        );
+        match serde_json::from_value(json).unwrap() {
+            LedgerCommand::RegisterWallet(res) => assert_eq!(res, policy),
+            _ => panic!("expected a wallet registration"),
+        }
+        let json = serde_json::to_string(&command).unwrap();
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            LedgerCommand::SignPsbt { .. }
+        ));
+
+        let xpub = policy.keys[0].inner;
+        let json = serde_json::to_value(LedgerResponse::Xpub(xpub)).unwrap();
+        assert_eq!(json["Xpub"], serde_json::Value::String(xpub.to_string()));
+        assert_eq!(
+            serde_json::to_string(&LedgerError::MissingCommandInfo("paths")).unwrap(),
+            r#"{"MissingCommandInfo":"paths"}"#
+        );
+    }
+
+    #[test]
+    fn test_get_xpubs() {
+        const XPUB: &str = "tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW";
+        let paths: Vec<DerivationPath> = ["m/84'/1'/0'", "m/86'/1'/0'"]
+            .iter()
+            .map(|p| DerivationPath::from_str(p).unwrap())
+            .collect();
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter
+            .start(LedgerCommand::GetXpubs(paths.clone()))
+            .unwrap();
+        assert_eq!(
+            apdu.encode(),
+            command::get_extended_pubkey(&paths[0], false)
+                .unwrap()
+                .encode()
+        );
+        assert!(interpreter.poll_event().is_none());
+
+        let mut answer = XPUB.as_bytes().to_vec();
+        answer.extend([0x90, 0x00]);
+        let apdu = interpreter.exchange(answer.clone()).unwrap().unwrap();
+        assert_eq!(
+            apdu.encode(),
+            command::get_extended_pubkey(&paths[1], false)
+                .unwrap()
+                .encode()
+        );
+        assert!(interpreter.exchange(answer).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::Xpubs(xpubs) => {
+                let xpub = Xpub::from_str(XPUB).unwrap();
+                assert_eq!(
+                    xpubs,
+                    vec![(paths[0].clone(), xpub), (paths[1].clone(), xpub)]
+                );
+            }
+            _ => panic!("expected xpubs"),
+        }
+
+        let mut interpreter = Ledger::default();
+        assert!(matches!(
+            interpreter.start(LedgerCommand::GetXpubs(Vec::new())),
+            Err(LedgerError::MissingCommandInfo("paths"))
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_sighash() {
+        use bitcoin::{psbt::PsbtSighashType, Txid};
+
+        let (command, _) = sign_psbt_command();
+        let with_sighash = |sighash_type: u32, inputs: usize| {
+            let mut command = command.clone();
+            if let LedgerCommand::SignPsbt { psbt, .. } = &mut command {
+                for i in 1..inputs {
+                    psbt.unsigned_tx.input.push(TxIn {
+                        previous_output: OutPoint::new(Txid::all_zeros(), i as u32),
+                        ..Default::default()
+                    });
+                    psbt.inputs.push(Default::default());
+                }
+                psbt.inputs[inputs - 1].sighash_type =
+                    Some(PsbtSighashType::from_u32(sighash_type));
+            }
+            Ledger::default().start(command)
+        };
+        // SIGHASH_NONE | SIGHASH_ANYONECANPAY goes through to the device.
+        assert!(with_sighash(0x82, 1).is_ok());
+        assert!(with_sighash(0x03, 1).is_ok());
+        // SIGHASH_DEFAULT is only valid for taproot.
+        assert!(matches!(
+            with_sighash(0x00, 1),
+            Err(LedgerError::UnsupportedSighash(0))
+        ));
+        assert!(matches!(
+            with_sighash(0x04, 1),
+            Err(LedgerError::UnsupportedSighash(0))
+        ));
+        // No output is signed by SIGHASH_SINGLE for the second input.
+        assert!(matches!(
+            with_sighash(0x03, 2),
+            Err(LedgerError::UnsupportedSighash(1))
+        ));
+    }
+
+    #[test]
+    fn test_path_policy() {
+        let command = LedgerCommand::GetXpubs(vec![
+            DerivationPath::from_str("m/84'/1'/0'").unwrap(),
+            DerivationPath::from_str("m/84'/1'/0").unwrap(),
+        ]);
+        let mut interpreter = Ledger::default().with_path_policy(PathPolicy::Warn);
+        interpreter.start(command.clone()).unwrap();
+        assert_eq!(
+            interpreter.poll_event(),
+            Some(Event::UnusualPath(PathWarning::Unhardened(2)))
+        );
+        assert_eq!(interpreter.poll_event(), None);
+
+        let mut interpreter = Ledger::default().with_path_policy(PathPolicy::Reject);
+        assert!(matches!(
+            interpreter.start(command.clone()),
+            Err(LedgerError::UnusualPath(PathWarning::Unhardened(2)))
+        ));
+        assert!(Ledger::default().start(command).is_ok());
+    }
+
+    #[test]
+    fn test_network_mismatch() {
+        const XPUB: &str = "tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW";
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let mut interpreter = Ledger::default().with_network(Network::Bitcoin);
+        assert!(matches!(
+            interpreter.start(LedgerCommand::GetXpub {
+                path: path.clone(),
+                display: false
+            }),
+            Err(LedgerError::NetworkMismatch)
+        ));
+
+        // The testnet app answers a tpub to a non standard path.
+        let mut interpreter = Ledger::default().with_network(Network::Bitcoin);
+        interpreter
+            .start(LedgerCommand::GetXpub {
+                path: DerivationPath::from_str("m/0'/1'").unwrap(),
+                display: false,
+            })
+            .unwrap();
+        let mut answer = XPUB.as_bytes().to_vec();
+        answer.extend([0x90, 0x00]);
+        assert!(matches!(
+            interpreter.exchange(answer.clone()),
+            Err(LedgerError::NetworkMismatch)
+        ));
+
+        let mut interpreter = Ledger::default().with_network(Network::Signet);
+        interpreter
+            .start(LedgerCommand::GetXpub {
+                path,
+                display: false,
+            })
+            .unwrap();
+        assert!(interpreter.exchange(answer).unwrap().is_none());
+
+        // The policy keys are testnet ones.
+        let (command, _) = sign_psbt_command();
+        let mut interpreter = Ledger::default().with_network(Network::Bitcoin);
+        assert!(matches!(
+            interpreter.start(command.clone()),
+            Err(LedgerError::NetworkMismatch)
+        ));
+
+        // The open app is checked before signing.
+        let mut interpreter = Ledger::default().with_network(Network::Testnet);
+        let apdu = interpreter.start(command.clone()).unwrap();
+        assert_eq!(apdu.encode(), command::get_version().encode());
+        assert!(matches!(
+            interpreter.exchange(app_info("Bitcoin")),
+            Err(LedgerError::NetworkMismatch)
+        ));
+
+        let mut interpreter = Ledger::default().with_network(Network::Testnet);
+        interpreter.start(command).unwrap();
+        let apdu = interpreter
+            .exchange(app_info("Bitcoin Test"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (apdu.cla, apdu.ins),
+            (Cla::Bitcoin as u8, BitcoinCommandCode::SignPSBT as u8)
+        );
+    }
+
+    #[test]
+    fn test_get_app_info() {
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter.start(LedgerCommand::GetAppInfo).unwrap();
+        assert_eq!(apdu.encode(), vec![0xb0, 0x01, 0x00, 0x00, 0x00]);
+        assert!(interpreter.exchange(app_info("Bitcoin")).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::AppInfo(info) => {
+                assert!(info.is_bitcoin_app(Network::Bitcoin));
+                assert!(info.version.at_least(2, 1, 0));
+            }
+            _ => panic!("expected the app info"),
         }
+
+        let mut interpreter = Ledger::default();
+        interpreter.start(LedgerCommand::GetAppInfo).unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x55, 0x15]),
+            Err(LedgerError::DeviceLocked)
+        ));
+
+        let mut interpreter = Ledger::default();
+        interpreter.start(LedgerCommand::GetAppInfo).unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x6e, 0x00]),
+            Err(LedgerError::AppNotOpen)
+        ));
+    }
+
+    fn app_info(name: &str) -> Vec<u8> {
+        let mut response = vec![0x01, name.len() as u8];
+        response.extend(name.as_bytes());
+        response.push(0x05);
+        response.extend(b"2.2.4");
+        response.extend([0x01, 0x00, 0x90, 0x00]);
+        response
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy {
+            max_retries: 2,
+            backoff_ms: 10,
+        };
+        assert_eq!(policy.delay_ms(3), 40);
+
+        let mut interpreter = Ledger::default().with_retry_policy(policy);
+        let first = interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        let retry = interpreter.exchange(vec![0x6f, 0x00]).unwrap().unwrap();
+        assert_eq!(retry.encode(), first.encode());
+        assert_eq!(interpreter.poll_event(), Some(Event::Retrying(1, 10)));
+        // A truncated answer is retried as well.
+        let retry = interpreter.exchange(vec![0x90]).unwrap().unwrap();
+        assert_eq!(retry.encode(), first.encode());
+        assert_eq!(interpreter.poll_event(), Some(Event::Retrying(2, 20)));
+        assert!(matches!(
+            interpreter.exchange(vec![0x6f, 0x00]),
+            Err(LedgerError::Status(StatusWord::TechnicalProblem))
+        ));
+
+        let mut interpreter = Ledger::default().with_retry_policy(policy);
+        interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        assert!(interpreter.exchange(vec![0x6f, 0x00]).unwrap().is_some());
+        assert!(interpreter
+            .exchange(vec![0xde, 0xad, 0xbe, 0xef, 0x90, 0x00])
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            interpreter.end(),
+            Ok(LedgerResponse::MasterFingerprint(_))
+        ));
+
+        // The failures are not retried without policy.
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        assert!(interpreter.exchange(vec![0x6f, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_unlock_wait() {
+        let mut interpreter = Ledger::default().with_unlock_wait(2, 500);
+        let first = interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        let probe = interpreter.exchange(vec![0x55, 0x15]).unwrap().unwrap();
+        assert_eq!(probe.encode(), command::get_version().encode());
+        assert_eq!(interpreter.poll_event(), Some(Event::AwaitingUnlock(500)));
+        let probe = interpreter.exchange(vec![0x69, 0x82]).unwrap().unwrap();
+        assert_eq!(probe.encode(), command::get_version().encode());
+        assert_eq!(interpreter.poll_event(), Some(Event::AwaitingUnlock(500)));
+        // Unlocked, the command is sent again.
+        let apdu = interpreter.exchange(app_info("Bitcoin")).unwrap().unwrap();
+        assert_eq!(apdu.encode(), first.encode());
+        assert!(interpreter.poll_event().is_none());
+        assert!(interpreter
+            .exchange(vec![0xf5, 0xac, 0xc2, 0xfd, 0x90, 0x00])
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            interpreter.end().unwrap(),
+            LedgerResponse::MasterFingerprint(..)
+        ));
+
+        let mut interpreter = Ledger::default().with_unlock_wait(1, 500);
+        interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        interpreter.exchange(vec![0x55, 0x15]).unwrap().unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x55, 0x15]),
+            Err(LedgerError::DeviceLocked)
+        ));
+
+        // Without the wait, the lock fails the command.
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x55, 0x15]),
+            Err(LedgerError::DeviceLocked)
+        ));
+    }
+
+    #[test]
+    fn test_list_apps() {
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter.start(LedgerCommand::ListApps).unwrap();
+        assert_eq!(apdu.encode(), vec![0xe0, 0xde, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            interpreter.poll_event(),
+            Some(Event::AwaitingUserConfirmation)
+        );
+        for name in ["Bitcoin", "Bitcoin Test"] {
+            let mut page = vec![0x01];
+            page.extend(app::tests::app_entry(name));
+            page.extend([0x90, 0x00]);
+            let apdu = interpreter.exchange(page).unwrap().unwrap();
+            assert_eq!(apdu.encode(), vec![0xe0, 0xdf, 0x00, 0x00, 0x00]);
+        }
+        assert!(interpreter.exchange(vec![0x90, 0x00]).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::Apps(apps) => {
+                let names: Vec<&str> = apps.iter().map(|app| app.name.as_str()).collect();
+                assert_eq!(names, vec!["Bitcoin", "Bitcoin Test"]);
+            }
+            _ => panic!("expected the installed apps"),
+        }
+
+        // An app is open.
+        let mut interpreter = Ledger::default();
+        interpreter.start(LedgerCommand::ListApps).unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x6e, 0x00]),
+            Err(LedgerError::AppNotOpen)
+        ));
+    }
+
+    #[test]
+    fn test_ensure_app() {
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::EnsureApp(Network::Testnet))
+            .unwrap();
+        assert!(interpreter
+            .exchange(app_info("Bitcoin Test"))
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            interpreter.end().unwrap(),
+            LedgerResponse::AppInfo(info) if info.name == "Bitcoin Test"
+        ));
+
+        // The dashboard opens the app.
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::EnsureApp(Network::Bitcoin))
+            .unwrap();
+        let apdu = interpreter.exchange(app_info("BOLOS")).unwrap().unwrap();
+        assert_eq!((apdu.cla, apdu.ins), (0xe0, 0xd8));
+        assert_eq!(apdu.data, b"Bitcoin");
+        assert!(interpreter.exchange(vec![0x90, 0x00]).unwrap().is_none());
+        assert_eq!(interpreter.poll_event(), Some(Event::Reenumerating));
+        assert!(matches!(
+            interpreter.end().unwrap(),
+            LedgerResponse::TaskDone
+        ));
+
+        // Another app is quit first.
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::EnsureApp(Network::Bitcoin))
+            .unwrap();
+        let apdu = interpreter
+            .exchange(app_info("Bitcoin Test"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(apdu.encode(), vec![0xb0, 0xa7, 0x00, 0x00, 0x00]);
+        assert!(interpreter.exchange(vec![0x90, 0x00]).unwrap().is_none());
+        assert!(matches!(
+            interpreter.end().unwrap(),
+            LedgerResponse::TaskDone
+        ));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = LedgerConfig::new()
+            .with_cla(Cla::Bitcoin, 0xe5)
+            .with_app_name(Network::Testnet, "Bitcoin QA");
+        let mut interpreter = Ledger::default().with_config(config.clone());
+        interpreter
+            .start(LedgerCommand::EnsureApp(Network::Testnet))
+            .unwrap();
+        let apdu = interpreter
+            .exchange(app_info("Bitcoin Test"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(apdu.encode(), vec![0xb0, 0xa7, 0x00, 0x00, 0x00]);
+
+        let mut interpreter = Ledger::default().with_config(config.clone());
+        interpreter
+            .start(LedgerCommand::EnsureApp(Network::Testnet))
+            .unwrap();
+        let apdu = interpreter.exchange(app_info("BOLOS")).unwrap().unwrap();
+        assert_eq!(apdu.data, b"Bitcoin QA");
+
+        let mut interpreter = Ledger::default().with_config(config);
+        let apdu = interpreter
+            .start(LedgerCommand::GetMasterFingerprint)
+            .unwrap();
+        assert_eq!((apdu.cla, apdu.ins), (0xe5, 0x05));
+    }
+
+    #[test]
+    fn test_register_wallet() {
+        let (command, _) = sign_psbt_command();
+        let policy = match command {
+            LedgerCommand::SignPsbt { policy, .. } => policy,
+            _ => unreachable!(),
+        };
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter
+            .start(LedgerCommand::RegisterWallet(policy.clone()))
+            .unwrap();
+        assert_eq!(apdu.ins, BitcoinCommandCode::RegisterWallet as u8);
+
+        // The device requests the descriptor template of the v2 policy.
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(sha256::Hash::hash(policy.descriptor_template.as_bytes()).to_byte_array());
+        request.extend([0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        assert!(apdu.data.ends_with(policy.descriptor_template.as_bytes()));
+
+        let mut response = policy.id().to_vec();
+        response.extend([0x03; 32]);
+        response.extend([0x90, 0x00]);
+        assert!(interpreter.exchange(response).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::WalletRegistered { id, hmac } => {
+                assert_eq!(id, policy.id());
+                assert_eq!(hmac, [0x03; 32]);
+            }
+            _ => panic!("expected a registered wallet"),
+        }
+
+        // The reason of the rejection is kept.
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::RegisterWallet(policy.clone()))
+            .unwrap();
+        let mut response = b"cosigner key #2 has wrong origin".to_vec();
+        response.extend([0x6a, 0x80]);
+        match interpreter.exchange(response) {
+            Err(LedgerError::Detailed(StatusWord::IncorrectData, detail)) => {
+                assert_eq!(detail.to_string(), "cosigner key #2 has wrong origin")
+            }
+            res => panic!("expected the detail of the failure, got {:?}", res.err()),
+        }
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::RegisterWallet(policy))
+            .unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x6a, 0x80]),
+            Err(LedgerError::WrongParameters(StatusWord::IncorrectData))
+        ));
+    }
+
+    #[test]
+    fn test_hmac_store() {
+        let (command, _) = sign_psbt_command();
+        let policy = match command {
+            LedgerCommand::SignPsbt { policy, .. } => policy,
+            _ => unreachable!(),
+        };
+        let hmacs = Rc::new(RefCell::new(MemoryHmacStore::default()));
+        let mut interpreter = Ledger::default().with_hmac_store(hmacs.clone());
+        interpreter
+            .start(LedgerCommand::RegisterWallet(policy.clone()))
+            .unwrap();
+        let mut response = policy.id().to_vec();
+        response.extend([0x03; 32]);
+        response.extend([0x90, 0x00]);
+        assert!(interpreter.exchange(response).unwrap().is_none());
+        assert_eq!(hmacs.get(&policy.id()), Some([0x03; 32]));
+
+        let mut interpreter = Ledger::default().with_hmac_store(hmacs);
+        let apdu = interpreter
+            .start(LedgerCommand::GetWalletAddress {
+                policy,
+                hmac: None,
+                change: false,
+                index: 0,
+                display: false,
+            })
+            .unwrap();
+        assert_eq!(apdu.data[33..65], [0x03; 32]);
+    }
+
+    #[test]
+    fn test_get_wallet_address() {
+        let (command, _) = sign_psbt_command();
+        let policy = match command {
+            LedgerCommand::SignPsbt { policy, .. } => policy,
+            _ => unreachable!(),
+        };
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter
+            .start(LedgerCommand::GetWalletAddress {
+                policy: policy.clone(),
+                hmac: None,
+                change: true,
+                index: 7,
+                display: true,
+            })
+            .unwrap();
+        assert_eq!(apdu.ins, BitcoinCommandCode::GetWalletAddress as u8);
+        assert_eq!(apdu.data[0], 1);
+        assert_eq!(apdu.data[1..33], policy.id());
+        assert_eq!(apdu.data[65..], [1, 0, 0, 0, 7]);
+
+        let address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let mut response = address.as_bytes().to_vec();
+        response.extend([0x90, 0x00]);
+        assert!(interpreter.exchange(response).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::Address(addr) => {
+                assert_eq!(
+                    addr.require_network(Network::Testnet).unwrap().to_string(),
+                    address
+                )
+            }
+            _ => panic!("expected an address"),
+        }
+
+        let mut interpreter = Ledger::default();
+        interpreter
+            .start(LedgerCommand::GetWalletAddress {
+                policy,
+                hmac: None,
+                change: false,
+                index: 0,
+                display: false,
+            })
+            .unwrap();
+        assert!(matches!(
+            interpreter.exchange(vec![0x69, 0x85]),
+            Err(LedgerError::DeniedByUser)
+        ));
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let secp = Secp256k1::new();
+        let key = Xpriv::new_master(Network::Testnet, &[0x01; 32])
+            .unwrap()
+            .private_key;
+        let message = "a".repeat(100);
+        let mut interpreter = Ledger::default();
+        let apdu = interpreter
+            .start(LedgerCommand::SignMessage {
+                path: DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap(),
+                message: message.as_bytes().to_vec(),
+            })
+            .unwrap();
+        assert_eq!(apdu.ins, BitcoinCommandCode::SignMessage as u8);
+        assert_eq!(apdu.data[0], 5);
+        assert_eq!(apdu.data[21], 100);
+
+        // The message is split in chunks of 64 bytes.
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(merkleized_map::leaf_hash(&message.as_bytes()[64..]));
+        request.extend([0xE0, 0x00]);
+        let apdu = interpreter.exchange(request).unwrap().unwrap();
+        assert!(apdu.data.ends_with(&message.as_bytes()[64..]));
+
+        let hash = bitcoin::sign_message::signed_msg_hash(&message);
+        let signature = MessageSignature::new(
+            secp.sign_ecdsa_recoverable(&Message::from_digest(hash.to_byte_array()), &key),
+            true,
+        );
+        let mut response = signature.serialize().to_vec();
+        response.extend([0x90, 0x00]);
+        assert!(interpreter.exchange(response).unwrap().is_none());
+        match interpreter.end().unwrap() {
+            LedgerResponse::MessageSignature(encoded) => {
+                let decoded =
+                    MessageSignature::from_slice(&Base64::decode_vec(&encoded).unwrap()).unwrap();
+                assert_eq!(
+                    decoded.recover_pubkey(&secp, hash).unwrap().inner,
+                    key.public_key(&secp)
+                );
+            }
+            _ => panic!("expected a message signature"),
+        }
+    }
+
+    #[test]
+    fn test_chunked_command() {
+        let mut interpreter = Ledger::default();
+        let first = interpreter.chunked(ApduCommand {
+            data: vec![0x01; 300],
+            ..Default::default()
+        });
+        assert_eq!(first.data.len(), 255);
+        match interpreter.exchange(vec![0x90, 0x00]).unwrap() {
+            Some(chunk) => {
+                assert_eq!(chunk.p1, apdu::P1_MORE_CHUNKS);
+                assert_eq!(chunk.data.len(), 45);
+            }
+            None => panic!("expected the next chunk"),
+        }
+
+        interpreter.start(LedgerCommand::GetAppInfo).unwrap();
+        assert_eq!(interpreter.status(), InterpreterStatus::AwaitingDevice);
+        interpreter.chunked(ApduCommand {
+            data: vec![0x01; 300],
+            ..Default::default()
+        });
+        assert_eq!(interpreter.status(), InterpreterStatus::Transmitting);
+        assert!(matches!(
+            interpreter.exchange(vec![0x6a, 0x87]),
+            Err(LedgerError::WrongParameters(StatusWord::WrongDataLength))
+        ));
     }
 }
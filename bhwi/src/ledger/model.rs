@@ -0,0 +1,93 @@
+//! Capabilities of the Ledger models, for the hosts to adjust the flows and
+//! the copy of their UI: the Stax and the Flex draw their flows with NBGL on
+//! a touch screen, the review of a command is scrolled and the user holds
+//! to sign, while the Nano models go through the steps with their buttons.
+
+use crate::devices::{self, DeviceKind};
+
+/// Deadline of the commands waiting for the user on the Nano models.
+pub const BUTTONS_CONFIRMATION_TIMEOUT_MS: u64 = 120_000;
+/// Deadline of the commands waiting for the user on the touch screens, the
+/// NBGL review shows every detail before the confirmation.
+pub const TOUCH_CONFIRMATION_TIMEOUT_MS: u64 = 300_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Confirmation {
+    /// Both buttons pressed on the last step of the review.
+    Buttons,
+    /// The sign button held at the end of the review.
+    HoldToSign,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceModel {
+    pub kind: DeviceKind,
+    /// The flows are drawn by NBGL on a touch screen.
+    pub touch_screen: bool,
+    pub confirmation: Confirmation,
+    /// The displayed address can be shown as a QR code on the device.
+    pub address_qr: bool,
+    /// The device connects over Bluetooth LE, see [`super::transport::ble`].
+    pub ble: bool,
+    /// Recommended deadline of the commands waiting for the user, see
+    /// [`super::LedgerInterpreter::with_deadline`].
+    pub confirmation_timeout_ms: u64,
+}
+
+impl DeviceModel {
+    /// Returns the capabilities of the Ledger model, None for the other
+    /// devices.
+    pub fn from_kind(kind: DeviceKind) -> Option<Self> {
+        let touch_screen = match kind {
+            DeviceKind::LedgerNanoS | DeviceKind::LedgerNanoSPlus | DeviceKind::LedgerNanoX => {
+                false
+            }
+            DeviceKind::LedgerStax | DeviceKind::LedgerFlex => true,
+            _ => return None,
+        };
+        Some(Self {
+            kind,
+            touch_screen,
+            confirmation: if touch_screen {
+                Confirmation::HoldToSign
+            } else {
+                Confirmation::Buttons
+            },
+            address_qr: touch_screen,
+            ble: !matches!(kind, DeviceKind::LedgerNanoS | DeviceKind::LedgerNanoSPlus),
+            confirmation_timeout_ms: if touch_screen {
+                TOUCH_CONFIRMATION_TIMEOUT_MS
+            } else {
+                BUTTONS_CONFIRMATION_TIMEOUT_MS
+            },
+        })
+    }
+
+    /// Returns the capabilities of the Ledger with the USB product id.
+    pub fn from_product_id(product_id: u16) -> Option<Self> {
+        devices::identify(devices::LEDGER_VID, product_id).and_then(Self::from_kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_model() {
+        let flex = DeviceModel::from_product_id(0x7011).unwrap();
+        assert_eq!(flex.kind, DeviceKind::LedgerFlex);
+        assert!(flex.touch_screen && flex.address_qr && flex.ble);
+        assert_eq!(flex.confirmation, Confirmation::HoldToSign);
+        assert_eq!(flex.confirmation_timeout_ms, TOUCH_CONFIRMATION_TIMEOUT_MS);
+
+        let nano = DeviceModel::from_kind(DeviceKind::LedgerNanoSPlus).unwrap();
+        assert!(!nano.touch_screen && !nano.address_qr && !nano.ble);
+        assert_eq!(nano.confirmation, Confirmation::Buttons);
+        assert!(DeviceModel::from_kind(DeviceKind::LedgerNanoX).unwrap().ble);
+        assert_eq!(DeviceModel::from_kind(DeviceKind::Coldcard), None);
+    }
+}
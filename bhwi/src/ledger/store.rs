@@ -1,37 +1,92 @@
+use alloc::collections::BTreeMap;
 use core::convert::TryFrom;
 use core::fmt::Debug;
 
 use bitcoin::{
     consensus::encode::{self, VarInt},
-    hashes::{sha256, Hash, HashEngine},
+    hashes::{sha256, Hash},
+    Psbt,
 };
 
-use super::{apdu::ClientCommandCode, merkle::MerkleTree};
+use super::apdu::ClientCommandCode;
+
+use crate::{
+    log_event,
+    merkle::MerkleTree,
+    merkleized_map::{self, MapCommitment},
+    prelude::*,
+    psbt::{self, Map, MapRef},
+};
+
+/// Source of the preimages requested by the device with GET_PREIMAGE, so
+/// that the host may serve them lazily, from its disk for example, instead
+/// of keeping them in memory.
+pub trait PreimageProvider {
+    /// Keeps the preimage of the hash, added by the store.
+    fn insert(&mut self, hash: [u8; 32], preimage: Vec<u8>);
+    /// Returns the preimage of the hash, none if it is unknown.
+    fn preimage(&mut self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError>;
+}
+
+/// Preimages kept in memory, the provider of the store by default.
+#[derive(Debug, Default)]
+pub struct KnownPreimages(BTreeMap<[u8; 32], Vec<u8>>);
+
+impl PreimageProvider for KnownPreimages {
+    fn insert(&mut self, hash: [u8; 32], preimage: Vec<u8>) {
+        self.0.insert(hash, preimage);
+    }
+
+    fn preimage(&mut self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.0.get(hash).cloned())
+    }
+}
+
+/// Leaf of a psbt map, the key or the value of the pair at the index of the
+/// sorted map.
+#[derive(Debug, Clone, Copy)]
+struct PsbtLeaf {
+    map: MapRef,
+    index: usize,
+    value: bool,
+}
 
 /// This struct keeps has methods to keep track of:
 ///   - known preimages
 ///   - known Merkle trees from lists of elements
+///
 /// Moreover, it containes the state that is relevant for the interpreted client side commands:
 ///   - a queue of bytes that contains any bytes that could not fit in a response from the
 ///     GET_PREIMAGE client command (when a preimage is too long to fit in a single message) or the
 ///     GET_MERKLE_LEAF_PROOF command (which returns a Merkle proof, which might be too long to fit
 ///     in a single message). The data in the queue is returned in one (or more) successive
 ///     GET_MORE_ELEMENTS commands from the hardware wallet.
+///
 /// Finally, it keeps track of the yielded values (that is, the values sent from the hardware
 /// wallet with a YIELD client command).
-pub struct DelegatedStore {
+#[derive(Default)]
+pub struct DelegatedStore<P = KnownPreimages> {
     yielded: Vec<Vec<u8>>,
     queue: Vec<Vec<u8>>,
-    known_preimages: Vec<([u8; 32], Vec<u8>)>,
+    known_preimages: P,
+    psbt_leaves: BTreeMap<[u8; 32], PsbtLeaf>,
     trees: Vec<MerkleTree>,
 }
 
 impl DelegatedStore {
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<P: PreimageProvider> DelegatedStore<P> {
+    /// Returns a store serving the preimages of the provider.
+    pub fn with_provider(provider: P) -> Self {
         Self {
             yielded: Vec::new(),
             queue: Vec::new(),
-            known_preimages: Vec::new(),
+            known_preimages: provider,
+            psbt_leaves: BTreeMap::new(),
             trees: Vec::new(),
         }
     }
@@ -40,10 +95,8 @@ impl DelegatedStore {
     /// The client must respond with `element` when a GET_PREIMAGE command is sent with
     /// `sha256(element)` in its request.
     pub fn add_known_preimage(&mut self, element: Vec<u8>) {
-        let mut engine = sha256::Hash::engine();
-        engine.input(&element);
-        let hash = sha256::Hash::from_engine(engine).to_byte_array();
-        self.known_preimages.push((hash, element));
+        let hash = sha256::Hash::hash(&element).to_byte_array();
+        self.known_preimages.insert(hash, element);
     }
 
     /// Adds a known Merkleized list.
@@ -55,18 +108,26 @@ impl DelegatedStore {
     /// Moreover, the commands GET_MERKLE_LEAF_INDEX and GET_MERKLE_LEAF_PROOF must correctly answer
     /// queries relative to the Merkle whose root is `mt_root`.
     pub fn add_known_list(&mut self, elements: &[impl AsRef<[u8]>]) -> [u8; 32] {
-        let mut leaves = Vec::with_capacity(elements.len());
-        for element in elements {
-            let mut preimage = vec![0x00];
-            preimage.extend_from_slice(element.as_ref());
-            let mut engine = sha256::Hash::engine();
-            engine.input(&preimage);
-            let hash = sha256::Hash::from_engine(engine).to_byte_array();
-            self.known_preimages.push((hash, preimage));
+        self.add_known_leaves(elements.iter().map(|e| e.as_ref().to_vec()))
+    }
+
+    /// Same as `add_known_list` but takes ownership of the elements, which are moved
+    /// into the known preimages instead of being copied.
+    fn add_known_leaves(&mut self, elements: impl IntoIterator<Item = Vec<u8>>) -> [u8; 32] {
+        let elements = elements.into_iter();
+        let mut leaves = Vec::with_capacity(elements.size_hint().0);
+        for mut preimage in elements {
+            let hash = merkleized_map::leaf_hash(&preimage);
+            preimage.insert(0, merkleized_map::LEAF_PREFIX);
+            self.known_preimages.insert(hash, preimage);
             leaves.push(hash);
         }
+        self.add_tree(leaves)
+    }
+
+    fn add_tree(&mut self, leaves: Vec<[u8; 32]>) -> [u8; 32] {
         let tree = MerkleTree::new(leaves);
-        let root_hash = *tree.root_hash();
+        let root_hash = *tree.root();
         self.trees.push(tree);
         root_hash
     }
@@ -75,42 +136,105 @@ impl DelegatedStore {
     /// of a mapping of bytes to bytes.
     /// Adds the Merkle tree of the list of keys, and the Merkle tree of the list of corresponding
     /// values, with the same semantics as the `add_known_list` applied separately to the two lists.
-    pub fn add_known_mapping(&mut self, mapping: &[(Vec<u8>, Vec<u8>)]) {
-        let mut sorted: Vec<&(Vec<u8>, Vec<u8>)> = mapping.iter().collect();
-        sorted.sort_by(|(k1, _), (k2, _)| k1.as_slice().cmp(k2));
-
-        let mut keys = Vec::with_capacity(sorted.len());
-        let mut values = Vec::with_capacity(sorted.len());
-        for (key, value) in sorted {
-            keys.push(key.as_slice());
-            values.push(value.as_slice());
+    /// The mapping is consumed and its serialized commitment is returned, so that
+    /// the caller does not have to keep the mapping around to compute it.
+    pub fn add_known_mapping(&mut self, mut mapping: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<u8> {
+        merkleized_map::sort_mapping(&mut mapping);
+        let size = mapping.len();
+        let (keys, values): (Vec<Vec<u8>>, Vec<Vec<u8>>) = mapping.into_iter().unzip();
+        MapCommitment {
+            size,
+            keys_root: self.add_known_leaves(keys),
+            values_root: self.add_known_leaves(values),
         }
-        self.add_known_list(&keys);
-        self.add_known_list(&values);
+        .serialize()
+    }
+
+    /// Same as `add_known_mapping` for the map of a psbt, whose leaves are not
+    /// kept but served from the psbt given to `execute_with_psbt`: only their
+    /// location in the psbt is kept for the session.
+    pub fn add_psbt_map(&mut self, map: MapRef, mut mapping: Map) -> Vec<u8> {
+        merkleized_map::sort_mapping(&mut mapping);
+        let mut keys = Vec::with_capacity(mapping.len());
+        let mut values = Vec::with_capacity(mapping.len());
+        for (index, (key, value)) in mapping.iter().enumerate() {
+            let hash = merkleized_map::leaf_hash(key);
+            self.psbt_leaves.insert(
+                hash,
+                PsbtLeaf {
+                    map,
+                    index,
+                    value: false,
+                },
+            );
+            keys.push(hash);
+            let hash = merkleized_map::leaf_hash(value);
+            self.psbt_leaves.insert(
+                hash,
+                PsbtLeaf {
+                    map,
+                    index,
+                    value: true,
+                },
+            );
+            values.push(hash);
+        }
+        MapCommitment {
+            size: mapping.len(),
+            keys_root: self.add_tree(keys),
+            values_root: self.add_tree(values),
+        }
+        .serialize()
     }
 
     // Interprets the client command requested by the hardware wallet, returns the appropriate
     // response to transmit back and updates interpreter internal states.
     pub fn execute(&mut self, command: Vec<u8>) -> Result<Vec<u8>, StoreError> {
+        self.execute_command(command, None)
+    }
+
+    /// Same as `execute`, serving the leaves of the maps added with
+    /// `add_psbt_map` from the psbt.
+    pub fn execute_with_psbt(
+        &mut self,
+        command: Vec<u8>,
+        psbt: &Psbt,
+    ) -> Result<Vec<u8>, StoreError> {
+        self.execute_command(command, Some(psbt))
+    }
+
+    fn execute_command(
+        &mut self,
+        mut command: Vec<u8>,
+        psbt: Option<&Psbt>,
+    ) -> Result<Vec<u8>, StoreError> {
         if command.is_empty() {
             return Err(StoreError::EmptyInput);
         }
-        match ClientCommandCode::try_from(command[0]) {
-            Ok(ClientCommandCode::Yield) => {
-                self.yielded.push(command[1..].to_vec());
+        let code = ClientCommandCode::try_from(command[0])
+            .map_err(|()| StoreError::UnknownCommand(command[0]))?;
+        log_event!(debug, "client command {:?}", code);
+        match code {
+            ClientCommandCode::Yield => {
+                command.remove(0);
+                self.yielded.push(command);
                 Ok(Vec::new())
             }
-            Ok(ClientCommandCode::GetPreimage) => {
-                get_preimage_command(&mut self.queue, &self.known_preimages, &command[1..])
+            ClientCommandCode::GetPreimage => {
+                let hash = preimage_request(&command[1..])?;
+                let preimage = match (self.psbt_leaves.get(hash), psbt) {
+                    (Some(leaf), Some(psbt)) => Some(psbt_leaf_preimage(psbt, leaf, hash)?),
+                    _ => self.known_preimages.preimage(hash)?,
+                };
+                get_preimage_command(&mut self.queue, preimage.ok_or(StoreError::UnknownHash)?)
             }
-            Ok(ClientCommandCode::GetMerkleLeafProof) => {
+            ClientCommandCode::GetMerkleLeafProof => {
                 get_merkle_leaf_proof(&mut self.queue, &self.trees, &command[1..])
             }
-            Ok(ClientCommandCode::GetMerkleLeafIndex) => {
+            ClientCommandCode::GetMerkleLeafIndex => {
                 get_merkle_leaf_index(&self.trees, &command[1..])
             }
-            Ok(ClientCommandCode::GetMoreElements) => get_more_elements(&mut self.queue),
-            Err(()) => Err(StoreError::UnknownCommand(command[0])),
+            ClientCommandCode::GetMoreElements => get_more_elements(&mut self.queue),
         }
     }
 
@@ -120,22 +244,42 @@ impl DelegatedStore {
     }
 }
 
-fn get_preimage_command(
-    queue: &mut Vec<Vec<u8>>,
-    known_preimages: &[([u8; 32], Vec<u8>)],
-    request: &[u8],
-) -> Result<Vec<u8>, StoreError> {
-    if request.len() != 33 || request[0] != b'\0' {
-        return Err(StoreError::UnsupportedRequest(
+/// Returns the hash of the GET_PREIMAGE request.
+fn preimage_request(request: &[u8]) -> Result<&[u8; 32], StoreError> {
+    match request {
+        [b'\0', hash @ ..] => hash
+            .try_into()
+            .map_err(|_| StoreError::UnsupportedRequest(ClientCommandCode::GetPreimage as u8)),
+        _ => Err(StoreError::UnsupportedRequest(
             ClientCommandCode::GetPreimage as u8,
-        ));
-    };
+        )),
+    }
+}
 
-    let (_, preimage) = known_preimages
-        .iter()
-        .find(|(hash, _)| hash == &request[1..])
+/// Returns the preimage of the leaf, rebuilt from its map in the psbt.
+fn psbt_leaf_preimage(
+    psbt: &Psbt,
+    leaf: &PsbtLeaf,
+    hash: &[u8; 32],
+) -> Result<Vec<u8>, StoreError> {
+    let mut mapping = psbt::get_v2_map(psbt, leaf.map).ok_or(StoreError::UnknownHash)?;
+    merkleized_map::sort_mapping(&mut mapping);
+    let (key, value) = mapping
+        .into_iter()
+        .nth(leaf.index)
         .ok_or(StoreError::UnknownHash)?;
+    let element = if leaf.value { value } else { key };
+    // The psbt is not the one of the commitment.
+    if merkleized_map::leaf_hash(&element) != *hash {
+        return Err(StoreError::UnknownHash);
+    }
+    Ok(merkleized_map::leaf_preimage(&element))
+}
 
+fn get_preimage_command(
+    queue: &mut Vec<Vec<u8>>,
+    preimage: Vec<u8>,
+) -> Result<Vec<u8>, StoreError> {
     let preimage_len_out = encode::serialize(&VarInt(preimage.len() as u64));
 
     // We can send at most 255 - len(preimage_len_out) - 1 bytes in a single message;
@@ -183,7 +327,7 @@ fn get_merkle_leaf_proof(
 
     let tree = trees
         .iter()
-        .find(|tree| tree.root_hash() == root)
+        .find(|tree| tree.root() == root)
         .ok_or(StoreError::UnknownHash)?;
 
     if leaf_index >= tree_size || tree_size.0 != tree.size() as u64 {
@@ -191,7 +335,7 @@ fn get_merkle_leaf_proof(
     }
 
     let proof = tree
-        .get_leaf_proof(leaf_index.0 as usize)
+        .proof(leaf_index.0 as usize)
         .ok_or(StoreError::InvalidIndexOrSize)?;
 
     let len_proof = proof.len();
@@ -205,7 +349,7 @@ fn get_merkle_leaf_proof(
             n_response_elements += 1;
         } else {
             // Add to the queue any proof elements that do not fit the response
-            queue.push(p);
+            queue.push(p.to_vec());
         }
     }
 
@@ -227,7 +371,7 @@ fn get_merkle_leaf_index(trees: &[MerkleTree], request: &[u8]) -> Result<Vec<u8>
 
     let tree = trees
         .iter()
-        .find(|tree| tree.root_hash() == root)
+        .find(|tree| tree.root() == root)
         .ok_or(StoreError::UnknownHash)?;
 
     let leaf_index = tree.get_leaf_index(hash).ok_or(StoreError::UnknownHash)?;
@@ -256,7 +400,7 @@ fn get_more_elements(queue: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, StoreError> {
             n_added_elements += 1;
         }
     }
-    *queue = queue[n_added_elements..].to_vec();
+    queue.drain(..n_added_elements);
 
     let mut response = (n_added_elements as u8).to_be_bytes().to_vec();
     response.extend((element_length as u8).to_be_bytes());
@@ -264,37 +408,13 @@ fn get_more_elements(queue: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, StoreError> {
     Ok(response)
 }
 
-/// Returns a serialized Merkleized map commitment, encoded as the concatenation of:
-///     - the number of key/value pairs, as a Bitcoin-style varint;
-///     - the root of the Merkle tree of the keys
-///     - the root of the Merkle tree of the values.
+/// Returns a serialized Merkleized map commitment, see `merkleized_map::MapCommitment`.
 pub fn get_merkleized_map_commitment(mapping: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
-    let mut sorted: Vec<&(Vec<u8>, Vec<u8>)> = mapping.iter().collect();
-    sorted.sort_by(|(k1, _), (k2, _)| k1.as_slice().cmp(k2));
-
-    let mut keys_hashes: Vec<[u8; 32]> = Vec::with_capacity(sorted.len());
-    let mut values_hashes: Vec<[u8; 32]> = Vec::with_capacity(sorted.len());
-    for (key, value) in &sorted {
-        let mut preimage = vec![0x00];
-        preimage.extend_from_slice(key);
-        let mut engine = sha256::Hash::engine();
-        engine.input(&preimage);
-        keys_hashes.push(sha256::Hash::from_engine(engine).to_byte_array());
-
-        let mut preimage = vec![0x00];
-        preimage.extend_from_slice(value);
-        let mut engine = sha256::Hash::engine();
-        engine.input(&preimage);
-        values_hashes.push(sha256::Hash::from_engine(engine).to_byte_array());
-    }
-
-    let mut commitment = encode::serialize(&VarInt(sorted.len() as u64));
-    commitment.extend(MerkleTree::new(keys_hashes).root_hash());
-    commitment.extend(MerkleTree::new(values_hashes).root_hash());
-    commitment
+    MapCommitment::new(mapping).serialize()
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StoreError {
     EmptyInput,
     UnknownCommand(u8),
@@ -303,4 +423,353 @@ pub enum StoreError {
     UnknownHash,
     UnknownMerkleRoot,
     UnexpectedQueue,
+    /// The preimage provider failed, with its message.
+    Provider(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_known_mapping_commitment() {
+        let mapping = vec![
+            (vec![0x03], vec![0x01, 0x00, 0x00, 0x00]),
+            (vec![0x01, 0xaa], vec![0xbb; 300]),
+            (vec![0x02], Vec::new()),
+        ];
+        let mut store = DelegatedStore::new();
+        let commitment = store.add_known_mapping(mapping.clone());
+        assert_eq!(commitment, get_merkleized_map_commitment(&mapping));
+
+        // The preimage of a key leaf must be served back to the device.
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(merkleized_map::leaf_hash(&[0x01, 0xaa]));
+        assert_eq!(
+            store.execute(request).unwrap(),
+            vec![3, 3, 0x00, 0x01, 0xaa]
+        );
+    }
+
+    #[test]
+    fn test_psbt_map_served_from_psbt() {
+        use bitcoin::{
+            absolute::LockTime, transaction::Version, Amount, OutPoint, ScriptBuf, Transaction,
+            TxIn, TxOut,
+        };
+        let tx = |value| Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let psbt = Psbt::from_unsigned_tx(tx(1_000)).unwrap();
+        let map = psbt::get_v2_map(&psbt, MapRef::Output(0)).unwrap();
+        let mut store = DelegatedStore::new();
+        let commitment = store.add_psbt_map(MapRef::Output(0), map.clone());
+        assert_eq!(commitment, get_merkleized_map_commitment(&map));
+
+        let (_, amount) = map.iter().find(|(key, _)| key == &[0x03]).unwrap();
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(merkleized_map::leaf_hash(amount));
+        assert_eq!(
+            store.execute_with_psbt(request.clone(), &psbt).unwrap(),
+            [&[9, 9][..], &merkleized_map::leaf_preimage(amount)].concat()
+        );
+        // The preimages are not kept by the store.
+        assert!(matches!(
+            store.execute(request.clone()),
+            Err(StoreError::UnknownHash)
+        ));
+        let other = Psbt::from_unsigned_tx(tx(2_000)).unwrap();
+        assert!(matches!(
+            store.execute_with_psbt(request, &other),
+            Err(StoreError::UnknownHash)
+        ));
+    }
+
+    /// Provider streaming the preimages it was never given, and failing
+    /// after the given number of reads.
+    struct Streamed {
+        element: Vec<u8>,
+        reads: usize,
+    }
+
+    impl PreimageProvider for Streamed {
+        fn insert(&mut self, _hash: [u8; 32], _preimage: Vec<u8>) {}
+
+        fn preimage(&mut self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError> {
+            if self.reads == 0 {
+                return Err(StoreError::Provider("disk unavailable".to_string()));
+            }
+            self.reads -= 1;
+            Ok((sha256::Hash::hash(&self.element).to_byte_array() == *hash)
+                .then(|| self.element.clone()))
+        }
+    }
+
+    #[test]
+    fn test_preimage_provider() {
+        let element: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let hash = sha256::Hash::hash(&element).to_byte_array();
+        let mut store = DelegatedStore::with_provider(Streamed {
+            element: element.clone(),
+            reads: 2,
+        });
+        assert_eq!(fetch_preimage(&mut store, &hash), element);
+
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend([0x00; 32]);
+        assert!(matches!(
+            store.execute(request.clone()),
+            Err(StoreError::UnknownHash)
+        ));
+        assert!(matches!(
+            store.execute(request),
+            Err(StoreError::Provider(_))
+        ));
+    }
+
+    /// Reads the elements queued by the store for the GET_MORE_ELEMENTS command.
+    fn fetch_more<P: PreimageProvider>(
+        store: &mut DelegatedStore<P>,
+        data: &mut Vec<u8>,
+        count: usize,
+    ) {
+        let response = store
+            .execute(vec![ClientCommandCode::GetMoreElements as u8])
+            .unwrap();
+        let (n, len) = (response[0] as usize, response[1] as usize);
+        assert!(n > 0 && n <= count);
+        assert_eq!(response.len(), 2 + n * len);
+        data.extend_from_slice(&response[2..]);
+    }
+
+    fn fetch_preimage<P: PreimageProvider>(
+        store: &mut DelegatedStore<P>,
+        hash: &[u8; 32],
+    ) -> Vec<u8> {
+        let mut request = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        request.extend(hash);
+        let response = store.execute(request).unwrap();
+        let (len, read): (VarInt, usize) = encode::deserialize_partial(&response).unwrap();
+        let len = len.0 as usize;
+        let mut preimage = response[read + 1..].to_vec();
+        assert_eq!(preimage.len(), response[read] as usize);
+        while preimage.len() < len {
+            let remaining = len - preimage.len();
+            fetch_more(store, &mut preimage, remaining);
+        }
+        preimage
+    }
+
+    fn fetch_leaf_proof(
+        store: &mut DelegatedStore,
+        root: &[u8; 32],
+        size: usize,
+        index: usize,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut request = vec![ClientCommandCode::GetMerkleLeafProof as u8];
+        request.extend(root);
+        request.extend(encode::serialize(&VarInt(size as u64)));
+        request.extend(encode::serialize(&VarInt(index as u64)));
+        let response = store.execute(request).unwrap();
+        let (len, n) = (response[32] as usize, response[33] as usize);
+        let mut proof = response[34..].to_vec();
+        assert_eq!(proof.len(), n * 32);
+        while proof.len() < len * 32 {
+            let remaining = len - proof.len() / 32;
+            fetch_more(store, &mut proof, remaining);
+        }
+        (response[..32].to_vec(), proof)
+    }
+
+    fn fetch_leaf_index(store: &mut DelegatedStore, root: &[u8; 32], hash: &[u8; 32]) -> usize {
+        let mut request = vec![ClientCommandCode::GetMerkleLeafIndex as u8];
+        request.extend(root);
+        request.extend(hash);
+        let response = store.execute(request).unwrap();
+        assert_eq!(response[0], 1);
+        let index: VarInt = encode::deserialize(&response[1..]).unwrap();
+        index.0 as usize
+    }
+
+    /// Client commands issued by a scripted device, each one followed by
+    /// the GET_MORE_ELEMENTS commands needed to read the whole response.
+    #[derive(Clone, Copy, Debug)]
+    enum Step {
+        Preimage,
+        ListPreimage,
+        LeafProof,
+        LeafIndex,
+        Yield,
+    }
+
+    struct Fixture {
+        long_preimage: Vec<u8>,
+        elements: Vec<Vec<u8>>,
+        root: [u8; 32],
+        tree: MerkleTree,
+    }
+
+    impl Fixture {
+        fn new(store: &mut DelegatedStore) -> Self {
+            let long_preimage: Vec<u8> = (0..600).map(|i| i as u8).collect();
+            store.add_known_preimage(long_preimage.clone());
+            // More than 64 leaves: the proofs do not fit a single response.
+            let elements: Vec<Vec<u8>> = (0..100u32).map(|i| i.to_le_bytes().to_vec()).collect();
+            let root = store.add_known_list(&elements);
+            let tree = MerkleTree::new(
+                elements
+                    .iter()
+                    .map(|e| merkleized_map::leaf_hash(e))
+                    .collect(),
+            );
+            Self {
+                long_preimage,
+                elements,
+                root,
+                tree,
+            }
+        }
+
+        fn run(&self, store: &mut DelegatedStore, step: Step, i: usize) {
+            match step {
+                Step::Preimage => {
+                    let hash = sha256::Hash::hash(&self.long_preimage).to_byte_array();
+                    assert_eq!(fetch_preimage(store, &hash), self.long_preimage);
+                }
+                Step::ListPreimage => {
+                    let element = &self.elements[i];
+                    assert_eq!(
+                        fetch_preimage(store, &merkleized_map::leaf_hash(element)),
+                        merkleized_map::leaf_preimage(element)
+                    );
+                }
+                Step::LeafProof => {
+                    let (leaf, proof) = fetch_leaf_proof(store, &self.root, self.elements.len(), i);
+                    assert_eq!(leaf, self.tree.get_leaf(i).unwrap().to_vec());
+                    assert_eq!(proof, self.tree.proof(i).unwrap().concat());
+                }
+                Step::LeafIndex => {
+                    let hash = merkleized_map::leaf_hash(&self.elements[i]);
+                    assert_eq!(fetch_leaf_index(store, &self.root, &hash), i);
+                }
+                Step::Yield => {
+                    let mut request = vec![ClientCommandCode::Yield as u8];
+                    request.extend((i as u32).to_le_bytes());
+                    assert_eq!(store.execute(request).unwrap(), Vec::<u8>::new());
+                }
+            }
+            // Nothing is left behind once the device read the whole response.
+            assert!(matches!(
+                store.execute(vec![ClientCommandCode::GetMoreElements as u8]),
+                Err(StoreError::UnexpectedQueue)
+            ));
+        }
+    }
+
+    fn permutations(steps: &[Step]) -> Vec<Vec<Step>> {
+        if steps.is_empty() {
+            return vec![Vec::new()];
+        }
+        let mut res = Vec::new();
+        for i in 0..steps.len() {
+            let mut rest = steps.to_vec();
+            let first = rest.remove(i);
+            for mut perm in permutations(&rest) {
+                perm.insert(0, first);
+                res.push(perm);
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_client_commands_in_every_order() {
+        let steps = [
+            Step::Preimage,
+            Step::ListPreimage,
+            Step::LeafProof,
+            Step::LeafIndex,
+            Step::Yield,
+        ];
+        let mut shared = DelegatedStore::new();
+        let shared_fixture = Fixture::new(&mut shared);
+        for (n, order) in permutations(&steps).into_iter().enumerate() {
+            let mut store = DelegatedStore::new();
+            let fixture = Fixture::new(&mut store);
+            let mut yielded = Vec::new();
+            for (i, step) in order.iter().enumerate() {
+                let index = (n * 7 + i * 13) % fixture.elements.len();
+                fixture.run(&mut store, *step, index);
+                // The same store serves every session, one after the other.
+                shared_fixture.run(&mut shared, *step, index);
+                if let Step::Yield = step {
+                    yielded.push((index as u32).to_le_bytes().to_vec());
+                }
+            }
+            assert_eq!(store.yielded(), yielded);
+        }
+        assert_eq!(shared.yielded().len(), 120);
+    }
+
+    #[test]
+    fn test_truncated_client_commands() {
+        let mut store = DelegatedStore::new();
+        let fixture = Fixture::new(&mut store);
+
+        let hash = merkleized_map::leaf_hash(&fixture.elements[42]);
+        let mut preimage = vec![ClientCommandCode::GetPreimage as u8, 0x00];
+        preimage.extend(hash);
+        let mut proof = vec![ClientCommandCode::GetMerkleLeafProof as u8];
+        proof.extend(fixture.root);
+        proof.extend([100, 42]);
+        let mut index = vec![ClientCommandCode::GetMerkleLeafIndex as u8];
+        index.extend(fixture.root);
+        index.extend(hash);
+
+        for (request, step) in [
+            (preimage, Step::ListPreimage),
+            (proof, Step::LeafProof),
+            (index, Step::LeafIndex),
+        ] {
+            for len in 0..request.len() {
+                assert!(store.execute(request[..len].to_vec()).is_err());
+            }
+            // A truncated request does not leave the store in a bad state.
+            assert!(matches!(
+                store.execute(vec![ClientCommandCode::GetMoreElements as u8]),
+                Err(StoreError::UnexpectedQueue)
+            ));
+            fixture.run(&mut store, step, 42);
+            fixture.run(&mut store, Step::Preimage, 0);
+        }
+    }
+
+    #[test]
+    fn test_leaf_proof_while_reading_a_response() {
+        let mut store = DelegatedStore::new();
+        let fixture = Fixture::new(&mut store);
+
+        let mut request = vec![ClientCommandCode::GetMerkleLeafProof as u8];
+        request.extend(fixture.root);
+        request.extend([100, 0]);
+        store.execute(request.clone()).unwrap();
+        // The rest of the proof is still queued.
+        assert!(matches!(
+            store.execute(request),
+            Err(StoreError::UnexpectedQueue)
+        ));
+        let mut proof = Vec::new();
+        fetch_more(&mut store, &mut proof, 1);
+        assert_eq!(proof, fixture.tree.proof(0).unwrap()[6..].concat());
+        fixture.run(&mut store, Step::LeafProof, 0);
+    }
 }
@@ -0,0 +1,446 @@
+//! Known-good exchanges of the commands of the Ledger Bitcoin app, to check
+//! the interpreter and the transports byte for byte. The keys, the policy
+//! and the psbt of the commands derive from `SEED` on mainnet.
+
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{DerivationPath, Xpriv, Xpub},
+    hex::FromHex,
+    secp256k1::Secp256k1,
+    transaction::Version,
+    Amount, Network, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use core::str::FromStr;
+
+use super::{
+    apdu::ApduCommand, wallet, LedgerCommand, LedgerError, LedgerInterpreter, LedgerResponse,
+    WalletPolicy,
+};
+use crate::{prelude::*, Interpreter};
+
+pub const SEED: [u8; 32] = [0x01; 32];
+
+/// Apdu of the host and answer of the device with its status word, in hex.
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange {
+    pub request: &'static str,
+    pub response: &'static str,
+}
+
+impl Exchange {
+    pub fn request(&self) -> Vec<u8> {
+        Vec::from_hex(self.request).expect("valid hex")
+    }
+
+    pub fn response(&self) -> Vec<u8> {
+        Vec::from_hex(self.response).expect("valid hex")
+    }
+}
+
+pub struct Vector {
+    pub name: &'static str,
+    pub command: fn() -> LedgerCommand,
+    pub exchanges: &'static [Exchange],
+}
+
+/// Single signature policy of the account `m/84'/0'/0'`.
+pub fn wallet_policy() -> WalletPolicy {
+    let secp = Secp256k1::new();
+    let master = Xpriv::new_master(Network::Bitcoin, &SEED).expect("valid seed");
+    let path = DerivationPath::from_str("m/84'/0'/0'").expect("valid path");
+    let xpub = Xpub::from_priv(
+        &secp,
+        &master.derive_priv(&secp, &path).expect("valid path"),
+    );
+    WalletPolicy::new(
+        String::new(),
+        wallet::Version::V2,
+        "wpkh(@0/**)".to_string(),
+        [((master.fingerprint(&secp), path), xpub)],
+    )
+}
+
+/// Psbt spending a null outpoint to an empty script.
+pub fn psbt() -> Psbt {
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(1_000),
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+    Psbt::from_unsigned_tx(tx).expect("unsigned transaction")
+}
+
+fn path(path: &str) -> DerivationPath {
+    DerivationPath::from_str(path).expect("valid path")
+}
+
+fn open_app() -> LedgerCommand {
+    LedgerCommand::OpenApp(Network::Bitcoin)
+}
+
+fn quit_app() -> LedgerCommand {
+    LedgerCommand::QuitApp
+}
+
+fn get_app_info() -> LedgerCommand {
+    LedgerCommand::GetAppInfo
+}
+
+fn list_apps() -> LedgerCommand {
+    LedgerCommand::ListApps
+}
+
+fn ensure_app() -> LedgerCommand {
+    LedgerCommand::EnsureApp(Network::Bitcoin)
+}
+
+fn get_master_fingerprint() -> LedgerCommand {
+    LedgerCommand::GetMasterFingerprint
+}
+
+fn get_xpub() -> LedgerCommand {
+    LedgerCommand::GetXpub {
+        path: path("m/84'/0'/0'"),
+        display: false,
+    }
+}
+
+fn get_xpubs() -> LedgerCommand {
+    LedgerCommand::GetXpubs(vec![path("m/84'/0'/0'"), path("m/86'/0'/0'")])
+}
+
+fn register_wallet() -> LedgerCommand {
+    LedgerCommand::RegisterWallet(wallet_policy())
+}
+
+fn get_wallet_address() -> LedgerCommand {
+    LedgerCommand::GetWalletAddress {
+        policy: wallet_policy(),
+        hmac: None,
+        change: false,
+        index: 0,
+        display: false,
+    }
+}
+
+fn sign_psbt() -> LedgerCommand {
+    LedgerCommand::SignPsbt {
+        psbt: Box::new(psbt()),
+        policy: wallet_policy(),
+        hmac: None,
+    }
+}
+
+fn musig_sign_psbt() -> LedgerCommand {
+    LedgerCommand::MusigSignPsbt {
+        psbt: Box::new(psbt()),
+        policy: wallet_policy(),
+        hmac: None,
+    }
+}
+
+fn sign_message() -> LedgerCommand {
+    LedgerCommand::SignMessage {
+        path: path("m/84'/0'/0'/0/0"),
+        message: b"Hello, Ledger".to_vec(),
+    }
+}
+
+/// Exchanges of every command, the client commands of the device answered
+/// once: the preimage of the policy or of the message requested, the
+/// signature or the nonce yielded.
+pub const VECTORS: &[Vector] = &[
+    Vector {
+        name: "open_app",
+        command: open_app,
+        exchanges: &[
+            Exchange {
+                request: "e0d8000007426974636f696e",
+                response: "9000",
+            },
+        ],
+    },
+    Vector {
+        name: "quit_app",
+        command: quit_app,
+        exchanges: &[
+            Exchange {
+                request: "b0a7000000",
+                response: "9000",
+            },
+        ],
+    },
+    Vector {
+        name: "get_app_info",
+        command: get_app_info,
+        exchanges: &[
+            Exchange {
+                request: "b001000000",
+                response: "0107426974636f696e05322e322e3401009000",
+            },
+        ],
+    },
+    Vector {
+        name: "list_apps",
+        command: list_apps,
+        exchanges: &[
+            Exchange {
+                request: "e0de000000",
+                response: "014c00000a500101010101010101010101010101010101010101010101010101010101010101020202020202020202020202020202020202020202020202020202020202020207426974636f696e9000",
+            },
+            Exchange {
+                request: "e0df000000",
+                response: "9000",
+            },
+        ],
+    },
+    Vector {
+        name: "ensure_app",
+        command: ensure_app,
+        exchanges: &[
+            Exchange {
+                request: "b001000000",
+                response: "0107426974636f696e05322e322e3401009000",
+            },
+        ],
+    },
+    Vector {
+        name: "get_master_fingerprint",
+        command: get_master_fingerprint,
+        exchanges: &[
+            Exchange {
+                request: "e105000100",
+                response: "4ba436039000",
+            },
+        ],
+    },
+    Vector {
+        name: "get_xpub",
+        command: get_xpub,
+        exchanges: &[
+            Exchange {
+                request: "e10000010e0003800000548000000080000000",
+                response: "78707562364346746679345158734555573543746745376d5a65314c7673313559773763746a64796144527938394a646874794d31774666387559324264794a334a6d41466672486477373768456974316562565878423264797447417671396d6d514a32633833473171385037419000",
+            },
+        ],
+    },
+    Vector {
+        name: "get_xpubs",
+        command: get_xpubs,
+        exchanges: &[
+            Exchange {
+                request: "e10000010e0003800000548000000080000000",
+                response: "78707562364346746679345158734555573543746745376d5a65314c7673313559773763746a64796144527938394a646874794d31774666387559324264794a334a6d41466672486477373768456974316562565878423264797447417671396d6d514a32633833473171385037419000",
+            },
+            Exchange {
+                request: "e10000010e0003800000568000000080000000",
+                response: "7870756236436745326a4367613465644d7a65314679644d564e707657387662765455355a6351587a76754264663173346641433870727142706e37676d336567776e366f517a4a78766834584368436d61334c584535454a366b476f3377476d75784c6434797651675537796b449000",
+            },
+        ],
+    },
+    Vector {
+        name: "register_wallet",
+        command: register_wallet,
+        exchanges: &[
+            Exchange {
+                request: "e1020001454402000bc8974a0d8bdd29024b2ddb7a7fe8df1d9801b270f4e6c1e7e1011ae39e7c9b000153496ad60cc3c09ddd53041b96aa501a7a120aee088effa797d538d8eb152c47",
+                response: "4000c8974a0d8bdd29024b2ddb7a7fe8df1d9801b270f4e6c1e7e1011ae39e7c9b00e000",
+            },
+            Exchange {
+                request: "f80100010d0b0b77706b682840302f2a2a29",
+                response: "df712f3f0be610a0b63297399bc0920ba7f9aa763e6a2c57d23035f35a8807ac03030303030303030303030303030303030303030303030303030303030303039000",
+            },
+        ],
+    },
+    Vector {
+        name: "get_wallet_address",
+        command: get_wallet_address,
+        exchanges: &[
+            Exchange {
+                request: "e10300014600df712f3f0be610a0b63297399bc0920ba7f9aa763e6a2c57d23035f35a8807ac00000000000000000000000000000000000000000000000000000000000000000000000000",
+                response: "6263317165306737713566393270716a79336a6661616e613471797a73357939643276726478363466669000",
+            },
+        ],
+    },
+    Vector {
+        name: "sign_psbt",
+        command: sign_psbt,
+        exchanges: &[
+            Exchange {
+                request: "e1040001c305519b38dae74447b72151f354cb138ca3591a5ff8ac813289b18a004e313216206d0d3e783926a53f1a696f04944e03bc43440cf47684d9a959e98d2add8510f101ecb40cbe47f78042c27903e90879eb49c840d3637a2f16e4e8432f4448b5fc4d0139ad14b59af512e6596b703c83f4c1d02b66c5afba0f5a1fdf8f4708a6647aaddf712f3f0be610a0b63297399bc0920ba7f9aa763e6a2c57d23035f35a8807ac0000000000000000000000000000000000000000000000000000000000000000",
+                response: "4000df712f3f0be610a0b63297399bc0920ba7f9aa763e6a2c57d23035f35a8807ace000",
+            },
+            Exchange {
+                request: "f801000146444402000bc8974a0d8bdd29024b2ddb7a7fe8df1d9801b270f4e6c1e7e1011ae39e7c9b000153496ad60cc3c09ddd53041b96aa501a7a120aee088effa797d538d8eb152c47",
+                response: "100021037c603737cfdaa8d89492fb224ff1c6bf86fbd0225f579cfccb128b903a74403030440220430bc913c69bd2c659d2995c4d78423d25029ba85448eaec276708fe4c8aa2a4022012fd45fc332374da2bf2e40a25f2aba582c3ce4195f5bf47ac6bafad64270cc701e000",
+            },
+            Exchange {
+                request: "f801000100",
+                response: "9000",
+            },
+        ],
+    },
+    Vector {
+        name: "musig_sign_psbt",
+        command: musig_sign_psbt,
+        exchanges: &[
+            Exchange {
+                request: "e1040001c305519b38dae74447b72151f354cb138ca3591a5ff8ac813289b18a004e313216206d0d3e783926a53f1a696f04944e03bc43440cf47684d9a959e98d2add8510f101ecb40cbe47f78042c27903e90879eb49c840d3637a2f16e4e8432f4448b5fc4d0139ad14b59af512e6596b703c83f4c1d02b66c5afba0f5a1fdf8f4708a6647aaddf712f3f0be610a0b63297399bc0920ba7f9aa763e6a2c57d23035f35a8807ac0000000000000000000000000000000000000000000000000000000000000000",
+                response: "10feffffffff00aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa037c603737cfdaa8d89492fb224ff1c6bf86fbd0225f579cfccb128b903a744030037c603737cfdaa8d89492fb224ff1c6bf86fbd0225f579cfccb128b903a744030e000",
+            },
+            Exchange {
+                request: "f801000100",
+                response: "9000",
+            },
+        ],
+    },
+    Vector {
+        name: "sign_message",
+        command: sign_message,
+        exchanges: &[
+            Exchange {
+                request: "e1100001360580000054800000008000000000000000000000000d01b2a84a333c2ea1d79144a965dbb4a8379b753a36a1d38e176bab5a52adecfb",
+                response: "400001b2a84a333c2ea1d79144a965dbb4a8379b753a36a1d38e176bab5a52adecfbe000",
+            },
+            Exchange {
+                request: "f8010001100e0e0048656c6c6f2c204c6564676572",
+                response: "1f31013ef84429ccd4c5588119903998979908e6b902722198fae76f15eb4a224c5d09cfbc57fa7c4c4b1b1426ec600fd5663003013165e59152e459cd745d3eec9000",
+            },
+        ],
+    },
+];
+
+/// Returns the vector of the name.
+pub fn vector(name: &str) -> Option<&'static Vector> {
+    VECTORS.iter().find(|v| v.name == name)
+}
+
+type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+/// Runs the command of the vector with the default interpreter, asserts
+/// that its apdus are the ones of the vector and returns its response.
+pub fn assert_interpreter(vector: &Vector) -> LedgerResponse {
+    let mut interpreter = Ledger::default();
+    let mut apdu = Some(
+        interpreter
+            .start((vector.command)())
+            .expect("command started"),
+    );
+    let mut exchanges = vector.exchanges.iter().enumerate();
+    while let Some(command) = apdu {
+        let (i, exchange) = exchanges
+            .next()
+            .unwrap_or_else(|| panic!("{}: unexpected apdu {:?}", vector.name, command.encode()));
+        assert_eq!(
+            command.encode(),
+            exchange.request(),
+            "{}: apdu {}",
+            vector.name,
+            i
+        );
+        apdu = interpreter
+            .exchange(exchange.response())
+            .unwrap_or_else(|e| panic!("{}: answer {}: {:?}", vector.name, i, e));
+    }
+    assert!(
+        exchanges.next().is_none(),
+        "{}: exchanges left",
+        vector.name
+    );
+    interpreter
+        .end()
+        .unwrap_or_else(|e| panic!("{}: {:?}", vector.name, e))
+}
+
+/// Device answering the apdus of a vector, in order.
+pub struct Replay {
+    vector: &'static Vector,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(vector: &'static Vector) -> Self {
+        Self { vector, next: 0 }
+    }
+
+    /// Returns the answer to the apdu, panics if it is not the next one of
+    /// the vector.
+    pub fn answer(&mut self, request: &[u8]) -> Vec<u8> {
+        let exchange = self
+            .vector
+            .exchanges
+            .get(self.next)
+            .unwrap_or_else(|| panic!("{}: unexpected apdu {:?}", self.vector.name, request));
+        assert_eq!(
+            request,
+            exchange.request(),
+            "{}: apdu {}",
+            self.vector.name,
+            self.next
+        );
+        self.next += 1;
+        exchange.response()
+    }
+
+    /// Panics if an apdu of the vector was not sent.
+    pub fn assert_done(&self) {
+        assert_eq!(
+            self.next,
+            self.vector.exchanges.len(),
+            "{}: exchanges left",
+            self.vector.name
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vectors() {
+        for vector in VECTORS {
+            let response = assert_interpreter(vector);
+            let expected = match vector.name {
+                "open_app" | "quit_app" => matches!(response, LedgerResponse::TaskDone),
+                "get_app_info" | "ensure_app" => matches!(response, LedgerResponse::AppInfo(_)),
+                "list_apps" => {
+                    matches!(response, LedgerResponse::Apps(ref apps) if apps.len() == 1)
+                }
+                "get_master_fingerprint" => {
+                    matches!(response, LedgerResponse::MasterFingerprint(_))
+                }
+                "get_xpub" => matches!(response, LedgerResponse::Xpub(_)),
+                "get_xpubs" => {
+                    matches!(response, LedgerResponse::Xpubs(ref xpubs) if xpubs.len() == 2)
+                }
+                "register_wallet" => matches!(response, LedgerResponse::WalletRegistered { .. }),
+                "get_wallet_address" => matches!(response, LedgerResponse::Address(_)),
+                "sign_psbt" => {
+                    matches!(response, LedgerResponse::Signatures(ref sigs) if sigs.len() == 1)
+                }
+                "musig_sign_psbt" => {
+                    matches!(response, LedgerResponse::MusigNonces(ref nonces) if nonces.len() == 1)
+                }
+                "sign_message" => matches!(response, LedgerResponse::MessageSignature(_)),
+                name => panic!("no expected response for {}", name),
+            };
+            assert!(expected, "{}", vector.name);
+        }
+
+        let vector = vector("get_xpub").unwrap();
+        let mut device = Replay::new(vector);
+        let answer = device.answer(&vector.exchanges[0].request());
+        assert!(answer.ends_with(&[0x90, 0x00]));
+        device.assert_done();
+    }
+}
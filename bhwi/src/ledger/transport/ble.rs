@@ -0,0 +1,174 @@
+//! Bluetooth LE framing of the APDUs exchanged with Ledger devices.
+//!
+//! The commands are written to the write characteristic of the Ledger
+//! service and the answers are notified on its notify characteristic. The
+//! command is prefixed by its length and split in frames of the MTU
+//! negotiated with the device, each starting with a 3 bytes header: the tag
+//! and the sequence index.
+
+use super::framing::FramingError;
+use crate::{devices::DeviceKind, prelude::*};
+
+pub const TAG: u8 = 0x05;
+/// Tag of the MTU negotiation.
+pub const MTU_TAG: u8 = 0x08;
+/// Frame size before the negotiation, the BLE default MTU without the ATT
+/// header.
+pub const DEFAULT_MTU: usize = 20;
+const HEADER_SIZE: usize = 3;
+
+/// UUIDs of the GATT service of a Ledger model and of its characteristics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BleService {
+    pub kind: DeviceKind,
+    pub service: &'static str,
+    /// The answers are notified on it.
+    pub notify: &'static str,
+    /// The frames are written on it with response.
+    pub write: &'static str,
+    /// The frames are written on it without response.
+    pub write_cmd: &'static str,
+}
+
+pub const SERVICES: &[BleService] = &[
+    BleService {
+        kind: DeviceKind::LedgerNanoX,
+        service: "13d63400-2c97-0004-0000-4c6564676572",
+        notify: "13d63400-2c97-0004-0001-4c6564676572",
+        write: "13d63400-2c97-0004-0002-4c6564676572",
+        write_cmd: "13d63400-2c97-0004-0003-4c6564676572",
+    },
+    BleService {
+        kind: DeviceKind::LedgerStax,
+        service: "13d63400-2c97-6004-0000-4c6564676572",
+        notify: "13d63400-2c97-6004-0001-4c6564676572",
+        write: "13d63400-2c97-6004-0002-4c6564676572",
+        write_cmd: "13d63400-2c97-6004-0003-4c6564676572",
+    },
+    BleService {
+        kind: DeviceKind::LedgerFlex,
+        service: "13d63400-2c97-3004-0000-4c6564676572",
+        notify: "13d63400-2c97-3004-0001-4c6564676572",
+        write: "13d63400-2c97-3004-0002-4c6564676572",
+        write_cmd: "13d63400-2c97-3004-0003-4c6564676572",
+    },
+];
+
+/// Returns the Ledger service with the UUID advertised by the device.
+pub fn service(uuid: &str) -> Option<&'static BleService> {
+    SERVICES
+        .iter()
+        .find(|service| service.service.eq_ignore_ascii_case(uuid))
+}
+
+/// Frame to write to negotiate the MTU, before the first command.
+pub fn mtu_request() -> Vec<u8> {
+    vec![MTU_TAG, 0x00, 0x00, 0x00, 0x00]
+}
+
+/// Returns the frame size from the answer of the device to the MTU request.
+pub fn parse_mtu(answer: &[u8]) -> Result<usize, FramingError> {
+    match answer {
+        [MTU_TAG, _, _, _, _, mtu, ..] if *mtu as usize > HEADER_SIZE + 2 => Ok(*mtu as usize),
+        [MTU_TAG, ..] => Err(FramingError::IncompleteHeader),
+        _ => Err(FramingError::InvalidTag),
+    }
+}
+
+/// Returns the frames to write to the device for the apdu, of the size of
+/// the MTU at most.
+pub fn frame(apdu: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+    let mut data = Vec::with_capacity(apdu.len() + 2);
+    data.extend((apdu.len() as u16).to_be_bytes());
+    data.extend_from_slice(apdu);
+    data.chunks(mtu - HEADER_SIZE)
+        .enumerate()
+        .map(|(sequence_idx, chunk)| {
+            let mut frame = Vec::with_capacity(HEADER_SIZE + chunk.len());
+            frame.push(TAG);
+            frame.extend((sequence_idx as u16).to_be_bytes());
+            frame.extend_from_slice(chunk);
+            frame
+        })
+        .collect()
+}
+
+/// Reassembles the answer from the notified frames.
+#[derive(Debug, Default)]
+pub struct Unframer {
+    sequence_idx: u16,
+    expected_len: usize,
+    answer: Vec<u8>,
+}
+
+impl Unframer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next frame, returns the answer once it is complete.
+    pub fn push(&mut self, frame: &[u8]) -> Result<Option<Vec<u8>>, FramingError> {
+        if (self.sequence_idx == 0 && frame.len() < HEADER_SIZE + 2) || frame.len() < HEADER_SIZE {
+            return Err(FramingError::IncompleteHeader);
+        }
+        if frame[0] != TAG {
+            return Err(FramingError::InvalidTag);
+        }
+        if u16::from_be_bytes([frame[1], frame[2]]) != self.sequence_idx {
+            return Err(FramingError::InvalidSequence);
+        }
+
+        let mut chunk = &frame[HEADER_SIZE..];
+        if self.sequence_idx == 0 {
+            self.expected_len = u16::from_be_bytes([chunk[0], chunk[1]]) as usize;
+            self.answer = Vec::with_capacity(self.expected_len);
+            chunk = &chunk[2..];
+        }
+        let missing = self.expected_len - self.answer.len();
+        self.answer
+            .extend_from_slice(&chunk[..core::cmp::min(chunk.len(), missing)]);
+
+        if self.answer.len() >= self.expected_len {
+            self.sequence_idx = 0;
+            Ok(Some(core::mem::take(&mut self.answer)))
+        } else {
+            self.sequence_idx += 1;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ble_framing() {
+        assert_eq!(
+            service("13D63400-2C97-6004-0000-4C6564676572").map(|s| s.kind),
+            Some(DeviceKind::LedgerStax)
+        );
+        assert_eq!(parse_mtu(&[0x08, 0x00, 0x00, 0x00, 0x01, 0x99]), Ok(0x99));
+        assert_eq!(
+            parse_mtu(&[0x08, 0x00, 0x00, 0x00]),
+            Err(FramingError::IncompleteHeader)
+        );
+
+        let apdu: Vec<u8> = (0..100).map(|i| i as u8).collect();
+        let frames = frame(&apdu, DEFAULT_MTU);
+        // 102 bytes in frames of 17 bytes of data.
+        assert_eq!(frames.len(), 6);
+        assert!(frames.iter().all(|frame| frame.len() <= DEFAULT_MTU));
+        assert_eq!(frames[1][..3], [0x05, 0x00, 0x01]);
+        assert_eq!(frames[0][3..5], [0x00, 100]);
+
+        let mut unframer = Unframer::new();
+        for frame in &frames[..5] {
+            assert_eq!(unframer.push(frame), Ok(None));
+        }
+        assert_eq!(unframer.push(&frames[5]), Ok(Some(apdu)));
+        let mut frame = frame(&[0x90, 0x00], 0x99).remove(0);
+        frame[2] = 0x01;
+        assert_eq!(unframer.push(&frame), Err(FramingError::InvalidSequence));
+    }
+}
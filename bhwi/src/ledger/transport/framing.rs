@@ -0,0 +1,133 @@
+//! HID framing of the APDUs exchanged with Ledger devices.
+//!
+//! The command is prefixed by its length and split in reports of 64 bytes,
+//! each starting with a 5 bytes header: the channel, the tag and the
+//! sequence index. The answer is reassembled from the reports read the same way.
+
+use crate::prelude::*;
+
+pub const CHANNEL: u16 = 0x0101;
+pub const TAG: u8 = 0x05;
+pub const PACKET_SIZE: usize = 64;
+const HEADER_SIZE: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingError {
+    IncompleteHeader,
+    InvalidChannel,
+    InvalidTag,
+    InvalidSequence,
+}
+
+/// Returns the reports to write to the device for the apdu.
+pub fn frame(apdu: &[u8]) -> Vec<Vec<u8>> {
+    let mut data = Vec::with_capacity(apdu.len() + 2);
+    data.extend((apdu.len() as u16).to_be_bytes());
+    data.extend_from_slice(apdu);
+    data.chunks(PACKET_SIZE - HEADER_SIZE)
+        .enumerate()
+        .map(|(sequence_idx, chunk)| {
+            let mut report = Vec::with_capacity(PACKET_SIZE);
+            report.extend(CHANNEL.to_be_bytes());
+            report.push(TAG);
+            report.extend((sequence_idx as u16).to_be_bytes());
+            report.extend_from_slice(chunk);
+            report.resize(PACKET_SIZE, 0x00);
+            report
+        })
+        .collect()
+}
+
+/// Reassembles the answer from the reports read from the device.
+#[derive(Debug, Default)]
+pub struct Unframer {
+    sequence_idx: u16,
+    expected_len: usize,
+    answer: Vec<u8>,
+}
+
+impl Unframer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next report, returns the answer once it is complete.
+    pub fn push(&mut self, report: &[u8]) -> Result<Option<Vec<u8>>, FramingError> {
+        if (self.sequence_idx == 0 && report.len() < HEADER_SIZE + 2) || report.len() < HEADER_SIZE
+        {
+            return Err(FramingError::IncompleteHeader);
+        }
+        if u16::from_be_bytes([report[0], report[1]]) != CHANNEL {
+            return Err(FramingError::InvalidChannel);
+        }
+        if report[2] != TAG {
+            return Err(FramingError::InvalidTag);
+        }
+        if u16::from_be_bytes([report[3], report[4]]) != self.sequence_idx {
+            return Err(FramingError::InvalidSequence);
+        }
+
+        let mut chunk = &report[HEADER_SIZE..];
+        if self.sequence_idx == 0 {
+            self.expected_len = u16::from_be_bytes([chunk[0], chunk[1]]) as usize;
+            self.answer = Vec::with_capacity(self.expected_len);
+            chunk = &chunk[2..];
+        }
+        let missing = self.expected_len - self.answer.len();
+        self.answer
+            .extend_from_slice(&chunk[..core::cmp::min(chunk.len(), missing)]);
+
+        if self.answer.len() >= self.expected_len {
+            self.sequence_idx = 0;
+            Ok(Some(core::mem::take(&mut self.answer)))
+        } else {
+            self.sequence_idx += 1;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_and_unframe() {
+        let apdu: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let reports = frame(&apdu);
+        assert_eq!(reports.len(), 4);
+        for (i, report) in reports.iter().enumerate() {
+            assert_eq!(report.len(), PACKET_SIZE);
+            assert_eq!(report[..5], [0x01, 0x01, 0x05, 0x00, i as u8]);
+        }
+        assert_eq!(reports[0][5..7], [0x00, 200]);
+
+        // The answers are framed the same way by the device.
+        let mut unframer = Unframer::new();
+        for report in &reports[..3] {
+            assert_eq!(unframer.push(report), Ok(None));
+        }
+        assert_eq!(unframer.push(&reports[3]), Ok(Some(apdu)));
+        assert_eq!(
+            unframer.push(&frame(&[0x90, 0x00])[0]),
+            Ok(Some(vec![0x90, 0x00]))
+        );
+    }
+
+    #[test]
+    fn test_unframe_invalid_reports() {
+        for (offset, byte, error) in [
+            (0, 0x02, FramingError::InvalidChannel),
+            (2, 0x06, FramingError::InvalidTag),
+            (4, 0x01, FramingError::InvalidSequence),
+        ] {
+            let mut report = frame(&[0x90, 0x00]).remove(0);
+            report[offset] = byte;
+            assert_eq!(Unframer::new().push(&report), Err(error));
+        }
+        assert_eq!(
+            Unframer::new().push(&[0x01, 0x01, 0x05, 0x00, 0x00, 0x00]),
+            Err(FramingError::IncompleteHeader)
+        );
+    }
+}
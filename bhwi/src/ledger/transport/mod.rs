@@ -0,0 +1,2 @@
+pub mod ble;
+pub mod framing;
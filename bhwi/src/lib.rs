@@ -1,9 +1,128 @@
+//! The backends are enabled by the features of their device: `ledger`, the
+//! default one, `coldcard`, `jade`, `trezor`, `specter` and `airgap`.
+//!
+//! Without the default `std` feature, the crate is `no_std` and only needs
+//! `alloc`: the interpreter of the Ledger protocol is kept, the other devices
+//! and the common commands are removed.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 pub use bitcoin;
 
+use prelude::*;
+
+#[cfg(feature = "airgap")]
+pub mod airgap;
+#[cfg(feature = "std")]
+pub mod authorization;
+pub mod bip322;
+#[cfg(feature = "std")]
+pub mod bip85;
+#[cfg(feature = "coldcard")]
 pub mod coldcard;
+#[cfg(feature = "std")]
 pub mod common;
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+pub mod devices;
+#[cfg(feature = "jade")]
 pub mod jade;
+#[cfg(feature = "ledger")]
 pub mod ledger;
+pub mod merkle;
+pub mod merkleized_map;
+#[cfg(feature = "std")]
+pub mod mock;
+pub mod path;
+pub mod psbt;
+#[cfg(feature = "std")]
+pub mod reserves;
+#[cfg(feature = "std")]
+pub mod runner;
+pub mod screening;
+pub mod sequence;
+pub mod silentpayments;
+pub mod slip132;
+#[cfg(feature = "specter")]
+pub mod specter;
+#[cfg(feature = "trezor")]
+pub mod trezor;
+#[cfg(feature = "std")]
+pub mod verify;
+pub mod wallet;
+
+#[cfg(feature = "std")]
+pub use common::{Command, Response};
+
+/// Logs to the `log` facade with the `log` feature, the arguments are only
+/// type checked without it. Key material and payloads are never logged.
+#[cfg(feature = "ledger")]
+macro_rules! log_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        log::$level!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
+#[cfg(feature = "ledger")]
+pub(crate) use log_event;
+
+/// Items of the std prelude missing from the core one.
+pub(crate) mod prelude {
+    #[allow(unused_imports)]
+    pub use alloc::{
+        boxed::Box,
+        format,
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+}
+
+/// Progress of the running command, for the caller to report it while the
+/// device is busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The device signed the input of the index, out of the number of inputs.
+    InputSigned(usize, usize),
+    /// The user is asked to confirm the command on the device.
+    AwaitingUserConfirmation,
+    /// The device is locked, the next transmit probes it until the user
+    /// unlocks it: the delay in milliseconds the caller should wait before
+    /// sending the probe.
+    AwaitingUnlock(u32),
+    /// The last transmit failed with a transient error and the next one
+    /// sends it again: the attempt, and the delay in milliseconds the caller
+    /// should wait before sending it.
+    Retrying(usize, u32),
+    /// A path of the command is unusual, the device may warn the user about
+    /// it, see [`path::PathPolicy`].
+    UnusualPath(path::PathWarning),
+    /// The device switched its app and re-enumerates: the transport must
+    /// wait for it to reappear and reopen it before the next command.
+    Reenumerating,
+}
+
+/// Status of the interpreter between two exchanges, for the caller to tell
+/// the user what the device is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpreterStatus {
+    /// No command is running, or it was cancelled.
+    Idle,
+    /// Chunks of the command are left to send.
+    Transmitting,
+    /// The device processes the command.
+    AwaitingDevice,
+    /// The user is asked to confirm the command or to unlock the device.
+    AwaitingUserAction,
+    /// The command ended, its response is returned by `end`.
+    Done,
+}
 
 pub trait Interpreter {
     type Command;
@@ -13,4 +132,31 @@ pub trait Interpreter {
     fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error>;
     fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error>;
     fn end(self) -> Result<Self::Response, Self::Error>;
+    /// Returns the next event of the running command, polled after `start`
+    /// and after each exchange.
+    fn poll_event(&mut self) -> Option<Event> {
+        None
+    }
+    /// Returns the next frame to send after the transmit returned by `start`
+    /// or `exchange`, for the protocols writing several frames before the
+    /// device replies. Only the reply to the last frame is passed to the
+    /// next exchange.
+    fn poll_transmit(&mut self) -> Option<Self::Transmit> {
+        None
+    }
+    /// Aborts the running command: the next exchanges are ignored and `end`
+    /// fails. The interpreter may be started again with a new command.
+    fn cancel(&mut self) {}
+    /// Notifies the milliseconds elapsed since the start of the running
+    /// command. An interpreter with a deadline fails once it is passed,
+    /// then the next exchanges are ignored and `end` fails as well.
+    fn tick(&mut self, _elapsed_ms: u64) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Returns the deadline of the running command in milliseconds since its
+    /// start, for the caller to stop waiting for a device not answering and
+    /// notify it with `tick`. None without deadline.
+    fn deadline_ms(&self) -> Option<u64> {
+        None
+    }
 }
@@ -0,0 +1,341 @@
+//! Merkle trees of the lists committed to the Ledger Bitcoin app.
+//!
+//! The leaves are the hashes `sha256(0x00 || element)` of the elements, see
+//! [`leaf_hash`], and the nodes `sha256(0x01 || left || right)`. The trees are
+//! created once and read by the client commands of the device:
+//!  - GET_MERKLE_LEAF_PROOF: the proof of the leaf at an index, see
+//!    [`MerkleTree::proof`];
+//!  - GET_MERKLE_LEAF_INDEX: the index of a leaf.
+//!
+//! The left subtree of a node holds the largest power of 2 strictly less than
+//! its number of leaves: the nodes are either the complete subtrees aligned
+//! on their size, or the suffixes of the leaves on the right edge of the
+//! tree. Only the hashes of the nodes are kept, by level, and the root of a
+//! list is computed without keeping its leaves by the [`MerkleBuilder`].
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+pub use crate::merkleized_map::{leaf_hash, LEAF_PREFIX};
+
+use crate::prelude::*;
+
+/// Prefix of the preimages of the nodes.
+pub const NODE_PREFIX: u8 = 0x01;
+
+/// Root hash of the tree without leaves.
+pub const EMPTY_ROOT: [u8; 32] = [0x00; 32];
+
+/// Returns the hash of the node of the children: `sha256(0x01 || left || right)`.
+pub fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[NODE_PREFIX]);
+    engine.input(left);
+    engine.input(right);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Computes the root of the leaves as they are pushed, keeping only the
+/// complete subtrees of the frontier of the tree.
+#[derive(Debug, Default)]
+pub struct MerkleBuilder {
+    /// Roots of the complete subtrees with their height, by decreasing height.
+    frontier: Vec<([u8; 32], u32)>,
+}
+
+impl MerkleBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next leaf hash.
+    pub fn push(&mut self, leaf: [u8; 32]) {
+        let mut node = (leaf, 0);
+        while let Some(&(left, height)) = self.frontier.last() {
+            if height != node.1 {
+                break;
+            }
+            self.frontier.pop();
+            node = (hash_nodes(&left, &node.0), height + 1);
+        }
+        self.frontier.push(node);
+    }
+
+    /// Returns the root hash of the leaves pushed so far.
+    pub fn root(&self) -> [u8; 32] {
+        self.frontier
+            .iter()
+            .rev()
+            .map(|(hash, _)| *hash)
+            .reduce(|right, left| hash_nodes(&left, &right))
+            .unwrap_or(EMPTY_ROOT)
+    }
+}
+
+/// Merkle tree of a list of leaf hashes.
+pub struct MerkleTree {
+    /// Hashes of the complete subtrees aligned on their size, by height: the
+    /// first level holds the leaves.
+    levels: Vec<Vec<[u8; 32]>>,
+    /// Hashes of the suffixes of the leaves starting at each complete subtree
+    /// of the right edge, the first one is the root.
+    suffixes: Vec<[u8; 32]>,
+}
+
+impl MerkleTree {
+    /// Builds the tree of the leaf hashes.
+    pub fn new(leaves: Vec<[u8; 32]>) -> Self {
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = levels[levels.len() - 1]
+                .chunks_exact(2)
+                .map(|pair| hash_nodes(&pair[0], &pair[1]))
+                .collect();
+            levels.push(level);
+        }
+        let mut tree = Self {
+            levels,
+            suffixes: Vec::new(),
+        };
+        let mut suffixes: Vec<[u8; 32]> = Vec::new();
+        for (start, height) in tree.edge().into_iter().rev() {
+            let hash = tree.levels[height][start >> height];
+            suffixes.push(match suffixes.last() {
+                Some(right) => hash_nodes(&hash, right),
+                None => hash,
+            });
+        }
+        suffixes.reverse();
+        tree.suffixes = suffixes;
+        tree
+    }
+
+    /// Builds the tree of the elements of a list, hashed by [`leaf_hash`].
+    pub fn from_elements<T: AsRef<[u8]>>(elements: impl IntoIterator<Item = T>) -> Self {
+        Self::new(
+            elements
+                .into_iter()
+                .map(|e| leaf_hash(e.as_ref()))
+                .collect(),
+        )
+    }
+
+    /// Returns the start and the height of the complete subtrees of the
+    /// right edge, by decreasing height.
+    fn edge(&self) -> Vec<(usize, usize)> {
+        let size = self.size();
+        let mut start = 0;
+        (0..usize::BITS as usize)
+            .rev()
+            .filter(|height| size & (1 << height) != 0)
+            .map(|height| {
+                let subtree = (start, height);
+                start += 1 << height;
+                subtree
+            })
+            .collect()
+    }
+
+    /// Returns the number of leaves.
+    pub fn size(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Returns the root hash of the tree, [`EMPTY_ROOT`] without leaves.
+    pub fn root(&self) -> &[u8; 32] {
+        self.suffixes.first().unwrap_or(&EMPTY_ROOT)
+    }
+
+    /// Returns the leaf hash at index i.
+    pub fn get_leaf(&self, i: usize) -> Option<&[u8; 32]> {
+        self.levels[0].get(i)
+    }
+
+    /// Returns the index of the leaf hash in the tree.
+    pub fn get_leaf_index(&self, val: &[u8]) -> Option<usize> {
+        self.levels[0].iter().position(|v| v == val)
+    }
+
+    /// Returns the proof of the leaf at the index: the hashes of the siblings
+    /// from the leaf to the root, see [`root_from_proof`].
+    pub fn proof(&self, index: usize) -> Option<Vec<[u8; 32]>> {
+        if index >= self.size() {
+            return None;
+        }
+        // The siblings are collected from the root.
+        let mut siblings = Vec::new();
+        for (i, (start, height)) in self.edge().into_iter().enumerate() {
+            if index >= start + (1 << height) {
+                siblings.push(self.levels[height][start >> height]);
+                continue;
+            }
+            if let Some(right) = self.suffixes.get(i + 1) {
+                siblings.push(*right);
+            }
+            for level in (0..height).rev() {
+                siblings.push(self.levels[level][(index >> level) ^ 1]);
+            }
+            break;
+        }
+        siblings.reverse();
+        Some(siblings)
+    }
+}
+
+/// Returns the root of the tree of the size from the leaf at the index and
+/// its proof, None if the proof does not have the length of the path of the
+/// leaf.
+pub fn root_from_proof(
+    leaf: &[u8; 32],
+    index: usize,
+    size: usize,
+    proof: &[[u8; 32]],
+) -> Option<[u8; 32]> {
+    if index >= size {
+        return None;
+    }
+    if size == 1 {
+        return proof.is_empty().then_some(*leaf);
+    }
+    let (sibling, proof) = proof.split_last()?;
+    let split = size.next_power_of_two() / 2;
+    Some(if index < split {
+        hash_nodes(&root_from_proof(leaf, index, split, proof)?, sibling)
+    } else {
+        hash_nodes(
+            sibling,
+            &root_from_proof(leaf, index - split, size - split, proof)?,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+    #[test]
+    fn test_merkle_tree() {
+        let leaves = [
+            [
+                0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+                0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+                0x78, 0x52, 0xb8, 0x55,
+            ],
+            [
+                0xd7, 0xa8, 0xfb, 0xb3, 0x07, 0xd7, 0x80, 0x94, 0x69, 0xca, 0x9a, 0xbc, 0xb0, 0x08,
+                0x2e, 0x4f, 0x8d, 0x56, 0x51, 0xe4, 0x6d, 0x3c, 0xdb, 0x76, 0x2d, 0x02, 0xd0, 0xbf,
+                0x37, 0xc9, 0xe5, 0x92,
+            ],
+            [
+                0xef, 0x53, 0x7f, 0x25, 0xc8, 0x95, 0xbf, 0xa7, 0x82, 0x52, 0x65, 0x29, 0xa9, 0xb6,
+                0x3d, 0x97, 0xaa, 0x63, 0x15, 0x64, 0xd5, 0xd7, 0x89, 0xc2, 0xb7, 0x65, 0x44, 0x8c,
+                0x86, 0x35, 0xfb, 0x6c,
+            ],
+            [
+                0x00, 0x53, 0x7f, 0x25, 0xc8, 0x95, 0xbf, 0xa7, 0x82, 0x52, 0x65, 0x29, 0xa9, 0xb6,
+                0x3d, 0x00, 0xaa, 0x63, 0x15, 0x64, 0xd5, 0xd7, 0x89, 0xc2, 0xb7, 0x65, 0x44, 0x8c,
+                0x86, 0x35, 0xfb, 0x6c,
+            ],
+        ];
+
+        let tree = MerkleTree::new(leaves[0..3].to_vec());
+
+        assert_eq!(tree.proof(0), Some(vec![leaves[1], leaves[2]]));
+
+        assert_eq!(tree.proof(1), Some(vec![leaves[0], leaves[2]]));
+
+        let mut input = vec![0x01];
+        input.extend_from_slice(&leaves[0]);
+        input.extend_from_slice(&leaves[1]);
+
+        let mut engine = sha256::Hash::engine();
+        engine.input(input.as_slice());
+        let value = sha256::Hash::from_engine(engine).to_byte_array();
+        assert_eq!(tree.proof(2), Some(vec![value]));
+
+        let _tree = MerkleTree::new(leaves.to_vec());
+
+        let elements: [&[u8]; 3] = [b"a", b"b", b"c"];
+        let tree = MerkleTree::from_elements(elements);
+        assert_eq!(tree.get_leaf(1), Some(&leaf_hash(b"b")));
+        assert_eq!(
+            tree.root(),
+            &hash_nodes(
+                &hash_nodes(&leaf_hash(b"a"), &leaf_hash(b"b")),
+                &leaf_hash(b"c")
+            )
+        );
+    }
+
+    /// Root of the leaves following the definition of the Ledger Bitcoin app:
+    /// the left subtree holds the largest power of 2 strictly less than the
+    /// number of leaves.
+    fn reference_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+        match leaves.len() {
+            0 => [0x00; 32],
+            1 => leaves[0],
+            n => {
+                let split = n.next_power_of_two() / 2;
+                hash_nodes(
+                    &reference_root(&leaves[..split]),
+                    &reference_root(&leaves[split..]),
+                )
+            }
+        }
+    }
+
+    fn leaves(n: usize, seed: u8) -> Vec<[u8; 32]> {
+        (0..n)
+            .map(|i| {
+                let mut engine = sha256::Hash::engine();
+                engine.input(&[seed]);
+                engine.input(&i.to_le_bytes());
+                sha256::Hash::from_engine(engine).to_byte_array()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_tree_empty() {
+        let tree = MerkleTree::new(Vec::new());
+        assert_eq!(tree.root(), &[0x00; 32]);
+        assert_eq!(tree.proof(0), None);
+        assert_eq!(tree.get_leaf_index(&[0x00; 32]), None);
+        assert_eq!(MerkleBuilder::new().root(), [0x00; 32]);
+    }
+
+    #[test]
+    fn test_merkle_tree_properties() {
+        for n in 1..=130 {
+            for seed in 0..2 {
+                let leaves = leaves(n, seed);
+                let tree = MerkleTree::new(leaves.clone());
+                assert_eq!(tree.size(), n);
+                assert_eq!(tree.root(), &reference_root(&leaves), "size {}", n);
+
+                let mut builder = MerkleBuilder::new();
+                leaves.iter().for_each(|leaf| builder.push(*leaf));
+                assert_eq!(&builder.root(), tree.root());
+
+                for (i, leaf) in leaves.iter().enumerate() {
+                    assert_eq!(tree.get_leaf(i), Some(leaf));
+                    assert_eq!(tree.get_leaf_index(leaf), Some(i));
+                    let proof = tree.proof(i).unwrap();
+                    // The tree is balanced: its depth is ceil(log2(n)).
+                    assert!(proof.len() <= n.next_power_of_two().trailing_zeros() as usize);
+                    assert_eq!(root_from_proof(leaf, i, n, &proof), Some(*tree.root()));
+                    let mut longer = proof.clone();
+                    longer.push(*leaf);
+                    assert_eq!(root_from_proof(leaf, i, n, &longer), None);
+                }
+                assert_eq!(tree.proof(n), None);
+
+                // Changing any leaf changes the root.
+                let i = seed as usize * (n - 1);
+                let mut modified = leaves.clone();
+                modified[i][0] ^= 0x01;
+                assert_ne!(MerkleTree::new(modified).root(), tree.root());
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Commitments to the merkleized lists and maps that the client exposes to the
+//! Ledger bitcoin app through the GET_PREIMAGE / GET_MERKLE_LEAF_* client commands.
+//!
+//! A list is committed by the root of the Merkle tree of its leaf hashes
+//! `sha256(0x00 || element)`. A map is committed by the concatenation of:
+//!     - the number of key/value pairs, as a Bitcoin-style varint;
+//!     - the root of the Merkle tree of the keys, sorted lexicographically;
+//!     - the root of the Merkle tree of the values, ordered by key.
+
+use bitcoin::{
+    consensus::encode::{self, VarInt},
+    hashes::{sha256, Hash, HashEngine},
+};
+
+use crate::{merkle::MerkleBuilder, prelude::*};
+
+/// Prefix of the leaf preimages of a merkleized list.
+pub const LEAF_PREFIX: u8 = 0x00;
+
+/// Returns the preimage of the leaf committing to `element`.
+pub fn leaf_preimage(element: &[u8]) -> Vec<u8> {
+    let mut preimage = Vec::with_capacity(element.len() + 1);
+    preimage.push(LEAF_PREFIX);
+    preimage.extend_from_slice(element);
+    preimage
+}
+
+/// Returns the hash of the leaf committing to `element`: `sha256(0x00 || element)`.
+pub fn leaf_hash(element: &[u8]) -> [u8; 32] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[LEAF_PREFIX]);
+    engine.input(element);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Returns the Merkle root of the list of elements, without keeping their
+/// leaf hashes.
+pub fn list_root<T: AsRef<[u8]>>(elements: impl IntoIterator<Item = T>) -> [u8; 32] {
+    let mut builder = MerkleBuilder::new();
+    for element in elements {
+        builder.push(leaf_hash(element.as_ref()));
+    }
+    builder.root()
+}
+
+/// Sorts the mapping by key, the order in which keys and values are merkleized.
+pub fn sort_mapping<K: AsRef<[u8]>, V>(mapping: &mut [(K, V)]) {
+    mapping.sort_by(|(k1, _), (k2, _)| k1.as_ref().cmp(k2.as_ref()));
+}
+
+/// Commitment to a merkleized map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapCommitment {
+    pub size: usize,
+    pub keys_root: [u8; 32],
+    pub values_root: [u8; 32],
+}
+
+impl MapCommitment {
+    /// Computes the commitment of a mapping of bytes to bytes, in any order.
+    pub fn new<K: AsRef<[u8]>, V: AsRef<[u8]>>(mapping: &[(K, V)]) -> Self {
+        let mut sorted: Vec<&(K, V)> = mapping.iter().collect();
+        sorted.sort_by(|(k1, _), (k2, _)| k1.as_ref().cmp(k2.as_ref()));
+        Self {
+            size: sorted.len(),
+            keys_root: list_root(sorted.iter().map(|(k, _)| k.as_ref())),
+            values_root: list_root(sorted.iter().map(|(_, v)| v.as_ref())),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut res = encode::serialize(&VarInt(self.size as u64));
+        res.extend_from_slice(&self.keys_root);
+        res.extend_from_slice(&self.values_root);
+        res
+    }
+
+    /// Parses a serialized commitment at the start of `bytes`,
+    /// returns it with the number of bytes consumed.
+    pub fn deserialize_partial(bytes: &[u8]) -> Result<(Self, usize), encode::Error> {
+        let (size, read): (VarInt, usize) = encode::deserialize_partial(bytes)?;
+        let roots = bytes
+            .get(read..read + 64)
+            .ok_or(encode::Error::ParseFailed("map commitment is too short"))?;
+        let mut keys_root = [0x00; 32];
+        keys_root.copy_from_slice(&roots[..32]);
+        let mut values_root = [0x00; 32];
+        values_root.copy_from_slice(&roots[32..]);
+        Ok((
+            Self {
+                size: size.0 as usize,
+                keys_root,
+                values_root,
+            },
+            read + 64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leaf_hash() {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&leaf_preimage(b"element"));
+        assert_eq!(
+            leaf_hash(b"element"),
+            sha256::Hash::from_engine(engine).to_byte_array()
+        );
+    }
+
+    #[test]
+    fn test_map_commitment_is_order_independent() {
+        let mapping = vec![
+            (vec![0x03], vec![0x01]),
+            (vec![0x01, 0xaa], vec![0x02]),
+            (vec![0x02], vec![0x03]),
+        ];
+        let mut sorted = mapping.clone();
+        sort_mapping(&mut sorted);
+        assert_eq!(sorted[0].0, vec![0x01, 0xaa]);
+
+        let commitment = MapCommitment::new(&mapping);
+        assert_eq!(commitment, MapCommitment::new(&sorted));
+        assert_eq!(commitment.size, 3);
+        assert_eq!(
+            commitment.keys_root,
+            list_root([&[0x01, 0xaa][..], &[0x02], &[0x03]])
+        );
+        assert_eq!(
+            commitment.values_root,
+            list_root([&[0x02][..], &[0x03], &[0x01]])
+        );
+    }
+
+    #[test]
+    fn test_map_commitment_serialization() {
+        let commitment = MapCommitment::new(&[(b"key", b"value")]);
+        let mut bytes = commitment.serialize();
+        assert_eq!(bytes.len(), 65);
+        bytes.push(0xff);
+        assert_eq!(
+            MapCommitment::deserialize_partial(&bytes).unwrap(),
+            (commitment, 65)
+        );
+        assert!(MapCommitment::deserialize_partial(&bytes[..40]).is_err());
+    }
+}
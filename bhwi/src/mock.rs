@@ -0,0 +1,356 @@
+//! Signer holding its keys in memory, answering the common commands without
+//! device. Intended for the tests of the applications, never for real funds.
+//!
+//! The answer is computed by `start`, the transmit is empty and any reply to
+//! it ends the command.
+
+use bitcoin::{
+    bip32::{self, DerivationPath, Xpriv, Xpub},
+    hashes::{hmac, sha256, Hash, HashEngine},
+    key::CompressedPublicKey,
+    secp256k1::{All, Message, Secp256k1},
+    sign_message::MessageSignature,
+    Address, Network, NetworkKind,
+};
+
+use crate::{
+    bip322, bip85,
+    common::{self, purpose, Command, Recipient, Response, Transmit},
+    silentpayments, Interpreter,
+};
+
+pub struct MockSigner {
+    secp: Secp256k1<All>,
+    master: Xpriv,
+    network: Network,
+    /// Answer of the running command, the response once it is replied.
+    pending: Option<Response>,
+    response: Option<Response>,
+}
+
+impl MockSigner {
+    pub fn new(seed: &[u8], network: Network) -> Result<Self, bip32::Error> {
+        Ok(Self::from_xpriv(Xpriv::new_master(network, seed)?, network))
+    }
+
+    pub fn from_xpriv(master: Xpriv, network: Network) -> Self {
+        Self {
+            secp: Secp256k1::new(),
+            master,
+            network,
+            pending: None,
+            response: None,
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Derives the entropy of the application, see [`bip85::derive`].
+    pub fn bip85_derive(
+        &self,
+        application: bip85::Application,
+        index: u32,
+    ) -> Result<Vec<u8>, common::Error> {
+        bip85::derive(&self.secp, &self.master, application, index)
+            .map_err(|e| common::Error::Serialization(format!("{:?}", e)))
+    }
+
+    fn derive(&self, path: &DerivationPath) -> Result<Xpriv, common::Error> {
+        self.master
+            .derive_priv(&self.secp, path)
+            .map_err(|e| common::Error::Serialization(e.to_string()))
+    }
+
+    /// Returns the response of the command.
+    pub fn run(&self, command: Command) -> Result<Response, common::Error> {
+        match command {
+            Command::Unlock { options } => match options.network {
+                Some(network) if NetworkKind::from(network) != self.master.network => Err(
+                    common::Error::Request("Signer keys are for another network"),
+                ),
+                _ => Ok(Response::TaskDone),
+            },
+            Command::GetMasterFingerprint => Ok(Response::MasterFingerprint(
+                self.master.fingerprint(&self.secp),
+            )),
+            Command::GetXpub { path, .. } => Ok(Response::Xpub(Xpub::from_priv(
+                &self.secp,
+                &self.derive(&path)?,
+            ))),
+            Command::RegisterWallet { policy } => {
+                let id = policy.id();
+                let mut engine =
+                    hmac::HmacEngine::<sha256::Hash>::new(&self.master.private_key.secret_bytes());
+                engine.input(&id);
+                let hmac = hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array();
+                Ok(Response::WalletRegistered { id, hmac })
+            }
+            Command::SignPsbt { mut psbt, .. } => {
+                psbt.sign(&self.master, &self.secp)
+                    .map_err(|(_, errors)| common::Error::Serialization(format!("{:?}", errors)))?;
+                Ok(Response::SignedPsbt(psbt))
+            }
+            Command::SignMessage { path, message } => {
+                let key = self.derive(&path)?;
+                let msg =
+                    Message::from_digest(crate::verify::message_hash(&message).to_byte_array());
+                let signature = self.secp.sign_ecdsa_recoverable(&msg, &key.private_key);
+                Ok(Response::MessageSignature(
+                    MessageSignature::new(signature, true).to_base64(),
+                ))
+            }
+            Command::DisplayAddress { path, policy, .. } => {
+                if policy.is_some() {
+                    return Err(common::Error::UnsupportedCommand("display_address"));
+                }
+                let key =
+                    CompressedPublicKey(self.derive(&path)?.private_key.public_key(&self.secp));
+                let address = match purpose(&path) {
+                    Some(44) => Address::p2pkh(key, self.network),
+                    Some(49) => Address::p2shwpkh(&key, self.network),
+                    Some(84) => Address::p2wpkh(&key, self.network),
+                    Some(86) => Address::p2tr(&self.secp, key.0.into(), None, self.network),
+                    _ => return Err(common::Error::UnsupportedCommand("display_address")),
+                };
+                Ok(Response::Address(address.as_unchecked().clone()))
+            }
+            Command::GetSilentPaymentKeys { account } => Ok(Response::SilentPaymentKeys(
+                silentpayments::derive_keys(&self.secp, &self.master, account)
+                    .map_err(|e| common::Error::Serialization(e.to_string()))?,
+            )),
+            Command::ProveAddressOwnership {
+                message,
+                policy,
+                change,
+                index,
+                format,
+                ..
+            } => {
+                let mut psbt = bip322::build_psbt(&message, &policy, change, index, format)
+                    .map_err(|e| common::Error::Serialization(format!("{:?}", e)))?;
+                psbt.sign(&self.master, &self.secp)
+                    .map_err(|(_, errors)| common::Error::Serialization(format!("{:?}", errors)))?;
+                Ok(Response::OwnershipProof(
+                    bip322::prove(psbt, &policy, format)
+                        .map_err(|e| common::Error::Serialization(format!("{:?}", e)))?,
+                ))
+            }
+            Command::VerifyOwnedAddress {
+                address,
+                policy,
+                gap_limit,
+                ..
+            } => {
+                let (change, index) = crate::psbt::find_address_index(
+                    &policy,
+                    &address.assume_checked_ref().script_pubkey(),
+                    gap_limit,
+                )
+                .ok_or(common::Error::AddressNotOwned)?;
+                Ok(Response::OwnedAddress { change, index })
+            }
+            Command::GetStatus => Ok(Response::Status(common::DeviceStatus {
+                connected: true,
+                unlocked: true,
+                ..Default::default()
+            })),
+        }
+    }
+}
+
+/// The clone holds the keys, without the running command.
+impl Clone for MockSigner {
+    fn clone(&self) -> Self {
+        Self::from_xpriv(self.master, self.network)
+    }
+}
+
+impl Interpreter for MockSigner {
+    type Command = Command;
+    type Transmit = Transmit;
+    type Response = Response;
+    type Error = common::Error;
+
+    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        self.response = None;
+        self.pending = Some(self.run(command)?);
+        Ok(Transmit {
+            recipient: Recipient::Device,
+            payload: Vec::new(),
+            encrypted: false,
+        })
+    }
+    fn exchange(&mut self, _data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        if let Some(response) = self.pending.take() {
+            self.response = Some(response);
+        }
+        Ok(None)
+    }
+    fn end(self) -> Result<Self::Response, Self::Error> {
+        self.response.ok_or(common::Error::NoErrorOrResult)
+    }
+    fn cancel(&mut self) {
+        self.pending = None;
+        self.response = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::sign_message::signed_msg_hash;
+    use bitcoin::{
+        absolute::LockTime, hex::FromHex, transaction::Version, Amount, OutPoint, Psbt, ScriptBuf,
+        Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+    use std::str::FromStr;
+
+    fn signer() -> MockSigner {
+        MockSigner::new(
+            &Vec::<u8>::from_hex("000102030405060708090a0b0c0d0e0f").unwrap(),
+            Network::Testnet,
+        )
+        .unwrap()
+    }
+
+    fn run(command: Command) -> Result<Response, common::Error> {
+        let mut signer = signer();
+        signer.start(command)?;
+        assert!(signer.exchange(Vec::new())?.is_none());
+        signer.end()
+    }
+
+    #[test]
+    fn test_mock_signer_keys() {
+        // Test vector 1 of BIP-32.
+        match run(Command::GetMasterFingerprint).unwrap() {
+            Response::MasterFingerprint(fg) => assert_eq!(fg.to_string(), "3442193e"),
+            _ => panic!("expected a fingerprint"),
+        }
+        match run(Command::GetXpub {
+            path: DerivationPath::from_str("m/0'").unwrap(),
+            display: false,
+        })
+        .unwrap()
+        {
+            Response::Xpub(xpub) => assert_eq!(xpub.parent_fingerprint.to_string(), "3442193e"),
+            _ => panic!("expected an xpub"),
+        }
+        assert!(run(Command::Unlock {
+            options: common::UnlockOptions {
+                network: Some(Network::Bitcoin)
+            }
+        })
+        .is_err());
+
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let address = match run(Command::DisplayAddress {
+            path: path.clone(),
+            policy: None,
+            hmac: None,
+        })
+        .unwrap()
+        {
+            Response::Address(address) => address.require_network(Network::Testnet).unwrap(),
+            _ => panic!("expected an address"),
+        };
+        match run(Command::SignMessage {
+            path,
+            message: b"hello".to_vec(),
+        })
+        .unwrap()
+        {
+            Response::MessageSignature(sig) => {
+                let sig = MessageSignature::from_base64(&sig).unwrap();
+                let pubkey = sig
+                    .recover_pubkey(&Secp256k1::new(), signed_msg_hash("hello"))
+                    .unwrap();
+                assert!(address.is_related_to_pubkey(&pubkey));
+            }
+            _ => panic!("expected a message signature"),
+        }
+    }
+
+    #[test]
+    fn test_mock_signer_sign_psbt() {
+        let signer = signer();
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let xpub = Xpub::from_priv(&signer.secp, &signer.derive(&path).unwrap());
+        let script_pubkey =
+            ScriptBuf::new_p2wpkh(&CompressedPublicKey(xpub.public_key).wpubkey_hash());
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        });
+        psbt.inputs[0].bip32_derivation.insert(
+            xpub.public_key,
+            (signer.master.fingerprint(&signer.secp), path),
+        );
+
+        match run(Command::SignPsbt {
+            psbt: Box::new(psbt),
+            policy: None,
+            hmac: None,
+        })
+        .unwrap()
+        {
+            Response::SignedPsbt(psbt) => assert_eq!(psbt.inputs[0].partial_sigs.len(), 1),
+            _ => panic!("expected a signed psbt"),
+        }
+    }
+
+    #[test]
+    fn test_mock_signer_verify_owned_address() {
+        let signer = signer();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpub = Xpub::from_priv(&signer.secp, &signer.derive(&path).unwrap());
+        let policy = crate::wallet::WalletPolicy::new(
+            String::new(),
+            crate::wallet::Version::V2,
+            "wpkh(@0/**)".to_string(),
+            [((signer.master.fingerprint(&signer.secp), path), xpub)],
+        );
+        let address = |change: u32, index: u32| {
+            let path =
+                DerivationPath::from_str(&format!("m/84'/1'/0'/{}/{}", change, index)).unwrap();
+            let key = Xpub::from_priv(&signer.secp, &signer.derive(&path).unwrap()).to_pub();
+            Address::p2wpkh(&key, Network::Testnet)
+                .as_unchecked()
+                .clone()
+        };
+        let verify = |address, gap_limit| {
+            run(Command::VerifyOwnedAddress {
+                address,
+                policy: policy.clone(),
+                hmac: None,
+                gap_limit,
+            })
+        };
+
+        match verify(address(1, 4), 20).unwrap() {
+            Response::OwnedAddress { change, index } => assert_eq!((change, index), (true, 4)),
+            _ => panic!("expected an owned address"),
+        }
+        assert!(matches!(
+            verify(address(0, 20), 20),
+            Err(common::Error::AddressNotOwned)
+        ));
+    }
+}
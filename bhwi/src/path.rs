@@ -0,0 +1,189 @@
+//! Checks of the derivation paths against the standard schemes, BIP-44, 48,
+//! 49, 84 and 86, before they are sent to the device: the devices warn the
+//! user about the unusual paths, the applications may catch them first.
+
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath},
+    Network,
+};
+
+use crate::prelude::*;
+
+/// Highest account index of the standard paths, as enforced by the devices.
+pub const MAX_ACCOUNT: u32 = 100;
+/// Highest address index of the standard paths, as enforced by the devices.
+pub const MAX_ADDRESS_INDEX: u32 = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Purpose {
+    /// Legacy, p2pkh.
+    Bip44,
+    /// Multisig, p2wsh or p2sh-p2wsh.
+    Bip48,
+    /// Nested segwit, p2sh-p2wpkh.
+    Bip49,
+    /// Native segwit, p2wpkh.
+    Bip84,
+    /// Taproot, p2tr.
+    Bip86,
+}
+
+impl Purpose {
+    pub fn from_index(index: u32) -> Option<Self> {
+        match index {
+            44 => Some(Self::Bip44),
+            48 => Some(Self::Bip48),
+            49 => Some(Self::Bip49),
+            84 => Some(Self::Bip84),
+            86 => Some(Self::Bip86),
+            _ => None,
+        }
+    }
+
+    /// Number of hardened steps up to the account key: the script type
+    /// follows the account in the BIP-48 paths.
+    fn account_depth(&self) -> usize {
+        match self {
+            Self::Bip48 => 4,
+            _ => 3,
+        }
+    }
+}
+
+/// Unusual step of a path, the depth is the index of the step in the path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PathWarning {
+    /// The purpose is not one of the standard ones.
+    UnknownPurpose,
+    /// A step up to the account key is not hardened.
+    Unhardened(usize),
+    /// A step after the account key is hardened.
+    Hardened(usize),
+    /// The coin type is not the one of the network, or of any network.
+    CoinType(u32),
+    /// The account index is over [`MAX_ACCOUNT`].
+    Account(u32),
+    /// The BIP-48 script type is neither p2sh-p2wsh nor p2wsh.
+    ScriptType(u32),
+    /// The path is neither an account path nor an address path.
+    Depth(usize),
+    /// The change index is neither 0 nor 1.
+    Change(u32),
+    /// The address index is over [`MAX_ADDRESS_INDEX`].
+    AddressIndex(u32),
+}
+
+/// Handling of the unusual paths by the interpreters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathPolicy {
+    /// The paths are not checked.
+    #[default]
+    Allow,
+    /// The warnings are reported as events, the command is sent.
+    Warn,
+    /// The first warning fails the command.
+    Reject,
+}
+
+/// Returns the standard scheme of the path if its purpose is known.
+pub fn classify(path: &DerivationPath) -> Option<Purpose> {
+    match path.as_ref().first() {
+        Some(ChildNumber::Hardened { index }) => Purpose::from_index(*index),
+        _ => None,
+    }
+}
+
+/// Returns the unusual steps of the path, the coin type is checked against
+/// the network if given. The master path has no warning.
+pub fn check_path(path: &DerivationPath, network: Option<Network>) -> Vec<PathWarning> {
+    let steps = path.as_ref();
+    if steps.is_empty() {
+        return Vec::new();
+    }
+    let Some(purpose) = classify(path) else {
+        return vec![PathWarning::UnknownPurpose];
+    };
+    let mut warnings = Vec::new();
+    let account_depth = purpose.account_depth();
+    for (depth, step) in steps.iter().enumerate() {
+        match (depth < account_depth, step.is_hardened()) {
+            (true, false) => warnings.push(PathWarning::Unhardened(depth)),
+            (false, true) => warnings.push(PathWarning::Hardened(depth)),
+            _ => {}
+        }
+        let index = match step {
+            ChildNumber::Hardened { index } | ChildNumber::Normal { index } => *index,
+        };
+        match depth {
+            1 => {
+                let expected = network.map(|n| u32::from(n != Network::Bitcoin));
+                if index > 1 || expected.is_some_and(|e| e != index) {
+                    warnings.push(PathWarning::CoinType(index));
+                }
+            }
+            2 if index > MAX_ACCOUNT => warnings.push(PathWarning::Account(index)),
+            3 if purpose == Purpose::Bip48 && !(1..=2).contains(&index) => {
+                warnings.push(PathWarning::ScriptType(index))
+            }
+            d if d == account_depth && index > 1 => warnings.push(PathWarning::Change(index)),
+            d if d == account_depth + 1 && index > MAX_ADDRESS_INDEX => {
+                warnings.push(PathWarning::AddressIndex(index))
+            }
+            _ => {}
+        }
+    }
+    if steps.len() != account_depth && steps.len() != account_depth + 2 {
+        warnings.push(PathWarning::Depth(steps.len()));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    fn check(path: &str, network: Option<Network>) -> Vec<PathWarning> {
+        check_path(&DerivationPath::from_str(path).unwrap(), network)
+    }
+
+    #[test]
+    fn test_check_path() {
+        assert!(check("m", None).is_empty());
+        assert!(check("m/84'/0'/0'", Some(Network::Bitcoin)).is_empty());
+        assert!(check("m/86'/1'/3'/1/42", Some(Network::Testnet)).is_empty());
+        assert!(check("m/48'/1'/0'/2'", Some(Network::Signet)).is_empty());
+        assert_eq!(
+            classify(&DerivationPath::from_str("m/49'/0'/0'").unwrap()),
+            Some(Purpose::Bip49)
+        );
+
+        assert_eq!(check("m/0/1", None), vec![PathWarning::UnknownPurpose]);
+        assert_eq!(
+            check("m/84'/1'/0'", Some(Network::Bitcoin)),
+            vec![PathWarning::CoinType(1)]
+        );
+        assert_eq!(
+            check("m/44'/60'/0/0'/0", None),
+            vec![
+                PathWarning::CoinType(60),
+                PathWarning::Unhardened(2),
+                PathWarning::Hardened(3)
+            ]
+        );
+        assert_eq!(
+            check("m/84'/0'/101'/2/50001", None),
+            vec![
+                PathWarning::Account(101),
+                PathWarning::Change(2),
+                PathWarning::AddressIndex(50001)
+            ]
+        );
+        assert_eq!(
+            check("m/48'/0'/0'/3'/0", None),
+            vec![PathWarning::ScriptType(3), PathWarning::Depth(5)]
+        );
+    }
+}
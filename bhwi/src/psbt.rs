@@ -0,0 +1,2798 @@
+/// code is from github.com/rust-bitcoin/rust-bitcoin
+/// SPDX-License-Identifier: CC0-1.0
+///
+/// Note: Only psbt V2 is supported by the ledger bitcoin app.
+/// rust-bitcoin currently support V0.
+use alloc::collections::BTreeMap;
+
+use base64ct::{Base64, Encoding};
+
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource},
+    blockdata::transaction::{OutPoint, Sequence, Transaction, TxIn, TxOut, Version},
+    consensus::encode::{deserialize, deserialize_partial, serialize, VarInt},
+    ecdsa,
+    hashes::Hash,
+    key::FromSliceError as KeyError,
+    opcodes::all::OP_CHECKMULTISIG,
+    psbt::{raw, Input, Output, Psbt},
+    script::{Builder, PushBytes},
+    secp256k1::{self, Secp256k1, XOnlyPublicKey},
+    taproot,
+    taproot::TapLeafHash,
+    Amount, EcdsaSighashType, PublicKey, Script, ScriptBuf, TapSighashType, Witness,
+};
+
+use serialize::Serialize;
+
+use crate::{prelude::*, wallet::WalletPolicy};
+
+#[rustfmt::skip]
+macro_rules! impl_psbt_get_pair {
+    ($rv:ident.push($slf:ident.$unkeyed_name:ident, $unkeyed_typeval:ident)) => {
+        if let Some(ref $unkeyed_name) = $slf.$unkeyed_name {
+            $rv.push(bitcoin::psbt::raw::Pair {
+                key: bitcoin::psbt::raw::Key {
+                    type_value: $unkeyed_typeval,
+                    key: vec![],
+                },
+                value: Serialize::serialize($unkeyed_name),
+            });
+        }
+    };
+    ($rv:ident.push_map($slf:ident.$keyed_name:ident, $keyed_typeval:ident)) => {
+        for (key, val) in &$slf.$keyed_name {
+            $rv.push(bitcoin::psbt::raw::Pair {
+                key: bitcoin::psbt::raw::Key {
+                    type_value: $keyed_typeval,
+                    key: Serialize::serialize(key),
+                },
+                value: Serialize::serialize(val),
+            });
+        }
+    };
+}
+
+/// V0, Type: Unsigned Transaction PSBT_GLOBAL_UNSIGNED_TX = 0x00
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+/// Type: Extended Public Key PSBT_GLOBAL_XPUB = 0x01
+const PSBT_GLOBAL_XPUB: u8 = 0x01;
+/// V2 field
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+/// V2 field
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+/// V2 field
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+/// V2 field
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+/// V2 field
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+/// Type: Version Number PSBT_GLOBAL_VERSION = 0xFB
+const PSBT_GLOBAL_VERSION: u8 = 0xFB;
+
+pub fn get_v2_global_pairs(psbt: &Psbt) -> Vec<raw::Pair> {
+    let mut rv: Vec<raw::Pair> = Default::default();
+
+    for (xpub, (fingerprint, derivation)) in &psbt.xpub {
+        rv.push(raw::Pair {
+            key: raw::Key {
+                type_value: PSBT_GLOBAL_XPUB,
+                key: xpub.encode().to_vec(),
+            },
+            value: {
+                let mut ret = Vec::with_capacity(4 + derivation.len() * 4);
+                ret.extend(fingerprint.as_bytes());
+                derivation
+                    .into_iter()
+                    .for_each(|n| ret.extend(u32::from(*n).to_le_bytes()));
+                ret
+            },
+        });
+    }
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_GLOBAL_FALLBACK_LOCKTIME,
+            key: vec![],
+        },
+        value: serialize(&psbt.unsigned_tx.lock_time),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_GLOBAL_INPUT_COUNT,
+            key: vec![],
+        },
+        value: serialize(&VarInt(psbt.inputs.len() as u64)),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_GLOBAL_OUTPUT_COUNT,
+            key: vec![],
+        },
+        value: serialize(&VarInt(psbt.outputs.len() as u64)),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_GLOBAL_TX_VERSION,
+            key: vec![],
+        },
+        value: psbt.unsigned_tx.version.0.to_le_bytes().to_vec(),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_GLOBAL_VERSION,
+            key: vec![],
+        },
+        value: 2_u32.to_le_bytes().to_vec(),
+    });
+
+    for (key, value) in psbt.proprietary.iter() {
+        rv.push(raw::Pair {
+            key: key.to_key(),
+            value: value.clone(),
+        });
+    }
+
+    for (key, value) in psbt.unknown.iter() {
+        rv.push(raw::Pair {
+            key: key.clone(),
+            value: value.clone(),
+        });
+    }
+
+    rv
+}
+
+/// Type: Non-Witness UTXO PSBT_IN_NON_WITNESS_UTXO = 0x00
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+/// Type: Witness UTXO PSBT_IN_WITNESS_UTXO = 0x01
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+/// Type: Partial Signature PSBT_IN_PARTIAL_SIG = 0x02
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+/// Type: Sighash Type PSBT_IN_SIGHASH_TYPE = 0x03
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+/// Type: Redeem Script PSBT_IN_REDEEM_SCRIPT = 0x04
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+/// Type: Witness Script PSBT_IN_WITNESS_SCRIPT = 0x05
+const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+/// Type: BIP 32 Derivation Path PSBT_IN_BIP32_DERIVATION = 0x06
+const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+/// Type: Finalized scriptSig PSBT_IN_FINAL_SCRIPTSIG = 0x07
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+/// Type: Finalized scriptWitness PSBT_IN_FINAL_SCRIPTWITNESS = 0x08
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+/// V2
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+/// V2
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+/// V2
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+/// V2
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+/// Type: RIPEMD160 preimage PSBT_IN_RIPEMD160 = 0x0a
+const PSBT_IN_RIPEMD160: u8 = 0x0a;
+/// Type: SHA256 preimage PSBT_IN_SHA256 = 0x0b
+const PSBT_IN_SHA256: u8 = 0x0b;
+/// Type: HASH160 preimage PSBT_IN_HASH160 = 0x0c
+const PSBT_IN_HASH160: u8 = 0x0c;
+/// Type: HASH256 preimage PSBT_IN_HASH256 = 0x0d
+const PSBT_IN_HASH256: u8 = 0x0d;
+/// Type: Schnorr Signature in Key Spend PSBT_IN_TAP_KEY_SIG = 0x13
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+/// Type: Schnorr Signature in Script Spend PSBT_IN_TAP_SCRIPT_SIG = 0x14
+const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+/// Type: Taproot Leaf Script PSBT_IN_TAP_LEAF_SCRIPT = 0x14
+const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+/// Type: Taproot Key BIP 32 Derivation Path PSBT_IN_TAP_BIP32_DERIVATION = 0x16
+const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+/// Type: Taproot Internal Key PSBT_IN_TAP_INTERNAL_KEY = 0x17
+const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+/// Type: Taproot Merkle Root PSBT_IN_TAP_MERKLE_ROOT = 0x18
+const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+
+pub fn get_v2_input_pairs(input: &Input, txin: &TxIn) -> Vec<raw::Pair> {
+    let mut rv: Vec<raw::Pair> = Default::default();
+
+    impl_psbt_get_pair! {
+        rv.push(input.non_witness_utxo, PSBT_IN_NON_WITNESS_UTXO)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.witness_utxo, PSBT_IN_WITNESS_UTXO)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.partial_sigs, PSBT_IN_PARTIAL_SIG)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.sighash_type, PSBT_IN_SIGHASH_TYPE)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.redeem_script, PSBT_IN_REDEEM_SCRIPT)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.witness_script, PSBT_IN_WITNESS_SCRIPT)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.bip32_derivation, PSBT_IN_BIP32_DERIVATION)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.final_script_sig, PSBT_IN_FINAL_SCRIPTSIG)
+    }
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_IN_PREVIOUS_TXID,
+            key: vec![],
+        },
+        value: serialize(&txin.previous_output.txid),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_IN_OUTPUT_INDEX,
+            key: vec![],
+        },
+        value: serialize(&txin.previous_output.vout),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_IN_SEQUENCE,
+            key: vec![],
+        },
+        value: serialize(&txin.sequence),
+    });
+
+    impl_psbt_get_pair! {
+        rv.push(input.final_script_witness, PSBT_IN_FINAL_SCRIPTWITNESS)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.ripemd160_preimages, PSBT_IN_RIPEMD160)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.sha256_preimages, PSBT_IN_SHA256)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.hash160_preimages, PSBT_IN_HASH160)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.hash256_preimages, PSBT_IN_HASH256)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.tap_key_sig, PSBT_IN_TAP_KEY_SIG)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.tap_script_sigs, PSBT_IN_TAP_SCRIPT_SIG)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.tap_scripts, PSBT_IN_TAP_LEAF_SCRIPT)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(input.tap_key_origins, PSBT_IN_TAP_BIP32_DERIVATION)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.tap_internal_key, PSBT_IN_TAP_INTERNAL_KEY)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(input.tap_merkle_root, PSBT_IN_TAP_MERKLE_ROOT)
+    }
+
+    for (key, value) in input.proprietary.iter() {
+        rv.push(raw::Pair {
+            key: key.to_key(),
+            value: value.clone(),
+        });
+    }
+
+    for (key, value) in input.unknown.iter() {
+        rv.push(raw::Pair {
+            key: key.clone(),
+            value: value.clone(),
+        });
+    }
+
+    rv
+}
+
+/// Type: Redeem Script PSBT_OUT_REDEEM_SCRIPT = 0x00
+const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+/// Type: Witness Script PSBT_OUT_WITNESS_SCRIPT = 0x01
+const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+/// Type: BIP 32 Derivation Path PSBT_OUT_BIP32_DERIVATION = 0x02
+const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+/// V2
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+/// V2
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+/// Type: Taproot Internal Key PSBT_OUT_TAP_INTERNAL_KEY = 0x05
+const PSBT_OUT_TAP_INTERNAL_KEY: u8 = 0x05;
+/// Type: Taproot Tree PSBT_OUT_TAP_TREE = 0x06
+const PSBT_OUT_TAP_TREE: u8 = 0x06;
+/// Type: Taproot Key BIP 32 Derivation Path PSBT_OUT_TAP_BIP32_DERIVATION = 0x07
+const PSBT_OUT_TAP_BIP32_DERIVATION: u8 = 0x07;
+
+pub fn get_v2_output_pairs(output: &Output, txout: &TxOut) -> Vec<raw::Pair> {
+    let mut rv: Vec<raw::Pair> = Default::default();
+
+    impl_psbt_get_pair! {
+        rv.push(output.redeem_script, PSBT_OUT_REDEEM_SCRIPT)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(output.witness_script, PSBT_OUT_WITNESS_SCRIPT)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(output.bip32_derivation, PSBT_OUT_BIP32_DERIVATION)
+    }
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_OUT_AMOUNT,
+            key: vec![],
+        },
+        value: txout.value.to_sat().to_le_bytes().to_vec(),
+    });
+
+    rv.push(raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_OUT_SCRIPT,
+            key: vec![],
+        },
+        value: txout.script_pubkey.as_bytes().to_vec(),
+    });
+
+    impl_psbt_get_pair! {
+        rv.push(output.tap_internal_key, PSBT_OUT_TAP_INTERNAL_KEY)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push(output.tap_tree, PSBT_OUT_TAP_TREE)
+    }
+
+    impl_psbt_get_pair! {
+        rv.push_map(output.tap_key_origins, PSBT_OUT_TAP_BIP32_DERIVATION)
+    }
+
+    for (key, value) in output.proprietary.iter() {
+        rv.push(raw::Pair {
+            key: key.to_key(),
+            value: value.clone(),
+        });
+    }
+
+    for (key, value) in output.unknown.iter() {
+        rv.push(raw::Pair {
+            key: key.clone(),
+            value: value.clone(),
+        });
+    }
+
+    rv
+}
+
+/// Returns the key and the value of the pair as committed in a merkleized map,
+/// the key being its type value followed by the key data.
+pub fn deserialize_pair(pair: raw::Pair) -> (Vec<u8>, Vec<u8>) {
+    let mut key = Vec::with_capacity(pair.key.key.len() + 1);
+    key.push(pair.key.type_value);
+    key.extend(pair.key.key);
+    (key, pair.value)
+}
+
+/// Key/value pairs of a PSBT map, as committed to the device.
+pub type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+pub fn get_v2_global_map(psbt: &Psbt) -> Map {
+    get_v2_global_pairs(psbt)
+        .into_iter()
+        .map(deserialize_pair)
+        .collect()
+}
+
+/// Returns the maps of the psbt inputs.
+/// Maps are built lazily one input at a time, so that a caller committing to them
+/// never holds the expanded fields of every input at once.
+pub fn get_v2_input_maps(psbt: &Psbt) -> impl ExactSizeIterator<Item = Map> + '_ {
+    psbt.inputs
+        .iter()
+        .zip(psbt.unsigned_tx.input.iter())
+        .map(|(input, txin)| {
+            get_v2_input_pairs(input, txin)
+                .into_iter()
+                .map(deserialize_pair)
+                .collect()
+        })
+}
+
+/// Map of a psbt committed to the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapRef {
+    Global,
+    Input(usize),
+    Output(usize),
+}
+
+/// Returns the map of the psbt, none if its input or output is missing.
+pub fn get_v2_map(psbt: &Psbt, map: MapRef) -> Option<Map> {
+    let pairs = match map {
+        MapRef::Global => get_v2_global_pairs(psbt),
+        MapRef::Input(i) => get_v2_input_pairs(psbt.inputs.get(i)?, psbt.unsigned_tx.input.get(i)?),
+        MapRef::Output(i) => {
+            get_v2_output_pairs(psbt.outputs.get(i)?, psbt.unsigned_tx.output.get(i)?)
+        }
+    };
+    Some(pairs.into_iter().map(deserialize_pair).collect())
+}
+
+/// Returns the maps of the psbt outputs, built lazily one output at a time.
+pub fn get_v2_output_maps(psbt: &Psbt) -> impl ExactSizeIterator<Item = Map> + '_ {
+    psbt.outputs
+        .iter()
+        .zip(psbt.unsigned_tx.output.iter())
+        .map(|(output, txout)| {
+            get_v2_output_pairs(output, txout)
+                .into_iter()
+                .map(deserialize_pair)
+                .collect()
+        })
+}
+
+const PSBT_MAGIC: &[u8; 5] = b"psbt\xff";
+
+/// Maximum size of a serialized psbt, the one of Bitcoin Core.
+pub const MAX_PSBT_LEN: usize = 100_000_000;
+
+#[derive(Debug)]
+pub enum PsbtError {
+    InvalidMagic,
+    UnexpectedEnd,
+    /// The psbt is longer than [`MAX_PSBT_LEN`].
+    TooLong(usize),
+    /// A length or a count does not fit in memory.
+    LengthOverflow,
+    /// The number of maps does not match the counts of inputs and outputs.
+    MapCount {
+        expected: usize,
+        found: usize,
+    },
+    Base64(String),
+    /// A field required by the PSBT v2 is missing.
+    MissingField(&'static str),
+    /// The value of the field of the type is invalid.
+    InvalidField(u8),
+    Psbt(bitcoin::psbt::Error),
+}
+
+impl From<bitcoin::psbt::Error> for PsbtError {
+    fn from(value: bitcoin::psbt::Error) -> Self {
+        PsbtError::Psbt(value)
+    }
+}
+
+fn write_map(buf: &mut Vec<u8>, pairs: impl IntoIterator<Item = raw::Pair>) {
+    for pair in pairs {
+        buf.extend(pair.key.serialize());
+        buf.extend(serialize(&VarInt(pair.value.len() as u64)));
+        buf.extend(pair.value);
+    }
+    buf.push(0x00);
+}
+
+/// Serializes the psbt as a PSBT v2 (BIP-370).
+pub fn serialize_v2(psbt: &Psbt) -> Vec<u8> {
+    let mut buf = PSBT_MAGIC.to_vec();
+    write_map(&mut buf, get_v2_global_pairs(psbt));
+    for (input, txin) in psbt.inputs.iter().zip(psbt.unsigned_tx.input.iter()) {
+        write_map(&mut buf, get_v2_input_pairs(input, txin));
+    }
+    for (output, txout) in psbt.outputs.iter().zip(psbt.unsigned_tx.output.iter()) {
+        write_map(&mut buf, get_v2_output_pairs(output, txout));
+    }
+    buf
+}
+
+/// Reads the pairs of the maps following the magic, until the end of the
+/// data.
+fn read_maps(mut data: &[u8]) -> Result<Vec<Vec<raw::Pair>>, PsbtError> {
+    if data.len() > MAX_PSBT_LEN {
+        return Err(PsbtError::TooLong(data.len()));
+    }
+    fn read_slice<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], PsbtError> {
+        let (len, read) =
+            deserialize_partial::<VarInt>(data).map_err(|_| PsbtError::UnexpectedEnd)?;
+        let end = usize::try_from(len.0)
+            .ok()
+            .and_then(|len| read.checked_add(len))
+            .ok_or(PsbtError::LengthOverflow)?;
+        if end > data.len() {
+            return Err(PsbtError::UnexpectedEnd);
+        }
+        let slice = &data[read..end];
+        *data = &data[end..];
+        Ok(slice)
+    }
+    let mut maps = Vec::new();
+    let mut map = Vec::new();
+    while !data.is_empty() {
+        let key = read_slice(&mut data)?;
+        match key.split_first() {
+            None => maps.push(core::mem::take(&mut map)),
+            Some((type_value, key)) => map.push(raw::Pair {
+                key: raw::Key {
+                    type_value: *type_value,
+                    key: key.to_vec(),
+                },
+                value: read_slice(&mut data)?.to_vec(),
+            }),
+        }
+    }
+    if !map.is_empty() {
+        return Err(PsbtError::UnexpectedEnd);
+    }
+    Ok(maps)
+}
+
+fn field(map: &[raw::Pair], type_value: u8) -> Option<&[u8]> {
+    map.iter()
+        .find(|pair| pair.key.type_value == type_value && pair.key.key.is_empty())
+        .map(|pair| pair.value.as_slice())
+}
+
+fn decode_field<T: bitcoin::consensus::Decodable>(
+    map: &[raw::Pair],
+    type_value: u8,
+) -> Result<Option<T>, PsbtError> {
+    field(map, type_value)
+        .map(|value| deserialize(value).map_err(|_| PsbtError::InvalidField(type_value)))
+        .transpose()
+}
+
+/// Returns the counts of inputs and outputs of the global map of a v2, checked
+/// against the number of maps.
+fn map_counts(maps: &[Vec<raw::Pair>]) -> Result<(usize, usize), PsbtError> {
+    let global = maps.first().ok_or(PsbtError::UnexpectedEnd)?;
+    let count = |type_value, name| -> Result<usize, PsbtError> {
+        let count =
+            decode_field::<VarInt>(global, type_value)?.ok_or(PsbtError::MissingField(name))?;
+        usize::try_from(count.0).map_err(|_| PsbtError::LengthOverflow)
+    };
+    let input_count = count(PSBT_GLOBAL_INPUT_COUNT, "input count")?;
+    let output_count = count(PSBT_GLOBAL_OUTPUT_COUNT, "output count")?;
+    let expected = input_count
+        .checked_add(output_count)
+        .and_then(|count| count.checked_add(1))
+        .ok_or(PsbtError::LengthOverflow)?;
+    if maps.len() != expected {
+        return Err(PsbtError::MapCount {
+            expected,
+            found: maps.len(),
+        });
+    }
+    Ok((input_count, output_count))
+}
+
+#[cfg(feature = "elements")]
+const PSET_MAGIC: &[u8; 5] = b"pset\xff";
+
+/// Returns the global, the input and the output maps of a serialized PSET,
+/// the PSBT v2 of Elements, as committed to the Liquid app. The fields of the
+/// maps are not interpreted beyond the counts of inputs and outputs.
+#[cfg(feature = "elements")]
+pub fn get_pset_maps(data: &[u8]) -> Result<(Map, Vec<Map>, Vec<Map>), PsbtError> {
+    let maps = read_maps(
+        data.strip_prefix(PSET_MAGIC)
+            .ok_or(PsbtError::InvalidMagic)?,
+    )?;
+    let (input_count, _) = map_counts(&maps)?;
+    let mut maps = maps
+        .into_iter()
+        .map(|map| map.into_iter().map(deserialize_pair).collect::<Map>());
+    let global = maps.next().expect("global map");
+    let inputs = maps.by_ref().take(input_count).collect();
+    Ok((global, inputs, maps.collect()))
+}
+
+/// Returns the lock time of the transaction: the greatest lock time required
+/// by the inputs, by height if all of them support it, else the fallback.
+fn lock_time(global: &[raw::Pair], inputs: &[Vec<raw::Pair>]) -> Result<LockTime, PsbtError> {
+    let mut heights = Vec::new();
+    let mut times = Vec::new();
+    let mut height_supported = true;
+    for input in inputs {
+        let height: Option<u32> = decode_field(input, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)?;
+        let time: Option<u32> = decode_field(input, PSBT_IN_REQUIRED_TIME_LOCKTIME)?;
+        if height.is_none() && time.is_some() {
+            height_supported = false;
+        }
+        heights.extend(height);
+        times.extend(time);
+    }
+    let required = if height_supported {
+        heights.into_iter().max()
+    } else {
+        times.into_iter().max()
+    };
+    match required {
+        Some(lock_time) => Ok(LockTime::from_consensus(lock_time)),
+        None => Ok(decode_field(global, PSBT_GLOBAL_FALLBACK_LOCKTIME)?.unwrap_or(LockTime::ZERO)),
+    }
+}
+
+/// Deserializes a PSBT v0 or v2, the PSBT v2 is normalized in a v0 holding
+/// the unsigned transaction built from its fields.
+pub fn deserialize_psbt(data: &[u8]) -> Result<Psbt, PsbtError> {
+    let maps = read_maps(
+        data.strip_prefix(PSBT_MAGIC)
+            .ok_or(PsbtError::InvalidMagic)?,
+    )?;
+    let global = maps.first().ok_or(PsbtError::UnexpectedEnd)?;
+    if decode_field::<u32>(global, PSBT_GLOBAL_VERSION)?.unwrap_or(0) != 2 {
+        return Ok(Psbt::deserialize(data)?);
+    }
+
+    let (input_count, _) = map_counts(&maps)?;
+    let (inputs, outputs) = maps[1..].split_at(input_count);
+
+    let tx = Transaction {
+        version: Version(
+            decode_field(global, PSBT_GLOBAL_TX_VERSION)?
+                .ok_or(PsbtError::MissingField("tx version"))?,
+        ),
+        lock_time: lock_time(global, inputs)?,
+        input: inputs
+            .iter()
+            .map(|input| {
+                Ok(TxIn {
+                    previous_output: OutPoint {
+                        txid: decode_field(input, PSBT_IN_PREVIOUS_TXID)?
+                            .ok_or(PsbtError::MissingField("previous txid"))?,
+                        vout: decode_field(input, PSBT_IN_OUTPUT_INDEX)?
+                            .ok_or(PsbtError::MissingField("output index"))?,
+                    },
+                    sequence: decode_field(input, PSBT_IN_SEQUENCE)?.unwrap_or(Sequence::MAX),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<_, PsbtError>>()?,
+        output: outputs
+            .iter()
+            .map(|output| {
+                Ok(TxOut {
+                    value: decode_field(output, PSBT_OUT_AMOUNT)?
+                        .ok_or(PsbtError::MissingField("amount"))?,
+                    script_pubkey: ScriptBuf::from(
+                        field(output, PSBT_OUT_SCRIPT)
+                            .ok_or(PsbtError::MissingField("script"))?
+                            .to_vec(),
+                    ),
+                })
+            })
+            .collect::<Result<_, PsbtError>>()?,
+    };
+
+    // The fields of the PSBT v2 are replaced by the unsigned transaction.
+    let without = |map: &[raw::Pair], types: &[u8]| -> Vec<raw::Pair> {
+        map.iter()
+            .filter(|pair| !types.contains(&pair.key.type_value))
+            .map(|pair| raw::Pair {
+                key: pair.key.clone(),
+                value: pair.value.clone(),
+            })
+            .collect()
+    };
+    let mut v0 = PSBT_MAGIC.to_vec();
+    let mut global_pairs = vec![raw::Pair {
+        key: raw::Key {
+            type_value: PSBT_GLOBAL_UNSIGNED_TX,
+            key: vec![],
+        },
+        value: serialize(&tx),
+    }];
+    global_pairs.extend(without(
+        global,
+        &[
+            PSBT_GLOBAL_TX_VERSION,
+            PSBT_GLOBAL_FALLBACK_LOCKTIME,
+            PSBT_GLOBAL_INPUT_COUNT,
+            PSBT_GLOBAL_OUTPUT_COUNT,
+            PSBT_GLOBAL_TX_MODIFIABLE,
+            PSBT_GLOBAL_VERSION,
+        ],
+    ));
+    write_map(&mut v0, global_pairs);
+    for input in inputs {
+        write_map(
+            &mut v0,
+            without(
+                input,
+                &[
+                    PSBT_IN_PREVIOUS_TXID,
+                    PSBT_IN_OUTPUT_INDEX,
+                    PSBT_IN_SEQUENCE,
+                    PSBT_IN_REQUIRED_TIME_LOCKTIME,
+                    PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                ],
+            ),
+        );
+    }
+    for output in outputs {
+        write_map(
+            &mut v0,
+            without(output, &[PSBT_OUT_AMOUNT, PSBT_OUT_SCRIPT]),
+        );
+    }
+    Ok(Psbt::deserialize(&v0)?)
+}
+
+/// Deserializes the base64 encoding of a PSBT v0 or v2.
+pub fn psbt_from_base64(s: &str) -> Result<Psbt, PsbtError> {
+    let s = s.trim();
+    if s.len() / 4 * 3 > MAX_PSBT_LEN {
+        return Err(PsbtError::TooLong(s.len() / 4 * 3));
+    }
+    let data = Base64::decode_vec(s).map_err(|e| PsbtError::Base64(e.to_string()))?;
+    deserialize_psbt(&data)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PartialSignature {
+    /// signature stored in pbst.partial_sigs
+    Sig(PublicKey, ecdsa::Signature),
+    /// signature stored in pbst.tap_script_sigs
+    TapScriptSig(XOnlyPublicKey, Option<TapLeafHash>, taproot::Signature),
+}
+
+impl PartialSignature {
+    /// Adds the signature to the input it was yielded for.
+    pub fn add_to(self, input: &mut Input) {
+        match self {
+            Self::Sig(key, sig) => {
+                input.partial_sigs.insert(key, sig);
+            }
+            Self::TapScriptSig(key, Some(leaf_hash), sig) => {
+                input.tap_script_sigs.insert((key, leaf_hash), sig);
+            }
+            Self::TapScriptSig(_, None, sig) => input.tap_key_sig = Some(sig),
+        }
+    }
+
+    /// Returns the hash of the leaf of a script-path signature.
+    pub fn leaf_hash(&self) -> Option<TapLeafHash> {
+        match self {
+            Self::TapScriptSig(_, leaf_hash, _) => *leaf_hash,
+            Self::Sig(..) => None,
+        }
+    }
+
+    pub fn from_slice(slice: &[u8]) -> Result<Self, PartialSignatureError> {
+        let key_augment_byte = slice
+            .first()
+            .ok_or(PartialSignatureError::BadKeyAugmentLength)?;
+        let key_augment_len = u8::from_le_bytes([*key_augment_byte]) as usize;
+
+        if key_augment_len >= slice.len() {
+            Err(PartialSignatureError::BadKeyAugmentLength)
+        } else if key_augment_len == 64 {
+            let key = XOnlyPublicKey::from_slice(&slice[1..33])
+                .map_err(PartialSignatureError::XOnlyPubKey)?;
+            let tap_leaf_hash =
+                TapLeafHash::from_slice(&slice[33..65]).map_err(PartialSignatureError::TapLeaf)?;
+            let sig = taproot::Signature::from_slice(&slice[key_augment_len + 1..])
+                .map_err(PartialSignatureError::TaprootSig)?;
+            Ok(Self::TapScriptSig(key, Some(tap_leaf_hash), sig))
+        } else if key_augment_len == 32 {
+            let key = XOnlyPublicKey::from_slice(&slice[1..33])
+                .map_err(PartialSignatureError::XOnlyPubKey)?;
+            let sig = taproot::Signature::from_slice(&slice[key_augment_len + 1..])
+                .map_err(PartialSignatureError::TaprootSig)?;
+            Ok(Self::TapScriptSig(key, None, sig))
+        } else {
+            let key = PublicKey::from_slice(&slice[1..key_augment_len + 1])
+                .map_err(PartialSignatureError::PubKey)?;
+            let sig = ecdsa::Signature::from_slice(&slice[key_augment_len + 1..])
+                .map_err(PartialSignatureError::EcdsaSig)?;
+            Ok(Self::Sig(key, sig))
+        }
+    }
+}
+
+/// Type: MuSig2 Public Nonce PSBT_IN_MUSIG2_PUB_NONCE = 0x1b
+const PSBT_IN_MUSIG2_PUB_NONCE: u8 = 0x1b;
+/// Type: MuSig2 Participant Partial Signature PSBT_IN_MUSIG2_PARTIAL_SIG = 0x1c
+const PSBT_IN_MUSIG2_PARTIAL_SIG: u8 = 0x1c;
+
+/// Keys of a MuSig2 value: the participant, the aggregate key and the leaf
+/// hash of a script-path spend.
+fn decode_musig_keys(
+    slice: &[u8],
+) -> Result<(PublicKey, PublicKey, Option<TapLeafHash>), PartialSignatureError> {
+    if slice.len() != 66 && slice.len() != 98 {
+        return Err(PartialSignatureError::BadMusigLength);
+    }
+    let participant = PublicKey::from_slice(&slice[..33]).map_err(PartialSignatureError::PubKey)?;
+    let aggregate = PublicKey::from_slice(&slice[33..66]).map_err(PartialSignatureError::PubKey)?;
+    let leaf_hash = if slice.len() == 98 {
+        Some(TapLeafHash::from_slice(&slice[66..]).map_err(PartialSignatureError::TapLeaf)?)
+    } else {
+        None
+    };
+    Ok((participant, aggregate, leaf_hash))
+}
+
+fn musig_key(
+    type_value: u8,
+    participant: &PublicKey,
+    aggregate: &PublicKey,
+    leaf_hash: Option<&TapLeafHash>,
+) -> raw::Key {
+    let mut key = participant.to_bytes();
+    key.extend(aggregate.to_bytes());
+    if let Some(leaf_hash) = leaf_hash {
+        key.extend(leaf_hash.to_byte_array());
+    }
+    raw::Key { type_value, key }
+}
+
+/// MuSig2 public nonce of a participant, yielded by the first round of
+/// signing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MusigPubNonce {
+    pub participant: PublicKey,
+    pub aggregate: PublicKey,
+    pub leaf_hash: Option<TapLeafHash>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+    pub pubnonce: [u8; 66],
+}
+
+impl MusigPubNonce {
+    /// Adds the nonce to the input, for the second round of signing.
+    pub fn add_to(self, input: &mut Input) {
+        input.unknown.insert(
+            musig_key(
+                PSBT_IN_MUSIG2_PUB_NONCE,
+                &self.participant,
+                &self.aggregate,
+                self.leaf_hash.as_ref(),
+            ),
+            self.pubnonce.to_vec(),
+        );
+    }
+
+    /// Decodes the nonce followed by the keys.
+    pub fn from_slice(slice: &[u8]) -> Result<Self, PartialSignatureError> {
+        if slice.len() < 66 {
+            return Err(PartialSignatureError::BadMusigLength);
+        }
+        let (participant, aggregate, leaf_hash) = decode_musig_keys(&slice[66..])?;
+        let mut pubnonce = [0x00; 66];
+        pubnonce.copy_from_slice(&slice[..66]);
+        Ok(Self {
+            participant,
+            aggregate,
+            leaf_hash,
+            pubnonce,
+        })
+    }
+}
+
+/// MuSig2 partial signature of a participant, yielded by the second round of
+/// signing once the nonces of every participant are in the psbt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MusigPartialSignature {
+    pub participant: PublicKey,
+    pub aggregate: PublicKey,
+    pub leaf_hash: Option<TapLeafHash>,
+    pub signature: [u8; 32],
+}
+
+impl MusigPartialSignature {
+    /// Adds the partial signature to the input, for its aggregation.
+    pub fn add_to(self, input: &mut Input) {
+        input.unknown.insert(
+            musig_key(
+                PSBT_IN_MUSIG2_PARTIAL_SIG,
+                &self.participant,
+                &self.aggregate,
+                self.leaf_hash.as_ref(),
+            ),
+            self.signature.to_vec(),
+        );
+    }
+
+    /// Decodes the partial signature followed by the keys.
+    pub fn from_slice(slice: &[u8]) -> Result<Self, PartialSignatureError> {
+        if slice.len() < 32 {
+            return Err(PartialSignatureError::BadMusigLength);
+        }
+        let (participant, aggregate, leaf_hash) = decode_musig_keys(&slice[32..])?;
+        let mut signature = [0x00; 32];
+        signature.copy_from_slice(&slice[..32]);
+        Ok(Self {
+            participant,
+            aggregate,
+            leaf_hash,
+            signature,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PartialSignatureError {
+    BadKeyAugmentLength,
+    BadMusigLength,
+    XOnlyPubKey(secp256k1::Error),
+    PubKey(KeyError),
+    EcdsaSig(ecdsa::Error),
+    TaprootSig(taproot::SigFromSliceError),
+    TapLeaf(bitcoin::hashes::FromSliceError),
+}
+
+/// Returns true if the input is spent with a taproot signature.
+fn is_taproot_input(input: &Input) -> bool {
+    input.tap_internal_key.is_some()
+        || !input.tap_key_origins.is_empty()
+        || input
+            .witness_utxo
+            .as_ref()
+            .is_some_and(|utxo| utxo.script_pubkey.is_p2tr())
+}
+
+/// Returns the index of the first input of the psbt with a sighash type not
+/// valid for its script: a non standard type, SIGHASH_DEFAULT out of
+/// taproot, or SIGHASH_SINGLE without the output of the same index.
+pub fn invalid_sighash(psbt: &Psbt) -> Option<usize> {
+    let outputs = psbt.unsigned_tx.output.len();
+    psbt.inputs.iter().enumerate().position(|(i, input)| {
+        let Some(sighash_type) = input.sighash_type else {
+            return false;
+        };
+        let single = if is_taproot_input(input) {
+            match sighash_type.taproot_hash_ty() {
+                Ok(ty) => matches!(
+                    ty,
+                    TapSighashType::Single | TapSighashType::SinglePlusAnyoneCanPay
+                ),
+                Err(_) => return true,
+            }
+        } else {
+            match sighash_type.ecdsa_hash_ty() {
+                Ok(ty) => matches!(
+                    ty,
+                    EcdsaSighashType::Single | EcdsaSighashType::SinglePlusAnyoneCanPay
+                ),
+                Err(_) => return true,
+            }
+        };
+        single && i >= outputs
+    })
+}
+
+/// How the device will show an output of the psbt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutputStatus {
+    /// No key of the device is derived for the output.
+    External,
+    /// Change of the policy at the index, the script matches the policy.
+    Change { change: bool, index: u32 },
+    /// The keys of the device match the policy at the index, but the script
+    /// of the policy template cannot be checked here.
+    Unverified { change: bool, index: u32 },
+    /// The output claims keys of the device not matching the policy, the
+    /// device will show it as external.
+    Mismatch,
+}
+
+/// Report of the checks of the psbt against the policy, before signing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PolicyReport {
+    /// Inputs without a derivation of a key of the device, the device does
+    /// not sign them.
+    pub missing_input_derivations: Vec<usize>,
+    pub outputs: Vec<OutputStatus>,
+}
+
+impl PolicyReport {
+    /// Returns true if every input is signed and no output claimed as change
+    /// is shown as external.
+    pub fn is_ok(&self) -> bool {
+        self.missing_input_derivations.is_empty() && !self.outputs.contains(&OutputStatus::Mismatch)
+    }
+}
+
+/// Returns the index of the key and the children of its receive and change
+/// derivations, of a placeholder `@i/**` or `@i/<M;N>/*`.
+fn placeholder(token: &str) -> Option<(usize, u32, u32)> {
+    let (index, derivation) = token.strip_prefix('@')?.split_once('/')?;
+    let (receive, change) = match derivation {
+        "**" => (0, 1),
+        _ => {
+            let (receive, change) = derivation
+                .strip_prefix('<')?
+                .strip_suffix(">/*")?
+                .split_once(';')?;
+            (receive.parse().ok()?, change.parse().ok()?)
+        }
+    };
+    Some((index.parse().ok()?, receive, change))
+}
+
+/// Templates of the single key and multisig policies, with the arguments of
+/// their innermost fragment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Template<'a> {
+    Pkh(&'a str),
+    Wpkh(&'a str),
+    ShWpkh(&'a str),
+    /// Taproot policy without script path.
+    Tr(&'a str),
+    Sh(&'a str),
+    Wsh(&'a str),
+    ShWsh(&'a str),
+}
+
+impl<'a> Template<'a> {
+    fn parse(template: &'a str) -> Option<Self> {
+        if let Some(inner) = template
+            .strip_prefix("sh(wsh(")
+            .and_then(|t| t.strip_suffix("))"))
+        {
+            return Some(Template::ShWsh(inner));
+        }
+        if let Some(inner) = template
+            .strip_prefix("sh(wpkh(")
+            .and_then(|t| t.strip_suffix("))"))
+        {
+            return Some(Template::ShWpkh(inner));
+        }
+        let (wrapper, inner) = template.strip_suffix(')')?.split_once('(')?;
+        match wrapper {
+            "pkh" => Some(Template::Pkh(inner)),
+            "wpkh" => Some(Template::Wpkh(inner)),
+            "tr" if !inner.contains(',') => Some(Template::Tr(inner)),
+            "wsh" => Some(Template::Wsh(inner)),
+            "sh" => Some(Template::Sh(inner)),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the key of the placeholder derived at the index of the policy.
+fn policy_key<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    policy: &WalletPolicy,
+    token: &str,
+    change: bool,
+    index: u32,
+) -> Option<PublicKey> {
+    let (i, receive, change_child) = placeholder(token)?;
+    let child = if change { change_child } else { receive };
+    let path = [
+        ChildNumber::from_normal_idx(child).ok()?,
+        ChildNumber::from_normal_idx(index).ok()?,
+    ];
+    let xpub = policy.keys.get(i)?.inner.derive_pub(secp, &path).ok()?;
+    Some(PublicKey::new(xpub.public_key))
+}
+
+/// Returns the threshold and the keys in script order of a `multi` or
+/// `sortedmulti` fragment derived at the index of the policy.
+fn multisig_keys<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    policy: &WalletPolicy,
+    inner: &str,
+    change: bool,
+    index: u32,
+) -> Option<(usize, Vec<PublicKey>)> {
+    let (sorted, args) = match inner.strip_prefix("sortedmulti(") {
+        Some(args) => (true, args),
+        None => (false, inner.strip_prefix("multi(")?),
+    };
+    let mut args = args.strip_suffix(')')?.split(',');
+    let threshold = args.next()?.parse().ok()?;
+    let mut keys = args
+        .map(|token| policy_key(secp, policy, token, change, index))
+        .collect::<Option<Vec<_>>>()?;
+    if sorted {
+        keys.sort_by_key(|k| k.to_bytes());
+    }
+    Some((threshold, keys))
+}
+
+fn multisig_script(threshold: usize, keys: &[PublicKey]) -> ScriptBuf {
+    let mut builder = Builder::new().push_int(threshold as i64);
+    for k in keys {
+        builder = builder.push_key(k);
+    }
+    builder
+        .push_int(keys.len() as i64)
+        .push_opcode(OP_CHECKMULTISIG)
+        .into_script()
+}
+
+/// Returns the threshold, the multisig script and the origins of its keys in
+/// script order of the multisig policy at the index, for the devices showing
+/// the multisig addresses. None if a key has no origin.
+pub fn multisig_origins(
+    policy: &WalletPolicy,
+    change: bool,
+    index: u32,
+) -> Option<(usize, ScriptBuf, Vec<KeySource>)> {
+    let secp = Secp256k1::verification_only();
+    let inner = match Template::parse(&policy.descriptor_template)? {
+        Template::Sh(inner) | Template::Wsh(inner) | Template::ShWsh(inner) => inner,
+        _ => return None,
+    };
+    let (threshold, keys) = multisig_keys(&secp, policy, inner, change, index)?;
+    let mut origins = BTreeMap::new();
+    for token in inner.strip_suffix(')')?.split(',').skip(1) {
+        let key = policy_key(&secp, policy, token, change, index)?;
+        origins.insert(key, key_origin(policy, token, change, index)?);
+    }
+    let origins = keys
+        .iter()
+        .map(|key| origins.get(key).cloned())
+        .collect::<Option<Vec<_>>>()?;
+    Some((threshold, multisig_script(threshold, &keys), origins))
+}
+
+/// Returns the script of the policy at the index, for the templates of single
+/// key and multisig policies.
+pub(crate) fn policy_script<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    policy: &WalletPolicy,
+    change: bool,
+    index: u32,
+) -> Option<ScriptBuf> {
+    let key = |token: &str| policy_key(secp, policy, token, change, index);
+    let multisig = |inner: &str| -> Option<ScriptBuf> {
+        let (threshold, keys) = multisig_keys(secp, policy, inner, change, index)?;
+        Some(multisig_script(threshold, &keys))
+    };
+    match Template::parse(&policy.descriptor_template)? {
+        Template::ShWsh(inner) => {
+            let wsh = ScriptBuf::new_p2wsh(&multisig(inner)?.wscript_hash());
+            Some(ScriptBuf::new_p2sh(&wsh.script_hash()))
+        }
+        Template::ShWpkh(inner) => {
+            let wpkh = ScriptBuf::new_p2wpkh(&key(inner)?.wpubkey_hash().ok()?);
+            Some(ScriptBuf::new_p2sh(&wpkh.script_hash()))
+        }
+        Template::Pkh(inner) => Some(ScriptBuf::new_p2pkh(&key(inner)?.pubkey_hash())),
+        Template::Wpkh(inner) => Some(ScriptBuf::new_p2wpkh(&key(inner)?.wpubkey_hash().ok()?)),
+        Template::Tr(inner) => Some(ScriptBuf::new_p2tr(
+            secp,
+            XOnlyPublicKey::from(key(inner)?.inner),
+            None,
+        )),
+        Template::Wsh(inner) => Some(ScriptBuf::new_p2wsh(&multisig(inner)?.wscript_hash())),
+        Template::Sh(inner) => Some(ScriptBuf::new_p2sh(&multisig(inner)?.script_hash())),
+    }
+}
+
+/// Returns the change flag and the index of the address of the policy with
+/// the script, searched in the receive and the change addresses below the
+/// gap limit. For the templates of single key and multisig policies.
+pub fn find_address_index(
+    policy: &WalletPolicy,
+    script_pubkey: &Script,
+    gap_limit: u32,
+) -> Option<(bool, u32)> {
+    let secp = Secp256k1::verification_only();
+    (0..gap_limit)
+        .flat_map(|index| [(false, index), (true, index)])
+        .find(|(change, index)| {
+            policy_script(&secp, policy, *change, *index).as_deref() == Some(script_pubkey)
+        })
+}
+
+/// Returns the origin of the key of the placeholder derived at the index of
+/// the policy.
+fn key_origin(policy: &WalletPolicy, token: &str, change: bool, index: u32) -> Option<KeySource> {
+    let (i, receive, change_child) = placeholder(token)?;
+    let (fingerprint, path) = policy.keys.get(i)?.source.clone()?;
+    let child = if change { change_child } else { receive };
+    let path = path
+        .child(ChildNumber::from_normal_idx(child).ok()?)
+        .child(ChildNumber::from_normal_idx(index).ok()?);
+    Some((fingerprint, path))
+}
+
+/// Returns the script of the policy at the index and the psbt input with the
+/// scripts and the key origins needed to sign its spend, for the templates
+/// of single key and multisig policies. The previous output is left to the
+/// caller. None if a key has no origin.
+pub fn policy_input(policy: &WalletPolicy, change: bool, index: u32) -> Option<(ScriptBuf, Input)> {
+    let secp = Secp256k1::verification_only();
+    let script = policy_script(&secp, policy, change, index)?;
+    let derivation = |token: &str| {
+        Some((
+            policy_key(&secp, policy, token, change, index)?,
+            key_origin(policy, token, change, index)?,
+        ))
+    };
+    let mut input = Input::default();
+    match Template::parse(&policy.descriptor_template)? {
+        Template::Pkh(token) | Template::Wpkh(token) => {
+            let (key, origin) = derivation(token)?;
+            input.bip32_derivation.insert(key.inner, origin);
+        }
+        Template::ShWpkh(token) => {
+            let (key, origin) = derivation(token)?;
+            input.redeem_script = Some(ScriptBuf::new_p2wpkh(&key.wpubkey_hash().ok()?));
+            input.bip32_derivation.insert(key.inner, origin);
+        }
+        Template::Tr(token) => {
+            let (key, origin) = derivation(token)?;
+            let internal_key = XOnlyPublicKey::from(key.inner);
+            input.tap_internal_key = Some(internal_key);
+            input
+                .tap_key_origins
+                .insert(internal_key, (Vec::new(), origin));
+        }
+        template @ (Template::Sh(inner) | Template::Wsh(inner) | Template::ShWsh(inner)) => {
+            for token in inner.strip_suffix(')')?.split(',').skip(1) {
+                let (key, origin) = derivation(token)?;
+                input.bip32_derivation.insert(key.inner, origin);
+            }
+            let (threshold, keys) = multisig_keys(&secp, policy, inner, change, index)?;
+            let multisig = multisig_script(threshold, &keys);
+            match template {
+                Template::Sh(_) => input.redeem_script = Some(multisig),
+                Template::ShWsh(_) => {
+                    input.redeem_script = Some(ScriptBuf::new_p2wsh(&multisig.wscript_hash()));
+                    input.witness_script = Some(multisig);
+                }
+                _ => input.witness_script = Some(multisig),
+            }
+        }
+    }
+    Some((script, input))
+}
+
+/// Returns the derivations of the keys, from the bip32 and the taproot
+/// derivations of the map.
+fn derivations<'a>(
+    bip32_derivation: &'a BTreeMap<secp256k1::PublicKey, KeySource>,
+    tap_key_origins: &'a BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+) -> impl Iterator<Item = (XOnlyPublicKey, &'a KeySource)> {
+    bip32_derivation
+        .iter()
+        .map(|(key, source)| (XOnlyPublicKey::from(*key), source))
+        .chain(
+            tap_key_origins
+                .iter()
+                .map(|(key, (_, source))| (*key, source)),
+        )
+}
+
+/// Returns the derivations of the keys of the device, from the bip32 and the
+/// taproot derivations of the map.
+fn own_derivations<'a>(
+    bip32_derivation: &'a BTreeMap<secp256k1::PublicKey, KeySource>,
+    tap_key_origins: &'a BTreeMap<XOnlyPublicKey, (Vec<TapLeafHash>, KeySource)>,
+    fingerprint: Fingerprint,
+) -> impl Iterator<Item = (XOnlyPublicKey, &'a DerivationPath)> {
+    derivations(bip32_derivation, tap_key_origins)
+        .filter(move |(_, (fg, _))| *fg == fingerprint)
+        .map(|(key, (_, path))| (key, path))
+}
+
+/// Returns the change and the index of the policy at which the key of the
+/// device is derived.
+fn policy_derivation<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    policy: &WalletPolicy,
+    key: XOnlyPublicKey,
+    path: &DerivationPath,
+) -> Option<(bool, u32)> {
+    let template = &policy.descriptor_template;
+    template.match_indices('@').find_map(|(start, _)| {
+        let end = template[start..]
+            .find([',', ')', '}'])
+            .map(|end| start + end)
+            .unwrap_or(template.len());
+        let (i, receive, change) = placeholder(&template[start..end])?;
+        let policy_key = policy.keys.get(i)?;
+        let (_, key_path) = policy_key.source.as_ref()?;
+        match path.as_ref().strip_prefix(key_path.as_ref())? {
+            children @ [ChildNumber::Normal { index: child }, ChildNumber::Normal { index }]
+                if *child == receive || *child == change =>
+            {
+                let derived = policy_key.inner.derive_pub(secp, &children.to_vec()).ok()?;
+                (XOnlyPublicKey::from(derived.public_key) == key)
+                    .then_some((*child == change, *index))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Checks the psbt against the policy before signing: the inputs must have a
+/// derivation of a key of the device of the fingerprint, and the outputs
+/// holding one must be change outputs of the policy.
+pub fn validate_against_policy(
+    psbt: &Psbt,
+    policy: &WalletPolicy,
+    fingerprint: Fingerprint,
+) -> PolicyReport {
+    let secp = Secp256k1::verification_only();
+    let missing_input_derivations = psbt
+        .inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, input)| {
+            own_derivations(&input.bip32_derivation, &input.tap_key_origins, fingerprint)
+                .next()
+                .is_none()
+        })
+        .map(|(i, _)| i)
+        .collect();
+    let outputs = psbt
+        .outputs
+        .iter()
+        .zip(psbt.unsigned_tx.output.iter())
+        .map(|(output, txout)| {
+            let mut derivations = own_derivations(
+                &output.bip32_derivation,
+                &output.tap_key_origins,
+                fingerprint,
+            )
+            .peekable();
+            if derivations.peek().is_none() {
+                return OutputStatus::External;
+            }
+            let Some((change, index)) =
+                derivations.find_map(|(key, path)| policy_derivation(&secp, policy, key, path))
+            else {
+                return OutputStatus::Mismatch;
+            };
+            match policy_script(&secp, policy, change, index) {
+                Some(script) if script == txout.script_pubkey => {
+                    OutputStatus::Change { change, index }
+                }
+                Some(_) => OutputStatus::Mismatch,
+                None => OutputStatus::Unverified { change, index },
+            }
+        })
+        .collect();
+    PolicyReport {
+        missing_input_derivations,
+        outputs,
+    }
+}
+
+/// Amounts of the psbt, as shown by the devices.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxSummary {
+    pub total_in: Amount,
+    pub total_out: Amount,
+    pub fee: Amount,
+    /// Outputs without any key origin in the psbt, sent to other wallets.
+    pub recipients: Vec<(ScriptBuf, Amount)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SummaryError {
+    /// The input at the index has neither a witness utxo nor its previous
+    /// transaction, the devices fail to sign it.
+    MissingUtxo(usize),
+    /// The witness utxo of the input at the index is not the output of its
+    /// previous transaction, or the transaction is not the one spent.
+    UtxoMismatch(usize),
+    /// The outputs spend more than the inputs.
+    NegativeFee,
+    /// The amounts of the inputs or of the outputs add up to more than the
+    /// money supply.
+    AmountOverflow,
+    /// The psbt has not one input map per input of its transaction.
+    InputCountMismatch,
+}
+
+/// Returns the utxo spent by the input at the index, checked against the
+/// previous transaction when the psbt has both.
+fn input_utxo(psbt: &Psbt, index: usize) -> Result<&TxOut, SummaryError> {
+    let input = &psbt.inputs[index];
+    let previous = match &input.non_witness_utxo {
+        Some(tx) => {
+            let outpoint = psbt.unsigned_tx.input[index].previous_output;
+            if tx.compute_txid() != outpoint.txid {
+                return Err(SummaryError::UtxoMismatch(index));
+            }
+            Some(
+                tx.output
+                    .get(outpoint.vout as usize)
+                    .ok_or(SummaryError::UtxoMismatch(index))?,
+            )
+        }
+        None => None,
+    };
+    match (&input.witness_utxo, previous) {
+        (Some(utxo), Some(previous)) if utxo != previous => Err(SummaryError::UtxoMismatch(index)),
+        (Some(utxo), _) => Ok(utxo),
+        (None, Some(previous)) => Ok(previous),
+        (None, None) => Err(SummaryError::MissingUtxo(index)),
+    }
+}
+
+/// Adds the amounts, up to the money supply: the sums of hostile psbts must
+/// not overflow.
+fn add_amount(total: Amount, value: Amount) -> Result<Amount, SummaryError> {
+    total
+        .checked_add(value)
+        .filter(|total| *total <= Amount::MAX_MONEY)
+        .ok_or(SummaryError::AmountOverflow)
+}
+
+/// Returns the utxo spent by the input at the index, see [`summary`].
+pub fn spent_utxo(psbt: &Psbt, index: usize) -> Option<&TxOut> {
+    if index >= psbt.inputs.len().min(psbt.unsigned_tx.input.len()) {
+        return None;
+    }
+    input_utxo(psbt, index).ok()
+}
+
+/// Returns the amounts of the psbt, to show the user the numbers the device
+/// will display and to detect the missing utxos before signing.
+pub fn summary(psbt: &Psbt) -> Result<TxSummary, SummaryError> {
+    if psbt.inputs.len() != psbt.unsigned_tx.input.len() {
+        return Err(SummaryError::InputCountMismatch);
+    }
+    let mut total_in = Amount::ZERO;
+    for index in 0..psbt.inputs.len() {
+        total_in = add_amount(total_in, input_utxo(psbt, index)?.value)?;
+    }
+    let total_out = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |total, txout| add_amount(total, txout.value))?;
+    let fee = total_in
+        .checked_sub(total_out)
+        .ok_or(SummaryError::NegativeFee)?;
+    let recipients = psbt
+        .outputs
+        .iter()
+        .zip(&psbt.unsigned_tx.output)
+        .filter(|(output, _)| {
+            output.bip32_derivation.is_empty() && output.tap_key_origins.is_empty()
+        })
+        .map(|(_, txout)| (txout.script_pubkey.clone(), txout.value))
+        .collect();
+    Ok(TxSummary {
+        total_in,
+        total_out,
+        fee,
+        recipients,
+    })
+}
+
+/// Returns the account of a standard single signature psbt (BIP-44, 49, 84
+/// or 86): the keys of all its inputs derive from the same fingerprint and
+/// account path `m/purpose'/coin'/account'`, at `/change/index`.
+pub fn default_account(psbt: &Psbt) -> Option<KeySource> {
+    let mut account: Option<KeySource> = None;
+    for input in &psbt.inputs {
+        let mut sources = derivations(&input.bip32_derivation, &input.tap_key_origins)
+            .map(|(_, source)| source)
+            .peekable();
+        sources.peek()?;
+        for (fingerprint, path) in sources {
+            let path = path.as_ref();
+            let standard = matches!(
+                path.first(),
+                Some(ChildNumber::Hardened {
+                    index: 44 | 49 | 84 | 86
+                })
+            ) && path.len() == 5
+                && path[..3].iter().all(ChildNumber::is_hardened)
+                && path[3..].iter().all(ChildNumber::is_normal);
+            if !standard {
+                return None;
+            }
+            let source = (*fingerprint, DerivationPath::from(&path[..3]));
+            match &account {
+                Some(account) if *account != source => return None,
+                _ => account = Some(source),
+            }
+        }
+    }
+    account
+}
+
+/// Returns the default policy of a standard single signature psbt, whose
+/// account xpub is one of the global xpubs, see [`default_account`]. The
+/// policy does not need to be registered on the device.
+pub fn default_policy(psbt: &Psbt) -> Option<WalletPolicy> {
+    let account = default_account(psbt)?;
+    let (xpub, _) = psbt.xpub.iter().find(|(_, source)| **source == account)?;
+    WalletPolicy::new_singlesig(account, *xpub).ok()
+}
+
+#[derive(Debug)]
+pub enum FinalizeError {
+    /// The policy is not one of the single key and multisig templates, the
+    /// policies with a script path or timelocks are not finalized here.
+    UnsupportedPolicy,
+    /// The input has no previous output.
+    MissingUtxo(usize),
+    /// The previous transaction of the input is not the one spent, or does
+    /// not hold its witness utxo.
+    UtxoMismatch(usize),
+    /// No key of the input is derived from a key of the policy.
+    MissingDerivation(usize),
+    /// The previous output of the input is not a script of the policy.
+    ScriptMismatch(usize),
+    /// The input has not enough signatures to be spent.
+    MissingSignatures(usize),
+    /// The input is not finalized.
+    NotFinalized(usize),
+    /// The psbt has not one input map per input of its transaction.
+    InputCountMismatch,
+    Extract(bitcoin::psbt::ExtractTxError),
+}
+
+/// Finalizes the inputs spending scripts of the policy with the signatures
+/// returned by the devices, as the finalizer of BIP-174. The inputs already
+/// finalized are kept, the psbt is left unchanged on error.
+pub fn finalize(psbt: &mut Psbt, policy: &WalletPolicy) -> Result<(), FinalizeError> {
+    let secp = Secp256k1::verification_only();
+    let template =
+        Template::parse(&policy.descriptor_template).ok_or(FinalizeError::UnsupportedPolicy)?;
+    if psbt.inputs.len() != psbt.unsigned_tx.input.len() {
+        return Err(FinalizeError::InputCountMismatch);
+    }
+    let mut finalized = Vec::new();
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let utxo = input_utxo(psbt, i).map_err(|e| match e {
+            SummaryError::MissingUtxo(i) => FinalizeError::MissingUtxo(i),
+            _ => FinalizeError::UtxoMismatch(i),
+        })?;
+        let (change, index) = derivations(&input.bip32_derivation, &input.tap_key_origins)
+            .find_map(|(key, (_, path))| policy_derivation(&secp, policy, key, path))
+            .ok_or(FinalizeError::MissingDerivation(i))?;
+        if policy_script(&secp, policy, change, index).as_ref() != Some(&utxo.script_pubkey) {
+            return Err(FinalizeError::ScriptMismatch(i));
+        }
+
+        let key = |token| {
+            policy_key(&secp, policy, token, change, index).ok_or(FinalizeError::UnsupportedPolicy)
+        };
+        let signature = |key: &PublicKey| {
+            input
+                .partial_sigs
+                .get(key)
+                .ok_or(FinalizeError::MissingSignatures(i))
+        };
+        let multisig = |inner| -> Result<(ScriptBuf, Vec<ecdsa::Signature>), FinalizeError> {
+            let (threshold, keys) = multisig_keys(&secp, policy, inner, change, index)
+                .ok_or(FinalizeError::UnsupportedPolicy)?;
+            let signatures: Vec<ecdsa::Signature> = keys
+                .iter()
+                .filter_map(|key| input.partial_sigs.get(key).copied())
+                .take(threshold)
+                .collect();
+            if signatures.len() < threshold {
+                return Err(FinalizeError::MissingSignatures(i));
+            }
+            Ok((multisig_script(threshold, &keys), signatures))
+        };
+        // The empty element consumed by the off-by-one of OP_CHECKMULTISIG.
+        let multisig_witness = |script: &ScriptBuf, signatures: &[ecdsa::Signature]| {
+            let mut witness = Witness::new();
+            witness.push([]);
+            for signature in signatures {
+                witness.push(signature.to_vec());
+            }
+            witness.push(script.as_bytes());
+            witness
+        };
+
+        let (script_sig, witness) = match template {
+            Template::Pkh(token) => {
+                let key = key(token)?;
+                let script_sig = Builder::new()
+                    .push_slice(signature(&key)?.serialize())
+                    .push_key(&key)
+                    .into_script();
+                (Some(script_sig), None)
+            }
+            Template::Wpkh(token) => {
+                let key = key(token)?;
+                (None, Some(Witness::p2wpkh(signature(&key)?, &key.inner)))
+            }
+            Template::ShWpkh(token) => {
+                let key = key(token)?;
+                let wpkh = ScriptBuf::new_p2wpkh(
+                    &key.wpubkey_hash()
+                        .map_err(|_| FinalizeError::UnsupportedPolicy)?,
+                );
+                let script_sig = Builder::new()
+                    .push_slice(<&PushBytes>::try_from(wpkh.as_bytes()).expect("22 bytes"))
+                    .into_script();
+                (
+                    Some(script_sig),
+                    Some(Witness::p2wpkh(signature(&key)?, &key.inner)),
+                )
+            }
+            Template::Tr(_) => {
+                let signature = input
+                    .tap_key_sig
+                    .as_ref()
+                    .ok_or(FinalizeError::MissingSignatures(i))?;
+                (None, Some(Witness::p2tr_key_spend(signature)))
+            }
+            Template::Sh(inner) => {
+                let (script, signatures) = multisig(inner)?;
+                let mut builder = Builder::new().push_int(0);
+                for signature in &signatures {
+                    builder = builder.push_slice(signature.serialize());
+                }
+                let script_sig = builder
+                    .push_slice(
+                        <&PushBytes>::try_from(script.as_bytes())
+                            .map_err(|_| FinalizeError::UnsupportedPolicy)?,
+                    )
+                    .into_script();
+                (Some(script_sig), None)
+            }
+            Template::Wsh(inner) => {
+                let (script, signatures) = multisig(inner)?;
+                (None, Some(multisig_witness(&script, &signatures)))
+            }
+            Template::ShWsh(inner) => {
+                let (script, signatures) = multisig(inner)?;
+                let wsh = ScriptBuf::new_p2wsh(&script.wscript_hash());
+                let script_sig = Builder::new()
+                    .push_slice(<&PushBytes>::try_from(wsh.as_bytes()).expect("34 bytes"))
+                    .into_script();
+                (
+                    Some(script_sig),
+                    Some(multisig_witness(&script, &signatures)),
+                )
+            }
+        };
+        finalized.push((i, script_sig, witness));
+    }
+
+    for (i, script_sig, witness) in finalized {
+        let input = &mut psbt.inputs[i];
+        input.final_script_sig = script_sig;
+        input.final_script_witness = witness;
+        // The finalizer of BIP-174 removes the fields used to sign.
+        input.partial_sigs.clear();
+        input.sighash_type = None;
+        input.redeem_script = None;
+        input.witness_script = None;
+        input.bip32_derivation.clear();
+        input.tap_key_sig = None;
+        input.tap_script_sigs.clear();
+        input.tap_scripts.clear();
+        input.tap_key_origins.clear();
+        input.tap_internal_key = None;
+        input.tap_merkle_root = None;
+    }
+    Ok(())
+}
+
+/// Returns the transaction of the finalized psbt, ready to be broadcast.
+/// Fails on the absurd fee rates, as `Psbt::extract_tx`.
+pub fn extract_tx(psbt: Psbt) -> Result<Transaction, FinalizeError> {
+    // `Psbt::extract_tx` leaves the inputs without a map unsigned.
+    if psbt.inputs.len() != psbt.unsigned_tx.input.len() {
+        return Err(FinalizeError::InputCountMismatch);
+    }
+    if let Some(i) = psbt
+        .inputs
+        .iter()
+        .position(|input| input.final_script_sig.is_none() && input.final_script_witness.is_none())
+    {
+        return Err(FinalizeError::NotFinalized(i));
+    }
+    psbt.extract_tx().map_err(FinalizeError::Extract)
+}
+
+mod serialize {
+    use core::convert::{TryFrom, TryInto};
+
+    use crate::prelude::*;
+
+    use bitcoin::{
+        bip32::{ChildNumber, Fingerprint, KeySource},
+        blockdata::{
+            script::ScriptBuf,
+            transaction::{Transaction, TxOut},
+            witness::Witness,
+        },
+        consensus::encode::{self, deserialize_partial, serialize, Decodable, Encodable},
+        ecdsa,
+        hashes::{hash160, ripemd160, sha256, sha256d, Hash},
+        key::PublicKey,
+        psbt::{Error, PsbtSighashType},
+        secp256k1::{self, XOnlyPublicKey},
+        taproot,
+        taproot::{ControlBlock, LeafVersion, TapLeafHash, TapNodeHash, TapTree, TaprootBuilder},
+        VarInt,
+    };
+
+    macro_rules! impl_psbt_de_serialize {
+        ($thing:ty) => {
+            impl_psbt_serialize!($thing);
+            impl_psbt_deserialize!($thing);
+        };
+    }
+
+    macro_rules! impl_psbt_deserialize {
+        ($thing:ty) => {
+            impl Deserialize for $thing {
+                fn deserialize(bytes: &[u8]) -> Result<Self, bitcoin::psbt::Error> {
+                    bitcoin::consensus::deserialize(&bytes[..])
+                        .map_err(|e| bitcoin::psbt::Error::from(e))
+                }
+            }
+        };
+    }
+
+    macro_rules! impl_psbt_serialize {
+        ($thing:ty) => {
+            impl Serialize for $thing {
+                fn serialize(&self) -> Vec<u8> {
+                    bitcoin::consensus::serialize(self)
+                }
+            }
+        };
+    }
+
+    // macros for serde of hashes
+    macro_rules! impl_psbt_hash_de_serialize {
+        ($hash_type:ty) => {
+            impl_psbt_hash_serialize!($hash_type);
+            impl_psbt_hash_deserialize!($hash_type);
+        };
+    }
+
+    macro_rules! impl_psbt_hash_deserialize {
+        ($hash_type:ty) => {
+            impl $crate::psbt::serialize::Deserialize for $hash_type {
+                fn deserialize(bytes: &[u8]) -> Result<Self, bitcoin::psbt::Error> {
+                    <$hash_type>::from_slice(&bytes[..]).map_err(|e| bitcoin::psbt::Error::from(e))
+                }
+            }
+        };
+    }
+
+    macro_rules! impl_psbt_hash_serialize {
+        ($hash_type:ty) => {
+            impl $crate::psbt::serialize::Serialize for $hash_type {
+                fn serialize(&self) -> Vec<u8> {
+                    self.as_byte_array().to_vec()
+                }
+            }
+        };
+    }
+
+    /// A trait for serializing a value as raw data for insertion into PSBT
+    /// key-value maps.
+    pub(crate) trait Serialize {
+        /// Serialize a value as raw data.
+        fn serialize(&self) -> Vec<u8>;
+    }
+
+    /// A trait for deserializing a value from raw data in PSBT key-value maps.
+    #[allow(dead_code)]
+    pub(crate) trait Deserialize: Sized {
+        /// Deserialize a value from raw data.
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error>;
+    }
+
+    impl_psbt_de_serialize!(Transaction);
+    impl_psbt_de_serialize!(TxOut);
+    impl_psbt_de_serialize!(Witness);
+    impl_psbt_hash_de_serialize!(ripemd160::Hash);
+    impl_psbt_hash_de_serialize!(sha256::Hash);
+    impl_psbt_hash_de_serialize!(TapLeafHash);
+    impl_psbt_hash_de_serialize!(TapNodeHash);
+    impl_psbt_hash_de_serialize!(hash160::Hash);
+    impl_psbt_hash_de_serialize!(sha256d::Hash);
+
+    // taproot
+    impl_psbt_de_serialize!(Vec<TapLeafHash>);
+
+    impl Serialize for bitcoin::psbt::raw::Key {
+        fn serialize(&self) -> Vec<u8> {
+            let mut buf = Vec::new();
+            VarInt((self.key.len() + 1) as u64)
+                .consensus_encode(&mut buf)
+                .expect("in-memory writers don't error");
+
+            self.type_value
+                .consensus_encode(&mut buf)
+                .expect("in-memory writers don't error");
+
+            for key in &self.key {
+                key.consensus_encode(&mut buf)
+                    .expect("in-memory writers don't error");
+            }
+
+            buf
+        }
+    }
+
+    impl Serialize for ScriptBuf {
+        fn serialize(&self) -> Vec<u8> {
+            self.to_bytes()
+        }
+    }
+
+    impl Deserialize for ScriptBuf {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            Ok(Self::from(bytes.to_vec()))
+        }
+    }
+
+    impl Serialize for PublicKey {
+        fn serialize(&self) -> Vec<u8> {
+            let mut buf = Vec::new();
+            self.write_into(&mut buf).expect("vecs don't error");
+            buf
+        }
+    }
+
+    impl Deserialize for PublicKey {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            PublicKey::from_slice(bytes).map_err(Error::InvalidPublicKey)
+        }
+    }
+
+    impl Serialize for secp256k1::PublicKey {
+        fn serialize(&self) -> Vec<u8> {
+            self.serialize().to_vec()
+        }
+    }
+
+    impl Deserialize for secp256k1::PublicKey {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            secp256k1::PublicKey::from_slice(bytes).map_err(Error::InvalidSecp256k1PublicKey)
+        }
+    }
+
+    impl Serialize for ecdsa::Signature {
+        fn serialize(&self) -> Vec<u8> {
+            self.to_vec()
+        }
+    }
+
+    impl Deserialize for ecdsa::Signature {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            // NB: Since BIP-174 says "the signature as would be pushed to the stack from
+            // a scriptSig or witness" we should ideally use a consensus deserialization and do
+            // not error on a non-standard values. However,
+            //
+            // 1) the current implementation of from_u32_consensus(`flag`) does not preserve
+            // the sighash byte `flag` mapping all unknown values to EcdsaSighashType::All or
+            // EcdsaSighashType::AllPlusAnyOneCanPay. Therefore, break the invariant
+            // EcdsaSig::from_slice(&sl[..]).to_vec = sl.
+            //
+            // 2) This would cause to have invalid signatures because the sighash message
+            // also has a field sighash_u32 (See BIP141). For example, when signing with non-standard
+            // 0x05, the sighash message would have the last field as 0x05u32 while, the verification
+            // would use check the signature assuming sighash_u32 as `0x01`.
+            ecdsa::Signature::from_slice(bytes).map_err(|e| match e {
+                ecdsa::Error::EmptySignature => Error::InvalidEcdsaSignature(e),
+                ecdsa::Error::SighashType(flag) => Error::NonStandardSighashType(flag.0),
+                ecdsa::Error::Secp256k1(..) => Error::InvalidEcdsaSignature(e),
+                ecdsa::Error::Hex(..) => {
+                    unreachable!("Decoding from slice, not hex")
+                }
+                _ => Error::InvalidEcdsaSignature(e),
+            })
+        }
+    }
+
+    impl Serialize for KeySource {
+        fn serialize(&self) -> Vec<u8> {
+            let mut rv: Vec<u8> = Vec::with_capacity(key_source_len(self));
+
+            rv.append(&mut self.0.to_bytes().to_vec());
+
+            for cnum in self.1.into_iter() {
+                rv.append(&mut serialize(&u32::from(*cnum)))
+            }
+
+            rv
+        }
+    }
+
+    impl Deserialize for KeySource {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            if bytes.len() < 4 {
+                return Err(Error::ConsensusEncoding(
+                    bitcoin::consensus::encode::Error::ParseFailed(
+                        "Not enough bytes for key source",
+                    ),
+                ));
+            }
+
+            let fprint: Fingerprint = bytes[0..4].try_into().expect("4 is the fingerprint length");
+            let mut dpath: Vec<ChildNumber> = Default::default();
+
+            let mut d = &bytes[4..];
+            while !d.is_empty() {
+                match u32::consensus_decode(&mut d) {
+                    Ok(index) => dpath.push(index.into()),
+                    Err(e) => return Err(e)?,
+                }
+            }
+
+            Ok((fprint, dpath.into()))
+        }
+    }
+
+    // partial sigs
+    impl Serialize for Vec<u8> {
+        fn serialize(&self) -> Vec<u8> {
+            self.clone()
+        }
+    }
+
+    impl Deserialize for Vec<u8> {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            Ok(bytes.to_vec())
+        }
+    }
+
+    impl Serialize for PsbtSighashType {
+        fn serialize(&self) -> Vec<u8> {
+            serialize(&self.to_u32())
+        }
+    }
+
+    impl Deserialize for PsbtSighashType {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            let raw: u32 = encode::deserialize(bytes)?;
+            Ok(PsbtSighashType::from_u32(raw))
+        }
+    }
+
+    // Taproot related ser/deser
+    impl Serialize for XOnlyPublicKey {
+        fn serialize(&self) -> Vec<u8> {
+            XOnlyPublicKey::serialize(self).to_vec()
+        }
+    }
+
+    impl Deserialize for XOnlyPublicKey {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            XOnlyPublicKey::from_slice(bytes).map_err(|_| Error::InvalidXOnlyPublicKey)
+        }
+    }
+
+    impl Serialize for taproot::Signature {
+        fn serialize(&self) -> Vec<u8> {
+            self.to_vec()
+        }
+    }
+
+    impl Deserialize for taproot::Signature {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            taproot::Signature::from_slice(bytes).map_err(Error::InvalidTaprootSignature)
+        }
+    }
+
+    impl Serialize for (XOnlyPublicKey, TapLeafHash) {
+        fn serialize(&self) -> Vec<u8> {
+            let ser_pk = self.0.serialize();
+            let mut buf = Vec::with_capacity(ser_pk.len() + self.1.as_byte_array().len());
+            buf.extend(ser_pk);
+            buf.extend(self.1.as_byte_array());
+            buf
+        }
+    }
+
+    impl Deserialize for (XOnlyPublicKey, TapLeafHash) {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            if bytes.len() < 32 {
+                return Err(Error::ConsensusEncoding(
+                    bitcoin::consensus::encode::Error::ParseFailed(
+                        "Not enough bytes for public key and tapleaf hash",
+                    ),
+                ));
+            }
+            let a: XOnlyPublicKey = Deserialize::deserialize(&bytes[..32])?;
+            let b: TapLeafHash = Deserialize::deserialize(&bytes[32..])?;
+            Ok((a, b))
+        }
+    }
+
+    impl Serialize for ControlBlock {
+        fn serialize(&self) -> Vec<u8> {
+            ControlBlock::serialize(self)
+        }
+    }
+
+    impl Deserialize for ControlBlock {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            Self::decode(bytes).map_err(|_| Error::InvalidControlBlock)
+        }
+    }
+
+    // Versioned ScriptBuf
+    impl Serialize for (ScriptBuf, LeafVersion) {
+        fn serialize(&self) -> Vec<u8> {
+            let mut buf = Vec::with_capacity(self.0.len() + 1);
+            buf.extend(self.0.as_bytes());
+            buf.push(self.1.to_consensus());
+            buf
+        }
+    }
+
+    impl Deserialize for (ScriptBuf, LeafVersion) {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            if bytes.is_empty() {
+                return Err(Error::ConsensusEncoding(
+                    bitcoin::consensus::encode::Error::ParseFailed(
+                        "Not enough bytes for script buf and leaf version",
+                    ),
+                ));
+            }
+            // The last byte is LeafVersion.
+            let script = ScriptBuf::deserialize(&bytes[..bytes.len() - 1])?;
+            let leaf_ver = LeafVersion::from_consensus(bytes[bytes.len() - 1])
+                .map_err(|_| Error::InvalidLeafVersion)?;
+            Ok((script, leaf_ver))
+        }
+    }
+
+    impl Serialize for (Vec<TapLeafHash>, KeySource) {
+        fn serialize(&self) -> Vec<u8> {
+            let mut buf = Vec::with_capacity(32 * self.0.len() + key_source_len(&self.1));
+            self.0
+                .consensus_encode(&mut buf)
+                .expect("Vecs don't error allocation");
+            // TODO: Add support for writing into a writer for key-source
+            buf.extend(self.1.serialize());
+            buf
+        }
+    }
+
+    impl Deserialize for (Vec<TapLeafHash>, KeySource) {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            let (leafhash_vec, consumed) = deserialize_partial::<Vec<TapLeafHash>>(bytes)?;
+            let key_source = KeySource::deserialize(&bytes[consumed..])?;
+            Ok((leafhash_vec, key_source))
+        }
+    }
+
+    impl Serialize for TapTree {
+        fn serialize(&self) -> Vec<u8> {
+            let capacity = self
+                .script_leaves()
+                .map(|l| {
+                    l.script().len() + VarInt(l.script().len() as u64).size() // script version
+            + 1 // merkle branch
+            + 1 // leaf version
+                })
+                .sum::<usize>();
+            let mut buf = Vec::with_capacity(capacity);
+            for leaf_info in self.script_leaves() {
+                // # Cast Safety:
+                //
+                // TaprootMerkleBranch can only have len atmost 128(TAPROOT_CONTROL_MAX_NODE_COUNT).
+                // safe to cast from usize to u8
+                buf.push(leaf_info.merkle_branch().len() as u8);
+                buf.push(leaf_info.version().to_consensus());
+                leaf_info
+                    .script()
+                    .consensus_encode(&mut buf)
+                    .expect("Vecs dont err");
+            }
+            buf
+        }
+    }
+
+    impl Deserialize for TapTree {
+        fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+            let mut builder = TaprootBuilder::new();
+            let mut bytes_iter = bytes.iter();
+            while let Some(depth) = bytes_iter.next() {
+                let version = bytes_iter
+                    .next()
+                    .ok_or(Error::Taproot("Invalid Taproot Builder"))?;
+                let (script, consumed) = deserialize_partial::<ScriptBuf>(bytes_iter.as_slice())?;
+                if consumed > 0 {
+                    bytes_iter.nth(consumed - 1);
+                }
+                let leaf_version =
+                    LeafVersion::from_consensus(*version).map_err(|_| Error::InvalidLeafVersion)?;
+                builder = builder
+                    .add_leaf_with_ver(*depth, script, leaf_version)
+                    .map_err(|_| Error::Taproot("Tree not in DFS order"))?;
+            }
+            TapTree::try_from(builder).map_err(Error::TapTree)
+        }
+    }
+
+    // Helper function to compute key source len
+    fn key_source_len(key_source: &KeySource) -> usize {
+        4 + 4 * (key_source.1).as_ref().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        secp256k1::{Keypair, Message, Secp256k1},
+        ScriptBuf, TapSighashType,
+    };
+
+    #[test]
+    fn test_taproot_partial_signatures() {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[0x01; 32]).unwrap();
+        let (key, _) = keypair.x_only_public_key();
+        let signature = taproot::Signature {
+            signature: secp.sign_schnorr_no_aux_rand(&Message::from_digest([0x02; 32]), &keypair),
+            sighash_type: TapSighashType::Default,
+        };
+        let leaf_hash = TapLeafHash::from_script(
+            &ScriptBuf::from(vec![0x51]),
+            taproot::LeafVersion::TapScript,
+        );
+
+        let mut script_path = vec![64];
+        script_path.extend(key.serialize());
+        script_path.extend(leaf_hash.to_byte_array());
+        script_path.extend(signature.to_vec());
+        let sig = PartialSignature::from_slice(&script_path).unwrap();
+        assert_eq!(sig.leaf_hash(), Some(leaf_hash));
+
+        let mut key_path = vec![32];
+        key_path.extend(key.serialize());
+        key_path.extend(signature.to_vec());
+        let key_sig = PartialSignature::from_slice(&key_path).unwrap();
+        assert_eq!(key_sig.leaf_hash(), None);
+
+        let mut input = Input::default();
+        sig.add_to(&mut input);
+        key_sig.add_to(&mut input);
+        assert_eq!(
+            input.tap_script_sigs.get(&(key, leaf_hash)),
+            Some(&signature)
+        );
+        assert_eq!(input.tap_key_sig, Some(signature));
+    }
+
+    #[test]
+    fn test_psbt_v2() {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(700_000),
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: bitcoin::Txid::from_byte_array([0x01; 32]),
+                    vout: 3,
+                },
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::from(vec![0x51]),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: bitcoin::Amount::from_sat(2_000),
+            script_pubkey: ScriptBuf::from(vec![0x52]),
+        });
+
+        let v2 = serialize_v2(&psbt);
+        assert!(Psbt::deserialize(&v2).is_err());
+        assert_eq!(deserialize_psbt(&v2).unwrap(), psbt);
+        assert_eq!(deserialize_psbt(&psbt.serialize()).unwrap(), psbt);
+        assert_eq!(psbt_from_base64(&Base64::encode_string(&v2)).unwrap(), psbt);
+
+        // The lock time required by an input replaces the fallback.
+        psbt.inputs[0].unknown.insert(
+            raw::Key {
+                type_value: PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+                key: vec![],
+            },
+            800_000u32.to_le_bytes().to_vec(),
+        );
+        let res = deserialize_psbt(&serialize_v2(&psbt)).unwrap();
+        assert_eq!(res.unsigned_tx.lock_time, LockTime::from_consensus(800_000));
+        assert!(res.inputs[0].unknown.is_empty());
+
+        assert!(matches!(
+            deserialize_psbt(&v2[..v2.len() - 1]),
+            Err(PsbtError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            deserialize_psbt(b"psbt"),
+            Err(PsbtError::InvalidMagic)
+        ));
+
+        // The hostile lengths and counts fail without panic.
+        let mut extra = v2.clone();
+        extra.push(0x00);
+        assert!(matches!(
+            deserialize_psbt(&extra),
+            Err(PsbtError::MapCount {
+                expected: 3,
+                found: 4
+            })
+        ));
+        let mut long_value = PSBT_MAGIC.to_vec();
+        long_value.extend([0x01, 0x00, 0xff]);
+        long_value.extend(u64::MAX.to_le_bytes());
+        assert!(matches!(
+            deserialize_psbt(&long_value),
+            Err(PsbtError::LengthOverflow)
+        ));
+        let mut counts = PSBT_MAGIC.to_vec();
+        write_map(
+            &mut counts,
+            [
+                (PSBT_GLOBAL_VERSION, serialize(&2u32)),
+                (PSBT_GLOBAL_INPUT_COUNT, serialize(&VarInt(u64::MAX))),
+                (PSBT_GLOBAL_OUTPUT_COUNT, serialize(&VarInt(1))),
+            ]
+            .map(|(type_value, value)| raw::Pair {
+                key: raw::Key {
+                    type_value,
+                    key: vec![],
+                },
+                value,
+            }),
+        );
+        assert!(matches!(
+            deserialize_psbt(&counts),
+            Err(PsbtError::LengthOverflow)
+        ));
+        let mut too_long = PSBT_MAGIC.to_vec();
+        too_long.resize(MAX_PSBT_LEN + 6, 0x00);
+        assert!(matches!(
+            deserialize_psbt(&too_long),
+            Err(PsbtError::TooLong(_))
+        ));
+    }
+
+    #[test]
+    fn test_summary() {
+        let txout = |value, script: &[u8]| TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::from(script.to_vec()),
+        };
+        let previous = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![txout(50_000, &[0x00, 0x01]), txout(70_000, &[0x00, 0x02])],
+        };
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(previous.compute_txid(), 1),
+                ..Default::default()
+            }],
+            output: vec![txout(60_000, &[0x51]), txout(9_000, &[0x52])],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(summary(&psbt), Err(SummaryError::MissingUtxo(0)));
+
+        psbt.inputs[0].non_witness_utxo = Some(previous.clone());
+        // The output with a key origin is the change.
+        let key = Keypair::from_seckey_slice(&Secp256k1::new(), &[0x01; 32])
+            .unwrap()
+            .public_key();
+        psbt.outputs[1]
+            .bip32_derivation
+            .insert(key, Default::default());
+        assert_eq!(
+            summary(&psbt),
+            Ok(TxSummary {
+                total_in: Amount::from_sat(70_000),
+                total_out: Amount::from_sat(69_000),
+                fee: Amount::from_sat(1_000),
+                recipients: vec![(ScriptBuf::from(vec![0x51]), Amount::from_sat(60_000))],
+            })
+        );
+
+        // The witness utxo claims another amount than the spent output.
+        psbt.inputs[0].witness_utxo = Some(previous.output[0].clone());
+        assert_eq!(summary(&psbt), Err(SummaryError::UtxoMismatch(0)));
+        assert!(spent_utxo(&psbt, 0).is_none());
+        assert!(spent_utxo(&psbt, 1).is_none());
+
+        // The previous transaction is not the one spent.
+        psbt.inputs[0].witness_utxo = None;
+        let mut other = previous.clone();
+        other.lock_time = LockTime::from_consensus(1);
+        psbt.inputs[0].non_witness_utxo = Some(other);
+        assert_eq!(summary(&psbt), Err(SummaryError::UtxoMismatch(0)));
+
+        // The spent output is out of the previous transaction.
+        psbt.inputs[0].non_witness_utxo = Some(previous.clone());
+        psbt.unsigned_tx.input[0].previous_output.vout = 2;
+        assert_eq!(summary(&psbt), Err(SummaryError::UtxoMismatch(0)));
+        assert!(spent_utxo(&psbt, 0).is_none());
+    }
+
+    #[test]
+    fn test_summary_hostile_amounts() {
+        let txout = |value| TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::from(vec![0x51]),
+        };
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![txout(1_000)],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(txout(u64::MAX));
+        psbt.inputs[1].witness_utxo = Some(txout(u64::MAX));
+        assert_eq!(summary(&psbt), Err(SummaryError::AmountOverflow));
+
+        // Each amount is valid, their sum is above the money supply.
+        psbt.inputs[0].witness_utxo = Some(txout(Amount::MAX_MONEY.to_sat()));
+        psbt.inputs[1].witness_utxo = Some(txout(1));
+        assert_eq!(summary(&psbt), Err(SummaryError::AmountOverflow));
+
+        psbt.inputs[0].witness_utxo = Some(txout(2_000));
+        psbt.unsigned_tx.output = vec![txout(u64::MAX), txout(u64::MAX)];
+        assert_eq!(summary(&psbt), Err(SummaryError::AmountOverflow));
+
+        psbt.unsigned_tx.output = vec![txout(5_000)];
+        assert_eq!(summary(&psbt), Err(SummaryError::NegativeFee));
+
+        psbt.unsigned_tx.output = vec![txout(1_000)];
+        assert_eq!(summary(&psbt).unwrap().fee, Amount::from_sat(1_001));
+
+        // The input maps do not match the inputs of the transaction.
+        psbt.inputs.pop();
+        assert_eq!(summary(&psbt), Err(SummaryError::InputCountMismatch));
+        psbt.inputs.push(Default::default());
+        psbt.inputs.push(Default::default());
+        assert_eq!(summary(&psbt), Err(SummaryError::InputCountMismatch));
+    }
+
+    #[test]
+    fn test_invalid_sighash() {
+        use bitcoin::{key::XOnlyPublicKey, psbt::PsbtSighashType};
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::from(vec![0x51]),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        assert_eq!(invalid_sighash(&psbt), None);
+        let key = XOnlyPublicKey::from_slice(&[0x02; 32]).unwrap();
+        psbt.inputs[1].tap_internal_key = Some(key);
+        let with_sighash = |psbt: &Psbt, index: usize, sighash_type: u32| {
+            let mut psbt = psbt.clone();
+            psbt.inputs[index].sighash_type = Some(PsbtSighashType::from_u32(sighash_type));
+            invalid_sighash(&psbt)
+        };
+
+        // SIGHASH_ALL | SIGHASH_ANYONECANPAY and SIGHASH_SINGLE with its
+        // output.
+        assert_eq!(with_sighash(&psbt, 0, 0x81), None);
+        assert_eq!(with_sighash(&psbt, 0, 0x03), None);
+        assert_eq!(with_sighash(&psbt, 1, 0x81), None);
+        // Not a sighash type.
+        assert_eq!(with_sighash(&psbt, 0, 0x04), Some(0));
+        assert_eq!(with_sighash(&psbt, 0, 0x100), Some(0));
+        assert_eq!(with_sighash(&psbt, 1, 0x84), Some(1));
+        // SIGHASH_DEFAULT is only valid for taproot.
+        assert_eq!(with_sighash(&psbt, 0, 0x00), Some(0));
+        assert_eq!(with_sighash(&psbt, 1, 0x00), None);
+        // No output is signed by SIGHASH_SINGLE for the second input.
+        assert_eq!(with_sighash(&psbt, 1, 0x03), Some(1));
+        assert_eq!(with_sighash(&psbt, 1, 0x83), Some(1));
+        psbt.inputs[1].tap_internal_key = None;
+        assert_eq!(with_sighash(&psbt, 1, 0x03), Some(1));
+    }
+
+    #[test]
+    fn test_default_policy() {
+        use bitcoin::bip32::{Xpriv, Xpub};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(bitcoin::Network::Testnet, &[0x01; 32]).unwrap();
+        let fingerprint = master.fingerprint(&secp);
+        let derivation = |path: &str| {
+            let path = DerivationPath::from_str(path).unwrap();
+            let key = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap());
+            (key, (fingerprint, path))
+        };
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: Vec::new(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (input, path) in psbt
+            .inputs
+            .iter_mut()
+            .zip(["m/84'/1'/0'/0/1", "m/84'/1'/0'/1/3"])
+        {
+            let (key, source) = derivation(path);
+            input.bip32_derivation.insert(key.public_key, source);
+        }
+        let (account, account_source) = derivation("m/84'/1'/0'");
+        assert_eq!(default_account(&psbt), Some(account_source.clone()));
+        assert!(default_policy(&psbt).is_none());
+
+        psbt.xpub.insert(account, account_source.clone());
+        let policy = default_policy(&psbt).unwrap();
+        assert_eq!(policy.descriptor_template, "wpkh(@0/**)");
+        assert_eq!(policy.keys[0].source, Some(account_source));
+
+        // Inputs of several accounts are not signed with a default policy.
+        let (key, source) = derivation("m/84'/1'/1'/0/1");
+        psbt.inputs[1]
+            .bip32_derivation
+            .insert(key.public_key, source);
+        assert!(default_account(&psbt).is_none());
+    }
+
+    #[test]
+    fn test_validate_against_policy() {
+        use crate::wallet::{self, AddressType};
+        use bitcoin::bip32::{Xpriv, Xpub};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(bitcoin::Network::Testnet, &[0x01; 32]).unwrap();
+        let fingerprint = master.fingerprint(&secp);
+        let account_path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let account = Xpub::from_priv(&secp, &master.derive_priv(&secp, &account_path).unwrap());
+        let policy =
+            WalletPolicy::new_singlesig((fingerprint, account_path.clone()), account).unwrap();
+        let derivation = |path: &str| {
+            let path = DerivationPath::from_str(path).unwrap();
+            let key = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap());
+            (key.public_key, (fingerprint, path))
+        };
+
+        let (change_key, change_source) = derivation("m/84'/1'/0'/1/5");
+        let txout = |script_pubkey| TxOut {
+            value: bitcoin::Amount::from_sat(1_000),
+            script_pubkey,
+        };
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![
+                txout(ScriptBuf::new_p2wpkh(
+                    &PublicKey::new(change_key).wpubkey_hash().unwrap(),
+                )),
+                txout(ScriptBuf::from(vec![0x51])),
+                txout(ScriptBuf::from(vec![0x52])),
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let (key, source) = derivation("m/84'/1'/0'/0/1");
+        psbt.inputs[0].bip32_derivation.insert(key, source);
+        psbt.outputs[0]
+            .bip32_derivation
+            .insert(change_key, change_source.clone());
+        psbt.outputs[2]
+            .bip32_derivation
+            .insert(change_key, change_source);
+
+        let report = validate_against_policy(&psbt, &policy, fingerprint);
+        assert_eq!(report.missing_input_derivations, vec![1]);
+        assert_eq!(
+            report.outputs,
+            vec![
+                OutputStatus::Change {
+                    change: true,
+                    index: 5
+                },
+                OutputStatus::External,
+                OutputStatus::Mismatch,
+            ]
+        );
+        assert!(!report.is_ok());
+
+        // A key of the device out of the policy account.
+        let (key, source) = derivation("m/84'/1'/1'/1/5");
+        psbt.outputs[1].bip32_derivation.insert(key, source);
+        let report = validate_against_policy(&psbt, &policy, fingerprint);
+        assert_eq!(report.outputs[1], OutputStatus::Mismatch);
+
+        let multisig = WalletPolicy::new_multisig(
+            String::new(),
+            wallet::Version::V2,
+            AddressType::NativeSegwit,
+            1,
+            [((fingerprint, account_path.clone()), account)],
+            true,
+        )
+        .unwrap();
+        let script = policy_script(&secp, &multisig, true, 5).unwrap();
+        assert!(script.is_p2wsh());
+        let report = validate_against_policy(&psbt, &multisig, fingerprint);
+        assert_eq!(report.outputs[0], OutputStatus::Mismatch);
+
+        let taptree = WalletPolicy::new(
+            String::new(),
+            wallet::Version::V2,
+            "tr(@0/**,pk(@0/<2;3>/*))".to_string(),
+            [((fingerprint, account_path), account)],
+        );
+        assert!(policy_script(&secp, &taptree, true, 5).is_none());
+    }
+
+    #[test]
+    fn test_finalize() {
+        use crate::wallet::{self, AddressType};
+        use bitcoin::bip32::{Xpriv, Xpub};
+        use std::str::FromStr;
+
+        let secp = Secp256k1::new();
+        let account_path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let signers: Vec<(Xpriv, Xpub)> = [[0x01; 32], [0x02; 32]]
+            .iter()
+            .map(|seed| {
+                let master = Xpriv::new_master(bitcoin::Network::Testnet, seed).unwrap();
+                let account = master.derive_priv(&secp, &account_path).unwrap();
+                (master, Xpub::from_priv(&secp, &account))
+            })
+            .collect();
+        let policy = WalletPolicy::new_multisig(
+            String::new(),
+            wallet::Version::V2,
+            AddressType::NativeSegwit,
+            2,
+            signers.iter().map(|(master, account)| {
+                ((master.fingerprint(&secp), account_path.clone()), *account)
+            }),
+            true,
+        )
+        .unwrap();
+
+        let (_, keys) =
+            multisig_keys(&secp, &policy, "sortedmulti(2,@0/**,@1/**)", false, 3).unwrap();
+        let witness_script = multisig_script(2, &keys);
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bitcoin::Amount::from_sat(9_000),
+                script_pubkey: ScriptBuf::from(vec![0x51]),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: bitcoin::Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_p2wsh(&witness_script.wscript_hash()),
+        });
+        psbt.inputs[0].witness_script = Some(witness_script.clone());
+        for (master, _) in &signers {
+            let path = account_path.extend([
+                ChildNumber::from_normal_idx(0).unwrap(),
+                ChildNumber::from_normal_idx(3).unwrap(),
+            ]);
+            let key = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap());
+            psbt.inputs[0]
+                .bip32_derivation
+                .insert(key.public_key, (master.fingerprint(&secp), path));
+        }
+
+        let mut partially_signed = psbt.clone();
+        partially_signed.sign(&signers[0].0, &secp).unwrap();
+        assert!(matches!(
+            finalize(&mut partially_signed, &policy),
+            Err(FinalizeError::MissingSignatures(0))
+        ));
+        assert!(matches!(
+            extract_tx(partially_signed),
+            Err(FinalizeError::NotFinalized(0))
+        ));
+
+        for (master, _) in &signers {
+            psbt.sign(master, &secp).unwrap();
+        }
+
+        // The witness utxo is not a script of the policy.
+        let mut hostile = psbt.clone();
+        hostile.inputs[0]
+            .witness_utxo
+            .as_mut()
+            .unwrap()
+            .script_pubkey = ScriptBuf::from(vec![0x51]);
+        assert!(matches!(
+            finalize(&mut hostile, &policy),
+            Err(FinalizeError::ScriptMismatch(0))
+        ));
+        hostile.inputs[0].witness_utxo = None;
+        assert!(matches!(
+            finalize(&mut hostile, &policy),
+            Err(FinalizeError::MissingUtxo(0))
+        ));
+        // The previous transaction is not the one spent, or does not have
+        // the spent output.
+        let previous = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![psbt.inputs[0].witness_utxo.clone().unwrap()],
+        };
+        hostile.inputs[0].non_witness_utxo = Some(previous.clone());
+        assert!(matches!(
+            finalize(&mut hostile, &policy),
+            Err(FinalizeError::UtxoMismatch(0))
+        ));
+        hostile.unsigned_tx.input[0].previous_output = OutPoint::new(previous.compute_txid(), 1);
+        assert!(matches!(
+            finalize(&mut hostile, &policy),
+            Err(FinalizeError::UtxoMismatch(0))
+        ));
+        hostile.unsigned_tx.input[0].previous_output.vout = 0;
+        finalize(&mut hostile.clone(), &policy).unwrap();
+
+        // The psbt is left unchanged when an input fails.
+        let mut hostile = psbt.clone();
+        hostile.unsigned_tx.input.push(TxIn::default());
+        hostile.inputs.push(Default::default());
+        let unchanged = hostile.clone();
+        assert!(matches!(
+            finalize(&mut hostile, &policy),
+            Err(FinalizeError::MissingUtxo(1))
+        ));
+        assert_eq!(hostile, unchanged);
+        hostile.inputs.pop();
+        assert!(matches!(
+            finalize(&mut hostile, &policy),
+            Err(FinalizeError::InputCountMismatch)
+        ));
+
+        finalize(&mut psbt, &policy).unwrap();
+        let input = &psbt.inputs[0];
+        assert!(input.partial_sigs.is_empty() && input.witness_script.is_none());
+        let witness = input.final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness.last(), Some(witness_script.as_bytes()));
+
+        let tx = extract_tx(psbt.clone()).unwrap();
+        assert_eq!(&tx.input[0].witness, witness);
+        // The input without a map would be broadcast unsigned.
+        let mut hostile = psbt.clone();
+        hostile.unsigned_tx.input.push(TxIn::default());
+        assert!(matches!(
+            extract_tx(hostile),
+            Err(FinalizeError::InputCountMismatch)
+        ));
+        // The finalized inputs are kept.
+        finalize(&mut psbt, &policy).unwrap();
+
+        let (master, _) = &signers[0];
+        let account_path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let account = Xpub::from_priv(&secp, &master.derive_priv(&secp, &account_path).unwrap());
+        let singlesig =
+            WalletPolicy::new_singlesig((master.fingerprint(&secp), account_path.clone()), account)
+                .unwrap();
+        let path = account_path.extend([ChildNumber::from_normal_idx(1).unwrap(); 2]);
+        let key = Xpub::from_priv(&secp, &master.derive_priv(&secp, &path).unwrap()).public_key;
+        let mut psbt = Psbt::from_unsigned_tx(psbt.unsigned_tx.clone()).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: bitcoin::Amount::from_sat(10_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&PublicKey::new(key).wpubkey_hash().unwrap()),
+        });
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(key, (master.fingerprint(&secp), path));
+        assert!(matches!(
+            finalize(&mut psbt.clone(), &policy),
+            Err(FinalizeError::MissingDerivation(0))
+        ));
+        psbt.sign(master, &secp).unwrap();
+        finalize(&mut psbt, &singlesig).unwrap();
+        let witness = psbt.inputs[0].final_script_witness.as_ref().unwrap();
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness.last(), Some(&key.serialize()[..]));
+
+        let taptree = WalletPolicy::new(
+            String::new(),
+            wallet::Version::V2,
+            "tr(@0/**,pk(@1/**))".to_string(),
+            policy.keys.clone(),
+        );
+        assert!(matches!(
+            finalize(&mut psbt, &taptree),
+            Err(FinalizeError::UnsupportedPolicy)
+        ));
+    }
+
+    #[test]
+    fn test_musig_values() {
+        let secp = Secp256k1::new();
+        let participant = PublicKey::new(
+            Keypair::from_seckey_slice(&secp, &[0x01; 32])
+                .unwrap()
+                .public_key(),
+        );
+        let aggregate = PublicKey::new(
+            Keypair::from_seckey_slice(&secp, &[0x02; 32])
+                .unwrap()
+                .public_key(),
+        );
+        let leaf_hash = TapLeafHash::from_script(
+            &ScriptBuf::from(vec![0x51]),
+            taproot::LeafVersion::TapScript,
+        );
+
+        let mut value = vec![0xaa; 66];
+        value.extend(participant.to_bytes());
+        value.extend(aggregate.to_bytes());
+        let nonce = MusigPubNonce::from_slice(&value).unwrap();
+        assert_eq!(nonce.participant, participant);
+        assert_eq!(nonce.leaf_hash, None);
+
+        let mut value = vec![0xbb; 32];
+        value.extend(participant.to_bytes());
+        value.extend(aggregate.to_bytes());
+        value.extend(leaf_hash.to_byte_array());
+        let sig = MusigPartialSignature::from_slice(&value).unwrap();
+        assert_eq!(sig.aggregate, aggregate);
+        assert_eq!(sig.leaf_hash, Some(leaf_hash));
+        assert!(matches!(
+            MusigPartialSignature::from_slice(&value[..80]),
+            Err(PartialSignatureError::BadMusigLength)
+        ));
+
+        let mut input = Input::default();
+        nonce.add_to(&mut input);
+        sig.add_to(&mut input);
+        let keys: Vec<(u8, usize)> = input
+            .unknown
+            .iter()
+            .map(|(key, value)| (key.type_value, key.key.len() + value.len()))
+            .collect();
+        assert_eq!(keys, vec![(0x1b, 66 + 66), (0x1c, 98 + 32)]);
+    }
+}
@@ -0,0 +1,268 @@
+//! Proof of reserves following BIP-127: a transaction spending the wallet
+//! utxos along with a commitment input that can never be valid, so that the
+//! signatures prove the control of the coins without allowing to spend them.
+//!
+//! The first input spends the output 0 of the transaction whose txid is
+//! `sha256d("Proof-of-Reserves: " || message)`, the single output sends the
+//! sum of the wallet utxos to `OP_TRUE`.
+
+use bitcoin::{
+    absolute::LockTime,
+    hashes::{sha256d, Hash},
+    opcodes::OP_TRUE,
+    psbt::{Input, Psbt},
+    secp256k1::{Message, Secp256k1, XOnlyPublicKey},
+    sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType},
+    transaction::Version,
+    Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+/// Prefix of the committed message.
+pub const MESSAGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReservesError {
+    NoUtxos,
+    InvalidChallenge,
+    InvalidOutput,
+    /// The input has no witness utxo.
+    MissingUtxo(usize),
+    /// Only p2wpkh and p2tr key path spends are supported.
+    UnsupportedScript(usize),
+    MissingSignature(usize),
+    InvalidSignature(usize),
+}
+
+/// Returns the commitment input of the message.
+pub fn challenge_txin(message: &str) -> TxIn {
+    let hash = sha256d::Hash::hash(format!("{}{}", MESSAGE_PREFIX, message).as_bytes());
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_raw_hash(hash), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }
+}
+
+/// Output spent by the commitment input, needed to compute the sighashes.
+fn challenge_txout() -> TxOut {
+    TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::from(vec![OP_TRUE.to_u8()]),
+    }
+}
+
+/// Builds the psbt to sign for the message, spending the utxos given with
+/// their psbt input, which must hold the witness utxo and the key origins
+/// needed by the signer.
+pub fn build_psbt(
+    message: &str,
+    utxos: impl IntoIterator<Item = (OutPoint, Input)>,
+) -> Result<Psbt, ReservesError> {
+    let mut txins = vec![challenge_txin(message)];
+    let mut inputs = vec![Input {
+        witness_utxo: Some(challenge_txout()),
+        // Finalized so that signers leave it alone.
+        final_script_sig: Some(ScriptBuf::new()),
+        final_script_witness: Some(Witness::new()),
+        ..Default::default()
+    }];
+    let mut amount = Amount::ZERO;
+    for (i, (outpoint, input)) in utxos.into_iter().enumerate() {
+        let utxo = input
+            .witness_utxo
+            .as_ref()
+            .ok_or(ReservesError::MissingUtxo(i + 1))?;
+        amount += utxo.value;
+        txins.push(TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        });
+        inputs.push(input);
+    }
+    if inputs.len() == 1 {
+        return Err(ReservesError::NoUtxos);
+    }
+
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: txins,
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: challenge_txout().script_pubkey,
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("transaction is unsigned");
+    psbt.inputs = inputs;
+    Ok(psbt)
+}
+
+/// Verifies the signed psbt is a proof for the message, and returns the
+/// amount of the reserves. The utxos are the ones of the psbt inputs, the
+/// caller must check they are unspent.
+pub fn verify(psbt: &Psbt, message: &str) -> Result<Amount, ReservesError> {
+    let tx = &psbt.unsigned_tx;
+    if tx.input.first().map(|txin| txin.previous_output)
+        != Some(challenge_txin(message).previous_output)
+    {
+        return Err(ReservesError::InvalidChallenge);
+    }
+    if tx.input.len() < 2 {
+        return Err(ReservesError::NoUtxos);
+    }
+
+    let mut prevouts = vec![challenge_txout()];
+    for (i, input) in psbt.inputs.iter().enumerate().skip(1) {
+        prevouts.push(
+            input
+                .witness_utxo
+                .clone()
+                .ok_or(ReservesError::MissingUtxo(i))?,
+        );
+    }
+    let amount: Amount = prevouts.iter().map(|txout| txout.value).sum();
+    if tx.output.len() != 1
+        || tx.output[0].value != amount
+        || tx.output[0].script_pubkey != challenge_txout().script_pubkey
+    {
+        return Err(ReservesError::InvalidOutput);
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(tx);
+    for (i, (input, utxo)) in psbt.inputs.iter().zip(&prevouts).enumerate().skip(1) {
+        let script = &utxo.script_pubkey;
+        if script.is_p2wpkh() {
+            let (pubkey, sig) = input
+                .partial_sigs
+                .iter()
+                .find(|(pk, _)| {
+                    pk.wpubkey_hash()
+                        .is_ok_and(|hash| ScriptBuf::new_p2wpkh(&hash) == *script)
+                })
+                .ok_or(ReservesError::MissingSignature(i))?;
+            // The signatures must commit to every input and output.
+            if sig.sighash_type != EcdsaSighashType::All {
+                return Err(ReservesError::InvalidSignature(i));
+            }
+            let sighash = cache
+                .p2wpkh_signature_hash(i, script, utxo.value, sig.sighash_type)
+                .map_err(|_| ReservesError::InvalidSignature(i))?;
+            secp.verify_ecdsa(&Message::from(sighash), &sig.signature, &pubkey.inner)
+                .map_err(|_| ReservesError::InvalidSignature(i))?;
+        } else if script.is_p2tr() {
+            let sig = input
+                .tap_key_sig
+                .ok_or(ReservesError::MissingSignature(i))?;
+            if !matches!(
+                sig.sighash_type,
+                TapSighashType::Default | TapSighashType::All
+            ) {
+                return Err(ReservesError::InvalidSignature(i));
+            }
+            let output_key = XOnlyPublicKey::from_slice(&script.as_bytes()[2..])
+                .map_err(|_| ReservesError::UnsupportedScript(i))?;
+            let sighash = cache
+                .taproot_key_spend_signature_hash(i, &Prevouts::All(&prevouts), sig.sighash_type)
+                .map_err(|_| ReservesError::InvalidSignature(i))?;
+            secp.verify_schnorr(&sig.signature, &Message::from(sighash), &output_key)
+                .map_err(|_| ReservesError::InvalidSignature(i))?;
+        } else {
+            return Err(ReservesError::UnsupportedScript(i));
+        }
+    }
+    Ok(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        bip32::{DerivationPath, Xpriv, Xpub},
+        CompressedPublicKey, Network,
+    };
+    use std::str::FromStr;
+
+    fn input(xpriv: &Xpriv, path: &str, value: u64, taproot: bool) -> Input {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str(path).unwrap();
+        let pubkey = Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap()).public_key;
+        let mut input = Input::default();
+        let script_pubkey = if taproot {
+            let (internal_key, _) = pubkey.x_only_public_key();
+            input.tap_internal_key = Some(internal_key);
+            input
+                .tap_key_origins
+                .insert(internal_key, (Vec::new(), (xpriv.fingerprint(&secp), path)));
+            ScriptBuf::new_p2tr(&secp, internal_key, None)
+        } else {
+            input
+                .bip32_derivation
+                .insert(pubkey, (xpriv.fingerprint(&secp), path));
+            ScriptBuf::new_p2wpkh(&CompressedPublicKey(pubkey).wpubkey_hash())
+        };
+        input.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey,
+        });
+        input
+    }
+
+    #[test]
+    fn test_proof_of_reserves() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x02; 32]).unwrap();
+        let utxos = vec![
+            (
+                OutPoint::new(Txid::all_zeros(), 1),
+                input(&xpriv, "m/84'/1'/0'/0/0", 100_000, false),
+            ),
+            (
+                OutPoint::new(Txid::all_zeros(), 2),
+                input(&xpriv, "m/86'/1'/0'/0/0", 50_000, true),
+            ),
+        ];
+        let mut psbt = build_psbt("challenge", utxos).unwrap();
+        assert_eq!(
+            verify(&psbt, "challenge"),
+            Err(ReservesError::MissingSignature(1))
+        );
+
+        // The commitment input has no key to sign with.
+        let _ = psbt.sign(&xpriv, &secp);
+        assert_eq!(verify(&psbt, "challenge"), Ok(Amount::from_sat(150_000)));
+        assert_eq!(verify(&psbt, "other"), Err(ReservesError::InvalidChallenge));
+
+        let mut tampered = psbt.clone();
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(1_000);
+        assert_eq!(
+            verify(&tampered, "challenge"),
+            Err(ReservesError::InvalidOutput)
+        );
+
+        let mut tampered = psbt.clone();
+        tampered.inputs[1].witness_utxo.as_mut().unwrap().value = Amount::from_sat(200_000);
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(250_000);
+        assert_eq!(
+            verify(&tampered, "challenge"),
+            Err(ReservesError::InvalidSignature(1))
+        );
+    }
+
+    #[test]
+    fn test_challenge_txin() {
+        let txin = challenge_txin("");
+        assert_eq!(txin.previous_output.vout, 0);
+        assert_eq!(
+            txin.previous_output.txid,
+            Txid::from_raw_hash(sha256d::Hash::hash(b"Proof-of-Reserves: "))
+        );
+        assert_eq!(
+            build_psbt("", Vec::new()).unwrap_err(),
+            ReservesError::NoUtxos
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Drive loop of an interpreter over an async transport, for the interpreters
+//! whose every transmit is sent to the device.
+
+use crate::{prelude::*, Event, Interpreter};
+
+/// Transport exchanging raw messages with the device.
+// The futures are not required to be Send, as in the bhwi-async traits.
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    type Error;
+    async fn exchange(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error>;
+    /// Writes a frame the device does not reply to, by default it is
+    /// exchanged and the reply dropped.
+    async fn send(&self, data: &[u8]) -> Result<(), Self::Error> {
+        self.exchange(data).await.map(|_| ())
+    }
+    /// Waits for the device to re-enumerate and reopens it, once an
+    /// interpreter polls [`Event::Reenumerating`]. The connection is kept by
+    /// default.
+    async fn reconnect(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum RunError<I, T> {
+    Interpreter(I),
+    Transport(T),
+}
+
+/// Runs the command until the interpreter has nothing left to transmit,
+/// and returns its response. The transport is reconnected if the device
+/// re-enumerates.
+pub async fn run<I, T>(
+    mut interpreter: I,
+    command: I::Command,
+    transport: &T,
+) -> Result<I::Response, RunError<I::Error, T::Error>>
+where
+    I: Interpreter,
+    I::Transmit: Into<Vec<u8>>,
+    T: AsyncTransport + ?Sized,
+{
+    let mut transmit = Some(interpreter.start(command).map_err(RunError::Interpreter)?);
+    let mut reenumerating = false;
+    while let Some(mut t) = transmit {
+        while let Some(next) = interpreter.poll_transmit() {
+            transport
+                .send(&t.into())
+                .await
+                .map_err(RunError::Transport)?;
+            t = next;
+        }
+        let data = transport
+            .exchange(&t.into())
+            .await
+            .map_err(RunError::Transport)?;
+        transmit = interpreter.exchange(data).map_err(RunError::Interpreter)?;
+        while let Some(event) = interpreter.poll_event() {
+            reenumerating |= event == Event::Reenumerating;
+        }
+    }
+    if reenumerating {
+        transport.reconnect().await.map_err(RunError::Transport)?;
+    }
+    interpreter.end().map_err(RunError::Interpreter)
+}
+
+/// Runs the commands in turn, each with a new interpreter, and returns their
+/// responses. A command switching the app of the device, like the `OpenApp`
+/// of Ledger, is followed by the reconnection and the next commands resume
+/// on the reopened device. The commands left are dropped on a failure.
+pub async fn run_queue<I, T>(
+    new: impl Fn() -> I,
+    commands: impl IntoIterator<Item = I::Command>,
+    transport: &T,
+) -> Result<Vec<I::Response>, RunError<I::Error, T::Error>>
+where
+    I: Interpreter,
+    I::Transmit: Into<Vec<u8>>,
+    T: AsyncTransport + ?Sized,
+{
+    let mut responses = Vec::new();
+    for command in commands {
+        responses.push(run(new(), command, transport).await?);
+    }
+    Ok(responses)
+}
+
+#[cfg(all(test, feature = "ledger"))]
+mod tests {
+    use super::*;
+    use crate::ledger::{
+        apdu::ApduCommand, LedgerCommand, LedgerError, LedgerInterpreter, LedgerResponse,
+    };
+    use bitcoin::Network;
+    use std::cell::{Cell, RefCell};
+
+    type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+    /// Replies to every message with the next response.
+    struct Scripted {
+        sent: RefCell<Vec<Vec<u8>>>,
+        responses: RefCell<Vec<Vec<u8>>>,
+        reconnects: Cell<usize>,
+    }
+
+    impl Scripted {
+        fn new(mut responses: Vec<Vec<u8>>) -> Self {
+            responses.reverse();
+            Self {
+                sent: RefCell::new(Vec::new()),
+                responses: RefCell::new(responses),
+                reconnects: Cell::new(0),
+            }
+        }
+    }
+
+    impl AsyncTransport for Scripted {
+        type Error = &'static str;
+        async fn exchange(&self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+            self.sent.borrow_mut().push(data.to_vec());
+            self.responses.borrow_mut().pop().ok_or("disconnected")
+        }
+        async fn send(&self, data: &[u8]) -> Result<(), Self::Error> {
+            self.sent.borrow_mut().push(data.to_vec());
+            Ok(())
+        }
+        async fn reconnect(&self) -> Result<(), Self::Error> {
+            self.reconnects.set(self.reconnects.get() + 1);
+            Ok(())
+        }
+    }
+
+    /// Writes its command in frames of two bytes, the device replies once
+    /// all of them are sent.
+    #[derive(Default)]
+    struct Chunked {
+        frames: std::collections::VecDeque<Vec<u8>>,
+        reply: Option<Vec<u8>>,
+    }
+
+    impl Interpreter for Chunked {
+        type Command = Vec<u8>;
+        type Transmit = Vec<u8>;
+        type Response = Vec<u8>;
+        type Error = ();
+        fn start(&mut self, command: Vec<u8>) -> Result<Vec<u8>, ()> {
+            self.frames = command.chunks(2).map(|c| c.to_vec()).collect();
+            self.frames.pop_front().ok_or(())
+        }
+        fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Vec<u8>>, ()> {
+            self.reply = Some(data);
+            Ok(None)
+        }
+        fn end(self) -> Result<Vec<u8>, ()> {
+            self.reply.ok_or(())
+        }
+        fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+            self.frames.pop_front()
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let transport = Scripted::new(vec![vec![0xde, 0xad, 0xbe, 0xef, 0x90, 0x00]]);
+        let res = futures::executor::block_on(run(
+            Ledger::default(),
+            LedgerCommand::GetMasterFingerprint,
+            &transport,
+        ));
+        match res {
+            Ok(LedgerResponse::MasterFingerprint(fg)) => {
+                assert_eq!(fg.to_bytes(), [0xde, 0xad, 0xbe, 0xef])
+            }
+            _ => panic!("expected a fingerprint"),
+        }
+        assert_eq!(transport.sent.borrow().len(), 1);
+
+        // The interrupted executions are continued until the end.
+        let transport = Scripted::new(vec![
+            vec![0x10, 0x01, 0xE0, 0x00],
+            vec![0x10, 0x02, 0xE0, 0x00],
+        ]);
+        let res = futures::executor::block_on(run(
+            Ledger::default(),
+            LedgerCommand::SignMessage {
+                path: Default::default(),
+                message: b"message".to_vec(),
+            },
+            &transport,
+        ));
+        assert!(matches!(res, Err(RunError::Transport("disconnected"))));
+        assert_eq!(transport.sent.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_run_queue() {
+        let transport = Scripted::new(vec![
+            vec![0x90, 0x00],
+            vec![0xde, 0xad, 0xbe, 0xef, 0x90, 0x00],
+        ]);
+        let res = futures::executor::block_on(run_queue(
+            Ledger::default,
+            [
+                LedgerCommand::OpenApp(Network::Bitcoin),
+                LedgerCommand::GetMasterFingerprint,
+            ],
+            &transport,
+        ));
+        assert!(matches!(
+            res.as_deref(),
+            Ok([
+                LedgerResponse::TaskDone,
+                LedgerResponse::MasterFingerprint(_)
+            ])
+        ));
+        // The fingerprint is read from the reopened device.
+        assert_eq!(transport.reconnects.get(), 1);
+        assert_eq!(transport.sent.borrow().len(), 2);
+
+        // The app was already open, the device stays connected.
+        let transport = Scripted::new(vec![vec![0x6e, 0x00]]);
+        let res = futures::executor::block_on(run(
+            Ledger::default(),
+            LedgerCommand::OpenApp(Network::Bitcoin),
+            &transport,
+        ));
+        assert!(matches!(res, Ok(LedgerResponse::TaskDone)));
+        assert_eq!(transport.reconnects.get(), 0);
+    }
+
+    #[test]
+    fn test_run_several_frames() {
+        let transport = Scripted::new(vec![vec![0x90, 0x00]]);
+        let res =
+            futures::executor::block_on(run(Chunked::default(), vec![1, 2, 3, 4, 5], &transport));
+        assert!(matches!(res, Ok(reply) if reply == [0x90, 0x00]));
+        assert_eq!(
+            *transport.sent.borrow(),
+            vec![vec![1, 2], vec![3, 4], vec![5]]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Screening of a psbt before signing, for the host to warn the user about
+//! its outputs and its fee before the device starts prompting.
+
+use bitcoin::{psbt::Psbt, Amount, Script};
+
+use crate::{prelude::*, psbt::spent_utxo};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The output at the index carries data in an OP_RETURN script, with
+    /// the data pushed.
+    OpReturn { output: usize, data: Vec<u8> },
+    /// The output at the index pays to a bare multisig script.
+    BareMultisig(usize),
+    /// The script of the output at the index is not standard, it may not be
+    /// relayed.
+    NonStandardScript(usize),
+    /// The utxo spent by the input at the index is missing or inconsistent,
+    /// see [`crate::psbt::summary`], the fee cannot be checked.
+    MissingUtxo(usize),
+    /// The fee is above the limits, see [`FeeLimits`].
+    HighFee(Amount),
+    /// The outputs spend more than the inputs, or the amounts add up to more
+    /// than the money supply: the psbt is invalid.
+    InvalidFee,
+}
+
+/// Fees above one of the limits are flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeLimits {
+    pub max_fee: Amount,
+    /// Share of the spent amount, in percent.
+    pub max_percent: u64,
+}
+
+impl Default for FeeLimits {
+    fn default() -> Self {
+        Self {
+            max_fee: Amount::from_sat(1_000_000),
+            max_percent: 10,
+        }
+    }
+}
+
+/// Returns true if the script is one of the standard output templates.
+fn is_standard(script: &Script) -> bool {
+    script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2pk()
+        || script.is_witness_program()
+        || script.is_multisig()
+        || script.is_op_return()
+}
+
+/// Returns the data pushed by the OP_RETURN script.
+fn op_return_data(script: &Script) -> Vec<u8> {
+    script
+        .instructions()
+        .skip(1)
+        .filter_map(|ins| match ins {
+            Ok(bitcoin::script::Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_vec()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Returns the warnings of the outputs and of the fee of the psbt, in the
+/// order of the outputs then the inputs.
+pub fn screen(psbt: &Psbt, limits: &FeeLimits) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    for (i, txout) in psbt.unsigned_tx.output.iter().enumerate() {
+        let script = txout.script_pubkey.as_script();
+        if script.is_op_return() {
+            warnings.push(Warning::OpReturn {
+                output: i,
+                data: op_return_data(script),
+            });
+        } else if script.is_multisig() {
+            warnings.push(Warning::BareMultisig(i));
+        } else if !is_standard(script) {
+            warnings.push(Warning::NonStandardScript(i));
+        }
+    }
+    // The sums of hostile psbts overflow the amounts, they are checked
+    // against the money supply in u128.
+    let mut spent: u128 = 0;
+    let mut missing = false;
+    for i in 0..psbt.unsigned_tx.input.len() {
+        match spent_utxo(psbt, i) {
+            Some(utxo) => spent += u128::from(utxo.value.to_sat()),
+            None => {
+                missing = true;
+                warnings.push(Warning::MissingUtxo(i));
+            }
+        }
+    }
+    let sent: u128 = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|txout| u128::from(txout.value.to_sat()))
+        .sum();
+    if !missing {
+        if sent > spent || spent > u128::from(Amount::MAX_MONEY.to_sat()) {
+            warnings.push(Warning::InvalidFee);
+        } else {
+            let fee = spent - sent;
+            if fee > u128::from(limits.max_fee.to_sat())
+                || fee * 100 > spent * u128::from(limits.max_percent)
+            {
+                warnings.push(Warning::HighFee(Amount::from_sat(fee as u64)));
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime, hashes::Hash, opcodes::all::OP_RETURN, script::Builder,
+        transaction::Version, ScriptBuf, Transaction, TxIn, TxOut, WScriptHash,
+    };
+    use core::str::FromStr;
+
+    fn psbt(outputs: Vec<TxOut>, spent: Option<u64>) -> Psbt {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: outputs,
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = spent.map(|value| TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: ScriptBuf::new_p2wsh(&WScriptHash::all_zeros()),
+        });
+        psbt
+    }
+
+    fn txout(value: u64, script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey,
+        }
+    }
+
+    #[test]
+    fn test_screen() {
+        let op_return = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(b"hello")
+            .into_script();
+        let key = bitcoin::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let bare_multisig = Builder::new()
+            .push_int(1)
+            .push_key(&key)
+            .push_int(1)
+            .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        let outputs = vec![
+            txout(90_000, ScriptBuf::new_p2wsh(&WScriptHash::all_zeros())),
+            txout(0, op_return),
+            txout(1_000, bare_multisig),
+            txout(1_000, ScriptBuf::from(vec![0x51])),
+        ];
+        let limits = FeeLimits::default();
+        assert_eq!(
+            screen(&psbt(outputs.clone(), Some(100_000)), &limits),
+            vec![
+                Warning::OpReturn {
+                    output: 1,
+                    data: b"hello".to_vec()
+                },
+                Warning::BareMultisig(2),
+                Warning::NonStandardScript(3),
+            ]
+        );
+
+        let outputs = outputs[..1].to_vec();
+        assert_eq!(
+            screen(&psbt(outputs.clone(), Some(200_000)), &limits),
+            vec![Warning::HighFee(Amount::from_sat(110_000))]
+        );
+        assert_eq!(
+            screen(&psbt(outputs, None), &limits),
+            vec![Warning::MissingUtxo(0)]
+        );
+    }
+
+    #[test]
+    fn test_screen_invalid_fee() {
+        let script = ScriptBuf::new_p2wsh(&WScriptHash::all_zeros());
+        let limits = FeeLimits {
+            max_fee: Amount::MAX,
+            max_percent: u64::MAX,
+        };
+        // The outputs spend more than the input.
+        let outputs = vec![txout(100_001, script.clone())];
+        assert_eq!(
+            screen(&psbt(outputs, Some(100_000)), &limits),
+            vec![Warning::InvalidFee]
+        );
+
+        // The sums overflow the amounts.
+        let outputs = vec![txout(u64::MAX, script.clone()), txout(u64::MAX, script)];
+        assert_eq!(
+            screen(&psbt(outputs.clone(), Some(u64::MAX)), &limits),
+            vec![Warning::InvalidFee]
+        );
+        let mut psbt = psbt(outputs[..1].to_vec(), Some(u64::MAX));
+        psbt.unsigned_tx.output[0].value = Amount::from_sat(1);
+        assert_eq!(screen(&psbt, &limits), vec![Warning::InvalidFee]);
+    }
+}
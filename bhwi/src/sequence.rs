@@ -0,0 +1,189 @@
+//! Interpreter running several commands in turn as a single operation, for
+//! the routine flows such as opening the app then getting the fingerprint and
+//! the xpub of the account.
+
+use alloc::collections::VecDeque;
+
+use crate::{prelude::*, Event, Interpreter};
+
+#[derive(Debug)]
+pub enum SequenceError<E> {
+    /// The sequence was started without command.
+    Empty,
+    Interpreter(E),
+}
+
+/// Runs the commands in turn, each with a new interpreter of the factory, and
+/// returns their responses in the order of the commands. The first failure
+/// ends the sequence.
+pub struct Sequence<I: Interpreter, F> {
+    new_interpreter: F,
+    commands: VecDeque<I::Command>,
+    current: Option<I>,
+    responses: Vec<I::Response>,
+    /// Events of the interpreters ended during the last exchange.
+    events: VecDeque<Event>,
+    cancelled: bool,
+}
+
+impl<I, F> Sequence<I, F>
+where
+    I: Interpreter,
+    F: FnMut() -> I,
+{
+    pub fn new(new_interpreter: F) -> Self {
+        Self {
+            new_interpreter,
+            commands: VecDeque::new(),
+            current: None,
+            responses: Vec::new(),
+            events: VecDeque::new(),
+            cancelled: false,
+        }
+    }
+
+    /// Returns the number of commands ended so far.
+    pub fn completed(&self) -> usize {
+        self.responses.len()
+    }
+
+    /// Starts the next command with a new interpreter, if any is left.
+    fn start_next(&mut self) -> Result<Option<I::Transmit>, SequenceError<I::Error>> {
+        let Some(command) = self.commands.pop_front() else {
+            return Ok(None);
+        };
+        let mut interpreter = (self.new_interpreter)();
+        let transmit = interpreter
+            .start(command)
+            .map_err(SequenceError::Interpreter)?;
+        self.current = Some(interpreter);
+        Ok(Some(transmit))
+    }
+}
+
+impl<I, F> Interpreter for Sequence<I, F>
+where
+    I: Interpreter,
+    F: FnMut() -> I,
+{
+    type Command = Vec<I::Command>;
+    type Transmit = I::Transmit;
+    type Response = Vec<I::Response>;
+    type Error = SequenceError<I::Error>;
+
+    fn start(&mut self, commands: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        self.commands = commands.into();
+        self.current = None;
+        self.responses.clear();
+        self.events.clear();
+        self.cancelled = false;
+        self.start_next()?.ok_or(SequenceError::Empty)
+    }
+    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        let Some(current) = self.current.as_mut().filter(|_| !self.cancelled) else {
+            return Ok(None);
+        };
+        if let Some(transmit) = current.exchange(data).map_err(SequenceError::Interpreter)? {
+            return Ok(Some(transmit));
+        }
+        let mut current = self.current.take().expect("running interpreter");
+        while let Some(event) = current.poll_event() {
+            self.events.push_back(event);
+        }
+        let response = current.end().map_err(SequenceError::Interpreter)?;
+        self.responses.push(response);
+        self.start_next()
+    }
+    fn end(self) -> Result<Self::Response, Self::Error> {
+        // The interpreter of a cancelled command fails as it ends.
+        if let Some(current) = self.current {
+            current.end().map_err(SequenceError::Interpreter)?;
+        }
+        Ok(self.responses)
+    }
+    fn poll_event(&mut self) -> Option<Event> {
+        self.events
+            .pop_front()
+            .or_else(|| self.current.as_mut()?.poll_event())
+    }
+    fn cancel(&mut self) {
+        self.commands.clear();
+        self.events.clear();
+        self.cancelled = true;
+        if let Some(current) = self.current.as_mut() {
+            current.cancel();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ledger"))]
+mod tests {
+    use super::*;
+    use crate::ledger::{
+        apdu::ApduCommand, LedgerCommand, LedgerError, LedgerInterpreter, LedgerResponse,
+    };
+    use bitcoin::bip32::DerivationPath;
+    use core::str::FromStr;
+
+    type Ledger = LedgerInterpreter<LedgerCommand, ApduCommand, LedgerResponse, LedgerError>;
+
+    const XPUB: &str = "tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW";
+
+    #[test]
+    fn test_sequence() {
+        let mut sequence = Sequence::new(Ledger::default);
+        assert!(matches!(
+            sequence.start(Vec::new()),
+            Err(SequenceError::Empty)
+        ));
+
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let first = sequence
+            .start(vec![
+                LedgerCommand::GetMasterFingerprint,
+                LedgerCommand::GetXpub {
+                    path: path.clone(),
+                    display: false,
+                },
+            ])
+            .unwrap();
+        let mut xpub = XPUB.as_bytes().to_vec();
+        xpub.extend([0x90, 0x00]);
+        let second = sequence
+            .exchange(vec![0xde, 0xad, 0xbe, 0xef, 0x90, 0x00])
+            .unwrap()
+            .unwrap();
+        assert_ne!(first.encode(), second.encode());
+        assert_eq!(sequence.completed(), 1);
+        assert!(sequence.exchange(xpub).unwrap().is_none());
+        let responses = sequence.end().unwrap();
+        assert!(matches!(
+            responses[..],
+            [
+                LedgerResponse::MasterFingerprint(_),
+                LedgerResponse::Xpub(_)
+            ]
+        ));
+
+        // The commands left are not started once the sequence is cancelled.
+        let mut sequence = Sequence::new(Ledger::default);
+        sequence
+            .start(vec![
+                LedgerCommand::GetMasterFingerprint,
+                LedgerCommand::GetXpub {
+                    path,
+                    display: false,
+                },
+            ])
+            .unwrap();
+        sequence.cancel();
+        assert!(sequence
+            .exchange(vec![0xde, 0xad, 0xbe, 0xef, 0x90, 0x00])
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            sequence.end(),
+            Err(SequenceError::Interpreter(LedgerError::Cancelled))
+        ));
+    }
+}
@@ -0,0 +1,83 @@
+//! Keys of the silent payments of BIP-352: the scan and the spend keys of
+//! an account, from which the host builds the silent payment address and
+//! scans the transactions.
+
+use bitcoin::{
+    bip32::{self, ChildNumber, DerivationPath, Xpriv},
+    secp256k1::{PublicKey, Secp256k1, Signing},
+    NetworkKind,
+};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentKeys {
+    pub scan: PublicKey,
+    pub spend: PublicKey,
+}
+
+fn path(network: NetworkKind, account: u32, branch: u32) -> Result<DerivationPath, bip32::Error> {
+    let coin = match network {
+        NetworkKind::Main => 0,
+        NetworkKind::Test => 1,
+    };
+    Ok(DerivationPath::from(vec![
+        ChildNumber::from_hardened_idx(352)?,
+        ChildNumber::from_hardened_idx(coin)?,
+        ChildNumber::from_hardened_idx(account)?,
+        ChildNumber::from_hardened_idx(branch)?,
+        ChildNumber::from_normal_idx(0)?,
+    ]))
+}
+
+/// Path of the scan key of the account, `m/352'/coin'/account'/1'/0`.
+pub fn scan_path(network: NetworkKind, account: u32) -> Result<DerivationPath, bip32::Error> {
+    path(network, account, 1)
+}
+
+/// Path of the spend key of the account, `m/352'/coin'/account'/0'/0`.
+pub fn spend_path(network: NetworkKind, account: u32) -> Result<DerivationPath, bip32::Error> {
+    path(network, account, 0)
+}
+
+/// Derives the keys of the account from the master key.
+pub fn derive_keys<C: Signing>(
+    secp: &Secp256k1<C>,
+    master: &Xpriv,
+    account: u32,
+) -> Result<SilentPaymentKeys, bip32::Error> {
+    let key = |path: DerivationPath| -> Result<PublicKey, bip32::Error> {
+        Ok(master
+            .derive_priv(secp, &path)?
+            .private_key
+            .public_key(secp))
+    };
+    Ok(SilentPaymentKeys {
+        scan: key(scan_path(master.network, account)?)?,
+        spend: key(spend_path(master.network, account)?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            scan_path(NetworkKind::Main, 0).unwrap(),
+            DerivationPath::from_str("m/352'/0'/0'/1'/0").unwrap()
+        );
+        assert_eq!(
+            spend_path(NetworkKind::Test, 2).unwrap(),
+            DerivationPath::from_str("m/352'/1'/2'/0'/0").unwrap()
+        );
+        assert!(scan_path(NetworkKind::Main, 1 << 31).is_err());
+
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &[0x01; 32]).unwrap();
+        let keys = derive_keys(&secp, &master, 0).unwrap();
+        assert_ne!(keys.scan, keys.spend);
+    }
+}
@@ -0,0 +1,176 @@
+//! SLIP-132 forms of the extended public keys (ypub, zpub, Ypub, Zpub and
+//! their testnet variants), whose version bytes tell the script type of the
+//! account. Still required by many coordinator formats.
+
+use bitcoin::{
+    base58,
+    bip32::{self, ChildNumber, DerivationPath, Xpub},
+    NetworkKind,
+};
+
+use crate::prelude::*;
+
+/// Script type of the account, as told by the version bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScriptType {
+    /// xpub and tpub, for p2pkh and the legacy p2sh multisig.
+    P2pkh,
+    /// ypub and upub.
+    P2shP2wpkh,
+    /// Ypub and Upub.
+    P2shP2wsh,
+    /// zpub and vpub.
+    P2wpkh,
+    /// Zpub and Vpub.
+    P2wsh,
+}
+
+/// Version bytes of the script types, for mainnet then testnet.
+const VERSIONS: [(ScriptType, [u8; 4], [u8; 4]); 5] = [
+    (
+        ScriptType::P2pkh,
+        [0x04, 0x88, 0xb2, 0x1e],
+        [0x04, 0x35, 0x87, 0xcf],
+    ),
+    (
+        ScriptType::P2shP2wpkh,
+        [0x04, 0x9d, 0x7c, 0xb2],
+        [0x04, 0x4a, 0x52, 0x62],
+    ),
+    (
+        ScriptType::P2shP2wsh,
+        [0x02, 0x95, 0xb4, 0x3f],
+        [0x02, 0x42, 0x89, 0xef],
+    ),
+    (
+        ScriptType::P2wpkh,
+        [0x04, 0xb2, 0x47, 0x46],
+        [0x04, 0x5f, 0x1c, 0xf6],
+    ),
+    (
+        ScriptType::P2wsh,
+        [0x02, 0xaa, 0x7e, 0xd3],
+        [0x02, 0x57, 0x54, 0x83],
+    ),
+];
+
+#[derive(Debug)]
+pub enum Slip132Error {
+    Base58(base58::Error),
+    /// The key is not 78 bytes long.
+    InvalidLength(usize),
+    /// The version bytes are not the ones of a SLIP-132 script type.
+    UnknownVersion([u8; 4]),
+    Bip32(bip32::Error),
+}
+
+impl ScriptType {
+    /// Returns the script type of the standard account path: BIP-44, BIP-49,
+    /// BIP-84, or BIP-48 with its script type. Taproot has no SLIP-132 form.
+    pub fn from_path(path: &DerivationPath) -> Option<Self> {
+        let hardened = |index| ChildNumber::Hardened { index };
+        match path.as_ref() {
+            [purpose, ..] if *purpose == hardened(44) => Some(ScriptType::P2pkh),
+            [purpose, ..] if *purpose == hardened(49) => Some(ScriptType::P2shP2wpkh),
+            [purpose, ..] if *purpose == hardened(84) => Some(ScriptType::P2wpkh),
+            [purpose, _, _, script_type, ..] if *purpose == hardened(48) => {
+                if *script_type == hardened(1) {
+                    Some(ScriptType::P2shP2wsh)
+                } else if *script_type == hardened(2) {
+                    Some(ScriptType::P2wsh)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the version bytes of the script type for the network.
+    pub fn version(&self, network: NetworkKind) -> [u8; 4] {
+        let (_, main, test) = VERSIONS
+            .iter()
+            .find(|(script_type, ..)| script_type == self)
+            .expect("every script type has its versions");
+        match network {
+            NetworkKind::Main => *main,
+            NetworkKind::Test => *test,
+        }
+    }
+}
+
+/// Returns the SLIP-132 form of the xpub for the script type.
+pub fn encode(xpub: &Xpub, script_type: ScriptType) -> String {
+    let mut data = xpub.encode();
+    data[..4].copy_from_slice(&script_type.version(xpub.network));
+    base58::encode_check(&data)
+}
+
+/// Parses the key in its SLIP-132 form, or as a plain xpub or tpub, and
+/// returns it with the script type of its version.
+pub fn decode(s: &str) -> Result<(Xpub, ScriptType), Slip132Error> {
+    let mut data = base58::decode_check(s).map_err(Slip132Error::Base58)?;
+    if data.len() != 78 {
+        return Err(Slip132Error::InvalidLength(data.len()));
+    }
+    let version: [u8; 4] = data[..4].try_into().expect("4 bytes");
+    let (script_type, network) = VERSIONS
+        .iter()
+        .find_map(|(script_type, main, test)| {
+            if version == *main {
+                Some((*script_type, NetworkKind::Main))
+            } else if version == *test {
+                Some((*script_type, NetworkKind::Test))
+            } else {
+                None
+            }
+        })
+        .ok_or(Slip132Error::UnknownVersion(version))?;
+    data[..4].copy_from_slice(&ScriptType::P2pkh.version(network));
+    let xpub = Xpub::decode(&data).map_err(Slip132Error::Bip32)?;
+    Ok((xpub, script_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_slip132() {
+        // Test vectors of BIP-84.
+        let xpub = Xpub::from_str("xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V").unwrap();
+        let zpub = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+        assert_eq!(encode(&xpub, ScriptType::P2wpkh), zpub);
+        assert_eq!(decode(zpub).unwrap(), (xpub, ScriptType::P2wpkh));
+        assert_eq!(
+            decode(&xpub.to_string()).unwrap(),
+            (xpub, ScriptType::P2pkh)
+        );
+
+        let path = DerivationPath::from_str("m/84'/0'/0'").unwrap();
+        assert_eq!(ScriptType::from_path(&path), Some(ScriptType::P2wpkh));
+        let path = DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        assert_eq!(ScriptType::from_path(&path), Some(ScriptType::P2wsh));
+        let path = DerivationPath::from_str("m/86'/0'/0'").unwrap();
+        assert_eq!(ScriptType::from_path(&path), None);
+
+        let tpub = Xpub::from_str("tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW").unwrap();
+        for (script_type, prefix) in [
+            (ScriptType::P2shP2wpkh, "upub"),
+            (ScriptType::P2shP2wsh, "Upub"),
+            (ScriptType::P2wpkh, "vpub"),
+            (ScriptType::P2wsh, "Vpub"),
+        ] {
+            let encoded = encode(&tpub, script_type);
+            assert!(encoded.starts_with(prefix), "{}", encoded);
+            assert_eq!(decode(&encoded).unwrap(), (tpub, script_type));
+        }
+
+        assert!(matches!(
+            decode("1111111111111111111114oLvT2"),
+            Err(Slip132Error::InvalidLength(..))
+        ));
+    }
+}
@@ -0,0 +1,297 @@
+//! Specter DIY over its USB serial port. The host writes a command line
+//! ended by a carriage return, the device acknowledges it with an `ACK`
+//! line then answers with the result line once the user confirmed.
+
+use bitcoin::{
+    address::NetworkUnchecked,
+    base64::{prelude::BASE64_STANDARD, Engine as _},
+    bip32::{DerivationPath, Fingerprint, Xpub},
+    Address, Psbt,
+};
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use crate::devices::{Capabilities, NETWORKS};
+use crate::Interpreter;
+
+pub const SPECTER_ACK: &str = "ACK";
+pub const SPECTER_ERROR_PREFIX: &str = "error: ";
+pub const SPECTER_USER_CANCELLED: &str = "User cancelled";
+
+/// Returns the capabilities of the Specter DIY, which neither displays the
+/// taproot addresses nor signs messages.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        taproot: false,
+        musig2: false,
+        message_signing: false,
+        policy_registration: false,
+        max_inputs: None,
+        networks: NETWORKS.to_vec(),
+    }
+}
+
+#[derive(Debug)]
+pub enum SpecterError {
+    NoErrorOrResult,
+    MissingCommandInfo(&'static str),
+    Serialization(String),
+    /// The device answered with an error line.
+    Device(String),
+    /// The user refused the request on the device.
+    Refused,
+    /// The device does not support the command.
+    UnsupportedCommand(&'static str),
+}
+
+impl From<Infallible> for SpecterError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+pub enum SpecterCommand {
+    /// Checks that the device is unlocked by requesting its fingerprint.
+    Unlock,
+    GetMasterFingerprint,
+    GetXpub(DerivationPath),
+    SignPsbt(Box<Psbt>),
+    /// Single key address of the key at the path, the script type is one
+    /// of "pkh", "sh-wpkh" and "wpkh".
+    DisplayAddress {
+        script_type: &'static str,
+        path: DerivationPath,
+    },
+    /// Address of the descriptor, its keys are derived down to the
+    /// address index.
+    DisplayDescriptorAddress(String),
+}
+
+impl SpecterCommand {
+    fn line(&self) -> String {
+        match self {
+            Self::Unlock | Self::GetMasterFingerprint => "fingerprint".to_string(),
+            Self::GetXpub(path) => format!("xpub {}", format_path(path)),
+            Self::SignPsbt(psbt) => format!("sign {}", BASE64_STANDARD.encode(psbt.serialize())),
+            Self::DisplayAddress { script_type, path } => {
+                format!("showaddr {} {}", script_type, format_path(path))
+            }
+            Self::DisplayDescriptorAddress(descriptor) => format!("showdescaddr {}", descriptor),
+        }
+    }
+}
+
+/// Formats the path with the `h` hardened marker expected by the device.
+fn format_path(path: &DerivationPath) -> String {
+    format!("m/{}", path).replace('\'', "h")
+}
+
+pub enum SpecterResponse {
+    TaskDone,
+    MasterFingerprint(Fingerprint),
+    Xpub(Xpub),
+    Address(Address<NetworkUnchecked>),
+    SignedPsbt(Box<Psbt>),
+}
+
+/// Bytes written to the serial port, an empty payload only reads the next
+/// bytes of a partial answer.
+pub struct SpecterTransmit {
+    pub payload: Vec<u8>,
+}
+
+enum State {
+    New,
+    WaitingAck(SpecterCommand),
+    WaitingResult(SpecterCommand),
+}
+
+pub struct SpecterInterpreter<C, T, R, E> {
+    state: State,
+    buffer: Vec<u8>,
+    response: Option<SpecterResponse>,
+    _marker: std::marker::PhantomData<(C, T, R, E)>,
+}
+
+impl<C, T, R, E> Default for SpecterInterpreter<C, T, R, E> {
+    fn default() -> Self {
+        Self {
+            state: State::New,
+            buffer: Vec::new(),
+            response: None,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<C, T, R, E> SpecterInterpreter<C, T, R, E> {
+    /// Returns the next complete line of the buffer without its line ending.
+    fn next_line(&mut self) -> Result<Option<String>, SpecterError> {
+        let Some(end) = self.buffer.iter().position(|b| *b == b'\n') else {
+            return Ok(None);
+        };
+        let line: Vec<u8> = self.buffer.drain(..=end).collect();
+        let line = String::from_utf8(line)
+            .map_err(|_| SpecterError::Serialization("invalid utf8".to_string()))?;
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+fn parse_result(command: &SpecterCommand, line: &str) -> Result<SpecterResponse, SpecterError> {
+    if let Some(msg) = line.strip_prefix(SPECTER_ERROR_PREFIX) {
+        return Err(if msg == SPECTER_USER_CANCELLED {
+            SpecterError::Refused
+        } else {
+            SpecterError::Device(msg.to_string())
+        });
+    }
+    let serialization = |e: &dyn std::fmt::Display| SpecterError::Serialization(e.to_string());
+    match command {
+        SpecterCommand::Unlock => Ok(SpecterResponse::TaskDone),
+        SpecterCommand::GetMasterFingerprint => Fingerprint::from_str(line)
+            .map(SpecterResponse::MasterFingerprint)
+            .map_err(|e| serialization(&e)),
+        SpecterCommand::GetXpub(_) => Xpub::from_str(line)
+            .map(SpecterResponse::Xpub)
+            .map_err(|e| serialization(&e)),
+        SpecterCommand::SignPsbt(_) => {
+            let bytes = BASE64_STANDARD
+                .decode(line)
+                .map_err(|e| serialization(&e))?;
+            Psbt::deserialize(&bytes)
+                .map(|psbt| SpecterResponse::SignedPsbt(Box::new(psbt)))
+                .map_err(|e| serialization(&e))
+        }
+        SpecterCommand::DisplayAddress { .. } | SpecterCommand::DisplayDescriptorAddress(_) => {
+            Address::from_str(line)
+                .map(SpecterResponse::Address)
+                .map_err(|e| serialization(&e))
+        }
+    }
+}
+
+impl<C, T, R, E> Interpreter for SpecterInterpreter<C, T, R, E>
+where
+    C: TryInto<SpecterCommand>,
+    C::Error: Into<SpecterError>,
+    T: From<SpecterTransmit>,
+    R: From<SpecterResponse>,
+    E: From<SpecterError>,
+{
+    type Command = C;
+    type Transmit = T;
+    type Response = R;
+    type Error = E;
+
+    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        let command: SpecterCommand = command.try_into().map_err(Into::into)?;
+        let mut payload = command.line().into_bytes();
+        payload.push(b'\r');
+        self.buffer.clear();
+        self.state = State::WaitingAck(command);
+        Ok(SpecterTransmit { payload }.into())
+    }
+    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        self.buffer.extend(data);
+        while let Some(line) = self.next_line()? {
+            match std::mem::replace(&mut self.state, State::New) {
+                State::New => return Ok(None),
+                State::WaitingAck(command) => {
+                    if line != SPECTER_ACK {
+                        // The device refuses the unknown commands before
+                        // acknowledging them.
+                        parse_result(&command, &line)?;
+                        return Err(SpecterError::Serialization(line).into());
+                    }
+                    self.state = State::WaitingResult(command);
+                }
+                State::WaitingResult(command) => {
+                    self.response = Some(parse_result(&command, &line)?);
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(
+            SpecterTransmit {
+                payload: Vec::new(),
+            }
+            .into(),
+        ))
+    }
+    fn end(self) -> Result<Self::Response, Self::Error> {
+        self.response
+            .map(Self::Response::from)
+            .ok_or_else(|| SpecterError::NoErrorOrResult.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Specter =
+        SpecterInterpreter<SpecterCommand, SpecterTransmit, SpecterResponse, SpecterError>;
+
+    #[test]
+    fn test_get_xpub_in_several_reads() {
+        let mut specter = Specter::default();
+        let transmit = specter
+            .start(SpecterCommand::GetXpub("m/84'/1'/0'".parse().unwrap()))
+            .unwrap();
+        assert_eq!(transmit.payload, b"xpub m/84h/1h/0h\r");
+
+        let xpub = "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+        let transmit = specter.exchange(b"ACK\r\ntpub".to_vec()).unwrap().unwrap();
+        assert!(transmit.payload.is_empty());
+        let rest = format!("{}\r\n", &xpub[4..]);
+        assert!(specter.exchange(rest.into_bytes()).unwrap().is_none());
+        match specter.end().unwrap() {
+            SpecterResponse::Xpub(res) => assert_eq!(res.to_string(), xpub),
+            _ => panic!("expected an xpub"),
+        }
+
+        let mut specter = Specter::default();
+        specter
+            .start(SpecterCommand::SignPsbt(Box::new(
+                Psbt::from_str("cHNidP8BAAoCAAAAAAAAAAAAAAAA").unwrap(),
+            )))
+            .unwrap();
+        assert!(matches!(
+            specter.exchange(b"ACK\r\nerror: User cancelled\r\n".to_vec()),
+            Err(SpecterError::Refused)
+        ));
+    }
+
+    #[test]
+    fn test_display_address() {
+        use crate::common::Command;
+
+        let xpub = "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+        let command = SpecterCommand::try_from(Command::DisplayAddress {
+            path: "m/49'/1'/0'/0/2".parse().unwrap(),
+            policy: None,
+            hmac: None,
+        })
+        .unwrap();
+        assert_eq!(command.line(), "showaddr sh-wpkh m/49h/1h/0h/0/2");
+
+        let policy = crate::wallet::WalletPolicy::from_descriptor(&format!(
+            "wsh(sortedmulti(1,[f5acc2fd/48'/1'/0'/2']{}/<0;1>/*))",
+            xpub
+        ))
+        .unwrap();
+        let command = SpecterCommand::try_from(Command::DisplayAddress {
+            path: "m/48'/1'/0'/2'/1/3".parse().unwrap(),
+            policy: Some(policy),
+            hmac: None,
+        })
+        .unwrap();
+        assert_eq!(
+            command.line(),
+            format!(
+                "showdescaddr wsh(sortedmulti(1,[f5acc2fd/48'/1'/0'/2']{}/1/3))",
+                xpub
+            )
+        );
+    }
+}
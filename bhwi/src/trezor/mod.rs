@@ -0,0 +1,762 @@
+pub mod proto;
+pub mod sign;
+pub mod transport;
+
+use bitcoin::{
+    address::NetworkUnchecked,
+    base64::{prelude::BASE64_STANDARD, Engine as _},
+    bip32::{ChildNumber, DerivationPath, Fingerprint, Xpub},
+    Address, Network, Psbt, Txid,
+};
+
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use crate::devices::{Capabilities, NETWORKS};
+use crate::Interpreter;
+
+use proto::{Fields, Writer};
+
+/// Returns the capabilities of the Trezor, which spends single keys only.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        taproot: true,
+        musig2: false,
+        message_signing: true,
+        policy_registration: false,
+        max_inputs: None,
+        networks: NETWORKS.to_vec(),
+    }
+}
+
+#[derive(Debug)]
+pub enum TrezorError {
+    NoErrorOrResult,
+    Decoding(&'static str),
+    Serialization(String),
+    UnexpectedMessage(u16),
+    /// Failure message of the device.
+    Device(String),
+    /// The user refused the request on the device.
+    Refused,
+    /// The input is not spending a single key of the device.
+    UnsupportedInput(usize),
+    /// The input has a sighash type other than SIGHASH_ALL, the only one
+    /// signed by the device.
+    UnsupportedSighash(usize),
+    UnsupportedRequest(u64),
+    MissingPreviousTransaction(Txid),
+    /// The device does not support the command.
+    UnsupportedCommand(&'static str),
+}
+
+impl From<Infallible> for TrezorError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+pub enum TrezorCommand {
+    Initialize,
+    GetMasterFingerprint,
+    GetXpub {
+        path: DerivationPath,
+        display: bool,
+    },
+    /// Single signature address of the key at the path, the script type is
+    /// one of the `proto::SPEND_*` input script types.
+    GetAddress {
+        path: DerivationPath,
+        script_type: u64,
+        display: bool,
+    },
+    SignPsbt(Box<Psbt>),
+    SignMessage {
+        path: DerivationPath,
+        message: Vec<u8>,
+        script_type: u64,
+    },
+}
+
+pub enum TrezorResponse {
+    TaskDone,
+    MasterFingerprint(Fingerprint),
+    Xpub(Xpub),
+    Address(Address<NetworkUnchecked>),
+    SignedPsbt(Box<Psbt>),
+    MessageSignature {
+        address: String,
+        /// Recoverable signature of the message, base64 encoded.
+        signature: String,
+    },
+}
+
+/// Request of the device to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrezorPrompt {
+    /// The user must confirm on the device, the answer is empty.
+    Button { code: u64 },
+    /// The answer is the pin entered following the scrambled matrix displayed
+    /// by the device.
+    Pin { matrix: u64 },
+    /// The answer is the passphrase, empty for the default wallet.
+    Passphrase,
+}
+
+pub enum TrezorRecipient {
+    Device,
+    /// The prompt is relayed to the user, the answer is passed to the next
+    /// exchange.
+    User(TrezorPrompt),
+}
+
+pub struct TrezorTransmit {
+    pub recipient: TrezorRecipient,
+    /// The message with its header, empty for the user.
+    pub payload: Vec<u8>,
+}
+
+enum State {
+    New,
+    Running(TrezorCommand),
+    /// The device requests the parts of the psbt and returns its signatures,
+    /// once its master fingerprint is known.
+    Signing {
+        psbt: Box<Psbt>,
+        fingerprint: Option<Fingerprint>,
+    },
+    Finished(TrezorResponse),
+}
+
+pub struct TrezorInterpreter<C, T, R, E> {
+    network: Network,
+    state: State,
+    prompt: Option<TrezorPrompt>,
+    _marker: std::marker::PhantomData<(C, T, R, E)>,
+}
+
+impl<C, T, R, E> Default for TrezorInterpreter<C, T, R, E> {
+    fn default() -> Self {
+        Self {
+            network: Network::Bitcoin,
+            state: State::New,
+            prompt: None,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<C, T, R, E> TrezorInterpreter<C, T, R, E> {
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    fn coin_name(&self) -> &'static str {
+        match self.network {
+            Network::Bitcoin => "Bitcoin",
+            Network::Regtest => "Regtest",
+            _ => "Testnet",
+        }
+    }
+}
+
+fn request(msg_type: u16, payload: Vec<u8>) -> TrezorTransmit {
+    TrezorTransmit {
+        recipient: TrezorRecipient::Device,
+        payload: proto::message(msg_type, &payload),
+    }
+}
+
+/// The master key is not exported, its fingerprint is the parent
+/// fingerprint of its first hardened child.
+fn get_master_fingerprint(coin_name: &str) -> TrezorTransmit {
+    get_public_key(
+        &DerivationPath::from(vec![ChildNumber::Hardened { index: 0 }]),
+        coin_name,
+        false,
+    )
+}
+
+fn get_public_key(path: &DerivationPath, coin_name: &str, display: bool) -> TrezorTransmit {
+    request(
+        proto::GET_PUBLIC_KEY,
+        Writer::new()
+            .uints(1, path.to_u32_vec())
+            .bool(3, display)
+            .string(4, coin_name)
+            .finish(),
+    )
+}
+
+fn xpub(fields: &Fields) -> Result<Xpub, TrezorError> {
+    let xpub = fields
+        .string(2)
+        .ok_or(TrezorError::Decoding("missing xpub"))?;
+    Xpub::from_str(xpub).map_err(|e| TrezorError::Serialization(e.to_string()))
+}
+
+fn failure(fields: &Fields) -> TrezorError {
+    match fields.uint(1) {
+        Some(proto::FAILURE_ACTION_CANCELLED) | Some(proto::FAILURE_PIN_CANCELLED) => {
+            TrezorError::Refused
+        }
+        _ => TrezorError::Device(fields.string(2).unwrap_or_default().to_string()),
+    }
+}
+
+/// Returns the ack of the device to the answer of the user.
+fn answer(prompt: TrezorPrompt, data: Vec<u8>) -> Result<TrezorTransmit, TrezorError> {
+    let answer = String::from_utf8(data).map_err(|e| TrezorError::Serialization(e.to_string()))?;
+    Ok(match prompt {
+        TrezorPrompt::Button { .. } => request(proto::BUTTON_ACK, Vec::new()),
+        TrezorPrompt::Pin { .. } => request(
+            proto::PIN_MATRIX_ACK,
+            Writer::new().string(1, &answer).finish(),
+        ),
+        TrezorPrompt::Passphrase => request(
+            proto::PASSPHRASE_ACK,
+            Writer::new().string(1, &answer).finish(),
+        ),
+    })
+}
+
+impl<C, T, R, E> Interpreter for TrezorInterpreter<C, T, R, E>
+where
+    C: TryInto<TrezorCommand>,
+    C::Error: Into<TrezorError>,
+    T: From<TrezorTransmit>,
+    R: From<TrezorResponse>,
+    E: From<TrezorError>,
+{
+    type Command = C;
+    type Transmit = T;
+    type Response = R;
+    type Error = E;
+
+    fn start(&mut self, command: Self::Command) -> Result<Self::Transmit, Self::Error> {
+        let command: TrezorCommand = command.try_into().map_err(Into::into)?;
+        let req = match &command {
+            TrezorCommand::Initialize => request(proto::INITIALIZE, Vec::new()),
+            TrezorCommand::GetMasterFingerprint => get_master_fingerprint(self.coin_name()),
+            TrezorCommand::GetXpub { path, display } => {
+                get_public_key(path, self.coin_name(), *display)
+            }
+            TrezorCommand::GetAddress {
+                path,
+                script_type,
+                display,
+            } => request(
+                proto::GET_ADDRESS,
+                Writer::new()
+                    .uints(1, path.to_u32_vec())
+                    .string(2, self.coin_name())
+                    .bool(3, *display)
+                    .uint(5, *script_type)
+                    .finish(),
+            ),
+            TrezorCommand::SignPsbt(psbt) => {
+                if let Some(index) = psbt.inputs.iter().position(|input| {
+                    input
+                        .sighash_type
+                        .is_some_and(|ty| !matches!(ty.to_u32(), 0x00 | 0x01))
+                }) {
+                    return Err(TrezorError::UnsupportedSighash(index).into());
+                }
+                // The keys of the device are told apart by its fingerprint.
+                let req = get_master_fingerprint(self.coin_name());
+                if let TrezorCommand::SignPsbt(psbt) = command {
+                    self.state = State::Signing {
+                        psbt,
+                        fingerprint: None,
+                    };
+                }
+                return Ok(req.into());
+            }
+            TrezorCommand::SignMessage {
+                path,
+                message,
+                script_type,
+            } => request(
+                proto::SIGN_MESSAGE,
+                Writer::new()
+                    .uints(1, path.to_u32_vec())
+                    .bytes(2, message)
+                    .string(3, self.coin_name())
+                    .uint(4, *script_type)
+                    .finish(),
+            ),
+        };
+
+        self.state = State::Running(command);
+        Ok(req.into())
+    }
+    fn exchange(&mut self, data: Vec<u8>) -> Result<Option<Self::Transmit>, Self::Error> {
+        if let Some(prompt) = self.prompt.take() {
+            return Ok(Some(answer(prompt, data)?.into()));
+        }
+        if matches!(self.state, State::New | State::Finished(..)) {
+            return Ok(None);
+        }
+
+        let (msg_type, payload) = proto::parse_message(&data)?;
+        let fields = Fields::parse(payload)?;
+        let prompt = match msg_type {
+            proto::FAILURE => return Err(failure(&fields).into()),
+            proto::BUTTON_REQUEST => Some(TrezorPrompt::Button {
+                code: fields.uint(1).unwrap_or_default(),
+            }),
+            proto::PIN_MATRIX_REQUEST => Some(TrezorPrompt::Pin {
+                matrix: fields.uint(1).unwrap_or_default(),
+            }),
+            proto::PASSPHRASE_REQUEST => Some(TrezorPrompt::Passphrase),
+            _ => None,
+        };
+        if let Some(prompt) = prompt {
+            self.prompt = Some(prompt.clone());
+            return Ok(Some(
+                TrezorTransmit {
+                    recipient: TrezorRecipient::User(prompt),
+                    payload: Vec::new(),
+                }
+                .into(),
+            ));
+        }
+
+        let coin_name = self.coin_name();
+        let response = match (&mut self.state, msg_type) {
+            (State::Running(TrezorCommand::Initialize), proto::FEATURES) => {
+                TrezorResponse::TaskDone
+            }
+            (State::Running(TrezorCommand::GetMasterFingerprint), proto::PUBLIC_KEY) => {
+                TrezorResponse::MasterFingerprint(xpub(&fields)?.parent_fingerprint)
+            }
+            (State::Running(TrezorCommand::GetXpub { .. }), proto::PUBLIC_KEY) => {
+                TrezorResponse::Xpub(xpub(&fields)?)
+            }
+            (State::Running(TrezorCommand::GetAddress { .. }), proto::ADDRESS) => {
+                let address = fields
+                    .string(1)
+                    .ok_or(TrezorError::Decoding("missing address"))?;
+                TrezorResponse::Address(
+                    Address::from_str(address)
+                        .map_err(|e| TrezorError::Serialization(e.to_string()))?,
+                )
+            }
+            (State::Running(TrezorCommand::SignMessage { .. }), proto::MESSAGE_SIGNATURE) => {
+                TrezorResponse::MessageSignature {
+                    address: fields.string(1).unwrap_or_default().to_string(),
+                    signature: BASE64_STANDARD.encode(
+                        fields
+                            .bytes(2)
+                            .ok_or(TrezorError::Decoding("missing signature"))?,
+                    ),
+                }
+            }
+            (
+                State::Signing {
+                    psbt,
+                    fingerprint: fingerprint @ None,
+                },
+                proto::PUBLIC_KEY,
+            ) => {
+                *fingerprint = Some(xpub(&fields)?.parent_fingerprint);
+                return Ok(Some(
+                    TrezorTransmit {
+                        recipient: TrezorRecipient::Device,
+                        payload: sign::sign_tx(psbt, coin_name),
+                    }
+                    .into(),
+                ));
+            }
+            (
+                State::Signing {
+                    psbt,
+                    fingerprint: Some(fingerprint),
+                },
+                proto::TX_REQUEST,
+            ) => {
+                let fingerprint = *fingerprint;
+                if let Some(serialized) = fields.message(3)? {
+                    if let (Some(index), Some(signature)) =
+                        (serialized.uint(1), serialized.bytes(2))
+                    {
+                        sign::add_signature(psbt, fingerprint, index as usize, signature)?;
+                    }
+                }
+                let request_type = fields
+                    .uint(1)
+                    .ok_or(TrezorError::Decoding("missing request type"))?;
+                if request_type != proto::TX_FINISHED {
+                    let details = fields.message(2)?.unwrap_or_default();
+                    let ack =
+                        sign::tx_ack(psbt, self.network, fingerprint, request_type, &details)?;
+                    return Ok(Some(
+                        TrezorTransmit {
+                            recipient: TrezorRecipient::Device,
+                            payload: ack,
+                        }
+                        .into(),
+                    ));
+                }
+                TrezorResponse::SignedPsbt(psbt.clone())
+            }
+            (_, msg_type) => return Err(TrezorError::UnexpectedMessage(msg_type).into()),
+        };
+        self.state = State::Finished(response);
+        Ok(None)
+    }
+    fn end(self) -> Result<Self::Response, Self::Error> {
+        if let State::Finished(res) = self.state {
+            Ok(Self::Response::from(res))
+        } else {
+            Err(TrezorError::NoErrorOrResult.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime,
+        bip32::Xpriv,
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1},
+        transaction::Version,
+        Amount, CompressedPublicKey, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+        Witness,
+    };
+
+    type Trezor = TrezorInterpreter<TrezorCommand, TrezorTransmit, TrezorResponse, TrezorError>;
+
+    fn device_message(transmit: &TrezorTransmit) -> (u16, Vec<u8>) {
+        assert!(matches!(transmit.recipient, TrezorRecipient::Device));
+        let (msg_type, payload) = proto::parse_message(&transmit.payload).unwrap();
+        (msg_type, payload.to_vec())
+    }
+
+    fn tx_request(
+        request_type: u64,
+        index: u64,
+        hash: Option<Txid>,
+        signature: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut details = Writer::new().uint(1, index);
+        if let Some(txid) = hash {
+            let mut hash = txid.to_byte_array();
+            hash.reverse();
+            details = details.bytes(2, &hash);
+        }
+        let mut writer = Writer::new()
+            .uint(1, request_type)
+            .bytes(2, &details.finish());
+        if let Some(signature) = signature {
+            writer = writer.bytes(3, &Writer::new().uint(1, 0).bytes(2, signature).finish());
+        }
+        proto::message(proto::TX_REQUEST, &writer.finish())
+    }
+
+    #[test]
+    fn test_get_xpub_with_prompts() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let expected = Xpub::from_priv(&secp, &xpriv.derive_priv(&secp, &path).unwrap());
+
+        let mut trezor = Trezor::default().with_network(Network::Testnet);
+        let transmit = trezor
+            .start(TrezorCommand::GetXpub {
+                path: path.clone(),
+                display: true,
+            })
+            .unwrap();
+        let (msg_type, payload) = device_message(&transmit);
+        assert_eq!(msg_type, proto::GET_PUBLIC_KEY);
+        let fields = Fields::parse(&payload).unwrap();
+        assert_eq!(fields.uint(3), Some(1));
+        assert_eq!(fields.string(4), Some("Testnet"));
+
+        let transmit = trezor
+            .exchange(proto::message(
+                proto::PIN_MATRIX_REQUEST,
+                &Writer::new().uint(1, 1).finish(),
+            ))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            transmit.recipient,
+            TrezorRecipient::User(TrezorPrompt::Pin { matrix: 1 })
+        ));
+        let transmit = trezor.exchange(b"1234".to_vec()).unwrap().unwrap();
+        let (msg_type, payload) = device_message(&transmit);
+        assert_eq!(msg_type, proto::PIN_MATRIX_ACK);
+        assert_eq!(Fields::parse(&payload).unwrap().string(1), Some("1234"));
+
+        let transmit = trezor
+            .exchange(proto::message(
+                proto::BUTTON_REQUEST,
+                &Writer::new().uint(1, 8).finish(),
+            ))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            transmit.recipient,
+            TrezorRecipient::User(TrezorPrompt::Button { code: 8 })
+        ));
+        let transmit = trezor.exchange(Vec::new()).unwrap().unwrap();
+        assert_eq!(device_message(&transmit).0, proto::BUTTON_ACK);
+
+        assert!(trezor
+            .exchange(proto::message(
+                proto::PUBLIC_KEY,
+                &Writer::new().string(2, &expected.to_string()).finish(),
+            ))
+            .unwrap()
+            .is_none());
+        match trezor.end().unwrap() {
+            TrezorResponse::Xpub(xpub) => assert_eq!(xpub, expected),
+            _ => panic!("expected an xpub"),
+        }
+    }
+
+    #[test]
+    fn test_sign_psbt() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[0x01; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap();
+        let key = xpriv.derive_priv(&secp, &path).unwrap().private_key;
+        let pubkey = key.public_key(&secp);
+        let script_pubkey = ScriptBuf::new_p2wpkh(&CompressedPublicKey(pubkey).wpubkey_hash());
+
+        let prev_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 3),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+        let prev_txid = prev_tx.compute_txid();
+
+        // Key of a cosigner, spending its own input and receiving an output.
+        let other = Xpriv::new_master(Network::Testnet, &[0x02; 32]).unwrap();
+        let other_key = other
+            .derive_priv(&secp, &path)
+            .unwrap()
+            .private_key
+            .public_key(&secp);
+        let other_script = ScriptBuf::new_p2wpkh(&CompressedPublicKey(other_key).wpubkey_hash());
+        let other_origin = (other.fingerprint(&secp), path.clone());
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    previous_output: OutPoint::new(prev_txid, 0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                },
+                TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), 1),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                },
+            ],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(90_000),
+                    script_pubkey: script_pubkey.clone(),
+                },
+                TxOut {
+                    value: Amount::from_sat(50_000),
+                    script_pubkey: other_script.clone(),
+                },
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(prev_tx.output[0].clone());
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(pubkey, (xpriv.fingerprint(&secp), path.clone()));
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(other_key, other_origin.clone());
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: Amount::from_sat(60_000),
+            script_pubkey: other_script.clone(),
+        });
+        psbt.inputs[1]
+            .bip32_derivation
+            .insert(other_key, other_origin.clone());
+        psbt.inputs[1].final_script_witness = Some(Witness::from_slice(&[[0x01; 72]]));
+        psbt.outputs[0]
+            .bip32_derivation
+            .insert(pubkey, (xpriv.fingerprint(&secp), path.clone()));
+        psbt.outputs[1]
+            .bip32_derivation
+            .insert(other_key, other_origin);
+
+        let mut trezor = Trezor::default().with_network(Network::Testnet);
+        let transmit = trezor
+            .start(TrezorCommand::SignPsbt(Box::new(psbt.clone())))
+            .unwrap();
+        assert_eq!(device_message(&transmit).0, proto::GET_PUBLIC_KEY);
+        let child = Xpub::from_priv(
+            &secp,
+            &xpriv
+                .derive_priv(&secp, &[ChildNumber::Hardened { index: 0 }])
+                .unwrap(),
+        );
+        let transmit = trezor
+            .exchange(proto::message(
+                proto::PUBLIC_KEY,
+                &Writer::new().string(2, &child.to_string()).finish(),
+            ))
+            .unwrap()
+            .unwrap();
+        let (msg_type, payload) = device_message(&transmit);
+        assert_eq!(msg_type, proto::SIGN_TX);
+        let fields = Fields::parse(&payload).unwrap();
+        assert_eq!((fields.uint(1), fields.uint(2)), (Some(2), Some(2)));
+
+        let ack = |trezor: &mut Trezor, request: Vec<u8>| {
+            let transmit = trezor.exchange(request).unwrap().unwrap();
+            let (msg_type, payload) = device_message(&transmit);
+            assert_eq!(msg_type, proto::TX_ACK);
+            payload
+        };
+        let nested = |payload: &[u8], field: u64| {
+            Fields::parse(payload)
+                .unwrap()
+                .message(1)
+                .unwrap()
+                .unwrap()
+                .bytes(field)
+                .unwrap()
+                .to_vec()
+        };
+
+        let input = nested(
+            &ack(&mut trezor, tx_request(proto::TX_INPUT, 0, None, None)),
+            2,
+        );
+        let input = Fields::parse(&input).unwrap();
+        assert_eq!(input.uint(6), Some(proto::SPEND_WITNESS));
+        assert_eq!(input.uint(8), Some(100_000));
+        assert_eq!(input.uint(5), Some(0xffff_fffd));
+
+        let external = nested(
+            &ack(&mut trezor, tx_request(proto::TX_INPUT, 1, None, None)),
+            2,
+        );
+        let external = Fields::parse(&external).unwrap();
+        assert_eq!(external.uint(6), Some(proto::SPEND_EXTERNAL));
+        assert_eq!(external.uint(1), None);
+        assert_eq!(external.bytes(19), Some(other_script.as_bytes()));
+        assert_eq!(external.bytes(13).map(<[u8]>::len), Some(1 + 1 + 72));
+
+        let meta = ack(
+            &mut trezor,
+            tx_request(proto::TX_META, 0, Some(prev_txid), None),
+        );
+        let meta = Fields::parse(&meta).unwrap().message(1).unwrap().unwrap();
+        assert_eq!((meta.uint(6), meta.uint(7)), (Some(1), Some(1)));
+
+        let prev_input = nested(
+            &ack(
+                &mut trezor,
+                tx_request(proto::TX_INPUT, 0, Some(prev_txid), None),
+            ),
+            2,
+        );
+        assert_eq!(Fields::parse(&prev_input).unwrap().uint(3), Some(3));
+
+        let prev_output = nested(
+            &ack(
+                &mut trezor,
+                tx_request(proto::TX_OUTPUT, 0, Some(prev_txid), None),
+            ),
+            3,
+        );
+        assert_eq!(
+            Fields::parse(&prev_output).unwrap().bytes(2),
+            Some(script_pubkey.as_bytes())
+        );
+
+        let output = nested(
+            &ack(&mut trezor, tx_request(proto::TX_OUTPUT, 0, None, None)),
+            5,
+        );
+        let output = Fields::parse(&output).unwrap();
+        assert_eq!(output.uint(4), Some(proto::PAY_TO_WITNESS));
+        assert_eq!(output.uint(3), Some(90_000));
+
+        // The key of the cosigner is not sent as change.
+        let output = nested(
+            &ack(&mut trezor, tx_request(proto::TX_OUTPUT, 1, None, None)),
+            5,
+        );
+        let output = Fields::parse(&output).unwrap();
+        assert_eq!(output.uint(4), Some(proto::PAY_TO_ADDRESS));
+        assert_eq!(output.uint(2), None);
+        assert!(output.string(1).is_some());
+        assert!(trezor
+            .exchange(tx_request(proto::TX_OUTPUT, 2, None, None))
+            .is_err());
+
+        let signature = secp.sign_ecdsa(&Message::from_digest([0x42; 32]), &key);
+        assert!(trezor
+            .exchange(tx_request(
+                proto::TX_FINISHED,
+                0,
+                None,
+                Some(&signature.serialize_der()),
+            ))
+            .unwrap()
+            .is_none());
+        match trezor.end().unwrap() {
+            TrezorResponse::SignedPsbt(signed) => {
+                assert_eq!(signed.inputs[0].partial_sigs.len(), 1);
+                let sig = signed.inputs[0]
+                    .partial_sigs
+                    .get(&bitcoin::PublicKey::new(pubkey))
+                    .unwrap();
+                assert_eq!(sig.signature, signature);
+            }
+            _ => panic!("expected a signed psbt"),
+        }
+    }
+
+    #[test]
+    fn test_refused() {
+        let mut trezor = Trezor::default();
+        trezor.start(TrezorCommand::Initialize).unwrap();
+        let res = trezor.exchange(proto::message(
+            proto::FAILURE,
+            &Writer::new()
+                .uint(1, proto::FAILURE_ACTION_CANCELLED)
+                .string(2, "Cancelled")
+                .finish(),
+        ));
+        assert!(matches!(res, Err(TrezorError::Refused)));
+
+        let mut trezor = Trezor::default();
+        trezor.start(TrezorCommand::Initialize).unwrap();
+        let res = trezor.exchange(proto::message(proto::SUCCESS, &[]));
+        assert!(matches!(
+            res,
+            Err(TrezorError::UnexpectedMessage(proto::SUCCESS))
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+//! Minimal protobuf encoding of the Trezor messages.
+//!
+//! A message is sent as its type and the length of its payload, both big
+//! endian, followed by the payload: the protobuf fields of the message. Only
+//! the varint and length delimited wire types are used by the messages below.
+
+use super::TrezorError;
+
+pub const INITIALIZE: u16 = 0;
+pub const SUCCESS: u16 = 2;
+pub const FAILURE: u16 = 3;
+pub const GET_PUBLIC_KEY: u16 = 11;
+pub const PUBLIC_KEY: u16 = 12;
+pub const SIGN_TX: u16 = 15;
+pub const FEATURES: u16 = 17;
+pub const PIN_MATRIX_REQUEST: u16 = 18;
+pub const PIN_MATRIX_ACK: u16 = 19;
+pub const TX_REQUEST: u16 = 21;
+pub const TX_ACK: u16 = 22;
+pub const BUTTON_REQUEST: u16 = 26;
+pub const BUTTON_ACK: u16 = 27;
+pub const GET_ADDRESS: u16 = 29;
+pub const ADDRESS: u16 = 30;
+pub const SIGN_MESSAGE: u16 = 38;
+pub const MESSAGE_SIGNATURE: u16 = 40;
+pub const PASSPHRASE_REQUEST: u16 = 41;
+pub const PASSPHRASE_ACK: u16 = 42;
+
+/// Failure codes of the user cancelling the request.
+pub const FAILURE_ACTION_CANCELLED: u64 = 4;
+pub const FAILURE_PIN_CANCELLED: u64 = 6;
+
+/// Script types of the inputs.
+pub const SPEND_ADDRESS: u64 = 0;
+/// Input of another signer, identified by its script.
+pub const SPEND_EXTERNAL: u64 = 2;
+pub const SPEND_WITNESS: u64 = 3;
+pub const SPEND_P2SH_WITNESS: u64 = 4;
+pub const SPEND_TAPROOT: u64 = 5;
+
+/// Script types of the outputs.
+pub const PAY_TO_ADDRESS: u64 = 0;
+pub const PAY_TO_OP_RETURN: u64 = 3;
+pub const PAY_TO_WITNESS: u64 = 4;
+pub const PAY_TO_P2SH_WITNESS: u64 = 5;
+pub const PAY_TO_TAPROOT: u64 = 6;
+
+/// Request types of the `TxRequest` sent during the signature.
+pub const TX_INPUT: u64 = 0;
+pub const TX_OUTPUT: u64 = 1;
+pub const TX_META: u64 = 2;
+pub const TX_FINISHED: u64 = 3;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+const HEADER_SIZE: usize = 6;
+
+/// Returns the message with its header.
+pub fn message(msg_type: u16, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.extend(msg_type.to_be_bytes());
+    data.extend((payload.len() as u32).to_be_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Returns the type and the payload of the message.
+pub fn parse_message(data: &[u8]) -> Result<(u16, &[u8]), TrezorError> {
+    if data.len() < HEADER_SIZE {
+        return Err(TrezorError::Decoding("incomplete header"));
+    }
+    let msg_type = u16::from_be_bytes([data[0], data[1]]);
+    let len = u32::from_be_bytes([data[2], data[3], data[4], data[5]]) as usize;
+    data[HEADER_SIZE..]
+        .get(..len)
+        .map(|payload| (msg_type, payload))
+        .ok_or(TrezorError::Decoding("incomplete payload"))
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> Result<u64, TrezorError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*offset)
+            .ok_or(TrezorError::Decoding("truncated varint"))?;
+        *offset += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(TrezorError::Decoding("varint overflow"))
+}
+
+/// Writes the fields of a message payload.
+#[derive(Debug, Default)]
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn uint(mut self, field: u64, value: u64) -> Self {
+        write_varint(&mut self.0, (field << 3) | WIRE_VARINT);
+        write_varint(&mut self.0, value);
+        self
+    }
+
+    pub fn bool(self, field: u64, value: bool) -> Self {
+        self.uint(field, value as u64)
+    }
+
+    /// Writes each value as a field, for the repeated fields.
+    pub fn uints(self, field: u64, values: impl IntoIterator<Item = u32>) -> Self {
+        values
+            .into_iter()
+            .fold(self, |w, value| w.uint(field, value as u64))
+    }
+
+    pub fn bytes(mut self, field: u64, value: &[u8]) -> Self {
+        write_varint(&mut self.0, (field << 3) | WIRE_LEN);
+        write_varint(&mut self.0, value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn string(self, field: u64, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Value of a decoded field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Uint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Fields of a decoded message payload, in the order of the payload.
+#[derive(Debug, Default)]
+pub struct Fields<'a>(Vec<(u64, Value<'a>)>);
+
+impl<'a> Fields<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, TrezorError> {
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let key = read_varint(data, &mut offset)?;
+            let value = match key & 0x07 {
+                WIRE_VARINT => Value::Uint(read_varint(data, &mut offset)?),
+                WIRE_LEN => {
+                    let len = read_varint(data, &mut offset)? as usize;
+                    let value = offset
+                        .checked_add(len)
+                        .and_then(|end| data.get(offset..end))
+                        .ok_or(TrezorError::Decoding("truncated field"))?;
+                    offset += len;
+                    Value::Bytes(value)
+                }
+                _ => return Err(TrezorError::Decoding("unsupported wire type")),
+            };
+            fields.push((key >> 3, value));
+        }
+        Ok(Self(fields))
+    }
+
+    fn get(&self, field: u64) -> Option<&Value<'a>> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(f, _)| *f == field)
+            .map(|(_, v)| v)
+    }
+
+    pub fn uint(&self, field: u64) -> Option<u64> {
+        match self.get(field) {
+            Some(Value::Uint(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self, field: u64) -> Option<&'a [u8]> {
+        match self.get(field) {
+            Some(Value::Bytes(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self, field: u64) -> Option<&'a str> {
+        self.bytes(field)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// Decodes the embedded message of the field.
+    pub fn message(&self, field: u64) -> Result<Option<Fields<'a>>, TrezorError> {
+        self.bytes(field).map(Fields::parse).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let payload = Writer::new()
+            .uints(1, [0x8000_002c, 0])
+            .string(2, "Testnet")
+            .bool(3, true)
+            .bytes(4, &Writer::new().uint(1, 300).finish())
+            .finish();
+        assert_eq!(
+            payload[..8],
+            [0x08, 0xac, 0x80, 0x80, 0x80, 0x08, 0x08, 0x00]
+        );
+        let fields = Fields::parse(&payload).unwrap();
+        assert_eq!(fields.uint(1), Some(0));
+        assert_eq!(fields.string(2), Some("Testnet"));
+        assert_eq!(fields.uint(3), Some(1));
+        assert_eq!(fields.message(4).unwrap().unwrap().uint(1), Some(300));
+        assert_eq!(fields.uint(5), None);
+
+        let msg = message(GET_PUBLIC_KEY, &payload);
+        assert_eq!(parse_message(&msg).unwrap(), (GET_PUBLIC_KEY, &payload[..]));
+        assert!(parse_message(&msg[..msg.len() - 1]).is_err());
+        assert!(Fields::parse(&[0x12, 0x05, 0x00]).is_err());
+    }
+}
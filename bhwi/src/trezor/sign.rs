@@ -0,0 +1,333 @@
+//! Signature of a psbt with the `SignTx` flow: the device requests the
+//! inputs, the outputs and the previous transactions one by one with
+//! `TxRequest` messages, answered with `TxAck` messages, and returns the
+//! signatures of the inputs along the way.
+//!
+//! Only the keys with the master fingerprint of the device are its own: the
+//! inputs and outputs without one of them are sent as external inputs and
+//! addresses.
+
+use std::collections::BTreeMap;
+
+use bitcoin::{
+    bip32::{DerivationPath, Fingerprint, KeySource},
+    consensus, ecdsa,
+    hashes::Hash,
+    script::Instruction,
+    secp256k1, taproot, Address, CompressedPublicKey, EcdsaSighashType, Network, Psbt, PublicKey,
+    ScriptBuf, Transaction, Txid,
+};
+
+use super::{
+    proto::{self, Fields, Writer},
+    TrezorError,
+};
+
+/// Returns the `SignTx` message starting the signature.
+pub fn sign_tx(psbt: &Psbt, coin_name: &str) -> Vec<u8> {
+    let tx = &psbt.unsigned_tx;
+    proto::message(
+        proto::SIGN_TX,
+        &Writer::new()
+            .uint(1, tx.output.len() as u64)
+            .uint(2, tx.input.len() as u64)
+            .string(3, coin_name)
+            .uint(4, tx.version.0 as u64)
+            .uint(5, tx.lock_time.to_consensus_u32() as u64)
+            .finish(),
+    )
+}
+
+/// Hashes are sent in the order they are displayed.
+fn display_hash(txid: &Txid) -> Vec<u8> {
+    let mut hash = txid.to_byte_array().to_vec();
+    hash.reverse();
+    hash
+}
+
+/// Returns the `TxAck` answering the request of the device, the details are
+/// the index of the requested item and the hash of its previous transaction.
+pub fn tx_ack(
+    psbt: &Psbt,
+    network: Network,
+    fingerprint: Fingerprint,
+    request_type: u64,
+    details: &Fields,
+) -> Result<Vec<u8>, TrezorError> {
+    let index = details.uint(1).unwrap_or(0) as usize;
+    let tx = match details.bytes(2) {
+        Some(hash) => Some(previous_transaction(psbt, hash)?),
+        None => None,
+    };
+    let wrapper = match (request_type, tx) {
+        (proto::TX_INPUT, None) => Writer::new().bytes(2, &input(psbt, fingerprint, index)?),
+        (proto::TX_OUTPUT, None) => {
+            Writer::new().bytes(5, &output(psbt, network, fingerprint, index)?)
+        }
+        (proto::TX_META, Some(tx)) => {
+            let ack = Writer::new()
+                .uint(1, tx.version.0 as u64)
+                .uint(4, tx.lock_time.to_consensus_u32() as u64)
+                .uint(6, tx.input.len() as u64)
+                .uint(7, tx.output.len() as u64)
+                .finish();
+            return Ok(proto::message(
+                proto::TX_ACK,
+                &Writer::new().bytes(1, &ack).finish(),
+            ));
+        }
+        (proto::TX_INPUT, Some(tx)) => {
+            let txin = tx
+                .input
+                .get(index)
+                .ok_or(TrezorError::Decoding("previous input index"))?;
+            Writer::new().bytes(
+                2,
+                &Writer::new()
+                    .bytes(2, &display_hash(&txin.previous_output.txid))
+                    .uint(3, txin.previous_output.vout as u64)
+                    .bytes(4, txin.script_sig.as_bytes())
+                    .uint(5, txin.sequence.0 as u64)
+                    .finish(),
+            )
+        }
+        (proto::TX_OUTPUT, Some(tx)) => {
+            let txout = tx
+                .output
+                .get(index)
+                .ok_or(TrezorError::Decoding("previous output index"))?;
+            Writer::new().bytes(
+                3,
+                &Writer::new()
+                    .uint(1, txout.value.to_sat())
+                    .bytes(2, txout.script_pubkey.as_bytes())
+                    .finish(),
+            )
+        }
+        (request_type, _) => return Err(TrezorError::UnsupportedRequest(request_type)),
+    };
+    Ok(proto::message(
+        proto::TX_ACK,
+        &Writer::new().bytes(1, &wrapper.finish()).finish(),
+    ))
+}
+
+fn previous_transaction<'a>(psbt: &'a Psbt, hash: &[u8]) -> Result<&'a Transaction, TrezorError> {
+    let mut hash = hash.to_vec();
+    hash.reverse();
+    let txid = Txid::from_slice(&hash).map_err(|_| TrezorError::Decoding("transaction hash"))?;
+    psbt.inputs
+        .iter()
+        .filter_map(|input| input.non_witness_utxo.as_ref())
+        .find(|tx| tx.compute_txid() == txid)
+        .ok_or(TrezorError::MissingPreviousTransaction(txid))
+}
+
+/// Key of the device in an input or an output.
+enum DeviceKey<'a> {
+    Ecdsa(&'a secp256k1::PublicKey, &'a DerivationPath),
+    /// Key spending the taproot output without script.
+    Taproot(&'a secp256k1::XOnlyPublicKey, &'a DerivationPath),
+}
+
+impl DeviceKey<'_> {
+    fn path(&self) -> &DerivationPath {
+        match self {
+            Self::Ecdsa(_, path) | Self::Taproot(_, path) => path,
+        }
+    }
+}
+
+/// Returns the single key of the device with the fingerprint, None if the
+/// input or the output has none or several of them.
+fn device_key<'a>(
+    fingerprint: Fingerprint,
+    bip32_derivation: &'a BTreeMap<secp256k1::PublicKey, KeySource>,
+    tap_key_origins: &'a BTreeMap<
+        secp256k1::XOnlyPublicKey,
+        (Vec<taproot::TapLeafHash>, KeySource),
+    >,
+) -> Option<DeviceKey<'a>> {
+    let mut keys = bip32_derivation
+        .iter()
+        .filter(|(_, (fg, _))| *fg == fingerprint)
+        .map(|(key, (_, path))| DeviceKey::Ecdsa(key, path))
+        .chain(
+            tap_key_origins
+                .iter()
+                .filter(|(_, (leaves, (fg, _)))| leaves.is_empty() && *fg == fingerprint)
+                .map(|(key, (_, (_, path)))| DeviceKey::Taproot(key, path)),
+        );
+    match (keys.next(), keys.next()) {
+        (Some(key), None) => Some(key),
+        _ => None,
+    }
+}
+
+fn input(psbt: &Psbt, fingerprint: Fingerprint, index: usize) -> Result<Vec<u8>, TrezorError> {
+    let txin = psbt
+        .unsigned_tx
+        .input
+        .get(index)
+        .ok_or(TrezorError::Decoding("input index"))?;
+    let input = psbt
+        .inputs
+        .get(index)
+        .ok_or(TrezorError::Decoding("input index"))?;
+    let vout = txin.previous_output.vout as usize;
+    let utxo = match (&input.witness_utxo, &input.non_witness_utxo) {
+        (Some(utxo), _) => utxo,
+        (None, Some(tx)) => tx
+            .output
+            .get(vout)
+            .ok_or(TrezorError::UnsupportedInput(index))?,
+        (None, None) => return Err(TrezorError::UnsupportedInput(index)),
+    };
+    let writer = Writer::new()
+        .bytes(2, &display_hash(&txin.previous_output.txid))
+        .uint(3, vout as u64)
+        .uint(5, txin.sequence.0 as u64)
+        .uint(8, utxo.value.to_sat());
+    let Some(key) = device_key(fingerprint, &input.bip32_derivation, &input.tap_key_origins) else {
+        // Input of another signer, with its signatures if finalized.
+        let mut writer = writer
+            .uint(6, proto::SPEND_EXTERNAL)
+            .bytes(19, utxo.script_pubkey.as_bytes());
+        if let Some(script_sig) = &input.final_script_sig {
+            writer = writer.bytes(4, script_sig.as_bytes());
+        }
+        if let Some(witness) = &input.final_script_witness {
+            writer = writer.bytes(13, &consensus::serialize(witness));
+        }
+        return Ok(writer.finish());
+    };
+    let script_type = if utxo.script_pubkey.is_p2wpkh() {
+        proto::SPEND_WITNESS
+    } else if utxo.script_pubkey.is_p2tr() {
+        proto::SPEND_TAPROOT
+    } else if utxo.script_pubkey.is_p2pkh() {
+        proto::SPEND_ADDRESS
+    } else if utxo.script_pubkey.is_p2sh()
+        && input.redeem_script.as_ref().is_some_and(|s| s.is_p2wpkh())
+    {
+        proto::SPEND_P2SH_WITNESS
+    } else {
+        return Err(TrezorError::UnsupportedInput(index));
+    };
+    Ok(writer
+        .uints(1, key.path().to_u32_vec())
+        .uint(6, script_type)
+        .finish())
+}
+
+/// Returns the script type of the change output, None if the script is not
+/// the single key script of the key of the device.
+fn change_type(script: &ScriptBuf, output: &bitcoin::psbt::Output, key: &DeviceKey) -> Option<u64> {
+    match key {
+        DeviceKey::Ecdsa(key, _) => {
+            let p2wpkh = ScriptBuf::new_p2wpkh(&CompressedPublicKey(**key).wpubkey_hash());
+            if *script == p2wpkh {
+                Some(proto::PAY_TO_WITNESS)
+            } else if output.redeem_script.as_ref() == Some(&p2wpkh)
+                && *script == ScriptBuf::new_p2sh(&p2wpkh.script_hash())
+            {
+                Some(proto::PAY_TO_P2SH_WITNESS)
+            } else {
+                None
+            }
+        }
+        DeviceKey::Taproot(key, _) => {
+            let internal_key = output.tap_internal_key.as_ref() == Some(*key);
+            (internal_key && output.tap_tree.is_none() && script.is_p2tr())
+                .then_some(proto::PAY_TO_TAPROOT)
+        }
+    }
+}
+
+fn output(
+    psbt: &Psbt,
+    network: Network,
+    fingerprint: Fingerprint,
+    index: usize,
+) -> Result<Vec<u8>, TrezorError> {
+    let txout = psbt
+        .unsigned_tx
+        .output
+        .get(index)
+        .ok_or(TrezorError::Decoding("output index"))?;
+    let output = psbt
+        .outputs
+        .get(index)
+        .ok_or(TrezorError::Decoding("output index"))?;
+    let script = &txout.script_pubkey;
+    let writer = Writer::new().uint(3, txout.value.to_sat());
+    let change = device_key(
+        fingerprint,
+        &output.bip32_derivation,
+        &output.tap_key_origins,
+    )
+    .and_then(|key| Some((change_type(script, output, &key)?, key)));
+    let writer = match change {
+        Some((script_type, key)) => writer
+            .uints(2, key.path().to_u32_vec())
+            .uint(4, script_type),
+        _ if script.is_op_return() => {
+            let data: Vec<u8> = script
+                .instructions()
+                .filter_map(|ins| match ins {
+                    Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_vec()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .concat();
+            writer.uint(4, proto::PAY_TO_OP_RETURN).bytes(6, &data)
+        }
+        _ => {
+            let address = Address::from_script(script, network)
+                .map_err(|e| TrezorError::Serialization(e.to_string()))?;
+            writer
+                .string(1, &address.to_string())
+                .uint(4, proto::PAY_TO_ADDRESS)
+        }
+    };
+    Ok(writer.finish())
+}
+
+/// Adds the signature returned by the device to the input, for the key of
+/// the device.
+pub fn add_signature(
+    psbt: &mut Psbt,
+    fingerprint: Fingerprint,
+    index: usize,
+    signature: &[u8],
+) -> Result<(), TrezorError> {
+    let input = psbt
+        .inputs
+        .get_mut(index)
+        .ok_or(TrezorError::Decoding("signature index"))?;
+    match device_key(fingerprint, &input.bip32_derivation, &input.tap_key_origins)
+        .ok_or(TrezorError::UnsupportedInput(index))?
+    {
+        DeviceKey::Taproot(..) => {
+            let signature = secp256k1::schnorr::Signature::from_slice(signature)
+                .map_err(|_| TrezorError::Decoding("schnorr signature"))?;
+            input.tap_key_sig = Some(taproot::Signature {
+                signature,
+                sighash_type: bitcoin::TapSighashType::Default,
+            });
+        }
+        DeviceKey::Ecdsa(key, _) => {
+            let key = PublicKey::new(*key);
+            let signature = secp256k1::ecdsa::Signature::from_der(signature)
+                .map_err(|_| TrezorError::Decoding("ecdsa signature"))?;
+            input.partial_sigs.insert(
+                key,
+                ecdsa::Signature {
+                    signature,
+                    sighash_type: EcdsaSighashType::All,
+                },
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! HID framing of the messages exchanged with Trezor devices.
+//!
+//! The message is prefixed by the magic `##` and split in reports of 64
+//! bytes, each starting with the marker `?`. The answer is reassembled from
+//! the reports read the same way, its length is read from the header.
+
+pub const REPORT_MARKER: u8 = b'?';
+pub const MAGIC: &[u8; 2] = b"##";
+pub const PACKET_SIZE: usize = 64;
+/// Size of the type and the length of the message.
+const HEADER_SIZE: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingError {
+    IncompleteHeader,
+    InvalidMarker,
+    InvalidMagic,
+}
+
+/// Returns the reports to write to the device for the message.
+pub fn frame(message: &[u8]) -> Vec<Vec<u8>> {
+    let mut data = Vec::with_capacity(message.len() + MAGIC.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(message);
+    data.chunks(PACKET_SIZE - 1)
+        .map(|chunk| {
+            let mut report = Vec::with_capacity(PACKET_SIZE);
+            report.push(REPORT_MARKER);
+            report.extend_from_slice(chunk);
+            report.resize(PACKET_SIZE, 0x00);
+            report
+        })
+        .collect()
+}
+
+/// Reassembles the answer from the reports read from the device.
+#[derive(Debug, Default)]
+pub struct Unframer {
+    expected_len: Option<usize>,
+    answer: Vec<u8>,
+}
+
+impl Unframer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next report, returns the message once it is complete.
+    pub fn push(&mut self, report: &[u8]) -> Result<Option<Vec<u8>>, FramingError> {
+        if report.first() != Some(&REPORT_MARKER) {
+            return Err(FramingError::InvalidMarker);
+        }
+        let chunk = &report[1..];
+        let expected_len = match self.expected_len {
+            Some(len) => len,
+            None => {
+                if chunk.len() < MAGIC.len() + HEADER_SIZE {
+                    return Err(FramingError::IncompleteHeader);
+                }
+                if &chunk[..MAGIC.len()] != MAGIC {
+                    return Err(FramingError::InvalidMagic);
+                }
+                let header = &chunk[MAGIC.len()..MAGIC.len() + HEADER_SIZE];
+                let len = HEADER_SIZE
+                    + u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+                self.expected_len = Some(len);
+                self.answer = Vec::with_capacity(len);
+                return self.extend(&chunk[MAGIC.len()..], len);
+            }
+        };
+        self.extend(chunk, expected_len)
+    }
+
+    fn extend(
+        &mut self,
+        chunk: &[u8],
+        expected_len: usize,
+    ) -> Result<Option<Vec<u8>>, FramingError> {
+        let missing = expected_len - self.answer.len();
+        self.answer
+            .extend_from_slice(&chunk[..std::cmp::min(chunk.len(), missing)]);
+        if self.answer.len() >= expected_len {
+            self.expected_len = None;
+            Ok(Some(std::mem::take(&mut self.answer)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trezor::proto;
+
+    #[test]
+    fn test_frame_and_unframe() {
+        let message = proto::message(proto::PUBLIC_KEY, &[0xab; 100]);
+        let reports = frame(&message);
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.len() == PACKET_SIZE));
+        assert_eq!(&reports[0][..3], b"?##");
+        assert_eq!(reports[1][0], REPORT_MARKER);
+
+        let mut unframer = Unframer::new();
+        assert_eq!(unframer.push(&reports[0]), Ok(None));
+        assert_eq!(unframer.push(&reports[1]), Ok(Some(message)));
+
+        assert_eq!(unframer.push(&reports[1]), Err(FramingError::InvalidMagic));
+        assert_eq!(
+            Unframer::new().push(&[0x00; PACKET_SIZE]),
+            Err(FramingError::InvalidMarker)
+        );
+    }
+}
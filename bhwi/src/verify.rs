@@ -0,0 +1,306 @@
+//! Verification on the host of the signatures returned by the devices: each
+//! signature of a psbt is checked against the sighash of its input, and the
+//! signature of a message against the key of its path. A corrupted transport
+//! or a faulty firmware fails the command with
+//! [`Error::InvalidDeviceSignature`] instead of yielding a transaction that
+//! would be rejected by the network.
+
+use bitcoin::{
+    consensus::encode::{self, VarInt},
+    hashes::{sha256d, Hash, HashEngine},
+    psbt::Psbt,
+    secp256k1::{Message, Secp256k1, Verification, XOnlyPublicKey},
+    sighash::{Prevouts, SighashCache},
+    sign_message::{MessageSignature, BITCOIN_SIGNED_MSG_PREFIX},
+    PublicKey, TxOut,
+};
+
+use crate::{common::Error, psbt::PartialSignature};
+
+/// Returns the hash signed for the message, as the messages of the devices
+/// are bytes and not strings.
+pub fn message_hash(message: &[u8]) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(BITCOIN_SIGNED_MSG_PREFIX);
+    engine.input(&encode::serialize(&VarInt(message.len() as u64)));
+    engine.input(message);
+    sha256d::Hash::from_engine(engine)
+}
+
+/// Verifies the base64 signature of the message was made by the key.
+pub fn verify_message_signature(
+    message: &[u8],
+    signature: &str,
+    pubkey: &PublicKey,
+) -> Result<(), Error> {
+    let recovered = MessageSignature::from_base64(signature)
+        .and_then(|sig| sig.recover_pubkey(&Secp256k1::verification_only(), message_hash(message)))
+        .map_err(|_| Error::InvalidDeviceSignature(None))?;
+    if recovered.inner != pubkey.inner {
+        return Err(Error::InvalidDeviceSignature(None));
+    }
+    Ok(())
+}
+
+/// Verifies the signatures of the inputs at their index against the psbt
+/// they were made for.
+pub fn verify_signatures<'a>(
+    psbt: &Psbt,
+    signatures: impl IntoIterator<Item = (usize, &'a PartialSignature)>,
+) -> Result<(), Error> {
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    // Every spent output is committed by the taproot sighashes.
+    let prevouts: Option<Vec<TxOut>> = (0..psbt.unsigned_tx.input.len())
+        .map(|i| crate::psbt::spent_utxo(psbt, i).cloned())
+        .collect();
+    for (index, signature) in signatures {
+        verify_signature(
+            &secp,
+            psbt,
+            &mut cache,
+            prevouts.as_deref(),
+            index,
+            signature,
+        )
+        .ok_or(Error::InvalidDeviceSignature(Some(index)))?;
+    }
+    Ok(())
+}
+
+/// Verifies the signatures added to the psbt by the device, against the
+/// original psbt: the signatures already there are not checked again.
+pub fn verify_signed_psbt(original: &Psbt, signed: &Psbt) -> Result<(), Error> {
+    let mut signatures = Vec::new();
+    for (index, (input, signed)) in original.inputs.iter().zip(&signed.inputs).enumerate() {
+        for (key, sig) in &signed.partial_sigs {
+            if input.partial_sigs.get(key) != Some(sig) {
+                signatures.push((index, PartialSignature::Sig(*key, *sig)));
+            }
+        }
+        if let Some(sig) = signed
+            .tap_key_sig
+            .filter(|sig| input.tap_key_sig != Some(*sig))
+        {
+            // A key-path signature is verified with the output key.
+            let key = crate::psbt::spent_utxo(original, index)
+                .and_then(|utxo| utxo.script_pubkey.as_bytes().get(2..))
+                .and_then(|key| XOnlyPublicKey::from_slice(key).ok())
+                .ok_or(Error::InvalidDeviceSignature(Some(index)))?;
+            signatures.push((index, PartialSignature::TapScriptSig(key, None, sig)));
+        }
+        for ((key, leaf_hash), sig) in &signed.tap_script_sigs {
+            if input.tap_script_sigs.get(&(*key, *leaf_hash)) != Some(sig) {
+                signatures.push((
+                    index,
+                    PartialSignature::TapScriptSig(*key, Some(*leaf_hash), *sig),
+                ));
+            }
+        }
+    }
+    verify_signatures(original, signatures.iter().map(|(i, sig)| (*i, sig)))
+}
+
+fn verify_signature<C: Verification>(
+    secp: &Secp256k1<C>,
+    psbt: &Psbt,
+    cache: &mut SighashCache<&bitcoin::Transaction>,
+    prevouts: Option<&[TxOut]>,
+    index: usize,
+    signature: &PartialSignature,
+) -> Option<()> {
+    let input = psbt.inputs.get(index)?;
+    let utxo = crate::psbt::spent_utxo(psbt, index)?;
+    let script = &utxo.script_pubkey;
+    match signature {
+        PartialSignature::Sig(pubkey, sig) => {
+            let sighash_type = sig.sighash_type;
+            let segwit = |cache: &mut SighashCache<_>, script: &bitcoin::Script| {
+                if script.is_p2wpkh() {
+                    cache
+                        .p2wpkh_signature_hash(index, script, utxo.value, sighash_type)
+                        .ok()
+                } else if script.is_p2wsh() {
+                    cache
+                        .p2wsh_signature_hash(
+                            index,
+                            input.witness_script.as_ref()?,
+                            utxo.value,
+                            sighash_type,
+                        )
+                        .ok()
+                } else {
+                    None
+                }
+            };
+            let msg = if script.is_witness_program() {
+                Message::from(segwit(cache, script)?)
+            } else {
+                let script_code = match (script.is_p2sh(), &input.redeem_script) {
+                    (true, Some(redeem)) if redeem.is_witness_program() => {
+                        return secp
+                            .verify_ecdsa(
+                                &Message::from(segwit(cache, redeem)?),
+                                &sig.signature,
+                                &pubkey.inner,
+                            )
+                            .ok();
+                    }
+                    (true, Some(redeem)) => redeem,
+                    (true, None) => return None,
+                    (false, _) => script,
+                };
+                Message::from(
+                    cache
+                        .legacy_signature_hash(index, script_code, sighash_type.to_u32())
+                        .ok()?,
+                )
+            };
+            secp.verify_ecdsa(&msg, &sig.signature, &pubkey.inner).ok()
+        }
+        PartialSignature::TapScriptSig(key, leaf_hash, sig) => {
+            let prevouts = Prevouts::All(prevouts?);
+            let (msg, key) = match leaf_hash {
+                Some(leaf_hash) => (
+                    cache
+                        .taproot_script_spend_signature_hash(
+                            index,
+                            &prevouts,
+                            *leaf_hash,
+                            sig.sighash_type,
+                        )
+                        .ok()?,
+                    *key,
+                ),
+                None if script.is_p2tr() => (
+                    cache
+                        .taproot_key_spend_signature_hash(index, &prevouts, sig.sighash_type)
+                        .ok()?,
+                    XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).ok()?,
+                ),
+                None => return None,
+            };
+            secp.verify_schnorr(&sig.signature, &Message::from(msg), &key)
+                .ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{
+        absolute::LockTime,
+        bip32::{DerivationPath, Xpriv},
+        psbt::Input,
+        transaction::Version,
+        Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+        Witness,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn test_verify_signatures() {
+        let secp = Secp256k1::new();
+        let xpriv = Xpriv::new_master(Network::Testnet, &[1; 32]).unwrap();
+        let fingerprint = xpriv.fingerprint(&secp);
+        let key = |path: &str| {
+            let path = DerivationPath::from_str(path).unwrap();
+            let key = xpriv.derive_priv(&secp, &path).unwrap().private_key;
+            (path, PublicKey::new(key.public_key(&secp)))
+        };
+
+        let mut segwit = Input::default();
+        let (path, pubkey) = key("m/84'/1'/0'/0/0");
+        segwit.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: ScriptBuf::new_p2wpkh(&CompressedPublicKey(pubkey.inner).wpubkey_hash()),
+        });
+        segwit
+            .bip32_derivation
+            .insert(pubkey.inner, (fingerprint, path));
+        let mut taproot = Input::default();
+        let (path, pubkey) = key("m/86'/1'/0'/0/0");
+        let (internal_key, _) = pubkey.inner.x_only_public_key();
+        taproot.witness_utxo = Some(TxOut {
+            value: Amount::from_sat(70_000),
+            script_pubkey: ScriptBuf::new_p2tr(&secp, internal_key, None),
+        });
+        taproot.tap_internal_key = Some(internal_key);
+        taproot
+            .tap_key_origins
+            .insert(internal_key, (Vec::new(), (fingerprint, path)));
+
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(bitcoin::Txid::all_zeros(), vout),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![txin(0), txin(1)],
+            output: vec![TxOut {
+                value: Amount::from_sat(110_000),
+                script_pubkey: ScriptBuf::new_op_return([]),
+            }],
+        };
+        let mut unsigned = Psbt::from_unsigned_tx(tx).unwrap();
+        unsigned.inputs = vec![segwit, taproot];
+        let mut signed = unsigned.clone();
+        signed.sign(&xpriv, &secp).unwrap();
+        assert!(verify_signed_psbt(&unsigned, &signed).is_ok());
+
+        let (pubkey, sig) = signed.inputs[0].partial_sigs.first_key_value().unwrap();
+        let signatures = [
+            (0, PartialSignature::Sig(*pubkey, *sig)),
+            (
+                1,
+                PartialSignature::TapScriptSig(
+                    internal_key,
+                    None,
+                    signed.inputs[1].tap_key_sig.unwrap(),
+                ),
+            ),
+        ];
+        assert!(verify_signatures(&unsigned, signatures.iter().map(|(i, sig)| (*i, sig))).is_ok());
+        assert!(matches!(
+            verify_signatures(&unsigned, [(1, &signatures[0].1)]),
+            Err(Error::InvalidDeviceSignature(Some(1)))
+        ));
+        assert!(matches!(
+            verify_signatures(&unsigned, [(2, &signatures[0].1)]),
+            Err(Error::InvalidDeviceSignature(Some(2)))
+        ));
+
+        // The signatures of another transaction do not verify.
+        let mut other = unsigned.clone();
+        other.unsigned_tx.output[0].value = Amount::from_sat(100_000);
+        assert!(matches!(
+            verify_signed_psbt(&other, &signed),
+            Err(Error::InvalidDeviceSignature(Some(0)))
+        ));
+    }
+
+    #[test]
+    fn test_verify_message_signature() {
+        let secp = Secp256k1::new();
+        let key = Xpriv::new_master(Network::Testnet, &[1; 32])
+            .unwrap()
+            .private_key;
+        let pubkey = PublicKey::new(key.public_key(&secp));
+        let msg = Message::from_digest(message_hash(b"hello").to_byte_array());
+        let signature =
+            MessageSignature::new(secp.sign_ecdsa_recoverable(&msg, &key), true).to_base64();
+        assert!(verify_message_signature(b"hello", &signature, &pubkey).is_ok());
+        assert!(matches!(
+            verify_message_signature(b"hello!", &signature, &pubkey),
+            Err(Error::InvalidDeviceSignature(None))
+        ));
+        assert!(matches!(
+            verify_message_signature(b"hello", "not base64", &pubkey),
+            Err(Error::InvalidDeviceSignature(None))
+        ));
+    }
+}
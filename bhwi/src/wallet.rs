@@ -0,0 +1,1133 @@
+use alloc::{collections::BTreeMap, rc::Rc};
+use core::cell::RefCell;
+use core::convert::From;
+use core::iter::IntoIterator;
+use core::str::FromStr;
+
+use bitcoin::{
+    bip32::{ChildNumber, DerivationPath, Error, Fingerprint, KeySource, Xpub},
+    consensus::encode::{self, VarInt},
+    hashes::{sha256, Hash, HashEngine},
+    secp256k1::Secp256k1,
+    Address, Network, ScriptBuf,
+};
+
+use crate::{merkleized_map, prelude::*};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Version {
+    V1 = 1,
+    V2 = 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AddressType {
+    /// Legacy address type. P2PKH for single sig, P2SH for scripts.
+    Legacy,
+    /// Native segwit v0 address type. P2WPKH for single sig, P2WPSH for scripts.
+    NativeSegwit,
+    /// Nested segwit v0 address type. P2SH-P2WPKH for single sig, P2SH-P2WPSH for scripts.
+    NestedSegwit,
+    /// Segwit v1 Taproot address type. P2TR always.
+    Taproot,
+}
+
+/// Longest name of a registered policy, in bytes.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Represents a wallet stored with a wallet policy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WalletPolicy {
+    /// wallet name (ASCII string, max 64 bytes)
+    pub name: String,
+    /// wallet version
+    pub version: Version,
+    /// descriptor with keys aliased by '@i',
+    /// i the index of the key in the keys array.
+    pub descriptor_template: String,
+    /// Keys are the extended pubkeys used in the descriptor.
+    pub keys: Vec<WalletPubKey>,
+    /// Threshold of the multisig policy,
+    /// None if the policy is not a multisig.
+    pub threshold: Option<usize>,
+}
+
+impl WalletPolicy {
+    pub fn new(
+        name: String,
+        version: Version,
+        descriptor_template: String,
+        keys: impl IntoIterator<Item = impl Into<WalletPubKey>>,
+    ) -> Self {
+        Self {
+            name,
+            version,
+            descriptor_template,
+            keys: keys.into_iter().map(|k| k.into()).collect(),
+            threshold: None,
+        }
+    }
+
+    /// Returns the default single signature policy of the account key, its
+    /// script type is given by the purpose of the path (BIP-44, 49, 84 or 86).
+    pub fn new_singlesig(source: KeySource, xpub: Xpub) -> Result<Self, WalletError> {
+        let template = match source.1.into_iter().next() {
+            Some(ChildNumber::Hardened { index: 44 }) => "pkh(@0/**)",
+            Some(ChildNumber::Hardened { index: 49 }) => "sh(wpkh(@0/**))",
+            Some(ChildNumber::Hardened { index: 84 }) => "wpkh(@0/**)",
+            Some(ChildNumber::Hardened { index: 86 }) => "tr(@0/**)",
+            _ => return Err(WalletError::UnsupportedAddressType),
+        };
+        Ok(Self::new(
+            String::new(),
+            Version::V2,
+            template.to_string(),
+            [(source, xpub)],
+        ))
+    }
+
+    pub fn new_multisig<T: Into<WalletPubKey>>(
+        name: String,
+        version: Version,
+        address_type: AddressType,
+        threshold: usize,
+        keys: impl IntoIterator<Item = T>,
+        sorted: bool,
+    ) -> Result<Self, WalletError> {
+        let keys: Vec<WalletPubKey> = keys.into_iter().map(|k| k.into()).collect();
+        if threshold < 1 || threshold > keys.len() {
+            return Err(WalletError::InvalidThreshold);
+        }
+
+        let key_placeholder_suffix = if version == Version::V2 { "/**" } else { "" };
+        let multisig_op = if sorted { "sortedmulti" } else { "multi" };
+        let keys_str = keys
+            .iter()
+            .enumerate()
+            .map(|(i, _)| format!("@{}{}", i, key_placeholder_suffix))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let descriptor_template = match address_type {
+            AddressType::Legacy => format!("sh({}({},{}))", multisig_op, threshold, keys_str),
+            AddressType::NativeSegwit => {
+                format!("wsh({}({},{}))", multisig_op, threshold, keys_str)
+            }
+            AddressType::NestedSegwit => {
+                format!("sh(wsh({}({},{})))", multisig_op, threshold, keys_str)
+            }
+            _ => return Err(WalletError::UnsupportedAddressType),
+        };
+
+        Ok(Self {
+            name,
+            version,
+            descriptor_template,
+            keys,
+            threshold: Some(threshold),
+        })
+    }
+
+    /// Returns the taproot multisig policy spending with the internal key or
+    /// with a `multi_a` leaf of the keys, supported by the app from 2.1.0.
+    /// The internal key may be unspendable to only allow the script path.
+    pub fn new_taproot_multisig<T: Into<WalletPubKey>>(
+        name: String,
+        internal_key: impl Into<WalletPubKey>,
+        threshold: usize,
+        keys: impl IntoIterator<Item = T>,
+        sorted: bool,
+    ) -> Result<Self, WalletError> {
+        let mut keys: Vec<WalletPubKey> = keys.into_iter().map(|k| k.into()).collect();
+        if threshold < 1 || threshold > keys.len() {
+            return Err(WalletError::InvalidThreshold);
+        }
+
+        let multisig_op = if sorted { "sortedmulti_a" } else { "multi_a" };
+        let keys_str = (1..=keys.len())
+            .map(|i| format!("@{}/**", i))
+            .collect::<Vec<String>>()
+            .join(",");
+        keys.insert(0, internal_key.into());
+
+        Ok(Self {
+            name,
+            version: Version::V2,
+            descriptor_template: format!("tr(@0/**,{}({},{}))", multisig_op, threshold, keys_str),
+            keys,
+            threshold: Some(threshold),
+        })
+    }
+
+    /// Checks the name and the template against the constraints of the
+    /// Ledger app, which rejects the invalid policies without context. The
+    /// default single signature policies have an empty name.
+    pub fn validate(&self) -> Result<(), WalletError> {
+        if !self.name.bytes().all(|b| (0x20..=0x7e).contains(&b)) {
+            return Err(WalletError::NameNotAscii);
+        }
+        if self.name.len() > MAX_NAME_LENGTH {
+            return Err(WalletError::NameTooLong(self.name.len()));
+        }
+        if self.name.trim_matches(' ') != self.name {
+            return Err(WalletError::NameSpaces);
+        }
+        self.validate_template()
+    }
+
+    /// Checks the characters, the nesting and the key placeholders of the
+    /// template: each key is used, and derived with `/**` or `/<M;N>/*` in
+    /// the V2 policies. The placeholders of a `musig()` expression of a
+    /// taproot policy are not derived, the aggregate key is.
+    fn validate_template(&self) -> Result<(), WalletError> {
+        let template = self.descriptor_template.as_str();
+        if let Some(c) = template
+            .chars()
+            .find(|c| *c == '#' || !INPUT_CHARSET.contains(*c))
+        {
+            return Err(WalletError::TemplateCharacter(c));
+        }
+        let mut nesting = Vec::new();
+        for c in template.chars() {
+            match c {
+                '(' | '{' => nesting.push(c),
+                ')' if nesting.pop() != Some('(') => return Err(WalletError::UnbalancedTemplate),
+                '}' if nesting.pop() != Some('{') => return Err(WalletError::UnbalancedTemplate),
+                _ => {}
+            }
+        }
+        if !nesting.is_empty() {
+            return Err(WalletError::UnbalancedTemplate);
+        }
+
+        if template.contains("musig(") && !self.is_taproot() {
+            return Err(WalletError::InvalidMusig);
+        }
+
+        let mut used = vec![false; self.keys.len()];
+        let mut rest = template;
+        let mut musig = false;
+        while let Some(start) = rest.find('@') {
+            if musig && &rest[..start] != "," {
+                return Err(WalletError::InvalidMusig);
+            }
+            musig = musig || rest[..start].ends_with("musig(");
+            rest = &rest[start + 1..];
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let index: usize = rest[..end]
+                .parse()
+                .map_err(|_| WalletError::InvalidPlaceholder)?;
+            rest = &rest[end..];
+            *used.get_mut(index).ok_or(WalletError::MissingKey(index))? = true;
+            let derived = if !musig {
+                rest
+            } else if let Some(aggregate) = rest.strip_prefix(')') {
+                musig = false;
+                aggregate
+            } else if rest.starts_with(',') {
+                continue;
+            } else {
+                return Err(WalletError::InvalidMusig);
+            };
+            if self.version == Version::V2 && !is_placeholder_derivation(derived) {
+                return Err(WalletError::KeyDerivation(index));
+            }
+        }
+        match used.iter().position(|used| !used) {
+            Some(index) => Err(WalletError::UnusedKey(index)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if the policy is a taproot policy, spent with key-path or
+    /// script-path signatures.
+    pub fn is_taproot(&self) -> bool {
+        self.descriptor_template.starts_with("tr(")
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut res: Vec<u8> = (self.version as u8).to_be_bytes().to_vec();
+        res.extend_from_slice(&(self.name.len() as u8).to_be_bytes());
+        res.extend_from_slice(self.name.as_bytes());
+        res.extend(encode::serialize(&VarInt(
+            self.descriptor_template.as_bytes().len() as u64,
+        )));
+
+        if self.version == Version::V2 {
+            let mut engine = sha256::Hash::engine();
+            engine.input(self.descriptor_template.as_bytes());
+            let hash = sha256::Hash::from_engine(engine).to_byte_array();
+            res.extend_from_slice(&hash);
+        } else {
+            res.extend_from_slice(self.descriptor_template.as_bytes());
+        }
+
+        res.extend(encode::serialize(&VarInt(self.keys.len() as u64)));
+
+        res.extend_from_slice(&merkleized_map::list_root(
+            self.keys.iter().map(|key| key.to_string()),
+        ));
+
+        res
+    }
+
+    pub fn get_descriptor(&self, change: bool) -> Result<String, WalletError> {
+        let mut desc = self.descriptor_template.clone();
+
+        for (i, key) in self.keys.iter().enumerate().rev() {
+            desc = desc.replace(&format!("@{}", i), &key.to_string());
+        }
+
+        desc = desc.replace("/**", &format!("/{}/{}", if change { 1 } else { 0 }, "*"));
+
+        // For every "/<M;N>" expression, replace with M if not change, or with N if change
+        while let Some(start) = desc.find("/<") {
+            if let Some(end) = desc.find(">") {
+                let nums: Vec<&str> = desc[start + 2..end].split(";").collect();
+                if nums.len() == 2 {
+                    let replacement = if change { nums[1] } else { nums[0] };
+                    desc = format!("{}{}{}", &desc[..start + 1], replacement, &desc[end + 1..]);
+                } else {
+                    return Err(WalletError::InvalidPolicy);
+                }
+            }
+        }
+
+        Ok(desc)
+    }
+
+    /// Returns the script of the receive or the change address of the policy
+    /// at the index, for the hosts to check the address displayed by the
+    /// device or to derive the addresses of a watch-only wallet. For the
+    /// templates of single key and multisig policies.
+    pub fn derive_script(&self, change: bool, index: u32) -> Result<ScriptBuf, WalletError> {
+        crate::psbt::policy_script(&Secp256k1::verification_only(), self, change, index)
+            .ok_or(WalletError::UnsupportedAddressType)
+    }
+
+    pub fn derive_address(
+        &self,
+        change: bool,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, WalletError> {
+        Address::from_script(&self.derive_script(change, index)?, network)
+            .map_err(|_| WalletError::UnsupportedAddressType)
+    }
+
+    /// Returns the policy of the descriptor, its keys are replaced by their
+    /// placeholders in the order of their first appearance. The keys must
+    /// be derived with `/<0;1>/*` or another `/<M;N>/*` multipath, the keys
+    /// of a `musig()` expression are not derived but the expression is.
+    pub fn from_descriptor(descriptor: &str) -> Result<Self, WalletError> {
+        let descriptor = match descriptor.split_once('#') {
+            Some((desc, checksum)) => {
+                if descriptor_checksum(desc).as_deref() != Some(checksum) {
+                    return Err(WalletError::InvalidChecksum);
+                }
+                desc
+            }
+            None => descriptor,
+        };
+        let mut descriptor_template = String::with_capacity(descriptor.len());
+        let mut keys: Vec<WalletPubKey> = Vec::new();
+        // Inside a musig() expression, or after it with its derivation next.
+        let mut musig = false;
+        let mut aggregate = false;
+        for piece in descriptor.split_inclusive(|c| "(),{}".contains(c)) {
+            let (token, delimiter) = match piece.char_indices().last() {
+                Some((i, c)) if "(),{}".contains(c) => piece.split_at(i),
+                _ => (piece, ""),
+            };
+            if aggregate {
+                aggregate = false;
+                descriptor_template.push_str(placeholder_derivation(token)?);
+                descriptor_template.push_str(delimiter);
+                continue;
+            }
+            if token == "musig" && delimiter == "(" {
+                musig = true;
+                descriptor_template.push_str(piece);
+                continue;
+            }
+            let key_end = match token.find(']') {
+                Some(i) => token[i + 1..].find('/').map(|end| i + 1 + end),
+                None => token.find('/'),
+            }
+            .unwrap_or(token.len());
+            let (key, derivation) = token.split_at(key_end);
+            match WalletPubKey::from_str(key) {
+                Ok(key) => {
+                    let index = match keys.iter().position(|k| *k == key) {
+                        Some(index) => index,
+                        None => {
+                            keys.push(key);
+                            keys.len() - 1
+                        }
+                    };
+                    let derivation = match (musig, derivation) {
+                        (true, "") => "",
+                        (true, _) => return Err(WalletError::InvalidMusig),
+                        (false, d) => placeholder_derivation(d)?,
+                    };
+                    descriptor_template.push_str(&format!("@{}{}", index, derivation));
+                }
+                Err(_) if token.starts_with('[') => return Err(WalletError::InvalidDescriptor),
+                Err(_) if musig => return Err(WalletError::InvalidMusig),
+                Err(_) => descriptor_template.push_str(token),
+            }
+            if musig && delimiter == ")" {
+                musig = false;
+                aggregate = true;
+            }
+            descriptor_template.push_str(delimiter);
+        }
+        if musig || aggregate {
+            return Err(WalletError::InvalidMusig);
+        }
+
+        let mut inner = descriptor_template.as_str();
+        while let Some(s) = inner
+            .strip_prefix("sh(")
+            .or_else(|| inner.strip_prefix("wsh("))
+        {
+            inner = s;
+        }
+        let threshold = inner
+            .strip_prefix("sortedmulti(")
+            .or_else(|| inner.strip_prefix("multi("))
+            .and_then(|s| s.split(',').next())
+            .and_then(|k| k.parse().ok());
+
+        Ok(Self {
+            name: String::new(),
+            version: Version::V2,
+            descriptor_template,
+            keys,
+            threshold,
+        })
+    }
+
+    /// Returns the descriptor of the policy with its checksum, the keys are
+    /// derived with the `/<M;N>/*` multipath of their placeholder.
+    pub fn to_descriptor(&self) -> Result<String, WalletError> {
+        let mut desc = self.descriptor_template.clone();
+        for (i, key) in self.keys.iter().enumerate().rev() {
+            desc = desc.replace(&format!("@{}", i), &key.to_string());
+        }
+        desc = desc.replace("/**", "/<0;1>/*");
+        let checksum = descriptor_checksum(&desc).ok_or(WalletError::InvalidDescriptor)?;
+        Ok(format!("{}#{}", desc, checksum))
+    }
+
+    pub fn id(&self) -> [u8; 32] {
+        wallet_id(&self.serialize())
+    }
+
+    /// Checks the registration kept by the application before sending its
+    /// hmac with the policy: the id registered must be the one of the policy,
+    /// which changes with any edit of the policy, and the device must hold a
+    /// key of the policy. The hmac itself is keyed by a secret of the device,
+    /// only the device can check it.
+    pub fn verify_registration(
+        &self,
+        master_fingerprint: Fingerprint,
+        id: &[u8; 32],
+    ) -> Result<(), WalletError> {
+        if *id != self.id() {
+            return Err(WalletError::IdMismatch);
+        }
+        if !self
+            .keys
+            .iter()
+            .any(|key| matches!(&key.source, Some((fg, _)) if *fg == master_fingerprint))
+        {
+            return Err(WalletError::ForeignDevice);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the id of a policy from its serialization, see
+/// [`WalletPolicy::serialize`]: the device returns it with the hmac of the
+/// registered policy.
+pub fn wallet_id(serialized: &[u8]) -> [u8; 32] {
+    let mut engine = sha256::Hash::engine();
+    engine.input(serialized);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Storage of the proofs of registration of the policies, by policy id.
+pub trait WalletHmacStore {
+    fn get(&self, id: &[u8; 32]) -> Option<[u8; 32]>;
+    fn set(&mut self, id: [u8; 32], hmac: [u8; 32]);
+}
+
+/// Store kept in memory, lost with the application.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryHmacStore(BTreeMap<[u8; 32], [u8; 32]>);
+
+impl WalletHmacStore for MemoryHmacStore {
+    fn get(&self, id: &[u8; 32]) -> Option<[u8; 32]> {
+        self.0.get(id).copied()
+    }
+    fn set(&mut self, id: [u8; 32], hmac: [u8; 32]) {
+        self.0.insert(id, hmac);
+    }
+}
+
+/// The store is shared by the application and the interpreters of its
+/// commands.
+impl<S: WalletHmacStore + ?Sized> WalletHmacStore for Rc<RefCell<S>> {
+    fn get(&self, id: &[u8; 32]) -> Option<[u8; 32]> {
+        self.borrow().get(id)
+    }
+    fn set(&mut self, id: [u8; 32], hmac: [u8; 32]) {
+        self.borrow_mut().set(id, hmac)
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WalletError {
+    InvalidThreshold,
+    UnsupportedAddressType,
+    InvalidPolicy,
+    InvalidDescriptor,
+    InvalidChecksum,
+    /// The name has a character out of the printable ASCII ones.
+    NameNotAscii,
+    /// The name is longer than [`MAX_NAME_LENGTH`].
+    NameTooLong(usize),
+    /// The name starts or ends with a space.
+    NameSpaces,
+    /// The template has a character out of the descriptor charset.
+    TemplateCharacter(char),
+    /// The parentheses or the braces of the template are not balanced.
+    UnbalancedTemplate,
+    /// A `@` of the template is not followed by a key index.
+    InvalidPlaceholder,
+    /// The placeholder of the index has no key.
+    MissingKey(usize),
+    /// The key of the index is not used by the template.
+    UnusedKey(usize),
+    /// The placeholder of the key of the index is not followed by `/**` or
+    /// `/<M;N>/*`.
+    KeyDerivation(usize),
+    /// The registered id is not the id of the policy, it was edited since
+    /// its registration.
+    IdMismatch,
+    /// The master fingerprint is not the origin of any key of the policy.
+    ForeignDevice,
+    /// A `musig()` expression is out of a taproot policy, holds other than
+    /// keys or its keys are derived.
+    InvalidMusig,
+}
+
+/// Returns the derivation of a placeholder for the one of a key of a
+/// descriptor, `/**` for `/<0;1>/*`.
+fn placeholder_derivation(derivation: &str) -> Result<&str, WalletError> {
+    match derivation {
+        "/<0;1>/*" => Ok("/**"),
+        d if d.starts_with("/<") && d.ends_with(">/*") => Ok(d),
+        _ => Err(WalletError::InvalidPolicy),
+    }
+}
+
+/// Returns true if the template following a key placeholder starts with its
+/// derivation, `/**` or `/<M;N>/*` with distinct unhardened M and N.
+fn is_placeholder_derivation(template: &str) -> bool {
+    if template.starts_with("/**") {
+        return true;
+    }
+    let Some((multipath, _)) = template
+        .strip_prefix("/<")
+        .and_then(|s| s.split_once(">/*"))
+    else {
+        return false;
+    };
+    match multipath
+        .split_once(';')
+        .map(|(m, n)| (m.parse::<u32>(), n.parse::<u32>()))
+    {
+        Some((Ok(m), Ok(n))) => m != n && m < 0x8000_0000 && n < 0x8000_0000,
+        _ => false,
+    }
+}
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn polymod(chk: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let top = chk >> 35;
+    let mut chk = ((chk & 0x7ffffffff) << 5) ^ value;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            chk ^= generator;
+        }
+    }
+    chk
+}
+
+/// Returns the checksum of the descriptor (BIP-380), None if the descriptor
+/// has a character out of the charset.
+pub fn descriptor_checksum(desc: &str) -> Option<String> {
+    let mut chk = 1;
+    let mut groups = Vec::with_capacity(3);
+    for c in desc.chars() {
+        let value = INPUT_CHARSET.find(c)? as u64;
+        chk = polymod(chk, value & 31);
+        groups.push(value >> 5);
+        if groups.len() == 3 {
+            chk = polymod(chk, groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups[..] {
+        [a] => chk = polymod(chk, a),
+        [a, b] => chk = polymod(chk, a * 3 + b),
+        _ => {}
+    }
+    for _ in 0..8 {
+        chk = polymod(chk, 0);
+    }
+    chk ^= 1;
+    Some(
+        (0..8)
+            .map(|i| CHECKSUM_CHARSET[((chk >> (5 * (7 - i))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalletPubKey {
+    pub inner: Xpub,
+    pub source: Option<KeySource>,
+
+    /// Used by Version V1
+    /// either /** or /<NUM;NUM>/*
+    pub multipath: Option<String>,
+}
+
+impl From<Xpub> for WalletPubKey {
+    fn from(inner: Xpub) -> Self {
+        Self {
+            inner,
+            source: None,
+            multipath: None,
+        }
+    }
+}
+
+impl From<(KeySource, Xpub)> for WalletPubKey {
+    fn from(source_xpub: (KeySource, Xpub)) -> Self {
+        Self {
+            inner: source_xpub.1,
+            source: Some(source_xpub.0),
+            multipath: None,
+        }
+    }
+}
+
+impl From<(KeySource, Xpub, String)> for WalletPubKey {
+    fn from(source_xpub: (KeySource, Xpub, String)) -> Self {
+        Self {
+            inner: source_xpub.1,
+            source: Some(source_xpub.0),
+            multipath: Some(source_xpub.2),
+        }
+    }
+}
+
+impl FromStr for WalletPubKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(key) = Xpub::from_str(s) {
+            Ok(WalletPubKey {
+                inner: key,
+                source: None,
+                multipath: None,
+            })
+        } else {
+            let (keysource_str, xpub_str) = s
+                .strip_prefix('[')
+                .and_then(|s| s.rsplit_once(']'))
+                .ok_or(Error::InvalidDerivationPathFormat)?;
+            let (f_str, path_str) = keysource_str.split_once('/').unwrap_or((keysource_str, ""));
+            let fingerprint =
+                Fingerprint::from_str(f_str).map_err(|_| Error::InvalidDerivationPathFormat)?;
+            let derivation_path = if path_str.is_empty() {
+                DerivationPath::master()
+            } else {
+                DerivationPath::from_str(&format!("m/{}", path_str))?
+            };
+            let (xpub_str, multipath) = if let Some((xpub, multipath)) = xpub_str.rsplit_once('/') {
+                (xpub, Some(format!("/{}", multipath)))
+            } else {
+                (xpub_str, None)
+            };
+            Ok(WalletPubKey {
+                inner: Xpub::from_str(xpub_str)?,
+                source: Some((fingerprint, derivation_path)),
+                multipath,
+            })
+        }
+    }
+}
+
+/// The key is encoded as in the descriptors, see its `Display` and `FromStr`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for WalletPubKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for WalletPubKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        WalletPubKey::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl core::fmt::Display for WalletPubKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match &self.source {
+            None => write!(
+                f,
+                "{}{}",
+                self.inner,
+                self.multipath.as_ref().unwrap_or(&"".to_string())
+            ),
+            Some((fg, path)) => {
+                if path.is_master() {
+                    write!(
+                        f,
+                        "[{}]{}{}",
+                        fg,
+                        self.inner,
+                        self.multipath.as_ref().unwrap_or(&"".to_string())
+                    )
+                } else {
+                    write!(
+                        f,
+                        "[{}/{}]{}{}",
+                        fg,
+                        path,
+                        self.inner,
+                        self.multipath.as_ref().unwrap_or(&"".to_string())
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::hex::FromHex;
+    use core::str::FromStr;
+
+    const MASTER_KEY_EXAMPLE: &str = "[5c9e228d]tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW/**";
+    const KEY_EXAMPLE: &str = "[5c9e228d/48'/1'/0'/0']tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW/**";
+
+    #[test]
+    fn test_master_walletpubkey_fromstr() {
+        let key = WalletPubKey::from_str(MASTER_KEY_EXAMPLE).unwrap();
+        assert_eq!(
+            key.source.as_ref().unwrap().0,
+            Fingerprint::from_str("5c9e228d").unwrap()
+        );
+        assert_eq!(key.source.as_ref().unwrap().1, DerivationPath::master());
+        assert_eq!(key.inner, Xpub::from_str("tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW").unwrap());
+        assert_eq!(key.multipath, Some("/**".to_string()));
+    }
+
+    #[test]
+    fn test_walletpubkey_fromstr() {
+        let key = WalletPubKey::from_str(KEY_EXAMPLE).unwrap();
+        assert_eq!(
+            key.source.as_ref().unwrap().0,
+            Fingerprint::from_str("5c9e228d").unwrap()
+        );
+        assert_eq!(
+            key.source.as_ref().unwrap().1,
+            DerivationPath::from_str("m/48'/1'/0'/0'").unwrap()
+        );
+        assert_eq!(key.inner, Xpub::from_str("tpubDEGquuorgFNb8bjh5kNZQMPtABJzoWwNm78FUmeoPkfRtoPF7JLrtoZeT3J3ybq1HmC3Rn1Q8wFQ8J5usanzups5rj7PJoQLNyvq8QbJruW").unwrap());
+        assert_eq!(key.multipath, Some("/**".to_string()));
+    }
+
+    #[test]
+    fn test_walletpubkey_tostr() {
+        let key = WalletPubKey::from_str(KEY_EXAMPLE).unwrap();
+        assert_eq!(key.to_string(), format!("{}", KEY_EXAMPLE));
+    }
+
+    #[test]
+    fn test_new_singlesig() {
+        let key = WalletPubKey::from_str(KEY_EXAMPLE).unwrap();
+        let fingerprint = key.source.as_ref().unwrap().0;
+        let policy = |path: &str| {
+            WalletPolicy::new_singlesig(
+                (fingerprint, DerivationPath::from_str(path).unwrap()),
+                key.inner,
+            )
+        };
+        assert_eq!(
+            policy("m/84'/1'/0'").unwrap().descriptor_template,
+            "wpkh(@0/**)"
+        );
+        assert_eq!(
+            policy("m/49'/1'/0'").unwrap().descriptor_template,
+            "sh(wpkh(@0/**))"
+        );
+        let policy_tr = policy("m/86'/1'/0'").unwrap();
+        assert_eq!(policy_tr.descriptor_template, "tr(@0/**)");
+        assert_eq!(policy_tr.version, Version::V2);
+        assert!(policy_tr.name.is_empty());
+        assert!(matches!(
+            policy("m/48'/1'/0'/2'"),
+            Err(WalletError::UnsupportedAddressType)
+        ));
+    }
+
+    #[test]
+    fn test_derive_script() {
+        // Test vectors of BIP-84 and BIP-86.
+        let fingerprint = Fingerprint::from_str("73c5da0a").unwrap();
+        let policy = |path: &str, xpub: &str| {
+            WalletPolicy::new_singlesig(
+                (fingerprint, DerivationPath::from_str(path).unwrap()),
+                Xpub::from_str(xpub).unwrap(),
+            )
+            .unwrap()
+        };
+        let wpkh = policy("m/84'/0'/0'", "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V");
+        let address = |policy: &WalletPolicy, change, index| {
+            policy
+                .derive_address(change, index, Network::Bitcoin)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            address(&wpkh, false, 0),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            address(&wpkh, false, 1),
+            "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g"
+        );
+        assert_eq!(
+            address(&wpkh, true, 0),
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"
+        );
+        let tr = policy("m/86'/0'/0'", "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ");
+        assert_eq!(
+            address(&tr, false, 0),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+
+        let mut unsupported = wpkh.clone();
+        unsupported.descriptor_template = "wsh(or_d(pk(@0/**),older(144)))".to_string();
+        assert!(matches!(
+            unsupported.derive_script(false, 0),
+            Err(WalletError::UnsupportedAddressType)
+        ));
+    }
+
+    #[test]
+    fn test_new_taproot_multisig() {
+        let internal = WalletPubKey::from_str(MASTER_KEY_EXAMPLE).unwrap();
+        let key = WalletPubKey::from_str(KEY_EXAMPLE).unwrap();
+        let policy = WalletPolicy::new_taproot_multisig(
+            "Taproot".to_string(),
+            internal.clone(),
+            2,
+            vec![key.clone(), key.clone()],
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "tr(@0/**,sortedmulti_a(2,@1/**,@2/**))"
+        );
+        assert_eq!(
+            policy.keys,
+            vec![internal.clone(), key.clone(), key.clone()]
+        );
+        assert_eq!(policy.threshold, Some(2));
+        assert!(policy.is_taproot());
+        assert!(policy.get_descriptor(true).unwrap().contains("/1/*"));
+
+        assert!(matches!(
+            WalletPolicy::new_taproot_multisig(
+                "Taproot".to_string(),
+                internal,
+                2,
+                vec![key],
+                false
+            ),
+            Err(WalletError::InvalidThreshold)
+        ));
+    }
+
+    #[test]
+    fn test_wallet_serialize_v2() {
+        let wallet = WalletPolicy::new(
+            "Cold storage".to_string(),
+            Version::V2,
+            "wsh(sortedmulti(2,@0/**,@1/**))".to_string(),
+            vec![
+               WalletPubKey::from_str("[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF").unwrap(),
+               WalletPubKey::from_str("[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK").unwrap(),
+            ],
+        );
+        assert_eq!(wallet.serialize().as_slice(), Vec::<u8>::from_hex("020c436f6c642073746f726167651fb56c3d5542fa09b3956834a9ff6a1df5c36a38e5b02c63c54b41a9a04403b82602516d2c50a89476ecffeec658057f0110674bbfafc18797dc480c7ed53802f3fb").unwrap());
+    }
+
+    #[test]
+    fn test_verify_registration() {
+        let key_a = WalletPubKey::from_str(KEY_EXAMPLE).unwrap();
+        let key_b = WalletPubKey::from_str(MASTER_KEY_EXAMPLE).unwrap();
+        let fingerprint = key_a.source.as_ref().unwrap().0;
+        let mut wallet = WalletPolicy::new(
+            "Cold storage".to_string(),
+            Version::V2,
+            "wsh(sortedmulti(1,@0/**,@1/**))".to_string(),
+            vec![key_a, key_b],
+        );
+        let id = wallet_id(&wallet.serialize());
+        assert_eq!(id, wallet.id());
+        assert!(wallet.verify_registration(fingerprint, &id).is_ok());
+        assert!(matches!(
+            wallet.verify_registration(Fingerprint::from([1, 2, 3, 4]), &id),
+            Err(WalletError::ForeignDevice)
+        ));
+
+        wallet.descriptor_template = "wsh(sortedmulti(2,@0/**,@1/**))".to_string();
+        assert!(matches!(
+            wallet.verify_registration(fingerprint, &id),
+            Err(WalletError::IdMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let key = WalletPubKey::from_str(KEY_EXAMPLE).unwrap();
+        let policy = |name: &str, template: &str, keys: usize| {
+            WalletPolicy::new(
+                name.to_string(),
+                Version::V2,
+                template.to_string(),
+                vec![key.clone(); keys],
+            )
+            .validate()
+        };
+        assert!(policy("Cold storage", "wsh(sortedmulti(2,@0/**,@1/<2;3>/*))", 2).is_ok());
+        assert!(policy("", "tr(@0/**,{pk(@1/**),pk(@2/**)})", 3).is_ok());
+        assert!(policy("", "tr(musig(@0,@1,@2)/**)", 3).is_ok());
+        assert!(policy("", "tr(@0/**,pk(musig(@1,@2)/<2;3>/*))", 3).is_ok());
+        assert!(matches!(
+            policy("", "wsh(pk(musig(@0,@1)/**))", 2),
+            Err(WalletError::InvalidMusig)
+        ));
+        assert!(matches!(
+            policy("", "tr(musig(@0/**,@1)/**)", 2),
+            Err(WalletError::InvalidMusig)
+        ));
+        assert!(matches!(
+            policy("", "tr(musig(@0,@1))", 2),
+            Err(WalletError::KeyDerivation(1))
+        ));
+
+        assert!(matches!(
+            policy("Caf\u{e9}", "wpkh(@0/**)", 1),
+            Err(WalletError::NameNotAscii)
+        ));
+        assert!(matches!(
+            policy(&"a".repeat(65), "wpkh(@0/**)", 1),
+            Err(WalletError::NameTooLong(65))
+        ));
+        assert!(matches!(
+            policy(" Savings", "wpkh(@0/**)", 1),
+            Err(WalletError::NameSpaces)
+        ));
+        assert!(matches!(
+            policy("", "wpkh(@0/**)#abcdefgh", 1),
+            Err(WalletError::TemplateCharacter('#'))
+        ));
+        assert!(matches!(
+            policy("", "wsh(multi(1,@0/**)", 1),
+            Err(WalletError::UnbalancedTemplate)
+        ));
+        assert!(matches!(
+            policy("", "wsh(multi(1,@/**))", 1),
+            Err(WalletError::InvalidPlaceholder)
+        ));
+        assert!(matches!(
+            policy("", "wsh(multi(1,@0/**,@1/**))", 1),
+            Err(WalletError::MissingKey(1))
+        ));
+        assert!(matches!(
+            policy("", "wpkh(@0/**)", 2),
+            Err(WalletError::UnusedKey(1))
+        ));
+        assert!(matches!(
+            policy("", "wsh(multi(1,@0/**,@1/<1;1>/*))", 2),
+            Err(WalletError::KeyDerivation(1))
+        ));
+    }
+
+    #[test]
+    fn test_get_descriptor() {
+        let wallet = WalletPolicy::new(
+            "Cold storage".to_string(),
+            Version::V2,
+            "wsh(sortedmulti(2,@0/**,@1/<12;3>/*))".to_string(),
+            vec![
+               WalletPubKey::from_str("[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF").unwrap(),
+               WalletPubKey::from_str("[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK").unwrap(),
+            ],
+        );
+
+        assert_eq!(wallet.get_descriptor(false).unwrap(), "wsh(sortedmulti(2,[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/0/*,[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK/12/*))");
+        assert_eq!(wallet.get_descriptor(true).unwrap(), "wsh(sortedmulti(2,[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF/1/*,[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK/3/*))");
+
+        let wallet = WalletPolicy::new(
+            "Cold storage".to_string(),
+            Version::V2,
+            "wsh(or_d(pk(@0/<0;1>/*),and_v(v:pkh(@1/<0;1>/*),older(65535))))".to_string(),
+            vec![
+               WalletPubKey::from_str("[ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N").unwrap(),
+               WalletPubKey::from_str("[053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp").unwrap(),
+            ],
+        );
+        assert_eq!(wallet.get_descriptor(false).unwrap(), "wsh(or_d(pk([ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/0/*),and_v(v:pkh([053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp/0/*),older(65535))))");
+
+        assert_eq!(wallet.get_descriptor(true).unwrap(), "wsh(or_d(pk([ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/1/*),and_v(v:pkh([053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp/1/*),older(65535))))");
+
+        let wallet = WalletPolicy::new(
+            "Cold storage".to_string(),
+            Version::V2,
+            "wsh(or_d(pk(@0/<0;1>/*),and_v(v:pkh(@1/**),older(65535))))".to_string(),
+            vec![
+               WalletPubKey::from_str("[ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N").unwrap(),
+               WalletPubKey::from_str("[053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp").unwrap(),
+            ],
+        );
+        assert_eq!(wallet.get_descriptor(false).unwrap(), "wsh(or_d(pk([ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/0/*),and_v(v:pkh([053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp/0/*),older(65535))))");
+
+        assert_eq!(wallet.get_descriptor(true).unwrap(), "wsh(or_d(pk([ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/1/*),and_v(v:pkh([053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp/1/*),older(65535))))");
+
+        let wallet = WalletPolicy::new(
+            "Cold storage".to_string(),
+            Version::V2,
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@1/**),older(65535))))".to_string(),
+            vec![
+               WalletPubKey::from_str("[ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N").unwrap(),
+               WalletPubKey::from_str("[053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp").unwrap(),
+            ],
+        );
+        assert_eq!(wallet.get_descriptor(false).unwrap(), "wsh(or_d(pk([ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/0/*),and_v(v:pkh([053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp/0/*),older(65535))))");
+
+        assert_eq!(wallet.get_descriptor(true).unwrap(), "wsh(or_d(pk([ffd63c8d/48'/1'/0'/2']tpubDExA3EC3iAsPxPhFn4j6gMiVup6V2eH3qKyk69RcTc9TTNRfFYVPad8bJD5FCHVQxyBT4izKsvr7Btd2R4xmQ1hZkvsqGBaeE82J71uTK4N/1/*),and_v(v:pkh([053f423f/48'/1'/0'/2']tpubDEGZMZiz8Vnp7N7cTM9Cty897GJpQ8jqmw2yyDKMPfbMzqPtRbo8wViKtkx6zfrzY6jW5NPNULeN9j7oYCqvrFxCkhSdJs7QxwZ3qQ1PXSp/1/*),older(65535))))");
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert!(descriptor_checksum("raw(deadbeef)\u{e9}").is_none());
+    }
+
+    #[test]
+    fn test_from_descriptor() {
+        let key_a = "[76223a6e/48'/1'/0'/2']tpubDE7NQymr4AFtewpAsWtnreyq9ghkzQBXpCZjWLFVRAvnbf7vya2eMTvT2fPapNqL8SuVvLQdbUbMfWLVDCZKnsEBqp6UK93QEzL8Ck23AwF";
+        let key_b = "[f5acc2fd/48'/1'/0'/2']tpubDFAqEGNyad35aBCKUAXbQGDjdVhNueno5ZZVEn3sQbW5ci457gLR7HyTmHBg93oourBssgUxuWz1jX5uhc1qaqFo9VsybY1J5FuedLfm4dK";
+
+        let desc = format!("wsh(sortedmulti(2,{}/<0;1>/*,{}/<2;3>/*))", key_a, key_b);
+        let policy = WalletPolicy::from_descriptor(&desc).unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "wsh(sortedmulti(2,@0/**,@1/<2;3>/*))"
+        );
+        assert_eq!(policy.threshold, Some(2));
+        assert_eq!(policy.keys[0], WalletPubKey::from_str(key_a).unwrap());
+        assert_eq!(policy.version, Version::V2);
+        let res = policy.to_descriptor().unwrap();
+        let (res_desc, checksum) = res.split_once('#').unwrap();
+        assert_eq!(res_desc, desc);
+        assert_eq!(WalletPolicy::from_descriptor(&res).unwrap(), policy);
+        assert!(matches!(
+            WalletPolicy::from_descriptor(&format!("{}#{}", desc, "qqqqqqqq")),
+            Err(WalletError::InvalidChecksum)
+        ));
+        assert_eq!(checksum.len(), 8);
+
+        let desc = format!(
+            "tr({}/<0;1>/*,{{pk({}/<0;1>/*),and_v(v:pk({}/<2;3>/*),older(144))}})",
+            key_a, key_b, key_a
+        );
+        let policy = WalletPolicy::from_descriptor(&desc).unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "tr(@0/**,{pk(@1/**),and_v(v:pk(@0/<2;3>/*),older(144))})"
+        );
+        assert_eq!(policy.keys.len(), 2);
+        assert_eq!(policy.threshold, None);
+
+        let desc = format!("wpkh({}/<0;1>/*)", key_a);
+        let policy = WalletPolicy::from_descriptor(&desc).unwrap();
+        assert_eq!(policy.descriptor_template, "wpkh(@0/**)");
+        assert!(policy.to_descriptor().unwrap().starts_with(&desc));
+
+        assert!(matches!(
+            WalletPolicy::from_descriptor(&format!("wpkh({}/0/*)", key_a)),
+            Err(WalletError::InvalidPolicy)
+        ));
+
+        let desc = format!(
+            "tr(musig({},{})/<0;1>/*,pk(musig({},{})/<2;3>/*))",
+            key_a, key_b, key_b, key_a
+        );
+        let policy = WalletPolicy::from_descriptor(&desc).unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "tr(musig(@0,@1)/**,pk(musig(@1,@0)/<2;3>/*))"
+        );
+        assert!(policy.validate().is_ok());
+        let res = policy.to_descriptor().unwrap();
+        assert_eq!(res.split_once('#').unwrap().0, desc);
+        assert_eq!(WalletPolicy::from_descriptor(&res).unwrap(), policy);
+        assert_eq!(
+            policy.get_descriptor(true).unwrap(),
+            format!(
+                "tr(musig({},{})/1/*,pk(musig({},{})/3/*))",
+                key_a, key_b, key_b, key_a
+            )
+        );
+        assert!(matches!(
+            WalletPolicy::from_descriptor(&format!("tr(musig({}/<0;1>/*,{}))", key_a, key_b)),
+            Err(WalletError::InvalidMusig)
+        ));
+        assert!(matches!(
+            WalletPolicy::from_descriptor(&format!("tr(musig({},{}))", key_a, key_b)),
+            Err(WalletError::InvalidPolicy)
+        ));
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use bhwi::ledger::apdu::ApduResponse;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(res) = ApduResponse::try_from(data.to_vec()) {
+        assert_eq!(Vec::<u8>::from(res), data);
+    }
+});
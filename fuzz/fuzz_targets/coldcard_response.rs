@@ -0,0 +1,9 @@
+#![no_main]
+
+use bhwi::coldcard::api::response;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = response::xpub(data.to_vec());
+    let _ = response::mypub(data.to_vec());
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use bhwi::airgap::{fountain::Decoder, ur};
+use libfuzzer_sys::fuzz_target;
+
+// The lines are the scanned parts of a multi part UR. The decoder must not
+// panic on any sequence of them, and only returns a message of the announced
+// length.
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = core::str::from_utf8(data) else {
+        return;
+    };
+    let mut decoder = Decoder::new();
+    for line in s.lines() {
+        let Ok((_, ur::Part::Multi(part))) = ur::decode(line) else {
+            continue;
+        };
+        let message_len = part.message_len as usize;
+        if let Ok(Some(message)) = decoder.receive(part) {
+            assert_eq!(message.len(), message_len);
+            return;
+        }
+    }
+});
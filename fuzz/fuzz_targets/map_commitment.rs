@@ -0,0 +1,10 @@
+#![no_main]
+
+use bhwi::ledger::merkleized_map::MapCommitment;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((commitment, consumed)) = MapCommitment::deserialize_partial(data) {
+        assert_eq!(commitment.serialize(), data[..consumed]);
+    }
+});
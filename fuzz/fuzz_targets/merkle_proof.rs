@@ -0,0 +1,31 @@
+#![no_main]
+
+use bhwi::merkle::{root_from_proof, MerkleTree};
+use libfuzzer_sys::fuzz_target;
+
+// The first 8 bytes are the index and the size of the tree, the next 32
+// bytes chunks its leaves. Any proof must be checked without panicking, and
+// the proofs of the tree must lead to its root.
+fuzz_target!(|data: &[u8]| {
+    let Some((header, rest)) = data.split_first_chunk::<8>() else {
+        return;
+    };
+    let index = u32::from_be_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+    let size = u32::from_be_bytes(header[4..].try_into().expect("4 bytes")) as usize;
+    let leaves: Vec<[u8; 32]> = rest
+        .chunks_exact(32)
+        .map(|chunk| chunk.try_into().expect("32 bytes chunk"))
+        .collect();
+    let Some((leaf, proof)) = leaves.split_first() else {
+        return;
+    };
+    let _ = root_from_proof(leaf, index, size, proof);
+
+    let tree = MerkleTree::new(leaves.clone());
+    if let Some(proof) = tree.proof(index) {
+        assert_eq!(
+            root_from_proof(&leaves[index], index, tree.size(), &proof),
+            Some(*tree.root())
+        );
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use bhwi::psbt::{MusigPartialSignature, MusigPubNonce};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = MusigPubNonce::from_slice(data);
+    let _ = MusigPartialSignature::from_slice(data);
+});
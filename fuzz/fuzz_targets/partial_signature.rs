@@ -0,0 +1,8 @@
+#![no_main]
+
+use bhwi::ledger::psbt::PartialSignature;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PartialSignature::from_slice(data);
+});
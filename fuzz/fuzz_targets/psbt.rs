@@ -0,0 +1,12 @@
+#![no_main]
+
+use bhwi::psbt::{deserialize_psbt, psbt_from_base64};
+use libfuzzer_sys::fuzz_target;
+
+// The psbt v0 or v2, raw or in base64, must fail without panicking.
+fuzz_target!(|data: &[u8]| {
+    let _ = deserialize_psbt(data);
+    if let Ok(s) = core::str::from_utf8(data) {
+        let _ = psbt_from_base64(s);
+    }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use bhwi::ledger::store::DelegatedStore;
+use libfuzzer_sys::fuzz_target;
+
+// The device requests preimages, merkle proofs and leaf indexes of the
+// known elements, the store must answer or fail without panicking.
+fuzz_target!(|data: &[u8]| {
+    let mut store = DelegatedStore::new();
+    store.add_known_preimage(b"preimage".to_vec());
+    store.add_known_list(&[b"a".as_slice(), b"b", b"c"]);
+    store.add_known_mapping(vec![(b"key".to_vec(), b"value".to_vec())]);
+    for command in data.split(|b| *b == 0xff) {
+        let _ = store.execute(command.to_vec());
+    }
+});